@@ -0,0 +1,48 @@
+//! Compares the throughput of threads storing and looking up transactions
+//! concurrently in a [`ShardedStore`] with that of a single locked [`MemoryStore`].
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use payments::storage::{MemoryStore, ShardedStore, TxState, TxStore};
+use rust_decimal_macros::dec;
+
+/// Applies `ops` deposits and lookups of each of `threads` clients to `store`
+/// concurrently, one thread per client, starting from transaction id `first`.
+fn hammer<T: TxStore + Clone + Send>(store: &T, threads: u64, ops: u64, first: u64) {
+    std::thread::scope(|scope| {
+        for client in 0..threads {
+            let mut store = store.clone();
+            scope.spawn(move || {
+                for tx in (0..ops).map(|tx| first + tx * threads + client) {
+                    store.upsert(client, tx, TxState::Deposit(dec!(1))).unwrap();
+                    assert!(store.get(client, tx).is_some());
+                }
+            });
+        }
+    });
+}
+
+fn concurrent_upserts(c: &mut Criterion) {
+    let (threads, ops) = (8, 2_000);
+    let mut group = c.benchmark_group("concurrent upserts");
+    // threads only contend on the shards they share, rather than on every call
+    let sharded = ShardedStore::new(64);
+    let mut first = 0;
+    group.bench_function(BenchmarkId::new("sharded", threads), |b| {
+        b.iter(|| {
+            hammer(&sharded, threads, ops, first);
+            first += threads * ops;
+        })
+    });
+    let locked = MemoryStore::new();
+    let mut first = 0;
+    group.bench_function(BenchmarkId::new("locked", threads), |b| {
+        b.iter(|| {
+            hammer(&locked, threads, ops, first);
+            first += threads * ops;
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, concurrent_upserts);
+criterion_main!(benches);
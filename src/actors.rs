@@ -0,0 +1,426 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use anyhow::{anyhow, Context, Result};
+use tokio::runtime::Runtime;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tracing::error;
+
+use crate::clients::{Client, Policy, Summary};
+use crate::events::{ClientId, Event, EventType, Record, TxId};
+use crate::rules::RuleSet;
+use crate::storage::MemoryStore;
+
+/// A client owned by its actor, along with its own transactions.
+type Account = Client<Arc<Mutex<MemoryStore>>>;
+
+/// A message to a client's actor.
+enum Message {
+    /// Apply the event, replying with the client's balances afterwards if asked to.
+    Apply(Event, Option<oneshot::Sender<Result<Summary>>>),
+    /// Lend the client to the router to apply a transfer to, waiting until it is sent
+    /// back before handling any other message.
+    Lend(oneshot::Sender<Account>, oneshot::Receiver<Account>),
+}
+
+/// A handle on a running client actor.
+struct Actor {
+    sender: mpsc::UnboundedSender<Message>,
+    task: JoinHandle<Vec<Summary>>,
+}
+
+/// Routes events to an actor for each client, a task on the tokio runtime which owns
+/// the client's balances and transactions and applies its events in the order they
+/// are routed, according to `policy` if they pass `rules`.
+///
+/// Clients don't share any state, so the events of different clients are applied
+/// concurrently across the runtime's threads without contending on a lock. The router
+/// owns the index of transaction ids instead, claiming the id of each new transaction
+/// for its client as it is routed, so that another client's transaction reusing it is
+/// rejected even if the first was rejected itself. Transfers are applied by the router
+/// itself, once the actors of both clients have lent it their clients, so they are
+/// applied after every event routed to either client before them.
+///
+/// # Example
+/// ```
+/// use payments::actors::ClientRouter;
+/// use payments::clients::Policy;
+/// use payments::events::{Event, Record};
+/// use payments::rules::RuleSet;
+/// use rust_decimal_macros::dec;
+///
+/// let deposit = Event::try_from(Record {
+///     r#type: "deposit".to_string(),
+///     client: 1,
+///     tx: 1,
+///     amount: Some(dec!(1.0)),
+///     to: None,
+///     seq: None,
+///     timestamp: None,
+///     currency: None,
+/// })
+/// .unwrap();
+///
+/// let runtime = tokio::runtime::Runtime::new().unwrap();
+/// let summaries = runtime.block_on(async {
+///     let mut router = ClientRouter::new(RuleSet::default(), Policy::default());
+///     let summary = router.apply(deposit).await.unwrap();
+///     assert_eq!(summary.available, dec!(1.0));
+///     router.summaries().await
+/// });
+/// assert_eq!(summaries.len(), 1);
+/// ```
+pub struct ClientRouter {
+    #[doc(hidden)]
+    actors: HashMap<ClientId, Actor>,
+    #[doc(hidden)]
+    rules: Arc<RuleSet>,
+    #[doc(hidden)]
+    policy: Policy,
+    #[doc(hidden)]
+    claimed: HashMap<TxId, ClientId>,
+}
+
+impl ClientRouter {
+    /// Creates a router without any clients, whose actors are spawned on the current
+    /// tokio runtime as their first events are routed.
+    pub fn new(rules: RuleSet, policy: Policy) -> ClientRouter {
+        ClientRouter {
+            actors: HashMap::new(),
+            rules: Arc::new(rules),
+            policy,
+            claimed: HashMap::new(),
+        }
+    }
+
+    /// Routes `event` to its client's actor without waiting for it to be applied. A
+    /// rejected event is logged by the actor. Transfers are applied before returning.
+    pub async fn dispatch(&mut self, event: Event) {
+        if let Err(e) = self.claim(&event) {
+            let _span = event.span().entered();
+            error!("{:?}", e);
+            return;
+        }
+        if let EventType::Transfer { to, .. } = event.kind() {
+            let to = *to;
+            if let Err(e) = self.transfer(&event, to).await {
+                let _span = event.span().entered();
+                error!("{:?}", e);
+            }
+            return;
+        }
+        self.send(event, None);
+    }
+
+    /// Applies `event` to its client's account, returning the client's balances
+    /// afterwards.
+    pub async fn apply(&mut self, event: Event) -> Result<Summary> {
+        self.claim(&event)?;
+        if let EventType::Transfer { to, .. } = event.kind() {
+            let to = *to;
+            return self.transfer(&event, to).await;
+        }
+        let (reply, replied) = oneshot::channel();
+        let id = event.client_id();
+        self.send(event, Some(reply));
+        replied
+            .await
+            .map_err(|_| anyhow!("the actor of client {} stopped", id))?
+    }
+
+    /// Stops every actor once it has applied the events already routed to it, returning
+    /// the balances of every client in each currency it holds, ordered by client id and
+    /// then currency.
+    pub async fn summaries(self) -> Vec<Summary> {
+        let mut summaries = Vec::new();
+        for (id, actor) in self.actors {
+            drop(actor.sender);
+            match actor.task.await {
+                Ok(client) => summaries.extend(client),
+                Err(e) => error!("the actor of client {} failed: {:?}", id, e),
+            }
+        }
+        summaries.sort_by_key(|summary| (summary.id, summary.currency));
+        summaries
+    }
+
+    /// Claims the id of the transaction `event` stores, if it stores a new one, for its
+    /// client, failing if the transaction of another client claimed it first.
+    fn claim(&mut self, event: &Event) -> Result<()> {
+        if !event.kind().is_new_transaction() {
+            return Ok(());
+        }
+        let id = *self.claimed.entry(event.tx()).or_insert(event.client_id());
+        if id != event.client_id() {
+            return Err(anyhow!("cannot overwrite existing transaction"))
+                .with_context(|| format!("processing {:?}", event));
+        }
+        Ok(())
+    }
+
+    /// Sends `event` to its client's actor, spawning the actor if it has none.
+    fn send(&mut self, event: Event, reply: Option<oneshot::Sender<Result<Summary>>>) {
+        let id = event.client_id();
+        // a dropped reply is reported to whoever awaits it
+        let _ = self.actor(id).sender.send(Message::Apply(event, reply));
+    }
+
+    /// Returns the actor of the client specified by `id`, spawning it if it has none.
+    fn actor(&mut self, id: ClientId) -> &Actor {
+        let (rules, policy) = (&self.rules, self.policy);
+        self.actors.entry(id).or_insert_with(|| {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            let client = Client::new(id, MemoryStore::new()).with_policy(policy);
+            let task = tokio::spawn(run(client, Arc::clone(rules), receiver));
+            Actor { sender, task }
+        })
+    }
+
+    /// Borrows the client specified by `id` from its actor, along with the channel to
+    /// send it back through.
+    async fn borrow(&mut self, id: ClientId) -> Result<(Account, oneshot::Sender<Account>)> {
+        let (lend, lent) = oneshot::channel();
+        let (back, returned) = oneshot::channel();
+        let stopped = || anyhow!("the actor of client {} stopped", id);
+        self.actor(id)
+            .sender
+            .send(Message::Lend(lend, returned))
+            .map_err(|_| stopped())?;
+        Ok((lent.await.map_err(|_| stopped())?, back))
+    }
+
+    /// Applies a transfer `event` to the accounts of its client and the client `to`,
+    /// returning the sending client's balances afterwards.
+    async fn transfer(&mut self, event: &Event, to: ClientId) -> Result<Summary> {
+        let (mut client, client_back) = self.borrow(event.client_id()).await?;
+        let (mut receiver, receiver_back) = match self.borrow(to).await {
+            Ok(borrowed) => borrowed,
+            Err(e) => {
+                let _ = client_back.send(client);
+                return Err(e);
+            }
+        };
+        let applied = {
+            let _span = event.span().entered();
+            self.rules
+                .check(event, &client.summary())
+                .and_then(|_| client.transfer(&mut receiver, event).map(drop))
+                .with_context(|| format!("processing {:?}", event))
+                .map(|_| client.summary())
+        };
+        // an actor only stops waiting for its client if it panicked
+        let _ = client_back.send(client);
+        let _ = receiver_back.send(receiver);
+        applied
+    }
+}
+
+/// Runs the actor of `client`, handling `messages` in the order they are received
+/// until the router is dropped, then returning the client's balances in each currency
+/// it holds.
+async fn run(
+    mut client: Account,
+    rules: Arc<RuleSet>,
+    mut messages: mpsc::UnboundedReceiver<Message>,
+) -> Vec<Summary> {
+    let id = client.id();
+    while let Some(message) = messages.recv().await {
+        match message {
+            Message::Apply(event, reply) => {
+                let _span = event.span().entered();
+                let applied = rules
+                    .check(&event, &client.summary())
+                    .and_then(|_| client.update(&event).map(drop))
+                    .with_context(|| format!("processing {:?}", event))
+                    .map(|_| client.summary());
+                match (reply, applied) {
+                    (Some(reply), applied) => drop(reply.send(applied)),
+                    (None, Err(e)) => error!("{:?}", e),
+                    (None, Ok(_)) => {}
+                }
+            }
+            Message::Lend(lend, returned) => {
+                if let Err(unsent) = lend.send(client) {
+                    client = unsent;
+                    continue;
+                }
+                client = match returned.await {
+                    Ok(client) => client,
+                    Err(_) => {
+                        error!("client {} was not returned by the router", id);
+                        return Vec::new();
+                    }
+                };
+            }
+        }
+    }
+    client.summaries()
+}
+
+/// Processes the entries of each of `sources` in turn, parsing them into events with
+/// `parse` on a reader thread while a tokio runtime applies them with a
+/// [`ClientRouter`], an actor for each client. Invalid entries and rejected events are
+/// logged. Returns the balances of every client, ordered by client id.
+///
+/// # Example
+/// ```
+/// use payments::actors::process;
+/// use payments::clients::Policy;
+/// use payments::events::{Event, Record};
+/// use payments::rules::RuleSet;
+/// use rust_decimal_macros::dec;
+///
+/// let deposit = |client| Record {
+///     r#type: "deposit".to_string(),
+///     client,
+///     tx: client,
+///     amount: Some(dec!(1.0)),
+///     to: None,
+///     seq: None,
+///     timestamp: None,
+///     currency: None,
+/// };
+/// let sources = vec![(1..=10).map(|client| Ok(deposit(client)))];
+/// let parse = |entry: anyhow::Result<Record>| entry.and_then(Event::try_from);
+///
+/// let summaries = process(sources, &RuleSet::default(), Policy::default(), parse).unwrap();
+/// assert_eq!(summaries.len(), 10);
+/// assert_eq!(summaries[9].total, dec!(1.0));
+/// ```
+pub fn process<I, F>(
+    sources: Vec<I>,
+    rules: &RuleSet,
+    policy: Policy,
+    parse: F,
+) -> Result<Vec<Summary>>
+where
+    I: Iterator<Item = Result<Record>> + Send,
+    F: Fn(Result<Record>) -> Result<Event> + Sync,
+{
+    let runtime = Runtime::new().context("starting async runtime")?;
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    Ok(thread::scope(|scope| {
+        let parse = &parse;
+        scope.spawn(move || {
+            for entry in sources.into_iter().flatten() {
+                // the runtime only stops receiving if it panicked
+                if sender.send(parse(entry)).is_err() {
+                    break;
+                }
+            }
+        });
+        runtime.block_on(async {
+            let mut router = ClientRouter::new(rules.clone(), policy);
+            while let Some(event) = receiver.recv().await {
+                match event {
+                    Ok(event) => router.dispatch(event).await,
+                    Err(e) => error!("{:?}", e),
+                }
+            }
+            router.summaries().await
+        })
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use crate::events::TxId;
+
+    fn event(t: &str, client: ClientId, tx: TxId, amount: Option<Decimal>) -> Event {
+        Event::try_from(Record {
+            r#type: t.to_string(),
+            client,
+            tx,
+            amount,
+            to: None,
+            seq: None,
+            timestamp: None,
+            currency: None,
+        })
+        .unwrap()
+    }
+
+    fn transfer(client: ClientId, tx: TxId, to: ClientId, amount: Decimal) -> Event {
+        Event::try_from(Record {
+            r#type: "transfer".to_string(),
+            client,
+            tx,
+            amount: Some(amount),
+            to: Some(to),
+            seq: None,
+            timestamp: None,
+            currency: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_router() {
+        let runtime = Runtime::new().unwrap();
+        let summaries = runtime.block_on(async {
+            let mut router = ClientRouter::new(RuleSet::default(), Policy::default());
+            for client in 1..=100 {
+                for tx in 1..=10 {
+                    let tx = client * 10 + tx;
+                    router
+                        .dispatch(event("deposit", client, tx, Some(dec!(1.0))))
+                        .await;
+                }
+            }
+            // applied after every deposit routed to either client before it
+            let sent = router.apply(transfer(1, 2001, 2, dec!(4.0))).await.unwrap();
+            assert_eq!(sent.available, dec!(6.0));
+            let overdraft = router.apply(transfer(3, 2002, 4, dec!(20.0))).await;
+            assert!(overdraft.is_err());
+            router.dispatch(event("dispute", 2, 21, None)).await;
+            let withdrawn = router
+                .apply(event("withdrawal", 2, 2003, Some(dec!(14.0))))
+                .await;
+            // one of client 2's deposits is held
+            assert!(withdrawn.is_err());
+            router.summaries().await
+        });
+        assert_eq!(summaries.len(), 100);
+        assert_eq!(summaries[0].total, dec!(6.0));
+        assert_eq!(summaries[1].available, dec!(13.0));
+        assert_eq!(summaries[1].held, dec!(1.0));
+        assert_eq!(summaries[2].total, dec!(10.0));
+        assert_eq!(summaries[3].total, dec!(10.0));
+    }
+
+    #[test]
+    fn test_reused_tx() {
+        let runtime = Runtime::new().unwrap();
+        let summaries = runtime.block_on(async {
+            let mut router = ClientRouter::new(RuleSet::default(), Policy::default());
+            router
+                .apply(event("deposit", 1, 1, Some(dec!(5.0))))
+                .await
+                .unwrap();
+            // client 2's actor has its own store, yet the id is claimed by client 1
+            let reused = router.apply(event("deposit", 2, 1, Some(dec!(3.0)))).await;
+            assert!(reused.is_err());
+            router
+                .dispatch(event("withdrawal", 2, 1, Some(dec!(1.0))))
+                .await;
+            assert!(router.apply(transfer(2, 1, 1, dec!(1.0))).await.is_err());
+            router
+                .apply(event("deposit", 2, 2, Some(dec!(3.0))))
+                .await
+                .unwrap();
+            // disputes refer to a transaction rather than storing one
+            assert!(router.apply(event("dispute", 2, 1, None)).await.is_err());
+            router.summaries().await
+        });
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[0].available, dec!(5.0));
+        assert_eq!(summaries[1].available, dec!(3.0));
+    }
+}
@@ -0,0 +1,375 @@
+use std::collections::{HashSet, VecDeque};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Error, Result};
+use serde_json::json;
+use tracing::{error, warn};
+
+use crate::clients::Summary;
+use crate::events::ClientId;
+use crate::http::{self, Url};
+
+/// The number of most recent events considered when calculating the reject rate.
+const REJECT_RATE_WINDOW: usize = 1_000;
+
+/// The fewest events which must be seen before the reject rate is evaluated.
+const REJECT_RATE_MIN_EVENTS: usize = 100;
+
+/// The PagerDuty Events API v2 endpoint.
+const PAGERDUTY_URL: &str = "https://events.pagerduty.com/v2/enqueue";
+
+/// A condition which raises an alert when it is met.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlertRule {
+    /// A client account was frozen.
+    AccountLocked,
+    /// More than this percentage of recent events were rejected.
+    RejectRate(f64),
+    /// An event arrived more than this many seconds after its timestamp.
+    IngestionLag(u64),
+}
+
+impl AlertRule {
+    /// Returns the name of the rule, as used on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AlertRule::AccountLocked => "account-locked",
+            AlertRule::RejectRate(_) => "reject-rate",
+            AlertRule::IngestionLag(_) => "ingestion-lag",
+        }
+    }
+}
+
+impl FromStr for AlertRule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<AlertRule> {
+        let (name, threshold) = match s.split_once('=') {
+            Some((name, threshold)) => (name, Some(threshold)),
+            None => (s, None),
+        };
+        match (name, threshold) {
+            ("account-locked", None) => Ok(AlertRule::AccountLocked),
+            ("reject-rate", Some(percent)) => {
+                let percent = percent
+                    .trim_end_matches('%')
+                    .parse()
+                    .with_context(|| format!("invalid reject rate in alert rule {:?}", s))?;
+                Ok(AlertRule::RejectRate(percent))
+            }
+            ("ingestion-lag", Some(seconds)) => {
+                let seconds = seconds
+                    .trim_end_matches('s')
+                    .parse()
+                    .with_context(|| format!("invalid lag in alert rule {:?}", s))?;
+                Ok(AlertRule::IngestionLag(seconds))
+            }
+            _ => bail!(
+                "invalid alert rule {:?}, expected account-locked, reject-rate=PERCENT or \
+                 ingestion-lag=SECONDS",
+                s
+            ),
+        }
+    }
+}
+
+/// A notification raised by an alert rule.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alert {
+    /// The name of the rule which raised the alert.
+    pub rule: &'static str,
+    /// Identifies the condition alerted on, so repeated alerts can be deduplicated.
+    pub key: String,
+    /// A human readable description of the condition.
+    pub summary: String,
+}
+
+/// A destination for alerts.
+#[derive(Clone, Debug)]
+pub enum AlertSink {
+    /// Post the alert as JSON to this URL.
+    Webhook(Url),
+    /// Email the alert to this address using the given `sendmail` command.
+    Email { to: String, sendmail: String },
+    /// Trigger a PagerDuty incident using this Events API v2 routing key.
+    PagerDuty(String),
+}
+
+impl AlertSink {
+    /// Delivers `alert` to this destination.
+    pub fn notify(&self, alert: &Alert) -> Result<()> {
+        match self {
+            AlertSink::Webhook(url) => {
+                let body = json!({
+                    "rule": alert.rule,
+                    "key": alert.key,
+                    "summary": alert.summary,
+                });
+                http::post(url, "application/json", body.to_string().as_bytes())
+            }
+            AlertSink::Email { to, sendmail } => {
+                let mut child = Command::new(sendmail)
+                    .arg("-t")
+                    .stdin(Stdio::piped())
+                    .spawn()
+                    .with_context(|| format!("running {}", sendmail))?;
+                let mut stdin = child.stdin.take().unwrap();
+                write!(
+                    stdin,
+                    "To: {}\nSubject: [payment-processor] {}\n\n{}\n",
+                    to, alert.rule, alert.summary
+                )?;
+                drop(stdin);
+                let status = child.wait()?;
+                if !status.success() {
+                    bail!("{} exited with {}", sendmail, status);
+                }
+                Ok(())
+            }
+            AlertSink::PagerDuty(routing_key) => {
+                let body = json!({
+                    "routing_key": routing_key,
+                    "event_action": "trigger",
+                    "dedup_key": alert.key,
+                    "payload": {
+                        "summary": alert.summary,
+                        "source": "payment-processor",
+                        "severity": "critical",
+                        "custom_details": { "rule": alert.rule },
+                    },
+                });
+                http::post(
+                    &Url::parse(PAGERDUTY_URL)?,
+                    "application/json",
+                    body.to_string().as_bytes(),
+                )
+            }
+        }
+    }
+}
+
+/// Evaluates alert rules as events are processed, notifying every sink when a rule's
+/// condition starts to hold.
+///
+/// Rate and lag alerts are raised once when their threshold is crossed, and again only
+/// after the condition has cleared. An account locked alert is raised for each account
+/// frozen.
+///
+/// # Example
+/// ```
+/// use payments::alerts::{AlertRule, Alerter};
+///
+/// let mut alerter = Alerter::new(vec![AlertRule::IngestionLag(60)], vec![]);
+/// assert!(alerter.received(Some(1_000), 1_030).is_empty());
+///
+/// let alerts = alerter.received(Some(1_000), 1_090);
+/// assert_eq!(alerts[0].rule, "ingestion-lag");
+/// ```
+#[derive(Debug)]
+pub struct Alerter {
+    #[doc(hidden)]
+    rules: Vec<AlertRule>,
+    #[doc(hidden)]
+    sinks: Vec<AlertSink>,
+    #[doc(hidden)]
+    outcomes: VecDeque<bool>,
+    #[doc(hidden)]
+    rejected: usize,
+    #[doc(hidden)]
+    locked: HashSet<ClientId>,
+    #[doc(hidden)]
+    firing: HashSet<usize>,
+}
+
+impl Alerter {
+    /// Creates an alerter evaluating `rules` and notifying `sinks`.
+    pub fn new(rules: Vec<AlertRule>, sinks: Vec<AlertSink>) -> Alerter {
+        Alerter {
+            rules,
+            sinks,
+            outcomes: VecDeque::new(),
+            rejected: 0,
+            locked: HashSet::new(),
+            firing: HashSet::new(),
+        }
+    }
+
+    /// Records an event with the given `timestamp` arriving at `now`, both in seconds
+    /// since the Unix epoch, returning any alerts raised.
+    pub fn received(&mut self, timestamp: Option<u64>, now: u64) -> Vec<Alert> {
+        let lag = match timestamp {
+            Some(ts) => now.saturating_sub(ts),
+            None => return Vec::new(),
+        };
+        let mut alerts = Vec::new();
+        for i in 0..self.rules.len() {
+            if let AlertRule::IngestionLag(max) = self.rules[i] {
+                if self.transition(i, lag > max) {
+                    alerts.push(Alert {
+                        rule: self.rules[i].name(),
+                        key: self.rules[i].name().to_string(),
+                        summary: format!(
+                            "ingestion lag of {}s exceeds the threshold of {}s",
+                            lag, max
+                        ),
+                    });
+                }
+            }
+        }
+        self.notify(alerts)
+    }
+
+    /// Records an event which was applied, leaving its client with `summary`, returning
+    /// any alerts raised.
+    pub fn applied(&mut self, summary: &Summary) -> Vec<Alert> {
+        let mut alerts = self.outcome(false);
+        if !summary.locked {
+            self.locked.remove(&summary.id);
+        } else if self.locked.insert(summary.id) && self.rules.contains(&AlertRule::AccountLocked) {
+            alerts.push(Alert {
+                rule: AlertRule::AccountLocked.name(),
+                key: format!("{}-{}", AlertRule::AccountLocked.name(), summary.id),
+                summary: format!("client {} has been locked", summary.id),
+            });
+        }
+        self.notify(alerts)
+    }
+
+    /// Records an event which was rejected, returning any alerts raised.
+    pub fn rejected(&mut self) -> Vec<Alert> {
+        let alerts = self.outcome(true);
+        self.notify(alerts)
+    }
+
+    fn outcome(&mut self, rejected: bool) -> Vec<Alert> {
+        self.outcomes.push_back(rejected);
+        self.rejected += usize::from(rejected);
+        if self.outcomes.len() > REJECT_RATE_WINDOW {
+            self.rejected -= usize::from(self.outcomes.pop_front().unwrap());
+        }
+        if self.outcomes.len() < REJECT_RATE_MIN_EVENTS {
+            return Vec::new();
+        }
+
+        let rate = self.rejected as f64 * 100.0 / self.outcomes.len() as f64;
+        let mut alerts = Vec::new();
+        for i in 0..self.rules.len() {
+            if let AlertRule::RejectRate(max) = self.rules[i] {
+                if self.transition(i, rate > max) {
+                    alerts.push(Alert {
+                        rule: self.rules[i].name(),
+                        key: self.rules[i].name().to_string(),
+                        summary: format!(
+                            "{:.1}% of the last {} events were rejected, exceeding the \
+                             threshold of {}%",
+                            rate,
+                            self.outcomes.len(),
+                            max
+                        ),
+                    });
+                }
+            }
+        }
+        alerts
+    }
+
+    /// Returns whether the condition of the rule at `index` has just started to hold.
+    fn transition(&mut self, index: usize, holds: bool) -> bool {
+        if holds {
+            self.firing.insert(index)
+        } else {
+            self.firing.remove(&index);
+            false
+        }
+    }
+
+    fn notify(&self, alerts: Vec<Alert>) -> Vec<Alert> {
+        for alert in &alerts {
+            warn!("alert {}: {}", alert.rule, alert.summary);
+            for sink in &self.sinks {
+                if let Err(e) = sink.notify(alert) {
+                    error!("sending alert to {:?}: {:?}", sink, e);
+                }
+            }
+        }
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(id: ClientId, locked: bool) -> Summary {
+        Summary {
+            id,
+            locked,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        assert_eq!(
+            "account-locked".parse::<AlertRule>().unwrap(),
+            AlertRule::AccountLocked
+        );
+        assert_eq!(
+            "reject-rate=5%".parse::<AlertRule>().unwrap(),
+            AlertRule::RejectRate(5.0)
+        );
+        assert_eq!(
+            "ingestion-lag=300".parse::<AlertRule>().unwrap(),
+            AlertRule::IngestionLag(300)
+        );
+        assert!("reject-rate".parse::<AlertRule>().is_err());
+        assert!("account-locked=1".parse::<AlertRule>().is_err());
+    }
+
+    #[test]
+    fn test_account_locked() {
+        let mut alerter = Alerter::new(vec![AlertRule::AccountLocked], vec![]);
+        assert!(alerter.applied(&summary(1, false)).is_empty());
+        assert_eq!(
+            alerter.applied(&summary(1, true))[0].key,
+            "account-locked-1"
+        );
+        assert!(alerter.applied(&summary(1, true)).is_empty());
+        assert_eq!(alerter.applied(&summary(2, true)).len(), 1);
+    }
+
+    #[test]
+    fn test_reject_rate() {
+        let mut alerter = Alerter::new(vec![AlertRule::RejectRate(10.0)], vec![]);
+        for _ in 0..REJECT_RATE_MIN_EVENTS - 20 {
+            assert!(alerter.applied(&summary(1, false)).is_empty());
+        }
+        // too few events to evaluate the rate
+        for _ in 0..19 {
+            assert!(alerter.rejected().is_empty());
+        }
+        assert_eq!(alerter.rejected().len(), 1);
+        assert!(alerter.rejected().is_empty());
+
+        // recovers once enough events are applied, and may alert again
+        for _ in 0..REJECT_RATE_WINDOW {
+            assert!(alerter.applied(&summary(1, false)).is_empty());
+        }
+        for _ in 0..REJECT_RATE_WINDOW / 10 {
+            assert!(alerter.rejected().is_empty());
+        }
+        assert_eq!(alerter.rejected().len(), 1);
+    }
+
+    #[test]
+    fn test_ingestion_lag() {
+        let mut alerter = Alerter::new(vec![AlertRule::IngestionLag(60)], vec![]);
+        assert!(alerter.received(None, 1_000).is_empty());
+        assert_eq!(alerter.received(Some(900), 1_000).len(), 1);
+        assert!(alerter.received(Some(910), 1_000).is_empty());
+        assert!(alerter.received(Some(990), 1_000).is_empty());
+        assert_eq!(alerter.received(Some(900), 1_000).len(), 1);
+    }
+}
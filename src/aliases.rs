@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use serde::Deserialize;
+
+use crate::events::ClientId;
+
+#[derive(Debug, Deserialize)]
+struct Alias {
+    external: String,
+    client: ClientId,
+}
+
+/// Maps the identifiers partners use for their clients onto internal client ids, and
+/// back again for reporting.
+///
+/// # Example
+/// ```
+/// use payments::aliases::ClientAliases;
+///
+/// let aliases = ClientAliases::new([("acme-0042".to_string(), 42)]).unwrap();
+///
+/// assert_eq!(aliases.resolve("acme-0042").unwrap(), 42);
+/// assert_eq!(aliases.resolve("7").unwrap(), 7);
+/// assert_eq!(aliases.name(42), "acme-0042");
+/// assert_eq!(aliases.name(7), "7");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ClientAliases {
+    #[doc(hidden)]
+    clients: HashMap<String, ClientId>,
+    #[doc(hidden)]
+    names: HashMap<ClientId, String>,
+}
+
+impl ClientAliases {
+    /// Creates a mapping from `(external, client)` pairs, failing unless each external
+    /// id maps to exactly one client and each client has at most one external id.
+    pub fn new(aliases: impl IntoIterator<Item = (String, ClientId)>) -> Result<ClientAliases> {
+        let mut mapping = ClientAliases::default();
+        for (external, client) in aliases {
+            if mapping
+                .clients
+                .insert(external.clone(), client)
+                .is_some_and(|c| c != client)
+            {
+                bail!("external id {:?} maps to more than one client", external);
+            }
+            if mapping
+                .names
+                .insert(client, external.clone())
+                .is_some_and(|e| e != external)
+            {
+                bail!("client {} has more than one external id", client);
+            }
+        }
+        Ok(mapping)
+    }
+
+    /// Loads a mapping from a CSV file with `external` and `client` columns.
+    pub fn load(path: impl AsRef<Path>) -> Result<ClientAliases> {
+        let aliases = csv::Reader::from_path(path)?
+            .into_deserialize()
+            .map(|alias| alias.map(|alias: Alias| (alias.external, alias.client)))
+            .collect::<Result<Vec<_>, _>>()?;
+        ClientAliases::new(aliases)
+    }
+
+    /// Returns whether the mapping has no aliases.
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    /// Returns the internal client id for `id`, which is either an external id in the
+    /// mapping or an internal client id.
+    pub fn resolve(&self, id: &str) -> Result<ClientId> {
+        match self.clients.get(id) {
+            Some(&client) => Ok(client),
+            None => id
+                .parse()
+                .map_err(|_| anyhow!("unknown external client id {:?}", id)),
+        }
+    }
+
+    /// Returns the external id of `client` if it has one, or otherwise its internal id,
+    /// for reporting.
+    pub fn name(&self, client: ClientId) -> String {
+        self.names
+            .get(&client)
+            .cloned()
+            .unwrap_or_else(|| client.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let aliases = ClientAliases::new([("A-1".to_string(), 1), ("B-2".to_string(), 2)]).unwrap();
+        assert_eq!(aliases.resolve("A-1").unwrap(), 1);
+        assert_eq!(aliases.resolve("B-2").unwrap(), 2);
+        assert_eq!(aliases.resolve("3").unwrap(), 3);
+        assert!(aliases.resolve("C-3").is_err());
+        assert_eq!(aliases.name(2), "B-2");
+        assert_eq!(aliases.name(3), "3");
+    }
+
+    #[test]
+    fn test_invalid_mappings() {
+        assert!(ClientAliases::new([("A".to_string(), 1), ("A".to_string(), 2)]).is_err());
+        assert!(ClientAliases::new([("A".to_string(), 1), ("B".to_string(), 1)]).is_err());
+        assert!(ClientAliases::new([("A".to_string(), 1), ("A".to_string(), 1)]).is_ok());
+    }
+}
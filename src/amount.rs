@@ -0,0 +1,97 @@
+use std::fmt;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+
+/// A monetary amount, in whichever numeric representation an integration keeps its
+/// funds.
+///
+/// [`Client`](crate::clients::Client), [`Event`](crate::events::Event) and
+/// [`TxState`](crate::storage::TxState) are generic over their amount, defaulting to
+/// [`Decimal`]. Amounts are implemented for [`Decimal`], for `f64`, and for `i64`
+/// counting integer cents.
+///
+/// # Example
+/// ```
+/// use payments::clients::Client;
+/// use payments::events::{Event, Record};
+/// use payments::storage::MemoryStore;
+/// use rust_decimal::prelude::ToPrimitive;
+/// use rust_decimal_macros::dec;
+///
+/// let record = Record {
+///     r#type: "deposit".to_string(),
+///     client: 1,
+///     tx: 1,
+///     amount: Some(dec!(1.25)),
+///     to: None,
+///     seq: None,
+///     timestamp: None,
+///     currency: None,
+/// };
+/// // keep the client's funds as integer cents
+/// let event = Event::try_from(record)
+///     .unwrap()
+///     .map_amount(|amount| (amount * dec!(100)).to_i64().unwrap());
+///
+/// let mut client = Client::new(1, MemoryStore::<i64>::new());
+/// client.update(&event).unwrap();
+/// assert_eq!(client.available(), 125);
+/// ```
+pub trait Amount:
+    Copy
+    + Default
+    + PartialOrd
+    + fmt::Debug
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + AddAssign
+    + SubAssign
+{
+    /// The amount of no funds.
+    const ZERO: Self;
+
+    /// Returns the amount as a decimal, such as for the audit log.
+    fn to_decimal(self) -> Decimal;
+}
+
+impl Amount for Decimal {
+    const ZERO: Decimal = Decimal::ZERO;
+
+    fn to_decimal(self) -> Decimal {
+        self
+    }
+}
+
+impl Amount for f64 {
+    const ZERO: f64 = 0.0;
+
+    fn to_decimal(self) -> Decimal {
+        Decimal::from_f64(self).unwrap_or_default()
+    }
+}
+
+/// Integer cents.
+impl Amount for i64 {
+    const ZERO: i64 = 0;
+
+    fn to_decimal(self) -> Decimal {
+        Decimal::new(self, 2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_to_decimal() {
+        assert_eq!(dec!(1.5).to_decimal(), dec!(1.5));
+        assert_eq!(1.5f64.to_decimal(), dec!(1.5));
+        assert_eq!(f64::NAN.to_decimal(), Decimal::ZERO);
+        assert_eq!(150i64.to_decimal(), dec!(1.50));
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::events::{ClientId, Event, EventType, TxId};
+
+/// The fewest amounts which must be seen for a client before its events are checked.
+const MIN_SAMPLES: u64 = 5;
+
+/// A running mean and variance, updated using Welford's algorithm.
+#[derive(Clone, Copy, Debug, Default)]
+struct Stats {
+    count: u64,
+    mean: f64,
+    m2: f64,
+}
+
+impl Stats {
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    fn stddev(&self) -> f64 {
+        if self.count < 2 {
+            0.0
+        } else {
+            (self.m2 / (self.count - 1) as f64).sqrt()
+        }
+    }
+}
+
+/// An event whose amount was unusual for its client.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Anomaly {
+    /// The client associated with the event.
+    pub client: ClientId,
+    /// The transaction associated with the event.
+    pub tx: TxId,
+    /// The type of the event.
+    pub r#type: &'static str,
+    /// The amount of the event.
+    pub amount: Decimal,
+    /// The mean amount of the client's previous events of the same type.
+    pub mean: f64,
+    /// The standard deviation of the client's previous amounts of the same type.
+    pub stddev: f64,
+}
+
+/// Learns the typical deposit and withdrawal amounts of each client, flagging events
+/// whose amount deviates from the client's mean by more than a configurable number of
+/// standard deviations.
+///
+/// A client's events are only checked once at least five amounts of the same type have
+/// been seen. Every amount, including flagged ones, is learnt.
+///
+/// # Example
+/// ```
+/// use payments::anomaly::AnomalyDetector;
+/// use payments::events::{Event, Record};
+/// use rust_decimal_macros::dec;
+///
+/// let mut detector = AnomalyDetector::new(3.0);
+/// let amounts = [dec!(10), dec!(11), dec!(9), dec!(10), dec!(10), dec!(500)];
+/// for (tx, amount) in amounts.into_iter().enumerate() {
+///     let record = Record {
+///         r#type: "deposit".to_string(),
+///         client: 1,
+///         tx: tx as u64,
+///         amount: Some(amount),
+///         to: None,
+///         seq: None,
+///         timestamp: None,
+///         currency: None,
+///     };
+///     let anomaly = detector.observe(&Event::try_from(record).unwrap());
+///     assert_eq!(anomaly.is_some(), amount == dec!(500));
+/// }
+/// ```
+#[derive(Debug)]
+pub struct AnomalyDetector {
+    #[doc(hidden)]
+    threshold: f64,
+    #[doc(hidden)]
+    clients: HashMap<(ClientId, &'static str), Stats>,
+}
+
+impl AnomalyDetector {
+    /// Creates a detector flagging amounts more than `threshold` standard deviations
+    /// from the mean.
+    pub fn new(threshold: f64) -> AnomalyDetector {
+        AnomalyDetector {
+            threshold,
+            clients: HashMap::new(),
+        }
+    }
+
+    /// Checks the amount of `event` against those previously seen for its client,
+    /// returning an anomaly if it is unusual.
+    pub fn observe(&mut self, event: &Event) -> Option<Anomaly> {
+        let amount = match event.kind() {
+            EventType::Deposit(amount)
+            | EventType::Withdrawal(amount)
+            | EventType::Transfer { amount, .. } => *amount,
+            _ => return None,
+        };
+        let stats = self
+            .clients
+            .entry((event.client_id(), event.kind().name()))
+            .or_default();
+
+        // statistics are only estimates, so are kept as floating point
+        let value = amount.to_f64().unwrap_or_default();
+        let anomaly = (stats.count >= MIN_SAMPLES
+            && (value - stats.mean).abs() > self.threshold * stats.stddev())
+        .then(|| Anomaly {
+            client: event.client_id(),
+            tx: event.tx(),
+            r#type: event.kind().name(),
+            amount,
+            mean: stats.mean,
+            stddev: stats.stddev(),
+        });
+        stats.add(value);
+        anomaly
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    use crate::events::Record;
+
+    fn event(t: &str, client: ClientId, amount: Decimal) -> Event {
+        Event::try_from(Record {
+            r#type: t.to_string(),
+            client,
+            tx: 1,
+            amount: Some(amount),
+            to: None,
+            seq: None,
+            timestamp: None,
+            currency: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut stats = Stats::default();
+        for value in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+            stats.add(value);
+        }
+        assert_eq!(stats.mean, 5.0);
+        assert!((stats.stddev() - 2.138).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_learns_per_client_and_type() {
+        let mut detector = AnomalyDetector::new(2.0);
+        for amount in [dec!(100.0), dec!(110.0), dec!(90.0), dec!(105.0)] {
+            assert!(detector.observe(&event("deposit", 1, amount)).is_none());
+        }
+        // too few samples to judge
+        assert!(detector
+            .observe(&event("deposit", 1, dec!(1000.0)))
+            .is_none());
+        // other clients and types are learnt separately
+        assert!(detector
+            .observe(&event("withdrawal", 1, dec!(5.0)))
+            .is_none());
+        assert!(detector.observe(&event("deposit", 2, dec!(5.0))).is_none());
+
+        let anomaly = detector
+            .observe(&event("deposit", 1, dec!(5000.0)))
+            .unwrap();
+        assert_eq!(anomaly.r#type, "deposit");
+        assert_eq!(anomaly.mean, 281.0);
+    }
+
+    #[test]
+    fn test_threshold() {
+        let mut detector = AnomalyDetector::new(3.0);
+        for amount in [
+            dec!(8.0),
+            dec!(12.0),
+            dec!(8.0),
+            dec!(12.0),
+            dec!(8.0),
+            dec!(12.0),
+        ] {
+            detector.observe(&event("withdrawal", 1, amount));
+        }
+        // a standard deviation of ~2.19
+        assert!(detector
+            .observe(&event("withdrawal", 1, dec!(16.0)))
+            .is_none());
+        assert!(detector
+            .observe(&event("withdrawal", 1, dec!(30.0)))
+            .is_some());
+    }
+}
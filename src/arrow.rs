@@ -0,0 +1,275 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Error, Result};
+use arrow_array::cast::AsArray;
+use arrow_array::types::{
+    Float32Type, Float64Type, Int32Type, Int64Type, UInt16Type, UInt32Type, UInt64Type,
+};
+use arrow_array::{
+    Array, ArrayRef, BooleanArray, Float64Array, RecordBatch, StringArray, UInt64Array,
+};
+use arrow_schema::{DataType, Field, Schema};
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::Decimal;
+
+use crate::clients::Summary;
+use crate::events::{Event, Record};
+use crate::parallel::Book;
+use crate::rules::RuleSet;
+
+/// Returns the column called `name` in `batch`.
+fn column<'a>(batch: &'a RecordBatch, name: &str) -> Result<&'a ArrayRef> {
+    batch
+        .column_by_name(name)
+        .ok_or_else(|| anyhow!("missing column {:?}", name))
+}
+
+/// Reads an integer column as client or transaction ids.
+fn ids(array: &ArrayRef, name: &str) -> Result<Vec<Option<u64>>> {
+    let signed = |values: Vec<Option<i64>>| {
+        values
+            .into_iter()
+            .map(|v| v.map(u64::try_from).transpose())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("negative value in column {:?}", name))
+    };
+    match array.data_type() {
+        DataType::UInt64 => Ok(array.as_primitive::<UInt64Type>().iter().collect()),
+        DataType::UInt32 => Ok(array
+            .as_primitive::<UInt32Type>()
+            .iter()
+            .map(|v| v.map(u64::from))
+            .collect()),
+        DataType::UInt16 => Ok(array
+            .as_primitive::<UInt16Type>()
+            .iter()
+            .map(|v| v.map(u64::from))
+            .collect()),
+        DataType::Int64 => signed(array.as_primitive::<Int64Type>().iter().collect()),
+        DataType::Int32 => signed(
+            array
+                .as_primitive::<Int32Type>()
+                .iter()
+                .map(|v| v.map(i64::from))
+                .collect(),
+        ),
+        t => bail!("column {:?} has type {}, expected an integer type", name, t),
+    }
+}
+
+/// Reads a floating point column as amounts, failing on values that are not finite.
+fn amounts(array: &ArrayRef) -> Result<Vec<Option<Decimal>>> {
+    let amount = |v: Option<Decimal>, f: &dyn std::fmt::Display| {
+        v.ok_or_else(|| anyhow!("invalid amount {}", f))
+    };
+    match array.data_type() {
+        DataType::Float32 => array
+            .as_primitive::<Float32Type>()
+            .iter()
+            .map(|v| v.map(|v| amount(Decimal::from_f32(v), &v)).transpose())
+            .collect(),
+        DataType::Float64 => array
+            .as_primitive::<Float64Type>()
+            .iter()
+            .map(|v| v.map(|v| amount(Decimal::from_f64(v), &v)).transpose())
+            .collect(),
+        t => bail!(
+            "column \"amount\" has type {}, expected Float32 or Float64",
+            t
+        ),
+    }
+}
+
+/// Converts balances to floating point values for a report column.
+fn balances(summaries: &[Summary], balance: impl Fn(&Summary) -> Decimal) -> ArrayRef {
+    Arc::new(Float64Array::from_iter_values(
+        summaries
+            .iter()
+            .map(|s| balance(s).to_f64().unwrap_or(f64::NAN)),
+    ))
+}
+
+/// Reads a string column, such as event types or currencies.
+fn strings(array: &ArrayRef, name: &str) -> Result<Vec<Option<String>>> {
+    match array.data_type() {
+        DataType::Utf8 => Ok(array
+            .as_string::<i32>()
+            .iter()
+            .map(|v| v.map(str::to_string))
+            .collect()),
+        DataType::LargeUtf8 => Ok(array
+            .as_string::<i64>()
+            .iter()
+            .map(|v| v.map(str::to_string))
+            .collect()),
+        t => bail!(
+            "column {:?} has type {}, expected Utf8 or LargeUtf8",
+            name,
+            t
+        ),
+    }
+}
+
+/// Applies the payment events in `batch` to `book`, in row order, returning the row
+/// index and reason of every rejected event.
+///
+/// The batch has the same columns as input files: `type` strings, integer `client`
+/// and `tx` ids, nullable floating point `amount`s and optionally integer
+/// `timestamp`s. Any other columns are ignored.
+///
+/// # Example
+/// ```
+/// use std::sync::Arc;
+///
+/// use arrow_array::{Float32Array, RecordBatch, StringArray, UInt64Array};
+/// use payments::arrow::{apply_batch, summaries_batch};
+/// use payments::parallel::Book;
+///
+/// let batch = RecordBatch::try_from_iter([
+///     ("type", Arc::new(StringArray::from(vec!["deposit", "withdrawal"])) as _),
+///     ("client", Arc::new(UInt64Array::from(vec![1, 1])) as _),
+///     ("tx", Arc::new(UInt64Array::from(vec![1, 2])) as _),
+///     ("amount", Arc::new(Float32Array::from(vec![Some(2.0), Some(3.0)])) as _),
+/// ])
+/// .unwrap();
+///
+/// let mut book = Book::default();
+/// let rejected = apply_batch(&mut book, &batch).unwrap();
+/// assert_eq!(rejected[0].0, 1);
+///
+/// let summaries = summaries_batch(&book.summaries()).unwrap();
+/// assert_eq!(summaries.num_rows(), 1);
+/// ```
+pub fn apply_batch(book: &mut Book, batch: &RecordBatch) -> Result<Vec<(usize, Error)>> {
+    let kinds = strings(column(batch, "type")?, "type")?;
+    let clients = ids(column(batch, "client")?, "client")?;
+    let txs = ids(column(batch, "tx")?, "tx")?;
+    let amounts = amounts(column(batch, "amount")?)?;
+    let timestamps = match batch.column_by_name("timestamp") {
+        Some(array) => ids(array, "timestamp")?,
+        None => vec![None; batch.num_rows()],
+    };
+    let currencies = match batch.column_by_name("currency") {
+        Some(array) => strings(array, "currency")?,
+        None => vec![None; batch.num_rows()],
+    };
+
+    let rules = RuleSet::default();
+    let mut rejected = Vec::new();
+    for row in 0..batch.num_rows() {
+        let applied = match (&kinds[row], clients[row], txs[row]) {
+            (Some(kind), Some(client), Some(tx)) => currencies[row]
+                .as_deref()
+                .map(str::parse)
+                .transpose()
+                .and_then(|currency| {
+                    Event::try_from(Record {
+                        r#type: kind.clone(),
+                        client,
+                        tx,
+                        amount: amounts[row],
+                        to: None,
+                        seq: None,
+                        timestamp: timestamps[row],
+                        currency,
+                    })
+                })
+                .and_then(|event| book.apply(&event, &rules)),
+            _ => Err(anyhow!("missing type, client or tx")),
+        };
+        if let Err(e) = applied {
+            rejected.push((row, e));
+        }
+    }
+    Ok(rejected)
+}
+
+/// Returns `summaries` as a record batch with `client`, `available`, `held`, `total`,
+/// `locked`, `status` and `currency` columns, the currency being null for the base
+/// currency.
+pub fn summaries_batch(summaries: &[Summary]) -> Result<RecordBatch> {
+    let schema = Schema::new(vec![
+        Field::new("client", DataType::UInt64, false),
+        Field::new("available", DataType::Float64, false),
+        Field::new("held", DataType::Float64, false),
+        Field::new("total", DataType::Float64, false),
+        Field::new("locked", DataType::Boolean, false),
+        Field::new("status", DataType::Utf8, false),
+        Field::new("currency", DataType::Utf8, true),
+    ]);
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from_iter_values(
+            summaries.iter().map(|s| s.id),
+        )),
+        balances(summaries, |s| s.available),
+        balances(summaries, |s| s.held),
+        balances(summaries, |s| s.total),
+        Arc::new(BooleanArray::from(
+            summaries.iter().map(|s| s.locked).collect::<Vec<_>>(),
+        )),
+        Arc::new(StringArray::from_iter_values(
+            summaries.iter().map(|s| s.status.name()),
+        )),
+        Arc::new(StringArray::from_iter(
+            summaries.iter().map(|s| s.currency.map(|c| c.to_string())),
+        )),
+    ];
+    Ok(RecordBatch::try_new(Arc::new(schema), columns)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use arrow_array::{Int64Array, StringArray};
+
+    #[test]
+    fn test_apply_batch() {
+        let batch = RecordBatch::try_from_iter([
+            (
+                "type",
+                Arc::new(StringArray::from(vec![
+                    Some("deposit"),
+                    Some("deposit"),
+                    Some("dispute"),
+                    None,
+                    Some("withdrawal"),
+                ])) as ArrayRef,
+            ),
+            (
+                "client",
+                Arc::new(Int64Array::from(vec![1, 2, 1, 1, -1])) as ArrayRef,
+            ),
+            (
+                "tx",
+                Arc::new(Int64Array::from(vec![1, 2, 1, 3, 4])) as ArrayRef,
+            ),
+            (
+                "amount",
+                Arc::new(Float64Array::from(vec![
+                    Some(1.5),
+                    Some(2.0),
+                    None,
+                    Some(1.0),
+                    Some(1.0),
+                ])) as ArrayRef,
+            ),
+        ])
+        .unwrap();
+        // negative client ids are rejected with the whole batch
+        assert!(apply_batch(&mut Book::default(), &batch).is_err());
+
+        let batch = batch.slice(0, 4);
+        let mut book = Book::default();
+        let rejected = apply_batch(&mut book, &batch).unwrap();
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].0, 3);
+
+        let summaries = summaries_batch(&book.summaries()).unwrap();
+        assert_eq!(summaries.num_rows(), 2);
+        let held = summaries.column(2).as_primitive::<Float64Type>();
+        assert_eq!(held.values(), &[1.5, 0.0]);
+        let locked = summaries.column(4).as_boolean();
+        assert!(!locked.value(0));
+    }
+}
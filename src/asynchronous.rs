@@ -0,0 +1,205 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::thread;
+
+use anyhow::{anyhow, Context, Result};
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc;
+use tracing::error;
+
+use crate::clients::{Client, Policy, Summary};
+use crate::events::{Event, EventType, Record};
+use crate::rules::RuleSet;
+use crate::storage::AsyncTxStore;
+
+/// Applies the events received from `events` to client accounts kept in `store`, in
+/// the order they are received, until the channel is closed. Events are applied
+/// according to `policy` if they pass `rules`, and invalid entries and rejected events
+/// are logged.
+///
+/// Clients with balances already saved in `store` carry on from them. Returns the
+/// balances of every client, ordered by client id.
+pub async fn apply_all<T: AsyncTxStore + Clone>(
+    store: T,
+    rules: &RuleSet,
+    policy: Policy,
+    mut events: mpsc::UnboundedReceiver<Result<Event>>,
+) -> Vec<Summary> {
+    let mut clients = HashMap::new();
+    for id in store.clients().await {
+        let client = Client::new_async(id, store.clone()).await;
+        clients.insert(id, client.with_policy(policy));
+    }
+    while let Some(event) = events.recv().await {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                error!("{:?}", e);
+                continue;
+            }
+        };
+        let id = event.client_id();
+        let to = match event.kind() {
+            EventType::Transfer { to, .. } => Some(*to),
+            _ => None,
+        };
+        for id in [Some(id), to].into_iter().flatten() {
+            if let Entry::Vacant(entry) = clients.entry(id) {
+                let client = Client::new_async(id, store.clone()).await;
+                entry.insert(client.with_policy(policy));
+            }
+        }
+        let applied = match to {
+            // such as between members of the same joint account
+            Some(to) if to == id => Err(anyhow!("cannot transfer to the same client")),
+            Some(to) => match clients.get_disjoint_mut([&id, &to]) {
+                [Some(client), Some(to)] => match rules.check(&event, &client.summary()) {
+                    Ok(()) => client.transfer_async(to, &event).await.map(drop),
+                    Err(e) => Err(e),
+                },
+                _ => unreachable!("both clients were just added"),
+            },
+            None => {
+                let client = clients.get_mut(&id).unwrap();
+                match rules.check(&event, &client.summary()) {
+                    Ok(()) => client.update_async(&event).await.map(drop),
+                    Err(e) => Err(e),
+                }
+            }
+        };
+        if let Err(e) = applied.with_context(|| format!("processing {:?}", event)) {
+            // the span isn't entered while applying, as it would be held across awaits
+            event.span().in_scope(|| error!("{:?}", e));
+        }
+    }
+    let mut summaries: Vec<Summary> = clients.values().flat_map(Client::summaries).collect();
+    summaries.sort_by_key(|summary| (summary.id, summary.currency));
+    summaries
+}
+
+/// Processes the entries of each of `sources` in turn, parsing them into events with
+/// `parse` on a reader thread while a tokio runtime applies them to client accounts
+/// kept in `store`, so that neither reading input nor waiting on the store stalls the
+/// other. See [`apply_all`].
+///
+/// # Example
+/// ```
+/// use payments::asynchronous::process;
+/// use payments::clients::Policy;
+/// use payments::events::{Event, Record};
+/// use payments::rules::RuleSet;
+/// use payments::storage::{BlockingStore, MemoryStore};
+/// use rust_decimal_macros::dec;
+///
+/// let deposit = |client| Record {
+///     r#type: "deposit".to_string(),
+///     client,
+///     tx: client,
+///     amount: Some(dec!(1.0)),
+///     to: None,
+///     seq: None,
+///     timestamp: None,
+///     currency: None,
+/// };
+/// let sources = vec![vec![Ok(deposit(1)), Ok(deposit(2))].into_iter()];
+/// let parse = |entry: anyhow::Result<Record>| entry.and_then(Event::try_from);
+/// let store = BlockingStore::new(MemoryStore::new());
+///
+/// let summaries = process(store, sources, &RuleSet::default(), Policy::default(), parse);
+/// assert_eq!(summaries.unwrap()[1].total, dec!(1.0));
+/// ```
+pub fn process<T, I, F>(
+    store: T,
+    sources: Vec<I>,
+    rules: &RuleSet,
+    policy: Policy,
+    parse: F,
+) -> Result<Vec<Summary>>
+where
+    T: AsyncTxStore + Clone,
+    I: Iterator<Item = Result<Record>> + Send,
+    F: Fn(Result<Record>) -> Result<Event> + Sync,
+{
+    let runtime = Runtime::new().context("starting async runtime")?;
+    let (sender, receiver) = mpsc::unbounded_channel();
+    Ok(thread::scope(|scope| {
+        let parse = &parse;
+        scope.spawn(move || {
+            for entry in sources.into_iter().flatten() {
+                // the runtime only stops receiving if it panicked
+                if sender.send(parse(entry)).is_err() {
+                    break;
+                }
+            }
+        });
+        runtime.block_on(apply_all(store, rules, policy, receiver))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use crate::clients::DisputePolicy;
+    use crate::events::{ClientId, TxId};
+    use crate::storage::{BlockingStore, MemoryStore, TxStore};
+
+    fn record(t: &str, client: ClientId, tx: TxId, amount: Option<Decimal>) -> Result<Record> {
+        Ok(Record {
+            r#type: t.to_string(),
+            client,
+            tx,
+            amount,
+            to: None,
+            seq: None,
+            timestamp: None,
+            currency: None,
+        })
+    }
+
+    fn parse(entry: Result<Record>) -> Result<Event> {
+        Event::try_from(entry?)
+    }
+
+    #[test]
+    fn test_process() {
+        let memory = MemoryStore::new();
+        let store = BlockingStore::new(memory.clone());
+        let sources = vec![
+            vec![
+                record("deposit", 1, 1, Some(dec!(2.0))),
+                record("withdrawal", 1, 2, Some(dec!(5.0))),
+                record("deposit", 2, 3, Some(dec!(1.0))),
+            ]
+            .into_iter(),
+            vec![
+                record("dispute", 1, 1, None),
+                record("chargeback", 1, 1, None),
+                record("deposit", 1, 4, Some(dec!(1.0))),
+                record("refund", 2, 5, None),
+            ]
+            .into_iter(),
+        ];
+        let policy = Policy {
+            insufficient_funds: DisputePolicy::Reject,
+            ..Default::default()
+        };
+        let summaries =
+            process(store.clone(), sources, &RuleSet::default(), policy, parse).unwrap();
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(summaries[0].total, dec!(0.0));
+        assert!(summaries[0].locked);
+        assert_eq!(summaries[1].available, dec!(1.0));
+        assert_eq!(memory.get(1, 4), None);
+
+        // a later run carries on from the balances saved in the store
+        let sources = vec![vec![record("deposit", 2, 6, Some(dec!(0.5)))].into_iter()];
+        let summaries = process(store, sources, &RuleSet::default(), policy, parse).unwrap();
+        assert_eq!(summaries.len(), 2);
+        assert!(summaries[0].locked);
+        assert_eq!(summaries[1].total, dec!(1.5));
+    }
+}
@@ -0,0 +1,262 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Error, Result};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::amount::Amount;
+use crate::clients::Summary;
+use crate::events::{ClientId, Currency, Event, EventType, TxId};
+
+/// A record of an event being applied to a client's account, or rejected, along with
+/// the client's resulting balances in the event's currency.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AuditEntry {
+    /// The type of the event.
+    pub r#type: String,
+    /// The client the event was for.
+    pub client: ClientId,
+    /// The transaction referenced by the event.
+    pub tx: TxId,
+    /// The amount of the event, if it had one.
+    #[serde(with = "rust_decimal::serde::str_option")]
+    pub amount: Option<Decimal>,
+    /// The client receiving the funds of a transfer.
+    pub to: Option<ClientId>,
+    /// The time at which the event occurred, if known.
+    pub timestamp: Option<u64>,
+    /// The currency of the event, or `None` for the base currency.
+    pub currency: Option<Currency>,
+    /// Whether the event was applied.
+    pub applied: bool,
+    /// Why the event was rejected, if it was.
+    pub reason: Option<String>,
+    /// The funds available for withdrawal once the event was applied or rejected.
+    #[serde(with = "rust_decimal::serde::str")]
+    pub available: Decimal,
+    /// The funds held under dispute once the event was applied or rejected.
+    #[serde(with = "rust_decimal::serde::str")]
+    pub held: Decimal,
+    /// The total funds once the event was applied or rejected.
+    #[serde(with = "rust_decimal::serde::str")]
+    pub total: Decimal,
+    /// Whether the account was frozen once the event was applied or rejected.
+    pub locked: bool,
+    /// The file the event's record was read from, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// The line the event's record was read from, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<u64>,
+    /// The event's record as read, written as a row of a CSV file, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub record: Option<String>,
+}
+
+impl AuditEntry {
+    /// Returns an entry of `event` having been applied, leaving the client with
+    /// `balances`.
+    pub fn applied<A: Amount>(event: &Event<A>, balances: &Summary<A>) -> AuditEntry {
+        AuditEntry::new(event, balances, None)
+    }
+
+    /// Returns an entry of `event` having been rejected for `reason`, leaving the
+    /// client with `balances`.
+    pub fn rejected<A: Amount>(
+        event: &Event<A>,
+        balances: &Summary<A>,
+        reason: &Error,
+    ) -> AuditEntry {
+        AuditEntry::new(event, balances, Some(reason.root_cause().to_string()))
+    }
+
+    fn new<A: Amount>(
+        event: &Event<A>,
+        balances: &Summary<A>,
+        reason: Option<String>,
+    ) -> AuditEntry {
+        let (amount, to) = match event.kind() {
+            EventType::Transfer { to, amount } => (Some(*amount), Some(*to)),
+            kind => (kind.amount(), None),
+        };
+        AuditEntry {
+            r#type: event.kind().name().to_string(),
+            client: event.client_id(),
+            tx: event.tx(),
+            amount: amount.map(A::to_decimal),
+            to,
+            timestamp: event.timestamp(),
+            currency: event.currency(),
+            applied: reason.is_none(),
+            reason,
+            available: balances.available.to_decimal(),
+            held: balances.held.to_decimal(),
+            total: balances.total.to_decimal(),
+            locked: balances.locked,
+            source: event.position().map(|p| p.source.clone()),
+            line: event.position().map(|p| p.line),
+            record: event.position().map(|p| p.record.clone()),
+        }
+    }
+}
+
+/// Keeps a trail of every event applied to client accounts or rejected, such as for
+/// compliance.
+pub trait AuditLog: Send + fmt::Debug {
+    /// Records `entry` as the latest in the trail.
+    fn record(&mut self, entry: AuditEntry) -> Result<()>;
+}
+
+/// An audit log shared by the clients recording to it.
+pub type SharedAuditLog = Arc<Mutex<dyn AuditLog>>;
+
+/// An audit log kept in memory.
+///
+/// # Example
+/// ```
+/// use payments::audit::MemoryAuditLog;
+/// use payments::clients::Client;
+/// use payments::events::{Event, Record};
+/// use payments::storage::MemoryStore;
+/// use rust_decimal_macros::dec;
+///
+/// let log = MemoryAuditLog::new();
+/// let mut client = Client::new(1, MemoryStore::new()).with_audit_log(log.clone());
+/// for (r#type, tx) in [("deposit", 1), ("withdrawal", 2), ("withdrawal", 3)] {
+///     let record = Record {
+///         r#type: r#type.to_string(),
+///         client: 1,
+///         tx,
+///         amount: Some(dec!(1.0)),
+///         to: None,
+///         seq: None,
+///         timestamp: None,
+///         currency: None,
+///     };
+///     let _ = client.update(&Event::try_from(record).unwrap());
+/// }
+///
+/// let log = log.lock().unwrap();
+/// let applied: Vec<bool> = log.entries().iter().map(|entry| entry.applied).collect();
+/// assert_eq!(applied, vec![true, true, false]);
+/// ```
+#[derive(Debug, Default)]
+pub struct MemoryAuditLog {
+    #[doc(hidden)]
+    entries: Vec<AuditEntry>,
+}
+
+impl MemoryAuditLog {
+    pub fn new() -> Arc<Mutex<MemoryAuditLog>> {
+        Arc::new(Mutex::new(MemoryAuditLog::default()))
+    }
+
+    /// Returns every entry recorded, oldest first.
+    pub fn entries(&self) -> &[AuditEntry] {
+        &self.entries
+    }
+}
+
+impl AuditLog for MemoryAuditLog {
+    fn record(&mut self, entry: AuditEntry) -> Result<()> {
+        self.entries.push(entry);
+        Ok(())
+    }
+}
+
+/// An audit log appended to a file as JSON Lines, with an object for each entry. Every
+/// entry is written to the file as soon as it is recorded.
+#[derive(Debug)]
+pub struct FileAuditLog {
+    #[doc(hidden)]
+    writer: LineWriter<File>,
+}
+
+impl FileAuditLog {
+    /// Opens the log at `path` to append to, creating it if it does not exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<FileAuditLog> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("opening {}", path.display()))?;
+        Ok(FileAuditLog {
+            writer: LineWriter::new(file),
+        })
+    }
+}
+
+impl AuditLog for FileAuditLog {
+    fn record(&mut self, entry: AuditEntry) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, &entry)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::anyhow;
+    use rust_decimal_macros::dec;
+
+    use std::{env, fs};
+
+    use crate::events::{Position, Record};
+
+    #[test]
+    fn test_file_audit_log() {
+        let event = Event::try_from(Record {
+            r#type: "withdrawal".to_string(),
+            client: 1,
+            tx: 2,
+            amount: Some(dec!(1.5)),
+            to: None,
+            seq: None,
+            timestamp: Some(100),
+            currency: None,
+        })
+        .unwrap();
+        let balances = Summary {
+            id: 1,
+            available: dec!(0.5),
+            total: dec!(0.5),
+            ..Default::default()
+        };
+
+        let path = env::temp_dir().join(format!("audit-{}.jsonl", std::process::id()));
+        let mut log = FileAuditLog::open(&path).unwrap();
+        log.record(AuditEntry::applied(&event, &balances)).unwrap();
+        drop(log);
+        // reopening appends rather than replacing the trail
+        let mut log = FileAuditLog::open(&path).unwrap();
+        let reason = anyhow!("insufficient funds for withdrawal").context("processing event");
+        let event = event.with_position(Position {
+            source: "batch.csv".to_string(),
+            line: 3,
+            record: "withdrawal,1,2,1.5,,,100".to_string(),
+        });
+        log.record(AuditEntry::rejected(&event, &balances, &reason))
+            .unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            written,
+            "{\"type\":\"withdrawal\",\"client\":1,\"tx\":2,\"amount\":\"1.5\",\"to\":null,\
+             \"timestamp\":100,\"currency\":null,\"applied\":true,\"reason\":null,\
+             \"available\":\"0.5\",\"held\":\"0\",\"total\":\"0.5\",\"locked\":false}\n\
+             {\"type\":\"withdrawal\",\"client\":1,\"tx\":2,\"amount\":\"1.5\",\"to\":null,\
+             \"timestamp\":100,\"currency\":null,\"applied\":false,\
+             \"reason\":\"insufficient funds for withdrawal\",\
+             \"available\":\"0.5\",\"held\":\"0\",\"total\":\"0.5\",\"locked\":false,\
+             \"source\":\"batch.csv\",\"line\":3,\"record\":\"withdrawal,1,2,1.5,,,100\"}\n"
+        );
+    }
+}
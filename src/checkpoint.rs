@@ -0,0 +1,256 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::clients::{AccountStatus, Summary};
+use crate::events::{ClientId, Currency, TxId};
+use crate::storage::{Account, Balance, MemoryStore, TxState, TxStore};
+
+/// A stored transaction saved in a checkpoint.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Transaction {
+    /// The client the transaction belongs to.
+    pub client: ClientId,
+    /// The unique identifier of the transaction.
+    pub tx: TxId,
+    /// The amount and state of the transaction.
+    pub state: TxState,
+    /// The currency of the transaction, or `None` for the base currency.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    /// The time at which the transaction occurred, if it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+}
+
+/// The client balances and transactions at the end of a run, from which a later run
+/// can carry on applying new events rather than starting from genesis.
+///
+/// # Example
+/// ```
+/// use payments::checkpoint::Checkpoint;
+/// use payments::storage::{Account, ClientStore, MemoryStore, TxState, TxStore};
+/// use rust_decimal_macros::dec;
+///
+/// let mut store = MemoryStore::new();
+/// store.upsert(1, 1, TxState::Deposit(dec!(1.0))).unwrap();
+/// let account = Account { available: dec!(1.0), total: dec!(1.0), ..Default::default() };
+/// store.save_account(1, account.clone()).unwrap();
+/// let checkpoint = Checkpoint::capture(&store.lock().unwrap());
+///
+/// let mut restored = MemoryStore::new();
+/// checkpoint.restore(&mut restored).unwrap();
+/// assert_eq!(restored.get(1, 1), Some(TxState::Deposit(dec!(1.0))));
+/// assert_eq!(restored.account(1), Some(account));
+/// ```
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Checkpoint {
+    #[doc(hidden)]
+    clients: Vec<Summary>,
+    #[doc(hidden)]
+    transactions: Vec<Transaction>,
+    #[doc(hidden)]
+    #[serde(default)]
+    records: u64,
+    #[doc(hidden)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pruned: Vec<(TxId, TxId)>,
+}
+
+impl Checkpoint {
+    /// Captures every client account and transaction in `store`, with an entry for
+    /// each currency an account holds, and the ids of the transactions pruned from it.
+    pub fn capture(store: &MemoryStore) -> Checkpoint {
+        let mut clients: Vec<Summary> = store
+            .accounts()
+            .flat_map(|(id, account)| {
+                let currencies = account.currencies.keys().copied().map(Some);
+                [None].into_iter().chain(currencies).map(move |currency| {
+                    let balance = account.balance(currency);
+                    Summary {
+                        id,
+                        available: balance.available,
+                        held: balance.held(),
+                        total: balance.total,
+                        locked: account.status == AccountStatus::Frozen,
+                        status: account.status,
+                        currency,
+                    }
+                })
+            })
+            .collect();
+        clients.sort_by_key(|summary| (summary.id, summary.currency));
+        let mut transactions: Vec<Transaction> = store
+            .transactions()
+            .map(|(client, tx, state)| Transaction {
+                client,
+                tx,
+                state: state.clone(),
+                currency: store.currency(tx),
+                timestamp: store.timestamp(tx),
+            })
+            .collect();
+        transactions.sort_by_key(|transaction| transaction.tx);
+        let pruned = store
+            .pruned()
+            .map(|tx_ids| (*tx_ids.start(), *tx_ids.end()))
+            .collect();
+        Checkpoint {
+            clients,
+            transactions,
+            records: 0,
+            pruned,
+        }
+    }
+
+    /// Returns the checkpoint, recording that it was taken once `records` input records
+    /// had been processed, so that a run resuming from it can skip them.
+    pub fn with_records(self, records: u64) -> Checkpoint {
+        Checkpoint { records, ..self }
+    }
+
+    /// Returns the number of input records processed by the time the checkpoint was
+    /// taken, or zero if it was taken at the end of a run.
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    /// Loads a checkpoint from the JSON file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Checkpoint> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+        serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("reading checkpoint {}", path.display()))
+    }
+
+    /// Saves the checkpoint as JSON to `path`, replacing the previous checkpoint only
+    /// once the new one has been completely written.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let partial = path.with_extension("partial");
+        let mut writer = BufWriter::new(
+            File::create(&partial).with_context(|| format!("creating {}", partial.display()))?,
+        );
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        fs::rename(&partial, path)
+            .with_context(|| format!("replacing checkpoint {}", path.display()))
+    }
+
+    /// Saves the checkpointed client accounts and transactions into `store`, which
+    /// rejects the pruned ones if they are stored again.
+    pub fn restore(&self, store: &mut impl TxStore) -> Result<()> {
+        for &(first, last) in &self.pruned {
+            store.set_pruned(first..=last)?;
+        }
+        for transaction in &self.transactions {
+            store.upsert(
+                transaction.client,
+                transaction.tx,
+                transaction.state.clone(),
+            )?;
+            if let Some(currency) = transaction.currency {
+                store.set_currency(transaction.tx, currency)?;
+            }
+            if let Some(timestamp) = transaction.timestamp {
+                store.set_timestamp(transaction.tx, timestamp)?;
+            }
+        }
+        let mut accounts: BTreeMap<ClientId, Account> = BTreeMap::new();
+        for summary in &self.clients {
+            let account = accounts.entry(summary.id).or_default();
+            // checkpoints taken before account statuses only record whether accounts
+            // were locked
+            account.status = match summary.status {
+                AccountStatus::Active => AccountStatus::from_flags(summary.locked, false),
+                status => status,
+            };
+            let balance = Balance {
+                available: summary.available,
+                total: summary.total,
+            };
+            account.set_balance(summary.currency, balance);
+        }
+        for (id, account) in accounts {
+            store.save_account(id, account)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    use std::env;
+
+    use crate::storage::ClientStore;
+
+    #[test]
+    fn test_save_and_load() {
+        let mut store = MemoryStore::new();
+        store.upsert(1, 1, TxState::Deposit(dec!(1.5))).unwrap();
+        store.upsert(2, 2, TxState::Dispute(dec!(0.1))).unwrap();
+        store.upsert(1, 3, TxState::Withdrawal(dec!(1.0))).unwrap();
+        let accounts = [
+            Account {
+                available: dec!(0.5),
+                total: dec!(0.5),
+                status: AccountStatus::Frozen,
+                currencies: BTreeMap::from([(
+                    "EUR".parse().unwrap(),
+                    Balance {
+                        available: dec!(1),
+                        total: dec!(2),
+                    },
+                )]),
+            },
+            Account {
+                available: dec!(0.0),
+                total: dec!(0.1),
+                status: AccountStatus::Closed,
+                currencies: BTreeMap::new(),
+            },
+        ];
+        store.upsert(1, 4, TxState::Dispute(dec!(1))).unwrap();
+        store.set_currency(4, "EUR".parse().unwrap()).unwrap();
+        store.set_timestamp(4, 1700000000).unwrap();
+        store.save_account(1, accounts[0].clone()).unwrap();
+        store.save_account(2, accounts[1].clone()).unwrap();
+        store.set_pruned(10..=12).unwrap();
+        let checkpoint = Checkpoint::capture(&store.lock().unwrap()).with_records(42);
+
+        let path = env::temp_dir().join(format!("checkpoint-{}.json", std::process::id()));
+        checkpoint.save(&path).unwrap();
+        let loaded = Checkpoint::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut restored = MemoryStore::new();
+        loaded.restore(&mut restored).unwrap();
+        assert_eq!(restored.account(1).as_ref(), Some(&accounts[0]));
+        assert_eq!(restored.account(2).as_ref(), Some(&accounts[1]));
+        assert_eq!(restored.get(1, 1), Some(TxState::Deposit(dec!(1.5))));
+        assert_eq!(restored.get(2, 2), Some(TxState::Dispute(dec!(0.1))));
+        assert_eq!(restored.get(1, 3), Some(TxState::Withdrawal(dec!(1.0))));
+        assert_eq!(restored.get(2, 1), None);
+        assert_eq!(restored.currency(4), Some("EUR".parse().unwrap()));
+        assert_eq!(restored.currency(1), None);
+        assert_eq!(restored.timestamp(4), Some(1700000000));
+        assert_eq!(loaded.records(), 42);
+        assert!(restored.upsert(1, 11, TxState::Deposit(dec!(1))).is_err());
+        assert!(restored.upsert(1, 13, TxState::Deposit(dec!(1))).is_ok());
+
+        // checkpoints taken before records were counted were taken at the end of a run
+        let old: Checkpoint =
+            serde_json::from_str(r#"{"clients": [], "transactions": []}"#).unwrap();
+        assert_eq!(old.records(), 0);
+        assert!(old.pruned.is_empty());
+    }
+}
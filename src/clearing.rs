@@ -0,0 +1,330 @@
+use std::io::Write;
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+use rust_decimal::{Decimal, RoundingStrategy};
+
+use crate::clients::Summary;
+use crate::events::{format_amount, ClientId};
+use crate::rules::ClientAttributes;
+use crate::schedule::format_time;
+
+/// The namespace of the ISO 20022 customer credit transfer initiation messages written.
+const PAIN_001_NAMESPACE: &str = "urn:iso:std:iso:20022:tech:xsd:pain.001.001.03";
+
+/// The format of a clearing file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClearingFormat {
+    /// A CSV file with `client`, `amount`, `name` and `iban` columns.
+    Csv,
+    /// An ISO 20022 pain.001 customer credit transfer initiation message.
+    Pain001,
+}
+
+impl FromStr for ClearingFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ClearingFormat> {
+        match s {
+            "csv" => Ok(ClearingFormat::Csv),
+            "pain.001" => Ok(ClearingFormat::Pain001),
+            v => bail!("invalid clearing format {:?}, expected csv or pain.001", v),
+        }
+    }
+}
+
+/// The account payouts are made from.
+#[derive(Clone, Debug, Default)]
+pub struct Debtor {
+    /// The name of the account holder.
+    pub name: String,
+    /// The IBAN of the account, required by pain.001 files.
+    pub iban: Option<String>,
+    /// The currency of the payouts.
+    pub currency: String,
+}
+
+/// A payment owed to a client.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Payout {
+    /// The client being paid.
+    pub client: ClientId,
+    /// The amount owed to the client.
+    pub amount: Decimal,
+    /// The name of the client, from its `name` attribute.
+    pub name: Option<String>,
+    /// The IBAN of the client's account, from its `iban` attribute.
+    pub iban: Option<String>,
+}
+
+/// Returns the payouts owed to every unlocked client with available funds, ordered by
+/// client id.
+pub fn payouts(
+    summaries: impl IntoIterator<Item = Summary>,
+    attributes: &ClientAttributes,
+) -> Vec<Payout> {
+    let mut payouts: Vec<Payout> = summaries
+        .into_iter()
+        .filter(|summary| !summary.locked && summary.available > Decimal::ZERO)
+        .map(|summary| {
+            let attribute = |name: &str| {
+                attributes
+                    .get(&summary.id)
+                    .and_then(|values| values.get(name))
+                    .filter(|value| !value.is_empty())
+                    .cloned()
+            };
+            Payout {
+                client: summary.id,
+                amount: summary.available,
+                name: attribute("name"),
+                iban: attribute("iban"),
+            }
+        })
+        .collect();
+    payouts.sort_by_key(|payout| payout.client);
+    payouts
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Writes the outbound clearing file which disburses each client's payout.
+///
+/// # Example
+/// ```
+/// use payments::clearing::{ClearingFile, ClearingFormat, Debtor, Payout};
+/// use rust_decimal_macros::dec;
+///
+/// let payout = Payout { client: 1, amount: dec!(12.5), name: None, iban: None };
+/// let mut file = Vec::new();
+/// ClearingFile::new(ClearingFormat::Csv, Debtor::default())
+///     .unwrap()
+///     .write(&mut file, &[payout], 0)
+///     .unwrap();
+///
+/// assert_eq!(String::from_utf8(file).unwrap(), "client,amount,name,iban\n1,12.5000,,\n");
+/// ```
+#[derive(Clone, Debug)]
+pub struct ClearingFile {
+    #[doc(hidden)]
+    format: ClearingFormat,
+    #[doc(hidden)]
+    debtor: Debtor,
+}
+
+impl ClearingFile {
+    /// Creates a clearing file of the given format, paid from the `debtor` account.
+    pub fn new(format: ClearingFormat, debtor: Debtor) -> Result<ClearingFile> {
+        if format == ClearingFormat::Pain001 && debtor.iban.is_none() {
+            bail!("pain.001 clearing files require the IBAN of the debtor account");
+        }
+        Ok(ClearingFile { format, debtor })
+    }
+
+    /// Writes `payouts` to `writer`, as created at the UNIX timestamp `now`.
+    ///
+    /// Payouts to clients without an IBAN are left out of pain.001 files, since they
+    /// cannot be made.
+    pub fn write(&self, writer: impl Write, payouts: &[Payout], now: u64) -> Result<()> {
+        match self.format {
+            ClearingFormat::Csv => self.write_csv(writer, payouts),
+            ClearingFormat::Pain001 => self.write_pain001(writer, payouts, now),
+        }
+    }
+
+    fn write_csv(&self, writer: impl Write, payouts: &[Payout]) -> Result<()> {
+        let mut csv = csv::Writer::from_writer(writer);
+        csv.write_record(["client", "amount", "name", "iban"])?;
+        for payout in payouts {
+            csv.write_record([
+                payout.client.to_string(),
+                format_amount(payout.amount),
+                payout.name.clone().unwrap_or_default(),
+                payout.iban.clone().unwrap_or_default(),
+            ])?;
+        }
+        csv.flush()?;
+        Ok(())
+    }
+
+    fn write_pain001(&self, mut writer: impl Write, payouts: &[Payout], now: u64) -> Result<()> {
+        // amounts are rounded down to whole cents, so clients are never overpaid
+        let payouts: Vec<(&Payout, &str, Decimal)> = payouts
+            .iter()
+            .filter_map(|payout| {
+                let cents = payout
+                    .amount
+                    .round_dp_with_strategy(2, RoundingStrategy::ToZero);
+                (cents > Decimal::ZERO).then_some((payout, payout.iban.as_deref()?, cents))
+            })
+            .collect();
+        let count = payouts.len();
+        let total: Decimal = payouts.iter().map(|&(_, _, amount)| amount).sum();
+        let created = format_time(now);
+        let id = format!("PAYMENTS-{}", now);
+        let debtor = &self.debtor;
+        let debtor_iban = debtor.iban.as_deref().unwrap_or_default();
+
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(writer, r#"<Document xmlns="{}">"#, PAIN_001_NAMESPACE)?;
+        writeln!(writer, "  <CstmrCdtTrfInitn>")?;
+        writeln!(writer, "    <GrpHdr>")?;
+        writeln!(writer, "      <MsgId>{}</MsgId>", id)?;
+        writeln!(writer, "      <CreDtTm>{}</CreDtTm>", created)?;
+        writeln!(writer, "      <NbOfTxs>{}</NbOfTxs>", count)?;
+        writeln!(writer, "      <CtrlSum>{:.2}</CtrlSum>", total)?;
+        writeln!(
+            writer,
+            "      <InitgPty><Nm>{}</Nm></InitgPty>",
+            escape(&debtor.name)
+        )?;
+        writeln!(writer, "    </GrpHdr>")?;
+        writeln!(writer, "    <PmtInf>")?;
+        writeln!(writer, "      <PmtInfId>{}</PmtInfId>", id)?;
+        writeln!(writer, "      <PmtMtd>TRF</PmtMtd>")?;
+        writeln!(writer, "      <NbOfTxs>{}</NbOfTxs>", count)?;
+        writeln!(writer, "      <CtrlSum>{:.2}</CtrlSum>", total)?;
+        writeln!(
+            writer,
+            "      <ReqdExctnDt>{}</ReqdExctnDt>",
+            &created[..10]
+        )?;
+        writeln!(
+            writer,
+            "      <Dbtr><Nm>{}</Nm></Dbtr>",
+            escape(&debtor.name)
+        )?;
+        writeln!(
+            writer,
+            "      <DbtrAcct><Id><IBAN>{}</IBAN></Id></DbtrAcct>",
+            escape(debtor_iban)
+        )?;
+        writeln!(
+            writer,
+            "      <DbtrAgt><FinInstnId><Othr><Id>NOTPROVIDED</Id></Othr></FinInstnId></DbtrAgt>"
+        )?;
+        for (payout, iban, amount) in payouts {
+            let name = match &payout.name {
+                Some(name) => escape(name),
+                None => format!("Client {}", payout.client),
+            };
+            writeln!(writer, "      <CdtTrfTxInf>")?;
+            writeln!(
+                writer,
+                "        <PmtId><EndToEndId>{}-{}</EndToEndId></PmtId>",
+                id, payout.client
+            )?;
+            writeln!(
+                writer,
+                r#"        <Amt><InstdAmt Ccy="{}">{:.2}</InstdAmt></Amt>"#,
+                escape(&debtor.currency),
+                amount
+            )?;
+            writeln!(writer, "        <Cdtr><Nm>{}</Nm></Cdtr>", name)?;
+            writeln!(
+                writer,
+                "        <CdtrAcct><Id><IBAN>{}</IBAN></Id></CdtrAcct>",
+                escape(iban)
+            )?;
+            writeln!(writer, "      </CdtTrfTxInf>")?;
+        }
+        writeln!(writer, "    </PmtInf>")?;
+        writeln!(writer, "  </CstmrCdtTrfInitn>")?;
+        writeln!(writer, "</Document>")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use std::collections::HashMap;
+
+    fn summary(id: ClientId, available: Decimal, locked: bool) -> Summary {
+        Summary {
+            id,
+            available,
+            total: available,
+            locked,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_payouts() {
+        let mut attributes = ClientAttributes::new();
+        attributes.insert(
+            2,
+            HashMap::from([
+                ("name".to_string(), "Ada".to_string()),
+                ("iban".to_string(), String::new()),
+            ]),
+        );
+        let payouts = payouts(
+            [
+                summary(3, dec!(1.0), true),
+                summary(2, dec!(5.0), false),
+                summary(1, dec!(0.0), false),
+            ],
+            &attributes,
+        );
+        assert_eq!(
+            payouts,
+            vec![Payout {
+                client: 2,
+                amount: dec!(5.0),
+                name: Some("Ada".to_string()),
+                iban: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_pain001() {
+        let debtor = Debtor {
+            name: "Payments & Co".to_string(),
+            iban: Some("GB33BUKB20201555555555".to_string()),
+            currency: "EUR".to_string(),
+        };
+        assert!(ClearingFile::new(ClearingFormat::Pain001, Debtor::default()).is_err());
+
+        let payouts = [
+            Payout {
+                client: 1,
+                amount: dec!(10.129),
+                name: None,
+                iban: Some("DE89370400440532013000".to_string()),
+            },
+            Payout {
+                client: 2,
+                amount: dec!(5.0),
+                name: None,
+                iban: None,
+            },
+        ];
+        let mut file = Vec::new();
+        ClearingFile::new(ClearingFormat::Pain001, debtor)
+            .unwrap()
+            .write(&mut file, &payouts, 1_709_251_200)
+            .unwrap();
+        let file = String::from_utf8(file).unwrap();
+
+        assert!(file.contains("<NbOfTxs>1</NbOfTxs>"));
+        assert!(file.contains("<CtrlSum>10.12</CtrlSum>"));
+        assert!(file.contains("<CreDtTm>2024-03-01T00:00:00</CreDtTm>"));
+        assert!(file.contains("<Nm>Payments &amp; Co</Nm>"));
+        assert!(file.contains(r#"<InstdAmt Ccy="EUR">10.12</InstdAmt>"#));
+        assert!(file.contains("<Cdtr><Nm>Client 1</Nm></Cdtr>"));
+        assert!(!file.contains("Client 2"));
+    }
+}
@@ -0,0 +1,2210 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Context, Error, Result};
+use rust_decimal::Decimal;
+use serde_json::{json, Value};
+use structopt::clap::{self, AppSettings, ErrorKind};
+use structopt::StructOpt;
+use tracing::{error, info, warn, Level};
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::prelude::*;
+
+use crate::alerts::{AlertRule, AlertSink, Alerter};
+use crate::aliases::ClientAliases;
+use crate::anomaly::AnomalyDetector;
+use crate::audit::{FileAuditLog, SharedAuditLog};
+use crate::checkpoint::Checkpoint;
+use crate::clearing::{ClearingFile, ClearingFormat, Debtor};
+use crate::clients::{AccountStatus, Client, DisputePolicy, Policy, Summary};
+use crate::deadletter::FileSink;
+use crate::dedup::Deduplicator;
+use crate::disputes::{DisputeExpiry, ExpiryWindow, OpenDisputes};
+use crate::events::{format_amount, ClientId, Event, Position, Record, RoundingPolicy};
+use crate::follow::FollowReader;
+use crate::hierarchy::AccountHierarchy;
+use crate::history::{BalanceHistory, Bucket};
+use crate::http::{self, Url};
+use crate::input::{Compression, CsvDialect, InputFormat};
+use crate::joint::JointAccounts;
+use crate::kafka::{KafkaSink, KafkaSource};
+use crate::lockouts::Lockouts;
+use crate::manifest::Manifest;
+use crate::merge::MergedRecords;
+use crate::metrics::{SharedMetrics, TimedStore};
+use crate::otel::{OtlpExporter, Span};
+use crate::output::{OutputFormat, Report};
+use crate::parallel::{Book, ParallelMode};
+use crate::parking::DisputeParking;
+use crate::processor::{end_file_span, parse_entry, Backend, Processor, Telemetry, Tracing};
+use crate::progress::ProgressTracker;
+use crate::projection::project;
+use crate::rejects::RejectsWriter;
+use crate::reorder::ReorderBuffer;
+use crate::risk::{RiskScorer, RiskWeights};
+use crate::rules::RuleSet;
+use crate::schedule::{Period, Schedule, Scheduler};
+use crate::script::ScriptHook;
+use crate::sequence::{SequenceAnomaly, SequenceTracker};
+use crate::server::{self, HttpService};
+use crate::settlement::Settlement;
+use crate::signature::PublicKey;
+use crate::source::{EventSource, FileSource, ReadAhead, StdinSource};
+use crate::statsd::{StatsdEmitter, StatsdFlavor};
+use crate::storage::{
+    BlockingStore, BloomStore, CompactStore, MemoryStore, PostgresStore, Pruner, SledStore,
+    SpillStore, StoreKind,
+};
+use crate::tsdb::{TsdbExporter, TsdbFormat};
+use crate::wal::WriteAheadLog;
+use crate::watch::DirectoryWatcher;
+use crate::webhooks::WebhookNotifier;
+use crate::{actors, asynchronous, clearing, encryption, input, parallel, rules, schedule};
+
+/// The options of the command line utility, parsed from its arguments.
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "payment-processor",
+    about = "A tool for processing payment events",
+    setting = AppSettings::SubcommandsNegateReqs
+)]
+pub struct Opt {
+    /// Print error and warning messages to stderr
+    #[structopt(long)]
+    verbose: bool,
+    /// The format of the messages printed with --verbose, either "text" or "json" (one
+    /// JSON object per line, with the client, tx and type of the event being processed)
+    #[structopt(long, default_value = "text", possible_values = &["text", "json"])]
+    log_format: String,
+    /// The format of the input files, either "csv" or "json" (JSON Lines). Detected from
+    /// each file's extension if not given, with stdin read as CSV
+    #[structopt(long)]
+    format: Option<InputFormat>,
+    /// The compression of the input files, either "none", "gzip" or "zstd". Detected
+    /// from each file's extension if not given, with stdin read uncompressed
+    #[structopt(long)]
+    compression: Option<Compression>,
+    /// The character separating the fields of CSV input files, e.g. ";" or "tab"
+    #[structopt(long, default_value = ",", parse(try_from_str = parse_delimiter))]
+    delimiter: u8,
+    /// Read CSV input files without a header row, with the columns type, client, tx,
+    /// amount, to, seq, timestamp and currency in that order
+    #[structopt(long)]
+    no_headers: bool,
+    /// Ignore whitespace around the fields of CSV input files
+    #[structopt(long)]
+    trim: bool,
+    /// The format of the report, either "csv" or "json" (JSON Lines)
+    #[structopt(long, default_value = "csv")]
+    output_format: OutputFormat,
+    /// Write the report to this file rather than stdout
+    #[structopt(long)]
+    output: Option<String>,
+    /// Order the rows of the report by client id, so that reports can be compared
+    /// between runs
+    #[structopt(long)]
+    sorted: bool,
+    /// Encrypt the report to this age recipient, e.g. "age1...", so
+    /// that balances never rest unencrypted. May be given multiple times, allowing any
+    /// of the recipients to decrypt it
+    #[structopt(
+        long = "encrypt-to",
+        number_of_values = 1,
+        parse(try_from_str = encryption::parse_recipient)
+    )]
+    encrypt_to: Vec<age::x25519::Recipient>,
+    /// Reject transaction ids which do not fit in 32 bits, for compatibility with
+    /// systems still using u32 transaction ids
+    #[structopt(long)]
+    legacy_tx_ids: bool,
+    /// How to handle amounts with more than four decimal places: "reject" the event,
+    /// or round them with "round-half-even". Reported balances are always rounded
+    /// half to even
+    #[structopt(long, default_value = "reject")]
+    rounding: RoundingPolicy,
+    /// Allow resolving a transaction which was charged back, such as after an
+    /// investigation, restoring its funds and unlocking the account
+    #[structopt(long)]
+    unlock_on_resolve: bool,
+    /// How to apply disputes of deposits exceeding the client's available funds:
+    /// "reject" them, hold the full amount with "allow-negative-available", or
+    /// "hold-partial" to hold only the available funds, rejecting them if there are none
+    #[structopt(long, default_value = "reject")]
+    dispute_insufficient_funds: DisputePolicy,
+    /// Allow disputing withdrawals, provisionally crediting the withdrawn funds back as
+    /// held funds, which a chargeback returns to the client without freezing the
+    /// account
+    #[structopt(long)]
+    dispute_withdrawals: bool,
+    /// Allow administrative events, such as "unlock" events unfreezing a client's
+    /// account after an investigation, "review" events placing it under review and
+    /// "close" events decommissioning it
+    #[structopt(long)]
+    allow_admin_events: bool,
+    /// Report the status of each account, one of "active", "frozen", "under_review" or
+    /// "closed", in an additional "status" column
+    #[structopt(long)]
+    account_status: bool,
+    /// Reject disputes filed more than this period, e.g. "90d", after the transaction
+    /// they dispute, going by the timestamps of both events
+    #[structopt(long)]
+    dispute_window: Option<Period>,
+    /// Remove settled transactions from the store once they are this period, e.g.
+    /// "120d", older than the newest event, going by the timestamps of events, so that a
+    /// long-running service doesn't keep every transaction. Must be at least the
+    /// --dispute-window
+    #[structopt(
+        long,
+        requires = "dispute-window",
+        conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"]
+    )]
+    prune_after: Option<Period>,
+    /// Resolve disputes left open for this many events, e.g. "1000", or for this
+    /// period, e.g. "30d", going by the timestamps of events, releasing their held funds
+    #[structopt(long, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    dispute_expiry: Option<ExpiryWindow>,
+    /// A CSV file with "file", "rows" and "sha256" columns, giving the expected number
+    /// of records in and checksum of each input file. Every input file is verified
+    /// against it before processing, refusing to run on a mismatch
+    #[structopt(long)]
+    manifest: Option<String>,
+    /// A file containing a detached Ed25519 signature over an input file, made with
+    /// the key given by --pubkey. Given once for each input file, in the same order
+    #[structopt(long = "verify-signature", number_of_values = 1, requires = "pubkey")]
+    signatures: Vec<String>,
+    /// The Ed25519 public key which every input file must be signed with, either PEM
+    /// encoded or as 32 raw or hex encoded bytes. Input files are verified before
+    /// processing, refusing to run if any signature is missing or does not match
+    #[structopt(long)]
+    pubkey: Option<String>,
+    /// Drop records with the same type, client, transaction and amount as one of this
+    /// many records read before them, such as blocks of rows written twice upstream
+    #[structopt(long, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    dedup_window: Option<usize>,
+    /// Buffer timestamped events for this many seconds, applying them in timestamp
+    /// order. Events arriving after this window are applied immediately
+    #[structopt(long, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    reorder_window: Option<u64>,
+    /// Park disputes, resolutions, chargebacks and representments of transactions which
+    /// have not arrived yet for up to this many further events, applying them as soon as their
+    /// transaction arrives. Those still parked after this many events are rejected
+    #[structopt(long, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    park_disputes: Option<u64>,
+    /// Process multiple input files in global timestamp order rather than one file
+    /// after another. Each file is expected to be ordered by timestamp
+    #[structopt(long, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    merge_by_timestamp: bool,
+    /// Process input files concurrently, either applying every file's events to one
+    /// "shared" book of accounts, in no particular order between files, or to
+    /// "isolated" books reported separately for each file. Only validation rules are
+    /// applied to events processed concurrently
+    #[structopt(long, conflicts_with = "merge-by-timestamp")]
+    parallel: Option<ParallelMode>,
+    /// Apply events on an async runtime while input is read on another thread, so
+    /// that waiting on a networked --store does not stall reading. Only validation
+    /// rules are applied to events processed asynchronously
+    #[structopt(long, conflicts_with_all = &["parallel", "merge-by-timestamp"])]
+    async_io: bool,
+    /// Apply events across this many worker threads, each owning the accounts of the
+    /// clients whose id modulo the number of workers is its index, while input is read
+    /// in order on another thread. Transfers between clients of different workers are
+    /// rejected, and only validation rules are applied to events processed by workers
+    #[structopt(
+        long,
+        conflicts_with_all = &["parallel", "async-io", "store", "store-path"],
+        parse(try_from_str = parse_workers)
+    )]
+    workers: Option<usize>,
+    /// Apply each client's events on a task of its own, which owns the client's
+    /// accounts and transactions, while input is read in order on another thread.
+    /// Transaction ids are still claimed across clients as events are routed, and only
+    /// validation rules are applied to events processed by client tasks
+    #[structopt(
+        long,
+        conflicts_with_all = &["parallel", "async-io", "workers", "store", "store-path"]
+    )]
+    actors: bool,
+    /// Split the input into this many partitions by client id, written to temporary
+    /// files, then apply the events of every partition at once, each on its own thread
+    /// and with its own accounts, merging the accounts of every partition at the end.
+    /// Transfers between partitions are rejected, and only validation rules are applied
+    /// to partitioned events
+    #[structopt(
+        long,
+        conflicts_with_all = &["parallel", "async-io", "workers", "actors", "store", "store-path"],
+        parse(try_from_str = parse_partitions)
+    )]
+    partitions: Option<usize>,
+    /// Read and parse input files on a thread of their own, up to this many records
+    /// ahead of the events being applied, waiting for those read to be applied once
+    /// that many are waiting
+    #[structopt(long, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    read_ahead: Option<usize>,
+    /// Where transactions and client balances are kept: in "memory", or persisted to a
+    /// "sled" database in --store-path or a "postgres" database at --dsn, carrying on
+    /// from those saved there by earlier runs. Defaults to sled when --store-path is
+    /// given, and memory otherwise
+    #[structopt(
+        long,
+        conflicts_with = "parallel",
+        requires_ifs = &[("sled", "store-path"), ("postgres", "dsn")]
+    )]
+    store: Option<StoreKind>,
+    /// The directory of the sled database transactions and client balances are kept in
+    #[structopt(long, conflicts_with = "parallel")]
+    store_path: Option<String>,
+    /// The connection string of the PostgreSQL database used by --store postgres, e.g.
+    /// "host=localhost user=payments"
+    #[structopt(long)]
+    dsn: Option<String>,
+    /// Save a checkpoint of client balances and transactions to --checkpoint-path every
+    /// this many input records, so that a run which stops part way through can be
+    /// carried on with --resume
+    #[structopt(
+        long,
+        requires = "checkpoint-path",
+        conflicts_with_all = &[
+            "store", "store-path", "max-memory", "compact", "parallel", "workers", "actors", "partitions", "async-io",
+            "reorder-window", "park-disputes", "dispute-expiry", "schedule",
+        ]
+    )]
+    checkpoint_every: Option<u64>,
+    /// Where --checkpoint-every saves checkpoints
+    #[structopt(long, requires = "checkpoint-every")]
+    checkpoint_path: Option<String>,
+    /// Carry on from a checkpoint saved by --checkpoint-every, skipping the input
+    /// records processed before it was saved
+    #[structopt(
+        long,
+        conflicts_with_all = &[
+            "store", "store-path", "max-memory", "compact", "parallel", "workers", "actors", "partitions", "async-io",
+            "reorder-window", "park-disputes", "dispute-expiry", "schedule",
+        ]
+    )]
+    resume: Option<String>,
+    /// Log each event to this file before it is applied, along with the writes applying
+    /// it makes to the store, and replay the log on startup, so that a service which
+    /// crashed carries on exactly where it left off
+    #[structopt(
+        long,
+        conflicts_with_all = &[
+            "parallel", "workers", "actors", "partitions", "async-io", "checkpoint-every", "resume",
+            "max-memory", "compact", "reorder-window", "park-disputes", "dispute-expiry", "schedule",
+        ]
+    )]
+    wal: Option<String>,
+    /// Bound the memory transactions kept in memory use to roughly this many bytes,
+    /// with an optional K, M or G suffix, e.g. "512M". Least recently used transactions
+    /// are spilled to a temporary file on disk once the bound is reached
+    #[structopt(
+        long,
+        conflicts_with_all = &["store", "store-path", "parallel", "workers", "actors", "partitions"],
+        parse(try_from_str = parse_max_memory)
+    )]
+    max_memory: Option<usize>,
+    /// Pack transactions kept in memory into a fraction of the memory they otherwise
+    /// use, such as for days of hundreds of millions of transactions
+    #[structopt(
+        long,
+        conflicts_with_all = &["store", "store-path", "max-memory", "parallel", "workers", "actors", "partitions"]
+    )]
+    compact: bool,
+    /// Skip reading a sled store for transactions which were never stored, such as the
+    /// duplicate checks of new deposits and withdrawals, with a bloom filter of stored
+    /// transaction ids sized for this many transactions. A PostgreSQL store may be
+    /// written by other instances, whose transactions the filter would miss
+    #[structopt(long, conflicts_with_all = &["max-memory", "compact", "dsn"])]
+    bloom_filter: Option<usize>,
+    /// The most connections to open to the PostgreSQL database
+    #[structopt(long, default_value = "4")]
+    pool_size: u32,
+    /// Report the balances of each client at the end of every "hourly" or "daily"
+    /// time bucket, rather than only at the end of processing
+    #[structopt(long, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    history: Option<Bucket>,
+    /// Write every client's balances after each applied event to this file, for
+    /// loading into a time-series database
+    #[structopt(long, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    tsdb_export: Option<String>,
+    /// The format of the time-series export, either "influx" line protocol or "sql"
+    #[structopt(long, default_value = "influx")]
+    tsdb_format: TsdbFormat,
+    /// Write Prometheus metrics describing the run to this file once processing
+    /// completes, for collection by the node exporter's textfile collector
+    #[structopt(long, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    metrics_textfile: Option<String>,
+    /// Write statistics of the run to this JSON file once processing completes: the
+    /// number of events applied and rejected of each type, of invalid records, of
+    /// clients and of frozen accounts, and the total funds of every client
+    #[structopt(long, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    summary: Option<String>,
+    /// Show the number of records read per second and the percentage of the input
+    /// files read so far on stderr while processing them
+    #[structopt(long, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    progress: bool,
+    /// Watch this directory for new input files rather than reading input files,
+    /// processing each on top of the client accounts of those before it once it stops
+    /// changing, then moving it to the "processed" subdirectory, or "failed" if it
+    /// couldn't be read. The report is written after each file
+    #[structopt(
+        long,
+        conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io", "strict", "summary", "progress"]
+    )]
+    watch: Option<String>,
+    /// Keep reading the input file as rows are appended to it, like `tail -f`, until
+    /// interrupted with Ctrl-C, then write the report
+    #[structopt(
+        long,
+        conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io", "merge-by-timestamp", "watch"]
+    )]
+    follow: bool,
+    /// How often to check the --watch directory for new files, e.g. "10s"
+    #[structopt(long, default_value = "5s")]
+    watch_interval: Period,
+    /// Serve Prometheus metrics with GET /metrics on this address while consuming from
+    /// Kafka or watching a directory, e.g. "127.0.0.1:9100". The HTTP service serves
+    /// its own at /metrics
+    #[structopt(long)]
+    metrics_listen: Option<String>,
+    /// Export traces and metrics to the OpenTelemetry collector at this OTLP/HTTP
+    /// endpoint, e.g. "http://localhost:4318"
+    #[structopt(long, parse(try_from_str = http::parse), conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    otel_endpoint: Option<Url>,
+    /// Record a span for one in every N applied events when exporting traces
+    #[structopt(long, default_value = "0")]
+    otel_event_sample: u64,
+    /// How often services export traces and metrics, as they have no end of run to
+    /// export them at, e.g. "10s"
+    #[structopt(long, default_value = "10s")]
+    otel_export_interval: Period,
+    /// Emit metrics to the StatsD agent at this address as events are applied, e.g.
+    /// "localhost:8125"
+    #[structopt(long, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    statsd_host: Option<String>,
+    /// The prefix of every metric name emitted to StatsD
+    #[structopt(long, default_value = "payments")]
+    statsd_prefix: String,
+    /// The StatsD dialect to emit, either "statsd" or "dogstatsd"
+    #[structopt(long, default_value = "dogstatsd")]
+    statsd_flavor: StatsdFlavor,
+    /// A "key:value" tag attached to every metric emitted to DogStatsD. May be given
+    /// multiple times
+    #[structopt(long = "statsd-tag", number_of_values = 1)]
+    statsd_tags: Vec<String>,
+    /// Raise an alert when a rule's condition is met: "account-locked",
+    /// "reject-rate=PERCENT" or "ingestion-lag=SECONDS". May be given multiple times
+    #[structopt(long = "alert", number_of_values = 1, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    alert_rules: Vec<AlertRule>,
+    /// Post alerts as JSON to this URL. May be given multiple times
+    #[structopt(long = "alert-webhook", number_of_values = 1, parse(try_from_str = http::parse))]
+    alert_webhooks: Vec<Url>,
+    /// Email alerts to this address. May be given multiple times
+    #[structopt(long = "alert-email", number_of_values = 1)]
+    alert_emails: Vec<String>,
+    /// The sendmail-compatible command used to send alert emails
+    #[structopt(long, default_value = "sendmail")]
+    alert_sendmail: String,
+    /// Trigger PagerDuty incidents for alerts using this Events API v2 routing key
+    #[structopt(long)]
+    alert_pagerduty_key: Option<String>,
+    /// Post a JSON notification to this URL whenever an account is frozen or a
+    /// chargeback is applied, while serving or watching a directory. May be given
+    /// multiple times
+    #[structopt(long = "webhook", number_of_values = 1, parse(try_from_str = http::parse))]
+    webhooks: Vec<Url>,
+    /// How many times to retry a webhook notification which could not be sent
+    #[structopt(long, default_value = "5")]
+    webhook_retries: u32,
+    /// How long to wait before first retrying a webhook notification, doubling with
+    /// each retry after it, e.g. "1s"
+    #[structopt(long, default_value = "1s")]
+    webhook_backoff: Period,
+    /// Reject events matching any of the rules in this file, e.g.
+    /// "reject when type == withdrawal and amount > 10000"
+    #[structopt(long)]
+    rules: Option<String>,
+    /// A CSV file of client attributes, with a "client" column followed by a column
+    /// for each attribute, which rules may refer to as "client.<attribute>"
+    #[structopt(long)]
+    client_attributes: Option<String>,
+    /// A Rhai script defining an "on_event(event, account)" function, which decides
+    /// whether each event is allowed, denied or transformed before it is applied
+    #[structopt(long, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    script: Option<String>,
+    /// Score the risk of every client from its dispute rate, chargebacks and event
+    /// velocity, reported in an additional "risk" column
+    #[structopt(long, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    risk: bool,
+    /// The weights of each risk factor, e.g. "dispute=50,chargeback=25,velocity=0.5"
+    #[structopt(long, default_value = "")]
+    risk_weights: RiskWeights,
+    /// Stop at the first invalid record or rejected event, exiting with a non-zero
+    /// status and the offending line without writing any reports
+    #[structopt(long, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    strict: bool,
+    /// Check every client's balances against its transactions after each event it
+    /// applies, exiting with a non-zero status at the first which drifted, such as when
+    /// developing a new store
+    #[structopt(long, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    verify: bool,
+    /// Write every rejected record to this CSV file, with the columns of a payment
+    /// record followed by the "reason" it was rejected, so that dropped records can be
+    /// reconciled
+    #[structopt(long, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    rejects: Option<String>,
+    /// Append every event applied or rejected to this JSON Lines file, with the reason
+    /// for any rejection and the client's resulting balances, as an audit trail
+    #[structopt(long, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    audit_log: Option<String>,
+    /// Write deposits and withdrawals with unusual amounts for their client to this
+    /// CSV file for review. Flagged events are still applied
+    #[structopt(long, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    anomaly_report: Option<String>,
+    /// The number of standard deviations from a client's mean amount beyond which an
+    /// event is flagged as unusual
+    #[structopt(long, default_value = "3")]
+    anomaly_threshold: f64,
+    /// A CSV file with "client" and "parent" columns, relating sub-accounts to the
+    /// parent clients they roll up into. Parents report the combined balances of their
+    /// sub-accounts, and locking a parent locks its sub-accounts
+    #[structopt(long, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    account_hierarchy: Option<String>,
+    /// A CSV file with "client" and "account" columns, mapping the client ids of the
+    /// members of joint accounts onto the single account they share
+    #[structopt(long, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    joint_accounts: Option<String>,
+    /// A CSV file with "external" and "client" columns, mapping the identifiers
+    /// partners use for their clients onto internal client ids. Input files may use
+    /// either, and reports use the external ids
+    #[structopt(long)]
+    client_aliases: Option<String>,
+    /// A CSV file of scheduled and recurring payments, with "client", "type", "amount",
+    /// "start" and "every" columns, e.g. "1,withdrawal,500,2024-01-01,30d". Payments
+    /// are applied as the timestamps of processed events pass each time they fall due
+    #[structopt(long, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    schedule: Option<String>,
+    /// After processing, write every transaction still under dispute to this CSV file,
+    /// with the amount held and how many events ago the dispute was opened
+    #[structopt(long, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    open_disputes: Option<String>,
+    /// Fail the run, without writing any reports, if more than this many accounts are
+    /// locked by chargebacks during it
+    #[structopt(long, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    max_locked_accounts: Option<usize>,
+    /// After processing, write a clearing file of the payouts owed to each unlocked
+    /// client from its available funds to this file
+    #[structopt(long, conflicts_with_all = &["parallel", "workers", "actors", "partitions", "async-io"])]
+    clearing_file: Option<String>,
+    /// The format of the clearing file, either "csv" or ISO 20022 "pain.001". Client
+    /// names and IBANs are taken from the "name" and "iban" client attributes
+    #[structopt(long, default_value = "csv")]
+    clearing_format: ClearingFormat,
+    /// The name of the account holder payouts are made from
+    #[structopt(long, default_value = "payment-processor")]
+    clearing_debtor_name: String,
+    /// The IBAN of the account payouts are made from, required for pain.001 files
+    #[structopt(long)]
+    clearing_debtor_iban: Option<String>,
+    /// The currency of the payouts in pain.001 files
+    #[structopt(long, default_value = "EUR")]
+    clearing_currency: String,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+    /// The CSV files containing payment events, or "-" to read from stdin, which is
+    /// also read when no files are given
+    input_files: Vec<String>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Apply new events on top of the client balances and transactions saved in a
+    /// checkpoint by a previous run, then update the checkpoint
+    Process {
+        /// The checkpoint to carry on from and update. A run starts from genesis if it
+        /// does not exist yet
+        #[structopt(long)]
+        state: String,
+        /// The CSV files containing payment events, or "-" to read from stdin
+        input_files: Vec<String>,
+    },
+    /// Report each client's available balance projected over a horizon from the latest
+    /// event, given their scheduled payments, flagging projected overdrafts
+    Project {
+        /// How far ahead to project balances, e.g. "30d"
+        #[structopt(long)]
+        horizon: Period,
+        /// The CSV files containing payment events, or "-" to read from stdin
+        input_files: Vec<String>,
+    },
+    /// Report the payments settling the net change in each client's funds over a
+    /// period, netted into as few payments between clients as possible
+    Settle {
+        /// Only settle events at or after this timestamp or "YYYY-MM-DD" date
+        #[structopt(long, parse(try_from_str = schedule::parse_time))]
+        from: Option<u64>,
+        /// Only settle events before this timestamp or "YYYY-MM-DD" date
+        #[structopt(long, parse(try_from_str = schedule::parse_time))]
+        until: Option<u64>,
+        /// The CSV files containing payment events, or "-" to read from stdin
+        input_files: Vec<String>,
+    },
+    /// Check input files before running them, reporting every invalid record and every
+    /// event which would be rejected with its file and line, and exiting with a
+    /// non-zero status if there were any. Nothing is applied to the store
+    Validate {
+        /// The CSV files containing payment events, or "-" to read from stdin
+        input_files: Vec<String>,
+    },
+    /// Run as a long-lived service, applying events as they arrive rather than reading
+    /// input files
+    Serve {
+        #[structopt(subcommand)]
+        service: Service,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum Service {
+    /// Consume events from a Kafka topic, one JSON record per message, committing the
+    /// consumer group's offsets once messages have been processed
+    Kafka {
+        /// A broker to bootstrap from, e.g. "localhost:9092". May be given multiple
+        /// times
+        #[structopt(long = "broker", number_of_values = 1, required = true)]
+        brokers: Vec<String>,
+        /// The topic payment events are published to
+        #[structopt(long)]
+        topic: String,
+        /// The consumer group to join, sharing the topic's partitions between its
+        /// members
+        #[structopt(long, default_value = "payment-processor")]
+        group: String,
+        /// Append every message which is not a valid record, or whose event is
+        /// rejected, to this JSON Lines file with the "reason" why, so that it can be
+        /// repaired and replayed
+        #[structopt(long, conflicts_with = "dead-letter-topic")]
+        dead_letter_file: Option<String>,
+        /// Publish every message which is not a valid record, or whose event is
+        /// rejected, to this topic with the "reason" why, so that it can be repaired
+        /// and replayed
+        #[structopt(long)]
+        dead_letter_topic: Option<String>,
+    },
+    /// Serve a JSON API over HTTP, accepting events with POST /events and reporting
+    /// balances with GET /clients/{id}. Only validation rules are applied to events
+    /// submitted to the API
+    Http {
+        /// The address to listen on
+        #[structopt(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+        /// Also apply the rows appended to this file as it is written, like `tail -f`,
+        /// so that the balances served are kept up to date
+        #[structopt(long)]
+        follow: Option<String>,
+    },
+}
+
+impl Opt {
+    /// Returns the kind of transaction store selected.
+    fn store(&self) -> StoreKind {
+        match (self.store, &self.store_path) {
+            (Some(store), _) => store,
+            (None, Some(_)) => StoreKind::Sled,
+            (None, None) => StoreKind::Memory,
+        }
+    }
+
+    /// Checks that the options given, reading `input_files`, make sense together,
+    /// beyond the conflicts between them refused as the arguments are parsed.
+    fn check(&self, input_files: &[String]) -> Result<(), clap::Error> {
+        if input_files.iter().filter(|&file| file == STDIN).count() > 1 {
+            return Err(clap::Error::with_description(
+                "stdin may only be read once",
+                ErrorKind::InvalidValue,
+            ));
+        }
+        if let (Some(Command::Process { .. }), true) =
+            (&self.command, self.store() != StoreKind::Memory)
+        {
+            return Err(clap::Error::with_description(
+                "persistent stores already carry on from earlier runs, without a checkpoint",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if let (Some(Command::Process { .. } | Command::Serve { .. }), true) = (
+            &self.command,
+            self.checkpoint_every.is_some() || self.resume.is_some(),
+        ) {
+            return Err(clap::Error::with_description(
+                "--checkpoint-every and --resume only apply to processing input files",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if self.follow && (input_files.len() != 1 || input_files[0] == STDIN) {
+            return Err(clap::Error::with_description(
+                "--follow needs exactly one input file, as stdin is already read as it is written",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if self.watch.is_some()
+            && (self.command.is_some()
+                || !self.input_files.is_empty()
+                || self.checkpoint_every.is_some()
+                || self.resume.is_some())
+        {
+            return Err(clap::Error::with_description(
+                "--watch processes the files appearing in its directory, rather than input files",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if let (Some(Command::Serve { .. } | Command::Validate { .. }), Some(_)) =
+            (&self.command, &self.summary)
+        {
+            return Err(clap::Error::with_description(
+                "--summary is only written once input files are processed",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if let (Some(Command::Serve { .. } | Command::Validate { .. }), true) =
+            (&self.command, self.progress)
+        {
+            return Err(clap::Error::with_description(
+                "--progress is only shown while input files are processed",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if let (Some(Command::Process { .. }), true) =
+            (&self.command, self.max_memory.is_some() || self.compact)
+        {
+            return Err(clap::Error::with_description(
+                "checkpoints can only be taken of transactions kept wholly in memory, unpacked",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if let (Some(Command::Process { .. }), Some(_)) = (&self.command, &self.wal) {
+            return Err(clap::Error::with_description(
+                "--wal already carries on from where a run stopped, without a checkpoint",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if let (Some(horizon), Some(window)) = (self.prune_after, self.dispute_window) {
+            if horizon.seconds() < window.seconds() {
+                return Err(clap::Error::with_description(
+                    "--prune-after must be at least the --dispute-window",
+                    ErrorKind::InvalidValue,
+                ));
+            }
+        }
+        if let (Some(_), StoreKind::Memory) = (self.bloom_filter, self.store()) {
+            return Err(clap::Error::with_description(
+                "--bloom-filter only saves reads of a sled store",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if let (Some(Command::Serve { .. }), true) = (
+            &self.command,
+            self.parallel.is_some()
+                || self.async_io
+                || self.workers.is_some()
+                || self.actors
+                || self.partitions.is_some()
+                || self.strict,
+        ) {
+            return Err(clap::Error::with_description(
+                "services apply events one at a time as they arrive",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if !self.webhooks.is_empty()
+            && !matches!(self.command, Some(Command::Serve { .. }))
+            && self.watch.is_none()
+        {
+            return Err(clap::Error::with_description(
+                "--webhook notifies as events arrive, so only applies to services and --watch",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if self.metrics_listen.is_some()
+            && !matches!(
+                self.command,
+                Some(Command::Serve {
+                    service: Service::Kafka { .. }
+                })
+            )
+            && self.watch.is_none()
+        {
+            return Err(clap::Error::with_description(
+                "--metrics-listen only applies to consuming from Kafka and --watch",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        if input_files.iter().any(|file| file == STDIN)
+            && (self.manifest.is_some() || self.pubkey.is_some())
+        {
+            return Err(clap::Error::with_description(
+                "stdin cannot be verified against a manifest or signature",
+                ErrorKind::ArgumentConflict,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns the input files given either to the subcommand or the top-level command,
+    /// or stdin if none were given. Wildcards in file names are expanded to the files
+    /// matching them, in order of name, for shells which don't expand them.
+    fn input_files(&self) -> Vec<String> {
+        let input_files = match &self.command {
+            Some(Command::Process { input_files, .. })
+            | Some(Command::Project { input_files, .. })
+            | Some(Command::Settle { input_files, .. })
+            | Some(Command::Validate { input_files }) => input_files,
+            Some(Command::Serve { .. }) => return Vec::new(),
+            None => &self.input_files,
+        };
+        if input_files.is_empty() {
+            return vec![STDIN.to_string()];
+        }
+        let mut files = Vec::new();
+        for pattern in input_files {
+            match input::expand_glob(pattern) {
+                Ok(matches) => files.extend(matches),
+                Err(e) => {
+                    clap::Error::with_description(&format!("{:#}", e), ErrorKind::ValueValidation)
+                        .exit()
+                }
+            }
+        }
+        files
+    }
+
+    /// Returns the format of the input file at `path`.
+    fn input_format(&self, path: &str) -> InputFormat {
+        self.format.unwrap_or_else(|| InputFormat::detect(path))
+    }
+
+    /// Returns the compression of the input file at `path`.
+    fn input_compression(&self, path: &str) -> Compression {
+        self.compression
+            .unwrap_or_else(|| Compression::detect(path))
+    }
+
+    /// Returns how the fields of CSV input files are laid out.
+    fn csv_dialect(&self) -> CsvDialect {
+        CsvDialect {
+            delimiter: self.delimiter,
+            has_headers: !self.no_headers,
+            trim: self.trim,
+        }
+    }
+
+    /// Returns how often services export to the OpenTelemetry collector.
+    fn otel_export_interval(&self) -> Duration {
+        Duration::from_secs(self.otel_export_interval.seconds())
+    }
+
+    /// Returns an alerter evaluating the `--alert` rules, notifying each `--alert-*`
+    /// destination, or `None` if there are no rules.
+    fn alerter(&self) -> Option<Alerter> {
+        if self.alert_rules.is_empty() {
+            return None;
+        }
+        let mut sinks: Vec<AlertSink> = self
+            .alert_webhooks
+            .iter()
+            .cloned()
+            .map(AlertSink::Webhook)
+            .collect();
+        sinks.extend(self.alert_emails.iter().map(|to| AlertSink::Email {
+            to: to.clone(),
+            sendmail: self.alert_sendmail.clone(),
+        }));
+        sinks.extend(self.alert_pagerduty_key.clone().map(AlertSink::PagerDuty));
+        Some(Alerter::new(self.alert_rules.clone(), sinks))
+    }
+
+    /// Returns a notifier for each `--webhook` URL.
+    fn webhook_notifiers(&self) -> Vec<WebhookNotifier> {
+        let backoff = Duration::from_secs(self.webhook_backoff.seconds());
+        self.webhooks
+            .iter()
+            .map(|url| WebhookNotifier::new(url.clone(), self.webhook_retries, backoff))
+            .collect()
+    }
+
+    /// Returns the options changing how events are applied to client accounts.
+    fn policy(&self) -> Policy {
+        Policy {
+            unlock_on_resolve: self.unlock_on_resolve,
+            insufficient_funds: self.dispute_insufficient_funds,
+            dispute_withdrawals: self.dispute_withdrawals,
+            allow_admin_events: self.allow_admin_events,
+            dispute_window: self.dispute_window.map(|window| window.seconds()),
+        }
+    }
+
+    /// Returns the pruner of settled transactions older than `--prune-after`, if given.
+    fn pruner(&self) -> Option<Pruner> {
+        self.prune_after
+            .map(|horizon| Pruner::new(horizon.seconds()))
+    }
+
+    /// Opens the `--wal`, if given, replaying the writes logged to it into `store`, and
+    /// returns the store with its writes logged, the log, truncated to snapshots of the
+    /// store as it grows, and the event a crash interrupted, if any, to be applied again.
+    fn recover(&self, mut store: Backend) -> (Backend, Option<WriteAheadLog>, Option<Event>) {
+        let Some(path) = &self.wal else {
+            return (store, None, None);
+        };
+        let recovered = WriteAheadLog::open(path)
+            .and_then(|mut wal| wal.replay(&mut store).map(|interrupted| (wal, interrupted)));
+        let (wal, interrupted) = match recovered {
+            Ok(recovered) => recovered,
+            Err(e) => {
+                // reported even without --verbose, as the store can't be recovered
+                eprintln!("error: recovering --wal: {:#}", e);
+                std::process::exit(1);
+            }
+        };
+        let snapshotted = store.clone();
+        let wal = wal.with_snapshots(move || snapshotted.snapshot());
+        let store = Backend::Logged(Box::new(wal.wrap(store)));
+        (store, Some(wal), interrupted)
+    }
+
+    /// Returns everything observing the outcome of each event: the `--statsd-host`,
+    /// the `--otel-endpoint` and the `--alert` rules, if given.
+    fn telemetry(&self) -> Telemetry {
+        Telemetry {
+            statsd: self.statsd_host.as_ref().map(|host| {
+                StatsdEmitter::connect(
+                    host.as_str(),
+                    &self.statsd_prefix,
+                    self.statsd_flavor,
+                    self.statsd_tags.clone(),
+                )
+                .unwrap()
+            }),
+            tracing: self.otel_endpoint.clone().map(|endpoint| {
+                Tracing::new(
+                    OtlpExporter::new(endpoint, "payment-processor"),
+                    self.otel_event_sample,
+                )
+            }),
+            alerts: self.alerter(),
+            ..Telemetry::default()
+        }
+    }
+
+    /// Returns the processor applying events to the accounts in `store`, with its writes
+    /// logged to `wal`, once they pass `rules` and the `--script`, if given.
+    fn processor(&self, store: Backend, wal: Option<WriteAheadLog>, rules: RuleSet) -> Processor {
+        Processor {
+            script: self
+                .script
+                .as_ref()
+                .map(|path| ScriptHook::load(path).unwrap()),
+            hierarchy: match &self.account_hierarchy {
+                Some(path) => AccountHierarchy::load(path).unwrap(),
+                None => AccountHierarchy::default(),
+            },
+            telemetry: self.telemetry(),
+            rejects: self
+                .rejects
+                .as_ref()
+                .map(|path| RejectsWriter::create(path).unwrap()),
+            audit: self.audit_log.as_ref().map(|path| -> SharedAuditLog {
+                Arc::new(Mutex::new(FileAuditLog::open(path).unwrap()))
+            }),
+            parking: self.park_disputes.map(DisputeParking::new),
+            expiry: self.dispute_expiry.map(DisputeExpiry::new),
+            pruner: self.pruner(),
+            wal,
+            strict: self.strict,
+            verify: self.verify,
+            ..Processor::new(store, rules, self.policy())
+        }
+    }
+
+    /// Opens the transaction store client accounts are kept in.
+    fn backend(&self) -> Backend {
+        let backend = match self.store() {
+            StoreKind::Memory => match self.max_memory {
+                Some(bytes) => Backend::Spill(SpillStore::with_max_memory(bytes)),
+                None if self.compact => Backend::Compact(CompactStore::new()),
+                None => Backend::default(),
+            },
+            StoreKind::Sled => {
+                Backend::Sled(SledStore::open(self.store_path.as_ref().unwrap()).unwrap())
+            }
+            StoreKind::Postgres => Backend::Postgres(
+                PostgresStore::connect(self.dsn.as_ref().unwrap(), self.pool_size).unwrap(),
+            ),
+        };
+        match self.bloom_filter {
+            Some(expected) => Backend::Bloom(Box::new(BloomStore::new(backend, expected))),
+            None => backend,
+        }
+    }
+}
+
+/// The input file name standing for stdin.
+const STDIN: &str = "-";
+
+/// How often to check a followed file for appended rows.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Set by the first SIGINT, to stop reading input files at the next record.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// The aliases of client ids, loaded once for the whole run so that input files read
+/// ahead on threads of their own can resolve them.
+static ALIASES: OnceLock<ClientAliases> = OnceLock::new();
+
+/// Stops reading input files at the first SIGINT rather than exiting, so that the
+/// records processed so far are still reported. A second SIGINT exits straight away, as
+/// the run may be waiting on stdin.
+#[cfg(unix)]
+fn stop_on_interrupt() {
+    extern "C" fn interrupted(_: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+        // SAFETY: signal is async-signal-safe
+        unsafe { libc::signal(libc::SIGINT, libc::SIG_DFL) };
+    }
+    // SAFETY: the handler only stores to an atomic and restores the default handler
+    unsafe { libc::signal(libc::SIGINT, interrupted as *const () as libc::sighandler_t) };
+}
+
+#[cfg(not(unix))]
+fn stop_on_interrupt() {}
+
+/// The payment records of an input file, or of stdin.
+type Source<'a> = Box<dyn EventSource + Send + 'a>;
+
+/// Returns the source of the payment records of the file at `path`, or of stdin if
+/// `path` is [STDIN], with client ids resolved through `aliases`. Files are read in the
+/// `--format`, or the format detected from their extension if not given.
+fn open_source<'a>(path: &str, opt: &Opt, aliases: &'a ClientAliases) -> Source<'a> {
+    let (format, compression) = (opt.input_format(path), opt.input_compression(path));
+    if path == STDIN {
+        Box::new(StdinSource::new(format, compression, opt.csv_dialect(), aliases).unwrap())
+    } else {
+        read_source(path, Box::new(File::open(path).unwrap()), opt, aliases).unwrap()
+    }
+}
+
+/// Returns the source of the payment records read from `input`, opened from `path`,
+/// such as through a reader following the file or counting its progress.
+fn read_source<'a>(
+    path: &str,
+    input: Box<dyn Read + Send>,
+    opt: &Opt,
+    aliases: &'a ClientAliases,
+) -> Result<Source<'a>> {
+    let name = if path == STDIN { "stdin" } else { path };
+    Ok(Box::new(FileSource::new(
+        name,
+        input,
+        opt.input_format(path),
+        opt.input_compression(path),
+        opt.csv_dialect(),
+        aliases,
+    )?))
+}
+
+/// The columns of a report of client balances.
+const SUMMARY_COLUMNS: [&str; 5] = ["client", "available", "held", "total", "locked"];
+
+/// Returns an amount as reported, to four decimal places.
+fn amount(amount: Decimal) -> Value {
+    json!(format_amount(amount))
+}
+
+/// Returns the columns of a report of the balances in `summaries`, with a `currency`
+/// column after the client once any balances are in a currency other than the base
+/// currency. The `status` of each account is reported last if requested, and otherwise
+/// whether it is `closed` once any accounts are closed.
+fn summary_columns(summaries: &[Summary], status: bool) -> Vec<&'static str> {
+    let mut columns = SUMMARY_COLUMNS.to_vec();
+    if summaries.iter().any(|summary| summary.currency.is_some()) {
+        columns.insert(1, "currency");
+    }
+    if status {
+        columns.push("status");
+    } else if summaries
+        .iter()
+        .any(|summary| summary.status == AccountStatus::Closed)
+    {
+        columns.push("closed");
+    }
+    columns
+}
+
+/// Returns the row of a report of client balances for `summary`, with its currency,
+/// status and whether its account is closed if the report has `currency`, `status` and
+/// `closed` `columns`.
+fn summary_row(summary: &Summary, aliases: &ClientAliases, columns: &[&str]) -> Vec<Value> {
+    let mut row = vec![
+        json!(aliases.name(summary.id)),
+        amount(summary.available),
+        amount(summary.held),
+        amount(summary.total),
+        json!(summary.locked),
+    ];
+    if columns.contains(&"currency") {
+        row.insert(1, json!(summary.currency.map(|c| c.to_string())));
+    }
+    if columns.contains(&"status") {
+        row.push(json!(summary.status.name()));
+    }
+    if columns.contains(&"closed") {
+        row.push(json!(summary.status == AccountStatus::Closed));
+    }
+    row
+}
+
+/// Returns a report of the balances in `summaries`, with a row for each client and
+/// currency.
+fn summary_report(summaries: &[Summary], aliases: &ClientAliases, status: bool) -> Report {
+    let columns = summary_columns(summaries, status);
+    let mut report = Report::new(&columns);
+    for summary in summaries {
+        let row = summary_row(summary, aliases, &columns);
+        report.push_client(summary.id, row);
+    }
+    report
+}
+
+/// Writes `report` in the `--output-format` to the `--output` file, or stdout if not
+/// given, ordered by client with `--sorted` and encrypted to the `--encrypt-to`
+/// recipients if there are any.
+fn write_report(opt: &Opt, mut report: Report) {
+    if opt.sorted {
+        report.sort_by_client();
+    }
+    let mut out = Vec::new();
+    report
+        .write(opt.output_format.sink(&mut out).as_mut())
+        .unwrap();
+    if !opt.encrypt_to.is_empty() {
+        out = encryption::encrypt(&out, &opt.encrypt_to).unwrap();
+    }
+    match &opt.output {
+        Some(path) => fs::write(path, out).unwrap(),
+        None => io::stdout().write_all(&out).unwrap(),
+    }
+}
+
+/// Parses a `--max-memory` size in bytes, with an optional K, M or G suffix.
+fn parse_max_memory(s: &str) -> Result<usize> {
+    let (digits, unit) = match s.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&s[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&s[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&s[..i], 1 << 30),
+        _ => (s, 1),
+    };
+    let n: usize = digits
+        .parse()
+        .with_context(|| format!("invalid size {:?}", s))?;
+    n.checked_mul(unit)
+        .ok_or_else(|| anyhow!("size {:?} is too large", s))
+}
+
+/// Parses a `--delimiter`, which must be a single ASCII character or "tab".
+fn parse_delimiter(s: &str) -> Result<u8> {
+    match s {
+        "tab" | "\\t" => Ok(b'\t'),
+        s if s.len() == 1 && s.is_ascii() => Ok(s.as_bytes()[0]),
+        s => bail!(
+            "invalid delimiter {:?}, expected a single character or tab",
+            s
+        ),
+    }
+}
+
+/// Parses the number of `--workers`, of which there must be at least one.
+fn parse_workers(s: &str) -> Result<usize> {
+    match s.parse()? {
+        0 => bail!("at least one worker is needed"),
+        n => Ok(n),
+    }
+}
+
+/// Parses the number of `--partitions`, of which there must be at least one.
+fn parse_partitions(s: &str) -> Result<usize> {
+    match s.parse()? {
+        0 => bail!("at least one partition is needed"),
+        n => Ok(n),
+    }
+}
+
+/// Checks `input_files` without applying their events to the run's store, printing
+/// every invalid record and every event which would be rejected along with its file and
+/// line, and returns how many there were. Events are applied in turn to empty in-memory
+/// accounts, so that each is checked against those before it.
+fn validate(opt: &Opt, input_files: &[String], rules: &RuleSet, aliases: &ClientAliases) -> u64 {
+    let mut book = Book::new(MemoryStore::new(), opt.policy());
+    let mut errors = 0;
+    for path in input_files {
+        let mut source = open_source(path, opt, aliases);
+        let header = source.header_lines();
+        let name = source.name().to_string();
+        for (i, entry) in source.by_ref().enumerate() {
+            let line = i as u64 + 1 + header;
+            let location = match &entry {
+                Ok(record) => Position {
+                    source: name.clone(),
+                    line,
+                    record: record.to_string(),
+                }
+                .to_string(),
+                Err(_) => format!("{} line {}", name, line),
+            };
+            let applied = parse_entry(entry, opt.legacy_tx_ids, opt.rounding)
+                .and_then(|event| book.apply(&event, rules).map(drop));
+            if let Err(e) = applied {
+                println!("{}: {:#}", location, e);
+                errors += 1;
+            }
+        }
+    }
+    errors
+}
+
+/// Stops a `--strict` run at the first error, `message`, found at `location`, without
+/// writing any reports.
+fn abort(processor: &mut Processor, location: &str, message: &str) -> ! {
+    if let Some(rejects) = processor.rejects.as_mut() {
+        if let Err(e) = rejects.flush() {
+            error!("writing rejects: {:?}", e);
+        }
+    }
+    // reported even without --verbose, as the run's outcome
+    eprintln!("error: stopped at {}: {}", location, message);
+    std::process::exit(1);
+}
+
+/// Runs the command line utility with the options given.
+pub fn run(opt: Opt) {
+    if opt.verbose {
+        // spans are kept whatever their level, so that the messages within them are
+        // tagged with the event being processed
+        let filter = filter_fn(|metadata| metadata.is_span() || *metadata.level() <= Level::WARN);
+        let logs = tracing_subscriber::fmt::layer().with_writer(io::stderr);
+        match opt.log_format.as_str() {
+            "json" => tracing_subscriber::registry()
+                .with(logs.json().with_span_list(false).with_filter(filter))
+                .init(),
+            _ => tracing_subscriber::registry()
+                .with(logs.with_filter(filter))
+                .init(),
+        }
+    }
+
+    let input_files = &opt.input_files();
+    if let Err(e) = opt.check(input_files) {
+        e.exit();
+    }
+    if let Some(path) = &opt.manifest {
+        let manifest = Manifest::load(path).unwrap();
+        for file in input_files {
+            manifest.verify(file).unwrap();
+        }
+    }
+    if let Some(path) = &opt.pubkey {
+        let key = PublicKey::load(path).unwrap();
+        key.verify_files(input_files, &opt.signatures).unwrap();
+    }
+
+    let mut rules = match &opt.rules {
+        Some(path) => RuleSet::load(path).unwrap(),
+        None => RuleSet::default(),
+    };
+    let attributes = match &opt.client_attributes {
+        Some(path) => rules::load_client_attributes(path).unwrap(),
+        None => Default::default(),
+    };
+    rules = rules.with_client_attributes(attributes.clone());
+    let aliases = ALIASES.get_or_init(|| match &opt.client_aliases {
+        Some(path) => ClientAliases::load(path).unwrap(),
+        None => ClientAliases::default(),
+    });
+    if let Some(Command::Validate { .. }) = &opt.command {
+        let errors = validate(&opt, input_files, &rules, aliases);
+        if errors > 0 {
+            // reported even without --verbose, as the run's outcome
+            eprintln!("error: found {} invalid records or rejected events", errors);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(mode) = opt.parallel {
+        let sources = input_files
+            .iter()
+            .map(|path| open_source(path, &opt, aliases))
+            .collect();
+        let books = parallel::process(mode, sources, &rules, |entry| {
+            parse_entry(entry, opt.legacy_tx_ids, opt.rounding)
+        });
+        match mode {
+            ParallelMode::Shared => {
+                let summaries = books[0].summaries();
+                write_report(
+                    &opt,
+                    summary_report(&summaries, aliases, opt.account_status),
+                );
+            }
+            ParallelMode::Isolated => {
+                let summaries: Vec<Vec<Summary>> = books.iter().map(Book::summaries).collect();
+                let balances = summary_columns(&summaries.concat(), opt.account_status);
+                let columns: Vec<&str> = ["file"].into_iter().chain(balances.clone()).collect();
+                let mut report = Report::new(&columns);
+                for (file, summaries) in input_files.iter().zip(&summaries) {
+                    // each book's balances are already ordered, so files stay together
+                    for summary in summaries {
+                        let mut row = vec![json!(file)];
+                        row.extend(summary_row(summary, aliases, &balances));
+                        report.push(row);
+                    }
+                }
+                write_report(&opt, report);
+            }
+        }
+        return;
+    }
+    if let Some(Command::Serve {
+        service: Service::Http { listen, follow },
+    }) = &opt.command
+    {
+        let (legacy_tx_ids, rounding) = (opt.legacy_tx_ids, opt.rounding);
+        let metrics = SharedMetrics::default();
+        let (store, wal, interrupted) = opt.recover(opt.backend());
+        let store = TimedStore::new(store, Arc::clone(&metrics));
+        let mut book = Book::new(store, opt.policy());
+        if let Some(pruner) = opt.pruner() {
+            book = book.with_pruner(pruner);
+        }
+        if let Some(wal) = wal {
+            book = book.with_wal(wal);
+        }
+        if let Some(event) = interrupted {
+            warn!(
+                "applying {:?} again, as it was interrupted by a crash",
+                event
+            );
+            if let Err(e) = book.apply(&event, &rules) {
+                error!("{:?}", e);
+            }
+        }
+        let service = HttpService::new(book, rules, aliases.clone(), metrics, move |entry| {
+            parse_entry(entry, legacy_tx_ids, rounding)
+        });
+        for notifier in opt.webhook_notifiers() {
+            service.notify(notifier);
+        }
+        if let Some(alerter) = opt.alerter() {
+            service.alert(alerter);
+        }
+        if let Some(endpoint) = &opt.otel_endpoint {
+            let exporter = Arc::new(OtlpExporter::new(endpoint.clone(), "payment-processor"));
+            service.trace(Arc::clone(&exporter), opt.otel_event_sample);
+            exporter.export_every(service.metrics(), opt.otel_export_interval());
+        }
+        if let Some(path) = follow {
+            let reader = FollowReader::new(File::open(path).unwrap(), FOLLOW_INTERVAL);
+            service.follow(reader, opt.input_format(path), opt.csv_dialect());
+        }
+        let listener = TcpListener::bind(listen).unwrap();
+        if let Err(e) = service.run(listener) {
+            error!("serving {}: {:?}", listen, e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(workers) = opt.workers {
+        let sources = input_files
+            .iter()
+            .map(|path| open_source(path, &opt, aliases))
+            .collect();
+        let summaries =
+            parallel::process_sharded(workers, sources, &rules, opt.policy(), |entry| {
+                parse_entry(entry, opt.legacy_tx_ids, opt.rounding)
+            });
+        write_report(
+            &opt,
+            summary_report(&summaries, aliases, opt.account_status),
+        );
+        return;
+    }
+    if let Some(partitions) = opt.partitions {
+        let sources = input_files
+            .iter()
+            .map(|path| open_source(path, &opt, aliases))
+            .collect();
+        let summaries =
+            parallel::process_partitioned(partitions, sources, &rules, opt.policy(), |entry| {
+                parse_entry(entry, opt.legacy_tx_ids, opt.rounding)
+            })
+            .unwrap();
+        write_report(
+            &opt,
+            summary_report(&summaries, aliases, opt.account_status),
+        );
+        return;
+    }
+    if opt.actors {
+        let sources = input_files
+            .iter()
+            .map(|path| open_source(path, &opt, aliases))
+            .collect();
+        let summaries = actors::process(sources, &rules, opt.policy(), |entry| {
+            parse_entry(entry, opt.legacy_tx_ids, opt.rounding)
+        })
+        .unwrap();
+        write_report(
+            &opt,
+            summary_report(&summaries, aliases, opt.account_status),
+        );
+        return;
+    }
+    if opt.async_io {
+        let store = opt.backend();
+        let sources = input_files
+            .iter()
+            .map(|path| open_source(path, &opt, aliases))
+            .collect();
+        let summaries = asynchronous::process(
+            BlockingStore::new(store.clone()),
+            sources,
+            &rules,
+            opt.policy(),
+            |entry| parse_entry(entry, opt.legacy_tx_ids, opt.rounding),
+        )
+        .unwrap();
+        store.flush().unwrap();
+        write_report(
+            &opt,
+            summary_report(&summaries, aliases, opt.account_status),
+        );
+        return;
+    }
+    let joint = match &opt.joint_accounts {
+        Some(path) => JointAccounts::load(path).unwrap(),
+        None => JointAccounts::default(),
+    };
+    let schedule = match &opt.schedule {
+        Some(path) => Schedule::load(path).unwrap(),
+        None => Schedule::default(),
+    };
+    let clearing = opt.clearing_file.as_ref().map(|path| {
+        let debtor = Debtor {
+            name: opt.clearing_debtor_name.clone(),
+            iban: opt.clearing_debtor_iban.clone(),
+            currency: opt.clearing_currency.clone(),
+        };
+        (
+            ClearingFile::new(opt.clearing_format, debtor).unwrap(),
+            path,
+        )
+    });
+    let mut scheduler = Scheduler::new(schedule.clone());
+    let mut sequences = SequenceTracker::default();
+    let mut dedup = opt.dedup_window.map(Deduplicator::new);
+    let mut reorder = opt.reorder_window.map(ReorderBuffer::new);
+    let mut history = opt.history.map(BalanceHistory::new);
+    let mut tsdb = opt.tsdb_export.as_ref().map(|path| {
+        TsdbExporter::new(BufWriter::new(File::create(path).unwrap()), opt.tsdb_format)
+    });
+    let (store, wal, interrupted) = opt.recover(opt.backend());
+    let mut processor = opt.processor(store, wal, rules);
+    if opt.store() != StoreKind::Memory || opt.wal.is_some() {
+        processor.resume();
+    }
+    if let Some(event) = interrupted {
+        warn!(
+            "applying {:?} again, as it was interrupted by a crash",
+            event
+        );
+        // rejections are already logged
+        let _ = processor.process(event, &mut |_, _| {});
+    }
+    if let Some(Command::Process { state, .. }) = &opt.command {
+        if Path::new(state).exists() {
+            processor
+                .restore(&Checkpoint::load(state).unwrap())
+                .unwrap();
+        } else {
+            warn!("no checkpoint at {}, starting from genesis", state);
+        }
+    }
+    let mut skip = 0;
+    if let Some(path) = &opt.resume {
+        let checkpoint = Checkpoint::load(path).unwrap();
+        processor.restore(&checkpoint).unwrap();
+        skip = checkpoint.records();
+        info!("resuming from {} after {} records", path, skip);
+    }
+    let mut risk = opt.risk.then(|| RiskScorer::new(opt.risk_weights));
+    let mut disputes = opt.open_disputes.as_ref().map(|_| OpenDisputes::default());
+    let mut lockouts = Lockouts::default();
+    let mut settlement = match &opt.command {
+        Some(Command::Settle { from, until, .. }) => Some(Settlement::new(*from, *until)),
+        _ => None,
+    };
+    let mut anomalies = opt.anomaly_report.as_ref().map(|path| {
+        (
+            AnomalyDetector::new(opt.anomaly_threshold),
+            csv::Writer::from_path(path).unwrap(),
+        )
+    });
+    let mut webhooks = opt.webhook_notifiers();
+    if let Some(addr) = &opt.metrics_listen {
+        let listener = TcpListener::bind(addr).unwrap();
+        server::serve_metrics(listener, Arc::clone(&processor.telemetry.metrics));
+    }
+    let mut file_spans: Vec<Option<(Span, u64)>> = input_files.iter().map(|_| None).collect();
+    let mut clock = None;
+    let mut on_applied = |event: &Event, summary: Summary| {
+        if let Some(risk) = risk.as_mut() {
+            risk.observe(event);
+        }
+        if let Some(settlement) = settlement.as_mut() {
+            settlement.observe(event, &summary);
+        }
+        if let Some(disputes) = disputes.as_mut() {
+            disputes.observe(event);
+        }
+        lockouts.observe(event, &summary);
+        for webhook in &mut webhooks {
+            webhook.observe(event, &summary);
+        }
+        if let Some((detector, report)) = anomalies.as_mut() {
+            if let Some(anomaly) = detector.observe(event) {
+                warn!("{:?} has an unusual amount for the client", event);
+                if let Err(e) = report.serialize(anomaly) {
+                    error!("writing anomaly report: {:?}", e);
+                }
+            }
+        }
+        if let Some(history) = history.as_mut() {
+            history.record(event.timestamp(), summary);
+        }
+        if let Some(tsdb) = tsdb.as_mut() {
+            if let Err(e) = tsdb.record(event.timestamp(), &summary) {
+                error!("writing time-series export: {:?}", e);
+            }
+        }
+    };
+    if let Some(Command::Serve {
+        service:
+            Service::Kafka {
+                brokers,
+                topic,
+                group,
+                dead_letter_file,
+                dead_letter_topic,
+            },
+    }) = &opt.command
+    {
+        let metrics = Arc::clone(&processor.telemetry.metrics);
+        let mut source = KafkaSource::connect(brokers.clone(), topic, group)
+            .unwrap()
+            .with_metrics(Arc::clone(&metrics));
+        if let Some(t) = processor.telemetry.tracing.as_mut() {
+            // each event is traced on its own, as the service has no run to trace
+            t.root = None;
+            Arc::clone(&t.exporter).export_every(metrics, opt.otel_export_interval());
+        }
+        processor.dead_letters = match (dead_letter_file, dead_letter_topic) {
+            (Some(path), _) => Some(Box::new(FileSink::open(path).unwrap())),
+            (_, Some(topic)) => Some(Box::new(
+                KafkaSink::connect(brokers.clone(), topic).unwrap(),
+            )),
+            _ => None,
+        };
+        let served = source.run(aliases, |source, offset, entry| {
+            // messages redelivered after a crash were logged to the write-ahead log
+            if !processor.read(source, offset) {
+                return;
+            }
+            // services aren't strict, so carry on past invalid records and rejected events
+            processor.handle_entry(
+                entry,
+                opt.legacy_tx_ids,
+                opt.rounding,
+                &joint,
+                &mut on_applied,
+            );
+            // rejects are written as they happen, since the service only stops on failure
+            if let Some(rejects) = processor.rejects.as_mut() {
+                if let Err(e) = rejects.flush() {
+                    error!("writing rejects: {:?}", e);
+                }
+            }
+        });
+        if let Err(e) = served {
+            error!("consuming {}: {:?}", topic, e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(dir) = &opt.watch {
+        let mut watcher = DirectoryWatcher::new(dir).unwrap();
+        // files are only picked up between polls, so the current file is finished first
+        stop_on_interrupt();
+        while !INTERRUPTED.load(Ordering::SeqCst) {
+            let ready = watcher.poll().unwrap_or_else(|e| {
+                error!("watching {}: {:?}", dir, e);
+                Vec::new()
+            });
+            for path in ready {
+                if INTERRUPTED.load(Ordering::SeqCst) {
+                    break;
+                }
+                let source = path.to_string_lossy();
+                let entries = File::open(&path)
+                    .map_err(Error::from)
+                    .and_then(|file| read_source(&source, Box::new(file), &opt, aliases));
+                let moved = match entries {
+                    Ok(entries) => {
+                        for (position, entry) in (1..).zip(entries) {
+                            // a file a crash stopped part way through carries on after the
+                            // records logged to the write-ahead log
+                            if !processor.read(&source, position) {
+                                continue;
+                            }
+                            // like services, carry on past invalid records and rejected events
+                            processor.handle_entry(
+                                entry,
+                                opt.legacy_tx_ids,
+                                opt.rounding,
+                                &joint,
+                                &mut on_applied,
+                            );
+                        }
+                        watcher
+                            .processed(&path)
+                            .and_then(|moved| processor.finished(&source).map(|_| moved))
+                    }
+                    Err(e) => {
+                        error!("reading {}: {:?}", source, e);
+                        watcher.failed(&path)
+                    }
+                };
+                if let Err(e) = moved {
+                    // reported even without --verbose, as the file would be processed again
+                    eprintln!("error: {:#}", e);
+                    std::process::exit(1);
+                }
+                if let Some(rejects) = processor.rejects.as_mut() {
+                    if let Err(e) = rejects.flush() {
+                        error!("writing rejects: {:?}", e);
+                    }
+                }
+                let summaries: Vec<Summary> = processor
+                    .clients
+                    .values()
+                    .flat_map(Client::summaries)
+                    .collect();
+                write_report(
+                    &opt,
+                    summary_report(&summaries, aliases, opt.account_status),
+                );
+            }
+            let interval = Instant::now();
+            while interval.elapsed().as_secs() < opt.watch_interval.seconds()
+                && !INTERRUPTED.load(Ordering::SeqCst)
+            {
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        }
+        return;
+    }
+    stop_on_interrupt();
+    let mut progress = opt.progress.then(|| {
+        // the percentage read is only known when no input is read from stdin
+        let total_bytes = input_files
+            .iter()
+            .map(|path| match path.as_str() {
+                STDIN => None,
+                path => fs::metadata(path).ok().map(|metadata| metadata.len()),
+            })
+            .sum();
+        ProgressTracker::new(total_bytes, Duration::from_secs(1), |update| {
+            eprint!("\r{}", update)
+        })
+    });
+    let sources: Vec<_> = input_files
+        .iter()
+        .map(|path| {
+            let mut input: Box<dyn Read + Send> = match path.as_str() {
+                STDIN => Box::new(io::stdin()),
+                path => Box::new(File::open(path).unwrap()),
+            };
+            if opt.follow {
+                // interrupting stops following at the end of the rows written so far
+                input = Box::new(
+                    FollowReader::new(input, FOLLOW_INTERVAL)
+                        .until(|| INTERRUPTED.load(Ordering::SeqCst)),
+                );
+            }
+            if let Some(progress) = progress.as_ref() {
+                input = Box::new(progress.reader(input));
+            }
+            let source = read_source(path, input, &opt, aliases).unwrap();
+            match opt.read_ahead {
+                Some(capacity) => Box::new(ReadAhead::new(source, capacity)),
+                None => source,
+            }
+        })
+        .collect();
+    let names: Vec<String> = sources.iter().map(|s| s.name().to_string()).collect();
+    let header_lines: Vec<u64> = sources.iter().map(|s| s.header_lines()).collect();
+    let entries: Box<dyn Iterator<Item = (usize, Result<Record>)>> = if opt.merge_by_timestamp {
+        Box::new(MergedRecords::new(sources))
+    } else {
+        Box::new(
+            sources
+                .into_iter()
+                .enumerate()
+                .flat_map(|(i, source)| source.map(move |entry| (i, entry))),
+        )
+    };
+    // the number of records read from each file so far
+    let mut positions = vec![0; input_files.len()];
+    let mut read = skip;
+    let mut interrupted = false;
+    for (i, entry) in entries.skip(skip as usize) {
+        // a followed file is read to its end once interrupted, as the run ends there
+        if INTERRUPTED.load(Ordering::SeqCst) && !opt.follow {
+            interrupted = true;
+            break;
+        }
+        if let (Some(every), Some(path)) = (opt.checkpoint_every, &opt.checkpoint_path) {
+            if read > skip && read.is_multiple_of(every) {
+                processor.checkpoint(path, read);
+            }
+        }
+        read += 1;
+        positions[i] += 1;
+        if let Some(progress) = progress.as_mut() {
+            progress.record();
+        }
+        let source = &names[i];
+        if !processor.read(source, positions[i]) {
+            continue;
+        }
+        let line = positions[i] + header_lines[i];
+        if let Some(t) = processor.telemetry.tracing.as_mut() {
+            if !opt.merge_by_timestamp {
+                // files are read one after another, so earlier files are complete
+                for (span, records) in file_spans[..i].iter_mut().filter_map(Option::take) {
+                    end_file_span(t, span, records);
+                }
+            }
+            let (_, records) = file_spans[i].get_or_insert_with(|| {
+                let mut span = t.exporter.start_span("process file", t.root.as_ref());
+                span.set_attribute("file", source.as_str());
+                (span, 0)
+            });
+            *records += 1;
+            t.record_read();
+        }
+
+        if let Some(seq) = entry.as_ref().ok().and_then(|record| record.seq) {
+            if let Some(anomaly) = sequences.observe(source, seq) {
+                warn!("{}: {}", source, anomaly);
+            }
+        }
+        if let (Some(dedup), Ok(record)) = (dedup.as_mut(), entry.as_ref()) {
+            if dedup.is_duplicate(record) {
+                warn!(
+                    "{}: dropping duplicate {} record for transaction {}",
+                    source, record.r#type, record.tx
+                );
+                processor.discarded();
+                continue;
+            }
+        }
+
+        // the record is only kept when rejects are written, as it is rarely needed
+        let record = processor
+            .rejects
+            .as_ref()
+            .and_then(|_| entry.as_ref().ok().cloned());
+        let position = entry.as_ref().ok().map(|record| Position {
+            source: source.clone(),
+            line,
+            record: record.to_string(),
+        });
+        let event = match parse_entry(entry, opt.legacy_tx_ids, opt.rounding) {
+            Ok(event) => joint.resolve(match position {
+                Some(position) => event.with_position(position),
+                None => event,
+            }),
+            Err(e) => {
+                let message = format!("{:#}", e);
+                let location = match &position {
+                    Some(position) => position.to_string(),
+                    None => format!("{} line {}", source, line),
+                };
+                processor.reject_invalid(record.as_ref(), &e, Some(&location));
+                if opt.strict {
+                    abort(
+                        &mut processor,
+                        &format!("{} line {}", source, line),
+                        &message,
+                    );
+                }
+                continue;
+            }
+        };
+        clock = clock.max(event.timestamp());
+        if let Some(alerts) = processor.telemetry.alerts.as_mut() {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            alerts.received(event.timestamp(), now);
+        }
+
+        // scheduled payments falling due by the time of this event are applied first
+        let mut events: Vec<Event> = match event.timestamp() {
+            Some(ts) => scheduler
+                .advance(ts)
+                .into_iter()
+                .map(|event| joint.resolve(event))
+                .collect(),
+            None => Vec::new(),
+        };
+        let due = match reorder.as_mut() {
+            Some(buffer) => {
+                if buffer.is_late(&event) {
+                    warn!("{:?} arrived outside of the reordering window", event);
+                }
+                events.push(event);
+                events
+                    .into_iter()
+                    .flat_map(|event| buffer.push(event))
+                    .collect()
+            }
+            None => {
+                events.push(event);
+                events
+            }
+        };
+        if let Err(e) = processor.apply_events(due, &mut on_applied) {
+            abort(
+                &mut processor,
+                &format!("{} line {}", source, line),
+                &format!("{:#}", e),
+            );
+        }
+    }
+    if let Some(progress) = progress.as_mut() {
+        progress.finish();
+        eprintln!();
+    }
+    if let Some(buffer) = reorder.as_mut() {
+        if let Err(e) = processor.apply_events(buffer.drain(), &mut on_applied) {
+            abort(
+                &mut processor,
+                "the end of the reordering window",
+                &format!("{:#}", e),
+            );
+        }
+    }
+    if let Err(e) = processor.unpark(&mut on_applied) {
+        abort(&mut processor, "the end of the input", &format!("{:#}", e));
+    }
+    if let (Some(_), Some(path)) = (opt.checkpoint_every, &opt.checkpoint_path) {
+        processor.checkpoint(path, read);
+    }
+
+    let Processor {
+        clients,
+        store,
+        hierarchy,
+        mut telemetry,
+        ..
+    } = processor;
+    let locked = clients.values().filter(|c| c.locked()).count() as u64;
+    let metrics = telemetry.metrics;
+    metrics.lock().unwrap().set_locked_accounts(locked);
+    if let Some(statsd) = telemetry.statsd.as_mut() {
+        statsd.locked_accounts(locked);
+        statsd.flush();
+    }
+    if let Some(path) = &opt.metrics_textfile {
+        if let Err(e) = metrics.lock().unwrap().write_textfile(path) {
+            error!("writing metrics to {}: {:?}", path, e);
+        }
+    }
+    if let Some(path) = &opt.summary {
+        let mut stats = telemetry.stats;
+        stats.accounts(&clients.values().map(Client::summary).collect::<Vec<_>>());
+        if let Err(e) = stats.save(path) {
+            error!("writing summary to {}: {:?}", path, e);
+        }
+    }
+    if let Some(mut t) = telemetry.tracing {
+        t.end_batch();
+        for (span, records) in file_spans.into_iter().flatten() {
+            end_file_span(&mut t, span, records);
+        }
+        if let Some(root) = t.root {
+            t.exporter.end_span(root);
+        }
+        if let Err(e) = t.exporter.export_traces() {
+            error!("exporting traces: {:?}", e);
+        }
+        if let Err(e) = t.exporter.export_metrics(&metrics.lock().unwrap()) {
+            error!("exporting metrics: {:?}", e);
+        }
+    }
+
+    if let Some(tsdb) = tsdb.as_mut() {
+        if let Err(e) = tsdb.flush() {
+            error!("writing time-series export: {:?}", e);
+        }
+    }
+    if let Some((_, report)) = anomalies.as_mut() {
+        if let Err(e) = report.flush() {
+            error!("writing anomaly report: {:?}", e);
+        }
+    }
+    if let Some(rejects) = processor.rejects.as_mut() {
+        if let Err(e) = rejects.flush() {
+            error!("writing rejects: {:?}", e);
+        }
+    }
+
+    for lockout in lockouts.list() {
+        warn!(
+            "client {} was locked by the chargeback of transaction {}{}",
+            lockout.client,
+            lockout.tx,
+            lockout
+                .timestamp
+                .map(|ts| format!(" at {}", ts))
+                .unwrap_or_default()
+        );
+    }
+    if let Some(max) = opt.max_locked_accounts {
+        let locked = lockouts.list().len();
+        if locked > max {
+            error!(
+                "{} accounts were locked, more than the limit of {}",
+                locked, max
+            );
+            std::process::exit(1);
+        }
+    }
+
+    if let (Some(Command::Process { state, .. }), false) = (&opt.command, interrupted) {
+        if let Backend::Memory(store) = &store {
+            let checkpoint = Checkpoint::capture(&store.lock().unwrap());
+            if let Err(e) = checkpoint.save(state) {
+                error!("writing checkpoint {}: {:?}", state, e);
+            }
+        }
+    }
+    if let Err(e) = store.flush() {
+        error!(
+            "writing {}: {:?}",
+            opt.store_path.as_deref().unwrap_or_default(),
+            e
+        );
+    }
+
+    if let (Some(disputes), Some(path)) = (disputes, &opt.open_disputes) {
+        let written = csv::Writer::from_path(path).and_then(|mut report| {
+            for dispute in disputes.report(&store) {
+                report.serialize(dispute)?;
+            }
+            report.flush().map_err(csv::Error::from)
+        });
+        if let Err(e) = written {
+            error!("writing open disputes to {}: {:?}", path, e);
+        }
+    }
+    if let Some((clearing, path)) = clearing {
+        let payouts = clearing::payouts(clients.values().map(Client::summary), &attributes);
+        if opt.clearing_format == ClearingFormat::Pain001 {
+            for payout in payouts.iter().filter(|payout| payout.iban.is_none()) {
+                warn!(
+                    "client {} has no IBAN, leaving out its payout",
+                    payout.client
+                );
+            }
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let written = File::create(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|file| clearing.write(BufWriter::new(file), &payouts, now));
+        if let Err(e) = written {
+            error!("writing clearing file {}: {:?}", path, e);
+        }
+    }
+
+    if let Some(dedup) = &dedup {
+        warn!("dropped {} duplicate records", dedup.dropped());
+    }
+    // reported even without --verbose, as missing events leave balances incomplete
+    for (source, seq) in sequences.sources() {
+        eprintln!(
+            "sequence summary for {}: {} missing, {} duplicate, {} out of order",
+            source,
+            seq.missing_count(),
+            seq.duplicates(),
+            seq.out_of_order()
+        );
+        for &(from, to) in seq.missing() {
+            eprintln!(
+                "warning: {}: still {}",
+                source,
+                SequenceAnomaly::Gap { from, to }
+            );
+        }
+    }
+
+    let mut report = if let Some(Command::Project { horizon, .. }) = &opt.command {
+        // project from the latest event, or from now if events are not timestamped
+        let from = clock.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+        });
+        let mut report = Report::new(&["client", "available", "projected", "lowest", "overdraft"]);
+        for projection in project(
+            clients.values().map(Client::summary),
+            &schedule,
+            from,
+            *horizon,
+        ) {
+            if let Some(time) = projection.overdraft {
+                warn!(
+                    "client {} is projected to be overdrawn at {}",
+                    projection.id, time
+                );
+            }
+            report.push_client(
+                projection.id,
+                vec![
+                    json!(aliases.name(projection.id)),
+                    amount(projection.available),
+                    amount(projection.projected),
+                    amount(projection.lowest),
+                    json!(projection.overdraft),
+                ],
+            );
+        }
+        report
+    } else if let Some(settlement) = settlement {
+        let mut report = Report::new(&["from", "to", "amount"]);
+        let party = |id: Option<ClientId>| json!(id.map(|id| aliases.name(id)));
+        for movement in settlement.movements() {
+            report.push(vec![
+                party(movement.from),
+                party(movement.to),
+                amount(movement.amount),
+            ]);
+        }
+        report
+    } else if let Some(history) = history {
+        let mut report = Report::new(&["client", "time", "available", "held", "total", "locked"]);
+        for (time, summary) in history.series() {
+            let mut row = summary_row(&summary, aliases, &SUMMARY_COLUMNS);
+            row.insert(1, json!(time));
+            report.push_client(summary.id, row);
+        }
+        report
+    } else {
+        let summaries = clients.values().flat_map(Client::summaries);
+        let summaries: Vec<Summary> = if opt.account_hierarchy.is_some() {
+            hierarchy.roll_up(summaries)
+        } else {
+            summaries.collect()
+        };
+        let balances = summary_columns(&summaries, opt.account_status);
+        let mut columns = balances.clone();
+        if risk.is_some() {
+            columns.push("risk");
+        }
+        let mut report = Report::new(&columns);
+        for summary in summaries {
+            let mut row = summary_row(&summary, aliases, &balances);
+            if let Some(risk) = risk.as_ref() {
+                let score = risk.score(summary.id).unwrap_or_default();
+                row.push(json!(format!("{:.2}", score)));
+            }
+            report.push_client(summary.id, row);
+        }
+        report
+    };
+
+    // marked in the report and reported even without --verbose, so that partial output
+    // isn't mistaken for the whole run's
+    if interrupted {
+        report.set_partial(&format!("interrupted after {} records", read));
+    }
+    write_report(&opt, report);
+    if interrupted {
+        eprintln!(
+            "warning: interrupted after {} records, so the report is partial{}",
+            read,
+            match &opt.command {
+                Some(Command::Process { state, .. }) => format!(" and {} was not updated", state),
+                _ => String::new(),
+            }
+        );
+        std::process::exit(130);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_legacy_tx_ids() {
+        let opt = Opt::from_iter_safe(["payments", "--legacy-tx-ids", "input.csv"]).unwrap();
+        assert!(opt.legacy_tx_ids);
+        assert!(
+            !Opt::from_iter_safe(["payments", "input.csv"])
+                .unwrap()
+                .legacy_tx_ids
+        );
+
+        let record = |tx| Record {
+            r#type: "deposit".to_string(),
+            client: 1,
+            tx,
+            amount: Some(dec!(1.0)),
+            to: None,
+            seq: None,
+            timestamp: None,
+            currency: None,
+        };
+        let wide = u64::from(u32::MAX) + 1;
+        let parse = |tx| parse_entry(Ok(record(tx)), opt.legacy_tx_ids, opt.rounding);
+        assert_eq!(
+            parse(u64::from(u32::MAX)).unwrap().tx(),
+            u64::from(u32::MAX)
+        );
+        assert!(parse(wide).is_err());
+        assert!(parse_entry(Ok(record(wide)), false, opt.rounding).is_ok());
+    }
+
+    #[test]
+    fn test_concurrent_conflicts() {
+        let parse = |args: &[&str]| {
+            Opt::from_iter_safe(["payments"].iter().chain(args).chain(&["input.csv"]))
+        };
+        assert!(parse(&["--workers", "2"]).is_ok());
+        for mode in [
+            &["--parallel", "shared"][..],
+            &["--workers", "2"],
+            &["--partitions", "2"],
+            &["--actors"],
+            &["--async-io"],
+        ] {
+            for option in [
+                &["--history", "daily"][..],
+                &["--script", "hook.rhai"],
+                &["--risk"],
+                &["--tsdb-export", "balances.txt"],
+                &["--alert", "account-locked"],
+            ] {
+                let args: Vec<&str> = mode.iter().chain(option).copied().collect();
+                assert!(parse(&args).is_err(), "{:?}", args);
+            }
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let check = |args: &[&str]| {
+            let opt = Opt::from_iter_safe(["payments"].iter().chain(args)).unwrap();
+            opt.check(&opt.input_files()).map_err(|e| e.kind)
+        };
+        assert!(check(&["input.csv"]).is_ok());
+        assert_eq!(check(&["-", "-"]), Err(ErrorKind::InvalidValue));
+        assert!(check(&["--follow", "input.csv"]).is_ok());
+        assert_eq!(
+            check(&["--follow", "a.csv", "b.csv"]),
+            Err(ErrorKind::ArgumentConflict)
+        );
+        assert_eq!(
+            check(&[
+                "--dispute-window",
+                "90d",
+                "--prune-after",
+                "30d",
+                "input.csv"
+            ]),
+            Err(ErrorKind::InvalidValue)
+        );
+        assert_eq!(
+            check(&[
+                "--store-path",
+                "balances.db",
+                "process",
+                "--state",
+                "state.json"
+            ]),
+            Err(ErrorKind::ArgumentConflict)
+        );
+        assert_eq!(
+            check(&["--webhook", "http://localhost/hook", "input.csv"]),
+            Err(ErrorKind::ArgumentConflict)
+        );
+    }
+
+    #[test]
+    fn test_processor() {
+        let opt = Opt::from_iter_safe([
+            "payments",
+            "--strict",
+            "--verify",
+            "--park-disputes",
+            "10",
+            "--dispute-window",
+            "90d",
+            "--allow-admin-events",
+            "input.csv",
+        ])
+        .unwrap();
+        let processor = opt.processor(Backend::default(), None, RuleSet::default());
+        assert!(processor.strict && processor.verify);
+        assert!(processor.parking.is_some());
+        assert!(processor.expiry.is_none() && processor.pruner.is_none());
+        assert!(processor.rejects.is_none() && processor.audit.is_none());
+        assert!(processor.telemetry.statsd.is_none() && processor.telemetry.alerts.is_none());
+        assert_eq!(
+            processor.policy,
+            Policy {
+                allow_admin_events: true,
+                dispute_window: Some(90 * 24 * 60 * 60),
+                ..Policy::default()
+            }
+        );
+
+        let opt = Opt::from_iter_safe(["payments", "--alert", "account-locked", "input.csv"]);
+        let processor = opt
+            .unwrap()
+            .processor(Backend::default(), None, RuleSet::default());
+        assert!(!processor.strict && processor.parking.is_none());
+        assert!(processor.telemetry.alerts.is_some());
+    }
+}
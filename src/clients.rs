@@ -1,6 +1,20 @@
-use crate::events::{Event, EventType};
-use crate::storage::{TxState, TxStore};
-use anyhow::{anyhow, bail, Result};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::amount::Amount;
+use crate::audit::{AuditEntry, SharedAuditLog};
+use crate::events::{ClientId, Currency, Event, EventType, TxId};
+use crate::middleware::{Decision, SharedMiddleware};
+#[cfg(feature = "async")]
+use crate::storage::AsyncTxStore;
+use crate::storage::{Account, Balance, TxState, TxStore};
+use anyhow::{anyhow, bail, Error, Result};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tracing::error;
 
 /// Represents a client which has some associated transaction history
 ///
@@ -9,75 +23,1110 @@ use anyhow::{anyhow, bail, Result};
 /// use payments::clients::Client;
 /// use payments::events::{Record, Event};
 /// use payments::storage::MemoryStore;
+/// use rust_decimal_macros::dec;
 ///
 /// // create a deposit event for the client
 /// let record = Record {
-///     r#type: "deposit",
+///     r#type: "deposit".to_string(),
 ///     client: 1337,
 ///     tx: 1,
-///     amount: Some(1.0),
+///     amount: Some(dec!(1.0)),
+///     to: None,
+///     seq: None,
+///     timestamp: None,
+///     currency: None,
 /// };
 /// let event = Event::try_from(record).unwrap();
 ///
 /// // create a new client with id 1337 and an in-memory transaction store
-/// let client = Client::new(1337, MemoryStore::new());
+/// let mut client = Client::new(1337, MemoryStore::new());
 /// client.update(&event).unwrap();
 ///
 /// // prints "1.0"
 /// println!("{}", client.available());
 /// ```
-#[derive(Debug, Default)]
-pub struct Client<T: TxStore> {
+#[derive(Debug)]
+pub struct Client<T, A = Decimal> {
     #[doc(hidden)]
-    id: u16,
+    id: ClientId,
     #[doc(hidden)]
-    available: f32,
+    available: A,
     #[doc(hidden)]
-    total: f32,
+    total: A,
     #[doc(hidden)]
-    locked: bool,
+    status: AccountStatus,
+    #[doc(hidden)]
+    currencies: BTreeMap<Currency, Balance<A>>,
+    #[doc(hidden)]
+    policy: Arc<dyn AccountPolicy<A>>,
+    #[doc(hidden)]
+    audit: Option<SharedAuditLog>,
+    #[doc(hidden)]
+    middleware: Vec<SharedMiddleware<A>>,
     #[doc(hidden)]
     store: T,
 }
 
-impl<T: TxStore> Client<T> {
-    pub fn new(id: u16, store: T) -> Client<T> {
+/// The state of a client's account, deciding which events may be applied to it.
+///
+/// Accounts start out active. A chargeback freezes an account, and a `review` event
+/// places it under review, until an `unlock` event makes it active again. A `close`
+/// event closes an account for good, from any other status.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountStatus {
+    /// Every event may be applied.
+    #[default]
+    Active,
+    /// Frozen by a chargeback, so that no events are applied until it is unlocked.
+    Frozen,
+    /// Under review, such as by compliance, so that no funds are withdrawn or
+    /// transferred out of it until it is unlocked. Funds may still be deposited.
+    UnderReview,
+    /// Closed for good, so that no funds move in or out of it, while its earlier
+    /// transactions may still be disputed, resolved and charged back.
+    Closed,
+}
+
+impl AccountStatus {
+    /// Returns the status of an account saved before statuses were, which only
+    /// recorded whether it was `locked` or `closed`.
+    pub(crate) fn from_flags(locked: bool, closed: bool) -> AccountStatus {
+        match (locked, closed) {
+            (_, true) => AccountStatus::Closed,
+            (true, false) => AccountStatus::Frozen,
+            (false, false) => AccountStatus::Active,
+        }
+    }
+
+    /// Returns whether the account is active.
+    pub fn is_active(&self) -> bool {
+        *self == AccountStatus::Active
+    }
+
+    /// Returns the name of the status, as reported.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AccountStatus::Active => "active",
+            AccountStatus::Frozen => "frozen",
+            AccountStatus::UnderReview => "under_review",
+            AccountStatus::Closed => "closed",
+        }
+    }
+}
+
+impl fmt::Display for AccountStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for AccountStatus {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<AccountStatus> {
+        match s {
+            "active" => Ok(AccountStatus::Active),
+            "frozen" => Ok(AccountStatus::Frozen),
+            "under_review" => Ok(AccountStatus::UnderReview),
+            "closed" => Ok(AccountStatus::Closed),
+            v => bail!(
+                "invalid account status {:?}, expected active, frozen, under_review or closed",
+                v
+            ),
+        }
+    }
+}
+
+/// How disputes of transactions exceeding a client's available funds are applied.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DisputePolicy {
+    /// The dispute is rejected.
+    #[default]
+    Reject,
+    /// The full amount is held, leaving the available funds negative.
+    AllowNegativeAvailable,
+    /// Only the available funds are held, disputing the transaction for that amount. The
+    /// dispute is rejected if no funds are available.
+    HoldPartial,
+}
+
+impl FromStr for DisputePolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<DisputePolicy> {
+        match s {
+            "reject" => Ok(DisputePolicy::Reject),
+            "allow-negative-available" => Ok(DisputePolicy::AllowNegativeAvailable),
+            "hold-partial" => Ok(DisputePolicy::HoldPartial),
+            v => bail!(
+                "invalid dispute policy {:?}, expected reject, allow-negative-available or hold-partial",
+                v
+            ),
+        }
+    }
+}
+
+/// Options changing how payment events are applied to a client's account, which
+/// clients apply events according to unless given another [`AccountPolicy`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Policy {
+    /// Whether resolving the transaction charged back by a chargeback, such as after
+    /// an investigation, restores its funds and unlocks the account.
+    pub unlock_on_resolve: bool,
+    /// How disputes of transactions exceeding the available funds are applied.
+    pub insufficient_funds: DisputePolicy,
+    /// Whether withdrawals may be disputed, as card networks allow, provisionally
+    /// crediting the withdrawn funds back to the client as held funds until the
+    /// dispute is resolved or charged back.
+    pub dispute_withdrawals: bool,
+    /// Whether administrative events, such as [`EventType::Unlock`], may be applied.
+    pub allow_admin_events: bool,
+    /// How many seconds after a transaction it may be disputed, if disputes are only
+    /// allowed within a window. Only timestamped disputes of transactions whose time
+    /// was recorded while a window was set are checked.
+    pub dispute_window: Option<u64>,
+}
+
+impl<A: Amount> AccountPolicy<A> for Policy {
+    fn can_dispute(&self, _event: &Event<A>, tx: &TxState<A>) -> bool {
+        match tx {
+            TxState::Deposit(_) => true,
+            TxState::Withdrawal(_) => self.dispute_withdrawals,
+            _ => false,
+        }
+    }
+
+    fn unlock_on_resolve(&self) -> bool {
+        self.unlock_on_resolve
+    }
+
+    fn insufficient_funds(&self) -> DisputePolicy {
+        self.insufficient_funds
+    }
+
+    fn allow_admin_events(&self) -> bool {
+        self.allow_admin_events
+    }
+
+    fn dispute_window(&self) -> Option<u64> {
+        self.dispute_window
+    }
+}
+
+/// The business rules deciding how payment events are applied to a client's account,
+/// for ledgers whose rules differ from those [`Policy`] can be configured with.
+///
+/// Every rule defaults to how [`Policy::default`] applies events, so that ledgers only
+/// need to override the rules they change. Rules checked elsewhere, such as that
+/// only disputed transactions can be charged back, are the same for every ledger.
+///
+/// # Example
+/// ```
+/// use payments::clients::{AccountPolicy, AccountStatus, Client};
+/// use payments::events::{Event, Record};
+/// use payments::storage::MemoryStore;
+/// use rust_decimal_macros::dec;
+///
+/// /// Places accounts under review on a chargeback, rather than freezing them.
+/// #[derive(Debug)]
+/// struct ReviewChargebacks;
+///
+/// impl AccountPolicy for ReviewChargebacks {
+///     fn on_chargeback(&self, _status: AccountStatus) -> AccountStatus {
+///         AccountStatus::UnderReview
+///     }
+/// }
+///
+/// let mut client = Client::new(1, MemoryStore::new()).with_policy(ReviewChargebacks);
+/// for (r#type, tx) in [("deposit", 1), ("deposit", 2), ("dispute", 1), ("chargeback", 1), ("deposit", 3)] {
+///     let record = Record {
+///         r#type: r#type.to_string(),
+///         client: 1,
+///         tx,
+///         amount: (r#type == "deposit").then(|| dec!(1.0)),
+///         to: None,
+///         seq: None,
+///         timestamp: None,
+///         currency: None,
+///     };
+///     client.update(&Event::try_from(record).unwrap()).unwrap();
+/// }
+///
+/// assert_eq!(client.status(), AccountStatus::UnderReview);
+/// assert_eq!(client.available(), dec!(2.0));
+/// ```
+pub trait AccountPolicy<A = Decimal>: Send + Sync + fmt::Debug {
+    /// Returns whether `event` may be applied to a frozen account. Only representments,
+    /// and voids releasing a hold, are by default, along with resolves if
+    /// [`AccountPolicy::unlock_on_resolve`].
+    fn allow_when_frozen(&self, event: &Event<A>) -> bool {
+        match event.kind() {
+            EventType::Resolve => self.unlock_on_resolve(),
+            EventType::Representment | EventType::Void => true,
+            _ => false,
+        }
+    }
+
+    /// Returns whether the transaction in state `tx` may be disputed by `event`. Only
+    /// deposits may be by default.
+    fn can_dispute(&self, _event: &Event<A>, tx: &TxState<A>) -> bool {
+        matches!(tx, TxState::Deposit(_))
+    }
+
+    /// Returns the status an account in `status` moves to when one of its deposits is
+    /// charged back. Accounts are frozen by default, unless they are closed.
+    fn on_chargeback(&self, status: AccountStatus) -> AccountStatus {
+        match status {
+            AccountStatus::Closed => AccountStatus::Closed,
+            _ => AccountStatus::Frozen,
+        }
+    }
+
+    /// Returns whether resolving a charged back transaction restores its funds and
+    /// unlocks the account, as [`Policy::unlock_on_resolve`] does.
+    fn unlock_on_resolve(&self) -> bool {
+        false
+    }
+
+    /// Returns how disputes of transactions exceeding the available funds are applied,
+    /// as [`Policy::insufficient_funds`] does.
+    fn insufficient_funds(&self) -> DisputePolicy {
+        DisputePolicy::default()
+    }
+
+    /// Returns whether administrative events may be applied, as
+    /// [`Policy::allow_admin_events`] does.
+    fn allow_admin_events(&self) -> bool {
+        false
+    }
+
+    /// Returns how many seconds after a transaction it may be disputed, as
+    /// [`Policy::dispute_window`] does.
+    fn dispute_window(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// A point-in-time view of a client's account balances.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Summary<A = Decimal> {
+    /// The unique identifier of the client.
+    pub id: ClientId,
+    /// The funds available for withdrawal.
+    pub available: A,
+    /// The funds held under dispute.
+    pub held: A,
+    /// The total funds available and held under dispute.
+    pub total: A,
+    /// Whether the client's account is frozen.
+    pub locked: bool,
+    /// The status of the client's account.
+    #[serde(default, skip_serializing_if = "AccountStatus::is_active")]
+    pub status: AccountStatus,
+    /// The currency of the balances, or `None` for the base currency.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+}
+
+/// What applying an event changed in a client's account, such as for emitting ledger
+/// entries downstream.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Outcome<A = Decimal> {
+    /// The unique identifier of the client whose account was changed.
+    pub client: ClientId,
+    /// The transaction referenced by the event, if any.
+    pub tx: Option<TxId>,
+    /// The time at which the event occurred, if known.
+    pub timestamp: Option<u64>,
+    /// The currency of the changed balances, or `None` for the base currency.
+    pub currency: Option<Currency>,
+    /// The state of the transaction before the event was applied, if it was stored.
+    pub from: Option<TxState<A>>,
+    /// The state of the transaction after the event was applied, if it is stored.
+    pub to: Option<TxState<A>>,
+    /// The change in the funds available for withdrawal.
+    pub available: A,
+    /// The change in the funds held under dispute.
+    pub held: A,
+    /// The change in the total funds.
+    pub total: A,
+    /// The status the account moved to because of the event, if it changed.
+    pub status: Option<AccountStatus>,
+}
+
+impl<T, A: Amount> Client<T, A> {
+    /// Returns the client applying events according to `policy`, such as a [`Policy`]
+    /// or a ledger's own [`AccountPolicy`].
+    pub fn with_policy(self, policy: impl AccountPolicy<A> + 'static) -> Client<T, A> {
         Client {
-            id,
-            store,
-            ..Default::default()
+            policy: Arc::new(policy),
+            ..self
         }
     }
 
+    /// Returns the client recording every event it applies or rejects to `log`.
+    pub fn with_audit_log(self, log: SharedAuditLog) -> Client<T, A> {
+        Client {
+            audit: Some(log),
+            ..self
+        }
+    }
+
+    /// Returns the client running every event it applies through `middleware`, after
+    /// any middleware already added.
+    pub fn with_middleware(mut self, middleware: SharedMiddleware<A>) -> Client<T, A> {
+        self.middleware.push(middleware);
+        self
+    }
+
     /// Returns the unique identifier of the client.
-    pub fn id(&self) -> u16 {
+    pub fn id(&self) -> ClientId {
         self.id
     }
 
-    /// Returns the funds available for withdrawal.
-    pub fn available(&self) -> f32 {
+    /// Returns the funds available for withdrawal, in the base currency.
+    pub fn available(&self) -> A {
         self.available
     }
 
-    /// Returns the funds held under dispute.
-    pub fn held(&self) -> f32 {
+    /// Returns the funds held under dispute, in the base currency.
+    pub fn held(&self) -> A {
         self.total - self.available
     }
 
-    /// Returns the total funds available and held under dispute.
-    pub fn total(&self) -> f32 {
+    /// Returns the total funds available and held under dispute, in the base currency.
+    pub fn total(&self) -> A {
         self.total
     }
 
     /// Returns whether the client's account is frozen.
     pub fn locked(&self) -> bool {
-        self.locked
+        self.status == AccountStatus::Frozen
+    }
+
+    /// Returns whether the client's account is closed.
+    pub fn closed(&self) -> bool {
+        self.status == AccountStatus::Closed
+    }
+
+    /// Returns the status of the client's account.
+    pub fn status(&self) -> AccountStatus {
+        self.status
+    }
+
+    /// Returns the funds held in `currency`, or in the base currency if `None`.
+    pub fn balance(&self, currency: Option<Currency>) -> Balance<A> {
+        match currency {
+            None => Balance {
+                available: self.available,
+                total: self.total,
+            },
+            Some(currency) => self.currencies.get(&currency).copied().unwrap_or_default(),
+        }
+    }
+
+    /// Returns a snapshot of the client's current account balances in the base
+    /// currency.
+    pub fn summary(&self) -> Summary<A> {
+        self.summary_in(None)
+    }
+
+    /// Returns a snapshot of the client's current account balances in every currency
+    /// it holds, starting with the base currency.
+    pub fn summaries(&self) -> Vec<Summary<A>> {
+        let currencies = self
+            .currencies
+            .keys()
+            .map(|&currency| self.summary_in(Some(currency)));
+        [self.summary()].into_iter().chain(currencies).collect()
+    }
+
+    /// Returns a snapshot of the client's current account balances in `currency`, or
+    /// in the base currency if `None`.
+    pub fn summary_in(&self, currency: Option<Currency>) -> Summary<A> {
+        let balance = self.balance(currency);
+        Summary {
+            id: self.id,
+            available: balance.available,
+            held: balance.held(),
+            total: balance.total,
+            locked: self.locked(),
+            status: self.status,
+            currency,
+        }
+    }
+
+    /// Records the `result` of applying `event` to the audit log, if there is one.
+    fn audit(&self, event: &Event<A>, result: Result<&Outcome<A>, &Error>) {
+        let Some(log) = &self.audit else {
+            return;
+        };
+        let entry = match result {
+            Ok(outcome) => AuditEntry::applied(event, &self.summary_in(outcome.currency)),
+            Err(e) => AuditEntry::rejected(event, &self.summary_in(event.currency()), e),
+        };
+        if let Err(e) = log.lock().unwrap().record(entry) {
+            error!("writing audit log: {:?}", e);
+        }
+    }
+
+    /// Runs `event` through the client's middleware before it is applied, returning the
+    /// event to apply in its place, or the error it was rejected with.
+    fn before<'e>(&self, event: &'e Event<A>) -> Result<Cow<'e, Event<A>>> {
+        let mut event = Cow::Borrowed(event);
+        for middleware in &self.middleware {
+            match middleware.lock().unwrap().before(&event) {
+                Decision::Continue => {}
+                Decision::Replace(replaced) => event = Cow::Owned(replaced),
+                Decision::Reject(e) => return Err(e),
+            }
+        }
+        Ok(event)
+    }
+
+    /// Lets the client's middleware observe `event`, once applied with `result`.
+    fn after(&self, event: &Event<A>, result: Result<&Outcome<A>, &Error>) {
+        if let Ok(outcome) = result {
+            for middleware in &self.middleware {
+                middleware.lock().unwrap().after(event, outcome);
+            }
+        }
+    }
+
+    /// Returns the client's current account balances, as saved in a transaction store.
+    fn account(&self) -> Account<A> {
+        Account {
+            available: self.available,
+            total: self.total,
+            status: self.status,
+            currencies: self.currencies.clone(),
+        }
+    }
+
+    /// Fails unless `event` may be applied given the status of the account.
+    fn check_status(&self, event: &Event<A>) -> Result<()> {
+        let allowed = match (self.status, event.kind()) {
+            (AccountStatus::Active, _) => true,
+            (AccountStatus::Frozen, _) => self.policy.allow_when_frozen(event),
+            (AccountStatus::UnderReview, kind) => !matches!(
+                kind,
+                EventType::Withdrawal(_)
+                    | EventType::Transfer { .. }
+                    | EventType::Authorize(_)
+                    | EventType::Capture(_)
+            ),
+            // closed accounts keep their history, so only movements of funds are rejected
+            (AccountStatus::Closed, kind) => !matches!(
+                kind,
+                EventType::Deposit(_)
+                    | EventType::Withdrawal(_)
+                    | EventType::Transfer { .. }
+                    | EventType::Authorize(_)
+                    | EventType::Capture(_)
+            ),
+        };
+        if !allowed {
+            bail!("account is {}", self.status.name().replace('_', " "));
+        }
+        Ok(())
+    }
+
+    /// Works out the new state of the transaction referenced by `event` and the
+    /// client's resulting balances, given the transaction's `stored` state, the
+    /// `currency` of the balances it changes and whether `other_chargebacks` of the
+    /// client's transactions are outstanding, without changing either.
+    fn plan(
+        &self,
+        event: &Event<A>,
+        stored: Option<TxState<A>>,
+        currency: Option<Currency>,
+        other_chargebacks: bool,
+    ) -> Result<(TxState<A>, Account<A>)> {
+        // disputes and the like are in the currency of the transaction they reference
+        let referencing = matches!(
+            event.kind(),
+            EventType::Dispute(_)
+                | EventType::Resolve
+                | EventType::Chargeback
+                | EventType::Representment
+                | EventType::Capture(_)
+                | EventType::Void
+        );
+        if let Some(expected) = event.currency().filter(|_| referencing && stored.is_some()) {
+            if currency != Some(expected) {
+                bail!("transaction is not in {}", expected);
+            }
+        }
+
+        let mut account = self.account();
+        let mut balance = account.balance(currency);
+        let tx = match event.kind() {
+            EventType::Deposit(amount) => {
+                if stored.is_some() {
+                    bail!("cannot overwrite existing transaction");
+                }
+
+                balance.available += *amount;
+                balance.total += *amount;
+                TxState::Deposit(*amount)
+            }
+            EventType::Withdrawal(amount) | EventType::Transfer { amount, .. } => {
+                if balance.available < *amount {
+                    bail!("insufficient funds for {}", event.kind().name());
+                }
+
+                if stored.is_some() {
+                    bail!("cannot overwrite existing transaction");
+                }
+
+                balance.available -= *amount;
+                balance.total -= *amount;
+                match event.kind() {
+                    EventType::Transfer { .. } => TxState::Transfer(*amount),
+                    _ => TxState::Withdrawal(*amount),
+                }
+            }
+            EventType::Authorize(amount) => {
+                if balance.available < *amount {
+                    bail!("insufficient funds for authorization");
+                }
+
+                if stored.is_some() {
+                    bail!("cannot overwrite existing transaction");
+                }
+
+                // the funds are held, so only leave the total once captured
+                balance.available -= *amount;
+                TxState::Authorized(*amount)
+            }
+            EventType::Capture(captured) => {
+                match stored.ok_or_else(|| anyhow!("transaction does not exist"))? {
+                    TxState::Authorized(amount) => {
+                        let captured = match *captured {
+                            Some(captured) if captured > amount => {
+                                bail!("capture amount exceeds the authorization")
+                            }
+                            Some(captured) => captured,
+                            None => amount,
+                        };
+                        // whatever isn't captured is released
+                        balance.available += amount - captured;
+                        balance.total -= captured;
+                        TxState::Withdrawal(captured)
+                    }
+                    TxState::Voided(_) => bail!("authorization was voided"),
+                    TxState::Deposit(_)
+                    | TxState::Dispute(_)
+                    | TxState::PartialDispute { .. }
+                    | TxState::Withdrawal(_)
+                    | TxState::ChargedBack(_)
+                    | TxState::PartialChargedBack { .. }
+                    | TxState::WithdrawalDispute(_)
+                    | TxState::WithdrawalChargedBack(_)
+                    | TxState::Transfer(_)
+                    | TxState::Represented(_) => bail!("transaction is not an authorization"),
+                }
+            }
+            EventType::Void => {
+                match stored.ok_or_else(|| anyhow!("transaction does not exist"))? {
+                    TxState::Authorized(amount) => {
+                        balance.available += amount;
+                        TxState::Voided(amount)
+                    }
+                    TxState::Voided(_) => bail!("authorization was already voided"),
+                    TxState::Deposit(_)
+                    | TxState::Dispute(_)
+                    | TxState::PartialDispute { .. }
+                    | TxState::Withdrawal(_)
+                    | TxState::ChargedBack(_)
+                    | TxState::PartialChargedBack { .. }
+                    | TxState::WithdrawalDispute(_)
+                    | TxState::WithdrawalChargedBack(_)
+                    | TxState::Transfer(_)
+                    | TxState::Represented(_) => bail!("transaction is not an authorization"),
+                }
+            }
+            EventType::Dispute(disputed) => {
+                let stored = stored.ok_or_else(|| anyhow!("transaction does not exist"))?;
+                let disputable = self.policy.can_dispute(event, &stored);
+                match stored {
+                    TxState::Deposit(amount) if disputable => {
+                        let mut held = match *disputed {
+                            Some(disputed) if disputed > amount => {
+                                bail!("dispute amount exceeds the transaction")
+                            }
+                            Some(disputed) => disputed,
+                            None => amount,
+                        };
+                        if held > balance.available {
+                            match self.policy.insufficient_funds() {
+                                DisputePolicy::AllowNegativeAvailable => {}
+                                DisputePolicy::HoldPartial if balance.available > A::ZERO => {
+                                    held = balance.available
+                                }
+                                // with no funds available, there is nothing to hold
+                                DisputePolicy::Reject | DisputePolicy::HoldPartial => {
+                                    bail!("not enough funds to dispute transaction")
+                                }
+                            }
+                        }
+
+                        balance.available -= held;
+                        if held == amount {
+                            TxState::Dispute(amount)
+                        } else {
+                            TxState::PartialDispute {
+                                disputed: held,
+                                undisputed: amount - held,
+                            }
+                        }
+                    }
+                    TxState::Withdrawal(amount) if disputable => {
+                        if disputed.is_some_and(|disputed| disputed != amount) {
+                            bail!("withdrawals can only be disputed in full");
+                        }
+                        balance.total += amount;
+                        TxState::WithdrawalDispute(amount)
+                    }
+                    TxState::Dispute(_)
+                    | TxState::PartialDispute { .. }
+                    | TxState::WithdrawalDispute(_) => {
+                        bail!("transaction already disputed")
+                    }
+                    TxState::Deposit(_) => bail!("cannot dispute a deposit"),
+                    TxState::Withdrawal(_) => bail!("cannot dispute a withdrawal"),
+                    TxState::Transfer(_) => bail!("cannot dispute a transfer"),
+                    TxState::Authorized(_) => bail!("cannot dispute an authorization"),
+                    TxState::Voided(_) => bail!("transaction was voided"),
+                    TxState::ChargedBack(_)
+                    | TxState::PartialChargedBack { .. }
+                    | TxState::WithdrawalChargedBack(_)
+                    | TxState::Represented(_) => {
+                        bail!("transaction was charged back")
+                    }
+                }
+            }
+            EventType::Resolve => {
+                match stored.ok_or_else(|| anyhow!("transaction does not exist"))? {
+                    TxState::Dispute(_)
+                    | TxState::PartialDispute { .. }
+                    | TxState::WithdrawalDispute(_)
+                        if self.locked() =>
+                    {
+                        bail!("account is frozen")
+                    }
+                    TxState::Dispute(amount) => {
+                        balance.available += amount;
+                        TxState::Deposit(amount)
+                    }
+                    TxState::PartialDispute {
+                        disputed,
+                        undisputed,
+                    } => {
+                        balance.available += disputed;
+                        TxState::Deposit(disputed + undisputed)
+                    }
+                    TxState::ChargedBack(amount) if self.policy.unlock_on_resolve() => {
+                        balance.available += amount;
+                        balance.total += amount;
+                        // a closed account stays closed, and one frozen by other
+                        // chargebacks stays frozen
+                        if account.status == AccountStatus::Frozen && !other_chargebacks {
+                            account.status = AccountStatus::Active;
+                        }
+                        TxState::Deposit(amount)
+                    }
+                    TxState::PartialChargedBack {
+                        charged_back,
+                        undisputed,
+                    } if self.policy.unlock_on_resolve() => {
+                        balance.available += charged_back;
+                        balance.total += charged_back;
+                        if account.status == AccountStatus::Frozen && !other_chargebacks {
+                            account.status = AccountStatus::Active;
+                        }
+                        TxState::Deposit(charged_back + undisputed)
+                    }
+                    TxState::WithdrawalDispute(amount) => {
+                        balance.total -= amount;
+                        TxState::Withdrawal(amount)
+                    }
+                    TxState::Deposit(_)
+                    | TxState::Withdrawal(_)
+                    | TxState::ChargedBack(_)
+                    | TxState::PartialChargedBack { .. }
+                    | TxState::WithdrawalChargedBack(_)
+                    | TxState::Transfer(_)
+                    | TxState::Represented(_)
+                    | TxState::Authorized(_)
+                    | TxState::Voided(_) => bail!("transaction is not disputed"),
+                }
+            }
+            EventType::Chargeback => {
+                match stored.ok_or_else(|| anyhow!("transaction does not exist"))? {
+                    TxState::Dispute(amount) => {
+                        balance.total -= amount;
+                        account.status = self.policy.on_chargeback(account.status);
+                        TxState::ChargedBack(amount)
+                    }
+                    // only the disputed funds are charged back
+                    TxState::PartialDispute {
+                        disputed,
+                        undisputed,
+                    } => {
+                        balance.total -= disputed;
+                        account.status = self.policy.on_chargeback(account.status);
+                        TxState::PartialChargedBack {
+                            charged_back: disputed,
+                            undisputed,
+                        }
+                    }
+                    // the client was owed the withdrawn funds, so keeps them unfrozen
+                    TxState::WithdrawalDispute(amount) => {
+                        balance.available += amount;
+                        TxState::WithdrawalChargedBack(amount)
+                    }
+                    TxState::Deposit(_)
+                    | TxState::Withdrawal(_)
+                    | TxState::ChargedBack(_)
+                    | TxState::PartialChargedBack { .. }
+                    | TxState::WithdrawalChargedBack(_)
+                    | TxState::Transfer(_)
+                    | TxState::Represented(_)
+                    | TxState::Authorized(_)
+                    | TxState::Voided(_) => bail!("transaction is not disputed"),
+                }
+            }
+            EventType::Representment => {
+                match stored.ok_or_else(|| anyhow!("transaction does not exist"))? {
+                    TxState::ChargedBack(amount) => {
+                        balance.available += amount;
+                        balance.total += amount;
+                        // a closed account stays closed, and one frozen by other
+                        // chargebacks stays frozen
+                        if account.status == AccountStatus::Frozen && !other_chargebacks {
+                            account.status = AccountStatus::Active;
+                        }
+                        TxState::Represented(amount)
+                    }
+                    TxState::PartialChargedBack {
+                        charged_back,
+                        undisputed,
+                    } => {
+                        balance.available += charged_back;
+                        balance.total += charged_back;
+                        if account.status == AccountStatus::Frozen && !other_chargebacks {
+                            account.status = AccountStatus::Active;
+                        }
+                        // the whole deposit is restored, along with the funds which were
+                        // never disputed
+                        TxState::Represented(charged_back + undisputed)
+                    }
+                    TxState::Represented(_) => bail!("transaction was already represented"),
+                    TxState::Deposit(_)
+                    | TxState::Dispute(_)
+                    | TxState::PartialDispute { .. }
+                    | TxState::Withdrawal(_)
+                    | TxState::WithdrawalDispute(_)
+                    | TxState::WithdrawalChargedBack(_)
+                    | TxState::Transfer(_)
+                    | TxState::Authorized(_)
+                    | TxState::Voided(_) => bail!("transaction was not charged back"),
+                }
+            }
+            EventType::Unlock | EventType::Review | EventType::Close => {
+                bail!(
+                    "{} events do not reference a transaction",
+                    event.kind().name()
+                )
+            }
+        };
+        account.set_balance(currency, balance);
+        Ok((tx, account))
+    }
+
+    /// Works out the client's account once it moves from its current status to
+    /// `status`, without changing it, failing unless the account may make that
+    /// transition.
+    fn plan_transition(&self, status: AccountStatus) -> Result<Account<A>> {
+        use AccountStatus::*;
+
+        let allowed = matches!(
+            (self.status, status),
+            (Frozen | UnderReview, Active)
+                | (Active, UnderReview)
+                | (Active | Frozen | UnderReview, Closed)
+        );
+        if !allowed {
+            bail!(
+                "account can't become {} when it is {}",
+                status.name().replace('_', " "),
+                self.status.name().replace('_', " ")
+            );
+        }
+        Ok(Account {
+            status,
+            ..self.account()
+        })
+    }
+
+    /// Fails if `event` is a dispute filed outside of the [`Policy::dispute_window`]
+    /// after the transaction it references, which occurred at `original`.
+    fn check_dispute_window(&self, event: &Event<A>, original: Option<u64>) -> Result<()> {
+        if let (EventType::Dispute(_), Some(window), Some(original), Some(filed)) = (
+            event.kind(),
+            self.policy.dispute_window(),
+            original,
+            event.timestamp(),
+        ) {
+            if filed.saturating_sub(original) > window {
+                bail!(
+                    "dispute filed {}s after the transaction, outside the {}s dispute window",
+                    filed - original,
+                    window
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns whether the client's other transactions must be looked up for any which
+    /// are charged back, to decide whether the representment `event`, or the resolve
+    /// `event` under [`Policy::unlock_on_resolve`], unfreezes the account.
+    fn needs_chargebacks(&self, event: &Event<A>) -> bool {
+        let unfreezing = match event.kind() {
+            EventType::Representment => true,
+            EventType::Resolve => self.policy.unlock_on_resolve(),
+            _ => false,
+        };
+        unfreezing && self.status == AccountStatus::Frozen
+    }
+
+    /// Returns whether the time of the transaction referenced by `event` must be
+    /// looked up, to check a dispute against the [`Policy::dispute_window`].
+    fn needs_original(&self, event: &Event<A>) -> bool {
+        matches!(event.kind(), EventType::Dispute(_)) && self.policy.dispute_window().is_some()
+    }
+
+    /// Returns the time to record for the transaction created by `event`, if any, so
+    /// that it can later be checked against the [`Policy::dispute_window`].
+    fn recorded_time(&self, event: &Event<A>) -> Option<u64> {
+        self.policy.dispute_window().and(event.timestamp())
+    }
+
+    /// Fails unless administrative events may be applied.
+    fn check_admin(&self, event: &Event<A>) -> Result<()> {
+        if !self.policy.allow_admin_events() {
+            bail!("{} events are not allowed", event.kind().name());
+        }
+        Ok(())
+    }
+
+    /// Works out the client's balances after receiving `amount` in `currency` from a
+    /// transfer, without changing them.
+    fn plan_credit(&self, amount: A, currency: Option<Currency>) -> Result<Account<A>> {
+        // accounts under review may still receive funds
+        if matches!(self.status, AccountStatus::Frozen | AccountStatus::Closed) {
+            bail!("destination account is {}", self.status);
+        }
+        let mut account = self.account();
+        let balance = account.balance(currency);
+        account.set_balance(
+            currency,
+            Balance {
+                available: balance.available + amount,
+                total: balance.total + amount,
+            },
+        );
+        Ok(account)
+    }
+
+    /// Fails unless `event` is a transfer from this client to `to`, returning the
+    /// amount transferred.
+    fn transferred(&self, to: &Client<T, A>, event: &Event<A>) -> Result<A> {
+        match event.kind() {
+            EventType::Transfer { to: id, amount }
+                if *id == to.id && event.client_id() == self.id =>
+            {
+                Ok(*amount)
+            }
+            _ => bail!("not a transfer from client {} to client {}", self.id, to.id),
+        }
+    }
+
+    /// Describes the changes from the client's current balances in `currency` to those
+    /// of `account` made by `event`, if any, and the transition of the transaction it
+    /// references from its `from` state to `to`.
+    fn outcome(
+        &self,
+        event: Option<&Event<A>>,
+        currency: Option<Currency>,
+        from: Option<TxState<A>>,
+        to: Option<TxState<A>>,
+        account: &Account<A>,
+    ) -> Outcome<A> {
+        let (before, after) = (self.balance(currency), account.balance(currency));
+        Outcome {
+            client: self.id,
+            tx: event.map(Event::tx),
+            timestamp: event.and_then(Event::timestamp),
+            currency,
+            from,
+            to,
+            available: after.available - before.available,
+            held: after.held() - before.held(),
+            total: after.total - before.total,
+            status: (account.status != self.status).then_some(account.status),
+        }
+    }
+
+    /// Adopts the balances of `account`, once saved.
+    fn commit(&mut self, account: Account<A>) {
+        self.available = account.available;
+        self.total = account.total;
+        self.status = account.status;
+        self.currencies = account.currencies;
+    }
+}
+
+impl<T: TxStore<A>, A: Amount> Client<T, A> {
+    /// Creates the client specified by `id`, carrying on from any account balances
+    /// saved in `store`, such as by a previous run against a persistent store.
+    pub fn new(id: ClientId, store: T) -> Client<T, A> {
+        let account = store.account(id).unwrap_or_default();
+        Client::with_account(id, account, store)
+    }
+
+    /// Rebuilds the client specified by `id` purely from the states of its transactions
+    /// in `store`, ignoring any account balances saved there, such as to recover from a
+    /// crash in which only the transactions survived, or to check the balances of a live
+    /// client against.
+    ///
+    /// Funds received by transfer are not stored as transactions of the receiving
+    /// client, so are left out. Administrative events are not stored either, so the
+    /// rebuilt account is frozen if any of its transactions is charged back, and active
+    /// otherwise. Nothing is saved to `store` until the client applies an event.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::clients::Client;
+    /// use payments::events::{Event, Record};
+    /// use payments::storage::MemoryStore;
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let store = MemoryStore::new();
+    /// let mut client = Client::new(1, store.clone());
+    /// for (r#type, tx, amount) in [("deposit", 1, Some(dec!(3.0))), ("deposit", 2, Some(dec!(1.0))), ("dispute", 1, None)] {
+    ///     let record = Record {
+    ///         r#type: r#type.to_string(),
+    ///         client: 1,
+    ///         tx,
+    ///         amount,
+    ///         to: None,
+    ///         seq: None,
+    ///         timestamp: None,
+    ///         currency: None,
+    ///     };
+    ///     client.update(&Event::try_from(record).unwrap()).unwrap();
+    /// }
+    ///
+    /// let rebuilt = Client::rebuild(1, &store);
+    /// assert_eq!(rebuilt.summaries(), client.summaries());
+    /// assert_eq!(rebuilt.held(), dec!(3.0));
+    /// ```
+    pub fn rebuild(id: ClientId, store: &T) -> Client<T, A>
+    where
+        T: Clone,
+    {
+        let mut account = Account::default();
+        for (tx_id, tx) in store.list(id) {
+            let currency = store.currency(tx_id);
+            let mut balance = account.balance(currency);
+            if tx.is_charged_back() {
+                account.status = AccountStatus::Frozen;
+            }
+            let (available, total) = left_in_account(&tx);
+            balance.available += available;
+            balance.total += total;
+            account.set_balance(currency, balance);
+        }
+        Client::with_account(id, account, store.clone())
+    }
+
+    /// Checks the client's balances in every currency against its transactions in the
+    /// transaction storage layer, failing with what drifted if its total funds aren't
+    /// its available funds and those held by its disputed transactions and
+    /// authorizations, its available funds are negative, or the balances saved to the
+    /// store differ from the client's, such as when developing a new store.
+    ///
+    /// Available funds may only be negative under the
+    /// [`DisputePolicy::AllowNegativeAvailable`] policy. Every transaction of the client
+    /// is read, so checks are slow for clients with long histories.
+    pub fn verify_invariants(&self) -> Result<()> {
+        let mut held: BTreeMap<Option<Currency>, A> = BTreeMap::new();
+        for (tx_id, tx) in self.store.list(self.id) {
+            let (available, total) = left_in_account(&tx);
+            if total != available {
+                *held.entry(self.store.currency(tx_id)).or_default() += total - available;
+            }
+        }
+
+        let currencies = [None]
+            .into_iter()
+            .chain(self.currencies.keys().copied().map(Some));
+        for currency in currencies {
+            let balance = self.balance(currency);
+            let name = currency.map_or("the base currency".to_string(), |c| c.to_string());
+            let expected = held.remove(&currency).unwrap_or_default();
+            if balance.held() != expected {
+                bail!(
+                    "client {} has {:?} in total and {:?} available in {}, but its disputed \
+                     transactions hold {:?}",
+                    self.id,
+                    balance.total,
+                    balance.available,
+                    name,
+                    expected
+                );
+            }
+            let negative_allowed =
+                self.policy.insufficient_funds() == DisputePolicy::AllowNegativeAvailable;
+            if balance.available < A::ZERO && !negative_allowed {
+                bail!(
+                    "client {} has {:?} available in {}",
+                    self.id,
+                    balance.available,
+                    name
+                );
+            }
+        }
+        if let Some((currency, amount)) = held.into_iter().next() {
+            bail!(
+                "client {} has no {} balance but its disputed transactions hold {:?} in it",
+                self.id,
+                currency.map_or("base currency".to_string(), |c| c.to_string()),
+                amount
+            );
+        }
+
+        if let Some(saved) = self.store.account(self.id) {
+            if saved != self.account() {
+                bail!(
+                    "client {} has balances {:?} but the store saved {:?}",
+                    self.id,
+                    self.account(),
+                    saved
+                );
+            }
+        }
+        Ok(())
     }
 
     /// Updates the client's transaction state based on the provided payment event.
     ///
-    /// Client state is updated based on the payment [`EventType`]. If the client's
-    /// account is frozen then no update is performed. All events are checked against
+    /// Client state is updated based on the payment [`EventType`]. Events are only
+    /// applied if the [`AccountStatus`] of the client's account allows them. All events are checked against
     /// the transaction storage layer prior to updating state.
     ///
     ///
@@ -95,124 +1144,675 @@ impl<T: TxStore> Client<T> {
     /// [`EventType::Dispute`]
     ///
     /// If the referenced transaction exists and is not already disputed then decrease
-    /// the client's available funds by the amount of the specified transaction
+    /// the client's available funds by the amount of the specified transaction. If the
+    /// amount exceeds the available funds then the dispute is applied according to the
+    /// [`Policy::insufficient_funds`] policy. If the [`Policy::dispute_withdrawals`]
+    /// policy is set and the referenced transaction is a withdrawal then instead
+    /// increase the client's total funds by its amount, holding the provisionally
+    /// credited funds. Disputes filed outside the [`Policy::dispute_window`] are
+    /// rejected
     ///
     /// [`EventType::Resolve`]
     ///
     /// If the referenced transaction exists and is disputed then increase the client's
-    /// available funds by the amount of the specified transaction
+    /// available funds by the amount of the specified transaction. If the
+    /// [`Policy::unlock_on_resolve`] policy is set and the referenced transaction was
+    /// charged back then restore its funds and unfreeze the client's account. Resolving
+    /// a disputed withdrawal instead decreases the client's total funds by its amount,
+    /// withdrawing the provisionally credited funds again
     ///
     /// [`EventType::Chargeback`]
     ///
     /// If the referenced transaction exists and is disputed then decrease the client's
     /// total funds by the amount of the specified transaction and freeze the client's
-    /// account
-    pub fn update(&mut self, event: &Event) -> Result<()> {
-        if self.locked {
-            bail!("account is frozen");
-        }
+    /// account. A chargeback of a disputed withdrawal instead increases the client's
+    /// available funds by its amount, returning the held funds to the client without
+    /// freezing the account
+    ///
+    /// [`EventType::Unlock`]
+    ///
+    /// If the [`Policy::allow_admin_events`] policy is set then unfreeze the client's
+    /// account, as [`Client::unlock`] does
+    ///
+    /// [`EventType::Review`] and [`EventType::Close`]
+    ///
+    /// If the [`Policy::allow_admin_events`] policy is set then place the client's
+    /// account under review, as [`Client::review`] does, or close it, as
+    /// [`Client::close`] does
+    ///
+    /// The client's resulting account balances are saved to the transaction storage
+    /// layer after every successful update, and what changed is returned as an
+    /// [`Outcome`]. Rejected events change nothing, returning the reason as an error.
+    ///
+    /// [`EventType::Transfer`] events involve two clients, so must be applied with
+    /// [`Client::transfer`] instead.
+    ///
+    /// Every event, whether applied or rejected, is recorded to the client's audit log
+    /// if it has one. Events are run through the client's [`Middleware`] first, which
+    /// may replace or reject them.
+    ///
+    /// [`Middleware`]: crate::middleware::Middleware
+    pub fn update(&mut self, event: &Event<A>) -> Result<Outcome<A>> {
+        let event = match self.before(event) {
+            Ok(event) => event,
+            Err(e) => {
+                self.audit(event, Err(&e));
+                return Err(e);
+            }
+        };
+        let result = self.apply(&event);
+        self.audit(&event, result.as_ref());
+        self.after(&event, result.as_ref());
+        result
+    }
 
+    /// Applies `event` as [`Client::update`] does, without recording it.
+    fn apply(&mut self, event: &Event<A>) -> Result<Outcome<A>> {
         match event.kind() {
-            EventType::Deposit(amount) => {
-                if self.store.get(self.id, event.tx()).is_some() {
-                    bail!("cannot overwrite existing transaction");
-                }
+            EventType::Transfer { .. } => bail!("transfers must be applied to both clients"),
+            EventType::Unlock => {
+                self.check_admin(event)?;
+                let outcome = self.unlock()?;
+                return Ok(Outcome {
+                    tx: Some(event.tx()),
+                    timestamp: event.timestamp(),
+                    ..outcome
+                });
+            }
+            EventType::Review => {
+                self.check_admin(event)?;
+                let outcome = self.review()?;
+                return Ok(Outcome {
+                    tx: Some(event.tx()),
+                    timestamp: event.timestamp(),
+                    ..outcome
+                });
+            }
+            EventType::Close => {
+                self.check_admin(event)?;
+                let outcome = self.close()?;
+                return Ok(Outcome {
+                    tx: Some(event.tx()),
+                    timestamp: event.timestamp(),
+                    ..outcome
+                });
+            }
+            _ => {}
+        }
+        self.check_status(event)?;
+        let stored = self.store.get(self.id, event.tx());
+        let currency = match stored {
+            Some(_) => self.store.currency(event.tx()),
+            None => event.currency(),
+        };
+        if self.needs_original(event) {
+            self.check_dispute_window(event, self.store.timestamp(event.tx()))?;
+        }
+        let other_chargebacks = self.needs_chargebacks(event)
+            && self
+                .store
+                .list(self.id)
+                .any(|(tx, state)| tx != event.tx() && state.is_charged_back());
+        let (tx, account) = self.plan(event, stored.clone(), currency, other_chargebacks)?;
+        let outcome = self.outcome(
+            Some(event),
+            currency,
+            stored.clone(),
+            Some(tx.clone()),
+            &account,
+        );
+        self.store.upsert(self.id, event.tx(), tx)?;
+        if stored.is_none() {
+            if let Some(currency) = currency {
+                self.store.set_currency(event.tx(), currency)?;
+            }
+            if let Some(timestamp) = self.recorded_time(event) {
+                self.store.set_timestamp(event.tx(), timestamp)?;
+            }
+        }
+        self.store.save_account(self.id, account.clone())?;
+        self.commit(account);
+        Ok(outcome)
+    }
+
+    /// Unfreezes the client's account, such as once an investigation of the chargeback
+    /// which froze it has reinstated the client, or ends its review, saving its
+    /// balances to the transaction storage layer. Fails unless the account is frozen or
+    /// under review.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::clients::Client;
+    /// use payments::events::{Event, Record};
+    /// use payments::storage::MemoryStore;
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let mut client = Client::new(1, MemoryStore::new());
+    /// for (r#type, amount) in [("deposit", Some(dec!(1.0))), ("dispute", None), ("chargeback", None)] {
+    ///     let record = Record {
+    ///         r#type: r#type.to_string(),
+    ///         client: 1,
+    ///         tx: 1,
+    ///         amount,
+    ///         to: None,
+    ///         seq: None,
+    ///         timestamp: None,
+    ///         currency: None,
+    ///     };
+    ///     client.update(&Event::try_from(record).unwrap()).unwrap();
+    /// }
+    /// assert!(client.locked());
+    ///
+    /// client.unlock().unwrap();
+    /// assert!(!client.locked());
+    /// assert!(client.unlock().is_err());
+    /// ```
+    pub fn unlock(&mut self) -> Result<Outcome<A>> {
+        let account = self.plan_transition(AccountStatus::Active)?;
+        let outcome = self.outcome(None, None, None, None, &account);
+        self.store.save_account(self.id, account.clone())?;
+        self.commit(account);
+        Ok(outcome)
+    }
+
+    /// Places the client's account under review, such as by compliance, saving its
+    /// balances to the transaction storage layer. Accounts under review reject
+    /// withdrawals and transfers out of them until unlocked, while still receiving
+    /// funds. Fails unless the account is active.
+    pub fn review(&mut self) -> Result<Outcome<A>> {
+        let account = self.plan_transition(AccountStatus::UnderReview)?;
+        let outcome = self.outcome(None, None, None, None, &account);
+        self.store.save_account(self.id, account.clone())?;
+        self.commit(account);
+        Ok(outcome)
+    }
+
+    /// Closes the client's account, decommissioning it, saving its balances to the
+    /// transaction storage layer. Closed accounts reject deposits, withdrawals and
+    /// transfers, but their earlier transactions may still be disputed, resolved and
+    /// charged back. Fails if the account is already closed.
+    pub fn close(&mut self) -> Result<Outcome<A>> {
+        let account = self.plan_transition(AccountStatus::Closed)?;
+        let outcome = self.outcome(None, None, None, None, &account);
+        self.store.save_account(self.id, account.clone())?;
+        self.commit(account);
+        Ok(outcome)
+    }
+
+    /// Returns every transaction of the client in the transaction storage layer, in
+    /// order of transaction id, along with its current state, such as for statements.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::clients::Client;
+    /// use payments::events::{Event, Record};
+    /// use payments::storage::{MemoryStore, TxState};
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let mut client = Client::new(1, MemoryStore::new());
+    /// let events = [
+    ///     ("deposit", 2, Some(dec!(2.0))),
+    ///     ("deposit", 1, Some(dec!(1.0))),
+    ///     ("dispute", 1, None),
+    /// ];
+    /// for (r#type, tx, amount) in events {
+    ///     let record = Record {
+    ///         r#type: r#type.to_string(),
+    ///         client: 1,
+    ///         tx,
+    ///         amount,
+    ///         to: None,
+    ///         seq: None,
+    ///         timestamp: None,
+    ///         currency: None,
+    ///     };
+    ///     client.update(&Event::try_from(record).unwrap()).unwrap();
+    /// }
+    ///
+    /// let history: Vec<_> = client.history().collect();
+    /// assert_eq!(
+    ///     history,
+    ///     vec![(1, TxState::Dispute(dec!(1.0))), (2, TxState::Deposit(dec!(2.0)))]
+    /// );
+    /// ```
+    pub fn history(&self) -> impl Iterator<Item = (TxId, TxState<A>)> + '_ {
+        self.store.list(self.id)
+    }
+
+    /// Merges the balances of the same client kept by `other`, such as in another
+    /// partition of the input processed separately, into the client's own, saving them
+    /// to its store. The funds in each currency are added together, and the account
+    /// takes whichever status of the two is most restrictive, so that an account frozen
+    /// or closed in either stays so. The transactions of `other` stay in its own store.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::clients::Client;
+    /// use payments::events::{Event, Record};
+    /// use payments::storage::MemoryStore;
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let deposit = |tx| {
+    ///     Event::try_from(Record {
+    ///         r#type: "deposit".to_string(),
+    ///         client: 1,
+    ///         tx,
+    ///         amount: Some(dec!(1.5)),
+    ///         to: None,
+    ///         seq: None,
+    ///         timestamp: None,
+    ///         currency: None,
+    ///     })
+    ///     .unwrap()
+    /// };
+    /// let mut client = Client::new(1, MemoryStore::new());
+    /// client.update(&deposit(1)).unwrap();
+    /// let mut partition = Client::new(1, MemoryStore::new());
+    /// partition.update(&deposit(2)).unwrap();
+    ///
+    /// client.merge(partition).unwrap();
+    /// assert_eq!(client.total(), dec!(3.0));
+    /// assert!(client.merge(Client::new(2, MemoryStore::new())).is_err());
+    /// ```
+    pub fn merge<U>(&mut self, other: Client<U, A>) -> Result<()> {
+        if other.id != self.id {
+            bail!("cannot merge client {} into client {}", other.id, self.id);
+        }
+        let mut account = self.account();
+        account.available += other.available;
+        account.total += other.total;
+        for (currency, balance) in other.currencies {
+            let merged = account.currencies.entry(currency).or_default();
+            merged.available += balance.available;
+            merged.total += balance.total;
+        }
+        let restriction = |status| match status {
+            AccountStatus::Active => 0,
+            AccountStatus::UnderReview => 1,
+            AccountStatus::Frozen => 2,
+            AccountStatus::Closed => 3,
+        };
+        if restriction(other.status) > restriction(account.status) {
+            account.status = other.status;
+        }
+        self.store.save_account(self.id, account.clone())?;
+        self.commit(account);
+        Ok(())
+    }
 
-                self.store
-                    .upsert(self.id, event.tx(), TxState::Deposit(*amount))?;
-                self.available += amount;
-                self.total += amount;
+    /// Applies a [`EventType::Transfer`] `event` from this client to the `to` client,
+    /// decreasing this client's available and total funds and increasing those of
+    /// `to` by the amount specified. Neither client is changed if the transfer is
+    /// rejected, such as when this client has insufficient available funds or either
+    /// account is frozen.
+    ///
+    /// The transaction is stored as a transfer of this client, so it can't be
+    /// disputed. The [`Outcome`]s of this client and of `to` are returned, in that
+    /// order.
+    ///
+    /// Transfers are run through this client's middleware, as [`Client::update`] does,
+    /// and once applied are observed by the middleware of both clients.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::clients::Client;
+    /// use payments::events::{Event, Record};
+    /// use payments::storage::MemoryStore;
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let store = MemoryStore::new();
+    /// let mut alice = Client::new(1, store.clone());
+    /// let mut bob = Client::new(2, store);
+    /// let record = |r#type: &str, tx, to| Record {
+    ///     r#type: r#type.to_string(),
+    ///     client: 1,
+    ///     tx,
+    ///     amount: Some(dec!(2.0)),
+    ///     to,
+    ///     seq: None,
+    ///     timestamp: None,
+    ///     currency: None,
+    /// };
+    ///
+    /// alice.update(&Event::try_from(record("deposit", 1, None)).unwrap()).unwrap();
+    /// let transfer = Event::try_from(record("transfer", 2, Some(2))).unwrap();
+    /// alice.transfer(&mut bob, &transfer).unwrap();
+    /// assert_eq!(alice.total(), dec!(0.0));
+    /// assert_eq!(bob.available(), dec!(2.0));
+    ///
+    /// let overdraft = Event::try_from(record("transfer", 3, Some(2))).unwrap();
+    /// assert!(alice.transfer(&mut bob, &overdraft).is_err());
+    /// assert_eq!(bob.available(), dec!(2.0));
+    /// ```
+    pub fn transfer(
+        &mut self,
+        to: &mut Client<T, A>,
+        event: &Event<A>,
+    ) -> Result<(Outcome<A>, Outcome<A>)> {
+        let event = match self.before(event) {
+            Ok(event) => event,
+            Err(e) => {
+                self.audit(event, Err(&e));
+                return Err(e);
             }
-            EventType::Withdrawal(amount) => {
-                if self.available < *amount {
-                    bail!("insufficient funds for withdrawal");
-                }
+        };
+        let result = self.apply_transfer(to, &event);
+        self.audit(&event, result.as_ref().map(|(outcome, _)| outcome));
+        self.after(&event, result.as_ref().map(|(outcome, _)| outcome));
+        to.after(&event, result.as_ref().map(|(_, outcome)| outcome));
+        result
+    }
 
-                if self.store.get(self.id, event.tx()).is_some() {
-                    bail!("cannot overwrite existing transaction");
-                }
+    /// Applies a transfer `event` as [`Client::transfer`] does, without recording it.
+    fn apply_transfer(
+        &mut self,
+        to: &mut Client<T, A>,
+        event: &Event<A>,
+    ) -> Result<(Outcome<A>, Outcome<A>)> {
+        let amount = self.transferred(to, event)?;
+        self.check_status(event)?;
+        let stored = self.store.get(self.id, event.tx());
+        let currency = event.currency();
+        let (tx, debited) = self.plan(event, stored.clone(), currency, false)?;
+        let credited = to.plan_credit(amount, currency)?;
+        let outcomes = (
+            self.outcome(Some(event), currency, stored, Some(tx.clone()), &debited),
+            to.outcome(Some(event), currency, None, None, &credited),
+        );
+        // the destination is credited first, as its balances before the transfer are
+        // still at hand to restore should debiting the source fail, so that failing
+        // part way through never leaves funds credited without being debited
+        to.store.save_account(to.id, credited.clone())?;
+        let debit = (|| {
+            self.store.upsert(self.id, event.tx(), tx)?;
+            if let Some(currency) = currency {
+                self.store.set_currency(event.tx(), currency)?;
+            }
+            self.store.save_account(self.id, debited.clone())
+        })();
+        if let Err(e) = debit {
+            return Err(match to.store.save_account(to.id, to.account()) {
+                Ok(()) => e,
+                Err(undo) => e.context(format!(
+                    "crediting client {} could not be undone: {:#}",
+                    to.id, undo
+                )),
+            });
+        }
+        self.commit(debited);
+        to.commit(credited);
+        Ok(outcomes)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T: AsyncTxStore> Client<T> {
+    /// Creates the client specified by `id`, like [`Client::new`], carrying on from
+    /// any account balances saved in the asynchronous `store`.
+    pub async fn new_async(id: ClientId, store: T) -> Client<T> {
+        let account = store.account(id).await.unwrap_or_default();
+        Client::with_account(id, account, store)
+    }
+
+    /// Updates the client's transaction state based on the provided payment event,
+    /// exactly as [`Client::update`] does, but waiting on the asynchronous store
+    /// rather than blocking the thread.
+    pub async fn update_async(&mut self, event: &Event) -> Result<Outcome> {
+        let event = match self.before(event) {
+            Ok(event) => event,
+            Err(e) => {
+                self.audit(event, Err(&e));
+                return Err(e);
+            }
+        };
+        let result = self.apply_async(&event).await;
+        self.audit(&event, result.as_ref());
+        self.after(&event, result.as_ref());
+        result
+    }
+
+    /// Applies `event` as [`Client::update_async`] does, without recording it.
+    async fn apply_async(&mut self, event: &Event) -> Result<Outcome> {
+        match event.kind() {
+            EventType::Transfer { .. } => bail!("transfers must be applied to both clients"),
+            EventType::Unlock => {
+                self.check_admin(event)?;
+                let outcome = self.unlock_async().await?;
+                return Ok(Outcome {
+                    tx: Some(event.tx()),
+                    timestamp: event.timestamp(),
+                    ..outcome
+                });
+            }
+            EventType::Review => {
+                self.check_admin(event)?;
+                let outcome = self.review_async().await?;
+                return Ok(Outcome {
+                    tx: Some(event.tx()),
+                    timestamp: event.timestamp(),
+                    ..outcome
+                });
+            }
+            EventType::Close => {
+                self.check_admin(event)?;
+                let outcome = self.close_async().await?;
+                return Ok(Outcome {
+                    tx: Some(event.tx()),
+                    timestamp: event.timestamp(),
+                    ..outcome
+                });
+            }
+            _ => {}
+        }
+        self.check_status(event)?;
+        let stored = self.store.get(self.id, event.tx()).await;
+        let currency = match stored {
+            Some(_) => self.store.currency(event.tx()).await,
+            None => event.currency(),
+        };
+        if self.needs_original(event) {
+            let original = self.store.timestamp(event.tx()).await;
+            self.check_dispute_window(event, original)?;
+        }
+        let other_chargebacks = self.needs_chargebacks(event)
+            && self
+                .store
+                .list(self.id)
+                .await
+                .into_iter()
+                .any(|(tx, state)| tx != event.tx() && state.is_charged_back());
+        let (tx, account) = self.plan(event, stored.clone(), currency, other_chargebacks)?;
+        let outcome = self.outcome(
+            Some(event),
+            currency,
+            stored.clone(),
+            Some(tx.clone()),
+            &account,
+        );
+        self.store.upsert(self.id, event.tx(), tx).await?;
+        if stored.is_none() {
+            if let Some(currency) = currency {
+                self.store.set_currency(event.tx(), currency).await?;
+            }
+            if let Some(timestamp) = self.recorded_time(event) {
+                self.store.set_timestamp(event.tx(), timestamp).await?;
+            }
+        }
+        self.store.save_account(self.id, account.clone()).await?;
+        self.commit(account);
+        Ok(outcome)
+    }
+
+    /// Unfreezes the client's account, exactly as [`Client::unlock`] does, but waiting
+    /// on the asynchronous store rather than blocking the thread.
+    pub async fn unlock_async(&mut self) -> Result<Outcome> {
+        let account = self.plan_transition(AccountStatus::Active)?;
+        let outcome = self.outcome(None, None, None, None, &account);
+        self.store.save_account(self.id, account.clone()).await?;
+        self.commit(account);
+        Ok(outcome)
+    }
+
+    /// Places the client's account under review, exactly as [`Client::review`] does,
+    /// but waiting on the asynchronous store rather than blocking the thread.
+    pub async fn review_async(&mut self) -> Result<Outcome> {
+        let account = self.plan_transition(AccountStatus::UnderReview)?;
+        let outcome = self.outcome(None, None, None, None, &account);
+        self.store.save_account(self.id, account.clone()).await?;
+        self.commit(account);
+        Ok(outcome)
+    }
 
-                self.store
-                    .upsert(self.id, event.tx(), TxState::Withdrawal)?;
-                self.available -= amount;
-                self.total -= amount;
-            }
-            EventType::Dispute => {
-                let tx = self
-                    .store
-                    .get(self.id, event.tx())
-                    .ok_or_else(|| anyhow!("transaction does not exist"))?;
-                match tx {
-                    TxState::Deposit(amount) => {
-                        if amount > self.available {
-                            bail!("not enough funds to dispute transaction");
-                        }
+    /// Closes the client's account, exactly as [`Client::close`] does, but waiting on
+    /// the asynchronous store rather than blocking the thread.
+    pub async fn close_async(&mut self) -> Result<Outcome> {
+        let account = self.plan_transition(AccountStatus::Closed)?;
+        let outcome = self.outcome(None, None, None, None, &account);
+        self.store.save_account(self.id, account.clone()).await?;
+        self.commit(account);
+        Ok(outcome)
+    }
 
-                        self.store
-                            .upsert(self.id, event.tx(), TxState::Dispute(amount))?;
-                        self.available -= amount;
-                    }
-                    TxState::Dispute(_) => bail!("transaction already disputed"),
-                    TxState::Withdrawal => bail!("cannot dispute a withdrawal"),
-                }
-            }
-            EventType::Resolve => {
-                let tx = self
-                    .store
-                    .get(self.id, event.tx())
-                    .ok_or_else(|| anyhow!("transaction does not exist"))?;
-                match tx {
-                    TxState::Dispute(amount) => {
-                        self.store
-                            .upsert(self.id, event.tx(), TxState::Deposit(amount))?;
-                        self.available += amount;
-                    }
-                    TxState::Deposit(_) | TxState::Withdrawal => {
-                        bail!("transaction is not disputed")
-                    }
-                }
-            }
-            EventType::Chargeback => {
-                let tx = self
-                    .store
-                    .get(self.id, event.tx())
-                    .ok_or_else(|| anyhow!("transaction does not exist"))?;
-                match tx {
-                    TxState::Dispute(amount) => {
-                        self.total -= amount;
-                        self.locked = true;
-                    }
-                    TxState::Deposit(_) | TxState::Withdrawal => {
-                        bail!("transaction is not disputed")
-                    }
-                }
+    /// Returns every transaction of the client, exactly as [`Client::history`] does,
+    /// but waiting on the asynchronous store rather than blocking the thread.
+    pub async fn history_async(&self) -> Vec<(TxId, TxState)> {
+        self.store.list(self.id).await
+    }
+
+    /// Applies a transfer `event` from this client to the `to` client, exactly as
+    /// [`Client::transfer`] does, but waiting on the asynchronous store rather than
+    /// blocking the thread.
+    pub async fn transfer_async(
+        &mut self,
+        to: &mut Client<T>,
+        event: &Event,
+    ) -> Result<(Outcome, Outcome)> {
+        let event = match self.before(event) {
+            Ok(event) => event,
+            Err(e) => {
+                self.audit(event, Err(&e));
+                return Err(e);
             }
         };
+        let result = self.apply_transfer_async(to, &event).await;
+        self.audit(&event, result.as_ref().map(|(outcome, _)| outcome));
+        self.after(&event, result.as_ref().map(|(outcome, _)| outcome));
+        to.after(&event, result.as_ref().map(|(_, outcome)| outcome));
+        result
+    }
 
-        Ok(())
+    /// Applies a transfer `event` as [`Client::transfer_async`] does, without
+    /// recording it.
+    async fn apply_transfer_async(
+        &mut self,
+        to: &mut Client<T>,
+        event: &Event,
+    ) -> Result<(Outcome, Outcome)> {
+        let amount = self.transferred(to, event)?;
+        self.check_status(event)?;
+        let stored = self.store.get(self.id, event.tx()).await;
+        let currency = event.currency();
+        let (tx, debited) = self.plan(event, stored.clone(), currency, false)?;
+        let credited = to.plan_credit(amount, currency)?;
+        let outcomes = (
+            self.outcome(Some(event), currency, stored, Some(tx.clone()), &debited),
+            to.outcome(Some(event), currency, None, None, &credited),
+        );
+        // credited first, as with Client::apply_transfer, so that it can be undone
+        to.store.save_account(to.id, credited.clone()).await?;
+        let debit = async {
+            self.store.upsert(self.id, event.tx(), tx).await?;
+            if let Some(currency) = currency {
+                self.store.set_currency(event.tx(), currency).await?;
+            }
+            self.store.save_account(self.id, debited.clone()).await
+        }
+        .await;
+        if let Err(e) = debit {
+            return Err(match to.store.save_account(to.id, to.account()).await {
+                Ok(()) => e,
+                Err(undo) => e.context(format!(
+                    "crediting client {} could not be undone: {:#}",
+                    to.id, undo
+                )),
+            });
+        }
+        self.commit(debited);
+        to.commit(credited);
+        Ok(outcomes)
+    }
+}
+
+impl<T, A: Amount> Client<T, A> {
+    fn with_account(id: ClientId, account: Account<A>, store: T) -> Client<T, A> {
+        Client {
+            id,
+            available: account.available,
+            total: account.total,
+            status: account.status,
+            currencies: account.currencies,
+            policy: Arc::new(Policy::default()),
+            audit: None,
+            middleware: Vec::new(),
+            store,
+        }
+    }
+}
+
+/// Returns the funds available and in total which a transaction in state `tx` left in
+/// its client's account, along with the events which referenced it, so that an
+/// account can be worked out from its transactions as applying them worked it out.
+fn left_in_account<A: Amount>(tx: &TxState<A>) -> (A, A) {
+    match *tx {
+        TxState::Deposit(amount) | TxState::Represented(amount) => (amount, amount),
+        TxState::Dispute(amount) => (A::ZERO, amount),
+        TxState::PartialDispute {
+            disputed,
+            undisputed,
+        } => (undisputed, disputed + undisputed),
+        TxState::PartialChargedBack { undisputed, .. } => (undisputed, undisputed),
+        TxState::Withdrawal(amount) | TxState::Transfer(amount) => {
+            (A::ZERO - amount, A::ZERO - amount)
+        }
+        TxState::WithdrawalDispute(amount) | TxState::Authorized(amount) => {
+            (A::ZERO - amount, A::ZERO)
+        }
+        TxState::ChargedBack(_) | TxState::WithdrawalChargedBack(_) | TxState::Voided(_) => {
+            (A::ZERO, A::ZERO)
+        }
     }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison, clippy::redundant_pattern_matching)]
 mod tests {
     use super::*;
 
-    use std::sync::Arc;
+    use rust_decimal_macros::dec;
 
-    use crate::MemoryStore;
-    use crate::Record;
+    use std::ops::RangeInclusive;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
 
-    fn event_with_client(t: &str, client: u16, tx: u32, amount: Option<f32>) -> Event {
+    use crate::events::Record;
+    use crate::events::TxId;
+    use crate::storage::{ClientStore, MemoryStore};
+
+    fn event_with_client(t: &str, client: ClientId, tx: TxId, amount: Option<Decimal>) -> Event {
         Event::try_from(Record {
             r#type: t.to_string(),
             client,
             tx,
             amount,
+            to: None,
+            seq: None,
+            timestamp: None,
+            currency: None,
         })
         .unwrap()
     }
 
-    fn event(t: &str, tx: u32, amount: Option<f32>) -> Event {
+    fn event(t: &str, tx: TxId, amount: Option<Decimal>) -> Event {
         event_with_client(t, 1337, tx, amount)
     }
 
@@ -220,25 +1820,63 @@ mod tests {
     fn test_deposit() {
         let mut client = Client::new(1337, MemoryStore::new());
 
-        client.update(&event("deposit", 1, Some(1.0))).unwrap();
-        assert_eq!(client.available(), 1.0);
-        assert_eq!(client.held(), 0.0);
-        assert_eq!(client.total(), 1.0);
+        client
+            .update(&event("deposit", 1, Some(dec!(1.0))))
+            .unwrap();
+        assert_eq!(client.available(), dec!(1.0));
+        assert_eq!(client.held(), dec!(0.0));
+        assert_eq!(client.total(), dec!(1.0));
         assert_eq!(client.locked(), false);
 
-        client.update(&event("deposit", 2, Some(10.0))).unwrap();
-        assert_eq!(client.available(), 11.0);
-        assert_eq!(client.held(), 0.0);
-        assert_eq!(client.total(), 11.0);
+        client
+            .update(&event("deposit", 2, Some(dec!(10.0))))
+            .unwrap();
+        assert_eq!(client.available(), dec!(11.0));
+        assert_eq!(client.held(), dec!(0.0));
+        assert_eq!(client.total(), dec!(11.0));
         assert_eq!(client.locked(), false);
     }
 
+    #[test]
+    fn test_exact_amounts() {
+        let mut client = Client::new(1, MemoryStore::new());
+
+        for tx in 1..=10 {
+            client
+                .update(&event("deposit", tx, Some(dec!(0.1))))
+                .unwrap();
+        }
+        client
+            .update(&event("withdrawal", 11, Some(dec!(0.3))))
+            .unwrap();
+        assert_eq!(client.available(), dec!(0.7));
+        client
+            .update(&event("withdrawal", 12, Some(dec!(0.7))))
+            .unwrap();
+        assert!(client.available().is_zero());
+        assert!(client.total().is_zero());
+    }
+
+    #[test]
+    fn test_deposit_wide_client_id() {
+        let id = u64::from(u32::MAX) + 1;
+        let mut client = Client::new(id, MemoryStore::new());
+
+        client
+            .update(&event_with_client("deposit", id, 1, Some(dec!(1.0))))
+            .unwrap();
+        assert_eq!(client.id(), id);
+        assert_eq!(client.available(), dec!(1.0));
+    }
+
     #[test]
     fn test_deposit_same_tx() {
         let mut client = Client::new(1337, MemoryStore::new());
 
-        client.update(&event("deposit", 1, Some(10.0))).unwrap();
-        if let Ok(_) = client.update(&event("deposit", 1, Some(5.0))) {
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
+        if let Ok(_) = client.update(&event("deposit", 1, Some(dec!(5.0)))) {
             panic!("deposit with pre-existing tx id expected to fail")
         }
     }
@@ -247,10 +1885,12 @@ mod tests {
     fn test_hijack_deposit() {
         let store = MemoryStore::new();
         let mut client = Client::new(1337, Arc::clone(&store));
-        client.update(&event("deposit", 1, Some(10.0))).unwrap();
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
 
         let mut client = Client::new(1234, Arc::clone(&store));
-        if let Ok(_) = client.update(&event_with_client("deposit", 1234, 1, Some(10.0))) {
+        if let Ok(_) = client.update(&event_with_client("deposit", 1234, 1, Some(dec!(10.0)))) {
             panic!("expected deposit of pre-existing tx id for different client to fail")
         }
     }
@@ -259,7 +1899,7 @@ mod tests {
     fn test_double_deposit() {
         let mut client = Client::new(1337, MemoryStore::new());
 
-        let deposit_event = event("deposit", 1, Some(1.0));
+        let deposit_event = event("deposit", 1, Some(dec!(1.0)));
         client.update(&deposit_event).unwrap();
         if let Ok(_) = client.update(&deposit_event) {
             panic!("expected duplicate deposit to fail");
@@ -270,10 +1910,12 @@ mod tests {
     fn test_deposit_frozen() {
         let mut client = Client::new(1337, MemoryStore::new());
 
-        client.update(&event("deposit", 1, Some(1.0))).unwrap();
+        client
+            .update(&event("deposit", 1, Some(dec!(1.0))))
+            .unwrap();
         client.update(&event("dispute", 1, None)).unwrap();
         client.update(&event("chargeback", 1, None)).unwrap();
-        if let Ok(_) = client.update(&event("deposit", 2, Some(10.0))) {
+        if let Ok(_) = client.update(&event("deposit", 2, Some(dec!(10.0)))) {
             panic!("expected deposit to fail for frozen client");
         }
     }
@@ -282,17 +1924,23 @@ mod tests {
     fn test_withdrawal() {
         let mut client = Client::new(1337, MemoryStore::new());
 
-        client.update(&event("deposit", 1, Some(10.0))).unwrap();
-        client.update(&event("withdrawal", 2, Some(9.5))).unwrap();
-        assert_eq!(client.available(), 0.5);
-        assert_eq!(client.held(), 0.0);
-        assert_eq!(client.total(), 0.5);
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
+        client
+            .update(&event("withdrawal", 2, Some(dec!(9.5))))
+            .unwrap();
+        assert_eq!(client.available(), dec!(0.5));
+        assert_eq!(client.held(), dec!(0.0));
+        assert_eq!(client.total(), dec!(0.5));
         assert_eq!(client.locked(), false);
 
-        client.update(&event("withdrawal", 3, Some(0.5))).unwrap();
-        assert_eq!(client.available(), 0.0);
-        assert_eq!(client.held(), 0.0);
-        assert_eq!(client.total(), 0.0);
+        client
+            .update(&event("withdrawal", 3, Some(dec!(0.5))))
+            .unwrap();
+        assert_eq!(client.available(), dec!(0.0));
+        assert_eq!(client.held(), dec!(0.0));
+        assert_eq!(client.total(), dec!(0.0));
         assert_eq!(client.locked(), false);
     }
 
@@ -300,8 +1948,10 @@ mod tests {
     fn test_withdrawal_same_tx() {
         let mut client = Client::new(1337, MemoryStore::new());
 
-        client.update(&event("deposit", 1, Some(10.0))).unwrap();
-        if let Ok(_) = client.update(&event("withdrawal", 1, Some(5.0))) {
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
+        if let Ok(_) = client.update(&event("withdrawal", 1, Some(dec!(5.0)))) {
             panic!("withdrawal with pre-existing tx id expected to fail")
         }
     }
@@ -310,10 +1960,12 @@ mod tests {
     fn test_withdrawal_unowned_tx() {
         let store = MemoryStore::new();
         let mut client = Client::new(1337, Arc::clone(&store));
-        client.update(&event("deposit", 1, Some(10.0))).unwrap();
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
 
         let mut client = Client::new(1234, Arc::clone(&store));
-        if let Ok(_) = client.update(&event_with_client("withdrawal", 1234, 1, Some(10.0))) {
+        if let Ok(_) = client.update(&event_with_client("withdrawal", 1234, 1, Some(dec!(10.0)))) {
             panic!("expected withdrawal of tx associated with different client to fail")
         }
     }
@@ -322,8 +1974,10 @@ mod tests {
     fn test_withdrawal_insufficient() {
         let mut client = Client::new(1337, MemoryStore::new());
 
-        client.update(&event("deposit", 1, Some(10.0))).unwrap();
-        if let Ok(_) = client.update(&event("withdrawal", 2, Some(11.0))) {
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
+        if let Ok(_) = client.update(&event("withdrawal", 2, Some(dec!(11.0)))) {
             panic!("overdraft expected to fail")
         }
     }
@@ -332,9 +1986,11 @@ mod tests {
     fn test_withdrawal_insufficient_held() {
         let mut client = Client::new(1337, MemoryStore::new());
 
-        client.update(&event("deposit", 1, Some(10.0))).unwrap();
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
         client.update(&event("dispute", 1, None)).unwrap();
-        if let Ok(_) = client.update(&event("withdrawal", 2, Some(5.0))) {
+        if let Ok(_) = client.update(&event("withdrawal", 2, Some(dec!(5.0)))) {
             panic!("withdrawal of held funds expected to fail")
         }
     }
@@ -343,24 +1999,34 @@ mod tests {
     fn test_withdrawal_partial_held() {
         let mut client = Client::new(1337, MemoryStore::new());
 
-        client.update(&event("deposit", 1, Some(5.0))).unwrap();
-        client.update(&event("deposit", 2, Some(6.0))).unwrap();
+        client
+            .update(&event("deposit", 1, Some(dec!(5.0))))
+            .unwrap();
+        client
+            .update(&event("deposit", 2, Some(dec!(6.0))))
+            .unwrap();
         client.update(&event("dispute", 1, None)).unwrap();
-        client.update(&event("withdrawal", 3, Some(5.0))).unwrap();
-        assert_eq!(client.available(), 1.0);
-        assert_eq!(client.held(), 5.0);
-        assert_eq!(client.total(), 6.0);
+        client
+            .update(&event("withdrawal", 3, Some(dec!(5.0))))
+            .unwrap();
+        assert_eq!(client.available(), dec!(1.0));
+        assert_eq!(client.held(), dec!(5.0));
+        assert_eq!(client.total(), dec!(6.0));
         assert_eq!(client.locked(), false);
     }
 
     #[test]
     fn test_withdrawal_frozen() {
         let mut client = Client::new(1337, MemoryStore::new());
-        client.update(&event("deposit", 1, Some(5.0))).unwrap();
-        client.update(&event("deposit", 2, Some(6.0))).unwrap();
+        client
+            .update(&event("deposit", 1, Some(dec!(5.0))))
+            .unwrap();
+        client
+            .update(&event("deposit", 2, Some(dec!(6.0))))
+            .unwrap();
         client.update(&event("dispute", 1, None)).unwrap();
         client.update(&event("chargeback", 1, None)).unwrap();
-        if let Ok(_) = client.update(&event("withdrawal", 3, Some(1.0))) {
+        if let Ok(_) = client.update(&event("withdrawal", 3, Some(dec!(1.0)))) {
             panic!("withdrawal from frozen account expected to fail")
         }
     }
@@ -369,20 +2035,64 @@ mod tests {
     fn test_dispute() {
         let mut client = Client::new(1337, MemoryStore::new());
 
-        client.update(&event("deposit", 1, Some(10.0))).unwrap();
-        client.update(&event("deposit", 2, Some(5.0))).unwrap();
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
+        client
+            .update(&event("deposit", 2, Some(dec!(5.0))))
+            .unwrap();
         client.update(&event("dispute", 1, None)).unwrap();
-        assert_eq!(client.available(), 5.0);
-        assert_eq!(client.held(), 10.0);
-        assert_eq!(client.total(), 15.0);
+        assert_eq!(client.available(), dec!(5.0));
+        assert_eq!(client.held(), dec!(10.0));
+        assert_eq!(client.total(), dec!(15.0));
         assert_eq!(client.locked(), false);
     }
 
+    #[test]
+    fn test_dispute_window() {
+        let at = |t: &str, tx: TxId, amount: Option<Decimal>, timestamp: u64| {
+            Event::try_from(Record {
+                r#type: t.to_string(),
+                client: 1337,
+                tx,
+                amount,
+                to: None,
+                seq: None,
+                timestamp: Some(timestamp),
+                currency: None,
+            })
+            .unwrap()
+        };
+        let day = 24 * 60 * 60;
+        let mut client = Client::new(1337, MemoryStore::new()).with_policy(Policy {
+            dispute_window: Some(90 * day),
+            ..Default::default()
+        });
+        client
+            .update(&at("deposit", 1, Some(dec!(1.0)), 0))
+            .unwrap();
+        client
+            .update(&at("deposit", 2, Some(dec!(2.0)), 10 * day))
+            .unwrap();
+        client
+            .update(&event("deposit", 3, Some(dec!(4.0))))
+            .unwrap();
+
+        assert!(client.update(&at("dispute", 1, None, 91 * day)).is_err());
+        let outcome = client.update(&at("dispute", 2, None, 91 * day)).unwrap();
+        assert_eq!(outcome.timestamp, Some(91 * day));
+        // disputes of transactions without a recorded time can't be checked
+        client.update(&at("dispute", 3, None, 365 * day)).unwrap();
+        assert_eq!(client.held(), dec!(6.0));
+    }
+
     #[test]
     fn test_double_dispute() {
         let mut client = Client::new(1337, MemoryStore::new());
 
-        client.update(&event("deposit", 1, Some(10.0))).unwrap();
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
         client.update(&event("dispute", 1, None)).unwrap();
         if let Ok(_) = client.update(&event("dispute", 1, None)) {
             panic!("disputing the same transaction multiple times expected to fail")
@@ -393,7 +2103,9 @@ mod tests {
     fn test_dispute_unowned_tx() {
         let store = MemoryStore::new();
         let mut client = Client::new(1337, Arc::clone(&store));
-        client.update(&event("deposit", 1, Some(10.0))).unwrap();
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
 
         let mut client = Client::new(1234, Arc::clone(&store));
         if let Ok(_) = client.update(&event_with_client("dispute", 1234, 1, None)) {
@@ -401,11 +2113,233 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_dispute_insufficient_funds() {
+        let dispute = |insufficient_funds| {
+            let policy = Policy {
+                insufficient_funds,
+                ..Default::default()
+            };
+            let mut client = Client::new(1337, MemoryStore::new()).with_policy(policy);
+            client
+                .update(&event("deposit", 1, Some(dec!(10.0))))
+                .unwrap();
+            client
+                .update(&event("withdrawal", 2, Some(dec!(4.0))))
+                .unwrap();
+            client.update(&event("dispute", 1, None)).map(|_| client)
+        };
+
+        assert!(dispute(DisputePolicy::Reject).is_err());
+
+        let mut client = dispute(DisputePolicy::AllowNegativeAvailable).unwrap();
+        assert_eq!(client.available(), dec!(-4.0));
+        assert_eq!(client.held(), dec!(10.0));
+        client.update(&event("resolve", 1, None)).unwrap();
+        assert_eq!(client.available(), dec!(6.0));
+
+        let mut client = dispute(DisputePolicy::HoldPartial).unwrap();
+        assert_eq!(client.available(), dec!(0.0));
+        assert_eq!(client.held(), dec!(6.0));
+        client.update(&event("chargeback", 1, None)).unwrap();
+        assert_eq!(client.total(), dec!(0.0));
+
+        // with nothing available to hold, the dispute leaves the transaction undisputed
+        let store = MemoryStore::new();
+        let policy = Policy {
+            insufficient_funds: DisputePolicy::HoldPartial,
+            ..Default::default()
+        };
+        let mut client = Client::new(1337, store.clone()).with_policy(policy);
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
+        client
+            .update(&event("withdrawal", 2, Some(dec!(10.0))))
+            .unwrap();
+        assert!(client.update(&event("dispute", 1, None)).is_err());
+        assert_eq!((client.available(), client.held()), (dec!(0.0), dec!(0.0)));
+        assert_eq!(store.get(1337, 1), Some(TxState::Deposit(dec!(10.0))));
+
+        assert_eq!(
+            DisputePolicy::from_str("hold-partial").unwrap(),
+            DisputePolicy::HoldPartial
+        );
+        assert!(DisputePolicy::from_str("partial").is_err());
+    }
+
+    #[test]
+    fn test_partial_dispute() {
+        let store = MemoryStore::new();
+        let mut client = Client::new(1337, store.clone());
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
+        assert!(client
+            .update(&event("dispute", 1, Some(dec!(10.5))))
+            .is_err());
+
+        let outcome = client
+            .update(&event("dispute", 1, Some(dec!(4.0))))
+            .unwrap();
+        assert_eq!(
+            outcome.to,
+            Some(TxState::PartialDispute {
+                disputed: dec!(4.0),
+                undisputed: dec!(6.0)
+            })
+        );
+        assert_eq!(client.available(), dec!(6.0));
+        assert_eq!(client.held(), dec!(4.0));
+        assert!(client.update(&event("dispute", 1, None)).is_err());
+
+        // resolving restores the whole transaction, so it can be disputed again
+        client.update(&event("resolve", 1, None)).unwrap();
+        assert_eq!(client.available(), dec!(10.0));
+        assert_eq!(store.get(1337, 1), Some(TxState::Deposit(dec!(10.0))));
+        client
+            .update(&event("dispute", 1, Some(dec!(10.0))))
+            .unwrap();
+        assert_eq!(store.get(1337, 1), Some(TxState::Dispute(dec!(10.0))));
+        client.update(&event("resolve", 1, None)).unwrap();
+
+        client
+            .update(&event("dispute", 1, Some(dec!(3.0))))
+            .unwrap();
+        client.update(&event("chargeback", 1, None)).unwrap();
+        assert_eq!(client.available(), dec!(7.0));
+        assert_eq!(client.total(), dec!(7.0));
+        assert!(client.locked());
+        assert_eq!(
+            store.get(1337, 1),
+            Some(TxState::PartialChargedBack {
+                charged_back: dec!(3.0),
+                undisputed: dec!(7.0)
+            })
+        );
+
+        // withdrawals are only disputed in full
+        let mut client = Client::new(1234, MemoryStore::new()).with_policy(Policy {
+            dispute_withdrawals: true,
+            ..Default::default()
+        });
+        client
+            .update(&event_with_client("deposit", 1234, 1, Some(dec!(5.0))))
+            .unwrap();
+        client
+            .update(&event_with_client("withdrawal", 1234, 2, Some(dec!(2.0))))
+            .unwrap();
+        assert!(client
+            .update(&event_with_client("dispute", 1234, 2, Some(dec!(1.0))))
+            .is_err());
+        client
+            .update(&event_with_client("dispute", 1234, 2, Some(dec!(2.0))))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_partial_chargeback() {
+        // resolving the chargeback restores the whole deposit
+        let store = MemoryStore::new();
+        let mut client = Client::new(1337, store.clone()).with_policy(Policy {
+            unlock_on_resolve: true,
+            ..Default::default()
+        });
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
+        client
+            .update(&event("dispute", 1, Some(dec!(4.0))))
+            .unwrap();
+        client.update(&event("chargeback", 1, None)).unwrap();
+        assert_eq!(client.total(), dec!(6.0));
+        client.update(&event("resolve", 1, None)).unwrap();
+        assert_eq!(client.available(), dec!(10.0));
+        assert!(!client.locked());
+        assert_eq!(store.get(1337, 1), Some(TxState::Deposit(dec!(10.0))));
+
+        // and so does representing it
+        let store = MemoryStore::new();
+        let mut client = Client::new(1337, store.clone());
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
+        client
+            .update(&event("dispute", 1, Some(dec!(4.0))))
+            .unwrap();
+        client.update(&event("chargeback", 1, None)).unwrap();
+        let outcome = client.update(&event("representment", 1, None)).unwrap();
+        assert_eq!(outcome.to, Some(TxState::Represented(dec!(10.0))));
+        assert_eq!(client.available(), dec!(10.0));
+        assert_eq!(client.total(), dec!(10.0));
+        assert!(!client.locked());
+        assert!(client.update(&event("representment", 1, None)).is_err());
+    }
+
+    #[test]
+    fn test_authorize_capture() {
+        let store = MemoryStore::new();
+        let mut client = Client::new(1337, store.clone());
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
+        assert!(client
+            .update(&event("authorize", 2, Some(dec!(11.0))))
+            .is_err());
+
+        // an authorization holds funds without settling them
+        client
+            .update(&event("authorize", 2, Some(dec!(4.0))))
+            .unwrap();
+        assert_eq!(client.available(), dec!(6.0));
+        assert_eq!(client.held(), dec!(4.0));
+        assert_eq!(client.total(), dec!(10.0));
+        assert!(client
+            .update(&event("withdrawal", 3, Some(dec!(7.0))))
+            .is_err());
+        assert!(client.update(&event("dispute", 2, None)).is_err());
+        assert!(client
+            .update(&event("capture", 2, Some(dec!(5.0))))
+            .is_err());
+
+        // a partial capture settles part of it and releases the rest
+        client
+            .update(&event("capture", 2, Some(dec!(3.0))))
+            .unwrap();
+        assert_eq!(client.available(), dec!(7.0));
+        assert_eq!(client.held(), dec!(0.0));
+        assert_eq!(client.total(), dec!(7.0));
+        assert_eq!(store.get(1337, 2), Some(TxState::Withdrawal(dec!(3.0))));
+        assert!(client.update(&event("capture", 2, None)).is_err());
+        assert!(client.update(&event("void", 2, None)).is_err());
+
+        client
+            .update(&event("authorize", 4, Some(dec!(2.0))))
+            .unwrap();
+        client.update(&event("void", 4, None)).unwrap();
+        assert_eq!(client.available(), dec!(7.0));
+        assert_eq!(client.total(), dec!(7.0));
+        assert_eq!(store.get(1337, 4), Some(TxState::Voided(dec!(2.0))));
+        assert!(client.update(&event("void", 4, None)).is_err());
+        assert!(client.update(&event("capture", 4, None)).is_err());
+
+        client
+            .update(&event("authorize", 5, Some(dec!(7.0))))
+            .unwrap();
+        client.update(&event("capture", 5, None)).unwrap();
+        assert_eq!(client.total(), dec!(0.0));
+        assert!(client.update(&event("capture", 6, None)).is_err());
+    }
+
     #[test]
     fn test_dispute_frozen() {
         let mut client = Client::new(1337, MemoryStore::new());
-        client.update(&event("deposit", 1, Some(5.0))).unwrap();
-        client.update(&event("deposit", 2, Some(6.0))).unwrap();
+        client
+            .update(&event("deposit", 1, Some(dec!(5.0))))
+            .unwrap();
+        client
+            .update(&event("deposit", 2, Some(dec!(6.0))))
+            .unwrap();
         client.update(&event("dispute", 1, None)).unwrap();
         client.update(&event("chargeback", 1, None)).unwrap();
         if let Ok(_) = client.update(&event("dispute", 2, None)) {
@@ -417,12 +2351,14 @@ mod tests {
     fn test_resolve() {
         let mut client = Client::new(1337, MemoryStore::new());
 
-        client.update(&event("deposit", 1, Some(10.0))).unwrap();
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
         client.update(&event("dispute", 1, None)).unwrap();
         client.update(&event("resolve", 1, None)).unwrap();
-        assert_eq!(client.available(), 10.0);
-        assert_eq!(client.held(), 0.0);
-        assert_eq!(client.total(), 10.0);
+        assert_eq!(client.available(), dec!(10.0));
+        assert_eq!(client.held(), dec!(0.0));
+        assert_eq!(client.total(), dec!(10.0));
         assert_eq!(client.locked(), false);
     }
 
@@ -430,7 +2366,9 @@ mod tests {
     fn test_double_resolve() {
         let mut client = Client::new(1337, MemoryStore::new());
 
-        client.update(&event("deposit", 1, Some(10.0))).unwrap();
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
         client.update(&event("dispute", 1, None)).unwrap();
         client.update(&event("resolve", 1, None)).unwrap();
         if let Ok(_) = client.update(&event("resolve", 1, None)) {
@@ -442,7 +2380,9 @@ mod tests {
     fn test_resolve_unowned_tx() {
         let store = MemoryStore::new();
         let mut client = Client::new(1337, Arc::clone(&store));
-        client.update(&event("deposit", 1, Some(10.0))).unwrap();
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
         client.update(&event("dispute", 1, None)).unwrap();
 
         let mut client = Client::new(1234, Arc::clone(&store));
@@ -454,8 +2394,12 @@ mod tests {
     #[test]
     fn test_resolve_frozen() {
         let mut client = Client::new(1337, MemoryStore::new());
-        client.update(&event("deposit", 1, Some(5.0))).unwrap();
-        client.update(&event("deposit", 2, Some(6.0))).unwrap();
+        client
+            .update(&event("deposit", 1, Some(dec!(5.0))))
+            .unwrap();
+        client
+            .update(&event("deposit", 2, Some(dec!(6.0))))
+            .unwrap();
         client.update(&event("dispute", 1, None)).unwrap();
         client.update(&event("chargeback", 1, None)).unwrap();
         if let Ok(_) = client.update(&event("resolve", 1, None)) {
@@ -467,20 +2411,365 @@ mod tests {
     fn test_chargeback() {
         let mut client = Client::new(1337, MemoryStore::new());
 
-        client.update(&event("deposit", 1, Some(10.0))).unwrap();
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
         client.update(&event("dispute", 1, None)).unwrap();
         client.update(&event("chargeback", 1, None)).unwrap();
-        assert_eq!(client.available(), 0.0);
-        assert_eq!(client.held(), 0.0);
-        assert_eq!(client.total(), 0.0);
+        assert_eq!(client.available(), dec!(0.0));
+        assert_eq!(client.held(), dec!(0.0));
+        assert_eq!(client.total(), dec!(0.0));
         assert_eq!(client.locked(), true);
     }
 
+    #[test]
+    fn test_unlock_on_resolve() {
+        let policy = Policy {
+            unlock_on_resolve: true,
+            ..Default::default()
+        };
+        let mut client = Client::new(1337, MemoryStore::new()).with_policy(policy);
+        client
+            .update(&event("deposit", 1, Some(dec!(5.0))))
+            .unwrap();
+        client
+            .update(&event("deposit", 2, Some(dec!(6.0))))
+            .unwrap();
+        client
+            .update(&event("deposit", 3, Some(dec!(7.0))))
+            .unwrap();
+        client.update(&event("dispute", 1, None)).unwrap();
+        client.update(&event("dispute", 2, None)).unwrap();
+        client.update(&event("chargeback", 1, None)).unwrap();
+        assert!(client.locked());
+
+        // only the charged back transaction may be resolved while frozen
+        if client.update(&event("resolve", 2, None)).is_ok() {
+            panic!("resolve of disputed tx associated with frozen account expected to fail")
+        }
+        client.update(&event("resolve", 1, None)).unwrap();
+        assert_eq!(client.available(), dec!(12.0));
+        assert_eq!(client.held(), dec!(6.0));
+        assert_eq!(client.total(), dec!(18.0));
+        assert!(!client.locked());
+
+        client.update(&event("resolve", 2, None)).unwrap();
+        if client.update(&event("resolve", 1, None)).is_ok() {
+            panic!("double resolve of charged back tx expected to fail")
+        }
+        assert_eq!(client.available(), dec!(18.0));
+
+        // the account stays frozen while another chargeback is outstanding
+        for event in [
+            event("dispute", 1, None),
+            event("dispute", 2, None),
+            event("chargeback", 1, None),
+        ] {
+            client.update(&event).unwrap();
+        }
+        client.unlock().unwrap();
+        client.update(&event("chargeback", 2, None)).unwrap();
+        client.update(&event("resolve", 1, None)).unwrap();
+        assert!(client.locked());
+        client.update(&event("resolve", 2, None)).unwrap();
+        assert!(!client.locked());
+        assert_eq!(client.available(), dec!(18.0));
+    }
+
+    #[test]
+    fn test_unlock() {
+        let store = MemoryStore::new();
+        let mut client = Client::new(1337, store.clone());
+        client
+            .update(&event("deposit", 1, Some(dec!(5.0))))
+            .unwrap();
+        client
+            .update(&event("deposit", 2, Some(dec!(3.0))))
+            .unwrap();
+        client.update(&event("dispute", 1, None)).unwrap();
+        client.update(&event("chargeback", 1, None)).unwrap();
+        assert!(client.locked());
+
+        // admin events are only applied when allowed
+        if client.update(&event("unlock", 3, None)).is_ok() {
+            panic!("unlock expected to fail without admin events allowed")
+        }
+        let policy = Policy {
+            allow_admin_events: true,
+            ..Default::default()
+        };
+        let mut client = client.with_policy(policy);
+        client.update(&event("unlock", 3, None)).unwrap();
+        assert!(!client.locked());
+        assert_eq!(client.available(), dec!(3.0));
+        assert_eq!(store.account(1337).unwrap().status, AccountStatus::Active);
+        assert_eq!(store.get(1337, 3), None);
+
+        if client.update(&event("unlock", 4, None)).is_ok() {
+            panic!("unlock of unfrozen account expected to fail")
+        }
+        client
+            .update(&event("withdrawal", 5, Some(dec!(1.0))))
+            .unwrap();
+        assert_eq!(client.total(), dec!(2.0));
+    }
+
+    #[test]
+    fn test_close() {
+        let store = MemoryStore::new();
+        let mut client = Client::new(1337, store.clone());
+        client
+            .update(&event("deposit", 1, Some(dec!(5.0))))
+            .unwrap();
+        assert!(client.update(&event("close", 2, None)).is_err());
+
+        let mut client = client.with_policy(Policy {
+            allow_admin_events: true,
+            ..Default::default()
+        });
+        let outcome = client.update(&event("close", 2, None)).unwrap();
+        assert_eq!(outcome.status, Some(AccountStatus::Closed));
+        assert!(client.closed());
+        assert!(!client.locked());
+        assert_eq!(store.account(1337).unwrap().status, AccountStatus::Closed);
+        assert_eq!(client.summary().status, AccountStatus::Closed);
+        assert!(client.update(&event("close", 3, None)).is_err());
+
+        // no more funds move in or out of a closed account
+        assert!(client
+            .update(&event("deposit", 4, Some(dec!(1.0))))
+            .is_err());
+        assert!(client
+            .update(&event("withdrawal", 5, Some(dec!(1.0))))
+            .is_err());
+        let mut other = Client::new(1234, store.clone());
+        other.update(&event("deposit", 6, Some(dec!(1.0)))).unwrap();
+        let transfer = |from, tx, to| {
+            Event::try_from(Record {
+                r#type: "transfer".to_string(),
+                client: from,
+                tx,
+                amount: Some(dec!(1.0)),
+                to: Some(to),
+                seq: None,
+                timestamp: None,
+                currency: None,
+            })
+            .unwrap()
+        };
+        assert!(client
+            .transfer(&mut other, &transfer(1337, 7, 1234))
+            .is_err());
+        assert!(other
+            .transfer(&mut client, &transfer(1234, 8, 1337))
+            .is_err());
+        assert_eq!(client.total(), dec!(5.0));
+
+        // while its history can still be disputed
+        client.update(&event("dispute", 1, None)).unwrap();
+        client.update(&event("chargeback", 1, None)).unwrap();
+        // without being frozen, as it stays closed
+        assert!(!client.locked());
+        assert!(client.closed());
+        assert_eq!(client.total(), dec!(0.0));
+    }
+
+    #[test]
+    fn test_representment() {
+        let store = MemoryStore::new();
+        let mut client = Client::new(1337, store.clone()).with_policy(Policy {
+            allow_admin_events: true,
+            ..Default::default()
+        });
+        for tx in [1, 2] {
+            client
+                .update(&event("deposit", tx, Some(dec!(2.0))))
+                .unwrap();
+        }
+        assert!(client.update(&event("representment", 1, None)).is_err());
+        assert!(client.update(&event("representment", 3, None)).is_err());
+        client.update(&event("dispute", 1, None)).unwrap();
+        client.update(&event("chargeback", 1, None)).unwrap();
+        client.unlock().unwrap();
+        client.update(&event("dispute", 2, None)).unwrap();
+        client.update(&event("chargeback", 2, None)).unwrap();
+        assert_eq!(client.total(), dec!(0.0));
+
+        // the account stays frozen while another chargeback is outstanding
+        let outcome = client.update(&event("representment", 1, None)).unwrap();
+        assert_eq!(outcome.to, Some(TxState::Represented(dec!(2.0))));
+        assert_eq!(client.available(), dec!(2.0));
+        assert_eq!(client.total(), dec!(2.0));
+        assert!(client.locked());
+        assert!(client.update(&event("representment", 1, None)).is_err());
+        assert!(client.update(&event("dispute", 1, None)).is_err());
+
+        client.update(&event("representment", 2, None)).unwrap();
+        assert_eq!(client.available(), dec!(4.0));
+        assert_eq!(client.status(), AccountStatus::Active);
+        assert_eq!(store.get(1337, 2), Some(TxState::Represented(dec!(2.0))));
+    }
+
+    #[test]
+    fn test_account_status() {
+        let mut client = Client::new(1337, MemoryStore::new()).with_policy(Policy {
+            allow_admin_events: true,
+            ..Default::default()
+        });
+        client
+            .update(&event("deposit", 1, Some(dec!(5.0))))
+            .unwrap();
+        assert!(client.update(&event("unlock", 2, None)).is_err());
+
+        // accounts under review still receive funds, without paying any out
+        client.update(&event("review", 3, None)).unwrap();
+        assert_eq!(client.status(), AccountStatus::UnderReview);
+        assert!(!client.locked());
+        assert!(client.update(&event("review", 4, None)).is_err());
+        client
+            .update(&event("deposit", 5, Some(dec!(1.0))))
+            .unwrap();
+        let err = client
+            .update(&event("withdrawal", 6, Some(dec!(1.0))))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "account is under review");
+        client.update(&event("dispute", 1, None)).unwrap();
+
+        // a chargeback freezes an account under review
+        client.update(&event("chargeback", 1, None)).unwrap();
+        assert_eq!(client.status(), AccountStatus::Frozen);
+        assert!(client.update(&event("review", 7, None)).is_err());
+        client.update(&event("unlock", 8, None)).unwrap();
+        assert_eq!(client.status(), AccountStatus::Active);
+        client
+            .update(&event("withdrawal", 9, Some(dec!(1.0))))
+            .unwrap();
+
+        client.update(&event("close", 10, None)).unwrap();
+        assert!(client.update(&event("unlock", 11, None)).is_err());
+        assert!(client.update(&event("review", 12, None)).is_err());
+        assert_eq!(client.status(), AccountStatus::Closed);
+
+        assert_eq!(
+            "under_review".parse::<AccountStatus>().unwrap(),
+            AccountStatus::UnderReview
+        );
+        assert!("locked".parse::<AccountStatus>().is_err());
+    }
+
+    #[test]
+    fn test_outcome() {
+        let mut client = Client::new(1337, MemoryStore::new());
+        let outcome = client
+            .update(&event("deposit", 1, Some(dec!(5.0))))
+            .unwrap();
+        assert_eq!(
+            outcome,
+            Outcome {
+                client: 1337,
+                tx: Some(1),
+                timestamp: None,
+                currency: None,
+                from: None,
+                to: Some(TxState::Deposit(dec!(5.0))),
+                available: dec!(5.0),
+                held: dec!(0.0),
+                total: dec!(5.0),
+                status: None,
+            }
+        );
+
+        let outcome = client.update(&event("dispute", 1, None)).unwrap();
+        assert_eq!(outcome.from, Some(TxState::Deposit(dec!(5.0))));
+        assert_eq!(outcome.to, Some(TxState::Dispute(dec!(5.0))));
+        assert_eq!(
+            (outcome.available, outcome.held, outcome.total),
+            (dec!(-5.0), dec!(5.0), dec!(0.0))
+        );
+
+        let outcome = client.update(&event("chargeback", 1, None)).unwrap();
+        assert_eq!(
+            (outcome.available, outcome.held, outcome.total),
+            (dec!(0.0), dec!(-5.0), dec!(-5.0))
+        );
+        assert_eq!(outcome.status, Some(AccountStatus::Frozen));
+
+        let mut client = client.with_policy(Policy {
+            allow_admin_events: true,
+            ..Default::default()
+        });
+        let outcome = client.update(&event("unlock", 2, None)).unwrap();
+        assert_eq!(outcome.tx, Some(2));
+        assert_eq!((outcome.from, outcome.to), (None, None));
+        assert_eq!(outcome.status, Some(AccountStatus::Active));
+    }
+
+    #[test]
+    fn test_currencies() {
+        let eur: Currency = "EUR".parse().unwrap();
+        let in_eur = |t: &str, tx: TxId, amount: Option<Decimal>| {
+            Event::try_from(Record {
+                r#type: t.to_string(),
+                client: 1337,
+                tx,
+                amount,
+                to: None,
+                seq: None,
+                timestamp: None,
+                currency: Some(eur),
+            })
+            .unwrap()
+        };
+        let store = MemoryStore::new();
+        let mut client = Client::new(1337, store.clone());
+        client
+            .update(&event("deposit", 1, Some(dec!(1.0))))
+            .unwrap();
+        let outcome = client
+            .update(&in_eur("deposit", 2, Some(dec!(5.0))))
+            .unwrap();
+        assert_eq!(outcome.currency, Some(eur));
+        assert_eq!(client.available(), dec!(1.0));
+        assert_eq!(client.balance(Some(eur)).available, dec!(5.0));
+
+        // disputes are in the currency of the transaction they reference
+        client.update(&event("dispute", 2, None)).unwrap();
+        assert_eq!(client.balance(Some(eur)).held(), dec!(5.0));
+        assert_eq!(client.held(), dec!(0.0));
+        assert!(client.update(&in_eur("resolve", 1, None)).is_err());
+        client.update(&in_eur("resolve", 2, None)).unwrap();
+
+        // balances in one currency can't be spent in another
+        assert!(client
+            .update(&event("withdrawal", 3, Some(dec!(2.0))))
+            .is_err());
+        client
+            .update(&in_eur("withdrawal", 3, Some(dec!(2.0))))
+            .unwrap();
+
+        let summaries = client.summaries();
+        assert_eq!(summaries.len(), 2);
+        assert_eq!(
+            (summaries[0].currency, summaries[0].total),
+            (None, dec!(1.0))
+        );
+        assert_eq!(
+            (summaries[1].currency, summaries[1].total),
+            (Some(eur), dec!(3.0))
+        );
+
+        // balances in every currency carry on from the store
+        let client = Client::new(1337, store);
+        assert_eq!(client.balance(Some(eur)).total, dec!(3.0));
+    }
+
     #[test]
     fn test_double_chargeback() {
         let mut client = Client::new(1337, MemoryStore::new());
 
-        client.update(&event("deposit", 1, Some(10.0))).unwrap();
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
         client.update(&event("dispute", 1, None)).unwrap();
         client.update(&event("chargeback", 1, None)).unwrap();
         if let Ok(_) = client.update(&event("chargeback", 1, None)) {
@@ -492,7 +2781,9 @@ mod tests {
     fn test_chargeback_unowned_tx() {
         let store = MemoryStore::new();
         let mut client = Client::new(1337, Arc::clone(&store));
-        client.update(&event("deposit", 1, Some(10.0))).unwrap();
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
         client.update(&event("dispute", 1, None)).unwrap();
 
         let mut client = Client::new(1234, Arc::clone(&store));
@@ -500,4 +2791,558 @@ mod tests {
             panic!("chargeback tx associated with different client expected to fail")
         }
     }
+
+    /// Only allows disputes of deposits up to a limit, and deposits to frozen accounts.
+    #[derive(Debug)]
+    struct LimitedDisputes;
+
+    impl AccountPolicy for LimitedDisputes {
+        fn can_dispute(&self, _event: &Event, tx: &TxState) -> bool {
+            matches!(tx, TxState::Deposit(amount) if *amount <= dec!(5.0))
+        }
+
+        fn allow_when_frozen(&self, event: &Event) -> bool {
+            matches!(event.kind(), EventType::Deposit(_))
+        }
+    }
+
+    #[test]
+    fn test_account_policy() {
+        let mut client = Client::new(1337, MemoryStore::new()).with_policy(LimitedDisputes);
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
+        client
+            .update(&event("deposit", 2, Some(dec!(5.0))))
+            .unwrap();
+        let refused = client.update(&event("dispute", 1, None)).unwrap_err();
+        assert_eq!(refused.to_string(), "cannot dispute a deposit");
+
+        client.update(&event("dispute", 2, None)).unwrap();
+        client.update(&event("chargeback", 2, None)).unwrap();
+        assert!(client.locked());
+
+        // the policy lets frozen accounts keep receiving deposits, and nothing else
+        client
+            .update(&event("deposit", 3, Some(dec!(1.0))))
+            .unwrap();
+        assert!(client
+            .update(&event("withdrawal", 4, Some(dec!(1.0))))
+            .is_err());
+        assert_eq!(client.available(), dec!(11.0));
+        assert_eq!(client.total(), dec!(11.0));
+    }
+
+    #[test]
+    fn test_dispute_withdrawal() {
+        let mut client = Client::new(1337, MemoryStore::new());
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
+        client
+            .update(&event("withdrawal", 2, Some(dec!(4.0))))
+            .unwrap();
+        if client.update(&event("dispute", 2, None)).is_ok() {
+            panic!("disputing a withdrawal expected to fail without the policy")
+        }
+
+        let policy = Policy {
+            dispute_withdrawals: true,
+            ..Default::default()
+        };
+        let mut client = client.with_policy(policy);
+        // the withdrawn funds are provisionally credited back, and held
+        client.update(&event("dispute", 2, None)).unwrap();
+        assert_eq!(client.available(), dec!(6.0));
+        assert_eq!(client.held(), dec!(4.0));
+        assert_eq!(client.total(), dec!(10.0));
+        assert!(client.update(&event("dispute", 2, None)).is_err());
+
+        client.update(&event("resolve", 2, None)).unwrap();
+        assert_eq!(client.available(), dec!(6.0));
+        assert_eq!(client.held(), dec!(0.0));
+        assert_eq!(client.total(), dec!(6.0));
+
+        // a chargeback returns the funds without freezing the account
+        client.update(&event("dispute", 2, None)).unwrap();
+        client.update(&event("chargeback", 2, None)).unwrap();
+        assert_eq!(client.available(), dec!(10.0));
+        assert_eq!(client.held(), dec!(0.0));
+        assert_eq!(client.total(), dec!(10.0));
+        assert!(!client.locked());
+        assert!(client.update(&event("dispute", 2, None)).is_err());
+        assert!(client.update(&event("resolve", 2, None)).is_err());
+    }
+
+    #[test]
+    fn test_saved_account() {
+        let store = MemoryStore::new();
+        let mut client = Client::new(1337, Arc::clone(&store));
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
+        client
+            .update(&event("withdrawal", 2, Some(dec!(4.0))))
+            .unwrap();
+        assert!(client
+            .update(&event("withdrawal", 3, Some(dec!(7.0))))
+            .is_err());
+        assert_eq!(store.clients(), [1337]);
+
+        // a client created later, such as by a later run, carries on from the store
+        let mut client = Client::new(1337, Arc::clone(&store));
+        assert_eq!(client.available(), dec!(6.0));
+        assert_eq!(client.total(), dec!(6.0));
+        client.update(&event("dispute", 1, None)).unwrap_err();
+        client
+            .update(&event("deposit", 4, Some(dec!(4.0))))
+            .unwrap();
+        client.update(&event("dispute", 1, None)).unwrap();
+        client.update(&event("chargeback", 1, None)).unwrap();
+
+        let client = Client::new(1337, Arc::clone(&store));
+        assert_eq!(client.total(), dec!(0.0));
+        assert!(client.locked());
+    }
+
+    fn transfer(tx: TxId, to: Option<ClientId>, amount: Option<Decimal>) -> Result<Event> {
+        Event::try_from(Record {
+            r#type: "transfer".to_string(),
+            client: 1337,
+            tx,
+            amount,
+            to,
+            seq: None,
+            timestamp: None,
+            currency: None,
+        })
+    }
+
+    #[test]
+    fn test_transfer() {
+        let store = MemoryStore::new();
+        let mut client = Client::new(1337, Arc::clone(&store));
+        let mut other = Client::new(1234, Arc::clone(&store));
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
+
+        let event = transfer(2, Some(1234), Some(dec!(4.0))).unwrap();
+        assert!(client.update(&event).is_err());
+        client.transfer(&mut other, &event).unwrap();
+        assert_eq!(client.available(), dec!(6.0));
+        assert_eq!(client.total(), dec!(6.0));
+        assert_eq!(other.available(), dec!(4.0));
+        assert_eq!(other.total(), dec!(4.0));
+        assert_eq!(
+            store.account(1234),
+            Some(Account {
+                available: dec!(4.0),
+                total: dec!(4.0),
+                status: AccountStatus::Active,
+                currencies: BTreeMap::new(),
+            })
+        );
+        // the transfer reuses its transaction id, and can't be disputed
+        assert!(client.transfer(&mut other, &event).is_err());
+        assert!(client
+            .update(&event_with_client("dispute", 1337, 2, None))
+            .is_err());
+        // transfers can only be applied from their source to their destination
+        assert!(other.transfer(&mut client, &event).is_err());
+
+        // neither client changes when the source has insufficient funds
+        let event = transfer(3, Some(1234), Some(dec!(7.0))).unwrap();
+        assert!(client.transfer(&mut other, &event).is_err());
+        assert_eq!(client.available(), dec!(6.0));
+        assert_eq!(other.available(), dec!(4.0));
+        assert_eq!(store.get(1337, 3), None);
+
+        assert!(transfer(4, None, Some(dec!(1.0))).is_err());
+        assert!(transfer(4, Some(1337), Some(dec!(1.0))).is_err());
+        assert!(transfer(4, Some(1234), None).is_err());
+        assert!(transfer(4, Some(1234), Some(dec!(-1.0))).is_err());
+    }
+
+    /// A memory store whose writes fail once `fail` is set.
+    #[derive(Clone, Default)]
+    struct FailingStore {
+        inner: Arc<Mutex<MemoryStore>>,
+        fail: Arc<AtomicBool>,
+    }
+
+    impl FailingStore {
+        fn check(&self) -> Result<()> {
+            if self.fail.load(Ordering::SeqCst) {
+                bail!("store unavailable");
+            }
+            Ok(())
+        }
+    }
+
+    impl ClientStore for FailingStore {
+        fn account(&self, client_id: ClientId) -> Option<Account> {
+            self.inner.account(client_id)
+        }
+
+        fn save_account(&mut self, client_id: ClientId, account: Account) -> Result<()> {
+            self.check()?;
+            self.inner.save_account(client_id, account)
+        }
+
+        fn clients(&self) -> Vec<ClientId> {
+            self.inner.clients()
+        }
+    }
+
+    impl TxStore for FailingStore {
+        fn get(&self, client_id: ClientId, tx_id: TxId) -> Option<TxState> {
+            self.inner.get(client_id, tx_id)
+        }
+
+        fn upsert(&mut self, client_id: ClientId, tx_id: TxId, tx: TxState) -> Result<()> {
+            self.check()?;
+            self.inner.upsert(client_id, tx_id, tx)
+        }
+
+        fn list(&self, client_id: ClientId) -> impl Iterator<Item = (TxId, TxState)> {
+            self.inner.list(client_id)
+        }
+
+        fn currency(&self, tx_id: TxId) -> Option<Currency> {
+            self.inner.currency(tx_id)
+        }
+
+        fn set_currency(&mut self, tx_id: TxId, currency: Currency) -> Result<()> {
+            self.check()?;
+            self.inner.set_currency(tx_id, currency)
+        }
+
+        fn timestamp(&self, tx_id: TxId) -> Option<u64> {
+            self.inner.timestamp(tx_id)
+        }
+
+        fn set_timestamp(&mut self, tx_id: TxId, timestamp: u64) -> Result<()> {
+            self.check()?;
+            self.inner.set_timestamp(tx_id, timestamp)
+        }
+
+        fn prune(&mut self, before: u64) -> Result<usize> {
+            self.check()?;
+            self.inner.prune(before)
+        }
+
+        fn set_pruned(&mut self, tx_ids: RangeInclusive<TxId>) -> Result<()> {
+            self.check()?;
+            self.inner.set_pruned(tx_ids)
+        }
+    }
+
+    #[test]
+    fn test_transfer_failing_store() {
+        let source = FailingStore::default();
+        let destination = FailingStore {
+            inner: Arc::clone(&source.inner),
+            ..FailingStore::default()
+        };
+        let mut client = Client::new(1337, source.clone());
+        let mut other = Client::new(1234, destination.clone());
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
+        let unchanged = |client: &Client<FailingStore>, other: &Client<FailingStore>| {
+            assert_eq!(client.available(), dec!(10.0));
+            assert_eq!(other.available(), dec!(0));
+            assert_eq!(source.account(1337).unwrap().available, dec!(10.0));
+            assert_eq!(source.account(1234).unwrap_or_default().available, dec!(0));
+            assert_eq!(source.get(1337, 2), None);
+        };
+
+        // crediting the destination fails before the source is debited
+        let event = transfer(2, Some(1234), Some(dec!(4.0))).unwrap();
+        destination.fail.store(true, Ordering::SeqCst);
+        assert!(client.transfer(&mut other, &event).is_err());
+        unchanged(&client, &other);
+
+        // debiting the source fails once the destination is credited, which is undone
+        destination.fail.store(false, Ordering::SeqCst);
+        source.fail.store(true, Ordering::SeqCst);
+        assert!(client.transfer(&mut other, &event).is_err());
+        unchanged(&client, &other);
+
+        source.fail.store(false, Ordering::SeqCst);
+        client.transfer(&mut other, &event).unwrap();
+        assert_eq!(source.account(1234).unwrap().available, dec!(4.0));
+        assert_eq!(source.account(1337).unwrap().available, dec!(6.0));
+    }
+
+    #[test]
+    fn test_transfer_frozen() {
+        let store = MemoryStore::new();
+        let mut client = Client::new(1337, Arc::clone(&store));
+        let mut other = Client::new(1234, Arc::clone(&store));
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
+        other
+            .update(&event_with_client("deposit", 1234, 2, Some(dec!(1.0))))
+            .unwrap();
+        other
+            .update(&event_with_client("dispute", 1234, 2, None))
+            .unwrap();
+        other
+            .update(&event_with_client("chargeback", 1234, 2, None))
+            .unwrap();
+
+        // frozen accounts can not receive transfers
+        let event = transfer(3, Some(1234), Some(dec!(1.0))).unwrap();
+        assert!(client.transfer(&mut other, &event).is_err());
+        assert_eq!(client.available(), dec!(10.0));
+        assert_eq!(store.get(1337, 3), None);
+    }
+
+    #[test]
+    fn test_merge() {
+        let store = MemoryStore::new();
+        let mut client = Client::new(1337, Arc::clone(&store));
+        client
+            .update(&event("deposit", 1, Some(dec!(10.0))))
+            .unwrap();
+        client.update(&event("dispute", 1, None)).unwrap();
+        let mut partition = Client::new(1337, MemoryStore::new());
+        partition
+            .update(&event("deposit", 2, Some(dec!(1.0))))
+            .unwrap();
+        partition.update(&event("dispute", 2, None)).unwrap();
+        partition.update(&event("chargeback", 2, None)).unwrap();
+
+        client.merge(partition).unwrap();
+        assert_eq!(client.available(), dec!(0.0));
+        assert_eq!(client.held(), dec!(10.0));
+        // frozen in the other partition, so frozen once merged
+        assert!(client.locked());
+        assert_eq!(store.account(1337).unwrap().status, AccountStatus::Frozen);
+        // the merged account doesn't take back a less restrictive status
+        client.merge(Client::new(1337, MemoryStore::new())).unwrap();
+        assert!(client.locked());
+    }
+
+    #[test]
+    fn test_amount_in_cents() {
+        let cents = |t, tx, amount| {
+            event(t, tx, amount).map_amount(|amount| i64::try_from(amount * dec!(100)).unwrap())
+        };
+        let store = MemoryStore::<i64>::new();
+        let mut client = Client::new(1337, Arc::clone(&store)).with_policy(Policy {
+            insufficient_funds: DisputePolicy::HoldPartial,
+            ..Policy::default()
+        });
+        client
+            .update(&cents("deposit", 1, Some(dec!(10.25))))
+            .unwrap();
+        client
+            .update(&cents("withdrawal", 2, Some(dec!(4.00))))
+            .unwrap();
+        assert_eq!(client.available(), 625);
+
+        // only the available funds are held
+        let outcome = client.update(&cents("dispute", 1, None)).unwrap();
+        assert_eq!(
+            outcome.to,
+            Some(TxState::PartialDispute {
+                disputed: 625,
+                undisputed: 400
+            })
+        );
+        assert_eq!((client.available(), client.held()), (0, 625));
+        assert!(client
+            .update(&cents("withdrawal", 3, Some(dec!(0.01))))
+            .is_err());
+
+        client.update(&cents("chargeback", 1, None)).unwrap();
+        assert_eq!(client.summary().total, 0);
+        assert_eq!(
+            store.get(1337, 1),
+            Some(TxState::PartialChargedBack {
+                charged_back: 625,
+                undisputed: 400
+            })
+        );
+    }
+
+    #[test]
+    fn test_rebuild() {
+        let eur: Currency = "EUR".parse().unwrap();
+        let store = MemoryStore::new();
+        let mut client = Client::new(1337, store.clone()).with_policy(Policy {
+            dispute_withdrawals: true,
+            ..Policy::default()
+        });
+        let mut other = Client::new(1234, store.clone());
+        let events = [
+            event("deposit", 1, Some(dec!(20.0))),
+            event("deposit", 2, Some(dec!(5.0))),
+            event("deposit", 3, Some(dec!(4.0))),
+            event("withdrawal", 4, Some(dec!(1.5))),
+            event("withdrawal", 5, Some(dec!(1.0))),
+            event("authorize", 6, Some(dec!(2.0))),
+            event("authorize", 7, Some(dec!(1.0))),
+            event("capture", 7, Some(dec!(0.5))),
+            event("authorize", 8, Some(dec!(1.0))),
+            event("void", 8, None),
+            event("dispute", 2, None),
+            event("dispute", 3, Some(dec!(1.0))),
+            event("dispute", 4, None),
+            event("dispute", 5, None),
+            event("chargeback", 5, None),
+        ];
+        for event in &events {
+            client.update(event).unwrap();
+        }
+        let record = |r#type: &str, tx, to, currency| Record {
+            r#type: r#type.to_string(),
+            client: 1337,
+            tx,
+            amount: Some(dec!(2.0)),
+            to,
+            seq: None,
+            timestamp: None,
+            currency,
+        };
+        let in_eur = Event::try_from(record("deposit", 9, None, Some(eur))).unwrap();
+        client.update(&in_eur).unwrap();
+        let transfer = Event::try_from(record("transfer", 10, Some(1234), None)).unwrap();
+        client.transfer(&mut other, &transfer).unwrap();
+
+        let rebuilt = Client::rebuild(1337, &store);
+        assert_eq!(rebuilt.summaries(), client.summaries());
+        assert!(!rebuilt.locked());
+
+        // a chargeback of a deposit freezes the rebuilt account
+        client.update(&event("chargeback", 2, None)).unwrap();
+        let rebuilt = Client::rebuild(1337, &store);
+        assert_eq!(rebuilt.summaries(), client.summaries());
+        assert!(rebuilt.locked());
+
+        // funds received by transfer are not stored as the receiver's transactions
+        assert_eq!(Client::rebuild(1234, &store).total(), dec!(0.0));
+        assert_eq!(other.total(), dec!(2.0));
+    }
+
+    #[test]
+    fn test_rebuild_matches_live() {
+        // a linear congruential generator, so that the sequences are repeatable
+        let mut seed = 1337u64;
+        let mut next = move |n: u64| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) % n
+        };
+        let policies = [
+            Policy::default(),
+            Policy {
+                unlock_on_resolve: true,
+                ..Policy::default()
+            },
+            Policy {
+                insufficient_funds: DisputePolicy::HoldPartial,
+                dispute_withdrawals: true,
+                ..Policy::default()
+            },
+            Policy {
+                insufficient_funds: DisputePolicy::AllowNegativeAvailable,
+                unlock_on_resolve: true,
+                ..Policy::default()
+            },
+        ];
+        for policy in policies {
+            for _ in 0..50 {
+                let store = MemoryStore::new();
+                let mut client = Client::new(1337, Arc::clone(&store)).with_policy(policy);
+                let cents_store = MemoryStore::<i64>::new();
+                let mut cents = Client::new(1337, Arc::clone(&cents_store)).with_policy(policy);
+                for _ in 0..40 {
+                    let tx = next(8) + 1;
+                    let amount = Some(Decimal::from(next(10) + 1) / dec!(4));
+                    let event = match next(10) {
+                        0 | 1 => event("deposit", tx, amount),
+                        2 => event("withdrawal", tx, amount),
+                        3 => event("dispute", tx, None),
+                        4 => event("dispute", tx, amount),
+                        5 => event("resolve", tx, None),
+                        6 => event("chargeback", tx, None),
+                        7 => event("representment", tx, None),
+                        8 => event("authorize", tx, amount),
+                        _ => event(["capture", "void"][next(2) as usize], tx, None),
+                    };
+                    // rejected events change neither the store nor the account
+                    let _ = client.update(&event);
+                    let rebuilt = Client::rebuild(1337, &store);
+                    assert_eq!(rebuilt.summaries(), client.summaries(), "{:?}", event);
+
+                    let event =
+                        event.map_amount(|amount| i64::try_from(amount * dec!(100)).unwrap());
+                    let _ = cents.update(&event);
+                    let rebuilt = Client::rebuild(1337, &cents_store);
+                    assert_eq!(rebuilt.summaries(), cents.summaries(), "{:?}", event);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_verify_invariants() {
+        let mut store = MemoryStore::new();
+        let mut client = Client::new(1337, store.clone());
+        for event in [
+            event("deposit", 1, Some(dec!(5.0))),
+            event("deposit", 2, Some(dec!(3.0))),
+            event("dispute", 1, Some(dec!(2.0))),
+            event("authorize", 3, Some(dec!(1.0))),
+        ] {
+            client.update(&event).unwrap();
+            client.verify_invariants().unwrap();
+        }
+
+        // a store which loses a dispute leaves the held funds unaccounted for
+        store.upsert(1337, 1, TxState::Deposit(dec!(5.0))).unwrap();
+        assert!(client.verify_invariants().is_err());
+        store
+            .upsert(
+                1337,
+                1,
+                TxState::PartialDispute {
+                    disputed: dec!(2.0),
+                    undisputed: dec!(3.0),
+                },
+            )
+            .unwrap();
+        client.verify_invariants().unwrap();
+
+        // as does one which saves different balances
+        let mut account = client.account();
+        account.total += dec!(1.0);
+        store.save_account(1337, account).unwrap();
+        assert!(client.verify_invariants().is_err());
+
+        // available funds may only be negative when the policy allows it
+        let store = MemoryStore::new();
+        let policy = |insufficient_funds| Policy {
+            insufficient_funds,
+            ..Policy::default()
+        };
+        let mut client = Client::new(1337, store.clone())
+            .with_policy(policy(DisputePolicy::AllowNegativeAvailable));
+        client
+            .update(&event("deposit", 1, Some(dec!(5.0))))
+            .unwrap();
+        client
+            .update(&event("withdrawal", 2, Some(dec!(4.0))))
+            .unwrap();
+        client.update(&event("dispute", 1, None)).unwrap();
+        client.verify_invariants().unwrap();
+        let client = client.with_policy(policy(DisputePolicy::Reject));
+        assert!(client.verify_invariants().is_err());
+    }
 }
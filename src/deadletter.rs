@@ -0,0 +1,145 @@
+use std::fs::{File, OpenOptions};
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::sync::mpsc::Sender;
+
+use anyhow::{anyhow, Context, Error, Result};
+use serde::Serialize;
+
+use crate::events::Record;
+
+/// An entry which could not be processed, either because it was not a valid record or
+/// because its event was rejected, along with the reason why.
+///
+/// Dead letters serialize as the fields of their record followed by a `reason`, so that
+/// a file of them can be repaired and replayed as JSON Lines input.
+#[derive(Clone, Debug, Serialize)]
+pub struct DeadLetter {
+    /// The record as it was read, if it could be read at all.
+    #[serde(flatten)]
+    pub record: Option<Record>,
+    /// Why the entry could not be processed.
+    pub reason: String,
+}
+
+impl DeadLetter {
+    /// Returns a dead letter of `record`, or of an entry which could not be read as a
+    /// record if `None`, which could not be processed for `reason`.
+    pub fn new(record: Option<Record>, reason: &Error) -> DeadLetter {
+        // unreadable entries keep the context of where they were read from, as there is
+        // no record to find them by
+        let reason = match record {
+            Some(_) => reason.root_cause().to_string(),
+            None => format!("{:#}", reason),
+        };
+        DeadLetter { record, reason }
+    }
+}
+
+/// Receives the entries a long-lived service could not process, rather than dropping
+/// them, so that they can be repaired and replayed.
+pub trait DeadLetterSink: Send {
+    /// Sends `letter` to the sink.
+    fn send(&mut self, letter: DeadLetter) -> Result<()>;
+}
+
+/// Sends dead letters to a channel, for the application embedding the processor to
+/// handle.
+impl DeadLetterSink for Sender<DeadLetter> {
+    fn send(&mut self, letter: DeadLetter) -> Result<()> {
+        Sender::send(self, letter).map_err(|_| anyhow!("dead letter channel closed"))
+    }
+}
+
+/// A dead letter sink appending to a file as JSON Lines, with an object for each dead
+/// letter. Every dead letter is written to the file as soon as it is sent.
+///
+/// # Example
+/// ```
+/// use anyhow::anyhow;
+/// use payments::deadletter::{DeadLetter, DeadLetterSink, FileSink};
+///
+/// let path = std::env::temp_dir().join(format!("dead-letters-{}.jsonl", std::process::id()));
+/// let mut sink = FileSink::open(&path).unwrap();
+/// let reason = anyhow!("expected value at line 1 column 1");
+/// sink.send(DeadLetter::new(None, &reason)).unwrap();
+///
+/// let written = std::fs::read_to_string(&path).unwrap();
+/// assert_eq!(written, "{\"reason\":\"expected value at line 1 column 1\"}\n");
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct FileSink {
+    #[doc(hidden)]
+    writer: LineWriter<File>,
+}
+
+impl FileSink {
+    /// Opens the file at `path` to append to, creating it if it does not exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<FileSink> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("opening {}", path.display()))?;
+        Ok(FileSink {
+            writer: LineWriter::new(file),
+        })
+    }
+}
+
+impl DeadLetterSink for FileSink {
+    fn send(&mut self, letter: DeadLetter) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, &letter)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc;
+
+    use rust_decimal_macros::dec;
+
+    use crate::aliases::ClientAliases;
+    use crate::input;
+
+    #[test]
+    fn test_dead_letter_replay() {
+        let record = Record {
+            r#type: "withdrawal".to_string(),
+            client: 1,
+            tx: 2,
+            amount: Some(dec!(1.5)),
+            to: None,
+            seq: Some(7),
+            timestamp: None,
+            currency: Some("EUR".parse().unwrap()),
+        };
+        let reason = anyhow!("insufficient funds for withdrawal").context("processing event");
+        let (mut sender, receiver) = mpsc::channel();
+        sender.send(DeadLetter::new(Some(record), &reason)).unwrap();
+        let letter = receiver.recv().unwrap();
+        assert_eq!(letter.reason, "insufficient funds for withdrawal");
+
+        let line = serde_json::to_string(&letter).unwrap();
+        assert_eq!(
+            line,
+            "{\"type\":\"withdrawal\",\"client\":1,\"tx\":2,\"amount\":\"1.5\",\"to\":null,\
+             \"seq\":7,\"timestamp\":null,\"currency\":\"EUR\",\
+             \"reason\":\"insufficient funds for withdrawal\"}"
+        );
+        // dead letters are read back as the records they hold
+        let replayed = input::parse_json(&line, &ClientAliases::default()).unwrap();
+        assert_eq!(replayed.tx, 2);
+        assert_eq!(replayed.amount, Some(dec!(1.5)));
+        assert_eq!(replayed.seq, Some(7));
+
+        drop(receiver);
+        assert!(DeadLetterSink::send(&mut sender, letter).is_err());
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+
+use crate::events::Record;
+
+/// Returns a hash of the type, client, transaction and amount of a record.
+fn content_hash(record: &Record) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    record.r#type.hash(&mut hasher);
+    record.client.hash(&mut hasher);
+    record.tx.hash(&mut hasher);
+    // decimals hash equally when equal, so 1.5 and 1.50 are duplicates
+    record.amount.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Drops records which exactly duplicate one of the most recently read records, such
+/// as blocks of rows written twice by an upstream exporter.
+///
+/// Records are compared by a hash of their type, client, transaction and amount, so a
+/// repeated dispute of the same transaction within the window is also dropped.
+///
+/// # Example
+/// ```
+/// use payments::dedup::Deduplicator;
+/// use payments::events::Record;
+/// use rust_decimal_macros::dec;
+///
+/// let record = Record {
+///     r#type: "deposit".to_string(),
+///     client: 1,
+///     tx: 1,
+///     amount: Some(dec!(1.0)),
+///     to: None,
+///     seq: None,
+///     timestamp: None,
+///     currency: None,
+/// };
+/// let mut dedup = Deduplicator::new(100);
+/// assert!(!dedup.is_duplicate(&record));
+/// assert!(dedup.is_duplicate(&record));
+/// assert_eq!(dedup.dropped(), 1);
+/// ```
+#[derive(Debug)]
+pub struct Deduplicator {
+    #[doc(hidden)]
+    window: usize,
+    #[doc(hidden)]
+    recent: VecDeque<u64>,
+    #[doc(hidden)]
+    counts: HashMap<u64, usize>,
+    #[doc(hidden)]
+    dropped: u64,
+}
+
+impl Deduplicator {
+    /// Creates a deduplicator comparing each record against the `window` records read
+    /// before it.
+    pub fn new(window: usize) -> Deduplicator {
+        Deduplicator {
+            window,
+            recent: VecDeque::with_capacity(window),
+            counts: HashMap::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Reads `record`, returning whether it duplicates a record in the window and
+    /// should be dropped.
+    pub fn is_duplicate(&mut self, record: &Record) -> bool {
+        let hash = content_hash(record);
+        let duplicate = self.counts.contains_key(&hash);
+        if duplicate {
+            self.dropped += 1;
+        }
+
+        if self.window > 0 {
+            if self.recent.len() == self.window {
+                if let Some(oldest) = self.recent.pop_front() {
+                    if let Some(count) = self.counts.get_mut(&oldest) {
+                        *count -= 1;
+                        if *count == 0 {
+                            self.counts.remove(&oldest);
+                        }
+                    }
+                }
+            }
+            self.recent.push_back(hash);
+            *self.counts.entry(hash).or_default() += 1;
+        }
+        duplicate
+    }
+
+    /// Returns the number of records dropped as duplicates.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    fn record(t: &str, tx: u64, amount: Option<Decimal>, timestamp: Option<u64>) -> Record {
+        Record {
+            r#type: t.to_string(),
+            client: 1,
+            tx,
+            amount,
+            to: None,
+            seq: None,
+            timestamp,
+            currency: None,
+        }
+    }
+
+    #[test]
+    fn test_window() {
+        let mut dedup = Deduplicator::new(2);
+        assert!(!dedup.is_duplicate(&record("deposit", 1, Some(dec!(1.0)), Some(1))));
+        assert!(!dedup.is_duplicate(&record("deposit", 2, Some(dec!(1.0)), None)));
+        // only the type, client, transaction and amount are compared
+        assert!(dedup.is_duplicate(&record("deposit", 1, Some(dec!(1.0)), Some(2))));
+        assert!(!dedup.is_duplicate(&record("deposit", 1, Some(dec!(2.0)), None)));
+        assert!(!dedup.is_duplicate(&record("dispute", 1, None, None)));
+        // outside of the window
+        assert!(!dedup.is_duplicate(&record("deposit", 2, Some(dec!(1.0)), None)));
+        assert_eq!(dedup.dropped(), 1);
+    }
+
+    #[test]
+    fn test_double_written_block() {
+        let block: Vec<_> = (1..=5)
+            .map(|tx| record("deposit", tx, Some(dec!(1.0)), None))
+            .collect();
+        let mut dedup = Deduplicator::new(5);
+        let kept = block
+            .iter()
+            .chain(&block)
+            .filter(|record| !dedup.is_duplicate(record))
+            .count();
+        assert_eq!(kept, 5);
+        assert_eq!(dedup.dropped(), 5);
+    }
+}
@@ -0,0 +1,398 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::events::{ClientId, Event, EventType, Record, TxId};
+use crate::schedule::Period;
+use crate::storage::{TxState, TxStore};
+
+/// A disputed transaction whose funds are still held.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct OpenDispute {
+    /// The client associated with the transaction.
+    pub client: ClientId,
+    /// The disputed transaction.
+    pub tx: TxId,
+    /// The amount held.
+    pub amount: Decimal,
+    /// The number of events applied since the dispute was opened.
+    pub events_ago: u64,
+    /// The timestamp of the event which opened the dispute, if known.
+    pub opened_at: Option<u64>,
+}
+
+/// When a dispute was opened.
+#[derive(Clone, Copy, Debug)]
+struct Opened {
+    event: u64,
+    timestamp: Option<u64>,
+}
+
+/// Tracks when each dispute was opened, for reporting on the age of held funds.
+///
+/// # Example
+/// ```
+/// use payments::clients::Client;
+/// use payments::disputes::OpenDisputes;
+/// use payments::events::{Event, Record};
+/// use payments::storage::MemoryStore;
+/// use rust_decimal_macros::dec;
+///
+/// let store = MemoryStore::new();
+/// let mut client = Client::new(1, store.clone());
+/// let mut disputes = OpenDisputes::default();
+/// for t in ["deposit", "dispute"] {
+///     let event = Event::try_from(Record {
+///         r#type: t.to_string(),
+///         client: 1,
+///         tx: 1,
+///         amount: Some(dec!(5.0)),
+///         to: None,
+///         seq: None,
+///         timestamp: None,
+///         currency: None,
+///     })
+///     .unwrap();
+///     client.update(&event).unwrap();
+///     disputes.observe(&event);
+/// }
+///
+/// let open = disputes.report(&store);
+/// assert_eq!((open[0].tx, open[0].amount, open[0].events_ago), (1, dec!(5.0), 0));
+/// ```
+#[derive(Debug, Default)]
+pub struct OpenDisputes {
+    #[doc(hidden)]
+    events: u64,
+    #[doc(hidden)]
+    opened: BTreeMap<(ClientId, TxId), Opened>,
+}
+
+impl OpenDisputes {
+    /// Records `event` after it was applied, opening or closing a dispute.
+    pub fn observe(&mut self, event: &Event) {
+        let key = (event.client_id(), event.tx());
+        match event.kind() {
+            EventType::Dispute(_) => {
+                self.opened.insert(
+                    key,
+                    Opened {
+                        event: self.events,
+                        timestamp: event.timestamp(),
+                    },
+                );
+            }
+            EventType::Resolve | EventType::Chargeback | EventType::Representment => {
+                self.opened.remove(&key);
+            }
+            EventType::Deposit(_)
+            | EventType::Withdrawal(_)
+            | EventType::Transfer { .. }
+            | EventType::Authorize(_)
+            | EventType::Capture(_)
+            | EventType::Void
+            | EventType::Unlock
+            | EventType::Review
+            | EventType::Close => {}
+        }
+        self.events += 1;
+    }
+
+    /// Stops tracking the disputes `policy` considers expired by time `now`, returning
+    /// them ordered by client and transaction id.
+    fn expire(&mut self, policy: &impl ExpiryPolicy, now: Option<u64>) -> Vec<(ClientId, TxId)> {
+        let events = self.events;
+        let expired: Vec<_> = self
+            .opened
+            .iter()
+            .filter(|(_, opened)| {
+                policy.is_expired(events - opened.event - 1, opened.timestamp, now)
+            })
+            .map(|(&key, _)| key)
+            .collect();
+        for key in &expired {
+            self.opened.remove(key);
+        }
+        expired
+    }
+
+    /// Returns every transaction still disputed in `store`, ordered by client and
+    /// transaction id.
+    pub fn report(&self, store: &impl TxStore) -> Vec<OpenDispute> {
+        self.opened
+            .iter()
+            .filter_map(|(&(client, tx), opened)| match store.get(client, tx)? {
+                TxState::Dispute(amount)
+                | TxState::PartialDispute {
+                    disputed: amount, ..
+                }
+                | TxState::WithdrawalDispute(amount) => Some(OpenDispute {
+                    client,
+                    tx,
+                    amount,
+                    events_ago: self.events - opened.event - 1,
+                    opened_at: opened.timestamp,
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Decides when a dispute left unresolved expires.
+///
+/// Implemented for closures taking the same arguments as [`ExpiryPolicy::is_expired`],
+/// so that tests can decide exactly which disputes expire.
+pub trait ExpiryPolicy {
+    /// Returns whether a dispute opened `events_ago` events ago, at the time
+    /// `opened_at` if known, has expired by the time `now`, the newest time seen.
+    fn is_expired(&self, events_ago: u64, opened_at: Option<u64>, now: Option<u64>) -> bool;
+}
+
+impl<F: Fn(u64, Option<u64>, Option<u64>) -> bool> ExpiryPolicy for F {
+    fn is_expired(&self, events_ago: u64, opened_at: Option<u64>, now: Option<u64>) -> bool {
+        self(events_ago, opened_at, now)
+    }
+}
+
+/// How long a dispute may stay open before it expires.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExpiryWindow {
+    /// Disputes expire once this many more events have been applied.
+    Events(u64),
+    /// Disputes expire once an event this many seconds newer has been applied. Only
+    /// disputes with a timestamp expire.
+    Seconds(u64),
+}
+
+impl ExpiryPolicy for ExpiryWindow {
+    fn is_expired(&self, events_ago: u64, opened_at: Option<u64>, now: Option<u64>) -> bool {
+        match (*self, opened_at, now) {
+            (ExpiryWindow::Events(window), _, _) => events_ago >= window,
+            (ExpiryWindow::Seconds(window), Some(opened_at), Some(now)) => {
+                now.saturating_sub(opened_at) >= window
+            }
+            (ExpiryWindow::Seconds(_), _, _) => false,
+        }
+    }
+}
+
+impl FromStr for ExpiryWindow {
+    type Err = Error;
+
+    /// Parses a number of events, e.g. "1000", or a period, e.g. "30d".
+    fn from_str(s: &str) -> Result<ExpiryWindow> {
+        if !s.is_empty() && s.bytes().all(|c| c.is_ascii_digit()) {
+            return match s.parse()? {
+                0 => bail!("invalid expiry window {:?}, must be greater than zero", s),
+                events => Ok(ExpiryWindow::Events(events)),
+            };
+        }
+        Ok(ExpiryWindow::Seconds(s.parse::<Period>()?.seconds()))
+    }
+}
+
+/// Resolves disputes left open for longer than an [`ExpiryPolicy`] allows, so that
+/// their funds aren't held forever.
+///
+/// # Example
+/// ```
+/// use payments::clients::Client;
+/// use payments::disputes::{DisputeExpiry, ExpiryWindow};
+/// use payments::events::{Event, Record};
+/// use payments::storage::MemoryStore;
+/// use rust_decimal_macros::dec;
+///
+/// let mut client = Client::new(1, MemoryStore::new());
+/// let mut expiry = DisputeExpiry::new(ExpiryWindow::Events(1));
+/// for (t, tx) in [("deposit", 1), ("dispute", 1), ("deposit", 2)] {
+///     let event = Event::try_from(Record {
+///         r#type: t.to_string(),
+///         client: 1,
+///         tx,
+///         amount: (t == "deposit").then(|| dec!(5.0)),
+///         to: None,
+///         seq: None,
+///         timestamp: None,
+///         currency: None,
+///     })
+///     .unwrap();
+///     client.update(&event).unwrap();
+///     expiry.observe(&event);
+/// }
+///
+/// // the dispute was left open for an event, so it is resolved
+/// for resolve in expiry.expired() {
+///     client.update(&resolve).unwrap();
+/// }
+/// assert_eq!(client.held(), dec!(0.0));
+/// ```
+#[derive(Debug)]
+pub struct DisputeExpiry<P = ExpiryWindow> {
+    #[doc(hidden)]
+    policy: P,
+    #[doc(hidden)]
+    open: OpenDisputes,
+    #[doc(hidden)]
+    now: Option<u64>,
+}
+
+impl<P: ExpiryPolicy> DisputeExpiry<P> {
+    /// Creates an expiry of disputes according to `policy`.
+    pub fn new(policy: P) -> DisputeExpiry<P> {
+        DisputeExpiry {
+            policy,
+            open: OpenDisputes::default(),
+            now: None,
+        }
+    }
+
+    /// Records `event` after it was applied, opening or closing a dispute.
+    pub fn observe(&mut self, event: &Event) {
+        self.now = self.now.max(event.timestamp());
+        self.open.observe(event);
+    }
+
+    /// Returns resolutions of every dispute which has expired, ordered by client and
+    /// transaction id, no longer tracking them. Each is timestamped with the newest
+    /// time seen, if any.
+    pub fn expired(&mut self) -> Vec<Event> {
+        self.open
+            .expire(&self.policy, self.now)
+            .into_iter()
+            .map(|(client, tx)| {
+                Event::try_from(Record {
+                    r#type: "resolve".to_string(),
+                    client,
+                    tx,
+                    amount: None,
+                    to: None,
+                    seq: None,
+                    timestamp: self.now,
+                    currency: None,
+                })
+                .expect("resolutions are always valid")
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    use crate::clients::Client;
+    use crate::events::Record;
+    use crate::storage::MemoryStore;
+
+    fn event(t: &str, client: ClientId, tx: TxId, timestamp: Option<u64>) -> Event {
+        Event::try_from(Record {
+            r#type: t.to_string(),
+            client,
+            tx,
+            amount: Some(dec!(10.0)),
+            to: None,
+            seq: None,
+            timestamp,
+            currency: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_report() {
+        let store = MemoryStore::new();
+        let mut clients = [Client::new(1, store.clone()), Client::new(2, store.clone())];
+        let mut disputes = OpenDisputes::default();
+        for (t, client, tx, timestamp) in [
+            ("deposit", 1, 1, None),
+            ("deposit", 1, 2, None),
+            ("deposit", 2, 3, None),
+            ("dispute", 1, 1, Some(100)),
+            ("dispute", 2, 3, None),
+            ("dispute", 1, 2, Some(200)),
+            ("resolve", 1, 2, None),
+            ("deposit", 2, 4, None),
+        ] {
+            let event = event(t, client, tx, timestamp);
+            clients[client as usize - 1].update(&event).unwrap();
+            disputes.observe(&event);
+        }
+
+        assert_eq!(
+            disputes.report(&store),
+            vec![
+                OpenDispute {
+                    client: 1,
+                    tx: 1,
+                    amount: dec!(10.0),
+                    events_ago: 4,
+                    opened_at: Some(100),
+                },
+                OpenDispute {
+                    client: 2,
+                    tx: 3,
+                    amount: dec!(10.0),
+                    events_ago: 3,
+                    opened_at: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expiry() {
+        let store = MemoryStore::new();
+        let mut client = Client::new(1, store.clone());
+        let events_ago = std::cell::RefCell::new(Vec::new());
+        // expires only the dispute of transaction 1, once it has seen two events
+        let mut expiry = DisputeExpiry::new(|ago, opened_at, now| {
+            events_ago.borrow_mut().push((ago, opened_at, now));
+            opened_at == Some(100) && ago >= 2
+        });
+        let mut resolved = Vec::new();
+        for (t, tx, timestamp) in [
+            ("deposit", 1, Some(50)),
+            ("deposit", 2, None),
+            ("dispute", 1, Some(100)),
+            ("dispute", 2, None),
+            ("deposit", 3, Some(150)),
+            ("deposit", 4, None),
+        ] {
+            let event = event(t, 1, tx, timestamp);
+            client.update(&event).unwrap();
+            expiry.observe(&event);
+            for resolve in expiry.expired() {
+                client.update(&resolve).unwrap();
+                expiry.observe(&resolve);
+                resolved.push((resolve.tx(), resolve.timestamp()));
+            }
+        }
+        assert_eq!(resolved, vec![(1, Some(150))]);
+        assert_eq!(client.held(), dec!(10.0));
+        // the resolution counts as an event applied since the other dispute
+        assert_eq!(events_ago.borrow().last(), Some(&(3, None, Some(150))));
+
+        let window = ExpiryWindow::Seconds(60);
+        assert!(!window.is_expired(100, Some(100), Some(159)));
+        assert!(window.is_expired(0, Some(100), Some(160)));
+        assert!(!window.is_expired(100, None, Some(160)));
+        assert!(ExpiryWindow::Events(3).is_expired(3, None, None));
+
+        assert_eq!(
+            "1000".parse::<ExpiryWindow>().unwrap(),
+            ExpiryWindow::Events(1000)
+        );
+        assert_eq!(
+            "2h".parse::<ExpiryWindow>().unwrap(),
+            ExpiryWindow::Seconds(7200)
+        );
+        assert!("0".parse::<ExpiryWindow>().is_err());
+        assert!("soon".parse::<ExpiryWindow>().is_err());
+    }
+}
@@ -0,0 +1,78 @@
+use std::io::Write;
+
+use age::armor::{ArmoredWriter, Format};
+use age::x25519::Recipient;
+use anyhow::{anyhow, Result};
+
+/// Parses an age X25519 recipient, e.g. "age1ql3z7hjy54pw3hyww5ayyfg7zqgvc7w3j2elw8zmrj2kg5sfn9aqmcac8p".
+pub fn parse_recipient(s: &str) -> Result<Recipient> {
+    s.parse()
+        .map_err(|e| anyhow!("invalid age recipient {:?}: {}", s, e))
+}
+
+/// Encrypts `plaintext` to every one of `recipients` in the ASCII armored
+/// [age](https://age-encryption.org) format, so that any of their identities can
+/// decrypt it.
+///
+/// # Example
+/// ```
+/// use payments::encryption::encrypt;
+///
+/// let identity = age::x25519::Identity::generate();
+/// let report = encrypt(b"client,available,held,total,locked\n", &[identity.to_public()]).unwrap();
+///
+/// assert!(report.starts_with(b"-----BEGIN AGE ENCRYPTED FILE-----"));
+/// ```
+pub fn encrypt(plaintext: &[u8], recipients: &[Recipient]) -> Result<Vec<u8>> {
+    let encryptor =
+        age::Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn age::Recipient))
+            .map_err(|e| anyhow!("encrypting report: {}", e))?;
+    let mut ciphertext = Vec::new();
+    let armor = ArmoredWriter::wrap_output(&mut ciphertext, Format::AsciiArmor)?;
+    let mut writer = encryptor.wrap_output(armor)?;
+    writer.write_all(plaintext)?;
+    writer.finish()?.finish()?;
+    Ok(ciphertext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+    use std::iter;
+
+    use age::armor::ArmoredReader;
+    use age::x25519::Identity;
+
+    fn decrypt(ciphertext: &[u8], identity: &Identity) -> Result<Vec<u8>> {
+        let decryptor = age::Decryptor::new(ArmoredReader::new(ciphertext))?;
+        let mut reader = decryptor.decrypt(iter::once(identity as &dyn age::Identity))?;
+        let mut plaintext = Vec::new();
+        reader.read_to_end(&mut plaintext)?;
+        Ok(plaintext)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (ops, backup, other) = (
+            Identity::generate(),
+            Identity::generate(),
+            Identity::generate(),
+        );
+        let report = b"client,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n";
+        let ciphertext = encrypt(report, &[ops.to_public(), backup.to_public()]).unwrap();
+
+        assert_eq!(decrypt(&ciphertext, &ops).unwrap(), report);
+        assert_eq!(decrypt(&ciphertext, &backup).unwrap(), report);
+        assert!(decrypt(&ciphertext, &other).is_err());
+    }
+
+    #[test]
+    fn test_parse_recipient() {
+        let identity = Identity::generate();
+        let recipient = parse_recipient(&identity.to_public().to_string()).unwrap();
+        assert_eq!(recipient.to_string(), identity.to_public().to_string());
+        assert!(parse_recipient("age1invalid").is_err());
+    }
+}
@@ -1,10 +1,93 @@
 use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
 
-use anyhow::{anyhow, bail, Result};
-use serde::Deserialize;
+use anyhow::{anyhow, bail, Error, Result};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use tracing::{info_span, Span};
+
+/// The unique identifier of a client.
+pub type ClientId = u64;
+
+/// The unique identifier of a transaction.
+pub type TxId = u64;
+
+/// A three letter currency code, such as `USD`, always in upper case.
+///
+/// # Example
+/// ```
+/// use payments::events::Currency;
+///
+/// let currency: Currency = "eur".parse().unwrap();
+/// assert_eq!(currency.as_str(), "EUR");
+/// assert!("euro".parse::<Currency>().is_err());
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    /// Returns the currency code.
+    pub fn as_str(&self) -> &str {
+        // only ever built from ASCII letters
+        std::str::from_utf8(&self.0).unwrap()
+    }
+}
+
+impl FromStr for Currency {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Currency> {
+        match <[u8; 3]>::try_from(s.as_bytes()) {
+            Ok(code) if code.iter().all(u8::is_ascii_alphabetic) => {
+                Ok(Currency(code.map(|c| c.to_ascii_uppercase())))
+            }
+            _ => bail!("invalid currency {:?}, expected a three letter code", s),
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Currency, D::Error> {
+        struct CurrencyVisitor;
+
+        impl de::Visitor<'_> for CurrencyVisitor {
+            type Value = Currency;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a three letter currency code")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Currency, E> {
+                v.parse().map_err(de::Error::custom)
+            }
+        }
+
+        // parsed from the text as it is, without copying it
+        deserializer.deserialize_str(CurrencyVisitor)
+    }
+}
 
 /// A raw, unvalidated payment event type for requesting client updates.
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Record {
     /// The type of payment event.
     ///
@@ -14,69 +97,664 @@ pub struct Record {
     /// - "dispute"
     /// - "resolve"
     /// - "chargeback"
+    /// - "representment"
+    /// - "authorize"
+    /// - "capture"
+    /// - "void"
+    /// - "transfer"
+    /// - "unlock"
     pub r#type: String,
     /// The unique identifier of the client associated with the payment event.
-    pub client: u16,
+    pub client: ClientId,
     /// The ID of the transaction associated with the payment event.
-    pub tx: u32,
+    pub tx: TxId,
     /// An optional amount of funds associated with the payment event.
     ///
-    /// Only valid for [`EventType::Deposit`] and [`EventType::Withdrawal`].
-    pub amount: Option<f32>,
+    /// Only valid for [`EventType::Deposit`], [`EventType::Withdrawal`],
+    /// [`EventType::Transfer`] and [`EventType::Authorize`], and optional for
+    /// [`EventType::Dispute`] and [`EventType::Capture`].
+    #[serde(default, with = "amount_option")]
+    pub amount: Option<Decimal>,
+    /// The client funds are transferred to.
+    ///
+    /// Only valid for [`EventType::Transfer`].
+    pub to: Option<ClientId>,
+    /// An optional sequence number assigned by the source of the payment event.
+    ///
+    /// Sequence numbers are expected to increase by one for every event emitted by a
+    /// source, and are used to detect lost, replayed or reordered events.
+    pub seq: Option<u64>,
+    /// An optional time at which the payment event occurred, in seconds since the
+    /// Unix epoch.
+    pub timestamp: Option<u64>,
+    /// An optional currency of the payment event.
+    ///
+    /// Amounts without a currency are in the base currency. Disputes, resolutions and
+    /// chargebacks are in the currency of the transaction they reference, which they
+    /// need not repeat.
+    pub currency: Option<Currency>,
+}
+
+impl fmt::Display for Record {
+    /// Writes the record as a row of a CSV file, with its columns in the order of its
+    /// fields, leaving out trailing empty columns.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let optional = |field: Option<String>| field.unwrap_or_default();
+        let mut columns = vec![
+            self.r#type.clone(),
+            self.client.to_string(),
+            self.tx.to_string(),
+            optional(self.amount.map(|amount| amount.to_string())),
+            optional(self.to.map(|to| to.to_string())),
+            optional(self.seq.map(|seq| seq.to_string())),
+            optional(self.timestamp.map(|timestamp| timestamp.to_string())),
+            optional(self.currency.map(|currency| currency.to_string())),
+        ];
+        while columns.last().is_some_and(String::is_empty) {
+            columns.pop();
+        }
+        f.write_str(&columns.join(","))
+    }
+}
+
+/// A raw, unvalidated payment event borrowing its type from the row it was read from,
+/// such as a [`csv::ByteRecord`], so that records can be read and validated into
+/// events without allocating. Its fields are those of a [`Record`].
+///
+/// # Example
+/// ```
+/// use payments::events::{Event, RawRecord};
+/// use rust_decimal_macros::dec;
+///
+/// let headers = csv::ByteRecord::from(vec!["type", "client", "tx", "amount"]);
+/// let row = csv::ByteRecord::from(vec!["deposit", "1", "7", "1.5"]);
+/// let raw: RawRecord = row.deserialize(Some(&headers)).unwrap();
+/// assert_eq!(raw.r#type, "deposit");
+///
+/// let event = Event::try_from(raw).unwrap();
+/// assert_eq!(event.tx(), 7);
+/// ```
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct RawRecord<'a> {
+    /// The type of payment event, one of those of [`Record::type`](Record#structfield.type).
+    pub r#type: &'a str,
+    /// The unique identifier of the client associated with the payment event.
+    pub client: ClientId,
+    /// The ID of the transaction associated with the payment event.
+    pub tx: TxId,
+    /// An optional amount of funds associated with the payment event.
+    #[serde(default, with = "amount_option")]
+    pub amount: Option<Decimal>,
+    /// The client funds are transferred to.
+    pub to: Option<ClientId>,
+    /// An optional sequence number assigned by the source of the payment event.
+    pub seq: Option<u64>,
+    /// An optional time at which the payment event occurred, in seconds since the
+    /// Unix epoch.
+    pub timestamp: Option<u64>,
+    /// An optional currency of the payment event.
+    pub currency: Option<Currency>,
+}
+
+impl<'a> From<&'a Record> for RawRecord<'a> {
+    fn from(record: &'a Record) -> RawRecord<'a> {
+        RawRecord {
+            r#type: &record.r#type,
+            client: record.client,
+            tx: record.tx,
+            amount: record.amount,
+            to: record.to,
+            seq: record.seq,
+            timestamp: record.timestamp,
+            currency: record.currency,
+        }
+    }
+}
+
+impl From<RawRecord<'_>> for Record {
+    fn from(raw: RawRecord<'_>) -> Record {
+        Record {
+            r#type: raw.r#type.to_string(),
+            client: raw.client,
+            tx: raw.tx,
+            amount: raw.amount,
+            to: raw.to,
+            seq: raw.seq,
+            timestamp: raw.timestamp,
+            currency: raw.currency,
+        }
+    }
+}
+
+/// Where the record of a payment event was read from, so that errors can point at it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Position {
+    /// The file the record was read from, or "-" for stdin.
+    pub source: String,
+    /// The line the record was read from, counted from the top of the file including
+    /// a CSV header.
+    pub line: u64,
+    /// The record as read, written as a row of a CSV file.
+    pub record: String,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} line {} ({})", self.source, self.line, self.record)
+    }
 }
 
 /// Represents a valid payment event that can be used to attempt to update a client's
 /// account state.
 #[derive(Clone)]
-pub struct Event {
+pub struct Event<A = Decimal> {
+    #[doc(hidden)]
+    client: ClientId,
     #[doc(hidden)]
-    client: u16,
+    tx: TxId,
     #[doc(hidden)]
-    tx: u32,
+    kind: EventType<A>,
     #[doc(hidden)]
-    kind: EventType,
+    timestamp: Option<u64>,
+    #[doc(hidden)]
+    currency: Option<Currency>,
+    #[doc(hidden)]
+    position: Option<Arc<Position>>,
 }
 
 /// Represents supported payment event types and any metadata specific to them.
 #[derive(Clone, Debug)]
-pub enum EventType {
+pub enum EventType<A = Decimal> {
     /// An addition of some funds to a client's account.
-    Deposit(f32),
+    Deposit(A),
     /// A deduction of some funds from a client's account.
-    Withdrawal(f32),
-    /// A request to contest the validity of some funds in a client's account.
-    Dispute,
+    Withdrawal(A),
+    /// A request to contest the validity of some funds in a client's account, either
+    /// all of the funds of the transaction or only the amount given.
+    Dispute(Option<A>),
     /// A request to validate contested funds of a client's account.
     Resolve,
     /// A request to remove contested funds and freeze a client's account.
     Chargeback,
+    /// A hold placed on some of a client's available funds for a payment which is yet
+    /// to be captured or voided, leaving its total funds unchanged.
+    Authorize(A),
+    /// A request to settle an authorization as a withdrawal, either of all of the
+    /// funds it holds or only the amount given, releasing the rest.
+    Capture(Option<A>),
+    /// A request to release the funds held by an authorization without settling it.
+    Void,
+    /// A request to restore the funds of a charged back transaction, such as after the
+    /// merchant won the dispute, unfreezing the account unless other chargebacks are
+    /// outstanding.
+    Representment,
+    /// A movement of some funds from a client's account to another client's account.
+    Transfer {
+        /// The client receiving the funds.
+        to: ClientId,
+        /// The funds transferred.
+        amount: A,
+    },
+    /// An administrative request to unfreeze a client's account, such as after an
+    /// investigation of the chargeback which froze it.
+    Unlock,
+    /// An administrative request to place a client's account under review, such as by
+    /// compliance, so that no funds are withdrawn or transferred out of it until it is
+    /// unlocked.
+    Review,
+    /// An administrative request to close a client's account, decommissioning it so
+    /// that no more funds move in or out of it.
+    Close,
 }
 
-impl fmt::Debug for Event {
+impl<A: Copy> EventType<A> {
+    /// Returns the amount of funds the event moves or holds, if it has one.
+    pub fn amount(&self) -> Option<A> {
+        match *self {
+            EventType::Deposit(amount)
+            | EventType::Withdrawal(amount)
+            | EventType::Authorize(amount)
+            | EventType::Transfer { amount, .. } => Some(amount),
+            EventType::Dispute(amount) | EventType::Capture(amount) => amount,
+            EventType::Resolve
+            | EventType::Chargeback
+            | EventType::Representment
+            | EventType::Void
+            | EventType::Unlock
+            | EventType::Review
+            | EventType::Close => None,
+        }
+    }
+
+    /// Returns whether the event stores a new transaction under its id, rather than
+    /// referring to one stored before.
+    pub fn is_new_transaction(&self) -> bool {
+        matches!(
+            self,
+            EventType::Deposit(_)
+                | EventType::Withdrawal(_)
+                | EventType::Authorize(_)
+                | EventType::Transfer { .. }
+        )
+    }
+
+    /// Returns the name of the event type as it appears in payment records.
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventType::Deposit(_) => "deposit",
+            EventType::Withdrawal(_) => "withdrawal",
+            EventType::Dispute(_) => "dispute",
+            EventType::Resolve => "resolve",
+            EventType::Chargeback => "chargeback",
+            EventType::Representment => "representment",
+            EventType::Authorize(_) => "authorize",
+            EventType::Capture(_) => "capture",
+            EventType::Void => "void",
+            EventType::Transfer { .. } => "transfer",
+            EventType::Unlock => "unlock",
+            EventType::Review => "review",
+            EventType::Close => "close",
+        }
+    }
+}
+
+impl<A: fmt::Debug> fmt::Debug for Event<A> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // disputes of whole transactions are written as they were before disputes
+        // could be partial
+        match self.kind {
+            EventType::Dispute(None) => f.write_str("Dispute")?,
+            ref kind => write!(f, "{:?}", kind)?,
+        }
         write!(
             f,
-            "{:?} for client {} with transaction {}",
-            self.kind, self.client, self.tx
+            " for client {} with transaction {}",
+            self.client, self.tx
         )
     }
 }
 
 impl Event {
+    /// Attempts to create a valid payment event from an un-validated payment record,
+    /// applying `rounding` to amounts with more than [`MAX_DECIMAL_PLACES`] decimal
+    /// places. [`Event::try_from`] rejects them.
+    ///
+    /// # Example
+    /// ```
+    /// use payments::events::{Event, EventType, Record, RoundingPolicy};
+    /// use rust_decimal_macros::dec;
+    ///
+    /// let record = Record {
+    ///     r#type: "deposit".to_string(),
+    ///     client: 1,
+    ///     tx: 1,
+    ///     amount: Some(dec!(1.00005)),
+    ///     to: None,
+    ///     seq: None,
+    ///     timestamp: None,
+    ///     currency: None,
+    /// };
+    /// assert!(Event::try_from(record.clone()).is_err());
+    ///
+    /// let event = Event::from_record(record, RoundingPolicy::RoundHalfEven).unwrap();
+    /// assert!(matches!(event.kind(), EventType::Deposit(amount) if *amount == dec!(1.0000)));
+    /// ```
+    pub fn from_record(record: Record, rounding: RoundingPolicy) -> Result<Event> {
+        Event::from_raw(RawRecord::from(&record), rounding)
+    }
+
+    /// Attempts to create a valid payment event from a borrowed, un-validated payment
+    /// record, as [`Event::from_record`] does.
+    pub fn from_raw(record: RawRecord<'_>, rounding: RoundingPolicy) -> Result<Event> {
+        let amount = |kind| positive_amount(kind, record.amount, rounding);
+        Ok(Event {
+            client: record.client,
+            tx: record.tx,
+            timestamp: record.timestamp,
+            currency: record.currency,
+            position: None,
+            kind: match record.r#type {
+                "deposit" => EventType::Deposit(amount("deposit")?),
+                "withdrawal" => EventType::Withdrawal(amount("withdrawal")?),
+                "dispute" => EventType::Dispute(match record.amount {
+                    Some(_) => Some(amount("dispute")?),
+                    None => None,
+                }),
+                "resolve" => EventType::Resolve,
+                "chargeback" => EventType::Chargeback,
+                "representment" => EventType::Representment,
+                "authorize" => EventType::Authorize(amount("authorize")?),
+                "capture" => EventType::Capture(match record.amount {
+                    Some(_) => Some(amount("capture")?),
+                    None => None,
+                }),
+                "void" => EventType::Void,
+                "unlock" => EventType::Unlock,
+                "review" => EventType::Review,
+                "close" => EventType::Close,
+                "transfer" => {
+                    let to = record
+                        .to
+                        .ok_or_else(|| anyhow!("transfer requires a destination client"))?;
+                    if to == record.client {
+                        bail!("cannot transfer to the same client");
+                    }
+                    EventType::Transfer {
+                        to,
+                        amount: amount("transfer")?,
+                    }
+                }
+                v => bail!("invalid transaction type {:?}", v),
+            },
+        })
+    }
+}
+
+impl<A> Event<A> {
     /// Returns the unique identifier of the client associated with the payment event.
-    pub fn client_id(&self) -> u16 {
+    pub fn client_id(&self) -> ClientId {
         self.client
     }
 
     /// Returns the unique identifier of the transaction associated with the payment event.
-    pub fn tx(&self) -> u32 {
+    pub fn tx(&self) -> TxId {
         self.tx
     }
 
     /// Returns the type of the payment event and any associated metadata.
-    pub fn kind(&self) -> &EventType {
+    pub fn kind(&self) -> &EventType<A> {
         &self.kind
     }
+
+    /// Returns the time at which the payment event occurred, if known.
+    pub fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+
+    /// Returns the currency of the payment event, or `None` for the base currency.
+    pub fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    /// Returns where the record of the payment event was read from, if known.
+    pub fn position(&self) -> Option<&Position> {
+        self.position.as_deref()
+    }
+
+    /// Returns this payment event read from the record at `position`, so that errors
+    /// applying it can point at the record.
+    pub fn with_position(self, position: Position) -> Event<A> {
+        Event {
+            position: Some(Arc::new(position)),
+            ..self
+        }
+    }
+
+    /// Returns this payment event with its client replaced by `client`.
+    pub fn with_client(self, client: ClientId) -> Event<A> {
+        Event { client, ..self }
+    }
+
+    /// Returns this payment event with the client a transfer is sent to replaced by
+    /// `to`. Other payment events are returned unchanged.
+    pub fn with_destination(self, to: ClientId) -> Event<A> {
+        let kind = match self.kind {
+            EventType::Transfer { amount, .. } => EventType::Transfer { to, amount },
+            kind => kind,
+        };
+        Event { kind, ..self }
+    }
+
+    /// Returns this payment event with its amount, if it has one, converted by `f`,
+    /// such as into the [`Amount`](crate::amount::Amount) an integration keeps its
+    /// funds in.
+    pub fn map_amount<B>(self, f: impl FnOnce(A) -> B) -> Event<B> {
+        let kind = match self.kind {
+            EventType::Deposit(amount) => EventType::Deposit(f(amount)),
+            EventType::Withdrawal(amount) => EventType::Withdrawal(f(amount)),
+            EventType::Dispute(amount) => EventType::Dispute(amount.map(f)),
+            EventType::Resolve => EventType::Resolve,
+            EventType::Chargeback => EventType::Chargeback,
+            EventType::Authorize(amount) => EventType::Authorize(f(amount)),
+            EventType::Capture(amount) => EventType::Capture(amount.map(f)),
+            EventType::Void => EventType::Void,
+            EventType::Representment => EventType::Representment,
+            EventType::Transfer { to, amount } => EventType::Transfer {
+                to,
+                amount: f(amount),
+            },
+            EventType::Unlock => EventType::Unlock,
+            EventType::Review => EventType::Review,
+            EventType::Close => EventType::Close,
+        };
+        Event {
+            client: self.client,
+            tx: self.tx,
+            kind,
+            timestamp: self.timestamp,
+            currency: self.currency,
+            position: self.position,
+        }
+    }
+}
+
+impl<A: Copy> Event<A> {
+    /// Returns a span for processing the payment event, recording its client, transaction
+    /// and type, and the file and line its record was read from if known, on every
+    /// message logged within it.
+    pub fn span(&self) -> Span {
+        let position = self.position();
+        info_span!(
+            "event",
+            client = self.client,
+            tx = self.tx,
+            "type" = self.kind.name(),
+            source = position.map(|p| p.source.as_str()),
+            line = position.map(|p| p.line),
+        )
+    }
+}
+
+/// Why the amount of a record is not valid for its event.
+///
+/// Returned within the error of [`Event::try_from`], and of deserializing a [`Record`]
+/// whose amount is not a number, so callers can tell invalid amounts from other
+/// invalid records with [`anyhow::Error::downcast_ref`].
+///
+/// # Example
+/// ```
+/// use payments::events::{AmountError, Event, Record};
+/// use rust_decimal_macros::dec;
+///
+/// let record = Record {
+///     r#type: "deposit".to_string(),
+///     client: 1,
+///     tx: 1,
+///     amount: Some(dec!(-100.0)),
+///     to: None,
+///     seq: None,
+///     timestamp: None,
+///     currency: None,
+/// };
+/// let error = Event::try_from(record).unwrap_err();
+/// assert_eq!(
+///     error.downcast_ref::<AmountError>(),
+///     Some(&AmountError::NotPositive {
+///         kind: "deposit",
+///         amount: dec!(-100.0),
+///     })
+/// );
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AmountError {
+    /// The event requires an amount, but the record has none.
+    Missing {
+        /// The type of the event.
+        kind: &'static str,
+    },
+    /// The amount is zero or negative.
+    NotPositive {
+        /// The type of the event.
+        kind: &'static str,
+        /// The amount of the record.
+        amount: Decimal,
+    },
+    /// The amount is `NaN` or an infinity, which can't be held as a [`Decimal`].
+    NotFinite(String),
+    /// The amount has more than [`MAX_DECIMAL_PLACES`] decimal places.
+    TooPrecise(Decimal),
+}
+
+impl fmt::Display for AmountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AmountError::Missing { kind } => write!(f, "{} requires an amount", kind),
+            AmountError::NotPositive { kind, amount } => {
+                write!(f, "{} amounts must be positive, got {}", kind, amount)
+            }
+            AmountError::NotFinite(amount) => {
+                write!(f, "amounts must be finite numbers, got {:?}", amount)
+            }
+            AmountError::TooPrecise(amount) => write!(
+                f,
+                "amounts may have at most {} decimal places, got {}",
+                MAX_DECIMAL_PLACES, amount
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AmountError {}
+
+/// The most decimal places an amount may have.
+pub const MAX_DECIMAL_PLACES: u32 = 4;
+
+/// How amounts with more than [`MAX_DECIMAL_PLACES`] decimal places are handled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RoundingPolicy {
+    /// The event is rejected with [`AmountError::TooPrecise`].
+    #[default]
+    Reject,
+    /// The amount is rounded half to even, so that rounding many amounts doesn't
+    /// drift their sum up or down.
+    RoundHalfEven,
+}
+
+impl RoundingPolicy {
+    /// Returns `amount` with at most [`MAX_DECIMAL_PLACES`] decimal places, or an error
+    /// if it has more and they are rejected.
+    pub fn apply(self, amount: Decimal) -> Result<Decimal, AmountError> {
+        // trailing zeros don't count, so 1.50000 is as valid as 1.5
+        if amount.normalize().scale() <= MAX_DECIMAL_PLACES {
+            return Ok(amount);
+        }
+        match self {
+            RoundingPolicy::Reject => Err(AmountError::TooPrecise(amount)),
+            RoundingPolicy::RoundHalfEven => Ok(round(amount)),
+        }
+    }
+}
+
+impl FromStr for RoundingPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<RoundingPolicy> {
+        match s {
+            "reject" => Ok(RoundingPolicy::Reject),
+            "round-half-even" => Ok(RoundingPolicy::RoundHalfEven),
+            v => bail!(
+                "invalid rounding policy {:?}, expected reject or round-half-even",
+                v
+            ),
+        }
+    }
+}
+
+/// Rounds `amount` half to even to [`MAX_DECIMAL_PLACES`] decimal places.
+fn round(amount: Decimal) -> Decimal {
+    amount.round_dp_with_strategy(MAX_DECIMAL_PLACES, RoundingStrategy::MidpointNearestEven)
+}
+
+/// Returns an amount or balance as reported, rounded as [`RoundingPolicy::RoundHalfEven`]
+/// rounds amounts and padded to [`MAX_DECIMAL_PLACES`] decimal places.
+///
+/// # Example
+/// ```
+/// use payments::events::format_amount;
+/// use rust_decimal_macros::dec;
+///
+/// assert_eq!(format_amount(dec!(1.5)), "1.5000");
+/// assert_eq!(format_amount(dec!(0.00005)), "0.0000");
+/// assert_eq!(format_amount(dec!(0.00015)), "0.0002");
+/// ```
+pub fn format_amount(amount: Decimal) -> String {
+    format!("{:.*}", MAX_DECIMAL_PLACES as usize, round(amount))
+}
+
+/// Returns the amount of a `kind` event, which must be present and positive once
+/// `rounding` is applied.
+fn positive_amount(
+    kind: &'static str,
+    amount: Option<Decimal>,
+    rounding: RoundingPolicy,
+) -> Result<Decimal, AmountError> {
+    match amount.map(|amount| rounding.apply(amount)).transpose()? {
+        None => Err(AmountError::Missing { kind }),
+        Some(amount) if amount <= Decimal::ZERO => Err(AmountError::NotPositive { kind, amount }),
+        Some(amount) => Ok(amount),
+    }
+}
+
+/// Serializes amounts as [`rust_decimal::serde::str_option`] does, but deserializes
+/// `NaN` and infinities as [`AmountError::NotFinite`] rather than a generic parse error.
+mod amount_option {
+    use super::*;
+
+    pub use rust_decimal::serde::str_option::serialize;
+
+    struct AmountVisitor;
+
+    impl<'de> de::Visitor<'de> for AmountVisitor {
+        type Value = Option<Decimal>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a decimal amount")
+        }
+
+        fn visit_none<E: de::Error>(self) -> Result<Option<Decimal>, E> {
+            Ok(None)
+        }
+
+        // a null buffered by an internally tagged enum is read back as a unit
+        fn visit_unit<E: de::Error>(self) -> Result<Option<Decimal>, E> {
+            Ok(None)
+        }
+
+        fn visit_some<D: Deserializer<'de>>(self, d: D) -> Result<Option<Decimal>, D::Error> {
+            d.deserialize_str(self)
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Option<Decimal>, E> {
+            if v.is_empty() {
+                return Ok(None);
+            }
+            let magnitude = v.trim().trim_start_matches(['+', '-']);
+            if ["nan", "inf", "infinity"]
+                .iter()
+                .any(|name| magnitude.eq_ignore_ascii_case(name))
+            {
+                return Err(de::Error::custom(AmountError::NotFinite(v.to_string())));
+            }
+            Decimal::from_str(v)
+                .or_else(|_| Decimal::from_scientific(v))
+                .map(Some)
+                .map_err(de::Error::custom)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Decimal>, D::Error> {
+        d.deserialize_option(AmountVisitor)
+    }
 }
 
 impl TryFrom<Record> for Event {
@@ -87,61 +765,209 @@ impl TryFrom<Record> for Event {
     /// # Example
     /// ```
     /// use payments::events::{Event, Record};
+    /// use rust_decimal_macros::dec;
     ///
     /// let valid_record = Record {
-    ///     r#type: "deposit",
+    ///     r#type: "deposit".to_string(),
     ///     client: 1337,
     ///     tx: 1,
-    ///     amount: Some(1.0),
+    ///     amount: Some(dec!(1.0)),
+    ///     to: None,
+    ///     seq: None,
+    ///     timestamp: None,
+    ///     currency: None,
     /// };
     ///
     /// // prints "Ok('Deposit(1.0) for client 1337 with transaction 1')"
     /// println!("{:?}", Event::try_from(valid_record));
     ///
     /// let invalid_record = Record {
-    ///     r#type: "invalid_event",
+    ///     r#type: "invalid_event".to_string(),
     ///     client: 1337,
     ///     tx: 1,
     ///     amount: None,
+    ///     to: None,
+    ///     seq: None,
+    ///     timestamp: None,
+    ///     currency: None,
     /// };
     ///
     /// // prints "Err('invalid transaction type invalid_event')"
     /// println!("{:?}", Event::try_from(invalid_record));
     /// ```
     fn try_from(record: Record) -> Result<Event> {
-        Ok(Event {
-            client: record.client,
-            tx: record.tx,
-            kind: match record.r#type.as_str() {
-                "deposit" => EventType::Deposit(
-                    record
-                        .amount
-                        .ok_or_else(|| anyhow!("deposit requires an amount"))
-                        .and_then(|amount| {
-                            if amount > 0.0 {
-                                Ok(amount)
-                            } else {
-                                bail!("deposit amounts must be positive")
-                            }
-                        })?,
-                ),
-                "withdrawal" => EventType::Withdrawal(
-                    record
-                        .amount
-                        .ok_or_else(|| anyhow!("withdrawal requires an  amount"))
-                        .and_then(|amount| {
-                            if amount > 0.0 {
-                                Ok(amount)
-                            } else {
-                                bail!("withdrawal amounts must be positive")
-                            }
-                        })?,
-                ),
-                "dispute" => EventType::Dispute,
-                "resolve" => EventType::Resolve,
-                "chargeback" => EventType::Chargeback,
-                v => bail!("invalid transaction type {:?}", v),
-            },
-        })
+        Event::from_record(record, RoundingPolicy::Reject)
+    }
+}
+
+impl From<&Event> for Record {
+    /// Returns the record `event` was parsed from, such as to write it out again.
+    fn from(event: &Event) -> Record {
+        let (amount, to) = match event.kind() {
+            EventType::Transfer { to, amount } => (Some(*amount), Some(*to)),
+            kind => (kind.amount(), None),
+        };
+        Record {
+            r#type: event.kind().name().to_string(),
+            client: event.client_id(),
+            tx: event.tx(),
+            amount,
+            to,
+            seq: None,
+            timestamp: event.timestamp(),
+            currency: event.currency(),
+        }
+    }
+}
+
+impl TryFrom<RawRecord<'_>> for Event {
+    type Error = anyhow::Error;
+
+    /// Attempt to create a valid payment event from a borrowed, un-validated payment
+    /// record, as [`Event::try_from`] a [`Record`] does.
+    fn try_from(record: RawRecord<'_>) -> Result<Event> {
+        Event::from_raw(record, RoundingPolicy::Reject)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    fn record(kind: &str, amount: Option<Decimal>) -> Record {
+        Record {
+            r#type: kind.to_string(),
+            client: 1,
+            tx: 1,
+            amount,
+            to: Some(2),
+            seq: None,
+            timestamp: None,
+            currency: None,
+        }
+    }
+
+    #[test]
+    fn test_amount_validation() {
+        for kind in ["deposit", "withdrawal", "transfer"] {
+            for amount in [dec!(-100.0), Decimal::ZERO] {
+                let error = Event::try_from(record(kind, Some(amount))).unwrap_err();
+                assert_eq!(
+                    error.downcast_ref::<AmountError>(),
+                    Some(&AmountError::NotPositive { kind, amount })
+                );
+            }
+            let error = Event::try_from(record(kind, None)).unwrap_err();
+            assert_eq!(error.to_string(), format!("{} requires an amount", kind));
+            assert!(Event::try_from(record(kind, Some(dec!(0.0001)))).is_ok());
+        }
+        // disputes may leave out their amount, but those they give are checked
+        assert!(Event::try_from(record("dispute", None)).is_ok());
+        assert!(Event::try_from(record("dispute", Some(dec!(-1)))).is_err());
+        assert!(Event::try_from(record("chargeback", Some(dec!(-1)))).is_ok());
+
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,NaN\n\
+                     deposit,1,2,-inf\n\
+                     deposit,1,3,Infinity\n\
+                     deposit,1,4,1.5\n\
+                     deposit,1,5,1e2\n\
+                     dispute,1,1,\n";
+        let results: Vec<_> = csv::Reader::from_reader(input.as_bytes())
+            .deserialize::<Record>()
+            .collect();
+        for (result, amount) in results[..3].iter().zip(["NaN", "-inf", "Infinity"]) {
+            let error = result.as_ref().unwrap_err().to_string();
+            assert!(
+                error.contains(&AmountError::NotFinite(amount.to_string()).to_string()),
+                "{}",
+                error
+            );
+        }
+        let amounts: Vec<_> = results[3..]
+            .iter()
+            .map(|result| result.as_ref().unwrap().amount)
+            .collect();
+        assert_eq!(amounts, vec![Some(dec!(1.5)), Some(dec!(100)), None]);
+
+        let record: Record =
+            serde_json::from_str(r#"{"type":"deposit","client":1,"tx":1,"amount":"2.5"}"#).unwrap();
+        assert_eq!(record.amount, Some(dec!(2.5)));
+        assert!(serde_json::from_str::<Record>(
+            r#"{"type":"deposit","client":1,"tx":1,"amount":"nan"}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_record_display() {
+        assert_eq!(
+            record("transfer", Some(dec!(1.50))).to_string(),
+            "transfer,1,1,1.50,2"
+        );
+        let record = Record {
+            to: None,
+            ..record("dispute", None)
+        };
+        assert_eq!(record.to_string(), "dispute,1,1");
+        let record = Record {
+            timestamp: Some(100),
+            currency: Some("EUR".parse().unwrap()),
+            ..record
+        };
+        assert_eq!(record.to_string(), "dispute,1,1,,,,100,EUR");
+    }
+
+    #[test]
+    fn test_rounding_policy() {
+        for amount in [dec!(1.5), dec!(1.2345), dec!(1.50000)] {
+            assert_eq!(RoundingPolicy::Reject.apply(amount), Ok(amount));
+        }
+        assert_eq!(
+            RoundingPolicy::Reject.apply(dec!(1.23456)),
+            Err(AmountError::TooPrecise(dec!(1.23456)))
+        );
+        // ties round to the even digit, in either direction
+        assert_eq!(
+            RoundingPolicy::RoundHalfEven.apply(dec!(1.00005)),
+            Ok(dec!(1.0000))
+        );
+        assert_eq!(
+            RoundingPolicy::RoundHalfEven.apply(dec!(1.00015)),
+            Ok(dec!(1.0002))
+        );
+
+        let error = Event::try_from(record("withdrawal", Some(dec!(0.00001)))).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "amounts may have at most 4 decimal places, got 0.00001"
+        );
+        // amounts rounding down to nothing are as invalid as any other empty amount
+        let error = Event::from_record(
+            record("deposit", Some(dec!(0.00001))),
+            RoundingPolicy::RoundHalfEven,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<AmountError>(),
+            Some(&AmountError::NotPositive {
+                kind: "deposit",
+                amount: dec!(0.0000)
+            })
+        );
+        let event = Event::from_record(
+            record("transfer", Some(dec!(2.71828))),
+            RoundingPolicy::RoundHalfEven,
+        )
+        .unwrap();
+        assert!(
+            matches!(event.kind(), EventType::Transfer { to: 2, amount } if *amount == dec!(2.7183))
+        );
+
+        assert_eq!(format_amount(dec!(-0.00025)), "-0.0002");
+        assert_eq!(format_amount(dec!(10)), "10.0000");
+        assert!("round-half-up".parse::<RoundingPolicy>().is_err());
     }
 }
@@ -0,0 +1,108 @@
+use std::io::{self, Read};
+use std::thread;
+use std::time::Duration;
+
+/// Reads a file which is still being appended to, like `tail -f`: at the end of the
+/// file it waits for more to be written rather than ending, checking again every
+/// interval. Records split across writes are read once they are complete, as the
+/// reader of records waits along with it.
+///
+/// Files which are truncated or replaced, such as by log rotation, are not followed
+/// onto their new contents.
+///
+/// # Example
+/// ```
+/// use std::fs::{File, OpenOptions};
+/// use std::io::{BufRead, BufReader, Write};
+/// use std::time::Duration;
+///
+/// use payments::follow::FollowReader;
+///
+/// let path = std::env::temp_dir().join(format!("follow-doc-{}.csv", std::process::id()));
+/// std::fs::write(&path, "type,client,tx,amount\n").unwrap();
+/// let mut lines = BufReader::new(FollowReader::new(File::open(&path).unwrap(), Duration::from_millis(10))).lines();
+/// assert_eq!(lines.next().unwrap().unwrap(), "type,client,tx,amount");
+///
+/// // the reader waits for the next line to be appended
+/// let mut writer = OpenOptions::new().append(true).open(&path).unwrap();
+/// writer.write_all(b"deposit,1,1,1.0\n").unwrap();
+/// assert_eq!(lines.next().unwrap().unwrap(), "deposit,1,1,1.0");
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+pub struct FollowReader<R> {
+    #[doc(hidden)]
+    inner: R,
+    #[doc(hidden)]
+    interval: Duration,
+    #[doc(hidden)]
+    stop: Box<dyn Fn() -> bool + Send>,
+}
+
+impl<R: Read> FollowReader<R> {
+    /// Follows `inner`, checking for more to read every `interval` at its end.
+    pub fn new(inner: R, interval: Duration) -> FollowReader<R> {
+        FollowReader {
+            inner,
+            interval,
+            stop: Box::new(|| false),
+        }
+    }
+
+    /// Stops following once `stop` returns true, ending at the end of what has been
+    /// written so far, such as when the run is interrupted.
+    pub fn until(self, stop: impl Fn() -> bool + Send + 'static) -> FollowReader<R> {
+        FollowReader {
+            stop: Box::new(stop),
+            ..self
+        }
+    }
+}
+
+impl<R: Read> Read for FollowReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.inner.read(buf)?;
+            if n > 0 || buf.is_empty() || (self.stop)() {
+                return Ok(n);
+            }
+            thread::sleep(self.interval);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs::{self, File, OpenOptions};
+    use std::io::Write;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_follow_until() {
+        let path = env::temp_dir().join(format!("follow-{}.csv", std::process::id()));
+        fs::write(&path, "type,client,tx,amount\ndeposit,1,1,").unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        let mut reader = FollowReader::new(File::open(&path).unwrap(), Duration::from_millis(5))
+            .until(move || stopped.load(Ordering::SeqCst));
+        let following = thread::spawn(move || {
+            let mut read = String::new();
+            reader.read_to_string(&mut read).map(|_| read)
+        });
+
+        // the reader waits out the end of the file until it is stopped
+        thread::sleep(Duration::from_millis(50));
+        let mut writer = OpenOptions::new().append(true).open(&path).unwrap();
+        writer.write_all(b"1.0\n").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert!(!following.is_finished());
+        stop.store(true, Ordering::SeqCst);
+        let read = following.join().unwrap().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(read, "type,client,tx,amount\ndeposit,1,1,1.0\n");
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+
+use crate::clients::{AccountStatus, Summary};
+use crate::events::{ClientId, Currency};
+
+#[derive(Debug, Deserialize)]
+struct Link {
+    client: ClientId,
+    parent: ClientId,
+}
+
+/// Relates sub-accounts to the parent clients they roll up into, such as the accounts
+/// of a corporation's departments.
+///
+/// # Example
+/// ```
+/// use payments::clients::Summary;
+/// use payments::hierarchy::AccountHierarchy;
+/// use rust_decimal_macros::dec;
+///
+/// let hierarchy = AccountHierarchy::new([(2, 1), (3, 1)]).unwrap();
+/// let balances = hierarchy.roll_up([
+///     Summary { id: 2, available: dec!(5.0), total: dec!(5.0), ..Default::default() },
+///     Summary { id: 3, available: dec!(2.0), total: dec!(2.0), ..Default::default() },
+/// ]);
+///
+/// // the parent reports the combined balances of its sub-accounts
+/// assert_eq!(balances[0].id, 1);
+/// assert_eq!(balances[0].available, dec!(7.0));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct AccountHierarchy {
+    #[doc(hidden)]
+    parents: HashMap<ClientId, ClientId>,
+}
+
+impl AccountHierarchy {
+    /// Creates a hierarchy from `(client, parent)` pairs, failing if a client has more
+    /// than one parent or is its own ancestor.
+    pub fn new(links: impl IntoIterator<Item = (ClientId, ClientId)>) -> Result<AccountHierarchy> {
+        let mut parents = HashMap::new();
+        for (client, parent) in links {
+            if parents.insert(client, parent).is_some_and(|p| p != parent) {
+                bail!("client {} has more than one parent", client);
+            }
+        }
+        let hierarchy = AccountHierarchy { parents };
+        for &client in hierarchy.parents.keys() {
+            if hierarchy
+                .ancestors(client)
+                .any(|ancestor| ancestor == client)
+            {
+                bail!("client {} is its own ancestor", client);
+            }
+        }
+        Ok(hierarchy)
+    }
+
+    /// Loads a hierarchy from a CSV file with `client` and `parent` columns.
+    pub fn load(path: impl AsRef<Path>) -> Result<AccountHierarchy> {
+        let links = csv::Reader::from_path(path)?
+            .into_deserialize()
+            .map(|link| link.map(|link: Link| (link.client, link.parent)))
+            .collect::<Result<Vec<_>, _>>()?;
+        AccountHierarchy::new(links)
+    }
+
+    /// Returns the parent of a client, its parent's parent and so on, stopping early if
+    /// a cycle is found.
+    pub fn ancestors(&self, client: ClientId) -> impl Iterator<Item = ClientId> + '_ {
+        let mut seen = HashSet::new();
+        std::iter::successors(self.parents.get(&client).copied(), |id| {
+            self.parents.get(id).copied()
+        })
+        .take_while(move |id| seen.insert(*id))
+    }
+
+    /// Combines the balances of every client with those of its sub-accounts in the same
+    /// currency, ordered by client id and then currency.
+    ///
+    /// Parents are included even if they have no activity of their own, and a client is
+    /// reported as frozen, and so locked, if it or any of its ancestors is frozen, unless
+    /// it is closed. Only a client's own status is reported otherwise, as closing a
+    /// parent or placing it under review leaves its sub-accounts as they are.
+    pub fn roll_up(&self, summaries: impl IntoIterator<Item = Summary>) -> Vec<Summary> {
+        let mut balances: BTreeMap<(ClientId, Option<Currency>), Summary> = BTreeMap::new();
+        let mut frozen = HashSet::new();
+        for summary in summaries {
+            if summary.status == AccountStatus::Frozen {
+                frozen.insert(summary.id);
+            }
+            for id in std::iter::once(summary.id).chain(self.ancestors(summary.id)) {
+                let balance = balances.entry((id, summary.currency)).or_insert(Summary {
+                    id,
+                    currency: summary.currency,
+                    ..Default::default()
+                });
+                balance.available += summary.available;
+                balance.held += summary.held;
+                balance.total += summary.total;
+                if id == summary.id {
+                    balance.status = summary.status;
+                }
+            }
+        }
+        for balance in balances.values_mut() {
+            if balance.status != AccountStatus::Closed
+                && self.ancestors(balance.id).any(|id| frozen.contains(&id))
+            {
+                balance.status = AccountStatus::Frozen;
+            }
+            balance.locked = balance.status == AccountStatus::Frozen;
+        }
+        balances.into_values().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    fn summary(id: ClientId, available: Decimal, locked: bool) -> Summary {
+        Summary {
+            id,
+            available,
+            total: available,
+            locked,
+            status: AccountStatus::from_flags(locked, false),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_invalid_hierarchies() {
+        assert!(AccountHierarchy::new([(2, 1), (2, 3)]).is_err());
+        assert!(AccountHierarchy::new([(2, 1), (1, 3), (3, 2)]).is_err());
+        assert!(AccountHierarchy::new([(2, 1), (2, 1)]).is_ok());
+    }
+
+    #[test]
+    fn test_ancestors() {
+        let hierarchy = AccountHierarchy::new([(3, 2), (2, 1)]).unwrap();
+        assert_eq!(hierarchy.ancestors(3).collect::<Vec<_>>(), vec![2, 1]);
+        assert_eq!(hierarchy.ancestors(1).count(), 0);
+    }
+
+    #[test]
+    fn test_roll_up() {
+        let hierarchy = AccountHierarchy::new([(3, 2), (2, 1), (4, 1)]).unwrap();
+        let balances = hierarchy.roll_up([
+            summary(1, dec!(1.0), false),
+            summary(2, dec!(2.0), true),
+            summary(3, dec!(4.0), false),
+            summary(4, dec!(8.0), false),
+            summary(5, dec!(16.0), false),
+        ]);
+        assert_eq!(
+            balances,
+            vec![
+                summary(1, dec!(15.0), false),
+                summary(2, dec!(6.0), true),
+                summary(3, dec!(4.0), true),
+                summary(4, dec!(8.0), false),
+                summary(5, dec!(16.0), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_roll_up_status() {
+        let hierarchy = AccountHierarchy::new([(2, 1), (3, 1), (4, 1)]).unwrap();
+        let with_status = |id, status| Summary {
+            status,
+            ..summary(id, dec!(1.0), false)
+        };
+        let balances = hierarchy.roll_up([
+            summary(1, dec!(1.0), true),
+            summary(2, dec!(1.0), false),
+            with_status(3, AccountStatus::UnderReview),
+            with_status(4, AccountStatus::Closed),
+        ]);
+        let statuses = balances
+            .iter()
+            .map(|balance| (balance.id, balance.locked, balance.status))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            [
+                (1, true, AccountStatus::Frozen),
+                (2, true, AccountStatus::Frozen),
+                (3, true, AccountStatus::Frozen),
+                (4, false, AccountStatus::Closed),
+            ]
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+
+use crate::clients::Summary;
+use crate::events::ClientId;
+
+/// The width of the time buckets used to record balance history.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bucket {
+    /// One bucket per hour.
+    Hourly,
+    /// One bucket per day, aligned to midnight UTC.
+    Daily,
+}
+
+impl Bucket {
+    /// Returns the width of the bucket in seconds.
+    pub fn seconds(&self) -> u64 {
+        match self {
+            Bucket::Hourly => 60 * 60,
+            Bucket::Daily => 24 * 60 * 60,
+        }
+    }
+
+    /// Returns the start of the bucket containing `timestamp`.
+    pub fn start(&self, timestamp: u64) -> u64 {
+        timestamp - timestamp % self.seconds()
+    }
+}
+
+impl FromStr for Bucket {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Bucket> {
+        match s {
+            "hourly" => Ok(Bucket::Hourly),
+            "daily" => Ok(Bucket::Daily),
+            v => bail!("invalid history bucket {:?}, expected hourly or daily", v),
+        }
+    }
+}
+
+/// Records the balances of every client at the end of each time bucket.
+///
+/// Events without a timestamp are attributed to the most recent timestamp seen.
+///
+/// # Example
+/// ```
+/// use payments::clients::Summary;
+/// use payments::history::{BalanceHistory, Bucket};
+/// use rust_decimal_macros::dec;
+///
+/// let mut history = BalanceHistory::new(Bucket::Daily);
+/// let mut summary = Summary { id: 1, available: dec!(1.0), total: dec!(1.0), ..Default::default() };
+/// history.record(Some(3_600), summary);
+///
+/// summary.available = dec!(3.0);
+/// summary.total = dec!(3.0);
+/// history.record(Some(2 * 86_400), summary);
+///
+/// // one row per day, with the quiet day carried forward
+/// assert_eq!(history.series().len(), 3);
+/// ```
+#[derive(Debug)]
+pub struct BalanceHistory {
+    #[doc(hidden)]
+    bucket: Bucket,
+    #[doc(hidden)]
+    clock: u64,
+    #[doc(hidden)]
+    clients: BTreeMap<ClientId, BTreeMap<u64, Summary>>,
+}
+
+impl BalanceHistory {
+    /// Creates an empty history using buckets of the given width.
+    pub fn new(bucket: Bucket) -> BalanceHistory {
+        BalanceHistory {
+            bucket,
+            clock: 0,
+            clients: BTreeMap::new(),
+        }
+    }
+
+    /// Records the balances of a client after an event which occurred at `timestamp`.
+    pub fn record(&mut self, timestamp: Option<u64>, summary: Summary) {
+        if let Some(ts) = timestamp {
+            self.clock = self.clock.max(ts);
+        }
+        let bucket = self.bucket.start(timestamp.unwrap_or(self.clock));
+        self.clients
+            .entry(summary.id)
+            .or_default()
+            .insert(bucket, summary);
+    }
+
+    /// Returns the end-of-bucket balances of every client, ordered by client and then
+    /// by bucket start time.
+    ///
+    /// Each client has a row for every bucket from its first recorded activity until
+    /// the last bucket of the whole history, with quiet buckets carrying forward the
+    /// previous balances.
+    pub fn series(&self) -> Vec<(u64, Summary)> {
+        let last = self.bucket.start(self.clock);
+        let mut series = Vec::new();
+        for buckets in self.clients.values() {
+            let mut recorded = buckets.iter().peekable();
+            let (mut bucket, mut summary) = match recorded.next() {
+                Some((bucket, summary)) => (*bucket, *summary),
+                None => continue,
+            };
+            loop {
+                if let Some((_, next)) = recorded.next_if(|(start, _)| **start == bucket) {
+                    summary = *next;
+                }
+                series.push((bucket, summary));
+                if bucket >= last && recorded.peek().is_none() {
+                    break;
+                }
+                bucket += self.bucket.seconds();
+            }
+        }
+        series
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    fn summary(id: ClientId, available: Decimal) -> Summary {
+        Summary {
+            id,
+            available,
+            total: available,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_end_of_bucket_balance() {
+        let mut history = BalanceHistory::new(Bucket::Hourly);
+        history.record(Some(10), summary(1, dec!(1.0)));
+        history.record(Some(20), summary(1, dec!(2.0)));
+        history.record(Some(3_700), summary(1, dec!(5.0)));
+
+        assert_eq!(
+            history.series(),
+            vec![(0, summary(1, dec!(2.0))), (3_600, summary(1, dec!(5.0)))]
+        );
+    }
+
+    #[test]
+    fn test_quiet_buckets_carried_forward() {
+        let mut history = BalanceHistory::new(Bucket::Hourly);
+        history.record(Some(10), summary(1, dec!(1.0)));
+        history.record(Some(7_300), summary(2, dec!(4.0)));
+
+        assert_eq!(
+            history.series(),
+            vec![
+                (0, summary(1, dec!(1.0))),
+                (3_600, summary(1, dec!(1.0))),
+                (7_200, summary(1, dec!(1.0))),
+                (7_200, summary(2, dec!(4.0))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_untimestamped_uses_latest_time() {
+        let mut history = BalanceHistory::new(Bucket::Daily);
+        history.record(Some(90_000), summary(1, dec!(1.0)));
+        history.record(None, summary(1, dec!(3.0)));
+
+        assert_eq!(history.series(), vec![(86_400, summary(1, dec!(3.0)))]);
+    }
+}
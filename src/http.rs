@@ -0,0 +1,117 @@
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use ureq::{Agent, AgentBuilder};
+
+pub use url::Url;
+
+/// The time allowed for a request to a remote server to complete.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Parses a `http://` or `https://` URL, rejecting any other scheme.
+pub fn parse(url: &str) -> Result<Url> {
+    let parsed = Url::parse(url).map_err(|e| anyhow!("invalid URL {:?}: {}", url, e))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        bail!("unsupported URL {:?}, expected http:// or https://", url);
+    }
+    if parsed.host_str().is_none_or(str::is_empty) {
+        bail!("missing host in URL {:?}", url);
+    }
+    Ok(parsed)
+}
+
+/// Returns a copy of `url` with `path` appended to its path.
+pub fn join(url: &Url, path: &str) -> Url {
+    let mut joined = url.clone();
+    joined.set_path(&format!(
+        "{}/{}",
+        url.path().trim_end_matches('/'),
+        path.trim_start_matches('/')
+    ));
+    joined
+}
+
+/// The agent shared by every request, so connections to a server are reused.
+fn agent() -> &'static Agent {
+    static AGENT: OnceLock<Agent> = OnceLock::new();
+    AGENT.get_or_init(|| AgentBuilder::new().timeout(TIMEOUT).build())
+}
+
+/// Sends `body` to `url` with a `POST` request, failing unless the server responds
+/// with a 2xx status.
+pub fn post(url: &Url, content_type: &str, body: &[u8]) -> Result<()> {
+    match agent()
+        .request_url("POST", url)
+        .set("Content-Type", content_type)
+        .send_bytes(body)
+    {
+        Ok(_) => Ok(()),
+        Err(ureq::Error::Status(status, _)) => {
+            bail!("{} responded with status {}", url, status)
+        }
+        Err(e) => Err(anyhow!(e).context(format!("sending request to {}", url))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_parse_url() {
+        let url = parse("http://collector:4318/otlp").unwrap();
+        assert_eq!(url.host_str(), Some("collector"));
+        assert_eq!(url.port_or_known_default(), Some(4318));
+        assert_eq!(url.path(), "/otlp");
+        assert_eq!(parse("http://localhost").unwrap().path(), "/");
+        assert_eq!(
+            parse("https://localhost").unwrap().port_or_known_default(),
+            Some(443)
+        );
+        assert!(parse("ftp://localhost").is_err());
+        assert!(parse("http://:80/").is_err());
+    }
+
+    #[test]
+    fn test_join() {
+        let url = parse("http://localhost:4318/").unwrap();
+        assert_eq!(join(&url, "/v1/traces").path(), "/v1/traces");
+        assert_eq!(
+            join(&url, "/v1/traces").to_string(),
+            "http://localhost:4318/v1/traces"
+        );
+        let url = parse("http://collector:4318/otlp").unwrap();
+        assert_eq!(join(&url, "v1/metrics").path(), "/otlp/v1/metrics");
+    }
+
+    #[test]
+    fn test_post() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"hello") {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let url = parse(&format!("http://127.0.0.1:{}/hook", port)).unwrap();
+        post(&url, "text/plain", b"hello").unwrap();
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /hook HTTP/1.1\r\n"));
+        assert!(request.to_lowercase().contains("content-length: 5\r\n"));
+    }
+}
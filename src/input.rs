@@ -0,0 +1,681 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::iter;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Error, Result};
+use flate2::read::MultiGzDecoder;
+use serde_json::Value;
+
+use crate::aliases::ClientAliases;
+use crate::events::{Event, RawRecord, Record, RoundingPolicy};
+
+/// The format of a file of payment records.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputFormat {
+    /// A CSV file with a header row naming the record fields.
+    Csv,
+    /// JSON Lines, with one record object per line.
+    Json,
+}
+
+impl InputFormat {
+    /// Guesses the format of the file at `path` from its extension, defaulting to CSV.
+    /// The extension of a compressed file is looked for before its compression's, such
+    /// as "jsonl" in "events.jsonl.gz".
+    pub fn detect(path: impl AsRef<Path>) -> InputFormat {
+        let mut path = path.as_ref();
+        if Compression::detect(path) != Compression::None {
+            path = Path::new(path.file_stem().unwrap_or_default());
+        }
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json" | "jsonl" | "ndjson") => InputFormat::Json,
+            _ => InputFormat::Csv,
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<InputFormat> {
+        match s {
+            "csv" => Ok(InputFormat::Csv),
+            "json" => Ok(InputFormat::Json),
+            v => bail!("invalid input format {:?}, expected csv or json", v),
+        }
+    }
+}
+
+/// Returns the files matching `pattern`, in which `*` in a file name matches any
+/// characters and `?` any one character, ordered by name so that files named by date,
+/// such as daily batches, are in chronological order. Hidden files are only matched by
+/// a pattern starting with a dot. A `pattern` without wildcards is returned as it is,
+/// whether or not the file exists.
+///
+/// # Example
+/// ```
+/// use payments::input::expand_glob;
+///
+/// assert_eq!(expand_glob("events.csv").unwrap(), ["events.csv"]);
+/// assert!(expand_glob("missing/*.csv").is_err());
+/// ```
+pub fn expand_glob(pattern: &str) -> Result<Vec<String>> {
+    if !pattern.contains(['*', '?']) {
+        return Ok(vec![pattern.to_string()]);
+    }
+    let (dir, name) = match pattern.rsplit_once('/') {
+        Some((dir, name)) => (Some(dir), name),
+        None => (None, pattern),
+    };
+    if dir.is_some_and(|dir| dir.contains(['*', '?'])) {
+        bail!(
+            "{:?} has wildcards in a directory, which only match file names",
+            pattern
+        );
+    }
+    let entries = fs::read_dir(match dir {
+        Some("") => "/",
+        Some(dir) => dir,
+        None => ".",
+    })
+    .with_context(|| format!("listing files matching {:?}", pattern))?;
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let file = entry.file_name();
+        let file = file.to_string_lossy();
+        if file.starts_with('.') && !name.starts_with('.') {
+            continue;
+        }
+        if entry.file_type()?.is_file() && wildcard_match(name.as_bytes(), file.as_bytes()) {
+            files.push(match dir {
+                Some(dir) => format!("{}/{}", dir, file),
+                None => file.into_owned(),
+            });
+        }
+    }
+    if files.is_empty() {
+        return Err(anyhow!("no files match {:?}", pattern));
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Returns whether `name` matches `pattern`, in which `*` matches any bytes and `?`
+/// any one byte.
+fn wildcard_match(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // the position of the last `*` and of the name it was matched up to, to backtrack
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // let the last `*` match one more byte
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// The compression of a file of payment records.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    /// An uncompressed file.
+    None,
+    /// A gzip compressed file, which may have several members, as written by `gzip`.
+    Gzip,
+    /// A Zstandard compressed file, which may have several frames, as written by
+    /// `zstd`.
+    Zstd,
+}
+
+impl Compression {
+    /// Guesses the compression of the file at `path` from its extension, defaulting to
+    /// none.
+    pub fn detect(path: impl AsRef<Path>) -> Compression {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Compression> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            v => bail!("invalid compression {:?}, expected none, gzip or zstd", v),
+        }
+    }
+}
+
+/// Returns a reader of the contents of `reader`, decompressed from the given
+/// `compression`, to read payment records from.
+///
+/// # Example
+/// ```
+/// use std::io::{Read, Write};
+///
+/// use flate2::write::GzEncoder;
+/// use payments::input::{decompress, Compression};
+///
+/// let mut gz = GzEncoder::new(Vec::new(), flate2::Compression::default());
+/// gz.write_all(b"type,client,tx,amount\n").unwrap();
+/// let gz = gz.finish().unwrap();
+///
+/// let mut csv = String::new();
+/// decompress(std::io::Cursor::new(gz), Compression::Gzip)
+///     .unwrap()
+///     .read_to_string(&mut csv)
+///     .unwrap();
+/// assert_eq!(csv, "type,client,tx,amount\n");
+/// ```
+pub fn decompress<R: Read + Send + 'static>(
+    reader: R,
+    compression: Compression,
+) -> Result<Box<dyn Read + Send>> {
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+        Compression::Zstd => {
+            Box::new(zstd::stream::read::Decoder::new(reader).context("decompressing zstd input")?)
+        }
+    })
+}
+
+/// The columns of a CSV file of payment records without a header row, in order.
+/// Trailing columns may be left out.
+pub const CSV_COLUMNS: [&str; 8] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "to",
+    "seq",
+    "timestamp",
+    "currency",
+];
+
+/// How the fields of a CSV file of payment records are laid out, for files which are
+/// not comma-separated or have no header row.
+///
+/// # Example
+/// ```
+/// use payments::aliases::ClientAliases;
+/// use payments::input::{read_records, CsvDialect, InputFormat};
+///
+/// let dialect = CsvDialect {
+///     delimiter: b';',
+///     has_headers: false,
+///     trim: true,
+/// };
+/// let csv = "deposit; 1; 1; 1.5\n";
+/// let records: Vec<_> = read_records(csv.as_bytes(), InputFormat::Csv, dialect, &ClientAliases::default())
+///     .unwrap()
+///     .collect();
+///
+/// assert_eq!(records[0].as_ref().unwrap().amount, Some("1.5".parse().unwrap()));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CsvDialect {
+    /// The byte separating fields, a comma by default.
+    pub delimiter: u8,
+    /// Whether the first row names the columns, otherwise they are read in the order
+    /// of [`CSV_COLUMNS`]. True by default.
+    pub has_headers: bool,
+    /// Whether whitespace around headers and fields is ignored. False by default.
+    pub trim: bool,
+}
+
+impl Default for CsvDialect {
+    fn default() -> CsvDialect {
+        CsvDialect {
+            delimiter: b',',
+            has_headers: true,
+            trim: false,
+        }
+    }
+}
+
+/// Returns a CSV reader of `reader` laid out in `dialect`.
+fn csv_reader<R: Read>(reader: R, dialect: CsvDialect) -> csv::Reader<R> {
+    csv::ReaderBuilder::new()
+        .delimiter(dialect.delimiter)
+        .has_headers(dialect.has_headers)
+        // rows without a header may leave out different trailing columns
+        .flexible(!dialect.has_headers)
+        .trim(match dialect.trim {
+            true => csv::Trim::All,
+            false => csv::Trim::None,
+        })
+        .from_reader(reader)
+}
+
+/// Reads the payment records of a CSV file laid out in `dialect`, with client ids
+/// resolved through `aliases`.
+fn read_csv<'a, R: Read + Send + 'a>(
+    reader: R,
+    dialect: CsvDialect,
+    aliases: &'a ClientAliases,
+) -> Result<Box<dyn Iterator<Item = Result<Record>> + Send + 'a>> {
+    let mut reader = csv_reader(reader, dialect);
+    let headers = match dialect.has_headers {
+        true => reader.headers()?.clone(),
+        false => csv::StringRecord::from(&CSV_COLUMNS[..]),
+    };
+    let client = headers.iter().position(|header| header == "client");
+    Ok(Box::new(reader.into_records().map(move |row| {
+        let mut row = row.map_err(Error::msg)?;
+        if let (false, Some(i)) = (aliases.is_empty(), client) {
+            let id = aliases.resolve(&row[i])?.to_string();
+            row = row
+                .iter()
+                .enumerate()
+                .map(|(j, field)| if j == i { id.as_str() } else { field })
+                .collect();
+        }
+        row.deserialize(Some(&headers)).map_err(Error::msg)
+    })))
+}
+
+/// Reads the payment events of a CSV file laid out in `dialect`, with client ids
+/// resolved through `aliases` and amounts rounded by `rounding`. Invalid rows are
+/// returned as errors, as [`Event::from_record`] would reject them.
+///
+/// Rather than reading every row into a [`Record`] of its own, each is read into the
+/// same buffer and validated from a [`RawRecord`] borrowing its fields, so that no
+/// strings are allocated for rows, unless their client ids are resolved through
+/// `aliases`. Events are not given the [`Position`](crate::events::Position) of their
+/// row.
+///
+/// # Example
+/// ```
+/// use payments::aliases::ClientAliases;
+/// use payments::events::{EventType, RoundingPolicy};
+/// use payments::input::{read_csv_events, CsvDialect};
+/// use rust_decimal_macros::dec;
+///
+/// let csv = "type,client,tx,amount\ndeposit,1,1,1.5\nwithdrawal,1,2,-1.0\n";
+/// let aliases = ClientAliases::default();
+/// let events: Vec<_> =
+///     read_csv_events(csv.as_bytes(), CsvDialect::default(), &aliases, RoundingPolicy::Reject)
+///         .unwrap()
+///         .collect();
+///
+/// assert!(matches!(events[0].as_ref().unwrap().kind(), EventType::Deposit(a) if *a == dec!(1.5)));
+/// assert!(events[1].is_err());
+/// ```
+pub fn read_csv_events<'a, R: Read + Send + 'a>(
+    reader: R,
+    dialect: CsvDialect,
+    aliases: &'a ClientAliases,
+    rounding: RoundingPolicy,
+) -> Result<impl Iterator<Item = Result<Event>> + Send + 'a> {
+    let mut reader = csv_reader(reader, dialect);
+    let headers = match dialect.has_headers {
+        true => reader.byte_headers()?.clone(),
+        false => csv::ByteRecord::from(&CSV_COLUMNS[..]),
+    };
+    let client = headers.iter().position(|header| header == b"client");
+    let mut row = csv::ByteRecord::new();
+    Ok(iter::from_fn(move || {
+        match reader.read_byte_record(&mut row) {
+            Ok(false) => return None,
+            Ok(true) => {}
+            Err(e) => return Some(Err(Error::msg(e))),
+        }
+        Some(match (aliases.is_empty(), client) {
+            (false, Some(i)) => {
+                resolve_client(&row, i, aliases).and_then(|row| parse_row(&row, &headers, rounding))
+            }
+            _ => parse_row(&row, &headers, rounding),
+        })
+    }))
+}
+
+/// Returns `row` with the client id in its column `i` resolved through `aliases`.
+fn resolve_client(
+    row: &csv::ByteRecord,
+    i: usize,
+    aliases: &ClientAliases,
+) -> Result<csv::ByteRecord> {
+    let id = aliases.resolve(std::str::from_utf8(&row[i])?)?.to_string();
+    Ok(row
+        .iter()
+        .enumerate()
+        .map(|(j, field)| if j == i { id.as_bytes() } else { field })
+        .collect())
+}
+
+/// Validates the event of a CSV `row` with the given `headers`, borrowing its fields.
+fn parse_row(
+    row: &csv::ByteRecord,
+    headers: &csv::ByteRecord,
+    rounding: RoundingPolicy,
+) -> Result<Event> {
+    let raw: RawRecord = row.deserialize(Some(headers)).map_err(Error::msg)?;
+    Event::from_raw(raw, rounding)
+}
+
+/// Parses one line of JSON Lines as a record, with its client id resolved through
+/// `aliases`.
+pub(crate) fn parse_json(line: &str, aliases: &ClientAliases) -> Result<Record> {
+    let mut value: Value = serde_json::from_str(line)?;
+    if let Some(object) = value.as_object_mut() {
+        if let Some(Value::String(client)) = object.get("client") {
+            let id = aliases.resolve(client)?;
+            object.insert("client".to_string(), id.into());
+        }
+        // amounts are read from their text, as they are from CSV files
+        if let Some(Value::Number(amount)) = object.get("amount") {
+            let amount = amount.to_string();
+            object.insert("amount".to_string(), amount.into());
+        }
+    }
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Reads the payment records of a JSON Lines file, with client ids resolved through
+/// `aliases`. Blank lines are skipped.
+fn read_json<'a, R: Read + Send + 'a>(
+    reader: R,
+    aliases: &'a ClientAliases,
+) -> Box<dyn Iterator<Item = Result<Record>> + Send + 'a> {
+    Box::new(
+        BufReader::new(reader)
+            .lines()
+            .filter(|line| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+            .map(move |line| parse_json(&line?, aliases)),
+    )
+}
+
+/// Reads the payment records from `reader` in the given `format`, with client ids
+/// resolved through `aliases`. CSV files are read in the given `dialect`. Every format
+/// is read into the same [`Record`]s, to be validated into events in the same way.
+///
+/// # Example
+/// ```
+/// use payments::aliases::ClientAliases;
+/// use payments::input::{read_records, CsvDialect, InputFormat};
+///
+/// let aliases = ClientAliases::default();
+/// let jsonl = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 1.5}"#;
+/// let records: Vec<_> = read_records(jsonl.as_bytes(), InputFormat::Json, CsvDialect::default(), &aliases)
+///     .unwrap()
+///     .collect();
+///
+/// assert_eq!(records[0].as_ref().unwrap().amount, Some("1.5".parse().unwrap()));
+/// ```
+pub fn read_records<'a, R: Read + Send + 'a>(
+    reader: R,
+    format: InputFormat,
+    dialect: CsvDialect,
+    aliases: &'a ClientAliases,
+) -> Result<Box<dyn Iterator<Item = Result<Record>> + Send + 'a>> {
+    match format {
+        InputFormat::Csv => read_csv(reader, dialect, aliases),
+        InputFormat::Json => Ok(read_json(reader, aliases)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    use crate::events::EventType;
+
+    #[test]
+    fn test_formats_agree() {
+        let aliases = ClientAliases::new([("acme-1".to_string(), 1)]).unwrap();
+        let csv = "type,client,tx,amount\ndeposit,acme-1,1,0.1\ndispute,2,1,\n";
+        let jsonl = concat!(
+            r#"{"type": "deposit", "client": "acme-1", "tx": 1, "amount": 0.1}"#,
+            "\n\n",
+            r#"{"type": "dispute", "client": 2, "tx": 1}"#,
+            "\n",
+        );
+        for (input, format) in [(csv, InputFormat::Csv), (jsonl, InputFormat::Json)] {
+            let records: Vec<Record> =
+                read_records(input.as_bytes(), format, CsvDialect::default(), &aliases)
+                    .unwrap()
+                    .collect::<Result<_>>()
+                    .unwrap();
+            assert_eq!(records.len(), 2);
+            assert_eq!(records[0].client, 1);
+            assert_eq!(records[0].amount, Some(dec!(0.1)));
+            assert_eq!(records[1].client, 2);
+            assert_eq!(records[1].amount, None);
+        }
+    }
+
+    #[test]
+    fn test_csv_dialect() {
+        let aliases = ClientAliases::new([("acme-1".to_string(), 1)]).unwrap();
+        let read = |csv: &str, dialect| -> Vec<Record> {
+            read_records(csv.as_bytes(), InputFormat::Csv, dialect, &aliases)
+                .unwrap()
+                .collect::<Result<_>>()
+                .unwrap()
+        };
+        let semicolons = CsvDialect {
+            delimiter: b';',
+            trim: true,
+            ..Default::default()
+        };
+        let records = read(
+            " type ; client ; tx ; amount \n deposit ; acme-1 ; 1 ; 0.1 \n",
+            semicolons,
+        );
+        assert_eq!(records[0].client, 1);
+        assert_eq!(records[0].amount, Some(dec!(0.1)));
+
+        // without a header row, trailing columns may be left out
+        let headerless = CsvDialect {
+            has_headers: false,
+            ..Default::default()
+        };
+        let records = read("transfer,2,3,1.5,4\ndispute,2,3\n", headerless);
+        assert_eq!((records[0].client, records[0].tx), (2, 3));
+        assert_eq!(records[0].to, Some(4));
+        assert_eq!(records[0].currency, None);
+        assert_eq!(records[1].amount, None);
+
+        // padded fields are only read when trimmed
+        let padded = "type,client,tx,amount\ndeposit, 1,1,1.0\n";
+        assert!(read_records(
+            padded.as_bytes(),
+            InputFormat::Csv,
+            CsvDialect::default(),
+            &aliases
+        )
+        .unwrap()
+        .all(|record| record.is_err()));
+    }
+
+    #[test]
+    fn test_csv_events() {
+        let aliases = ClientAliases::new([("acme-1".to_string(), 1)]).unwrap();
+        let csv = "type,client,tx,amount,to,timestamp,currency\n\
+                   deposit,acme-1,1,1.00005,,1700000000,eur\n\
+                   transfer,acme-1,2,0.5,2,,\n\
+                   dispute,acme-1,1,,,,\n\
+                   refund,acme-1,3,1.0,,,\n\
+                   deposit,acme-2,4,1.0,,,\n";
+        let events: Vec<_> = read_csv_events(
+            csv.as_bytes(),
+            CsvDialect::default(),
+            &aliases,
+            RoundingPolicy::RoundHalfEven,
+        )
+        .unwrap()
+        .collect();
+        assert_eq!(events.len(), 5);
+        let deposit = events[0].as_ref().unwrap();
+        assert!(matches!(deposit.kind(), EventType::Deposit(a) if *a == dec!(1.0000)));
+        assert_eq!(deposit.timestamp(), Some(1700000000));
+        assert_eq!(deposit.currency(), Some("EUR".parse().unwrap()));
+        assert!(matches!(
+            events[1].as_ref().unwrap().kind(),
+            EventType::Transfer { to: 2, .. }
+        ));
+        assert!(matches!(
+            events[2].as_ref().unwrap().kind(),
+            EventType::Dispute(None)
+        ));
+        assert!(events[3].is_err());
+        // unknown aliases are rejected as when reading records
+        assert!(events[4].is_err());
+
+        // trimmed, headerless rows are read as they are into records
+        let headerless = CsvDialect {
+            has_headers: false,
+            trim: true,
+            ..Default::default()
+        };
+        let csv = " withdrawal , 3 , 5 , 2.5 \n";
+        let event = read_csv_events(
+            csv.as_bytes(),
+            headerless,
+            &ClientAliases::default(),
+            RoundingPolicy::Reject,
+        )
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+        assert_eq!((event.client_id(), event.tx()), (3, 5));
+    }
+
+    #[test]
+    fn test_decompress() {
+        use flate2::write::GzEncoder;
+        use std::io::{Cursor, Write};
+
+        let csv = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+        // files written by concatenating gzip files have several members
+        let mut gz = Vec::new();
+        for part in [&csv[..22], &csv[22..]] {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(part.as_bytes()).unwrap();
+            gz.extend(encoder.finish().unwrap());
+        }
+        let mut read = String::new();
+        decompress(Cursor::new(gz), Compression::Gzip)
+            .unwrap()
+            .read_to_string(&mut read)
+            .unwrap();
+        assert_eq!(read, csv);
+
+        let mut zst = Vec::new();
+        for part in [&csv[..22], &csv[22..]] {
+            zst.extend(zstd::encode_all(part.as_bytes(), 0).unwrap());
+        }
+        let mut read = String::new();
+        decompress(Cursor::new(zst), Compression::Zstd)
+            .unwrap()
+            .read_to_string(&mut read)
+            .unwrap();
+        assert_eq!(read, csv);
+
+        let mut read = String::new();
+        decompress(Cursor::new(csv), Compression::None)
+            .unwrap()
+            .read_to_string(&mut read)
+            .unwrap();
+        assert_eq!(read, csv);
+
+        assert_eq!(Compression::detect("events.csv.gz"), Compression::Gzip);
+        assert_eq!(Compression::detect("events.csv.zst"), Compression::Zstd);
+        assert_eq!(Compression::detect("events.csv"), Compression::None);
+        assert!(Compression::from_str("bzip2").is_err());
+    }
+
+    #[test]
+    fn test_expand_glob() {
+        assert!(wildcard_match(b"events-*.csv", b"events-2022-09-01.csv"));
+        assert!(wildcard_match(b"*-0?.csv", b"events-2022-09-01.csv"));
+        assert!(wildcard_match(b"*", b""));
+        assert!(!wildcard_match(
+            b"events-*.csv",
+            b"events-2022-09-01.csv.gz"
+        ));
+        assert!(!wildcard_match(b"?", b""));
+
+        let dir = std::env::temp_dir().join(format!("glob-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for file in [
+            "2022-09-02.csv",
+            "2022-09-01.csv",
+            ".2022-09-03.csv",
+            "notes.txt",
+        ] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        let dir = dir.to_str().unwrap();
+        let files = expand_glob(&format!("{}/2022-*.csv", dir));
+        let hidden = expand_glob(&format!("{}/.*.csv", dir));
+        let missing = expand_glob(&format!("{}/*.json", dir));
+        fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(
+            files.unwrap(),
+            [
+                format!("{}/2022-09-01.csv", dir),
+                format!("{}/2022-09-02.csv", dir)
+            ]
+        );
+        assert_eq!(hidden.unwrap(), [format!("{}/.2022-09-03.csv", dir)]);
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn test_invalid_json() {
+        let jsonl = concat!(
+            r#"{"type": "deposit", "client": "unknown", "tx": 1, "amount": 1}"#,
+            "\n",
+            r#"{"type": "deposit", "client": 1, "tx": 2, "amount": "1.25"}"#,
+            "\n",
+            "deposit,1,3,1.0\n",
+        );
+        let records: Vec<_> = read_records(
+            jsonl.as_bytes(),
+            InputFormat::Json,
+            CsvDialect::default(),
+            &ClientAliases::default(),
+        )
+        .unwrap()
+        .collect();
+        assert!(records[0].is_err());
+        assert_eq!(records[1].as_ref().unwrap().amount, Some(dec!(1.25)));
+        assert!(records[2].is_err());
+
+        assert_eq!(InputFormat::detect("events.jsonl"), InputFormat::Json);
+        assert_eq!(InputFormat::detect("-"), InputFormat::Csv);
+        assert_eq!(InputFormat::detect("events.jsonl.gz"), InputFormat::Json);
+        assert_eq!(InputFormat::detect("events.csv.zst"), InputFormat::Csv);
+        assert!(InputFormat::from_str("xml").is_err());
+    }
+}
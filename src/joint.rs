@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use serde::Deserialize;
+
+use crate::events::{ClientId, Event, EventType};
+
+#[derive(Debug, Deserialize)]
+struct Member {
+    client: ClientId,
+    account: ClientId,
+}
+
+/// Maps the client ids of the members of joint accounts, such as households, onto the
+/// single account they share.
+///
+/// # Example
+/// ```
+/// use payments::events::{Event, Record};
+/// use payments::joint::JointAccounts;
+/// use rust_decimal_macros::dec;
+///
+/// let joint = JointAccounts::new([(2, 1), (3, 1)]).unwrap();
+/// let deposit = Event::try_from(Record {
+///     r#type: "deposit".to_string(),
+///     client: 2,
+///     tx: 1,
+///     amount: Some(dec!(1.0)),
+///     to: None,
+///     seq: None,
+///     timestamp: None,
+///     currency: None,
+/// })
+/// .unwrap();
+///
+/// assert_eq!(joint.resolve(deposit).client_id(), 1);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct JointAccounts {
+    #[doc(hidden)]
+    accounts: HashMap<ClientId, ClientId>,
+}
+
+impl JointAccounts {
+    /// Creates a mapping from `(client, account)` pairs, failing if a client belongs to
+    /// more than one account or an account is itself mapped to another.
+    pub fn new(members: impl IntoIterator<Item = (ClientId, ClientId)>) -> Result<JointAccounts> {
+        let mut accounts = HashMap::new();
+        for (client, account) in members {
+            if accounts
+                .insert(client, account)
+                .is_some_and(|a| a != account)
+            {
+                bail!("client {} belongs to more than one joint account", client);
+            }
+        }
+        for (&client, &account) in &accounts {
+            if accounts.get(&account).is_some_and(|&a| a != account) {
+                bail!(
+                    "client {} belongs to account {}, which itself belongs to another account",
+                    client,
+                    account
+                );
+            }
+        }
+        Ok(JointAccounts { accounts })
+    }
+
+    /// Loads a mapping from a CSV file with `client` and `account` columns.
+    pub fn load(path: impl AsRef<Path>) -> Result<JointAccounts> {
+        let members = csv::Reader::from_path(path)?
+            .into_deserialize()
+            .map(|member| member.map(|member: Member| (member.client, member.account)))
+            .collect::<Result<Vec<_>, _>>()?;
+        JointAccounts::new(members)
+    }
+
+    /// Returns the account shared by `client`, or `client` itself if it is not a member
+    /// of a joint account.
+    pub fn account(&self, client: ClientId) -> ClientId {
+        self.accounts.get(&client).copied().unwrap_or(client)
+    }
+
+    /// Returns `event` targeting the account shared by its client, and for transfers,
+    /// sent to the account shared by the client they are sent to. A transfer between
+    /// members of the same joint account is then sent to the account it is sent from,
+    /// and rejected when it is applied.
+    pub fn resolve(&self, event: Event) -> Event {
+        let account = self.account(event.client_id());
+        let event = event.with_client(account);
+        match *event.kind() {
+            EventType::Transfer { to, .. } => event.with_destination(self.account(to)),
+            _ => event,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_account() {
+        let joint = JointAccounts::new([(2, 1), (3, 1), (1, 1)]).unwrap();
+        assert_eq!(joint.account(1), 1);
+        assert_eq!(joint.account(2), 1);
+        assert_eq!(joint.account(3), 1);
+        assert_eq!(joint.account(4), 4);
+    }
+
+    #[test]
+    fn test_transfer_between_members() {
+        use crate::parallel::Book;
+        use crate::rules::RuleSet;
+        use rust_decimal_macros::dec;
+
+        let joint = JointAccounts::new([(2, 1), (3, 1)]).unwrap();
+        let event = |r#type: &str, tx, to| {
+            let event = Event::try_from(crate::events::Record {
+                r#type: r#type.to_string(),
+                client: 2,
+                tx,
+                amount: Some(dec!(1.0)),
+                to,
+                seq: None,
+                timestamp: None,
+                currency: None,
+            })
+            .unwrap();
+            joint.resolve(event)
+        };
+        let transfer = event("transfer", 2, Some(3));
+        assert_eq!(transfer.client_id(), 1);
+        assert!(matches!(transfer.kind(), EventType::Transfer { to: 1, .. }));
+
+        let mut book = Book::default();
+        let rules = RuleSet::default();
+        book.apply(&event("deposit", 1, None), &rules).unwrap();
+        assert!(book.apply(&transfer, &rules).is_err());
+        assert_eq!(book.summary(1).unwrap().available, dec!(1.0));
+    }
+
+    #[test]
+    fn test_invalid_mappings() {
+        assert!(JointAccounts::new([(2, 1), (2, 3)]).is_err());
+        assert!(JointAccounts::new([(3, 2), (2, 1)]).is_err());
+    }
+}
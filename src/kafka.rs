@@ -0,0 +1,151 @@
+use std::str;
+use std::time::{Duration, Instant};
+
+use ::kafka::consumer::{Consumer, FetchOffset, GroupOffsetStorage};
+use ::kafka::producer::{self, Producer, RequiredAcks};
+use anyhow::{Context, Result};
+
+use crate::aliases::ClientAliases;
+use crate::deadletter::{DeadLetter, DeadLetterSink};
+use crate::events::Record;
+use crate::input;
+use crate::metrics::SharedMetrics;
+
+/// Consumes payment records from a Kafka topic as a member of a consumer group, so that
+/// the processor can run as a long-lived service.
+///
+/// Each message holds one record as a JSON object, with the same fields as a line of
+/// JSON Lines input. A consumer group's offsets are only committed once every message
+/// polled before them has been processed, so messages polled by a processor which
+/// stops before processing them are consumed again by the group's next member.
+pub struct KafkaSource {
+    #[doc(hidden)]
+    consumer: Consumer,
+    #[doc(hidden)]
+    metrics: Option<SharedMetrics>,
+}
+
+impl KafkaSource {
+    /// Joins consumer `group` reading `topic` from the brokers at `hosts`, e.g.
+    /// "localhost:9092". A group with no committed offsets reads the topic from its
+    /// earliest message.
+    pub fn connect(hosts: Vec<String>, topic: &str, group: &str) -> Result<KafkaSource> {
+        let consumer = Consumer::from_hosts(hosts)
+            .with_topic(topic.to_string())
+            .with_group(group.to_string())
+            .with_fallback_offset(FetchOffset::Earliest)
+            .with_offset_storage(Some(GroupOffsetStorage::Kafka))
+            .create()
+            .with_context(|| format!("consuming topic {:?}", topic))?;
+        Ok(KafkaSource {
+            consumer,
+            metrics: None,
+        })
+    }
+
+    /// Records the time taken by each poll of the topic to `metrics`.
+    pub fn with_metrics(self, metrics: SharedMetrics) -> KafkaSource {
+        KafkaSource {
+            metrics: Some(metrics),
+            ..self
+        }
+    }
+
+    /// Polls the topic until it fails, passing the record of each message, with its
+    /// client id resolved through `aliases`, to `process` in partition order, along with
+    /// its topic and partition as "topic/partition" and its offset. Offsets are
+    /// committed after `process` has returned for every message of a poll.
+    pub fn run(
+        &mut self,
+        aliases: &ClientAliases,
+        mut process: impl FnMut(&str, u64, Result<Record>),
+    ) -> Result<()> {
+        loop {
+            let start = Instant::now();
+            let sets = self.consumer.poll().context("polling for messages")?;
+            if let Some(metrics) = &self.metrics {
+                metrics.lock().unwrap().ingested(start.elapsed());
+            }
+            for set in sets.iter() {
+                let source = format!("{}/{}", set.topic(), set.partition());
+                for message in set.messages() {
+                    let record = decode(message.value, aliases).with_context(|| {
+                        format!(
+                            "reading message {} of {}/{}",
+                            message.offset,
+                            set.topic(),
+                            set.partition()
+                        )
+                    });
+                    process(&source, message.offset as u64, record);
+                }
+                self.consumer.consume_messageset(set)?;
+            }
+            self.consumer
+                .commit_consumed()
+                .context("committing offsets")?;
+        }
+    }
+}
+
+/// A dead letter sink publishing to a Kafka topic, one JSON object per message with the
+/// fields of the record followed by a `reason`, as [`crate::deadletter::FileSink`]
+/// writes them. Each message is acknowledged by the topic's leader before the next
+/// entry is processed.
+pub struct KafkaSink {
+    #[doc(hidden)]
+    producer: Producer,
+    #[doc(hidden)]
+    topic: String,
+}
+
+impl KafkaSink {
+    /// Connects to the brokers at `hosts`, e.g. "localhost:9092", to publish dead
+    /// letters to `topic`.
+    pub fn connect(hosts: Vec<String>, topic: &str) -> Result<KafkaSink> {
+        let producer = Producer::from_hosts(hosts)
+            .with_ack_timeout(Duration::from_secs(1))
+            .with_required_acks(RequiredAcks::One)
+            .create()
+            .with_context(|| format!("producing to topic {:?}", topic))?;
+        Ok(KafkaSink {
+            producer,
+            topic: topic.to_string(),
+        })
+    }
+}
+
+impl DeadLetterSink for KafkaSink {
+    fn send(&mut self, letter: DeadLetter) -> Result<()> {
+        let value = serde_json::to_vec(&letter)?;
+        self.producer
+            .send(&producer::Record::from_value(&self.topic, value))
+            .with_context(|| format!("publishing to {}", self.topic))?;
+        Ok(())
+    }
+}
+
+/// Reads the record held by a message.
+fn decode(value: &[u8], aliases: &ClientAliases) -> Result<Record> {
+    input::parse_json(str::from_utf8(value)?, aliases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_decode() {
+        let aliases = ClientAliases::new([("acme-1".to_string(), 1)]).unwrap();
+        let message = br#"{"type": "deposit", "client": "acme-1", "tx": 7, "amount": 2.5}"#;
+        let record = decode(message, &aliases).unwrap();
+        assert_eq!(record.client, 1);
+        assert_eq!(record.tx, 7);
+        assert_eq!(record.amount, Some(dec!(2.5)));
+
+        assert!(decode(b"deposit,1,7,2.5", &aliases).is_err());
+        assert!(decode(&[0xff, 0xfe], &aliases).is_err());
+    }
+}
@@ -0,0 +1,79 @@
+//! A payments engine which applies deposits, withdrawals, disputes, resolutions and
+//! chargebacks to client accounts.
+//!
+//! The `payments` binary wraps the engine in the command line utility of the [`cli`]
+//! module. Building the library without default features leaves out the dependencies
+//! only the command line needs, and the backends with heavy dependencies, such as the
+//! `sled` and `postgres` stores or the `kafka` source and sink, are behind features of
+//! their own.
+
+#[cfg(feature = "async")]
+pub mod actors;
+#[cfg(feature = "http")]
+pub mod alerts;
+pub mod aliases;
+pub mod amount;
+pub mod anomaly;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod audit;
+pub mod checkpoint;
+pub mod clearing;
+#[cfg(feature = "cli")]
+pub mod cli;
+pub mod clients;
+pub mod deadletter;
+pub mod dedup;
+pub mod disputes;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+pub mod events;
+pub mod follow;
+pub mod hierarchy;
+pub mod history;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod input;
+pub mod joint;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod lockouts;
+pub mod manifest;
+pub mod merge;
+pub mod metrics;
+pub mod middleware;
+pub mod observer;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod output;
+pub mod parallel;
+pub mod parking;
+pub mod pipeline;
+#[cfg(feature = "cli")]
+mod processor;
+pub mod progress;
+pub mod projection;
+pub mod rejects;
+pub mod reorder;
+pub mod risk;
+pub mod rules;
+pub mod schedule;
+#[cfg(feature = "script")]
+pub mod script;
+pub mod sequence;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod settlement;
+#[cfg(feature = "signature")]
+pub mod signature;
+pub mod source;
+pub mod stats;
+pub mod statsd;
+pub mod storage;
+pub mod tsdb;
+pub mod wal;
+pub mod watch;
+#[cfg(feature = "http")]
+pub mod webhooks;
@@ -0,0 +1,117 @@
+use crate::clients::Summary;
+use crate::events::{ClientId, Event, EventType, TxId};
+
+/// An account locked by a chargeback.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lockout {
+    /// The client whose account was locked.
+    pub client: ClientId,
+    /// The transaction charged back.
+    pub tx: TxId,
+    /// The timestamp of the chargeback, if known.
+    pub timestamp: Option<u64>,
+}
+
+/// Records the accounts locked during a run, along with the chargeback which locked
+/// each of them.
+///
+/// # Example
+/// ```
+/// use payments::clients::Summary;
+/// use payments::events::{Event, Record};
+/// use payments::lockouts::Lockouts;
+///
+/// let chargeback = Event::try_from(Record {
+///     r#type: "chargeback".to_string(),
+///     client: 1,
+///     tx: 7,
+///     amount: None,
+///     to: None,
+///     seq: None,
+///     timestamp: None,
+///     currency: None,
+/// })
+/// .unwrap();
+/// let mut lockouts = Lockouts::default();
+/// lockouts.observe(&chargeback, &Summary { id: 1, locked: true, ..Default::default() });
+///
+/// assert_eq!(lockouts.list()[0].tx, 7);
+/// ```
+#[derive(Debug, Default)]
+pub struct Lockouts {
+    #[doc(hidden)]
+    lockouts: Vec<Lockout>,
+}
+
+impl Lockouts {
+    /// Records `event` after it was applied, given the resulting balances of its
+    /// client.
+    pub fn observe(&mut self, event: &Event, summary: &Summary) {
+        if matches!(event.kind(), EventType::Chargeback) && summary.locked {
+            self.lockouts.push(Lockout {
+                client: event.client_id(),
+                tx: event.tx(),
+                timestamp: event.timestamp(),
+            });
+        }
+    }
+
+    /// Returns the accounts locked so far, in the order they were locked.
+    pub fn list(&self) -> &[Lockout] {
+        &self.lockouts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    use crate::events::Record;
+
+    fn event(t: &str, client: ClientId, tx: TxId) -> Event {
+        Event::try_from(Record {
+            r#type: t.to_string(),
+            client,
+            tx,
+            amount: Some(dec!(1.0)),
+            to: None,
+            seq: None,
+            timestamp: Some(tx * 10),
+            currency: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_observe() {
+        let locked = |id| Summary {
+            id,
+            locked: true,
+            ..Default::default()
+        };
+        let mut lockouts = Lockouts::default();
+        lockouts.observe(&event("deposit", 1, 1), &Summary::default());
+        lockouts.observe(&event("chargeback", 2, 2), &locked(2));
+        // only chargebacks lock accounts
+        lockouts.observe(&event("resolve", 3, 3), &locked(3));
+        lockouts.observe(&event("chargeback", 1, 4), &locked(1));
+
+        assert_eq!(
+            lockouts.list(),
+            [
+                Lockout {
+                    client: 2,
+                    tx: 2,
+                    timestamp: Some(20),
+                },
+                Lockout {
+                    client: 1,
+                    tx: 4,
+                    timestamp: Some(40),
+                },
+            ]
+        );
+    }
+}
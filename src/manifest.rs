@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Deserialize)]
+struct Entry {
+    file: String,
+    rows: u64,
+    sha256: String,
+}
+
+/// The number of rows and SHA-256 checksum of an input file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileDigest {
+    /// The number of records in the file, not counting the header.
+    pub rows: u64,
+    /// The lowercase hex encoded SHA-256 checksum of the file's contents.
+    pub sha256: String,
+}
+
+/// Passes data through while hashing it.
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl FileDigest {
+    /// Counts the rows of, and checksums, the contents of `reader` in a single pass.
+    pub fn compute(reader: impl Read) -> Result<FileDigest> {
+        let mut reader = HashingReader {
+            inner: reader,
+            hasher: Sha256::new(),
+        };
+        let mut records = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(&mut reader);
+        let mut rows = 0;
+        let mut record = csv::ByteRecord::new();
+        while records.read_byte_record(&mut record)? {
+            rows += 1;
+        }
+        drop(records);
+        // hash anything left unread by the CSV reader
+        io::copy(&mut reader, &mut io::sink())?;
+
+        let sha256 = reader
+            .hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        Ok(FileDigest { rows, sha256 })
+    }
+}
+
+/// The expected row counts and checksums of input files, for detecting files which
+/// were truncated or altered in transfer.
+///
+/// # Example
+/// ```
+/// use payments::manifest::{FileDigest, Manifest};
+///
+/// let digest = FileDigest::compute("type,client,tx,amount\n".as_bytes()).unwrap();
+/// assert_eq!(digest.rows, 0);
+///
+/// let manifest = Manifest::new([("empty.csv".to_string(), digest.clone())]);
+/// assert!(manifest.check("data/empty.csv", &digest).is_ok());
+/// assert!(manifest.check("other.csv", &digest).is_err());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Manifest {
+    #[doc(hidden)]
+    files: HashMap<String, FileDigest>,
+}
+
+impl Manifest {
+    /// Creates a manifest from pairs of file names and their expected digests.
+    pub fn new(files: impl IntoIterator<Item = (String, FileDigest)>) -> Manifest {
+        Manifest {
+            files: files.into_iter().collect(),
+        }
+    }
+
+    /// Loads a manifest from a CSV file with `file`, `rows` and `sha256` columns.
+    pub fn load(path: impl AsRef<Path>) -> Result<Manifest> {
+        let files = csv::Reader::from_path(path)?
+            .into_deserialize()
+            .map(|entry| {
+                entry.map(|entry: Entry| {
+                    let digest = FileDigest {
+                        rows: entry.rows,
+                        sha256: entry.sha256.to_ascii_lowercase(),
+                    };
+                    (entry.file, digest)
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Manifest::new(files))
+    }
+
+    /// Checks the digest of the input file at `path` against the manifest, which lists
+    /// the file either by the same path or by its file name.
+    pub fn check(&self, path: &str, digest: &FileDigest) -> Result<()> {
+        let name = Path::new(path).file_name().and_then(|name| name.to_str());
+        let expected = self
+            .files
+            .get(path)
+            .or_else(|| self.files.get(name?))
+            .ok_or_else(|| anyhow!("{} is not listed in the manifest", path))?;
+        if expected.rows != digest.rows {
+            bail!(
+                "{} has {} rows, but the manifest expects {}",
+                path,
+                digest.rows,
+                expected.rows
+            );
+        }
+        if expected.sha256 != digest.sha256 {
+            bail!(
+                "{} has checksum {}, but the manifest expects {}",
+                path,
+                digest.sha256,
+                expected.sha256
+            );
+        }
+        Ok(())
+    }
+
+    /// Reads the input file at `path`, checking it against the manifest.
+    pub fn verify(&self, path: &str) -> Result<()> {
+        let file = File::open(path).with_context(|| format!("opening {}", path))?;
+        let digest = FileDigest::compute(file).with_context(|| format!("reading {}", path))?;
+        self.check(path, &digest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENTS: &str = "type,client,tx,amount\ndeposit,1,1,1.0\ndispute,1,1\n";
+
+    #[test]
+    fn test_compute() {
+        let digest = FileDigest::compute(CONTENTS.as_bytes()).unwrap();
+        assert_eq!(digest.rows, 2);
+        assert_eq!(
+            digest.sha256,
+            "d30df9129e799f00db5eec64303112b62ea7d07a337133a9634bf69843b18d52"
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        let digest = FileDigest::compute(CONTENTS.as_bytes()).unwrap();
+        let manifest = Manifest::new([("in/events.csv".to_string(), digest.clone())]);
+        assert!(manifest.check("in/events.csv", &digest).is_ok());
+        assert!(manifest.check("out/events.csv", &digest).is_err());
+
+        let truncated = FileDigest::compute(&CONTENTS.as_bytes()[..38]).unwrap();
+        let manifest = Manifest::new([("events.csv".to_string(), digest.clone())]);
+        assert!(manifest.check("in/events.csv", &digest).is_ok());
+        let e = manifest.check("in/events.csv", &truncated).unwrap_err();
+        assert!(e.to_string().contains("has 1 rows"));
+
+        let altered = FileDigest::compute(CONTENTS.replace("1.0", "9.0").as_bytes()).unwrap();
+        let e = manifest.check("in/events.csv", &altered).unwrap_err();
+        assert!(e.to_string().contains("checksum"));
+    }
+}
@@ -0,0 +1,153 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use anyhow::Result;
+
+use crate::events::Record;
+
+/// Merges several streams of payment records, each ordered by timestamp, into a
+/// single stream in global timestamp order.
+///
+/// Each item is yielded alongside the index of the stream it was read from. Ties are
+/// broken by stream index, so events from earlier streams are applied first. A
+/// record without a timestamp, or an unparseable entry, is ordered as if it occurred
+/// at the most recent timestamp seen on its own stream, so it keeps its position
+/// relative to its neighbours.
+///
+/// # Example
+/// ```
+/// use payments::events::Record;
+/// use payments::merge::MergedRecords;
+/// use rust_decimal_macros::dec;
+///
+/// let record = |tx, timestamp| Record {
+///     r#type: "deposit".to_string(),
+///     client: 1,
+///     tx,
+///     amount: Some(dec!(1.0)),
+///     to: None,
+///     seq: None,
+///     timestamp: Some(timestamp),
+///     currency: None,
+/// };
+/// let a = vec![Ok(record(1, 10)), Ok(record(3, 30))];
+/// let b = vec![Ok(record(2, 20))];
+///
+/// let merged = MergedRecords::new(vec![a.into_iter(), b.into_iter()]);
+/// let txs: Vec<_> = merged.map(|(_, r)| r.unwrap().tx).collect();
+/// assert_eq!(txs, vec![1, 2, 3]);
+/// ```
+pub struct MergedRecords<I: Iterator<Item = Result<Record>>> {
+    #[doc(hidden)]
+    sources: Vec<I>,
+    #[doc(hidden)]
+    heads: Vec<Option<Result<Record>>>,
+    #[doc(hidden)]
+    clocks: Vec<u64>,
+    #[doc(hidden)]
+    queue: BinaryHeap<Reverse<(u64, usize)>>,
+}
+
+impl<I: Iterator<Item = Result<Record>>> MergedRecords<I> {
+    /// Creates a merged stream over `sources`.
+    pub fn new(sources: Vec<I>) -> MergedRecords<I> {
+        let mut merged = MergedRecords {
+            heads: sources.iter().map(|_| None).collect(),
+            clocks: vec![0; sources.len()],
+            queue: BinaryHeap::with_capacity(sources.len()),
+            sources,
+        };
+        for i in 0..merged.sources.len() {
+            merged.advance(i);
+        }
+        merged
+    }
+
+    fn advance(&mut self, i: usize) {
+        if let Some(entry) = self.sources[i].next() {
+            if let Some(ts) = entry.as_ref().ok().and_then(|record| record.timestamp) {
+                self.clocks[i] = ts;
+            }
+            self.heads[i] = Some(entry);
+            self.queue.push(Reverse((self.clocks[i], i)));
+        }
+    }
+}
+
+impl<I: Iterator<Item = Result<Record>>> Iterator for MergedRecords<I> {
+    type Item = (usize, Result<Record>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((_, i)) = self.queue.pop()?;
+        let entry = self.heads[i].take()?;
+        self.advance(i);
+        Some((i, entry))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    use anyhow::anyhow;
+
+    use crate::events::TxId;
+
+    fn record(tx: TxId, timestamp: Option<u64>) -> Result<Record> {
+        Ok(Record {
+            r#type: "deposit".to_string(),
+            client: 1,
+            tx,
+            amount: Some(dec!(1.0)),
+            to: None,
+            seq: None,
+            timestamp,
+            currency: None,
+        })
+    }
+
+    fn merge(sources: Vec<Vec<Result<Record>>>) -> Vec<(usize, Option<TxId>)> {
+        MergedRecords::new(sources.into_iter().map(|s| s.into_iter()).collect())
+            .map(|(i, entry)| (i, entry.ok().map(|r| r.tx)))
+            .collect()
+    }
+
+    #[test]
+    fn test_interleaves_by_timestamp() {
+        let merged = merge(vec![
+            vec![record(1, Some(1)), record(4, Some(4)), record(5, Some(5))],
+            vec![record(2, Some(2)), record(3, Some(3)), record(6, Some(6))],
+        ]);
+        assert_eq!(
+            merged,
+            vec![
+                (0, Some(1)),
+                (1, Some(2)),
+                (1, Some(3)),
+                (0, Some(4)),
+                (0, Some(5)),
+                (1, Some(6))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ties_prefer_earlier_source() {
+        let merged = merge(vec![vec![record(1, Some(5))], vec![record(2, Some(5))]]);
+        assert_eq!(merged, vec![(0, Some(1)), (1, Some(2))]);
+    }
+
+    #[test]
+    fn test_untimestamped_and_errors_keep_position() {
+        let merged = merge(vec![
+            vec![record(1, Some(1)), Err(anyhow!("bad row")), record(3, None)],
+            vec![record(2, Some(2))],
+        ]);
+        assert_eq!(
+            merged,
+            vec![(0, Some(1)), (0, None), (0, Some(3)), (1, Some(2))]
+        );
+    }
+}
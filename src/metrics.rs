@@ -0,0 +1,556 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{Error, Result};
+
+use crate::clients::Summary;
+use crate::events::{ClientId, Currency, TxId};
+use crate::storage::{Account, ClientStore, TxState, TxStore};
+
+/// The default histogram bucket boundaries, in seconds.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.000_001, 0.000_005, 0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05, 0.1, 1.0,
+];
+
+/// A cumulative histogram of observed durations.
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    #[doc(hidden)]
+    counts: Vec<u64>,
+    #[doc(hidden)]
+    count: u64,
+    #[doc(hidden)]
+    sum: f64,
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram {
+            counts: vec![0; LATENCY_BUCKETS.len()],
+            count: 0,
+            sum: 0.0,
+        }
+    }
+}
+
+impl Histogram {
+    /// Records a single observed duration.
+    pub fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(self.counts.iter_mut()) {
+            if seconds <= *bound {
+                *count += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+
+    /// Returns the upper bound of each bucket, in seconds, alongside the number of
+    /// observations less than or equal to it.
+    pub fn buckets(&self) -> impl Iterator<Item = (f64, u64)> + '_ {
+        LATENCY_BUCKETS
+            .iter()
+            .copied()
+            .zip(self.counts.iter().copied())
+    }
+
+    /// Returns the number of observations recorded.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns the sum of every observation recorded, in seconds.
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        for (bound, count) in LATENCY_BUCKETS.iter().zip(self.counts.iter()) {
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}{}le=\"{}\"}} {}",
+                name, labels, sep, bound, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{}{}le=\"+Inf\"}} {}",
+            name, labels, sep, self.count
+        );
+        let braces = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{}}}", labels)
+        };
+        let _ = writeln!(out, "{}_sum{} {}", name, braces, self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, braces, self.count);
+    }
+}
+
+/// The kind of error an event was rejected for.
+///
+/// Rejected events are counted by kind rather than by error message, as messages can
+/// hold client ids, amounts and text returned by scripts or middleware, which would
+/// give every rejection a series of its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RejectKind {
+    /// The record could not be parsed into an event.
+    InvalidRecord,
+    /// The event refers to a transaction which does not exist.
+    UnknownTransaction,
+    /// The event reuses the id of an existing transaction.
+    DuplicateTransaction,
+    /// The client has too little available to apply the event.
+    InsufficientFunds,
+    /// The client's account is frozen, closed or otherwise not accepting the event.
+    AccountUnavailable,
+    /// The transaction the event refers to is in a state the event can't be applied
+    /// to, such as resolving a transaction which isn't disputed.
+    InvalidTransition,
+    /// Any other reason, such as a limit, a risk rule or a script denying the event.
+    Other,
+}
+
+impl RejectKind {
+    /// Returns the kind of `error`, which rejected an event.
+    pub fn of(error: &Error) -> RejectKind {
+        let message = error.root_cause().to_string();
+        if message == "transaction does not exist" {
+            RejectKind::UnknownTransaction
+        } else if message.starts_with("cannot overwrite")
+            || message.ends_with("for different client")
+        {
+            RejectKind::DuplicateTransaction
+        } else if message.starts_with("insufficient funds")
+            || message.starts_with("not enough funds")
+        {
+            RejectKind::InsufficientFunds
+        } else if message.starts_with("account is") || message.starts_with("destination account is")
+        {
+            RejectKind::AccountUnavailable
+        } else if ["transaction", "authorization", "dispute", "capture"]
+            .iter()
+            .any(|word| message.contains(word))
+        {
+            RejectKind::InvalidTransition
+        } else {
+            RejectKind::Other
+        }
+    }
+
+    /// Returns the label the kind is counted under.
+    pub fn name(&self) -> &'static str {
+        match self {
+            RejectKind::InvalidRecord => "invalid_record",
+            RejectKind::UnknownTransaction => "unknown_transaction",
+            RejectKind::DuplicateTransaction => "duplicate_transaction",
+            RejectKind::InsufficientFunds => "insufficient_funds",
+            RejectKind::AccountUnavailable => "account_unavailable",
+            RejectKind::InvalidTransition => "invalid_transition",
+            RejectKind::Other => "other",
+        }
+    }
+}
+
+/// Counters and histograms describing a processing run, rendered in the Prometheus
+/// text exposition format.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+///
+/// use payments::metrics::{Metrics, RejectKind};
+///
+/// let mut metrics = Metrics::default();
+/// metrics.processed("deposit", Duration::from_micros(3));
+/// metrics.rejected(RejectKind::InsufficientFunds);
+/// metrics.set_locked_accounts(0);
+///
+/// print!("{}", metrics.render());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    #[doc(hidden)]
+    processed: BTreeMap<&'static str, u64>,
+    #[doc(hidden)]
+    rejected: BTreeMap<&'static str, u64>,
+    #[doc(hidden)]
+    processing: Histogram,
+    #[doc(hidden)]
+    ingestion: Histogram,
+    #[doc(hidden)]
+    store_get: Histogram,
+    #[doc(hidden)]
+    store_upsert: Histogram,
+    #[doc(hidden)]
+    locked_accounts: u64,
+    #[doc(hidden)]
+    clients: HashSet<ClientId>,
+    #[doc(hidden)]
+    locked: HashSet<ClientId>,
+}
+
+/// Metrics shared between the processing loop and instrumented stores.
+pub type SharedMetrics = Arc<Mutex<Metrics>>;
+
+impl Metrics {
+    /// Records an event of type `kind` which was applied in `elapsed` time.
+    pub fn processed(&mut self, kind: &'static str, elapsed: Duration) {
+        *self.processed.entry(kind).or_default() += 1;
+        self.processing.observe(elapsed);
+    }
+
+    /// Records a service taking `elapsed` time to receive records from its source, such
+    /// as to poll a topic or read the body of a request.
+    pub fn ingested(&mut self, elapsed: Duration) {
+        self.ingestion.observe(elapsed);
+    }
+
+    /// Records an event which was rejected, or an invalid record, for an error of
+    /// kind `reason`.
+    pub fn rejected(&mut self, reason: RejectKind) {
+        *self.rejected.entry(reason.name()).or_default() += 1;
+    }
+
+    /// Records the balances of a client after an event was applied, keeping the number
+    /// of active clients and of frozen accounts up to date as events are processed.
+    pub fn account(&mut self, summary: &Summary) {
+        self.clients.insert(summary.id);
+        if summary.locked {
+            self.locked.insert(summary.id);
+        } else {
+            self.locked.remove(&summary.id);
+        }
+        self.locked_accounts = self.locked.len() as u64;
+    }
+
+    /// Sets the number of accounts which are currently frozen.
+    pub fn set_locked_accounts(&mut self, locked: u64) {
+        self.locked_accounts = locked;
+    }
+
+    /// Returns the number of events applied, by event type.
+    pub fn processed_by_type(&self) -> &BTreeMap<&'static str, u64> {
+        &self.processed
+    }
+
+    /// Returns the number of events rejected, by the name of their [`RejectKind`].
+    pub fn rejected_by_reason(&self) -> &BTreeMap<&'static str, u64> {
+        &self.rejected
+    }
+
+    /// Returns the time taken to apply each event.
+    pub fn processing_latency(&self) -> &Histogram {
+        &self.processing
+    }
+
+    /// Returns the time taken by a service to receive records from its source.
+    pub fn ingestion_latency(&self) -> &Histogram {
+        &self.ingestion
+    }
+
+    /// Returns the time taken by each kind of transaction store operation.
+    pub fn store_latency(&self) -> [(&'static str, &Histogram); 2] {
+        [("get", &self.store_get), ("upsert", &self.store_upsert)]
+    }
+
+    /// Returns the number of accounts which are currently frozen.
+    pub fn locked_accounts(&self) -> u64 {
+        self.locked_accounts
+    }
+
+    /// Returns the number of clients events have been applied to.
+    pub fn active_clients(&self) -> u64 {
+        self.clients.len() as u64
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP payments_events_processed_total Payment events applied, by type.\n");
+        out.push_str("# TYPE payments_events_processed_total counter\n");
+        for (kind, count) in &self.processed {
+            let _ = writeln!(
+                out,
+                "payments_events_processed_total{{type=\"{}\"}} {}",
+                kind, count
+            );
+        }
+
+        out.push_str("# HELP payments_events_rejected_total Payment events rejected, by reason.\n");
+        out.push_str("# TYPE payments_events_rejected_total counter\n");
+        for (reason, count) in &self.rejected {
+            let _ = writeln!(
+                out,
+                "payments_events_rejected_total{{reason=\"{}\"}} {}",
+                reason, count
+            );
+        }
+
+        out.push_str(
+            "# HELP payments_event_processing_seconds Time taken to apply a payment event.\n",
+        );
+        out.push_str("# TYPE payments_event_processing_seconds histogram\n");
+        self.processing
+            .render(&mut out, "payments_event_processing_seconds", "");
+
+        out.push_str(
+            "# HELP payments_ingestion_seconds Time taken by a service to receive payment records.\n",
+        );
+        out.push_str("# TYPE payments_ingestion_seconds histogram\n");
+        self.ingestion
+            .render(&mut out, "payments_ingestion_seconds", "");
+
+        out.push_str(
+            "# HELP payments_store_operation_seconds Time taken by transaction store operations.\n",
+        );
+        out.push_str("# TYPE payments_store_operation_seconds histogram\n");
+        self.store_get
+            .render(&mut out, "payments_store_operation_seconds", "op=\"get\"");
+        self.store_upsert.render(
+            &mut out,
+            "payments_store_operation_seconds",
+            "op=\"upsert\"",
+        );
+
+        out.push_str("# HELP payments_locked_accounts Client accounts which are frozen.\n");
+        out.push_str("# TYPE payments_locked_accounts gauge\n");
+        let _ = writeln!(out, "payments_locked_accounts {}", self.locked_accounts);
+
+        out.push_str(
+            "# HELP payments_active_clients Client accounts which events have been applied to.\n",
+        );
+        out.push_str("# TYPE payments_active_clients gauge\n");
+        let _ = writeln!(out, "payments_active_clients {}", self.active_clients());
+
+        out
+    }
+
+    /// Writes every metric to `path` in the format expected by the node exporter's
+    /// textfile collector, replacing the file atomically.
+    pub fn write_textfile(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, self.render())?;
+        fs::rename(&tmp, path)
+    }
+}
+
+/// A transaction store which records the latency of every operation performed on
+/// the wrapped store.
+#[derive(Clone, Debug, Default)]
+pub struct TimedStore<T: TxStore> {
+    #[doc(hidden)]
+    inner: T,
+    #[doc(hidden)]
+    metrics: SharedMetrics,
+}
+
+impl<T: TxStore> TimedStore<T> {
+    /// Wraps `inner`, recording operation latencies into `metrics`.
+    pub fn new(inner: T, metrics: SharedMetrics) -> TimedStore<T> {
+        TimedStore { inner, metrics }
+    }
+}
+
+impl<T: TxStore> ClientStore for TimedStore<T> {
+    fn account(&self, client_id: ClientId) -> Option<Account> {
+        self.inner.account(client_id)
+    }
+
+    fn save_account(&mut self, client_id: ClientId, account: Account) -> Result<()> {
+        self.inner.save_account(client_id, account)
+    }
+
+    fn clients(&self) -> Vec<ClientId> {
+        self.inner.clients()
+    }
+}
+
+impl<T: TxStore> TxStore for TimedStore<T> {
+    fn get(&self, client_id: ClientId, tx_id: TxId) -> Option<TxState> {
+        let start = Instant::now();
+        let tx = self.inner.get(client_id, tx_id);
+        self.metrics
+            .lock()
+            .unwrap()
+            .store_get
+            .observe(start.elapsed());
+        tx
+    }
+
+    fn upsert(&mut self, client_id: ClientId, tx_id: TxId, tx: TxState) -> Result<()> {
+        let start = Instant::now();
+        let result = self.inner.upsert(client_id, tx_id, tx);
+        self.metrics
+            .lock()
+            .unwrap()
+            .store_upsert
+            .observe(start.elapsed());
+        result
+    }
+
+    fn list(&self, client_id: ClientId) -> impl Iterator<Item = (TxId, TxState)> {
+        self.inner.list(client_id)
+    }
+
+    fn tx_ids(&self) -> impl Iterator<Item = TxId> {
+        self.inner.tx_ids()
+    }
+
+    fn currency(&self, tx_id: TxId) -> Option<Currency> {
+        self.inner.currency(tx_id)
+    }
+
+    fn set_currency(&mut self, tx_id: TxId, currency: Currency) -> Result<()> {
+        self.inner.set_currency(tx_id, currency)
+    }
+
+    fn timestamp(&self, tx_id: TxId) -> Option<u64> {
+        self.inner.timestamp(tx_id)
+    }
+
+    fn set_timestamp(&mut self, tx_id: TxId, timestamp: u64) -> Result<()> {
+        self.inner.set_timestamp(tx_id, timestamp)
+    }
+
+    fn prune(&mut self, before: u64) -> Result<usize> {
+        self.inner.prune(before)
+    }
+
+    fn set_pruned(&mut self, tx_ids: RangeInclusive<TxId>) -> Result<()> {
+        self.inner.set_pruned(tx_ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::anyhow;
+    use rust_decimal::Decimal;
+
+    use crate::clients::Client;
+    use crate::events::{Event, Record};
+    use crate::storage::MemoryStore;
+
+    #[test]
+    fn test_histogram_cumulative() {
+        let mut histogram = Histogram::default();
+        histogram.observe(Duration::from_micros(2));
+        histogram.observe(Duration::from_secs(2));
+
+        let mut out = String::new();
+        histogram.render(&mut out, "h", "");
+        assert!(out.contains("h_bucket{le=\"0.000001\"} 0\n"));
+        assert!(out.contains("h_bucket{le=\"0.000005\"} 1\n"));
+        assert!(out.contains("h_bucket{le=\"1\"} 1\n"));
+        assert!(out.contains("h_bucket{le=\"+Inf\"} 2\n"));
+        assert!(out.contains("h_count 2\n"));
+    }
+
+    #[test]
+    fn test_render_counters() {
+        let mut metrics = Metrics::default();
+        metrics.processed("deposit", Duration::ZERO);
+        metrics.processed("deposit", Duration::ZERO);
+        metrics.rejected(RejectKind::of(&anyhow!("transaction does not exist")));
+        metrics.set_locked_accounts(3);
+
+        let out = metrics.render();
+        assert!(out.contains("payments_events_processed_total{type=\"deposit\"} 2\n"));
+        assert!(out.contains("payments_events_rejected_total{reason=\"unknown_transaction\"} 1\n"));
+        assert!(out.contains("payments_locked_accounts 3\n"));
+    }
+
+    #[test]
+    fn test_reject_kinds() {
+        let mut client = Client::new(1, MemoryStore::new());
+        let mut kind = |t: &str, tx, amount: Option<i64>| {
+            let event = Event::try_from(Record {
+                r#type: t.to_string(),
+                client: 1,
+                tx,
+                amount: amount.map(Decimal::from),
+                to: None,
+                seq: None,
+                timestamp: None,
+                currency: None,
+            })
+            .unwrap();
+            client.update(&event).err().map(|e| RejectKind::of(&e))
+        };
+
+        assert_eq!(kind("deposit", 1, Some(5)), None);
+        assert_eq!(
+            kind("dispute", 2, None),
+            Some(RejectKind::UnknownTransaction)
+        );
+        assert_eq!(
+            kind("deposit", 1, Some(5)),
+            Some(RejectKind::DuplicateTransaction)
+        );
+        assert_eq!(
+            kind("withdrawal", 3, Some(10)),
+            Some(RejectKind::InsufficientFunds)
+        );
+        assert_eq!(
+            kind("resolve", 1, None),
+            Some(RejectKind::InvalidTransition)
+        );
+        assert_eq!(kind("dispute", 1, None), None);
+        assert_eq!(kind("chargeback", 1, None), None);
+        assert_eq!(
+            kind("deposit", 4, Some(5)),
+            Some(RejectKind::AccountUnavailable)
+        );
+        assert_eq!(
+            RejectKind::of(&anyhow!("more than 3 withdrawals")),
+            RejectKind::Other
+        );
+    }
+
+    #[test]
+    fn test_accounts() {
+        let mut metrics = Metrics::default();
+        let summary = |id, locked| Summary {
+            id,
+            locked,
+            ..Default::default()
+        };
+        metrics.account(&summary(1, false));
+        metrics.account(&summary(2, true));
+        metrics.account(&summary(2, true));
+        assert_eq!(metrics.active_clients(), 2);
+        assert_eq!(metrics.locked_accounts(), 1);
+
+        metrics.account(&summary(2, false));
+        assert_eq!(metrics.locked_accounts(), 0);
+        assert!(metrics.render().contains("payments_active_clients 2\n"));
+    }
+
+    #[test]
+    fn test_timed_store() {
+        let metrics = SharedMetrics::default();
+        let mut store = TimedStore::new(MemoryStore::new(), Arc::clone(&metrics));
+        store
+            .upsert(1, 1, TxState::Withdrawal(Decimal::ONE))
+            .unwrap();
+        store.get(1, 1);
+        store.get(1, 2);
+
+        let metrics = metrics.lock().unwrap();
+        assert_eq!(metrics.store_latency()[0].1.count(), 2);
+        assert_eq!(metrics.store_latency()[1].1.count(), 1);
+    }
+}
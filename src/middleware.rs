@@ -0,0 +1,231 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Error;
+use rust_decimal::Decimal;
+
+use crate::clients::Outcome;
+use crate::events::Event;
+
+/// What a [`Middleware`] decides to do with an event before it is applied.
+#[derive(Debug)]
+pub enum Decision<A = Decimal> {
+    /// Apply the event as it is.
+    Continue,
+    /// Apply this event in its place, such as the event enriched with more details.
+    Replace(Event<A>),
+    /// Reject the event with this error, without applying it.
+    Reject(Error),
+}
+
+/// Intercepts the events applied to a client's account, before and after they are
+/// applied, such as to enrich, filter or rate limit them, or to validate them in ways
+/// the client does not.
+///
+/// Middleware added to a client with [`Client::with_middleware`] runs in the order it
+/// was added, each seeing the event as replaced by the middleware before it. Events
+/// rejected by middleware are rejected by the client as any other, so are recorded to
+/// its audit log, and the middleware after it is not run.
+///
+/// [`Client::with_middleware`]: crate::clients::Client::with_middleware
+///
+/// # Example
+/// ```
+/// use std::sync::{Arc, Mutex};
+///
+/// use anyhow::anyhow;
+/// use payments::clients::{Client, Outcome};
+/// use payments::events::{Event, EventType, Record};
+/// use payments::middleware::{Decision, Middleware};
+/// use payments::storage::MemoryStore;
+/// use rust_decimal_macros::dec;
+///
+/// /// Rejects withdrawals once a client has made `limit` of them.
+/// #[derive(Debug)]
+/// struct WithdrawalLimit {
+///     limit: usize,
+///     made: usize,
+/// }
+///
+/// impl Middleware for WithdrawalLimit {
+///     fn before(&mut self, event: &Event) -> Decision {
+///         match event.kind() {
+///             EventType::Withdrawal(_) if self.made == self.limit => {
+///                 Decision::Reject(anyhow!("more than {} withdrawals", self.limit))
+///             }
+///             _ => Decision::Continue,
+///         }
+///     }
+///
+///     fn after(&mut self, event: &Event, _: &Outcome) {
+///         if let EventType::Withdrawal(_) = event.kind() {
+///             self.made += 1;
+///         }
+///     }
+/// }
+///
+/// let limit = Arc::new(Mutex::new(WithdrawalLimit { limit: 1, made: 0 }));
+/// let mut client = Client::new(1, MemoryStore::new()).with_middleware(limit);
+/// let mut update = |r#type: &str, tx| {
+///     client.update(&Event::try_from(Record {
+///         r#type: r#type.to_string(),
+///         client: 1,
+///         tx,
+///         amount: Some(dec!(1.0)),
+///         to: None,
+///         seq: None,
+///         timestamp: None,
+///         currency: None,
+///     }).unwrap())
+/// };
+///
+/// assert!(update("deposit", 1).is_ok());
+/// assert!(update("deposit", 2).is_ok());
+/// assert!(update("withdrawal", 3).is_ok());
+/// assert!(update("withdrawal", 4).is_err());
+/// ```
+pub trait Middleware<A = Decimal>: Send + fmt::Debug {
+    /// Decides what to do with `event` before it is applied, continuing with it by
+    /// default.
+    fn before(&mut self, _event: &Event<A>) -> Decision<A> {
+        Decision::Continue
+    }
+
+    /// Observes `event` once it has been applied with `outcome`. Events which were
+    /// rejected are not observed.
+    fn after(&mut self, _event: &Event<A>, _outcome: &Outcome<A>) {}
+}
+
+/// Middleware shared by the clients it intercepts the events of.
+pub type SharedMiddleware<A = Decimal> = Arc<Mutex<dyn Middleware<A>>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    use crate::clients::Client;
+    use crate::events::{ClientId, EventType, Record, TxId};
+    use crate::storage::MemoryStore;
+
+    /// Takes a fee of a tenth of every deposit.
+    #[derive(Debug)]
+    struct DepositFee;
+
+    impl Middleware for DepositFee {
+        fn before(&mut self, event: &Event) -> Decision {
+            match event.kind() {
+                EventType::Deposit(_) => {
+                    Decision::Replace(event.clone().map_amount(|amount| amount * dec!(0.9)))
+                }
+                _ => Decision::Continue,
+            }
+        }
+    }
+
+    /// Records the change in total funds of every event applied.
+    #[derive(Debug, Default)]
+    struct Totals(Vec<(TxId, Decimal)>);
+
+    impl Middleware for Totals {
+        fn after(&mut self, event: &Event, outcome: &Outcome) {
+            self.0.push((event.tx(), outcome.total));
+        }
+    }
+
+    fn event(r#type: &str, client: ClientId, tx: TxId, to: Option<ClientId>) -> Event {
+        Event::try_from(Record {
+            r#type: r#type.to_string(),
+            client,
+            tx,
+            amount: Some(dec!(10.0)),
+            to,
+            seq: None,
+            timestamp: None,
+            currency: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_middleware() {
+        let store = MemoryStore::new();
+        let totals = Arc::new(Mutex::new(Totals::default()));
+        let mut alice = Client::new(1, store.clone())
+            .with_middleware(Arc::new(Mutex::new(DepositFee)))
+            .with_middleware(totals.clone());
+        let mut bob = Client::new(2, store).with_middleware(totals.clone());
+
+        // later middleware sees the event as replaced
+        let outcome = alice.update(&event("deposit", 1, 1, None)).unwrap();
+        assert_eq!(outcome.total, dec!(9.0));
+        assert!(alice.update(&event("withdrawal", 1, 2, None)).is_err());
+
+        // both clients' middleware observes a transfer
+        let transfer = event("transfer", 1, 3, Some(2)).map_amount(|_| dec!(4.0));
+        alice.transfer(&mut bob, &transfer).unwrap();
+
+        assert_eq!(alice.available(), dec!(5.0));
+        assert_eq!(
+            totals.lock().unwrap().0,
+            [(1, dec!(9.0)), (3, dec!(-4.0)), (3, dec!(4.0))]
+        );
+    }
+
+    /// Rejects transfers of more than 5.
+    #[cfg(feature = "async")]
+    #[derive(Debug)]
+    struct TransferLimit;
+
+    #[cfg(feature = "async")]
+    impl Middleware for TransferLimit {
+        fn before(&mut self, event: &Event) -> Decision {
+            match event.kind() {
+                EventType::Transfer { amount, .. } if *amount > dec!(5) => {
+                    Decision::Reject(anyhow::anyhow!("transfer over the limit"))
+                }
+                _ => Decision::Continue,
+            }
+        }
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_middleware_async() {
+        use crate::storage::BlockingStore;
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let store = BlockingStore::new(MemoryStore::new());
+            let totals = Arc::new(Mutex::new(Totals::default()));
+            let mut alice = Client::new_async(1, store.clone())
+                .await
+                .with_middleware(Arc::new(Mutex::new(DepositFee)))
+                .with_middleware(Arc::new(Mutex::new(TransferLimit)))
+                .with_middleware(totals.clone());
+            let mut bob = Client::new_async(2, store)
+                .await
+                .with_middleware(totals.clone());
+            let outcome = alice
+                .update_async(&event("deposit", 1, 1, None))
+                .await
+                .unwrap();
+            assert_eq!(outcome.total, dec!(9.0));
+
+            // transfers run through the sending client's middleware, as they do when
+            // applied synchronously
+            let over = event("transfer", 1, 2, Some(2)).map_amount(|_| dec!(6.0));
+            assert!(alice.transfer_async(&mut bob, &over).await.is_err());
+            let transfer = event("transfer", 1, 3, Some(2)).map_amount(|_| dec!(4.0));
+            alice.transfer_async(&mut bob, &transfer).await.unwrap();
+
+            assert_eq!(alice.available(), dec!(5.0));
+            assert_eq!(bob.available(), dec!(4.0));
+            assert_eq!(
+                totals.lock().unwrap().0,
+                [(1, dec!(9.0)), (3, dec!(-4.0)), (3, dec!(4.0))]
+            );
+        });
+    }
+}
@@ -0,0 +1,151 @@
+use anyhow::Error;
+use rust_decimal::Decimal;
+
+use crate::clients::Summary;
+use crate::events::Event;
+
+/// Observes notable changes to client accounts as a [`Pipeline`] applies events, such
+/// as to raise alerts or call webhooks when they happen during a run.
+///
+/// Every callback does nothing by default, so that observers only implement those
+/// they are interested in.
+///
+/// [`Pipeline`]: crate::pipeline::Pipeline
+///
+/// # Example
+/// ```
+/// use payments::clients::Summary;
+/// use payments::events::{Event, Record};
+/// use payments::observer::AccountObserver;
+/// use payments::output::OutputFormat;
+/// use payments::pipeline::Pipeline;
+/// use payments::source::VecSource;
+/// use rust_decimal_macros::dec;
+///
+/// #[derive(Default)]
+/// struct Overdrafts(Vec<u64>);
+///
+/// impl AccountObserver for &mut Overdrafts {
+///     fn on_overdraft_attempt(&mut self, event: &Event, _summary: &Summary) {
+///         self.0.push(event.tx());
+///     }
+/// }
+///
+/// let record = |r#type: &str, tx| Record {
+///     r#type: r#type.to_string(),
+///     client: 1,
+///     tx,
+///     amount: Some(dec!(2.0)),
+///     to: None,
+///     seq: None,
+///     timestamp: None,
+///     currency: None,
+/// };
+/// let source = VecSource::new("queue", vec![record("deposit", 1), record("withdrawal", 2), record("withdrawal", 3)]);
+/// let mut overdrafts = Overdrafts::default();
+/// Pipeline::builder()
+///     .source(source)
+///     .observer(&mut overdrafts)
+///     .sink(OutputFormat::Csv.sink(std::io::sink()))
+///     .build()
+///     .unwrap()
+///     .run()
+///     .unwrap();
+///
+/// assert_eq!(overdrafts.0, [3]);
+/// ```
+pub trait AccountObserver {
+    /// Called when `event` freezes its client's account, leaving it with `summary`.
+    fn on_frozen(&mut self, _event: &Event, _summary: &Summary) {}
+
+    /// Called when `event`, a chargeback, is applied, leaving its client with `summary`.
+    fn on_chargeback(&mut self, _event: &Event, _summary: &Summary) {}
+
+    /// Called when `event`, a withdrawal or transfer, is rejected for exceeding the
+    /// funds available to its client, who has the balances in `summary`.
+    fn on_overdraft_attempt(&mut self, _event: &Event, _summary: &Summary) {}
+
+    /// Called when `event`, a deposit of at least `threshold`, is applied.
+    fn on_large_deposit(&mut self, _event: &Event, _threshold: Decimal) {}
+
+    /// Called when `event` is rejected with `error`, for any reason.
+    fn on_rejected(&mut self, _event: &Event, _error: &Error) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    use crate::events::{Record, TxId};
+    use crate::output::OutputFormat;
+    use crate::pipeline::Pipeline;
+    use crate::source::VecSource;
+
+    /// Records the transaction ids of the events each callback was called with.
+    #[derive(Default)]
+    struct Calls {
+        frozen: Vec<TxId>,
+        large: Vec<TxId>,
+        rejected: Vec<TxId>,
+    }
+
+    impl AccountObserver for &mut Calls {
+        fn on_frozen(&mut self, event: &Event, summary: &Summary) {
+            assert!(summary.locked);
+            self.frozen.push(event.tx());
+        }
+
+        fn on_large_deposit(&mut self, event: &Event, threshold: Decimal) {
+            assert_eq!(threshold, dec!(100.0));
+            self.large.push(event.tx());
+        }
+
+        fn on_rejected(&mut self, event: &Event, _error: &Error) {
+            self.rejected.push(event.tx());
+        }
+    }
+
+    fn record(r#type: &str, tx: TxId, amount: Option<Decimal>) -> Record {
+        Record {
+            r#type: r#type.to_string(),
+            client: 1,
+            tx,
+            amount,
+            to: None,
+            seq: None,
+            timestamp: None,
+            currency: None,
+        }
+    }
+
+    #[test]
+    fn test_observer() {
+        let source = VecSource::new(
+            "queue",
+            vec![
+                record("deposit", 1, Some(dec!(150.0))),
+                record("deposit", 2, Some(dec!(5.0))),
+                record("dispute", 1, None),
+                record("chargeback", 1, None),
+                record("deposit", 3, Some(dec!(100.0))),
+            ],
+        );
+        let mut calls = Calls::default();
+        Pipeline::builder()
+            .source(source)
+            .observer(&mut calls)
+            .large_deposits(dec!(100.0))
+            .sink(OutputFormat::Csv.sink(std::io::sink()))
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+
+        assert_eq!(calls.large, [1]);
+        assert_eq!(calls.frozen, [1]);
+        // deposits to a frozen account are rejected, however large
+        assert_eq!(calls.rejected, [3]);
+    }
+}
@@ -0,0 +1,490 @@
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use rand::Rng;
+use serde_json::{json, Value};
+use tracing::error;
+
+use crate::events::Event;
+use crate::http::{self, Url};
+use crate::metrics::{Histogram, Metrics, SharedMetrics};
+
+/// The name of the instrumentation scope reported with every span and metric.
+const SCOPE: &str = "payments";
+
+/// Returns a random trace or span id, which OTLP requires to be non-zero.
+fn random_id<const N: usize>() -> [u8; N] {
+    let mut id = [0; N];
+    while id == [0; N] {
+        rand::thread_rng().fill(&mut id[..]);
+    }
+    id
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+/// A value attached to a span as an attribute.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AttributeValue {
+    String(String),
+    Int(i64),
+    Bool(bool),
+}
+
+impl From<&str> for AttributeValue {
+    fn from(value: &str) -> AttributeValue {
+        AttributeValue::String(value.to_string())
+    }
+}
+
+impl From<String> for AttributeValue {
+    fn from(value: String) -> AttributeValue {
+        AttributeValue::String(value)
+    }
+}
+
+impl From<u64> for AttributeValue {
+    fn from(value: u64) -> AttributeValue {
+        AttributeValue::Int(value as i64)
+    }
+}
+
+impl From<bool> for AttributeValue {
+    fn from(value: bool) -> AttributeValue {
+        AttributeValue::Bool(value)
+    }
+}
+
+fn attributes_json(attributes: &[(String, AttributeValue)]) -> Value {
+    Value::Array(
+        attributes
+            .iter()
+            .map(|(key, value)| {
+                let value = match value {
+                    AttributeValue::String(v) => json!({ "stringValue": v }),
+                    AttributeValue::Int(v) => json!({ "intValue": v.to_string() }),
+                    AttributeValue::Bool(v) => json!({ "boolValue": v }),
+                };
+                json!({ "key": key, "value": value })
+            })
+            .collect(),
+    )
+}
+
+/// A timed operation which is reported to the collector once ended.
+#[derive(Clone, Debug)]
+pub struct Span {
+    #[doc(hidden)]
+    trace_id: [u8; 16],
+    #[doc(hidden)]
+    span_id: [u8; 8],
+    #[doc(hidden)]
+    parent_id: Option<[u8; 8]>,
+    #[doc(hidden)]
+    name: String,
+    #[doc(hidden)]
+    start: SystemTime,
+    #[doc(hidden)]
+    end: Option<SystemTime>,
+    #[doc(hidden)]
+    attributes: Vec<(String, AttributeValue)>,
+}
+
+impl Span {
+    /// Attaches an attribute to the span, replacing any previous value for `key`.
+    pub fn set_attribute(&mut self, key: &str, value: impl Into<AttributeValue>) {
+        let value = value.into();
+        match self.attributes.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value,
+            None => self.attributes.push((key.to_string(), value)),
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let mut span = json!({
+            "traceId": hex(&self.trace_id),
+            "spanId": hex(&self.span_id),
+            "name": self.name,
+            "kind": 1,
+            "startTimeUnixNano": unix_nanos(self.start),
+            "endTimeUnixNano": unix_nanos(self.end.unwrap_or(self.start)),
+            "attributes": attributes_json(&self.attributes),
+        });
+        if let Some(parent) = self.parent_id {
+            span["parentSpanId"] = json!(hex(&parent));
+        }
+        span
+    }
+}
+
+/// Exports spans and metrics to an OpenTelemetry collector using OTLP over HTTP with
+/// JSON encoding.
+///
+/// Spans are buffered once ended and sent in a single request by
+/// [`OtlpExporter::export_traces`]. Metrics are sent as cumulative values by
+/// [`OtlpExporter::export_metrics`]. Long-lived services, which have no end of run to
+/// export at, can share the exporter with a thread exporting both on an interval with
+/// [`OtlpExporter::export_every`].
+///
+/// # Example
+/// ```no_run
+/// use payments::http::Url;
+/// use payments::metrics::Metrics;
+/// use payments::otel::OtlpExporter;
+///
+/// let exporter = OtlpExporter::new(Url::parse("http://localhost:4318").unwrap(), "payments");
+/// let mut span = exporter.start_span("process", None);
+/// span.set_attribute("file", "example.csv");
+/// exporter.end_span(span);
+///
+/// exporter.export_traces().unwrap();
+/// exporter.export_metrics(&Metrics::default()).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct OtlpExporter {
+    #[doc(hidden)]
+    endpoint: Url,
+    #[doc(hidden)]
+    service_name: String,
+    #[doc(hidden)]
+    started: SystemTime,
+    #[doc(hidden)]
+    ended: Mutex<Vec<Span>>,
+}
+
+impl OtlpExporter {
+    /// Creates an exporter sending to the collector at `endpoint`, identifying this
+    /// process as `service_name`.
+    pub fn new(endpoint: Url, service_name: &str) -> OtlpExporter {
+        OtlpExporter {
+            endpoint,
+            service_name: service_name.to_string(),
+            started: SystemTime::now(),
+            ended: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Starts a new span, as a child of `parent` or else as the root of a new trace.
+    pub fn start_span(&self, name: &str, parent: Option<&Span>) -> Span {
+        Span {
+            trace_id: parent.map_or_else(random_id, |p| p.trace_id),
+            span_id: random_id(),
+            parent_id: parent.map(|p| p.span_id),
+            name: name.to_string(),
+            start: SystemTime::now(),
+            end: None,
+            attributes: Vec::new(),
+        }
+    }
+
+    /// Starts a span for applying `event`, as a child of `parent` or else as the root of
+    /// a new trace, with the event's client, transaction and type as attributes.
+    pub fn start_event_span(&self, event: &Event, parent: Option<&Span>) -> Span {
+        let mut span = self.start_span("apply event", parent);
+        span.set_attribute("client", event.client_id());
+        span.set_attribute("tx", event.tx());
+        span.set_attribute("type", event.kind().name());
+        span
+    }
+
+    /// Ends `span`, buffering it for export.
+    pub fn end_span(&self, mut span: Span) {
+        span.end = Some(SystemTime::now());
+        self.ended.lock().unwrap().push(span);
+    }
+
+    fn resource(&self) -> Value {
+        json!({
+            "attributes": attributes_json(&[(
+                "service.name".to_string(),
+                self.service_name.as_str().into(),
+            )])
+        })
+    }
+
+    /// Returns the OTLP JSON request body for every buffered span.
+    pub fn traces_json(&self) -> Value {
+        Self::spans_json(&self.resource(), &self.ended.lock().unwrap())
+    }
+
+    fn spans_json(resource: &Value, spans: &[Span]) -> Value {
+        json!({
+            "resourceSpans": [{
+                "resource": resource,
+                "scopeSpans": [{
+                    "scope": { "name": SCOPE },
+                    "spans": spans.iter().map(Span::to_json).collect::<Vec<_>>(),
+                }],
+            }],
+        })
+    }
+
+    /// Returns the OTLP JSON request body describing `metrics`.
+    pub fn metrics_json(&self, metrics: &Metrics) -> Value {
+        let start = unix_nanos(self.started);
+        let now = unix_nanos(SystemTime::now());
+        let counter = |name: &str, key: &str, counts: Vec<(String, u64)>| {
+            json!({
+                "name": name,
+                "unit": "1",
+                "sum": {
+                    "aggregationTemporality": 2,
+                    "isMonotonic": true,
+                    "dataPoints": counts.into_iter().map(|(value, count)| json!({
+                        "attributes": attributes_json(&[(key.to_string(), value.into())]),
+                        "startTimeUnixNano": start,
+                        "timeUnixNano": now,
+                        "asInt": count.to_string(),
+                    })).collect::<Vec<_>>(),
+                },
+            })
+        };
+        let histogram = |attributes: Vec<(String, AttributeValue)>, histogram: &Histogram| {
+            // OTLP bucket counts are per bucket rather than cumulative, with a final
+            // bucket for observations above the last bound
+            let mut previous = 0;
+            let mut bounds = Vec::new();
+            let mut counts = Vec::new();
+            for (bound, cumulative) in histogram.buckets() {
+                bounds.push(bound);
+                counts.push((cumulative - previous).to_string());
+                previous = cumulative;
+            }
+            counts.push((histogram.count() - previous).to_string());
+            json!({
+                "attributes": attributes_json(&attributes),
+                "startTimeUnixNano": start,
+                "timeUnixNano": now,
+                "count": histogram.count().to_string(),
+                "sum": histogram.sum(),
+                "bucketCounts": counts,
+                "explicitBounds": bounds,
+            })
+        };
+
+        let processed = metrics
+            .processed_by_type()
+            .iter()
+            .map(|(kind, count)| (kind.to_string(), *count))
+            .collect();
+        let rejected = metrics
+            .rejected_by_reason()
+            .iter()
+            .map(|(reason, count)| (reason.to_string(), *count))
+            .collect();
+        let store_latency: Vec<_> = metrics
+            .store_latency()
+            .iter()
+            .map(|(op, h)| histogram(vec![("op".to_string(), (*op).into())], h))
+            .collect();
+
+        json!({
+            "resourceMetrics": [{
+                "resource": self.resource(),
+                "scopeMetrics": [{
+                    "scope": { "name": SCOPE },
+                    "metrics": [
+                        counter("payments.events.processed", "type", processed),
+                        counter("payments.events.rejected", "reason", rejected),
+                        {
+                            "name": "payments.event.processing.duration",
+                            "unit": "s",
+                            "histogram": {
+                                "aggregationTemporality": 2,
+                                "dataPoints": [histogram(vec![], metrics.processing_latency())],
+                            },
+                        },
+                        {
+                            "name": "payments.ingestion.duration",
+                            "unit": "s",
+                            "histogram": {
+                                "aggregationTemporality": 2,
+                                "dataPoints": [histogram(vec![], metrics.ingestion_latency())],
+                            },
+                        },
+                        {
+                            "name": "payments.store.operation.duration",
+                            "unit": "s",
+                            "histogram": {
+                                "aggregationTemporality": 2,
+                                "dataPoints": store_latency,
+                            },
+                        },
+                        {
+                            "name": "payments.accounts.locked",
+                            "unit": "1",
+                            "gauge": {
+                                "dataPoints": [{
+                                    "timeUnixNano": now,
+                                    "asInt": metrics.locked_accounts().to_string(),
+                                }],
+                            },
+                        },
+                    ],
+                }],
+            }],
+        })
+    }
+
+    /// Sends every buffered span to the collector. Spans which could not be sent are
+    /// kept to be sent with the next export.
+    pub fn export_traces(&self) -> Result<()> {
+        // spans ended while sending are buffered for the next export
+        let spans = mem::take(&mut *self.ended.lock().unwrap());
+        if spans.is_empty() {
+            return Ok(());
+        }
+        let body = serde_json::to_vec(&Self::spans_json(&self.resource(), &spans))?;
+        let sent = http::post(
+            &http::join(&self.endpoint, "v1/traces"),
+            "application/json",
+            &body,
+        );
+        if sent.is_err() {
+            self.ended.lock().unwrap().splice(0..0, spans);
+        }
+        sent
+    }
+
+    /// Sends the current value of every metric to the collector.
+    pub fn export_metrics(&self, metrics: &Metrics) -> Result<()> {
+        let body = serde_json::to_vec(&self.metrics_json(metrics))?;
+        self.post_metrics(&body)
+    }
+
+    fn post_metrics(&self, body: &[u8]) -> Result<()> {
+        http::post(
+            &http::join(&self.endpoint, "v1/metrics"),
+            "application/json",
+            body,
+        )
+    }
+
+    /// Exports the spans ended with the exporter and the current value of `metrics`
+    /// every `interval` on a thread of its own, for as long as the process runs.
+    /// Failed exports are logged, and tried again at the next interval.
+    pub fn export_every(
+        self: Arc<Self>,
+        metrics: SharedMetrics,
+        interval: Duration,
+    ) -> JoinHandle<()> {
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            if let Err(e) = self.export_traces() {
+                error!("exporting traces: {:?}", e);
+            }
+            // the metrics are only locked while encoded, not while they are sent
+            let body = serde_json::to_vec(&self.metrics_json(&metrics.lock().unwrap()));
+            if let Err(e) = body
+                .map_err(Into::into)
+                .and_then(|body| self.post_metrics(&body))
+            {
+                error!("exporting metrics: {:?}", e);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use crate::metrics::RejectKind;
+
+    fn exporter() -> OtlpExporter {
+        OtlpExporter::new(Url::parse("http://localhost:4318").unwrap(), "test")
+    }
+
+    #[test]
+    fn test_span_hierarchy() {
+        let exporter = exporter();
+        let root = exporter.start_span("root", None);
+        let root_trace_id = root.trace_id;
+        let mut child = exporter.start_span("child", Some(&root));
+        child.set_attribute("events", 3u64);
+        child.set_attribute("events", 4u64);
+        exporter.end_span(child);
+        exporter.end_span(root);
+
+        let traces = exporter.traces_json();
+        let spans = &traces["resourceSpans"][0]["scopeSpans"][0]["spans"];
+        assert_eq!(spans[0]["name"], "child");
+        assert_eq!(spans[0]["traceId"], spans[1]["traceId"]);
+        assert_eq!(spans[0]["parentSpanId"], spans[1]["spanId"]);
+        assert!(spans[1].get("parentSpanId").is_none());
+        assert_eq!(
+            spans[0]["attributes"],
+            json!([{ "key": "events", "value": { "intValue": "4" } }])
+        );
+        assert_eq!(spans[0]["traceId"].as_str().unwrap().len(), 32);
+        assert_eq!(spans[0]["spanId"].as_str().unwrap().len(), 16);
+
+        let other = exporter.start_span("other", None);
+        assert_ne!(other.trace_id, root_trace_id);
+        assert_ne!(other.span_id, [0; 8]);
+    }
+
+    #[test]
+    fn test_export_failure() {
+        // nothing listens on the discard port
+        let exporter = OtlpExporter::new(Url::parse("http://127.0.0.1:9").unwrap(), "test");
+        exporter.end_span(exporter.start_span("first", None));
+        assert!(exporter.export_traces().is_err());
+        exporter.end_span(exporter.start_span("second", None));
+
+        // spans which weren't sent are kept, ahead of those ended since
+        let traces = exporter.traces_json();
+        let spans = &traces["resourceSpans"][0]["scopeSpans"][0]["spans"];
+        assert_eq!(spans[0]["name"], "first");
+        assert_eq!(spans[1]["name"], "second");
+    }
+
+    #[test]
+    fn test_metrics() {
+        let mut metrics = Metrics::default();
+        metrics.processed("deposit", Duration::from_micros(2));
+        metrics.processed("deposit", Duration::from_secs(2));
+        metrics.rejected(RejectKind::UnknownTransaction);
+
+        let body = exporter().metrics_json(&metrics);
+        let exported = &body["resourceMetrics"][0]["scopeMetrics"][0]["metrics"];
+        assert_eq!(
+            exported[0]["sum"]["dataPoints"][0]["asInt"],
+            json!("2"),
+            "{}",
+            exported
+        );
+        assert_eq!(
+            exported[1]["sum"]["dataPoints"][0]["attributes"][0]["value"]["stringValue"],
+            "unknown_transaction"
+        );
+
+        let latency = &exported[2]["histogram"]["dataPoints"][0];
+        let counts: Vec<u64> = latency["bucketCounts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| c.as_str().unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(counts.iter().sum::<u64>(), 2);
+        assert_eq!(counts.last(), Some(&1));
+        assert_eq!(
+            counts.len(),
+            latency["explicitBounds"].as_array().unwrap().len() + 1
+        );
+    }
+}
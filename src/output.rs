@@ -0,0 +1,274 @@
+use std::io::Write;
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+use serde_json::{json, Value};
+
+use crate::events::ClientId;
+
+/// The formats reports can be written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// A CSV file with a header row naming the columns.
+    Csv,
+    /// JSON Lines, with a JSON object for each row keyed by column.
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<OutputFormat> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            v => bail!("invalid output format {:?}, expected csv or json", v),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// Returns a sink writing reports to `writer` in this format.
+    pub fn sink<'a, W: Write + 'a>(self, writer: W) -> Box<dyn OutputSink + 'a> {
+        match self {
+            OutputFormat::Csv => Box::new(CsvSink::new(writer)),
+            OutputFormat::Json => Box::new(JsonSink::new(writer)),
+        }
+    }
+}
+
+/// A report being built up row by row, such as the balances of every client, which can
+/// be written to an [`OutputSink`].
+///
+/// # Example
+/// ```
+/// use payments::output::{OutputFormat, Report};
+/// use serde_json::json;
+///
+/// let mut report = Report::new(&["client", "total"]);
+/// report.push_client(2, vec![json!("2"), json!("1.0000")]);
+/// report.push_client(1, vec![json!("1"), json!("3.0000")]);
+/// report.sort_by_client();
+///
+/// let mut out = Vec::new();
+/// report.write(OutputFormat::Csv.sink(&mut out).as_mut()).unwrap();
+/// assert_eq!(String::from_utf8(out).unwrap(), "client,total\n1,3.0000\n2,1.0000\n");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Report {
+    #[doc(hidden)]
+    columns: Vec<String>,
+    #[doc(hidden)]
+    rows: Vec<(Option<ClientId>, Vec<Value>)>,
+    #[doc(hidden)]
+    partial: Option<String>,
+}
+
+impl Report {
+    /// Creates an empty report with the given `columns`.
+    pub fn new(columns: &[&str]) -> Report {
+        Report {
+            columns: columns.iter().map(|column| column.to_string()).collect(),
+            rows: Vec::new(),
+            partial: None,
+        }
+    }
+
+    /// Appends a row with a value for every column.
+    pub fn push(&mut self, row: Vec<Value>) {
+        self.rows.push((None, row));
+    }
+
+    /// Appends a row about the client specified by `client`, with a value for every
+    /// column.
+    pub fn push_client(&mut self, client: ClientId, row: Vec<Value>) {
+        self.rows.push((Some(client), row));
+    }
+
+    /// Orders rows by the id of the client they are about, so that reports are the same
+    /// from one run to the next. Rows about the same client, or about no client, keep
+    /// their order, with rows about no client first.
+    pub fn sort_by_client(&mut self) {
+        self.rows.sort_by_key(|(client, _)| *client);
+    }
+
+    /// Marks the report as covering only part of a run, for `reason`, such as the run
+    /// being interrupted. The mark is written after the rows, so that the report can't
+    /// be mistaken for a whole run's.
+    pub fn set_partial(&mut self, reason: &str) {
+        self.partial = Some(reason.to_string());
+    }
+
+    /// Writes the report to `sink`.
+    pub fn write(&self, sink: &mut dyn OutputSink) -> Result<()> {
+        let columns: Vec<&str> = self.columns.iter().map(String::as_str).collect();
+        let rows: Vec<Vec<Value>> = self.rows.iter().map(|(_, row)| row.clone()).collect();
+        sink.write(&columns, &rows)?;
+        match &self.partial {
+            Some(reason) => sink.write_partial(reason),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Writes reports, such as the balances of every client, as rows of values under named
+/// columns.
+pub trait OutputSink {
+    /// Writes a report with the given `columns`, where each of `rows` holds a value for
+    /// every column, in the same order. Amounts are expected as strings, so that their
+    /// precision is kept exactly, and missing values as nulls.
+    fn write(&mut self, columns: &[&str], rows: &[Vec<Value>]) -> Result<()>;
+
+    /// Marks the report just written as covering only part of a run, for `reason`.
+    /// Does nothing by default, for formats with no way to tell a mark from a row.
+    fn write_partial(&mut self, _reason: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes reports as CSV, with strings written as they are, nulls as empty fields and
+/// other values as their JSON representation.
+pub struct CsvSink<W: Write> {
+    #[doc(hidden)]
+    writer: csv::Writer<W>,
+}
+
+impl<W: Write> CsvSink<W> {
+    /// Creates a sink writing CSV to `writer`.
+    pub fn new(writer: W) -> CsvSink<W> {
+        CsvSink {
+            // flexible so that a partial report's mark can be written as a single field
+            writer: csv::WriterBuilder::new().flexible(true).from_writer(writer),
+        }
+    }
+}
+
+impl<W: Write> OutputSink for CsvSink<W> {
+    fn write(&mut self, columns: &[&str], rows: &[Vec<Value>]) -> Result<()> {
+        self.writer.write_record(columns)?;
+        for row in rows {
+            self.writer
+                .write_record(row.iter().map(|value| match value {
+                    Value::String(s) => s.clone(),
+                    Value::Null => String::new(),
+                    v => v.to_string(),
+                }))?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes a `# partial report: <reason>` comment line.
+    fn write_partial(&mut self, reason: &str) -> Result<()> {
+        self.writer
+            .write_record([format!("# partial report: {}", reason)])?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes reports as JSON Lines, with an object for each row whose keys are the
+/// columns, in the same order.
+pub struct JsonSink<W: Write> {
+    #[doc(hidden)]
+    writer: W,
+}
+
+impl<W: Write> JsonSink<W> {
+    /// Creates a sink writing JSON Lines to `writer`.
+    pub fn new(writer: W) -> JsonSink<W> {
+        JsonSink { writer }
+    }
+}
+
+impl<W: Write> OutputSink for JsonSink<W> {
+    fn write(&mut self, columns: &[&str], rows: &[Vec<Value>]) -> Result<()> {
+        for row in rows {
+            // written by hand rather than through a map, so keys keep the column order
+            let fields: Vec<String> = columns
+                .iter()
+                .zip(row)
+                .map(|(column, value)| format!("{}:{}", Value::from(*column), value))
+                .collect();
+            writeln!(self.writer, "{{{}}}", fields.join(","))?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes a `{"partial": true, "reason": <reason>}` object, which has none of the
+    /// columns of a row.
+    fn write_partial(&mut self, reason: &str) -> Result<()> {
+        writeln!(
+            self.writer,
+            "{}",
+            json!({ "partial": true, "reason": reason })
+        )?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    fn report(format: OutputFormat) -> String {
+        let mut out = Vec::new();
+        let rows = vec![
+            vec![
+                json!("acme, inc"),
+                json!("1.5000"),
+                json!(false),
+                json!(null),
+            ],
+            vec![json!("2"), json!("0.0000"), json!(true), json!(1700000000)],
+        ];
+        format
+            .sink(&mut out)
+            .write(&["client", "available", "locked", "time"], &rows)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_sinks() {
+        assert_eq!(
+            report(OutputFormat::Csv),
+            "client,available,locked,time\n\
+             \"acme, inc\",1.5000,false,\n\
+             2,0.0000,true,1700000000\n"
+        );
+        assert_eq!(
+            report(OutputFormat::Json),
+            "{\"client\":\"acme, inc\",\"available\":\"1.5000\",\"locked\":false,\"time\":null}\n\
+             {\"client\":\"2\",\"available\":\"0.0000\",\"locked\":true,\"time\":1700000000}\n"
+        );
+        assert_eq!(OutputFormat::from_str("json").unwrap(), OutputFormat::Json);
+        assert!(OutputFormat::from_str("xml").is_err());
+    }
+
+    #[test]
+    fn test_partial() {
+        let mut report = Report::new(&["client", "total"]);
+        report.push_client(1, vec![json!("1"), json!("3.0000")]);
+        report.set_partial("interrupted after 2 records");
+        let written = |format: OutputFormat| {
+            let mut out = Vec::new();
+            report.write(format.sink(&mut out).as_mut()).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(
+            written(OutputFormat::Csv),
+            "client,total\n1,3.0000\n# partial report: interrupted after 2 records\n"
+        );
+        assert_eq!(
+            written(OutputFormat::Json),
+            "{\"client\":\"1\",\"total\":\"3.0000\"}\n\
+             {\"partial\":true,\"reason\":\"interrupted after 2 records\"}\n"
+        );
+    }
+}
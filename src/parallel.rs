@@ -0,0 +1,709 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use anyhow::{anyhow, bail, Context, Error, Result};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::clients::{Client, Policy, Summary};
+use crate::events::{ClientId, Currency, Event, EventType, Position, Record, TxId};
+use crate::rules::RuleSet;
+use crate::source::EventSource;
+use crate::storage::{MemoryStore, Pruner, TxStore};
+use crate::wal::WriteAheadLog;
+
+/// The most events read for a worker of [`process_sharded`] which may wait to be
+/// applied by it before reading waits for the worker to catch up.
+pub const WORKER_BACKLOG: usize = 1024;
+
+/// How input files processed concurrently share client accounts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParallelMode {
+    /// Every file's events are applied to one book of accounts.
+    Shared,
+    /// Each file's events are applied to its own book of accounts.
+    Isolated,
+}
+
+impl FromStr for ParallelMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ParallelMode> {
+        match s {
+            "shared" => Ok(ParallelMode::Shared),
+            "isolated" => Ok(ParallelMode::Isolated),
+            v => bail!("invalid parallel mode {:?}, expected shared or isolated", v),
+        }
+    }
+}
+
+/// A book of client accounts along with the transactions applied to them, kept in
+/// memory unless another store is given.
+#[derive(Debug)]
+pub struct Book<T = Arc<Mutex<MemoryStore>>> {
+    #[doc(hidden)]
+    clients: HashMap<ClientId, Client<T>>,
+    #[doc(hidden)]
+    store: T,
+    #[doc(hidden)]
+    policy: Policy,
+    #[doc(hidden)]
+    pruner: Option<Pruner>,
+    #[doc(hidden)]
+    wal: Option<WriteAheadLog>,
+}
+
+impl Default for Book {
+    fn default() -> Book {
+        Book::new(MemoryStore::new(), Policy::default())
+    }
+}
+
+impl<T: TxStore + Clone> Book<T> {
+    /// Creates a book of the client accounts kept in `store`, carrying on from any
+    /// already saved there, which applies events according to `policy`.
+    pub fn new(store: T, policy: Policy) -> Book<T> {
+        let clients = store
+            .clients()
+            .into_iter()
+            .map(|id| (id, Client::new(id, store.clone()).with_policy(policy)))
+            .collect();
+        Book {
+            clients,
+            store,
+            policy,
+            pruner: None,
+            wal: None,
+        }
+    }
+
+    /// Prunes settled transactions from the book's store with `pruner` as events are
+    /// applied.
+    pub fn with_pruner(self, pruner: Pruner) -> Book<T> {
+        Book {
+            pruner: Some(pruner),
+            ..self
+        }
+    }
+
+    /// Logs each event to `wal` before it is applied, along with the writes applying it
+    /// makes to the book's store, which must be wrapped with [`WriteAheadLog::wrap`].
+    pub fn with_wal(self, wal: WriteAheadLog) -> Book<T> {
+        Book {
+            wal: Some(wal),
+            ..self
+        }
+    }
+
+    /// Applies `event` to its client's account if it passes `rules`, returning the
+    /// client's balances afterwards. Transfers are applied to the accounts of both
+    /// clients. Fails without applying the event if it could not be logged to the
+    /// book's write-ahead log, if it has one.
+    pub fn apply(&mut self, event: &Event, rules: &RuleSet) -> Result<Summary> {
+        if let Some(wal) = self.wal.as_mut() {
+            wal.begin(event).context("writing write-ahead log")?;
+        }
+        let applied = self.apply_unlogged(event, rules);
+        if let Some(wal) = self.wal.as_mut() {
+            wal.applied().context("writing write-ahead log")?;
+        }
+        applied
+    }
+
+    fn apply_unlogged(&mut self, event: &Event, rules: &RuleSet) -> Result<Summary> {
+        let id = event.client_id();
+        let to = match event.kind() {
+            EventType::Transfer { to, .. } => Some(*to),
+            _ => None,
+        };
+        for id in [Some(id), to].into_iter().flatten() {
+            self.clients
+                .entry(id)
+                .or_insert_with(|| Client::new(id, self.store.clone()).with_policy(self.policy));
+        }
+        let applied = match to {
+            // such as between members of the same joint account
+            Some(to) if to == id => Err(anyhow!("cannot transfer to the same client")),
+            Some(to) => match self.clients.get_disjoint_mut([&id, &to]) {
+                [Some(client), Some(to)] => rules
+                    .check(event, &client.summary())
+                    .and_then(|_| client.transfer(to, event).map(drop)),
+                _ => unreachable!("both clients were just added"),
+            },
+            None => {
+                let client = self.clients.get_mut(&id).unwrap();
+                rules
+                    .check(event, &client.summary())
+                    .and_then(|_| client.update(event).map(drop))
+            }
+        };
+        applied.with_context(|| format!("processing {:?}", event))?;
+        if let Some(pruner) = self.pruner.as_mut() {
+            // failing to prune doesn't fail the event, which was already applied
+            if let Err(e) = pruner.observe(&mut self.store, event.timestamp()) {
+                error!("pruning settled transactions: {:?}", e);
+            }
+        }
+        Ok(self.clients[&id].summary())
+    }
+
+    /// Returns the balances of the client specified by `id`, if it has an account.
+    pub fn summary(&self, id: ClientId) -> Option<Summary> {
+        self.clients.get(&id).map(Client::summary)
+    }
+
+    /// Returns the balances of the client specified by `id` in `currency`, or in the base
+    /// currency if `None`, if it has an account.
+    pub fn summary_in(&self, id: ClientId, currency: Option<Currency>) -> Option<Summary> {
+        self.clients
+            .get(&id)
+            .map(|client| client.summary_in(currency))
+    }
+
+    /// Returns the balances of every client in the book in each currency it holds,
+    /// ordered by client id and then currency.
+    pub fn summaries(&self) -> Vec<Summary> {
+        let mut summaries: Vec<Summary> =
+            self.clients.values().flat_map(Client::summaries).collect();
+        summaries.sort_by_key(|summary| (summary.id, summary.currency));
+        summaries
+    }
+
+    /// Merges the clients of `other`, such as a book of another partition of the input,
+    /// into the book with [`Client::merge`], adding those it doesn't have yet.
+    pub fn merge<U>(&mut self, other: Book<U>) -> Result<()> {
+        for (id, client) in other.clients {
+            self.clients
+                .entry(id)
+                .or_insert_with(|| Client::new(id, self.store.clone()).with_policy(self.policy))
+                .merge(client)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the entries of `source`, applying the events parsed from them to `book`.
+fn apply_all(
+    source: impl Iterator<Item = Result<Record>>,
+    book: &Mutex<Book>,
+    rules: &RuleSet,
+    parse: impl Fn(Result<Record>) -> Result<Event>,
+) {
+    for entry in source {
+        // parse outside of the lock, so that only applying events is serialized
+        match parse(entry) {
+            Ok(event) => {
+                let _span = event.span().entered();
+                if let Err(e) = book.lock().unwrap().apply(&event, rules) {
+                    error!("{:?}", e);
+                }
+            }
+            Err(e) => error!("{:?}", e),
+        }
+    }
+}
+
+/// Processes each of `sources` on its own thread, parsing entries into events with
+/// `parse`. Invalid entries and rejected events are logged.
+///
+/// In [`ParallelMode::Shared`] mode a single book is returned, with the events of
+/// every source applied to it in no particular order between sources, so sources
+/// should not depend on each other's events. In [`ParallelMode::Isolated`] mode a book
+/// is returned for each source, in the same order.
+///
+/// # Example
+/// ```
+/// use payments::events::{Event, Record};
+/// use payments::parallel::{process, ParallelMode};
+/// use payments::rules::RuleSet;
+/// use rust_decimal_macros::dec;
+///
+/// let deposit = |client| Record {
+///     r#type: "deposit".to_string(),
+///     client,
+///     tx: client,
+///     amount: Some(dec!(1.0)),
+///     to: None,
+///     seq: None,
+///     timestamp: None,
+///     currency: None,
+/// };
+/// let sources = vec![vec![Ok(deposit(1))].into_iter(), vec![Ok(deposit(2))].into_iter()];
+/// let parse = |entry: anyhow::Result<Record>| entry.and_then(Event::try_from);
+///
+/// let books = process(ParallelMode::Isolated, sources, &RuleSet::default(), parse);
+/// assert_eq!(books.len(), 2);
+/// assert_eq!(books[1].summaries()[0].id, 2);
+/// ```
+pub fn process<I, F>(mode: ParallelMode, sources: Vec<I>, rules: &RuleSet, parse: F) -> Vec<Book>
+where
+    I: Iterator<Item = Result<Record>> + Send,
+    F: Fn(Result<Record>) -> Result<Event> + Sync,
+{
+    let parse = &parse;
+    match mode {
+        ParallelMode::Shared => {
+            let book = Mutex::new(Book::default());
+            thread::scope(|scope| {
+                for source in sources {
+                    let book = &book;
+                    scope.spawn(move || apply_all(source, book, rules, parse));
+                }
+            });
+            vec![book.into_inner().unwrap()]
+        }
+        ParallelMode::Isolated => thread::scope(|scope| {
+            let handles: Vec<_> = sources
+                .into_iter()
+                .map(|source| {
+                    scope.spawn(move || {
+                        let book = Mutex::new(Book::default());
+                        apply_all(source, &book, rules, parse);
+                        book.into_inner().unwrap()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect()
+        }),
+    }
+}
+
+/// Applies the events of `sources`, read in turn and parsed with `parse` on the calling
+/// thread, across `workers` threads, each applying events according to `policy` to its
+/// own book of the clients whose id modulo `workers` is its index. Invalid entries and
+/// rejected events are logged. Returns the balances of every client, ordered by client
+/// id.
+///
+/// Events are applied in order for each client, but clients are otherwise independent
+/// of each other, so transaction ids only need to be unique within a worker's book, and
+/// transfers between clients of different workers are rejected.
+///
+/// Input is only read up to [`WORKER_BACKLOG`] events ahead of those a worker has
+/// applied, so that a worker falling behind holds up reading rather than the events
+/// read for it piling up in memory.
+///
+/// # Example
+/// ```
+/// use payments::clients::Policy;
+/// use payments::events::{Event, Record};
+/// use payments::parallel::process_sharded;
+/// use payments::rules::RuleSet;
+/// use rust_decimal_macros::dec;
+///
+/// let deposit = |client| Record {
+///     r#type: "deposit".to_string(),
+///     client,
+///     tx: client,
+///     amount: Some(dec!(1.0)),
+///     to: None,
+///     seq: None,
+///     timestamp: None,
+///     currency: None,
+/// };
+/// let sources = vec![(1..=10).map(|client| Ok(deposit(client)))];
+/// let parse = |entry: anyhow::Result<Record>| entry.and_then(Event::try_from);
+///
+/// let summaries = process_sharded(4, sources, &RuleSet::default(), Policy::default(), parse);
+/// assert_eq!(summaries.len(), 10);
+/// assert_eq!(summaries[9].total, dec!(1.0));
+/// ```
+pub fn process_sharded<I, F>(
+    workers: usize,
+    sources: Vec<I>,
+    rules: &RuleSet,
+    policy: Policy,
+    parse: F,
+) -> Vec<Summary>
+where
+    I: Iterator<Item = Result<Record>>,
+    F: Fn(Result<Record>) -> Result<Event>,
+{
+    assert!(workers > 0, "at least one worker is needed");
+    thread::scope(|scope| {
+        let (senders, handles): (Vec<_>, Vec<_>) = (0..workers)
+            .map(|_| {
+                let (sender, receiver) = mpsc::sync_channel::<Event>(WORKER_BACKLOG);
+                let handle = scope.spawn(move || {
+                    let mut book = Book::new(MemoryStore::new(), policy);
+                    for event in receiver {
+                        let _span = event.span().entered();
+                        if let Err(e) = book.apply(&event, rules) {
+                            error!("{:?}", e);
+                        }
+                    }
+                    book.summaries()
+                });
+                (sender, handle)
+            })
+            .unzip();
+        let shard = |id: ClientId| (id % workers as ClientId) as usize;
+        for entry in sources.into_iter().flatten() {
+            let event = parse(entry).and_then(|event| match event.kind() {
+                EventType::Transfer { to, .. } if shard(*to) != shard(event.client_id()) => {
+                    Err(anyhow!("transfers between workers are not supported"))
+                        .with_context(|| format!("processing {:?}", event))
+                }
+                _ => Ok(event),
+            });
+            match event {
+                // a worker only stops receiving if it panicked, which the join reports
+                Ok(event) => drop(senders[shard(event.client_id())].send(event)),
+                Err(e) => error!("{:?}", e),
+            }
+        }
+        drop(senders);
+        let mut summaries: Vec<Summary> = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect();
+        summaries.sort_by_key(|summary| summary.id);
+        summaries
+    })
+}
+
+/// Processes the entries of `sources` in two phases, for inputs too large to apply
+/// on one thread in good time. Entries are first read in turn, parsed into events with
+/// `parse` and written to one of `partitions` temporary files, by their client id
+/// modulo `partitions`. Then every partition is processed at once, each on its own
+/// thread applying its events to its own book according to `policy`, and the books are
+/// merged with [`Book::merge`]. Invalid entries and rejected events are logged, with
+/// the name of the source and the line they were read from. Returns the balances of
+/// every client, ordered by client id and then currency.
+///
+/// Events are applied in order for each client, but clients are otherwise independent
+/// of each other, so transfers between clients of different partitions are rejected.
+/// Transaction ids are unique across partitions: a new transaction is rejected when
+/// the client of another partition stored one under its id first, even if that one
+/// was rejected itself.
+///
+/// # Example
+/// ```
+/// use payments::clients::Policy;
+/// use payments::events::{Event, Record};
+/// use payments::parallel::process_partitioned;
+/// use payments::rules::RuleSet;
+/// use payments::source::VecSource;
+/// use rust_decimal_macros::dec;
+///
+/// let deposit = |client| Record {
+///     r#type: "deposit".to_string(),
+///     client,
+///     tx: client,
+///     amount: Some(dec!(1.0)),
+///     to: None,
+///     seq: None,
+///     timestamp: None,
+///     currency: None,
+/// };
+/// let sources = vec![VecSource::new("batch.csv", (1..=10).map(deposit).collect())];
+/// let parse = |entry: anyhow::Result<Record>| entry.and_then(Event::try_from);
+///
+/// let summaries =
+///     process_partitioned(4, sources, &RuleSet::default(), Policy::default(), parse).unwrap();
+/// assert_eq!(summaries.len(), 10);
+/// assert_eq!(summaries[9].total, dec!(1.0));
+/// ```
+pub fn process_partitioned<I, F>(
+    partitions: usize,
+    sources: Vec<I>,
+    rules: &RuleSet,
+    policy: Policy,
+    parse: F,
+) -> Result<Vec<Summary>>
+where
+    I: EventSource,
+    F: Fn(Result<Record>) -> Result<Event>,
+{
+    assert!(partitions > 0, "at least one partition is needed");
+    let dir = env::temp_dir().join(format!(
+        "partitions-{}-{}",
+        std::process::id(),
+        PARTITIONED_RUNS.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+    let processed = partition(&dir, partitions, sources, parse).and_then(|paths| {
+        let books = thread::scope(|scope| {
+            let handles: Vec<_> = paths
+                .iter()
+                .map(|path| {
+                    scope.spawn(move || -> Result<Book> {
+                        let file = File::open(path)
+                            .with_context(|| format!("opening {}", path.display()))?;
+                        let mut book = Book::new(MemoryStore::new(), policy);
+                        for line in BufReader::new(file).lines() {
+                            let partitioned: Partitioned = serde_json::from_str(&line?)
+                                .with_context(|| format!("reading {}", path.display()))?;
+                            let event = Event::try_from(partitioned.record)?
+                                .with_position(partitioned.position);
+                            let _span = event.span().entered();
+                            if let Err(e) = book.apply(&event, rules) {
+                                error!("{:?}", e);
+                            }
+                        }
+                        Ok(book)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<Result<Vec<_>>>()
+        })?;
+        let mut merged = Book::new(MemoryStore::new(), policy);
+        for book in books {
+            merged.merge(book)?;
+        }
+        Ok(merged.summaries())
+    });
+    if let Err(e) = fs::remove_dir_all(&dir) {
+        error!("removing {}: {:?}", dir.display(), e);
+    }
+    processed
+}
+
+/// Counts the runs of [`process_partitioned`], so that concurrent runs write their
+/// partitions to directories of their own.
+static PARTITIONED_RUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// An event written to a partition, along with where its record was read from.
+#[derive(Serialize, Deserialize)]
+struct Partitioned {
+    record: Record,
+    position: Position,
+}
+
+/// Writes the events parsed with `parse` from the records of `sources` to `partitions`
+/// JSON Lines files in `dir`, by their client id modulo `partitions`, returning the
+/// paths of the files. Invalid entries, transfers between partitions and new
+/// transactions reusing the id of one in another partition are logged.
+fn partition<I, F>(dir: &Path, partitions: usize, sources: Vec<I>, parse: F) -> Result<Vec<PathBuf>>
+where
+    I: EventSource,
+    F: Fn(Result<Record>) -> Result<Event>,
+{
+    let paths: Vec<PathBuf> = (0..partitions)
+        .map(|i| dir.join(format!("{}.jsonl", i)))
+        .collect();
+    let mut writers = paths
+        .iter()
+        .map(|path| {
+            let file =
+                File::create(path).with_context(|| format!("creating {}", path.display()))?;
+            Ok(BufWriter::new(file))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let shard = |id: ClientId| (id % partitions as ClientId) as usize;
+    // the partition which stored a transaction under each id first
+    let mut claimed: HashMap<TxId, usize> = HashMap::new();
+    for source in sources {
+        let (name, header_lines) = (source.name().to_string(), source.header_lines());
+        for (read, entry) in (1..).zip(source) {
+            let line = read + header_lines;
+            let position = match &entry {
+                Ok(record) => Position {
+                    source: name.clone(),
+                    line,
+                    record: record.to_string(),
+                },
+                Err(e) => {
+                    error!("{} line {}: {:?}", name, line, e);
+                    continue;
+                }
+            };
+            let event = match parse(entry) {
+                Ok(event) => event.with_position(position.clone()),
+                Err(e) => {
+                    error!("{}: {:?}", position, e);
+                    continue;
+                }
+            };
+            let i = shard(event.client_id());
+            let checked = match event.kind() {
+                EventType::Transfer { to, .. } if shard(*to) != i => {
+                    Err(anyhow!("transfers between partitions are not supported"))
+                }
+                kind if kind.is_new_transaction() => match claimed.entry(event.tx()) {
+                    Entry::Occupied(claimed) if *claimed.get() != i => {
+                        Err(anyhow!("cannot overwrite existing transaction"))
+                    }
+                    claimed => {
+                        claimed.or_insert(i);
+                        Ok(())
+                    }
+                },
+                _ => Ok(()),
+            };
+            if let Err(e) = checked.with_context(|| format!("processing {:?}", event)) {
+                let _span = event.span().entered();
+                error!("{:?}", e);
+                continue;
+            }
+            let partitioned = Partitioned {
+                record: Record::from(&event),
+                position,
+            };
+            serde_json::to_writer(&mut writers[i], &partitioned)?;
+            writers[i].write_all(b"\n")?;
+        }
+    }
+    for writer in &mut writers {
+        writer.flush()?;
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use crate::events::TxId;
+
+    fn record(t: &str, client: ClientId, tx: TxId, amount: Option<Decimal>) -> Result<Record> {
+        Ok(Record {
+            r#type: t.to_string(),
+            client,
+            tx,
+            amount,
+            to: None,
+            seq: None,
+            timestamp: None,
+            currency: None,
+        })
+    }
+
+    fn sources() -> Vec<std::vec::IntoIter<Result<Record>>> {
+        let a: Vec<_> = (1..=100)
+            .map(|tx| record("deposit", 1, tx, Some(dec!(1.0))))
+            .collect();
+        let b: Vec<_> = (101..=200)
+            .map(|tx| record("deposit", 1, tx, Some(dec!(2.0))))
+            .chain([record("deposit", 2, 1, Some(dec!(1.0)))])
+            .collect();
+        vec![a.into_iter(), b.into_iter()]
+    }
+
+    fn parse(entry: Result<Record>) -> Result<Event> {
+        Event::try_from(entry?)
+    }
+
+    #[test]
+    fn test_shared() {
+        let books = process(ParallelMode::Shared, sources(), &RuleSet::default(), parse);
+        assert_eq!(books.len(), 1);
+        let summaries = books[0].summaries();
+        assert_eq!(summaries[0].total, dec!(300.0));
+        // client 2's deposit reuses a transaction id already in the shared book
+        assert_eq!(summaries[1].total, dec!(0.0));
+    }
+
+    #[test]
+    fn test_isolated() {
+        let books = process(
+            ParallelMode::Isolated,
+            sources(),
+            &RuleSet::default(),
+            parse,
+        );
+        assert_eq!(books.len(), 2);
+        assert_eq!(books[0].summaries()[0].total, dec!(100.0));
+        let summaries = books[1].summaries();
+        assert_eq!(summaries[0].total, dec!(200.0));
+        // transaction ids only need to be unique within a book
+        assert_eq!(summaries[1].id, 2);
+        assert_eq!(summaries[1].total, dec!(1.0));
+        assert_eq!(
+            ParallelMode::from_str("shared").unwrap(),
+            ParallelMode::Shared
+        );
+        assert!(ParallelMode::from_str("both").is_err());
+    }
+
+    #[test]
+    fn test_sharded() {
+        let mut transfer = record("transfer", 1, 201, Some(dec!(50.0)));
+        transfer.as_mut().unwrap().to = Some(3);
+        let mut crossing = record("transfer", 1, 202, Some(dec!(50.0)));
+        crossing.as_mut().unwrap().to = Some(2);
+        let late = vec![
+            transfer,
+            crossing,
+            record("withdrawal", 1, 203, Some(dec!(250.0))),
+        ];
+        let mut sources = sources();
+        sources.push(late.into_iter());
+        let summaries = process_sharded(2, sources, &RuleSet::default(), Policy::default(), parse);
+        assert_eq!(summaries.len(), 3);
+        // client 1's events are applied in order, on its worker
+        assert_eq!(summaries[0].total, dec!(0.0));
+        // client 2 has its own worker, so its transaction id doesn't clash with client 1's
+        assert_eq!(summaries[1].total, dec!(1.0));
+        // the transfer to client 3 stays on client 1's worker, unlike the one to client 2
+        assert_eq!(summaries[2].total, dec!(50.0));
+    }
+
+    /// A source of entries, some of which may be unreadable.
+    struct Entries(std::vec::IntoIter<Result<Record>>);
+
+    impl Iterator for Entries {
+        type Item = Result<Record>;
+
+        fn next(&mut self) -> Option<Result<Record>> {
+            self.0.next()
+        }
+    }
+
+    impl EventSource for Entries {
+        fn name(&self) -> &str {
+            "entries.csv"
+        }
+    }
+
+    #[test]
+    fn test_partitioned() {
+        let mut transfer = record("transfer", 1, 201, Some(dec!(50.0)));
+        transfer.as_mut().unwrap().to = Some(3);
+        let mut crossing = record("transfer", 1, 202, Some(dec!(50.0)));
+        crossing.as_mut().unwrap().to = Some(2);
+        let mut euros = record("deposit", 4, 401, Some(dec!(5.0)));
+        euros.as_mut().unwrap().currency = Some("EUR".parse().unwrap());
+        let late = vec![
+            transfer,
+            crossing,
+            record("deposit", 3, 301, Some(dec!(10.0))),
+            record("dispute", 3, 301, None),
+            record("chargeback", 3, 301, None),
+            euros,
+            record("deposit", 4, 402, Some(dec!(1.0))),
+            Err(anyhow!("unreadable")),
+        ];
+        let mut sources: Vec<Entries> = sources().into_iter().map(Entries).collect();
+        sources.push(Entries(late.into_iter()));
+        let summaries =
+            process_partitioned(2, sources, &RuleSet::default(), Policy::default(), parse).unwrap();
+        // client 2's only deposit reuses a transaction id claimed in client 1's partition
+        assert_eq!(summaries.len(), 4);
+        // the transfer to client 2 crossed partitions, unlike the one to client 3
+        assert_eq!(summaries[0].total, dec!(250.0));
+        assert_eq!(summaries[1].id, 3);
+        assert_eq!(summaries[1].total, dec!(50.0));
+        assert!(summaries[1].locked);
+        assert_eq!(summaries[2].total, dec!(1.0));
+        assert_eq!(summaries[3].currency, Some("EUR".parse().unwrap()));
+        assert_eq!(summaries[3].total, dec!(5.0));
+    }
+}
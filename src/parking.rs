@@ -0,0 +1,195 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::events::{ClientId, Event, EventType, TxId};
+
+/// Parks disputes, resolutions, chargebacks and representments of transactions which
+/// have not arrived yet, for feeds which are only approximately ordered.
+///
+/// Parked events are released, in the order they were parked, as soon as the
+/// transaction they reference arrives. Those whose transaction hasn't arrived within
+/// `window` further events are released anyway, to be rejected as they would have been
+/// without parking.
+///
+/// # Example
+/// ```
+/// use payments::events::{Event, Record};
+/// use payments::parking::DisputeParking;
+/// use rust_decimal_macros::dec;
+///
+/// let record = |r#type: &str, amount| Record {
+///     r#type: r#type.to_string(),
+///     client: 1,
+///     tx: 7,
+///     amount,
+///     to: None,
+///     seq: None,
+///     timestamp: None,
+///     currency: None,
+/// };
+/// let dispute = Event::try_from(record("dispute", None)).unwrap();
+/// let deposit = Event::try_from(record("deposit", Some(dec!(5)))).unwrap();
+///
+/// let mut parking = DisputeParking::new(100);
+/// assert!(DisputeParking::parks(&dispute));
+/// parking.park(dispute);
+///
+/// // once the deposit is applied, its dispute can be retried
+/// assert!(parking.advance().is_empty());
+/// let retried = parking.release(deposit.client_id(), deposit.tx());
+/// assert_eq!(retried.len(), 1);
+/// assert!(parking.is_empty());
+/// ```
+#[derive(Debug)]
+pub struct DisputeParking {
+    #[doc(hidden)]
+    window: u64,
+    #[doc(hidden)]
+    parked: HashMap<(ClientId, TxId), (u64, Vec<Event>)>,
+    #[doc(hidden)]
+    deadlines: VecDeque<(u64, ClientId, TxId)>,
+    #[doc(hidden)]
+    seen: u64,
+}
+
+impl DisputeParking {
+    /// Creates a parking lot holding events for up to `window` further events.
+    pub fn new(window: u64) -> DisputeParking {
+        DisputeParking {
+            window,
+            parked: HashMap::new(),
+            deadlines: VecDeque::new(),
+            seen: 0,
+        }
+    }
+
+    /// Returns whether `event` is of a type which is parked while its transaction is
+    /// unknown.
+    pub fn parks(event: &Event) -> bool {
+        matches!(
+            event.kind(),
+            EventType::Dispute(_)
+                | EventType::Resolve
+                | EventType::Chargeback
+                | EventType::Representment
+        )
+    }
+
+    /// Returns whether events referencing the transaction `tx` of `client` are parked,
+    /// in which case later events referencing it should be parked behind them.
+    pub fn is_parked(&self, client: ClientId, tx: TxId) -> bool {
+        self.parked.contains_key(&(client, tx))
+    }
+
+    /// Returns whether no events are parked.
+    pub fn is_empty(&self) -> bool {
+        self.parked.is_empty()
+    }
+
+    /// Parks `event` until its transaction arrives or its window passes.
+    pub fn park(&mut self, event: Event) {
+        let key = (event.client_id(), event.tx());
+        let deadline = self.seen + self.window;
+        let (_, events) = self.parked.entry(key).or_insert_with(|| {
+            self.deadlines.push_back((deadline, key.0, key.1));
+            (deadline, Vec::new())
+        });
+        events.push(event);
+    }
+
+    /// Returns the events parked for the transaction `tx` of `client`, which has just
+    /// arrived, in the order they were parked.
+    pub fn release(&mut self, client: ClientId, tx: TxId) -> Vec<Event> {
+        // the deadline is left queued, and skipped once it passes
+        self.parked
+            .remove(&(client, tx))
+            .map(|(_, events)| events)
+            .unwrap_or_default()
+    }
+
+    /// Counts one more event, returning the parked events whose window has passed
+    /// without their transaction arriving.
+    pub fn advance(&mut self) -> Vec<Event> {
+        self.seen += 1;
+        let mut expired = Vec::new();
+        while let Some(&(deadline, client, tx)) = self.deadlines.front() {
+            if deadline > self.seen {
+                break;
+            }
+            self.deadlines.pop_front();
+            if let Some((parked_at, events)) = self.parked.remove(&(client, tx)) {
+                if parked_at == deadline {
+                    expired.extend(events);
+                } else {
+                    // released and parked again since, so still within its window
+                    self.parked.insert((client, tx), (parked_at, events));
+                }
+            }
+        }
+        expired
+    }
+
+    /// Releases every parked event, in the order their transactions were first parked.
+    pub fn drain(&mut self) -> Vec<Event> {
+        let mut released = Vec::new();
+        for (_, client, tx) in self.deadlines.drain(..) {
+            if let Some((_, events)) = self.parked.remove(&(client, tx)) {
+                released.extend(events);
+            }
+        }
+        released
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::events::Record;
+
+    fn event(r#type: &str, tx: TxId) -> Event {
+        Event::try_from(Record {
+            r#type: r#type.to_string(),
+            client: 1,
+            tx,
+            amount: None,
+            to: None,
+            seq: None,
+            timestamp: None,
+            currency: None,
+        })
+        .unwrap()
+    }
+
+    fn kinds(events: Vec<Event>) -> Vec<(&'static str, TxId)> {
+        events.iter().map(|e| (e.kind().name(), e.tx())).collect()
+    }
+
+    #[test]
+    fn test_dispute_parking() {
+        let mut parking = DisputeParking::new(2);
+        assert!(!DisputeParking::parks(&event("unlock", 1)));
+
+        parking.park(event("dispute", 1));
+        parking.park(event("resolve", 1));
+        assert!(parking.advance().is_empty());
+        parking.park(event("chargeback", 2));
+        assert!(parking.is_parked(1, 2));
+        assert_eq!(
+            kinds(parking.release(1, 1)),
+            vec![("dispute", 1), ("resolve", 1)]
+        );
+        assert!(parking.release(1, 1).is_empty());
+
+        // a transaction parked again after its release gets a fresh window
+        parking.park(event("dispute", 1));
+        assert!(parking.advance().is_empty());
+        assert_eq!(
+            kinds(parking.advance()),
+            vec![("chargeback", 2), ("dispute", 1)]
+        );
+        parking.park(event("dispute", 3));
+
+        assert_eq!(kinds(parking.drain()), vec![("dispute", 3)]);
+        assert!(parking.is_empty());
+    }
+}
@@ -0,0 +1,393 @@
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use anyhow::{bail, Error, Result};
+use rust_decimal::Decimal;
+use serde_json::{json, Value};
+use tracing::error;
+
+use crate::clients::{Policy, Summary};
+use crate::events::{format_amount, Event, EventType};
+use crate::metrics::{RejectKind, SharedMetrics};
+use crate::observer::AccountObserver;
+use crate::output::{OutputSink, Report};
+use crate::parallel::Book;
+use crate::rules::RuleSet;
+use crate::source::EventSource;
+use crate::stats::RunStats;
+use crate::storage::{MemoryStore, TxStore};
+
+/// The columns of the report of client balances written by a pipeline.
+const BALANCE_COLUMNS: [&str; 5] = ["client", "available", "held", "total", "locked"];
+
+/// The processing engine wired up end to end: the records of its sources are applied
+/// to client accounts kept in its store, and the balances of every client written to
+/// its sink once they are all read, as the command line does with its inputs.
+///
+/// Records which are not valid events, and events which are rejected, are logged and
+/// counted, and do not stop the run. Notable changes to accounts along the way are
+/// reported to the pipeline's [`AccountObserver`]s, and every event is recorded to its
+/// metrics, if it has any.
+///
+/// # Example
+/// ```
+/// use payments::output::OutputFormat;
+/// use payments::pipeline::Pipeline;
+/// use payments::source::FileSource;
+/// # use payments::aliases::ClientAliases;
+/// # use payments::input::{Compression, CsvDialect, InputFormat};
+/// # let aliases = ClientAliases::default();
+/// # let csv = "type,client,tx,amount\ndeposit,1,1,2.0\nwithdrawal,1,2,0.5\n";
+/// # let source = FileSource::new("events.csv", csv.as_bytes(), InputFormat::Csv, Compression::None, CsvDialect::default(), &aliases).unwrap();
+///
+/// let mut out = Vec::new();
+/// let pipeline = Pipeline::builder().source(source).sink(OutputFormat::Csv.sink(&mut out)).build().unwrap();
+/// let stats = pipeline.run().unwrap();
+///
+/// assert_eq!(stats.processed["deposit"], 1);
+/// assert_eq!(
+///     String::from_utf8(out).unwrap(),
+///     "client,available,held,total,locked\n1,1.5000,0.0000,1.5000,false\n",
+/// );
+/// ```
+pub struct Pipeline<'a, T = Arc<Mutex<MemoryStore>>> {
+    #[doc(hidden)]
+    sources: Vec<Box<dyn EventSource + 'a>>,
+    #[doc(hidden)]
+    book: Book<T>,
+    #[doc(hidden)]
+    rules: RuleSet,
+    #[doc(hidden)]
+    sink: Box<dyn OutputSink + 'a>,
+    #[doc(hidden)]
+    observers: Vec<Box<dyn AccountObserver + 'a>>,
+    #[doc(hidden)]
+    large_deposits: Option<Decimal>,
+    #[doc(hidden)]
+    metrics: Option<SharedMetrics>,
+}
+
+impl<'a> Pipeline<'a> {
+    /// Starts building a pipeline, which keeps client accounts in memory unless given
+    /// another store.
+    pub fn builder() -> PipelineBuilder<'a> {
+        PipelineBuilder {
+            sources: Vec::new(),
+            store: MemoryStore::new(),
+            policy: Policy::default(),
+            rules: RuleSet::default(),
+            sink: None,
+            observers: Vec::new(),
+            large_deposits: None,
+            metrics: None,
+        }
+    }
+}
+
+impl<T: TxStore + Clone> Pipeline<'_, T> {
+    /// Reads every source in turn, applying the events read from them, then writes the
+    /// balances of every client to the sink, ordered by client. Returns the statistics
+    /// of the run, or an error if the balances could not be written.
+    pub fn run(mut self) -> Result<RunStats> {
+        let mut stats = RunStats::default();
+        for mut source in mem::take(&mut self.sources) {
+            let name = source.name().to_string();
+            let header_lines = source.header_lines();
+            for (i, entry) in source.by_ref().enumerate() {
+                let line = header_lines + i as u64 + 1;
+                let event = match entry.and_then(Event::try_from) {
+                    Ok(event) => event,
+                    Err(e) => {
+                        error!("{}:{}: {:?}", name, line, e);
+                        stats.rejected(None);
+                        if let Some(metrics) = &self.metrics {
+                            metrics.lock().unwrap().rejected(RejectKind::InvalidRecord);
+                        }
+                        continue;
+                    }
+                };
+                let _span = event.span().entered();
+                let before = self
+                    .book
+                    .summary_in(event.client_id(), event.currency())
+                    .unwrap_or(Summary {
+                        id: event.client_id(),
+                        currency: event.currency(),
+                        ..Default::default()
+                    });
+                let start = Instant::now();
+                match self.book.apply(&event, &self.rules) {
+                    Ok(summary) => {
+                        stats.processed(event.kind().name());
+                        if let Some(metrics) = &self.metrics {
+                            let mut metrics = metrics.lock().unwrap();
+                            metrics.processed(event.kind().name(), start.elapsed());
+                            metrics.account(&summary);
+                        }
+                        self.applied(&event, &before, &summary);
+                    }
+                    Err(e) => {
+                        error!("{}:{}: {:?}", name, line, e);
+                        stats.rejected(Some(event.kind().name()));
+                        if let Some(metrics) = &self.metrics {
+                            metrics.lock().unwrap().rejected(RejectKind::of(&e));
+                        }
+                        self.rejected(&event, &before, &e);
+                    }
+                }
+            }
+        }
+        let summaries = self.book.summaries();
+        stats.accounts(&summaries);
+        balances(&summaries).write(self.sink.as_mut())?;
+        Ok(stats)
+    }
+
+    /// Reports `event` to the observers once applied, given its client's balances
+    /// `before` and `after`.
+    fn applied(&mut self, event: &Event, before: &Summary, after: &Summary) {
+        for observer in &mut self.observers {
+            if after.locked && !before.locked {
+                observer.on_frozen(event, after);
+            }
+            if let EventType::Chargeback = event.kind() {
+                observer.on_chargeback(event, after);
+            }
+            if let (EventType::Deposit(amount), Some(threshold)) =
+                (event.kind(), self.large_deposits)
+            {
+                if *amount >= threshold {
+                    observer.on_large_deposit(event, threshold);
+                }
+            }
+        }
+    }
+
+    /// Reports `event` to the observers once rejected with `error`, given its client's
+    /// balances `before` in the currency of the event.
+    fn rejected(&mut self, event: &Event, before: &Summary, error: &Error) {
+        let overdraft = match event.kind() {
+            EventType::Withdrawal(amount) | EventType::Transfer { amount, .. } => {
+                *amount > before.available
+            }
+            _ => false,
+        };
+        for observer in &mut self.observers {
+            observer.on_rejected(event, error);
+            if overdraft {
+                observer.on_overdraft_attempt(event, before);
+            }
+        }
+    }
+}
+
+/// Returns a report of the balances in `summaries`, with a `currency` column after the
+/// client once any balances are in a currency other than the base currency.
+fn balances(summaries: &[Summary]) -> Report {
+    let mut columns = BALANCE_COLUMNS.to_vec();
+    let currencies = summaries.iter().any(|summary| summary.currency.is_some());
+    if currencies {
+        columns.insert(1, "currency");
+    }
+    let mut report = Report::new(&columns);
+    for summary in summaries {
+        let mut row: Vec<Value> = vec![
+            json!(summary.id.to_string()),
+            json!(format_amount(summary.available)),
+            json!(format_amount(summary.held)),
+            json!(format_amount(summary.total)),
+            json!(summary.locked),
+        ];
+        if currencies {
+            row.insert(1, json!(summary.currency.map(|c| c.to_string())));
+        }
+        report.push_client(summary.id, row);
+    }
+    report
+}
+
+/// Builds a [`Pipeline`] from its sources, store and sink.
+pub struct PipelineBuilder<'a, T = Arc<Mutex<MemoryStore>>> {
+    #[doc(hidden)]
+    sources: Vec<Box<dyn EventSource + 'a>>,
+    #[doc(hidden)]
+    store: T,
+    #[doc(hidden)]
+    policy: Policy,
+    #[doc(hidden)]
+    rules: RuleSet,
+    #[doc(hidden)]
+    sink: Option<Box<dyn OutputSink + 'a>>,
+    #[doc(hidden)]
+    observers: Vec<Box<dyn AccountObserver + 'a>>,
+    #[doc(hidden)]
+    large_deposits: Option<Decimal>,
+    #[doc(hidden)]
+    metrics: Option<SharedMetrics>,
+}
+
+impl<'a, T: TxStore + Clone> PipelineBuilder<'a, T> {
+    /// Adds `source` to be read, after any sources already added.
+    pub fn source(mut self, source: impl EventSource + 'a) -> PipelineBuilder<'a, T> {
+        self.sources.push(Box::new(source));
+        self
+    }
+
+    /// Keeps client accounts in `store`, carrying on from any already saved there.
+    pub fn store<U: TxStore + Clone>(self, store: U) -> PipelineBuilder<'a, U> {
+        PipelineBuilder {
+            sources: self.sources,
+            store,
+            policy: self.policy,
+            rules: self.rules,
+            sink: self.sink,
+            observers: self.observers,
+            large_deposits: self.large_deposits,
+            metrics: self.metrics,
+        }
+    }
+
+    /// Applies events according to `policy`.
+    pub fn policy(self, policy: Policy) -> PipelineBuilder<'a, T> {
+        PipelineBuilder { policy, ..self }
+    }
+
+    /// Rejects events which do not pass `rules`.
+    pub fn rules(self, rules: RuleSet) -> PipelineBuilder<'a, T> {
+        PipelineBuilder { rules, ..self }
+    }
+
+    /// Writes the balances of every client to `sink` at the end of the run.
+    pub fn sink(self, sink: Box<dyn OutputSink + 'a>) -> PipelineBuilder<'a, T> {
+        PipelineBuilder {
+            sink: Some(sink),
+            ..self
+        }
+    }
+
+    /// Reports notable changes to accounts to `observer`, after any observers already
+    /// added.
+    pub fn observer(mut self, observer: impl AccountObserver + 'a) -> PipelineBuilder<'a, T> {
+        self.observers.push(Box::new(observer));
+        self
+    }
+
+    /// Reports deposits of at least `threshold` to the observers as large deposits.
+    pub fn large_deposits(self, threshold: Decimal) -> PipelineBuilder<'a, T> {
+        PipelineBuilder {
+            large_deposits: Some(threshold),
+            ..self
+        }
+    }
+
+    /// Records the events applied and rejected, how long each took to apply and the
+    /// number of active clients and frozen accounts to `metrics`, such as to serve them
+    /// to Prometheus.
+    pub fn metrics(self, metrics: SharedMetrics) -> PipelineBuilder<'a, T> {
+        PipelineBuilder {
+            metrics: Some(metrics),
+            ..self
+        }
+    }
+
+    /// Builds the pipeline, which needs at least one source and a sink.
+    pub fn build(self) -> Result<Pipeline<'a, T>> {
+        if self.sources.is_empty() {
+            bail!("a pipeline needs at least one source");
+        }
+        let Some(sink) = self.sink else {
+            bail!("a pipeline needs a sink to write balances to");
+        };
+        Ok(Pipeline {
+            sources: self.sources,
+            book: Book::new(self.store, self.policy),
+            rules: self.rules,
+            sink,
+            observers: self.observers,
+            large_deposits: self.large_deposits,
+            metrics: self.metrics,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    use crate::events::{ClientId, Record, TxId};
+    use crate::output::OutputFormat;
+    use crate::source::VecSource;
+    use crate::storage::ClientStore;
+
+    fn record(r#type: &str, client: ClientId, tx: TxId, amount: Option<&str>) -> Record {
+        Record {
+            r#type: r#type.to_string(),
+            client,
+            tx,
+            amount: amount.map(|a| a.parse().unwrap()),
+            to: None,
+            seq: None,
+            timestamp: None,
+            currency: None,
+        }
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let store = MemoryStore::new();
+        let first = VecSource::new(
+            "first",
+            vec![
+                record("deposit", 2, 1, Some("3.0")),
+                record("withdrawal", 2, 2, Some("5.0")),
+            ],
+        );
+        let second = VecSource::new(
+            "second",
+            vec![
+                record("deposit", 1, 3, Some("1.25")),
+                record("dispute", 2, 1, None),
+                record("deposit", 1, 4, None),
+            ],
+        );
+        let mut out = Vec::new();
+        let metrics = SharedMetrics::default();
+        let stats = Pipeline::builder()
+            .source(first)
+            .source(second)
+            .store(Arc::clone(&store))
+            .metrics(Arc::clone(&metrics))
+            .sink(OutputFormat::Json.sink(&mut out))
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+
+        assert_eq!(stats.processed["deposit"], 2);
+        assert_eq!(stats.processed["dispute"], 1);
+        assert_eq!(stats.rejected["withdrawal"], 1);
+        assert_eq!(stats.invalid, 1);
+        assert_eq!(stats.total, dec!(4.25));
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            concat!(
+                r#"{"client":"1","available":"1.2500","held":"0.0000","total":"1.2500","locked":false}"#,
+                "\n",
+                r#"{"client":"2","available":"0.0000","held":"3.0000","total":"3.0000","locked":false}"#,
+                "\n",
+            )
+        );
+        let metrics = metrics.lock().unwrap();
+        assert_eq!(metrics.processed_by_type()["deposit"], 2);
+        assert_eq!(metrics.rejected_by_reason()["invalid_record"], 1);
+        assert_eq!(metrics.active_clients(), 2);
+        // the store given is the one written to
+        assert_eq!(store.clients().len(), 2);
+
+        let missing = Pipeline::builder().sink(OutputFormat::Csv.sink(Vec::new()));
+        assert!(missing.build().is_err());
+    }
+}
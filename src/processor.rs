@@ -0,0 +1,885 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Error, Result};
+use tracing::{error, info, warn};
+
+use crate::alerts::Alerter;
+use crate::audit::{AuditEntry, SharedAuditLog};
+use crate::checkpoint::Checkpoint;
+use crate::clients::{Client, Policy, Summary};
+use crate::deadletter::{DeadLetter, DeadLetterSink};
+use crate::disputes::DisputeExpiry;
+use crate::events::{ClientId, Currency, Event, EventType, Record, RoundingPolicy, TxId};
+use crate::hierarchy::AccountHierarchy;
+use crate::joint::JointAccounts;
+use crate::metrics::{RejectKind, SharedMetrics, TimedStore};
+use crate::otel::{OtlpExporter, Span};
+use crate::parking::DisputeParking;
+use crate::rejects::{Reject, RejectsWriter};
+use crate::rules::RuleSet;
+use crate::script::{Decision, ScriptHook};
+use crate::stats::RunStats;
+use crate::statsd::StatsdEmitter;
+use crate::storage::{
+    Account, BloomStore, ClientStore, CompactStore, MemoryStore, PostgresStore, Pruner, SledStore,
+    SpillStore, TxState, TxStore,
+};
+use crate::wal::{LoggedStore, WriteAheadLog};
+
+/// The transaction store client accounts are kept in.
+#[derive(Clone, Debug)]
+pub(crate) enum Backend {
+    Memory(Arc<Mutex<MemoryStore>>),
+    Sled(SledStore),
+    Postgres(PostgresStore),
+    Spill(SpillStore),
+    Compact(CompactStore),
+    Bloom(Box<BloomStore<Backend>>),
+    Logged(Box<LoggedStore<Backend>>),
+}
+
+impl Backend {
+    /// Writes any transactions and balances not yet written to a sled store to disk.
+    pub(crate) fn flush(&self) -> Result<()> {
+        match self {
+            Backend::Sled(store) => store.flush(),
+            Backend::Bloom(store) => store.inner().flush(),
+            Backend::Logged(store) => store.inner().flush(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns a checkpoint of the store for the `--wal` to be truncated to, or `None`
+    /// once a store which persists itself has written every change to disk.
+    pub(crate) fn snapshot(&self) -> Result<Option<Checkpoint>> {
+        match self {
+            Backend::Memory(store) => Ok(Some(Checkpoint::capture(&store.lock().unwrap()))),
+            Backend::Spill(_) | Backend::Compact(_) => bail!("the store can't be snapshotted"),
+            Backend::Bloom(store) => store.inner().snapshot(),
+            Backend::Logged(store) => store.inner().snapshot(),
+            store => store.flush().map(|_| None),
+        }
+    }
+}
+
+impl Default for Backend {
+    fn default() -> Backend {
+        Backend::Memory(MemoryStore::new())
+    }
+}
+
+impl ClientStore for Backend {
+    fn account(&self, client_id: ClientId) -> Option<Account> {
+        match self {
+            Backend::Memory(store) => store.account(client_id),
+            Backend::Sled(store) => store.account(client_id),
+            Backend::Postgres(store) => store.account(client_id),
+            Backend::Spill(store) => store.account(client_id),
+            Backend::Compact(store) => store.account(client_id),
+            Backend::Bloom(store) => store.account(client_id),
+            Backend::Logged(store) => store.account(client_id),
+        }
+    }
+
+    fn save_account(&mut self, client_id: ClientId, account: Account) -> Result<()> {
+        match self {
+            Backend::Memory(store) => store.save_account(client_id, account),
+            Backend::Sled(store) => store.save_account(client_id, account),
+            Backend::Postgres(store) => store.save_account(client_id, account),
+            Backend::Spill(store) => store.save_account(client_id, account),
+            Backend::Compact(store) => store.save_account(client_id, account),
+            Backend::Bloom(store) => store.save_account(client_id, account),
+            Backend::Logged(store) => store.save_account(client_id, account),
+        }
+    }
+
+    fn clients(&self) -> Vec<ClientId> {
+        match self {
+            Backend::Memory(store) => store.clients(),
+            Backend::Sled(store) => store.clients(),
+            Backend::Postgres(store) => store.clients(),
+            Backend::Spill(store) => store.clients(),
+            Backend::Compact(store) => store.clients(),
+            Backend::Bloom(store) => store.clients(),
+            Backend::Logged(store) => store.clients(),
+        }
+    }
+}
+
+impl TxStore for Backend {
+    fn get(&self, client_id: ClientId, tx_id: TxId) -> Option<TxState> {
+        match self {
+            Backend::Memory(store) => store.get(client_id, tx_id),
+            Backend::Sled(store) => store.get(client_id, tx_id),
+            Backend::Postgres(store) => store.get(client_id, tx_id),
+            Backend::Spill(store) => store.get(client_id, tx_id),
+            Backend::Compact(store) => store.get(client_id, tx_id),
+            Backend::Bloom(store) => store.get(client_id, tx_id),
+            Backend::Logged(store) => store.get(client_id, tx_id),
+        }
+    }
+
+    fn upsert(&mut self, client_id: ClientId, tx_id: TxId, tx: TxState) -> Result<()> {
+        match self {
+            Backend::Memory(store) => store.upsert(client_id, tx_id, tx),
+            Backend::Sled(store) => store.upsert(client_id, tx_id, tx),
+            Backend::Postgres(store) => store.upsert(client_id, tx_id, tx),
+            Backend::Spill(store) => store.upsert(client_id, tx_id, tx),
+            Backend::Compact(store) => store.upsert(client_id, tx_id, tx),
+            Backend::Bloom(store) => store.upsert(client_id, tx_id, tx),
+            Backend::Logged(store) => store.upsert(client_id, tx_id, tx),
+        }
+    }
+
+    fn list(&self, client_id: ClientId) -> impl Iterator<Item = (TxId, TxState)> {
+        let transactions: Vec<_> = match self {
+            Backend::Memory(store) => store.list(client_id).collect(),
+            Backend::Sled(store) => store.list(client_id).collect(),
+            Backend::Postgres(store) => store.list(client_id).collect(),
+            Backend::Spill(store) => store.list(client_id).collect(),
+            Backend::Compact(store) => store.list(client_id).collect(),
+            Backend::Bloom(store) => store.list(client_id).collect(),
+            Backend::Logged(store) => store.list(client_id).collect(),
+        };
+        transactions.into_iter()
+    }
+
+    fn tx_ids(&self) -> impl Iterator<Item = TxId> {
+        let tx_ids: Vec<_> = match self {
+            Backend::Memory(store) => store.tx_ids().collect(),
+            Backend::Sled(store) => store.tx_ids().collect(),
+            Backend::Postgres(store) => store.tx_ids().collect(),
+            Backend::Spill(store) => store.tx_ids().collect(),
+            Backend::Compact(store) => store.tx_ids().collect(),
+            Backend::Bloom(store) => store.tx_ids().collect(),
+            Backend::Logged(store) => store.tx_ids().collect(),
+        };
+        tx_ids.into_iter()
+    }
+
+    fn currency(&self, tx_id: TxId) -> Option<Currency> {
+        match self {
+            Backend::Memory(store) => store.currency(tx_id),
+            Backend::Sled(store) => store.currency(tx_id),
+            Backend::Postgres(store) => store.currency(tx_id),
+            Backend::Spill(store) => store.currency(tx_id),
+            Backend::Compact(store) => store.currency(tx_id),
+            Backend::Bloom(store) => store.currency(tx_id),
+            Backend::Logged(store) => store.currency(tx_id),
+        }
+    }
+
+    fn set_currency(&mut self, tx_id: TxId, currency: Currency) -> Result<()> {
+        match self {
+            Backend::Memory(store) => store.set_currency(tx_id, currency),
+            Backend::Sled(store) => store.set_currency(tx_id, currency),
+            Backend::Postgres(store) => store.set_currency(tx_id, currency),
+            Backend::Spill(store) => store.set_currency(tx_id, currency),
+            Backend::Compact(store) => store.set_currency(tx_id, currency),
+            Backend::Bloom(store) => store.set_currency(tx_id, currency),
+            Backend::Logged(store) => store.set_currency(tx_id, currency),
+        }
+    }
+
+    fn timestamp(&self, tx_id: TxId) -> Option<u64> {
+        match self {
+            Backend::Memory(store) => store.timestamp(tx_id),
+            Backend::Sled(store) => store.timestamp(tx_id),
+            Backend::Postgres(store) => store.timestamp(tx_id),
+            Backend::Spill(store) => store.timestamp(tx_id),
+            Backend::Compact(store) => store.timestamp(tx_id),
+            Backend::Bloom(store) => store.timestamp(tx_id),
+            Backend::Logged(store) => store.timestamp(tx_id),
+        }
+    }
+
+    fn set_timestamp(&mut self, tx_id: TxId, timestamp: u64) -> Result<()> {
+        match self {
+            Backend::Memory(store) => store.set_timestamp(tx_id, timestamp),
+            Backend::Sled(store) => store.set_timestamp(tx_id, timestamp),
+            Backend::Postgres(store) => store.set_timestamp(tx_id, timestamp),
+            Backend::Spill(store) => store.set_timestamp(tx_id, timestamp),
+            Backend::Compact(store) => store.set_timestamp(tx_id, timestamp),
+            Backend::Bloom(store) => store.set_timestamp(tx_id, timestamp),
+            Backend::Logged(store) => store.set_timestamp(tx_id, timestamp),
+        }
+    }
+
+    fn prune(&mut self, before: u64) -> Result<usize> {
+        match self {
+            Backend::Memory(store) => store.prune(before),
+            Backend::Sled(store) => store.prune(before),
+            Backend::Postgres(store) => store.prune(before),
+            Backend::Spill(store) => store.prune(before),
+            Backend::Compact(store) => store.prune(before),
+            Backend::Bloom(store) => store.prune(before),
+            Backend::Logged(store) => store.prune(before),
+        }
+    }
+
+    fn set_pruned(&mut self, tx_ids: RangeInclusive<TxId>) -> Result<()> {
+        match self {
+            Backend::Memory(store) => store.set_pruned(tx_ids),
+            Backend::Sled(store) => store.set_pruned(tx_ids),
+            Backend::Postgres(store) => store.set_pruned(tx_ids),
+            Backend::Spill(store) => store.set_pruned(tx_ids),
+            Backend::Compact(store) => store.set_pruned(tx_ids),
+            Backend::Bloom(store) => store.set_pruned(tx_ids),
+            Backend::Logged(store) => store.set_pruned(tx_ids),
+        }
+    }
+}
+
+pub(crate) type Store = TimedStore<Backend>;
+
+/// The number of records covered by each batch span.
+const TRACE_BATCH_SIZE: u64 = 10_000;
+
+/// Spans being recorded for the current run.
+pub(crate) struct Tracing {
+    pub(crate) exporter: Arc<OtlpExporter>,
+    /// The span of the whole run, which services have none of, as they don't end.
+    pub(crate) root: Option<Span>,
+    batch: Option<(Span, u64)>,
+    sample: u64,
+    seen: u64,
+}
+
+impl Tracing {
+    pub(crate) fn new(exporter: OtlpExporter, sample: u64) -> Tracing {
+        let root = exporter.start_span("process", None);
+        Tracing {
+            exporter: Arc::new(exporter),
+            root: Some(root),
+            batch: None,
+            sample,
+            seen: 0,
+        }
+    }
+
+    /// Counts a record read in the current batch, ending the batch and exporting its
+    /// spans once it is full.
+    pub(crate) fn record_read(&mut self) {
+        let (_, records) = self.batch.get_or_insert_with(|| {
+            (
+                self.exporter
+                    .start_span("process batch", self.root.as_ref()),
+                0,
+            )
+        });
+        *records += 1;
+        if *records >= TRACE_BATCH_SIZE {
+            self.end_batch();
+        }
+    }
+
+    pub(crate) fn end_batch(&mut self) {
+        if let Some((mut span, records)) = self.batch.take() {
+            span.set_attribute("records", records);
+            self.exporter.end_span(span);
+            if let Err(e) = self.exporter.export_traces() {
+                error!("exporting traces: {:?}", e);
+            }
+        }
+    }
+
+    /// Starts a span for applying `event` if it is selected by sampling.
+    fn start_event_span(&mut self, event: &Event) -> Option<Span> {
+        self.seen += 1;
+        if self.sample == 0 || !self.seen.is_multiple_of(self.sample) {
+            return None;
+        }
+        let parent = self.batch.as_ref().map(|(span, _)| span);
+        Some(
+            self.exporter
+                .start_event_span(event, parent.or(self.root.as_ref())),
+        )
+    }
+}
+
+/// Everything observing the outcome of each event.
+#[derive(Default)]
+pub(crate) struct Telemetry {
+    pub(crate) metrics: SharedMetrics,
+    pub(crate) statsd: Option<StatsdEmitter>,
+    pub(crate) tracing: Option<Tracing>,
+    pub(crate) alerts: Option<Alerter>,
+    pub(crate) stats: RunStats,
+}
+
+impl Telemetry {
+    fn processed(&mut self, event: &Event, summary: &Summary, elapsed: Duration) {
+        self.stats.processed(event.kind().name());
+        let mut metrics = self.metrics.lock().unwrap();
+        metrics.processed(event.kind().name(), elapsed);
+        metrics.account(summary);
+        drop(metrics);
+        if let Some(statsd) = self.statsd.as_mut() {
+            statsd.processed(event.kind().name(), elapsed);
+        }
+        if let Some(alerts) = self.alerts.as_mut() {
+            alerts.applied(summary);
+        }
+    }
+
+    /// Counts an event of type `kind` being rejected for an error of kind `reason`, or
+    /// an invalid record if `None`.
+    fn rejected(&mut self, kind: Option<&'static str>, reason: RejectKind) {
+        self.stats.rejected(kind);
+        self.metrics.lock().unwrap().rejected(reason);
+        if let Some(statsd) = self.statsd.as_mut() {
+            statsd.rejected(reason);
+        }
+        if let Some(alerts) = self.alerts.as_mut() {
+            alerts.rejected();
+        }
+    }
+}
+
+/// Describes processing `event`, for the context of errors, along with where its
+/// record was read from if known.
+fn processing(event: &Event) -> String {
+    match event.position() {
+        Some(position) => format!("processing {:?} at {}", event, position),
+        None => format!("processing {:?}", event),
+    }
+}
+
+pub(crate) fn parse_entry(
+    entry: Result<Record>,
+    legacy_tx_ids: bool,
+    rounding: RoundingPolicy,
+) -> Result<Event> {
+    let record = entry?;
+    let event = Event::from_record(record, rounding)?;
+    if legacy_tx_ids && u32::try_from(event.tx()).is_err() {
+        bail!(
+            "transaction id {} exceeds the legacy 32-bit range",
+            event.tx()
+        );
+    }
+    Ok(event)
+}
+
+/// The state needed to apply events to client accounts.
+pub(crate) struct Processor {
+    pub(crate) clients: HashMap<ClientId, Client<Store>>,
+    pub(crate) store: Backend,
+    pub(crate) rules: RuleSet,
+    pub(crate) script: Option<ScriptHook>,
+    pub(crate) hierarchy: AccountHierarchy,
+    pub(crate) policy: Policy,
+    pub(crate) telemetry: Telemetry,
+    pub(crate) rejects: Option<RejectsWriter<File>>,
+    pub(crate) dead_letters: Option<Box<dyn DeadLetterSink>>,
+    pub(crate) audit: Option<SharedAuditLog>,
+    pub(crate) parking: Option<DisputeParking>,
+    pub(crate) expiry: Option<DisputeExpiry>,
+    pub(crate) pruner: Option<Pruner>,
+    pub(crate) wal: Option<WriteAheadLog>,
+    pub(crate) strict: bool,
+    pub(crate) verify: bool,
+}
+
+impl Processor {
+    /// Returns a processor applying events to the accounts in `store` according to
+    /// `policy` once they pass `rules`, with none of a run's optional stages.
+    pub(crate) fn new(store: Backend, rules: RuleSet, policy: Policy) -> Processor {
+        Processor {
+            clients: HashMap::new(),
+            store,
+            rules,
+            script: None,
+            hierarchy: AccountHierarchy::default(),
+            policy,
+            telemetry: Telemetry::default(),
+            rejects: None,
+            dead_letters: None,
+            audit: None,
+            parking: None,
+            expiry: None,
+            pruner: None,
+            wal: None,
+            strict: false,
+            verify: false,
+        }
+    }
+
+    fn summary(&self, client: ClientId) -> Summary {
+        self.clients.get(&client).map_or_else(
+            || Summary {
+                id: client,
+                ..Default::default()
+            },
+            Client::summary,
+        )
+    }
+
+    /// Carries on from the client accounts and transactions saved in `checkpoint`.
+    pub(crate) fn restore(&mut self, checkpoint: &Checkpoint) -> Result<()> {
+        checkpoint.restore(&mut self.store)?;
+        self.resume();
+        Ok(())
+    }
+
+    /// Saves a checkpoint of the client accounts and transactions in the store to
+    /// `path`, once `records` input records have been processed. Failing to save one is
+    /// logged, leaving the previous checkpoint in place.
+    pub(crate) fn checkpoint(&self, path: &str, records: u64) {
+        if let Backend::Memory(store) = &self.store {
+            let checkpoint = Checkpoint::capture(&store.lock().unwrap()).with_records(records);
+            if let Err(e) = checkpoint.save(path) {
+                error!("writing checkpoint {}: {:?}", path, e);
+            }
+        }
+    }
+
+    /// Carries on from the client accounts already saved in the store, including
+    /// clients with no events in this run.
+    pub(crate) fn resume(&mut self) {
+        for id in self.store.clients() {
+            let client = self.new_client(id);
+            self.clients.insert(id, client);
+        }
+    }
+
+    /// Returns a new client for `id`, applying events according to the run's policy
+    /// and recording them to the `--audit-log`, if there is one.
+    fn new_client(&self, id: ClientId) -> Client<Store> {
+        let store = TimedStore::new(self.store.clone(), Arc::clone(&self.telemetry.metrics));
+        let client = Client::new(id, store).with_policy(self.policy);
+        match &self.audit {
+            Some(log) => client.with_audit_log(Arc::clone(log)),
+            None => client,
+        }
+    }
+
+    /// Records `event` being rejected for `e` before reaching its client to the
+    /// `--audit-log`, if there is one, returning `e`.
+    fn refuse(&self, event: &Event, e: Error) -> Error {
+        if let Some(log) = &self.audit {
+            let entry = AuditEntry::rejected(event, &self.summary(event.client_id()), &e);
+            if let Err(e) = log.lock().unwrap().record(entry) {
+                error!("writing audit log: {:?}", e);
+            }
+        }
+        e
+    }
+
+    /// Writes `reject` to the `--rejects` file, if there is one.
+    fn write_reject(&mut self, reject: impl FnOnce() -> Reject) {
+        if let Some(rejects) = self.rejects.as_mut() {
+            if let Err(e) = rejects.write(&reject()) {
+                error!("writing rejects: {:?}", e);
+            }
+        }
+    }
+
+    /// Handles an entry which was not a valid record, its `record` if it could be read,
+    /// being rejected for `e`. The `location` it was read from is logged along with it,
+    /// if known.
+    pub(crate) fn reject_invalid(
+        &mut self,
+        record: Option<&Record>,
+        e: &Error,
+        location: Option<&str>,
+    ) {
+        self.telemetry.rejected(None, RejectKind::InvalidRecord);
+        self.write_reject(|| match record {
+            Some(record) => Reject::record(record, e),
+            None => Reject::unreadable(e),
+        });
+        match location {
+            Some(location) => error!("{}: {:?}", location, e),
+            None => error!("{:?}", e),
+        }
+        self.discarded();
+    }
+
+    /// Returns whether the record at `position` of the input `source` is yet to be
+    /// processed, rather than logged to the write-ahead log before a crash.
+    pub(crate) fn read(&mut self, source: &str, position: u64) -> bool {
+        self.wal
+            .as_mut()
+            .is_none_or(|wal| wal.read(source, position))
+    }
+
+    /// Logs that the input `source` was read to its end to the write-ahead log, if
+    /// there is one, so that another input of the same name is read from its start.
+    pub(crate) fn finished(&mut self, source: &str) -> Result<()> {
+        match self.wal.as_mut() {
+            Some(wal) => wal.finished(source),
+            None => Ok(()),
+        }
+    }
+
+    /// Logs that the record last read was discarded without an event being applied to
+    /// the write-ahead log, if there is one.
+    pub(crate) fn discarded(&mut self) {
+        if let Some(wal) = self.wal.as_mut() {
+            if let Err(e) = wal.discarded() {
+                eprintln!("error: writing write-ahead log: {:?}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    fn run_script(&self, script: &ScriptHook, event: &Event) -> Result<Event> {
+        match script.decide(event, &self.summary(event.client_id()))? {
+            Decision::Allow => Ok(event.clone()),
+            Decision::Deny(reason) => bail!("denied by script: {}", reason),
+            Decision::Transform(event) => Ok(event),
+        }
+    }
+
+    fn apply_event(&mut self, event: &Event) -> Result<Summary> {
+        let id = event.client_id();
+        let to = match event.kind() {
+            EventType::Transfer { to, .. } => Some(*to),
+            _ => None,
+        };
+        // a transfer whose destination resolves to the account it is sent from, such as
+        // between members of one joint account, is rejected
+        if to == Some(id) {
+            let e = anyhow!("cannot transfer to the same client");
+            return Err(self.refuse(event, e)).with_context(|| processing(event));
+        }
+        for id in [Some(id), to].into_iter().flatten() {
+            if let Some(parent) = self
+                .hierarchy
+                .ancestors(id)
+                .filter_map(|parent| self.clients.get(&parent))
+                .find(|parent| parent.locked())
+            {
+                let e = anyhow!("parent account {} is locked", parent.id());
+                return Err(self.refuse(event, e)).with_context(|| processing(event));
+            }
+        }
+        if let Err(e) = self.rules.check(event, &self.summary(id)) {
+            return Err(self.refuse(event, e)).with_context(|| processing(event));
+        }
+
+        for id in [Some(id), to].into_iter().flatten() {
+            if !self.clients.contains_key(&id) {
+                let client = self.new_client(id);
+                self.clients.insert(id, client);
+            }
+        }
+        let applied = match to {
+            Some(to) => match self.clients.get_disjoint_mut([&id, &to]) {
+                [Some(client), Some(to)] => client.transfer(to, event).map(drop),
+                _ => unreachable!("both clients were just added"),
+            },
+            None => self.clients.get_mut(&id).unwrap().update(event).map(drop),
+        };
+        applied.with_context(|| processing(event))?;
+        if self.verify {
+            for id in [Some(id), to].into_iter().flatten() {
+                if let Err(e) = self.clients[&id].verify_invariants() {
+                    // reported even without --verbose, as a bug rather than a bad event
+                    eprintln!("error: balances drifted processing {:?}: {}", event, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Ok(self.clients[&id].summary())
+    }
+
+    /// Applies `events` in turn, stopping at the first rejected event with its error in
+    /// `--strict` mode.
+    pub(crate) fn apply_events(
+        &mut self,
+        events: Vec<Event>,
+        on_applied: &mut dyn FnMut(&Event, Summary),
+    ) -> Result<()> {
+        for event in events {
+            let mut due = Vec::new();
+            match self.parking.as_mut() {
+                Some(parking) => {
+                    // events parked for too long are applied first, to be rejected
+                    due.extend(parking.advance());
+                    let (client, tx) = (event.client_id(), event.tx());
+                    if DisputeParking::parks(&event)
+                        && (parking.is_parked(client, tx) || self.store.get(client, tx).is_none())
+                    {
+                        warn!("parking {:?} until its transaction arrives", event);
+                        parking.park(event);
+                    } else {
+                        due.push(event);
+                    }
+                }
+                None => due.push(event),
+            }
+            // events parked for a transaction are applied as soon as it arrives
+            let mut i = 0;
+            while let Some(event) = due.get(i).cloned() {
+                i += 1;
+                match self.process(event.clone(), on_applied) {
+                    Ok(()) => {
+                        if let Some(parking) = self.parking.as_mut() {
+                            due.extend(parking.release(event.client_id(), event.tx()));
+                        }
+                    }
+                    Err(e) if self.strict => return Err(e),
+                    Err(_) => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the events still parked for transactions which never arrived, so that
+    /// they are rejected.
+    pub(crate) fn unpark(&mut self, on_applied: &mut dyn FnMut(&Event, Summary)) -> Result<()> {
+        match self.parking.take() {
+            Some(mut parking) => self.apply_events(parking.drain(), on_applied),
+            None => Ok(()),
+        }
+    }
+
+    /// Handles an `entry` consumed by a service, sending its record to the dead letter
+    /// sink, if there is one, when it is invalid or its event is rejected.
+    pub(crate) fn handle_entry(
+        &mut self,
+        entry: Result<Record>,
+        legacy_tx_ids: bool,
+        rounding: RoundingPolicy,
+        joint: &JointAccounts,
+        on_applied: &mut dyn FnMut(&Event, Summary),
+    ) {
+        let record = (self.rejects.is_some() || self.dead_letters.is_some())
+            .then(|| entry.as_ref().ok().cloned())
+            .flatten();
+        let result = match parse_entry(entry, legacy_tx_ids, rounding) {
+            Ok(event) => self.process(joint.resolve(event), on_applied),
+            Err(e) => {
+                self.reject_invalid(record.as_ref(), &e, None);
+                Err(e)
+            }
+        };
+        if let (Err(e), Some(sink)) = (result, self.dead_letters.as_mut()) {
+            if let Err(e) = sink.send(DeadLetter::new(record, &e)) {
+                error!("sending dead letter: {:?}", e);
+            }
+        }
+    }
+
+    /// Applies `event`, after running it through the script, if there is one, returning
+    /// the error it was rejected for once it has been reported.
+    pub(crate) fn process(
+        &mut self,
+        event: Event,
+        on_applied: &mut dyn FnMut(&Event, Summary),
+    ) -> Result<()> {
+        let _span = event.span().entered();
+        let mut span = self
+            .telemetry
+            .tracing
+            .as_mut()
+            .and_then(|t| t.start_event_span(&event));
+        if let Some(wal) = self.wal.as_mut() {
+            if let Err(e) = wal.begin(&event) {
+                // applying events which can't be recovered after a crash isn't crash-safe
+                eprintln!("error: writing write-ahead log: {:?}", e);
+                std::process::exit(1);
+            }
+        }
+        let start = Instant::now();
+        let result = match &self.script {
+            Some(script) => self
+                .run_script(script, &event)
+                .map_err(|e| self.refuse(&event, e))
+                .with_context(|| processing(&event)),
+            None => Ok(event.clone()),
+        };
+        let result = match result.and_then(|event| self.apply_event(&event).map(|s| (event, s))) {
+            Ok((event, summary)) => {
+                self.telemetry.processed(&event, &summary, start.elapsed());
+                if let Some(expiry) = self.expiry.as_mut() {
+                    expiry.observe(&event);
+                }
+                if let Some(pruner) = self.pruner.as_mut() {
+                    match pruner.observe(&mut self.store, event.timestamp()) {
+                        Ok(0) => {}
+                        Ok(pruned) => info!("pruned {} settled transactions", pruned),
+                        Err(e) => error!("pruning settled transactions: {:?}", e),
+                    }
+                }
+                on_applied(&event, summary);
+                Ok(())
+            }
+            Err(e) => {
+                let reason = e.root_cause().to_string();
+                error!(reason = %reason, "{:#}", e);
+                self.telemetry
+                    .rejected(Some(event.kind().name()), RejectKind::of(&e));
+                if let Some(span) = span.as_mut() {
+                    span.set_attribute("rejected", reason);
+                }
+                self.write_reject(|| Reject::event(&event, &e));
+                Err(e)
+            }
+        };
+        if let Some(wal) = self.wal.as_mut() {
+            if let Err(e) = wal.applied() {
+                eprintln!("error: writing write-ahead log: {:?}", e);
+                std::process::exit(1);
+            }
+        }
+
+        if let (Some(t), Some(span)) = (self.telemetry.tracing.as_mut(), span) {
+            t.exporter.end_span(span);
+        }
+
+        // resolving an expired dispute can't itself expire any others
+        let expired = self
+            .expiry
+            .as_mut()
+            .map(DisputeExpiry::expired)
+            .unwrap_or_default();
+        for resolve in expired {
+            warn!(
+                "dispute of transaction {} for client {} expired, resolving it",
+                resolve.tx(),
+                resolve.client_id()
+            );
+            // rejections are already logged, and don't fail the event which expired it
+            let _ = self.process(resolve, on_applied);
+        }
+        result
+    }
+}
+
+pub(crate) fn end_file_span(tracing: &mut Tracing, mut span: Span, records: u64) {
+    span.set_attribute("records", records);
+    tracing.exporter.end_span(span);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use crate::audit::MemoryAuditLog;
+
+    fn record(t: &str, client: ClientId, tx: TxId, amount: Option<Decimal>) -> Record {
+        Record {
+            r#type: t.to_string(),
+            client,
+            tx,
+            amount,
+            to: None,
+            seq: None,
+            timestamp: None,
+            currency: None,
+        }
+    }
+
+    fn event(t: &str, client: ClientId, tx: TxId, amount: Option<Decimal>) -> Event {
+        Event::try_from(record(t, client, tx, amount)).unwrap()
+    }
+
+    fn processor() -> Processor {
+        Processor::new(Backend::default(), RuleSet::default(), Policy::default())
+    }
+
+    #[test]
+    fn test_process() {
+        let mut processor = processor();
+        let mut applied = Vec::new();
+        let mut on_applied =
+            |event: &Event, summary: Summary| applied.push((event.tx(), summary.available));
+        processor
+            .process(event("deposit", 1, 1, Some(dec!(5.0))), &mut on_applied)
+            .unwrap();
+        assert!(processor
+            .process(event("withdrawal", 1, 2, Some(dec!(9.0))), &mut on_applied)
+            .is_err());
+        processor
+            .process(event("withdrawal", 1, 3, Some(dec!(2.0))), &mut on_applied)
+            .unwrap();
+        assert_eq!(applied, [(1, dec!(5.0)), (3, dec!(3.0))]);
+        assert_eq!(processor.summary(1).total, dec!(3.0));
+
+        let metrics = processor.telemetry.metrics.lock().unwrap();
+        assert_eq!(metrics.processed_by_type()["withdrawal"], 1);
+        assert_eq!(metrics.rejected_by_reason()["insufficient_funds"], 1);
+    }
+
+    #[test]
+    fn test_apply_events() {
+        let mut parking = processor();
+        parking.parking = Some(DisputeParking::new(10));
+        let mut applied = Vec::new();
+        let mut on_applied = |event: &Event, _: Summary| applied.push(event.tx());
+        let events = vec![
+            event("dispute", 1, 1, None),
+            event("deposit", 1, 1, Some(dec!(5.0))),
+            event("withdrawal", 1, 2, Some(dec!(9.0))),
+            event("deposit", 1, 3, Some(dec!(1.0))),
+        ];
+        // the dispute waits for its deposit, and rejections don't stop the rest
+        parking
+            .apply_events(events.clone(), &mut on_applied)
+            .unwrap();
+        assert_eq!(applied, [1, 1, 3]);
+        assert_eq!(parking.summary(1).held, dec!(5.0));
+
+        let mut strict = processor();
+        strict.strict = true;
+        let mut applied = Vec::new();
+        let mut on_applied = |event: &Event, _: Summary| applied.push(event.tx());
+        assert!(strict.apply_events(events, &mut on_applied).is_err());
+        assert!(applied.is_empty());
+    }
+
+    #[test]
+    fn test_transfer_to_same_account() {
+        let mut processor = processor();
+        let log = MemoryAuditLog::new();
+        processor.audit = Some(log.clone());
+        processor
+            .process(event("deposit", 1, 1, Some(dec!(5.0))), &mut |_, _| {})
+            .unwrap();
+
+        // clients 1 and 2 share an account, so a transfer between them goes nowhere
+        let joint = JointAccounts::new([(2, 1)]).unwrap();
+        let transfer = Event::try_from(Record {
+            to: Some(2),
+            ..record("transfer", 1, 2, Some(dec!(1.0)))
+        })
+        .unwrap();
+        let transfer = joint.resolve(transfer);
+        let e = processor.process(transfer, &mut |_, _| {}).unwrap_err();
+        assert_eq!(
+            e.root_cause().to_string(),
+            "cannot transfer to the same client"
+        );
+
+        let log = log.lock().unwrap();
+        let entry = log.entries().last().unwrap();
+        assert_eq!((entry.tx, entry.applied), (2, false));
+        assert_eq!(
+            entry.reason.as_deref(),
+            Some("cannot transfer to the same client")
+        );
+    }
+
+    #[test]
+    fn test_backend_snapshot() {
+        let mut backend = Backend::default();
+        backend.upsert(1, 1, TxState::Deposit(dec!(1.0))).unwrap();
+        let checkpoint = backend.snapshot().unwrap().unwrap();
+        let mut restored = Backend::default();
+        checkpoint.restore(&mut restored).unwrap();
+        assert_eq!(restored.get(1, 1), Some(TxState::Deposit(dec!(1.0))));
+
+        assert!(Backend::Compact(CompactStore::new()).snapshot().is_err());
+    }
+}
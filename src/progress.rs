@@ -0,0 +1,205 @@
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How far through its input a run has got.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProgressUpdate {
+    /// The number of records read so far.
+    pub records: u64,
+    /// The number of bytes of input read so far.
+    pub bytes: u64,
+    /// The size of the input in bytes, if known, such as when it isn't read from stdin.
+    pub total_bytes: Option<u64>,
+    /// The time since the run started.
+    pub elapsed: Duration,
+}
+
+impl ProgressUpdate {
+    /// Returns the average number of records read per second so far.
+    pub fn records_per_sec(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => self.records as f64 / secs,
+            _ => 0.0,
+        }
+    }
+
+    /// Returns the percentage of the input read so far, if its size is known.
+    pub fn percent(&self) -> Option<f64> {
+        self.total_bytes.map(|total| match total {
+            0 => 100.0,
+            total => (self.bytes.min(total) as f64 / total as f64) * 100.0,
+        })
+    }
+}
+
+impl fmt::Display for ProgressUpdate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} records, {:.0} records/s",
+            self.records,
+            self.records_per_sec()
+        )?;
+        if let Some(percent) = self.percent() {
+            write!(f, ", {:.1}%", percent)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reports the progress of a run through its input to a callback, at most once per
+/// interval, such as to show it to an operator or to embed the engine in other tools.
+///
+/// Input is read through [`ProgressTracker::reader`], so that the bytes read are
+/// counted towards the percentage complete.
+///
+/// # Example
+/// ```
+/// use std::io::Read;
+/// use std::time::Duration;
+///
+/// use payments::progress::ProgressTracker;
+///
+/// let input = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+/// let mut updates = Vec::new();
+/// let mut progress = ProgressTracker::new(Some(input.len() as u64), Duration::ZERO, |update| {
+///     updates.push(*update)
+/// });
+///
+/// let mut read = String::new();
+/// progress.reader(input.as_bytes()).read_to_string(&mut read).unwrap();
+/// progress.record();
+/// progress.finish();
+///
+/// assert_eq!(updates.last().unwrap().records, 1);
+/// assert_eq!(updates.last().unwrap().percent(), Some(100.0));
+/// ```
+pub struct ProgressTracker<F> {
+    #[doc(hidden)]
+    bytes: Arc<AtomicU64>,
+    #[doc(hidden)]
+    total_bytes: Option<u64>,
+    #[doc(hidden)]
+    records: u64,
+    #[doc(hidden)]
+    started: Instant,
+    #[doc(hidden)]
+    reported: Instant,
+    #[doc(hidden)]
+    interval: Duration,
+    #[doc(hidden)]
+    callback: F,
+}
+
+impl<F: FnMut(&ProgressUpdate)> ProgressTracker<F> {
+    /// Creates a tracker of a run through `total_bytes` of input, if known, calling
+    /// `callback` with its progress at most once every `interval`.
+    pub fn new(total_bytes: Option<u64>, interval: Duration, callback: F) -> ProgressTracker<F> {
+        let now = Instant::now();
+        ProgressTracker {
+            bytes: Arc::new(AtomicU64::new(0)),
+            total_bytes,
+            records: 0,
+            started: now,
+            reported: now,
+            interval,
+            callback,
+        }
+    }
+
+    /// Returns `inner`, counting the bytes read from it towards the progress of the run.
+    pub fn reader<R: Read>(&self, inner: R) -> CountingReader<R> {
+        CountingReader {
+            inner,
+            bytes: Arc::clone(&self.bytes),
+        }
+    }
+
+    /// Counts one more record read, reporting the run's progress if the interval has
+    /// passed since it was last reported.
+    pub fn record(&mut self) {
+        self.records += 1;
+        if self.reported.elapsed() >= self.interval {
+            self.report();
+        }
+    }
+
+    /// Reports the run's progress once it has read all of its input.
+    pub fn finish(&mut self) {
+        self.report();
+    }
+
+    /// Returns the run's progress so far.
+    pub fn update(&self) -> ProgressUpdate {
+        ProgressUpdate {
+            records: self.records,
+            bytes: self.bytes.load(Ordering::Relaxed),
+            total_bytes: self.total_bytes,
+            elapsed: self.started.elapsed(),
+        }
+    }
+
+    fn report(&mut self) {
+        self.reported = Instant::now();
+        let update = self.update();
+        (self.callback)(&update);
+    }
+}
+
+/// A reader counting the bytes read through it towards the progress of a
+/// [`ProgressTracker`].
+#[derive(Debug)]
+pub struct CountingReader<R> {
+    #[doc(hidden)]
+    inner: R,
+    #[doc(hidden)]
+    bytes: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.bytes.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_tracker() {
+        let mut updates = Vec::new();
+        let mut progress = ProgressTracker::new(Some(8), Duration::from_secs(3600), |update| {
+            updates.push(*update)
+        });
+        let mut buf = [0; 2];
+        let mut reader = progress.reader(&b"abcd"[..]);
+        reader.read_exact(&mut buf).unwrap();
+
+        // progress is only reported once the interval passes, or the run finishes
+        progress.record();
+        progress.record();
+        assert_eq!(progress.update().percent(), Some(25.0));
+        progress.finish();
+        assert_eq!(updates.len(), 1);
+        assert_eq!((updates[0].records, updates[0].bytes), (2, 2));
+
+        let update = ProgressUpdate {
+            records: 500,
+            bytes: 30,
+            total_bytes: None,
+            elapsed: Duration::from_secs(2),
+        };
+        assert_eq!(update.to_string(), "500 records, 250 records/s");
+        let update = ProgressUpdate {
+            total_bytes: Some(40),
+            ..update
+        };
+        assert_eq!(update.to_string(), "500 records, 250 records/s, 75.0%");
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::BTreeMap;
+
+use rust_decimal::Decimal;
+
+use crate::clients::Summary;
+use crate::events::{ClientId, EventType};
+use crate::schedule::{Period, Schedule};
+
+/// The estimated future available balance of a client.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Projection {
+    /// The id of the client.
+    pub id: ClientId,
+    /// The current available balance of the client.
+    pub available: Decimal,
+    /// The available balance projected at the end of the horizon.
+    pub projected: Decimal,
+    /// The lowest available balance projected during the horizon.
+    pub lowest: Decimal,
+    /// The time of the first scheduled withdrawal projected to exceed the available
+    /// balance, if any.
+    pub overdraft: Option<u64>,
+}
+
+/// Estimates the available balance of every client `horizon` after `from`, by applying
+/// the payments in `schedule` falling due in that time to their current balances.
+///
+/// Projections are ordered by client id, and include clients which only have scheduled
+/// payments. Withdrawals exceeding the available balance are still deducted, so that
+/// the projection shows the size of the shortfall. Locked accounts are projected to
+/// stay as they are, since they reject all payments.
+///
+/// # Example
+/// ```
+/// use payments::clients::Summary;
+/// use payments::events::EventType;
+/// use payments::projection::project;
+/// use payments::schedule::{Schedule, ScheduledPayment};
+/// use rust_decimal_macros::dec;
+///
+/// let rent = ScheduledPayment {
+///     client: 1,
+///     kind: EventType::Withdrawal(dec!(40)),
+///     start: 0,
+///     every: Some("7d".parse().unwrap()),
+/// };
+/// let balance = Summary { id: 1, available: dec!(100), total: dec!(100), ..Default::default() };
+/// let schedule = Schedule::new(vec![rent]).unwrap();
+/// let projections = project([balance], &schedule, 0, "30d".parse().unwrap());
+///
+/// assert_eq!(projections[0].projected, dec!(-60));
+/// assert_eq!(projections[0].overdraft, Some(3 * 7 * 24 * 60 * 60));
+/// ```
+pub fn project(
+    summaries: impl IntoIterator<Item = Summary>,
+    schedule: &Schedule,
+    from: u64,
+    horizon: Period,
+) -> Vec<Projection> {
+    let mut locked = Vec::new();
+    let mut projections: BTreeMap<ClientId, Projection> = BTreeMap::new();
+    for summary in summaries {
+        if summary.locked {
+            locked.push(summary.id);
+        }
+        projections.insert(
+            summary.id,
+            Projection {
+                id: summary.id,
+                available: summary.available,
+                projected: summary.available,
+                lowest: summary.available,
+                overdraft: None,
+            },
+        );
+    }
+
+    let until = from.saturating_add(horizon.seconds());
+    let mut due: Vec<_> = schedule
+        .payments()
+        .iter()
+        .flat_map(|payment| {
+            payment
+                .occurrences(from, until)
+                .map(move |time| (time, payment))
+        })
+        .collect();
+    due.sort_by_key(|&(time, _)| time);
+
+    for (time, payment) in due {
+        if locked.contains(&payment.client) {
+            continue;
+        }
+        let projection = projections.entry(payment.client).or_insert(Projection {
+            id: payment.client,
+            ..Default::default()
+        });
+        match payment.kind {
+            EventType::Deposit(amount) => projection.projected += amount,
+            EventType::Withdrawal(amount) => {
+                if amount > projection.projected && projection.overdraft.is_none() {
+                    projection.overdraft = Some(time);
+                }
+                projection.projected -= amount;
+            }
+            _ => {}
+        }
+        projection.lowest = projection.lowest.min(projection.projected);
+    }
+    projections.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    use crate::schedule::ScheduledPayment;
+
+    fn payment(client: ClientId, kind: EventType, start: u64, every: &str) -> ScheduledPayment {
+        ScheduledPayment {
+            client,
+            kind,
+            start,
+            every: every.parse().ok(),
+        }
+    }
+
+    fn summary(id: ClientId, available: Decimal, locked: bool) -> Summary {
+        Summary {
+            id,
+            available,
+            total: available,
+            locked,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_project() {
+        let schedule = Schedule::new(vec![
+            payment(1, EventType::Deposit(dec!(10.0)), 100, "100s"),
+            payment(1, EventType::Withdrawal(dec!(25.0)), 250, ""),
+            payment(2, EventType::Withdrawal(dec!(5.0)), 150, "50s"),
+            payment(3, EventType::Deposit(dec!(1.0)), 150, ""),
+            payment(4, EventType::Withdrawal(dec!(1.0)), 150, ""),
+        ])
+        .unwrap();
+        let projections = project(
+            [
+                summary(1, dec!(10.0), false),
+                summary(2, dec!(100.0), false),
+                summary(4, dec!(0.0), true),
+            ],
+            &schedule,
+            100,
+            "300s".parse().unwrap(),
+        );
+        assert_eq!(
+            projections,
+            vec![
+                // deposits at 200, 300 and 400, withdrawing 25 at 250
+                Projection {
+                    id: 1,
+                    available: dec!(10.0),
+                    projected: dec!(15.0),
+                    lowest: dec!(-5.0),
+                    overdraft: Some(250),
+                },
+                // withdrawals at 150, 200, 250, 300, 350 and 400
+                Projection {
+                    id: 2,
+                    available: dec!(100.0),
+                    projected: dec!(70.0),
+                    lowest: dec!(70.0),
+                    overdraft: None,
+                },
+                Projection {
+                    id: 3,
+                    available: dec!(0.0),
+                    projected: dec!(1.0),
+                    lowest: dec!(0.0),
+                    overdraft: None,
+                },
+                Projection {
+                    id: 4,
+                    available: dec!(0.0),
+                    projected: dec!(0.0),
+                    lowest: dec!(0.0),
+                    overdraft: None,
+                },
+            ]
+        );
+    }
+}
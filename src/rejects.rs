@@ -0,0 +1,159 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{Context, Error, Result};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::events::{ClientId, Currency, Event, EventType, Record, TxId};
+
+/// A payment record which was rejected, either because it was invalid or because its
+/// event could not be applied, along with the reason why. Its fields are those of the
+/// record, so that rejects can be corrected and processed again.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Reject {
+    /// The type of the record.
+    pub r#type: String,
+    /// The client associated with the record.
+    pub client: Option<ClientId>,
+    /// The transaction associated with the record.
+    pub tx: Option<TxId>,
+    /// The amount of the record, if it had one.
+    #[serde(with = "rust_decimal::serde::str_option")]
+    pub amount: Option<Decimal>,
+    /// The client receiving the funds of a transfer.
+    pub to: Option<ClientId>,
+    /// The sequence number of the record, if it had one.
+    pub seq: Option<u64>,
+    /// The time at which the record's event occurred, if known.
+    pub timestamp: Option<u64>,
+    /// The currency of the record, if it had one.
+    pub currency: Option<Currency>,
+    /// Why the record was rejected.
+    pub reason: String,
+}
+
+impl Reject {
+    /// Returns a reject of `record` for `reason`.
+    pub fn record(record: &Record, reason: &Error) -> Reject {
+        Reject {
+            r#type: record.r#type.clone(),
+            client: Some(record.client),
+            tx: Some(record.tx),
+            amount: record.amount,
+            to: record.to,
+            seq: record.seq,
+            timestamp: record.timestamp,
+            currency: record.currency,
+            reason: reason.root_cause().to_string(),
+        }
+    }
+
+    /// Returns a reject of an entry which could not be read as a record at all, with
+    /// only the `reason` why.
+    pub fn unreadable(reason: &Error) -> Reject {
+        Reject {
+            reason: format!("{:#}", reason),
+            ..Default::default()
+        }
+    }
+
+    /// Returns a reject of the record of `event` for `reason`.
+    pub fn event(event: &Event, reason: &Error) -> Reject {
+        let (amount, to) = match event.kind() {
+            EventType::Transfer { to, amount } => (Some(*amount), Some(*to)),
+            kind => (kind.amount(), None),
+        };
+        Reject {
+            r#type: event.kind().name().to_string(),
+            client: Some(event.client_id()),
+            tx: Some(event.tx()),
+            amount,
+            to,
+            seq: None,
+            timestamp: event.timestamp(),
+            currency: event.currency(),
+            reason: reason.root_cause().to_string(),
+        }
+    }
+}
+
+/// Writes rejects as CSV, with the columns of a payment record followed by a `reason`
+/// column.
+pub struct RejectsWriter<W: Write> {
+    #[doc(hidden)]
+    writer: csv::Writer<W>,
+}
+
+impl RejectsWriter<File> {
+    /// Creates a writer of the CSV file at `path`, replacing any existing file.
+    pub fn create(path: impl AsRef<Path>) -> Result<RejectsWriter<File>> {
+        let path = path.as_ref();
+        let writer =
+            csv::Writer::from_path(path).with_context(|| format!("creating {}", path.display()))?;
+        Ok(RejectsWriter { writer })
+    }
+}
+
+impl<W: Write> RejectsWriter<W> {
+    /// Creates a writer of CSV to `writer`.
+    pub fn new(writer: W) -> RejectsWriter<W> {
+        RejectsWriter {
+            writer: csv::Writer::from_writer(writer),
+        }
+    }
+
+    /// Writes `reject` as the next row.
+    pub fn write(&mut self, reject: &Reject) -> Result<()> {
+        self.writer.serialize(reject)?;
+        Ok(())
+    }
+
+    /// Writes any rows still buffered.
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::anyhow;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_write() {
+        let record = Record {
+            r#type: "withdrawal".to_string(),
+            client: 1,
+            tx: 2,
+            amount: Some(dec!(1.5)),
+            to: None,
+            seq: Some(7),
+            timestamp: None,
+            currency: Some("usd".parse().unwrap()),
+        };
+        let event = Event::try_from(record.clone()).unwrap();
+        let reason = anyhow!("insufficient funds for withdrawal").context("processing event");
+
+        let mut out = Vec::new();
+        let mut writer = RejectsWriter::new(&mut out);
+        writer.write(&Reject::record(&record, &reason)).unwrap();
+        writer.write(&Reject::event(&event, &reason)).unwrap();
+        writer
+            .write(&Reject::unreadable(&anyhow!("CSV error: record 3")))
+            .unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "type,client,tx,amount,to,seq,timestamp,currency,reason\n\
+             withdrawal,1,2,1.5,,7,,USD,insufficient funds for withdrawal\n\
+             withdrawal,1,2,1.5,,,,USD,insufficient funds for withdrawal\n\
+             ,,,,,,,,CSV error: record 3\n"
+        );
+    }
+}
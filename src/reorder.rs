@@ -0,0 +1,192 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+use crate::events::Event;
+
+/// Buffers timestamped payment events for a bounded window of time so that
+/// slightly-late events can be applied in timestamp order.
+///
+/// An event is held until an event at least `window` seconds newer has been seen,
+/// at which point it is released along with every other buffered event up to the
+/// same time. Events without a timestamp are ordered as if they occurred at the
+/// newest time seen so far, preserving their position relative to their neighbours.
+///
+/// An event older than the most recently released event has missed its window. It
+/// is released immediately, out of order, and [`ReorderBuffer::is_late`] can be used
+/// to detect this before pushing it.
+///
+/// # Example
+/// ```
+/// use payments::reorder::ReorderBuffer;
+///
+/// let mut buffer = ReorderBuffer::new(60);
+/// // events pushed here are released once they are older than 60 seconds
+/// // relative to the newest event seen
+/// assert!(buffer.drain().is_empty());
+/// ```
+#[derive(Debug)]
+pub struct ReorderBuffer {
+    #[doc(hidden)]
+    window: u64,
+    #[doc(hidden)]
+    pending: BinaryHeap<Reverse<Pending>>,
+    #[doc(hidden)]
+    newest: Option<u64>,
+    #[doc(hidden)]
+    watermark: Option<u64>,
+    #[doc(hidden)]
+    arrivals: u64,
+}
+
+#[derive(Debug)]
+struct Pending {
+    timestamp: u64,
+    arrival: u64,
+    event: Event,
+}
+
+impl PartialEq for Pending {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Pending {}
+
+impl PartialOrd for Pending {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Pending {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.timestamp, self.arrival).cmp(&(other.timestamp, other.arrival))
+    }
+}
+
+impl ReorderBuffer {
+    /// Creates a buffer holding events for up to `window` seconds.
+    pub fn new(window: u64) -> ReorderBuffer {
+        ReorderBuffer {
+            window,
+            pending: BinaryHeap::new(),
+            newest: None,
+            watermark: None,
+            arrivals: 0,
+        }
+    }
+
+    /// Returns whether `event` is older than events which were already released.
+    pub fn is_late(&self, event: &Event) -> bool {
+        matches!(
+            (event.timestamp(), self.watermark),
+            (Some(ts), Some(watermark)) if ts < watermark
+        )
+    }
+
+    /// Buffers `event`, returning any events which are now due, in timestamp order.
+    pub fn push(&mut self, event: Event) -> Vec<Event> {
+        if self.is_late(&event) {
+            return vec![event];
+        }
+
+        let timestamp = event.timestamp().unwrap_or(self.newest.unwrap_or(0));
+        let newest = self
+            .newest
+            .map_or(timestamp, |newest| newest.max(timestamp));
+        self.newest = Some(newest);
+        self.arrivals += 1;
+        self.pending.push(Reverse(Pending {
+            timestamp,
+            arrival: self.arrivals,
+            event,
+        }));
+
+        let mut released = Vec::new();
+        let due = match newest.checked_sub(self.window) {
+            Some(due) => due,
+            None => return released,
+        };
+        while self
+            .pending
+            .peek()
+            .is_some_and(|Reverse(next)| next.timestamp <= due)
+        {
+            let Reverse(next) = self.pending.pop().unwrap();
+            self.watermark = Some(next.timestamp);
+            released.push(next.event);
+        }
+        released
+    }
+
+    /// Releases every buffered event in timestamp order.
+    pub fn drain(&mut self) -> Vec<Event> {
+        let mut released = Vec::with_capacity(self.pending.len());
+        while let Some(Reverse(next)) = self.pending.pop() {
+            self.watermark = Some(next.timestamp);
+            released.push(next.event);
+        }
+        released
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    use crate::events::{Record, TxId};
+
+    fn event(tx: TxId, timestamp: Option<u64>) -> Event {
+        Event::try_from(Record {
+            r#type: "deposit".to_string(),
+            client: 1,
+            tx,
+            amount: Some(dec!(1.0)),
+            to: None,
+            seq: None,
+            timestamp,
+            currency: None,
+        })
+        .unwrap()
+    }
+
+    fn txs(events: Vec<Event>) -> Vec<TxId> {
+        events.iter().map(|e| e.tx()).collect()
+    }
+
+    #[test]
+    fn test_reorders_within_window() {
+        let mut buffer = ReorderBuffer::new(10);
+
+        assert!(buffer.push(event(2, Some(105))).is_empty());
+        assert!(buffer.push(event(1, Some(100))).is_empty());
+        assert_eq!(txs(buffer.push(event(3, Some(115)))), vec![1, 2]);
+        assert_eq!(txs(buffer.drain()), vec![3]);
+    }
+
+    #[test]
+    fn test_late_event_released_immediately() {
+        let mut buffer = ReorderBuffer::new(10);
+
+        buffer.push(event(1, Some(100)));
+        assert_eq!(txs(buffer.push(event(2, Some(120)))), vec![1]);
+
+        let late = event(3, Some(90));
+        assert!(buffer.is_late(&late));
+        assert_eq!(txs(buffer.push(late)), vec![3]);
+        assert_eq!(txs(buffer.drain()), vec![2]);
+    }
+
+    #[test]
+    fn test_untimestamped_keeps_position() {
+        let mut buffer = ReorderBuffer::new(10);
+
+        buffer.push(event(1, Some(100)));
+        buffer.push(event(2, None));
+        buffer.push(event(3, Some(95)));
+        assert_eq!(txs(buffer.drain()), vec![3, 1, 2]);
+    }
+}
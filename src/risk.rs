@@ -0,0 +1,253 @@
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Error, Result};
+
+use crate::events::{ClientId, Event, EventType};
+
+/// The period over which transaction velocity is measured, in seconds.
+const VELOCITY_WINDOW: u64 = 60 * 60;
+
+/// The weight given to each risk factor when scoring an account.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RiskWeights {
+    /// The weight of the fraction of deposits and withdrawals which were disputed.
+    pub dispute: f64,
+    /// The weight of each chargeback.
+    pub chargeback: f64,
+    /// The weight of each event in the last hour.
+    pub velocity: f64,
+}
+
+impl Default for RiskWeights {
+    fn default() -> RiskWeights {
+        RiskWeights {
+            dispute: 50.0,
+            chargeback: 25.0,
+            velocity: 0.5,
+        }
+    }
+}
+
+impl FromStr for RiskWeights {
+    type Err = Error;
+
+    /// Parses comma separated `factor=weight` pairs, with unspecified factors keeping
+    /// their default weight.
+    fn from_str(s: &str) -> Result<RiskWeights> {
+        let mut weights = RiskWeights::default();
+        for pair in s.split(',').filter(|pair| !pair.is_empty()) {
+            let (factor, weight) = pair.split_once('=').with_context(|| {
+                format!("invalid risk weight {:?}, expected factor=weight", pair)
+            })?;
+            let weight = weight
+                .parse()
+                .with_context(|| format!("invalid risk weight {:?}", pair))?;
+            match factor {
+                "dispute" => weights.dispute = weight,
+                "chargeback" => weights.chargeback = weight,
+                "velocity" => weights.velocity = weight,
+                v => bail!(
+                    "invalid risk factor {:?}, expected dispute, chargeback or velocity",
+                    v
+                ),
+            }
+        }
+        Ok(weights)
+    }
+}
+
+/// The risk factors observed for a single client.
+#[derive(Clone, Debug, Default)]
+pub struct RiskProfile {
+    #[doc(hidden)]
+    transactions: u64,
+    #[doc(hidden)]
+    disputes: u64,
+    #[doc(hidden)]
+    chargebacks: u64,
+    #[doc(hidden)]
+    recent: VecDeque<u64>,
+}
+
+impl RiskProfile {
+    /// Returns the fraction of the client's deposits and withdrawals which have been
+    /// disputed.
+    pub fn dispute_rate(&self) -> f64 {
+        self.disputes as f64 / self.transactions.max(1) as f64
+    }
+
+    /// Returns the number of chargebacks against the client.
+    pub fn chargebacks(&self) -> u64 {
+        self.chargebacks
+    }
+
+    /// Returns the number of the client's events in the hour up to its latest event.
+    pub fn velocity(&self) -> usize {
+        self.recent.len()
+    }
+
+    /// Returns the weighted risk score of the client.
+    pub fn score(&self, weights: &RiskWeights) -> f64 {
+        weights.dispute * self.dispute_rate()
+            + weights.chargeback * self.chargebacks() as f64
+            + weights.velocity * self.velocity() as f64
+    }
+}
+
+/// Maintains a risk score for every client, updated as each event is applied.
+///
+/// A client's score is the weighted sum of the fraction of its transactions which have
+/// been disputed, its number of chargebacks, and its number of events in the last hour.
+/// Events without a timestamp are attributed to the most recent timestamp seen.
+///
+/// # Example
+/// ```
+/// use payments::events::{Event, Record};
+/// use payments::risk::{RiskScorer, RiskWeights};
+/// use rust_decimal_macros::dec;
+///
+/// let mut risk = RiskScorer::new(RiskWeights::default());
+/// for (t, amount) in [("deposit", Some(dec!(5.0))), ("dispute", None)] {
+///     let record = Record {
+///         r#type: t.to_string(),
+///         client: 1,
+///         tx: 1,
+///         amount,
+///         to: None,
+///         seq: None,
+///         timestamp: None,
+///         currency: None,
+///     };
+///     risk.observe(&Event::try_from(record).unwrap());
+/// }
+///
+/// // every transaction disputed, and two events in the last hour
+/// assert_eq!(risk.score(1), Some(51.0));
+/// ```
+#[derive(Debug)]
+pub struct RiskScorer {
+    #[doc(hidden)]
+    weights: RiskWeights,
+    #[doc(hidden)]
+    clock: u64,
+    #[doc(hidden)]
+    clients: HashMap<ClientId, RiskProfile>,
+}
+
+impl RiskScorer {
+    /// Creates a scorer weighting risk factors with `weights`.
+    pub fn new(weights: RiskWeights) -> RiskScorer {
+        RiskScorer {
+            weights,
+            clock: 0,
+            clients: HashMap::new(),
+        }
+    }
+
+    /// Updates the risk factors of a client after `event` was applied.
+    pub fn observe(&mut self, event: &Event) {
+        if let Some(ts) = event.timestamp() {
+            self.clock = self.clock.max(ts);
+        }
+        let time = event.timestamp().unwrap_or(self.clock);
+
+        let profile = self.clients.entry(event.client_id()).or_default();
+        match event.kind() {
+            EventType::Deposit(_)
+            | EventType::Withdrawal(_)
+            | EventType::Transfer { .. }
+            | EventType::Authorize(_) => profile.transactions += 1,
+            EventType::Dispute(_) => profile.disputes += 1,
+            EventType::Chargeback => profile.chargebacks += 1,
+            EventType::Resolve
+            | EventType::Representment
+            | EventType::Capture(_)
+            | EventType::Void
+            | EventType::Unlock
+            | EventType::Review
+            | EventType::Close => {}
+        }
+        profile.recent.push_back(time);
+        let latest = profile.recent.iter().copied().max().unwrap_or(time);
+        profile.recent.retain(|ts| ts + VELOCITY_WINDOW > latest);
+    }
+
+    /// Returns the risk factors observed for a client.
+    pub fn profile(&self, client: ClientId) -> Option<&RiskProfile> {
+        self.clients.get(&client)
+    }
+
+    /// Returns the risk score of a client, if any of its events have been observed.
+    pub fn score(&self, client: ClientId) -> Option<f64> {
+        self.profile(client)
+            .map(|profile| profile.score(&self.weights))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use crate::events::Record;
+
+    fn event(t: &str, client: ClientId, amount: Option<Decimal>, timestamp: Option<u64>) -> Event {
+        Event::try_from(Record {
+            r#type: t.to_string(),
+            client,
+            tx: 1,
+            amount,
+            to: None,
+            seq: None,
+            timestamp,
+            currency: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_weights() {
+        let weights: RiskWeights = "chargeback=10,velocity=0".parse().unwrap();
+        assert_eq!(weights.dispute, RiskWeights::default().dispute);
+        assert_eq!(weights.chargeback, 10.0);
+        assert_eq!(weights.velocity, 0.0);
+        assert!("refunds=1".parse::<RiskWeights>().is_err());
+        assert!("dispute".parse::<RiskWeights>().is_err());
+    }
+
+    #[test]
+    fn test_dispute_and_chargeback_history() {
+        let weights = RiskWeights {
+            dispute: 10.0,
+            chargeback: 100.0,
+            velocity: 0.0,
+        };
+        let mut risk = RiskScorer::new(weights);
+        risk.observe(&event("deposit", 1, Some(dec!(1.0)), None));
+        risk.observe(&event("deposit", 1, Some(dec!(1.0)), None));
+        risk.observe(&event("dispute", 1, None, None));
+        assert_eq!(risk.score(1), Some(5.0));
+
+        risk.observe(&event("chargeback", 1, None, None));
+        assert_eq!(risk.score(1), Some(105.0));
+        assert_eq!(risk.score(2), None);
+    }
+
+    #[test]
+    fn test_velocity_window() {
+        let mut risk = RiskScorer::new(RiskWeights::default());
+        risk.observe(&event("deposit", 1, Some(dec!(1.0)), Some(0)));
+        risk.observe(&event("deposit", 1, Some(dec!(1.0)), Some(1_800)));
+        risk.observe(&event("deposit", 2, Some(dec!(1.0)), Some(3_000)));
+        assert_eq!(risk.profile(1).unwrap().velocity(), 2);
+
+        risk.observe(&event("withdrawal", 1, Some(dec!(1.0)), None));
+        assert_eq!(risk.profile(1).unwrap().velocity(), 3);
+
+        risk.observe(&event("withdrawal", 1, Some(dec!(1.0)), Some(3_700)));
+        assert_eq!(risk.profile(1).unwrap().velocity(), 3);
+    }
+}
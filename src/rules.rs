@@ -0,0 +1,520 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use rust_decimal::Decimal;
+
+use crate::clients::Summary;
+use crate::events::{ClientId, Event};
+
+/// Attributes describing each client, such as an account type, which rules may refer
+/// to as `client.<name>`.
+pub type ClientAttributes = HashMap<ClientId, HashMap<String, String>>;
+
+/// Loads client attributes from a CSV file with a `client` column followed by one
+/// column per attribute.
+pub fn load_client_attributes(path: impl AsRef<Path>) -> Result<ClientAttributes> {
+    let mut reader = csv::Reader::from_path(path)?;
+    let headers = reader.headers()?.clone();
+    let mut attributes = ClientAttributes::new();
+    for record in reader.records() {
+        let record = record?;
+        let mut client = None;
+        let mut values = HashMap::new();
+        for (name, value) in headers.iter().zip(record.iter()) {
+            if name == "client" {
+                client = Some(
+                    value
+                        .parse()
+                        .with_context(|| format!("invalid client id {:?}", value))?,
+                );
+            } else {
+                values.insert(name.to_string(), value.to_string());
+            }
+        }
+        let client = client.ok_or_else(|| anyhow!("client attributes require a client column"))?;
+        attributes.insert(client, values);
+    }
+    Ok(attributes)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Number(Decimal),
+    Text(String),
+    Op(Op),
+    Open,
+    Close,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    fn matches(&self, ordering: Ordering) -> bool {
+        match self {
+            Op::Eq => ordering == Ordering::Equal,
+            Op::Ne => ordering != Ordering::Equal,
+            Op::Lt => ordering == Ordering::Less,
+            Op::Le => ordering != Ordering::Greater,
+            Op::Gt => ordering == Ordering::Greater,
+            Op::Ge => ordering != Ordering::Less,
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '(' || c == ')' {
+            chars.next();
+            tokens.push(if c == '(' { Token::Open } else { Token::Close });
+        } else if "=!<>".contains(c) {
+            chars.next();
+            let eq = chars.next_if_eq(&'=').is_some();
+            tokens.push(Token::Op(match (c, eq) {
+                ('=', true) => Op::Eq,
+                ('!', true) => Op::Ne,
+                ('<', false) => Op::Lt,
+                ('<', true) => Op::Le,
+                ('>', false) => Op::Gt,
+                ('>', true) => Op::Ge,
+                _ => bail!("unexpected {:?}", c),
+            }));
+        } else if c == '"' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some(c) => text.push(c),
+                    None => bail!("unterminated string"),
+                }
+            }
+            tokens.push(Token::Text(text));
+        } else if c.is_ascii_digit() || c == '-' || c == '.' {
+            let mut number = String::new();
+            while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || *c == '-' || *c == '.') {
+                number.push(c);
+            }
+            tokens.push(Token::Number(
+                number
+                    .parse()
+                    .with_context(|| format!("invalid number {:?}", number))?,
+            ));
+        } else if c.is_alphanumeric() || c == '_' {
+            let mut word = String::new();
+            while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_' || *c == '.') {
+                word.push(c);
+            }
+            tokens.push(Token::Word(word));
+        } else {
+            bail!("unexpected {:?}", c);
+        }
+    }
+    Ok(tokens)
+}
+
+/// A value a rule may compare.
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Number(Decimal),
+    Text(String),
+    Bool(bool),
+}
+
+impl Value {
+    fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => Some(a.cmp(b)),
+            (Value::Text(a), Value::Text(b)) => Some(a.cmp(b)),
+            (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+            // client attributes are read as text, so compare them numerically with numbers
+            (Value::Text(a), Value::Number(b)) => Some(a.parse::<Decimal>().ok()?.cmp(b)),
+            (Value::Number(a), Value::Text(b)) => Some(a.cmp(&b.parse::<Decimal>().ok()?)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Field {
+    Type,
+    Client,
+    Tx,
+    Amount,
+    Timestamp,
+    Available,
+    Held,
+    Total,
+    Locked,
+    Attribute(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Operand {
+    Field(Field),
+    Literal(Value),
+}
+
+impl Operand {
+    fn from_token(token: Token) -> Result<Operand> {
+        Ok(match token {
+            Token::Number(n) => Operand::Literal(Value::Number(n)),
+            Token::Text(text) => Operand::Literal(Value::Text(text)),
+            Token::Word(word) => match word.as_str() {
+                "type" => Operand::Field(Field::Type),
+                "client" | "client.id" => Operand::Field(Field::Client),
+                "tx" => Operand::Field(Field::Tx),
+                "amount" => Operand::Field(Field::Amount),
+                "timestamp" => Operand::Field(Field::Timestamp),
+                "client.available" => Operand::Field(Field::Available),
+                "client.held" => Operand::Field(Field::Held),
+                "client.total" => Operand::Field(Field::Total),
+                "client.locked" => Operand::Field(Field::Locked),
+                "true" => Operand::Literal(Value::Bool(true)),
+                "false" => Operand::Literal(Value::Bool(false)),
+                _ => match word.strip_prefix("client.") {
+                    Some(name) => Operand::Field(Field::Attribute(name.to_string())),
+                    // any other bare word, such as a transaction type, is text
+                    None => Operand::Literal(Value::Text(word)),
+                },
+            },
+            token => bail!("expected a field or value, found {:?}", token),
+        })
+    }
+
+    fn value(&self, scope: &Scope) -> Option<Value> {
+        let field = match self {
+            Operand::Literal(value) => return Some(value.clone()),
+            Operand::Field(field) => field,
+        };
+        let event = scope.event;
+        let summary = scope.summary;
+        Some(match field {
+            Field::Type => Value::Text(event.kind().name().to_string()),
+            Field::Client => Value::Number(event.client_id().into()),
+            Field::Tx => Value::Number(event.tx().into()),
+            Field::Amount => Value::Number(event.kind().amount()?),
+            Field::Timestamp => Value::Number(event.timestamp()?.into()),
+            Field::Available => Value::Number(summary.available),
+            Field::Held => Value::Number(summary.held),
+            Field::Total => Value::Number(summary.total),
+            Field::Locked => Value::Bool(summary.locked),
+            Field::Attribute(name) => Value::Text(scope.attributes?.get(name)?.clone()),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Operand, Op, Operand),
+}
+
+/// The event being checked and the state of its client.
+struct Scope<'a> {
+    event: &'a Event,
+    summary: &'a Summary,
+    attributes: Option<&'a HashMap<String, String>>,
+}
+
+impl Expr {
+    fn eval(&self, scope: &Scope) -> bool {
+        match self {
+            Expr::And(a, b) => a.eval(scope) && b.eval(scope),
+            Expr::Or(a, b) => a.eval(scope) || b.eval(scope),
+            Expr::Not(a) => !a.eval(scope),
+            // comparisons against missing fields, or between mismatched types, never hold
+            Expr::Compare(a, op, b) => match (a.value(scope), b.value(scope)) {
+                (Some(a), Some(b)) => a.compare(&b).is_some_and(|ord| op.matches(ord)),
+                _ => false,
+            },
+        }
+    }
+}
+
+struct Parser {
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+}
+
+impl Parser {
+    fn keyword(&mut self, keyword: &str) -> bool {
+        self.tokens
+            .next_if(|token| matches!(token, Token::Word(word) if word == keyword))
+            .is_some()
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut expr = self.and()?;
+        while self.keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut expr = self.not()?;
+        while self.keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr> {
+        if self.keyword("not") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        if self.tokens.next_if_eq(&Token::Open).is_some() {
+            let expr = self.or()?;
+            if self.tokens.next() != Some(Token::Close) {
+                bail!("expected \")\"");
+            }
+            return Ok(expr);
+        }
+
+        let left = self.operand()?;
+        let op = match self.tokens.next() {
+            Some(Token::Op(op)) => op,
+            token => bail!("expected a comparison, found {:?}", token),
+        };
+        Ok(Expr::Compare(left, op, self.operand()?))
+    }
+
+    fn operand(&mut self) -> Result<Operand> {
+        self.tokens
+            .next()
+            .ok_or_else(|| anyhow!("unexpected end of rule"))
+            .and_then(Operand::from_token)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    source: String,
+    line: usize,
+    condition: Expr,
+}
+
+/// A set of custom validation rules, checked against each event before it is applied.
+///
+/// Rules are written one per line as `reject when <condition>`, where a condition
+/// compares fields of the event (`type`, `client`, `tx`, `amount`, `timestamp`) or of
+/// its client (`client.available`, `client.held`, `client.total`, `client.locked` or
+/// any other `client.<name>` attribute) using `==`, `!=`, `<`, `<=`, `>` or `>=`.
+/// Comparisons may be combined with `and`, `or`, `not` and parentheses. Bare words
+/// which are not fields are treated as text, and `#` starts a comment.
+///
+/// # Example
+/// ```
+/// use std::collections::HashMap;
+///
+/// use payments::clients::Summary;
+/// use payments::events::{Event, Record};
+/// use payments::rules::RuleSet;
+/// use rust_decimal_macros::dec;
+///
+/// let rules = RuleSet::parse(
+///     "# large withdrawals by customers need review\n\
+///      reject when type == withdrawal and amount > 10000 and client.account_type == customer",
+/// )
+/// .unwrap();
+///
+/// let withdrawal = Event::try_from(Record {
+///     r#type: "withdrawal".to_string(),
+///     client: 1,
+///     tx: 1,
+///     amount: Some(dec!(20000.0)),
+///     to: None,
+///     seq: None,
+///     timestamp: None,
+///     currency: None,
+/// })
+/// .unwrap();
+/// assert!(rules.check(&withdrawal, &Summary::default()).is_ok());
+///
+/// let attributes = HashMap::from([("account_type".to_string(), "customer".to_string())]);
+/// let rules = rules.with_client_attributes(HashMap::from([(1, attributes)]));
+/// assert!(rules.check(&withdrawal, &Summary::default()).is_err());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct RuleSet {
+    #[doc(hidden)]
+    rules: Vec<Rule>,
+    #[doc(hidden)]
+    attributes: ClientAttributes,
+}
+
+impl RuleSet {
+    /// Compiles the rules in `source`.
+    pub fn parse(source: &str) -> Result<RuleSet> {
+        let mut rules = Vec::new();
+        for (i, line) in source.lines().enumerate() {
+            let text = line.split('#').next().unwrap_or_default().trim();
+            if text.is_empty() {
+                continue;
+            }
+            let condition =
+                Self::parse_rule(text).with_context(|| format!("rule on line {}", i + 1))?;
+            rules.push(Rule {
+                source: text.to_string(),
+                line: i + 1,
+                condition,
+            });
+        }
+        Ok(RuleSet {
+            rules,
+            attributes: ClientAttributes::new(),
+        })
+    }
+
+    /// Returns these rules, evaluating `client.<name>` fields using `attributes`.
+    pub fn with_client_attributes(self, attributes: ClientAttributes) -> RuleSet {
+        RuleSet { attributes, ..self }
+    }
+
+    /// Compiles the rules in the file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<RuleSet> {
+        let path = path.as_ref();
+        let source =
+            fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        RuleSet::parse(&source).with_context(|| format!("in {}", path.display()))
+    }
+
+    fn parse_rule(text: &str) -> Result<Expr> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?.into_iter().peekable(),
+        };
+        if !parser.keyword("reject") || !parser.keyword("when") {
+            bail!("rules must start with \"reject when\"");
+        }
+        let condition = parser.or()?;
+        if let Some(token) = parser.tokens.next() {
+            bail!("unexpected {:?}", token);
+        }
+        Ok(condition)
+    }
+
+    /// Fails if any rule rejects `event`, given the current balances of its client.
+    pub fn check(&self, event: &Event, summary: &Summary) -> Result<()> {
+        let scope = Scope {
+            event,
+            summary,
+            attributes: self.attributes.get(&event.client_id()),
+        };
+        match self.rules.iter().find(|rule| rule.condition.eval(&scope)) {
+            Some(rule) => bail!("rejected by rule on line {}: {}", rule.line, rule.source),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    use crate::events::Record;
+
+    fn event(t: &str, client: ClientId, amount: Option<Decimal>) -> Event {
+        Event::try_from(Record {
+            r#type: t.to_string(),
+            client,
+            tx: 1,
+            amount,
+            to: None,
+            seq: None,
+            timestamp: None,
+            currency: None,
+        })
+        .unwrap()
+    }
+
+    fn attributes(name: &str, value: &str) -> ClientAttributes {
+        HashMap::from([(1, HashMap::from([(name.to_string(), value.to_string())]))])
+    }
+
+    #[test]
+    fn test_example_rule() {
+        let rules = RuleSet::parse(
+            "reject when type == withdrawal and amount > 10000 and client.account_type == customer",
+        )
+        .unwrap()
+        .with_client_attributes(attributes("account_type", "customer"));
+        let summary = Summary::default();
+
+        assert!(rules
+            .check(&event("withdrawal", 1, Some(dec!(20000.0))), &summary)
+            .is_err());
+        assert!(rules
+            .check(&event("withdrawal", 1, Some(dec!(200.0))), &summary)
+            .is_ok());
+        assert!(rules
+            .check(&event("deposit", 1, Some(dec!(20000.0))), &summary)
+            .is_ok());
+        // clients without attributes never match
+        assert!(rules
+            .check(&event("withdrawal", 2, Some(dec!(20000.0))), &summary)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_precedence_and_client_fields() {
+        let rules = RuleSet::parse(
+            "# comment\n\
+             \n\
+             reject when not (type == deposit or type == withdrawal) and client.held >= 5 # inline\n",
+        )
+        .unwrap();
+        let mut summary = Summary::default();
+        assert!(rules.check(&event("dispute", 1, None), &summary).is_ok());
+
+        summary.held = dec!(5.0);
+        let err = rules
+            .check(&event("dispute", 1, None), &summary)
+            .unwrap_err();
+        assert!(err.to_string().starts_with("rejected by rule on line 3"));
+        assert!(rules
+            .check(&event("deposit", 1, Some(dec!(1.0))), &summary)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_numeric_attributes() {
+        let rules = RuleSet::parse("reject when amount > client.limit")
+            .unwrap()
+            .with_client_attributes(attributes("limit", "100"));
+        let summary = Summary::default();
+        assert!(rules
+            .check(&event("deposit", 1, Some(dec!(150.0))), &summary)
+            .is_err());
+        assert!(rules
+            .check(&event("deposit", 1, Some(dec!(50.0))), &summary)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        assert!(RuleSet::parse("when amount > 1").is_err());
+        assert!(RuleSet::parse("reject when amount >").is_err());
+        assert!(RuleSet::parse("reject when (amount > 1").is_err());
+        assert!(RuleSet::parse("reject when amount > 1 1").is_err());
+        assert!(RuleSet::parse("reject when amount ~ 1").is_err());
+    }
+}
@@ -0,0 +1,437 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{bail, Context, Error, Result};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::events::{ClientId, Event, EventType, Record, TxId};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// The transaction id of the first event materialized from a schedule. Later events
+/// count up from here, well clear of the ids used in payment records.
+pub const SCHEDULED_TX_START: TxId = 1 << 63;
+
+/// A length of time, such as the interval between recurring payments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Period {
+    #[doc(hidden)]
+    seconds: u64,
+}
+
+impl Period {
+    /// Returns the length of the period in seconds.
+    pub fn seconds(&self) -> u64 {
+        self.seconds
+    }
+}
+
+impl FromStr for Period {
+    type Err = Error;
+
+    /// Parses a positive number followed by a unit of "s", "m", "h", "d" or "w", e.g.
+    /// "30d".
+    fn from_str(s: &str) -> Result<Period> {
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (count, unit) = s.split_at(split);
+        let unit = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => SECONDS_PER_DAY,
+            "w" => 7 * SECONDS_PER_DAY,
+            _ => bail!(
+                "invalid period {:?}, expected a number followed by s, m, h, d or w",
+                s
+            ),
+        };
+        let count: u64 = count
+            .parse()
+            .with_context(|| format!("invalid period {:?}", s))?;
+        if count == 0 {
+            bail!("invalid period {:?}, must be greater than zero", s);
+        }
+        Ok(Period {
+            seconds: count * unit,
+        })
+    }
+}
+
+/// Returns the number of days between 1970-01-01 and the given date.
+fn days_from_civil(year: i64, month: u64, day: u64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400) as u64;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era as i64 - 719_468
+}
+
+/// Returns the date which is the given number of days after 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u64, u64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097) as u64;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era as i64 + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+/// Formats a UNIX timestamp in seconds as a "YYYY-MM-DDTHH:MM:SS" date and time in
+/// UTC.
+pub fn format_time(timestamp: u64) -> String {
+    let (year, month, day) = civil_from_days((timestamp / SECONDS_PER_DAY) as i64);
+    let seconds = timestamp % SECONDS_PER_DAY;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Parses either a UNIX timestamp in seconds or a "YYYY-MM-DD" date, taken as
+/// midnight UTC.
+pub fn parse_time(s: &str) -> Result<u64> {
+    if let Ok(timestamp) = s.parse() {
+        return Ok(timestamp);
+    }
+    let invalid = || format!("invalid time {:?}, expected a timestamp or YYYY-MM-DD", s);
+    let mut parts = s.splitn(3, '-');
+    let mut part = || parts.next().unwrap_or_default().parse::<u64>();
+    let (year, month, day) = (
+        part().with_context(invalid)?,
+        part().with_context(invalid)?,
+        part().with_context(invalid)?,
+    );
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        bail!(invalid());
+    }
+    Ok(days_from_civil(year as i64, month, day) as u64 * SECONDS_PER_DAY)
+}
+
+#[derive(Debug, Deserialize)]
+struct Entry {
+    client: ClientId,
+    r#type: String,
+    #[serde(with = "rust_decimal::serde::str")]
+    amount: Decimal,
+    start: String,
+    every: Option<String>,
+}
+
+/// A deposit or withdrawal made on a client's behalf at a fixed time, optionally
+/// repeating at a regular interval, such as a standing order.
+#[derive(Clone, Debug)]
+pub struct ScheduledPayment {
+    /// The client the payment is made for.
+    pub client: ClientId,
+    /// The deposit or withdrawal made.
+    pub kind: EventType,
+    /// The time of the first payment, in seconds since the UNIX epoch.
+    pub start: u64,
+    /// The interval between payments, if the payment recurs.
+    pub every: Option<Period>,
+}
+
+impl ScheduledPayment {
+    /// Returns the times of the payments made after `from`, up to and including
+    /// `until`.
+    pub fn occurrences(&self, from: u64, until: u64) -> impl Iterator<Item = u64> {
+        let first = match self.every {
+            Some(every) if self.start <= from => {
+                let skipped = (from - self.start) / every.seconds() + 1;
+                self.start + skipped * every.seconds()
+            }
+            _ => self.start,
+        };
+        let step = self.every.map(|every| every.seconds());
+        std::iter::successors(Some(first), move |time| step.map(|step| time + step))
+            .skip_while(move |&time| time <= from)
+            .take_while(move |&time| time <= until)
+    }
+}
+
+/// Deposits and withdrawals scheduled to be made on behalf of clients.
+///
+/// # Example
+/// ```
+/// use payments::events::EventType;
+/// use payments::schedule::{Schedule, ScheduledPayment};
+/// use rust_decimal_macros::dec;
+///
+/// let schedule = Schedule::new(vec![ScheduledPayment {
+///     client: 1,
+///     kind: EventType::Withdrawal(dec!(10.0)),
+///     start: 100,
+///     every: Some("1m".parse().unwrap()),
+/// }])
+/// .unwrap();
+///
+/// let times: Vec<u64> = schedule.payments()[0].occurrences(100, 250).collect();
+/// assert_eq!(times, vec![160, 220]);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Schedule {
+    #[doc(hidden)]
+    payments: Vec<ScheduledPayment>,
+}
+
+impl Schedule {
+    /// Creates a schedule of the given payments, failing if any is not a deposit or
+    /// withdrawal of a positive amount.
+    pub fn new(payments: Vec<ScheduledPayment>) -> Result<Schedule> {
+        for payment in &payments {
+            match payment.kind {
+                EventType::Deposit(amount) | EventType::Withdrawal(amount)
+                    if amount > Decimal::ZERO => {}
+                EventType::Deposit(_) | EventType::Withdrawal(_) => bail!(
+                    "invalid scheduled {:?} for client {}, amounts must be positive",
+                    payment.kind,
+                    payment.client
+                ),
+                _ => bail!(
+                    "invalid scheduled {} for client {}, expected a deposit or withdrawal",
+                    payment.kind.name(),
+                    payment.client
+                ),
+            }
+        }
+        Ok(Schedule { payments })
+    }
+
+    /// Loads a schedule from a CSV file with `client`, `type`, `amount`, `start` and
+    /// `every` columns. `type` is either "deposit" or "withdrawal", `start` is a
+    /// timestamp or date, and `every` is a period such as "7d", left empty for
+    /// payments made only once.
+    pub fn load(path: impl AsRef<Path>) -> Result<Schedule> {
+        let mut payments = Vec::new();
+        for (i, entry) in csv::Reader::from_path(path)?.into_deserialize().enumerate() {
+            let entry: Entry = entry?;
+            let context = || format!("in scheduled payment {}", i + 1);
+            let kind = match entry.r#type.as_str() {
+                "deposit" => EventType::Deposit(entry.amount),
+                "withdrawal" => EventType::Withdrawal(entry.amount),
+                v => bail!(
+                    "invalid scheduled payment type {:?}, expected deposit or withdrawal",
+                    v
+                ),
+            };
+            payments.push(ScheduledPayment {
+                client: entry.client,
+                kind,
+                start: parse_time(&entry.start).with_context(context)?,
+                every: entry
+                    .every
+                    .filter(|every| !every.is_empty())
+                    .map(|every| every.parse())
+                    .transpose()
+                    .with_context(context)?,
+            });
+        }
+        Schedule::new(payments)
+    }
+
+    /// Returns every scheduled payment.
+    pub fn payments(&self) -> &[ScheduledPayment] {
+        &self.payments
+    }
+}
+
+/// Materializes the payments of a schedule into events as processing time advances
+/// past each of their occurrences.
+///
+/// Each event is timestamped with the time the payment fell due, and is given a
+/// transaction id counting up from [SCHEDULED_TX_START].
+///
+/// # Example
+/// ```
+/// use payments::events::EventType;
+/// use payments::schedule::{Schedule, ScheduledPayment, Scheduler};
+/// use rust_decimal_macros::dec;
+///
+/// let salary = ScheduledPayment {
+///     client: 1,
+///     kind: EventType::Deposit(dec!(2000.0)),
+///     start: 0,
+///     every: Some("30d".parse().unwrap()),
+/// };
+/// let mut scheduler = Scheduler::new(Schedule::new(vec![salary]).unwrap());
+///
+/// // the first payment falls due immediately, and the next after 30 days
+/// assert_eq!(scheduler.advance(100).len(), 1);
+/// assert_eq!(scheduler.advance(29 * 24 * 60 * 60).len(), 0);
+/// assert_eq!(scheduler.advance(60 * 24 * 60 * 60).len(), 2);
+/// ```
+#[derive(Debug)]
+pub struct Scheduler {
+    #[doc(hidden)]
+    schedule: Schedule,
+    #[doc(hidden)]
+    next: Vec<Option<u64>>,
+    #[doc(hidden)]
+    tx: TxId,
+}
+
+impl Scheduler {
+    /// Creates a scheduler for the payments in `schedule`, each first falling due at
+    /// its start time.
+    pub fn new(schedule: Schedule) -> Scheduler {
+        let next = schedule
+            .payments()
+            .iter()
+            .map(|payment| Some(payment.start))
+            .collect();
+        Scheduler {
+            schedule,
+            next,
+            tx: SCHEDULED_TX_START,
+        }
+    }
+
+    /// Returns the events of every payment falling due up to and including `now` which
+    /// has not already been returned, in time order.
+    pub fn advance(&mut self, now: u64) -> Vec<Event> {
+        let mut due = Vec::new();
+        for (payment, next) in self.schedule.payments().iter().zip(&mut self.next) {
+            while let Some(time) = next.filter(|&time| time <= now) {
+                due.push((time, payment));
+                *next = payment.every.map(|every| time + every.seconds());
+            }
+        }
+        due.sort_by_key(|&(time, _)| time);
+
+        due.into_iter()
+            .filter_map(|(time, payment)| {
+                let amount = match payment.kind {
+                    EventType::Deposit(amount) | EventType::Withdrawal(amount) => amount,
+                    _ => return None,
+                };
+                self.tx += 1;
+                // payments are validated when the schedule is created, so never fail
+                Event::try_from(Record {
+                    r#type: payment.kind.name().to_string(),
+                    client: payment.client,
+                    tx: self.tx - 1,
+                    amount: Some(amount),
+                    to: None,
+                    seq: None,
+                    timestamp: Some(time),
+                    currency: None,
+                })
+                .ok()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    fn payment(start: u64, every: Option<&str>) -> ScheduledPayment {
+        ScheduledPayment {
+            client: 1,
+            kind: EventType::Deposit(dec!(1.0)),
+            start,
+            every: every.map(|every| every.parse().unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_parse_period() {
+        assert_eq!("30d".parse::<Period>().unwrap().seconds(), 30 * 86_400);
+        assert_eq!("2w".parse::<Period>().unwrap().seconds(), 14 * 86_400);
+        assert_eq!("90s".parse::<Period>().unwrap().seconds(), 90);
+        assert!("30".parse::<Period>().is_err());
+        assert!("0d".parse::<Period>().is_err());
+        assert!("d".parse::<Period>().is_err());
+        assert!("1y".parse::<Period>().is_err());
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("1700000000").unwrap(), 1_700_000_000);
+        assert_eq!(parse_time("1970-01-01").unwrap(), 0);
+        assert_eq!(parse_time("2024-03-01").unwrap(), 1_709_251_200);
+        assert!(parse_time("2024-13-01").is_err());
+        assert!(parse_time("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(0), "1970-01-01T00:00:00");
+        assert_eq!(format_time(1_709_251_200 - 1), "2024-02-29T23:59:59");
+        assert_eq!(
+            format_time(parse_time("2100-12-31").unwrap()),
+            "2100-12-31T00:00:00"
+        );
+    }
+
+    #[test]
+    fn test_occurrences() {
+        let once = payment(100, None);
+        assert_eq!(once.occurrences(0, 100).collect::<Vec<_>>(), vec![100]);
+        assert_eq!(once.occurrences(100, 200).count(), 0);
+
+        let recurring = payment(100, Some("10s"));
+        assert_eq!(
+            recurring.occurrences(0, 120).collect::<Vec<_>>(),
+            vec![100, 110, 120]
+        );
+        assert_eq!(
+            recurring.occurrences(125, 150).collect::<Vec<_>>(),
+            vec![130, 140, 150]
+        );
+        assert_eq!(recurring.occurrences(0, 50).count(), 0);
+    }
+
+    #[test]
+    fn test_invalid_payments() {
+        let mut zero = payment(0, None);
+        zero.kind = EventType::Withdrawal(dec!(0.0));
+        assert!(Schedule::new(vec![zero]).is_err());
+
+        let mut dispute = payment(0, None);
+        dispute.kind = EventType::Dispute(None);
+        assert!(Schedule::new(vec![dispute]).is_err());
+    }
+
+    #[test]
+    fn test_scheduler() {
+        let mut withdrawal = payment(150, None);
+        withdrawal.kind = EventType::Withdrawal(dec!(5.0));
+        let schedule = Schedule::new(vec![payment(100, Some("20s")), withdrawal]).unwrap();
+        let mut scheduler = Scheduler::new(schedule);
+
+        assert!(scheduler.advance(99).is_empty());
+        let events = scheduler.advance(150);
+        let times: Vec<_> = events.iter().map(|e| e.timestamp().unwrap()).collect();
+        assert_eq!(times, vec![100, 120, 140, 150]);
+        let txs: Vec<_> = events.iter().map(Event::tx).collect();
+        assert_eq!(
+            txs,
+            (0..4).map(|i| SCHEDULED_TX_START + i).collect::<Vec<_>>()
+        );
+        assert!(matches!(events[3].kind(), EventType::Withdrawal(_)));
+
+        // payments are only materialized once
+        assert!(scheduler.advance(150).is_empty());
+        assert_eq!(scheduler.advance(200).len(), 3);
+    }
+}
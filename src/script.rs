@@ -0,0 +1,352 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::Decimal;
+
+use crate::clients::Summary;
+use crate::events::{Event, EventType, Record, RoundingPolicy};
+
+/// The most operations a script may perform for a single event, guarding against
+/// scripts which never finish.
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// The decision made by a script about an event.
+#[derive(Clone, Debug)]
+pub enum Decision {
+    /// Apply the event unchanged.
+    Allow,
+    /// Reject the event for the given reason.
+    Deny(String),
+    /// Apply this event in place of the original.
+    Transform(Event),
+}
+
+/// The value returned by the script helpers, before being validated.
+#[derive(Clone, Debug)]
+enum ScriptDecision {
+    Allow,
+    Deny(String),
+    Transform(Map),
+}
+
+fn event_map(event: &Event) -> Map {
+    let mut map = Map::new();
+    map.insert("type".into(), event.kind().name().into());
+    map.insert("client".into(), (event.client_id() as i64).into());
+    map.insert("tx".into(), (event.tx() as i64).into());
+    map.insert(
+        "amount".into(),
+        event
+            .kind()
+            .amount()
+            .map_or(Dynamic::UNIT, |amount| number(amount).into()),
+    );
+    map.insert(
+        "to".into(),
+        match event.kind() {
+            EventType::Transfer { to, .. } => (*to as i64).into(),
+            _ => Dynamic::UNIT,
+        },
+    );
+    map.insert(
+        "timestamp".into(),
+        event
+            .timestamp()
+            .map_or(Dynamic::UNIT, |ts| (ts as i64).into()),
+    );
+    map.insert(
+        "currency".into(),
+        event
+            .currency()
+            .map_or(Dynamic::UNIT, |currency| currency.to_string().into()),
+    );
+    map
+}
+
+/// Converts an amount to the floating point numbers scripts work with.
+fn number(amount: Decimal) -> f64 {
+    amount.to_f64().unwrap_or_default()
+}
+
+fn account_map(summary: &Summary) -> Map {
+    let mut map = Map::new();
+    map.insert("id".into(), (summary.id as i64).into());
+    map.insert("available".into(), number(summary.available).into());
+    map.insert("held".into(), number(summary.held).into());
+    map.insert("total".into(), number(summary.total).into());
+    map.insert("locked".into(), summary.locked.into());
+    map
+}
+
+fn field<T>(
+    map: &Map,
+    name: &str,
+    convert: impl FnOnce(Dynamic) -> Option<T>,
+) -> Result<Option<T>> {
+    match map.get(name) {
+        None => Ok(None),
+        Some(value) if value.is_unit() => Ok(None),
+        Some(value) => convert(value.clone())
+            .map(Some)
+            .ok_or_else(|| anyhow!("invalid {} {} returned by script", name, value)),
+    }
+}
+
+fn id(value: Dynamic) -> Option<u64> {
+    u64::try_from(value.as_int().ok()?).ok()
+}
+
+/// Overrides the fields of `event` with those in `map`, validating the result.
+fn transform(event: &Event, map: &Map) -> Result<Event> {
+    let (amount, to) = match event.kind() {
+        EventType::Transfer { to, amount } => (Some(*amount), Some(*to)),
+        kind => (kind.amount(), None),
+    };
+    let record = Record {
+        r#type: field(map, "type", |v| v.into_string().ok())?
+            .unwrap_or_else(|| event.kind().name().to_string()),
+        client: field(map, "client", id)?.unwrap_or(event.client_id()),
+        tx: field(map, "tx", id)?.unwrap_or(event.tx()),
+        amount: match map.get("amount") {
+            Some(_) => field(map, "amount", |v| {
+                v.as_float()
+                    .ok()
+                    .or_else(|| v.as_int().ok().map(|i| i as f64))
+                    .and_then(Decimal::from_f64)
+            })?,
+            None => amount,
+        },
+        to: match map.get("to") {
+            Some(_) => field(map, "to", id)?,
+            None => to,
+        },
+        seq: None,
+        timestamp: match map.get("timestamp") {
+            Some(_) => field(map, "timestamp", id)?,
+            None => event.timestamp(),
+        },
+        currency: match map.get("currency") {
+            Some(_) => field(map, "currency", |v| v.into_string().ok()?.parse().ok())?,
+            None => event.currency(),
+        },
+    };
+    // amounts scripts compute as floating point numbers are rarely exact decimals, so
+    // they are rounded rather than rejected
+    Event::from_record(record, RoundingPolicy::RoundHalfEven)
+        .context("invalid event returned by script")
+}
+
+/// A user-provided [Rhai](https://rhai.rs) script deciding whether each event should be
+/// applied, rejected or replaced.
+///
+/// The script must define a function `on_event(event, account)`, called before each
+/// event is applied. `event` is a map with `type`, `client`, `tx`, `amount`, `to`,
+/// `timestamp` and `currency` fields, and `account` is a map with the `id`, `available`, `held`,
+/// `total` and `locked` fields of the event's client. The function returns one of
+/// - `allow()`, or nothing, to apply the event unchanged
+/// - `deny(reason)` to reject the event
+/// - `transform(event)` to apply a modified copy of the event instead
+///
+/// # Example
+/// ```
+/// use payments::clients::Summary;
+/// use payments::events::{Event, Record};
+/// use payments::script::{Decision, ScriptHook};
+/// use rust_decimal_macros::dec;
+///
+/// let hook = ScriptHook::compile(
+///     r#"
+///     fn on_event(event, account) {
+///         if event.type == "withdrawal" && event.amount > account.available / 2.0 {
+///             return deny("withdrawals may not exceed half the available funds");
+///         }
+///         allow()
+///     }
+///     "#,
+/// )
+/// .unwrap();
+///
+/// let withdrawal = Event::try_from(Record {
+///     r#type: "withdrawal".to_string(),
+///     client: 1,
+///     tx: 1,
+///     amount: Some(dec!(10.0)),
+///     to: None,
+///     seq: None,
+///     timestamp: None,
+///     currency: None,
+/// })
+/// .unwrap();
+/// let account = Summary { id: 1, available: dec!(15.0), total: dec!(15.0), ..Default::default() };
+/// assert!(matches!(hook.decide(&withdrawal, &account).unwrap(), Decision::Deny(_)));
+/// ```
+#[derive(Debug)]
+pub struct ScriptHook {
+    #[doc(hidden)]
+    engine: Engine,
+    #[doc(hidden)]
+    ast: AST,
+}
+
+impl ScriptHook {
+    /// Compiles the script in `source`.
+    pub fn compile(source: &str) -> Result<ScriptHook> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine
+            .register_type_with_name::<ScriptDecision>("Decision")
+            .register_fn("allow", || ScriptDecision::Allow)
+            .register_fn("deny", |reason: &str| {
+                ScriptDecision::Deny(reason.to_string())
+            })
+            .register_fn("transform", ScriptDecision::Transform);
+        let ast = engine
+            .compile(source)
+            .map_err(|e| anyhow!("compiling script: {}", e))?;
+        if !ast
+            .iter_functions()
+            .any(|f| f.name == "on_event" && f.params.len() == 2)
+        {
+            bail!("script does not define on_event(event, account)");
+        }
+        Ok(ScriptHook { engine, ast })
+    }
+
+    /// Compiles the script in the file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<ScriptHook> {
+        let path = path.as_ref();
+        let source =
+            fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        ScriptHook::compile(&source).with_context(|| format!("in {}", path.display()))
+    }
+
+    /// Runs the script for `event`, given the current balances of its client.
+    pub fn decide(&self, event: &Event, account: &Summary) -> Result<Decision> {
+        let result: Dynamic = self
+            .engine
+            .call_fn(
+                &mut Scope::new(),
+                &self.ast,
+                "on_event",
+                (event_map(event), account_map(account)),
+            )
+            .map_err(|e| anyhow!("running script: {}", e))?;
+
+        if result.is_unit() {
+            return Ok(Decision::Allow);
+        }
+        match result.try_cast::<ScriptDecision>() {
+            Some(ScriptDecision::Allow) => Ok(Decision::Allow),
+            Some(ScriptDecision::Deny(reason)) => Ok(Decision::Deny(reason)),
+            Some(ScriptDecision::Transform(map)) => {
+                Ok(Decision::Transform(transform(event, &map)?))
+            }
+            None => bail!("script must return allow(), deny(reason) or transform(event)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    fn event(t: &str, amount: Option<Decimal>) -> Event {
+        Event::try_from(Record {
+            r#type: t.to_string(),
+            client: 1,
+            tx: 7,
+            amount,
+            to: None,
+            seq: None,
+            timestamp: Some(100),
+            currency: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_allow_and_deny() {
+        let hook = ScriptHook::compile(
+            r#"
+            fn on_event(event, account) {
+                if account.locked { return deny("locked"); }
+                if event.type == "dispute" { return allow(); }
+            }
+            "#,
+        )
+        .unwrap();
+        let mut account = Summary::default();
+        assert!(matches!(
+            hook.decide(&event("dispute", None), &account).unwrap(),
+            Decision::Allow
+        ));
+        assert!(matches!(
+            hook.decide(&event("deposit", Some(dec!(1.0))), &account)
+                .unwrap(),
+            Decision::Allow
+        ));
+
+        account.locked = true;
+        match hook.decide(&event("dispute", None), &account).unwrap() {
+            Decision::Deny(reason) => assert_eq!(reason, "locked"),
+            decision => panic!("unexpected {:?}", decision),
+        }
+    }
+
+    #[test]
+    fn test_transform() {
+        let hook = ScriptHook::compile(
+            r#"
+            fn on_event(event, account) {
+                event.amount = event.amount * 0.99;
+                event.client = 2;
+                transform(event)
+            }
+            "#,
+        )
+        .unwrap();
+        match hook
+            .decide(&event("deposit", Some(dec!(100.0))), &Summary::default())
+            .unwrap()
+        {
+            Decision::Transform(event) => {
+                assert_eq!(event.client_id(), 2);
+                assert_eq!(event.tx(), 7);
+                assert_eq!(event.timestamp(), Some(100));
+                assert!(
+                    matches!(event.kind(), EventType::Deposit(amount) if *amount == dec!(99.0))
+                );
+            }
+            decision => panic!("unexpected {:?}", decision),
+        }
+    }
+
+    #[test]
+    fn test_invalid_scripts() {
+        assert!(ScriptHook::compile("fn on_event(event) { allow() }").is_err());
+        assert!(ScriptHook::compile("fn on_event(event, account) {").is_err());
+
+        let hook = ScriptHook::compile("fn on_event(event, account) { 42 }").unwrap();
+        assert!(hook
+            .decide(&event("deposit", Some(dec!(1.0))), &Summary::default())
+            .is_err());
+
+        let hook = ScriptHook::compile(
+            "fn on_event(event, account) { event.amount = -1.0; transform(event) }",
+        )
+        .unwrap();
+        assert!(hook
+            .decide(&event("deposit", Some(dec!(1.0))), &Summary::default())
+            .is_err());
+
+        let hook = ScriptHook::compile("fn on_event(event, account) { loop {} }").unwrap();
+        assert!(hook
+            .decide(&event("deposit", Some(dec!(1.0))), &Summary::default())
+            .is_err());
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::fmt;
+
+/// Describes an irregularity found in the sequence numbers of a single source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SequenceAnomaly {
+    /// One or more sequence numbers were skipped, from `from` up to and including `to`.
+    Gap { from: u64, to: u64 },
+    /// The sequence number was already seen.
+    Duplicate(u64),
+    /// The sequence number arrived after a higher sequence number.
+    OutOfOrder { last: u64, found: u64 },
+}
+
+impl fmt::Display for SequenceAnomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SequenceAnomaly::Gap { from, to } if from == to => {
+                write!(f, "missing sequence number {}", from)
+            }
+            SequenceAnomaly::Gap { from, to } => {
+                write!(f, "missing sequence numbers {} to {}", from, to)
+            }
+            SequenceAnomaly::Duplicate(seq) => write!(f, "duplicate sequence number {}", seq),
+            SequenceAnomaly::OutOfOrder { last, found } => {
+                write!(f, "sequence number {} arrived after {}", found, last)
+            }
+        }
+    }
+}
+
+/// The sequencing state of a single source of payment events.
+#[derive(Clone, Debug, Default)]
+pub struct SourceSequence {
+    #[doc(hidden)]
+    last: Option<u64>,
+    #[doc(hidden)]
+    missing: Vec<(u64, u64)>,
+    #[doc(hidden)]
+    duplicates: u64,
+    #[doc(hidden)]
+    out_of_order: u64,
+}
+
+impl SourceSequence {
+    /// Returns the ranges of sequence numbers which were never seen.
+    pub fn missing(&self) -> &[(u64, u64)] {
+        &self.missing
+    }
+
+    /// Returns the total count of sequence numbers which were never seen.
+    pub fn missing_count(&self) -> u64 {
+        self.missing.iter().map(|(from, to)| to - from + 1).sum()
+    }
+
+    /// Returns the number of duplicate sequence numbers seen.
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    /// Returns the number of sequence numbers which arrived late.
+    pub fn out_of_order(&self) -> u64 {
+        self.out_of_order
+    }
+
+    fn observe(&mut self, seq: u64) -> Option<SequenceAnomaly> {
+        let last = match self.last {
+            None => {
+                self.last = Some(seq);
+                return None;
+            }
+            Some(last) => last,
+        };
+
+        if seq == last {
+            self.duplicates += 1;
+            return Some(SequenceAnomaly::Duplicate(seq));
+        }
+
+        if seq > last {
+            self.last = Some(seq);
+            if seq == last + 1 {
+                return None;
+            }
+            self.missing.push((last + 1, seq - 1));
+            return Some(SequenceAnomaly::Gap {
+                from: last + 1,
+                to: seq - 1,
+            });
+        }
+
+        // a late arrival either fills a previously reported gap or replays a number
+        // that was already seen
+        match self
+            .missing
+            .iter()
+            .position(|(from, to)| *from <= seq && seq <= *to)
+        {
+            Some(i) => {
+                let (from, to) = self.missing.remove(i);
+                if seq < to {
+                    self.missing.insert(i, (seq + 1, to));
+                }
+                if from < seq {
+                    self.missing.insert(i, (from, seq - 1));
+                }
+                self.out_of_order += 1;
+                Some(SequenceAnomaly::OutOfOrder { last, found: seq })
+            }
+            None => {
+                self.duplicates += 1;
+                Some(SequenceAnomaly::Duplicate(seq))
+            }
+        }
+    }
+}
+
+/// Tracks monotonically increasing sequence numbers per event source, detecting
+/// gaps, duplicates and out-of-order delivery.
+///
+/// # Example
+/// ```
+/// use payments::sequence::{SequenceAnomaly, SequenceTracker};
+///
+/// let mut tracker = SequenceTracker::default();
+/// assert_eq!(tracker.observe("gateway-a", 1), None);
+/// assert_eq!(
+///     tracker.observe("gateway-a", 4),
+///     Some(SequenceAnomaly::Gap { from: 2, to: 3 })
+/// );
+/// ```
+#[derive(Debug, Default)]
+pub struct SequenceTracker {
+    #[doc(hidden)]
+    sources: HashMap<String, SourceSequence>,
+}
+
+impl SequenceTracker {
+    /// Records the sequence number `seq` for `source`, returning any anomaly it reveals.
+    pub fn observe(&mut self, source: &str, seq: u64) -> Option<SequenceAnomaly> {
+        self.sources
+            .entry(source.to_string())
+            .or_default()
+            .observe(seq)
+    }
+
+    /// Returns the sequencing state of every source seen, ordered by source name.
+    pub fn sources(&self) -> Vec<(&str, &SourceSequence)> {
+        let mut sources: Vec<_> = self
+            .sources
+            .iter()
+            .map(|(name, seq)| (name.as_str(), seq))
+            .collect();
+        sources.sort_by_key(|(name, _)| *name);
+        sources
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_in_order() {
+        let mut tracker = SequenceTracker::default();
+        for seq in 1..=5 {
+            assert_eq!(tracker.observe("a", seq), None);
+        }
+
+        let (_, state) = tracker.sources()[0];
+        assert_eq!(state.missing_count(), 0);
+        assert_eq!(state.duplicates(), 0);
+        assert_eq!(state.out_of_order(), 0);
+    }
+
+    #[test]
+    fn test_gap_and_duplicate() {
+        let mut tracker = SequenceTracker::default();
+        tracker.observe("a", 1);
+        assert_eq!(
+            tracker.observe("a", 5),
+            Some(SequenceAnomaly::Gap { from: 2, to: 4 })
+        );
+        assert_eq!(tracker.observe("a", 5), Some(SequenceAnomaly::Duplicate(5)));
+
+        let (_, state) = tracker.sources()[0];
+        assert_eq!(state.missing(), &[(2, 4)]);
+        assert_eq!(state.duplicates(), 1);
+    }
+
+    #[test]
+    fn test_late_arrival_fills_gap() {
+        let mut tracker = SequenceTracker::default();
+        tracker.observe("a", 1);
+        tracker.observe("a", 5);
+        assert_eq!(
+            tracker.observe("a", 3),
+            Some(SequenceAnomaly::OutOfOrder { last: 5, found: 3 })
+        );
+        assert_eq!(tracker.observe("a", 3), Some(SequenceAnomaly::Duplicate(3)));
+
+        let (_, state) = tracker.sources()[0];
+        assert_eq!(state.missing(), &[(2, 2), (4, 4)]);
+        assert_eq!(state.out_of_order(), 1);
+        assert_eq!(state.duplicates(), 1);
+    }
+
+    #[test]
+    fn test_sources_independent() {
+        let mut tracker = SequenceTracker::default();
+        tracker.observe("a", 1);
+        assert_eq!(tracker.observe("b", 7), None);
+        assert_eq!(tracker.observe("a", 2), None);
+        assert_eq!(tracker.sources().len(), 2);
+    }
+}
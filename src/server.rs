@@ -0,0 +1,436 @@
+use std::io::Read;
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use axum::extract::{Path, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde_json::json;
+use tokio::runtime::Runtime;
+use tracing::error;
+
+use crate::alerts::Alerter;
+use crate::aliases::ClientAliases;
+use crate::clients::Summary;
+use crate::events::{Event, Record};
+use crate::input::{self, CsvDialect, InputFormat};
+use crate::metrics::{RejectKind, SharedMetrics};
+use crate::otel::{OtlpExporter, Span};
+use crate::parallel::Book;
+use crate::rules::RuleSet;
+use crate::storage::TxStore;
+use crate::webhooks::WebhookNotifier;
+
+/// Parses records submitted to the service into events.
+type Parse = dyn Fn(Result<Record>) -> Result<Event> + Send + Sync;
+
+/// The state shared by the handlers of every request.
+struct Shared<T> {
+    book: Mutex<Book<T>>,
+    rules: RuleSet,
+    aliases: ClientAliases,
+    parse: Box<Parse>,
+    webhooks: Mutex<Vec<WebhookNotifier>>,
+    alerts: Mutex<Option<Alerter>>,
+    metrics: SharedMetrics,
+    otel: Mutex<Option<(Arc<OtlpExporter>, u64)>>,
+    seen: AtomicU64,
+}
+
+impl<T: TxStore + Clone> Shared<T> {
+    /// Starts a span for applying `event` if the service is traced and the event is
+    /// selected by sampling, along with the exporter to end it with.
+    fn start_span(&self, event: &Event) -> Option<(Arc<OtlpExporter>, Span)> {
+        let otel = self.otel.lock().unwrap();
+        let (exporter, sample) = otel.as_ref()?;
+        let seen = self.seen.fetch_add(1, Ordering::Relaxed) + 1;
+        if *sample == 0 || !seen.is_multiple_of(*sample) {
+            return None;
+        }
+        Some((Arc::clone(exporter), exporter.start_event_span(event, None)))
+    }
+
+    /// Records an invalid record to the metrics and the alerter.
+    fn invalid(&self) {
+        self.metrics
+            .lock()
+            .unwrap()
+            .rejected(RejectKind::InvalidRecord);
+        if let Some(alerts) = self.alerts.lock().unwrap().as_mut() {
+            alerts.rejected();
+        }
+    }
+
+    /// Applies `event` to the book, recording the outcome to the metrics and the
+    /// alerter and notifying the webhooks of it, and logging the error if it is
+    /// rejected.
+    fn apply(&self, event: &Event) -> Result<Summary> {
+        let _span = event.span().entered();
+        let traced = self.start_span(event);
+        if let Some(alerts) = self.alerts.lock().unwrap().as_mut() {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            alerts.received(event.timestamp(), now);
+        }
+        let mut book = self.book.lock().unwrap();
+        let start = Instant::now();
+        let applied = book.apply(event, &self.rules);
+        let elapsed = start.elapsed();
+        drop(book);
+        let mut metrics = self.metrics.lock().unwrap();
+        match &applied {
+            Ok(summary) => {
+                metrics.processed(event.kind().name(), elapsed);
+                metrics.account(summary);
+            }
+            Err(e) => {
+                metrics.rejected(RejectKind::of(e));
+                error!("{:?}", e);
+            }
+        }
+        drop(metrics);
+        if let Some(alerts) = self.alerts.lock().unwrap().as_mut() {
+            match &applied {
+                Ok(summary) => drop(alerts.applied(summary)),
+                Err(_) => drop(alerts.rejected()),
+            }
+        }
+        if let Some((exporter, mut span)) = traced {
+            if let Err(e) = &applied {
+                span.set_attribute("rejected", e.root_cause().to_string());
+            }
+            exporter.end_span(span);
+        }
+        let summary = applied?;
+        for webhook in self.webhooks.lock().unwrap().iter_mut() {
+            webhook.observe(event, &summary);
+        }
+        Ok(summary)
+    }
+}
+
+/// A small payments service over HTTP, applying events submitted to it to a book of
+/// client accounts and reporting their balances. It has three routes:
+///
+/// - `POST /events` applies the record in the request body, a JSON object with the
+///   same fields as a line of JSON Lines input, responding with the client's balances
+///   afterwards, or `400 Bad Request` if the record is invalid or `422 Unprocessable
+///   Entity` if the event is rejected, with the reason in an `error` field.
+/// - `GET /clients/{id}` responds with the balances of the client, or `404 Not Found`
+///   if it has no account.
+/// - `GET /metrics` responds with the service's metrics in the Prometheus text
+///   exposition format.
+///
+/// Requests are handled concurrently, but events are applied one at a time, in the
+/// order they are received. Events may also be applied from a file as it is written,
+/// with [`HttpService::follow`].
+pub struct HttpService<T> {
+    #[doc(hidden)]
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: TxStore + Clone + Send + 'static> HttpService<T> {
+    /// Creates a service applying events to `book` if they pass `rules`, with client
+    /// ids resolved through `aliases` and records parsed into events with `parse`,
+    /// recording the events applied and rejected to `metrics`.
+    pub fn new(
+        book: Book<T>,
+        rules: RuleSet,
+        aliases: ClientAliases,
+        metrics: SharedMetrics,
+        parse: impl Fn(Result<Record>) -> Result<Event> + Send + Sync + 'static,
+    ) -> HttpService<T> {
+        HttpService {
+            shared: Arc::new(Shared {
+                book: Mutex::new(book),
+                rules,
+                aliases,
+                parse: Box::new(parse),
+                webhooks: Mutex::new(Vec::new()),
+                alerts: Mutex::new(None),
+                metrics,
+                otel: Mutex::new(None),
+                seen: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Returns the routes of the service.
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route("/events", post(submit_event::<T>))
+            .route("/clients/{id}", get(client_summary::<T>))
+            .with_state(Arc::clone(&self.shared))
+            .merge(metrics_router(Arc::clone(&self.shared.metrics)))
+    }
+
+    /// Posts notifications of frozen accounts and chargebacks to `notifier` as events
+    /// are applied, whether submitted to the service or followed.
+    pub fn notify(&self, notifier: WebhookNotifier) {
+        self.shared.webhooks.lock().unwrap().push(notifier);
+    }
+
+    /// Evaluates the rules of `alerter` as records are submitted and followed, notifying
+    /// its sinks of the alerts raised.
+    pub fn alert(&self, alerter: Alerter) {
+        *self.shared.alerts.lock().unwrap() = Some(alerter);
+    }
+
+    /// Returns the metrics the service records the events applied and rejected to.
+    pub fn metrics(&self) -> SharedMetrics {
+        Arc::clone(&self.shared.metrics)
+    }
+
+    /// Records a span to `exporter` for one in every `sample` events applied, whether
+    /// submitted to the service or followed, or none if `sample` is zero. Each event's
+    /// span is the root of a trace of its own.
+    pub fn trace(&self, exporter: Arc<OtlpExporter>, sample: u64) {
+        *self.shared.otel.lock().unwrap() = Some((exporter, sample));
+    }
+
+    /// Applies the records read from `reader` in `format` on a thread of its own, along
+    /// with the events submitted to the service, such as the rows appended to a file
+    /// followed with a [`FollowReader`](crate::follow::FollowReader), so that the
+    /// balances served are kept up to date. Invalid records and rejected events are
+    /// logged and skipped. The thread ends with `reader`, or if it could not be read.
+    pub fn follow<R: Read + Send + 'static>(
+        &self,
+        reader: R,
+        format: InputFormat,
+        dialect: CsvDialect,
+    ) -> JoinHandle<Result<()>> {
+        let shared = Arc::clone(&self.shared);
+        thread::spawn(move || {
+            for record in input::read_records(reader, format, dialect, &shared.aliases)? {
+                // rejected events are logged as they are applied
+                match (shared.parse)(record) {
+                    Ok(event) => drop(shared.apply(&event)),
+                    Err(e) => {
+                        shared.invalid();
+                        error!("{:?}", e);
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Serves requests accepted from `listener` until the server fails.
+    pub fn run(&self, listener: TcpListener) -> Result<()> {
+        serve(listener, self.router())
+    }
+}
+
+/// Serves `metrics` in the Prometheus text exposition format with `GET /metrics` to
+/// requests accepted from `listener`, on a thread of its own, until the server fails.
+/// This is for processing events other than through an [`HttpService`], which serves
+/// its own metrics.
+pub fn serve_metrics(listener: TcpListener, metrics: SharedMetrics) -> JoinHandle<Result<()>> {
+    thread::spawn(move || serve(listener, metrics_router(metrics)))
+}
+
+/// Serves `router` to requests accepted from `listener` until the server fails.
+fn serve(listener: TcpListener, router: Router) -> Result<()> {
+    let runtime = Runtime::new().context("starting async runtime")?;
+    runtime.block_on(async {
+        listener.set_nonblocking(true)?;
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+        axum::serve(listener, router).await?;
+        Ok(())
+    })
+}
+
+/// Returns the route serving `metrics`.
+fn metrics_router(metrics: SharedMetrics) -> Router {
+    Router::new()
+        .route("/metrics", get(render_metrics))
+        .with_state(metrics)
+}
+
+/// Responds with `status` and the reason a request failed.
+fn failure(status: StatusCode, e: anyhow::Error) -> Response {
+    (status, Json(json!({ "error": format!("{:#}", e) }))).into_response()
+}
+
+async fn submit_event<T: TxStore + Clone + Send + 'static>(
+    State(shared): State<Arc<Shared<T>>>,
+    body: String,
+) -> Response {
+    let start = Instant::now();
+    let record = input::parse_json(&body, &shared.aliases);
+    let event = (shared.parse)(record);
+    shared.metrics.lock().unwrap().ingested(start.elapsed());
+    let event = match event {
+        Ok(event) => event,
+        Err(e) => {
+            shared.invalid();
+            return failure(StatusCode::BAD_REQUEST, e);
+        }
+    };
+    // the store may block, so events are applied off the runtime's worker threads
+    let applied = tokio::task::spawn_blocking(move || shared.apply(&event))
+        .await
+        .expect("applying event panicked");
+    match applied {
+        Ok(summary) => Json(summary).into_response(),
+        Err(e) => failure(StatusCode::UNPROCESSABLE_ENTITY, e),
+    }
+}
+
+async fn client_summary<T: TxStore + Clone + Send + 'static>(
+    State(shared): State<Arc<Shared<T>>>,
+    Path(id): Path<String>,
+) -> Response {
+    let id = match shared.aliases.resolve(&id) {
+        Ok(id) => id,
+        Err(e) => return failure(StatusCode::NOT_FOUND, e),
+    };
+    let summary: Option<Summary> = shared.book.lock().unwrap().summary(id);
+    match summary {
+        Some(summary) => Json(summary).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn render_metrics(State(metrics): State<SharedMetrics>) -> Response {
+    let body = metrics.lock().unwrap().render();
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use crate::alerts::{AlertRule, AlertSink};
+    use crate::http;
+
+    fn parse(entry: Result<Record>) -> Result<Event> {
+        Event::try_from(entry?)
+    }
+
+    #[test]
+    fn test_service() {
+        let aliases = ClientAliases::new([("acme-1".to_string(), 1)]).unwrap();
+        let metrics = SharedMetrics::default();
+        let service = HttpService::new(
+            Book::default(),
+            RuleSet::default(),
+            aliases,
+            Arc::clone(&metrics),
+            parse,
+        );
+        let followed = HttpService {
+            shared: Arc::clone(&service.shared),
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || service.run(listener));
+
+        // the balances responded with, or the status of a failed request
+        let read = |response: Result<ureq::Response, ureq::Error>| match response {
+            Ok(response) => {
+                Ok(serde_json::from_reader::<_, Summary>(response.into_reader()).unwrap())
+            }
+            Err(ureq::Error::Status(status, _)) => Err(status),
+            Err(e) => panic!("{}", e),
+        };
+        let submit = |body: &str| read(ureq::post(&format!("{}/events", url)).send_string(body));
+        let client = |id: &str| read(ureq::get(&format!("{}/clients/{}", url, id)).call());
+
+        let summary = submit(r#"{"type": "deposit", "client": "acme-1", "tx": 1, "amount": 2.5}"#);
+        assert_eq!(summary.unwrap().available, dec!(2.5));
+        let rejected = submit(r#"{"type": "withdrawal", "client": 1, "tx": 2, "amount": "3"}"#);
+        assert_eq!(rejected, Err(422));
+        let invalid = submit(r#"{"type": "deposit", "client": 1, "tx": 3}"#);
+        assert_eq!(invalid, Err(400));
+
+        let summary = client("acme-1").unwrap();
+        assert_eq!(summary.total, dec!(2.5));
+        assert_eq!(summary.held, Decimal::ZERO);
+        assert_eq!(client("2"), Err(404));
+
+        // events followed from a file are applied alongside those submitted
+        let csv = "type,client,tx,amount\ndeposit,2,4,1.0\nwithdrawal,2,5,9.0\n";
+        followed
+            .follow(csv.as_bytes(), InputFormat::Csv, CsvDialect::default())
+            .join()
+            .unwrap()
+            .unwrap();
+        assert_eq!(client("2").unwrap().available, dec!(1.0));
+
+        let metrics = ureq::get(&format!("{}/metrics", url))
+            .call()
+            .unwrap()
+            .into_string()
+            .unwrap();
+        assert!(metrics.contains("payments_events_processed_total{type=\"deposit\"} 2\n"));
+        assert!(metrics.contains("payments_events_rejected_total{reason=\"invalid_record\"} 1\n"));
+        assert!(metrics.contains("payments_active_clients 2\n"));
+    }
+
+    #[test]
+    fn test_alerts() {
+        let hook = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = http::parse(&format!("http://{}/alerts", hook.local_addr().unwrap())).unwrap();
+        let received = thread::spawn(move || {
+            let (mut stream, _) = hook.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"}") {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let service = HttpService::new(
+            Book::default(),
+            RuleSet::default(),
+            ClientAliases::default(),
+            SharedMetrics::default(),
+            parse,
+        );
+        service.alert(Alerter::new(
+            vec![AlertRule::AccountLocked],
+            vec![AlertSink::Webhook(url)],
+        ));
+        for (r#type, amount) in [
+            ("deposit", Some(dec!(5))),
+            ("dispute", None),
+            ("chargeback", None),
+        ] {
+            let event = parse(Ok(Record {
+                r#type: r#type.to_string(),
+                client: 1,
+                tx: 1,
+                amount,
+                to: None,
+                seq: None,
+                timestamp: None,
+                currency: None,
+            }))
+            .unwrap();
+            service.shared.apply(&event).unwrap();
+        }
+
+        let request = received.join().unwrap();
+        assert!(request.starts_with("POST /alerts HTTP/1.1\r\n"));
+        assert!(request.contains("client 1 has been locked"));
+    }
+}
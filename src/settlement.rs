@@ -0,0 +1,233 @@
+use std::collections::{BTreeMap, HashMap};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::clients::Summary;
+use crate::events::{ClientId, Event};
+
+/// A payment settling part of the net positions of two parties.
+///
+/// A party of `None` is the processor's own settlement account, which absorbs the
+/// difference between the funds paid into and out of client accounts.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Movement {
+    /// The party making the payment.
+    pub from: Option<ClientId>,
+    /// The party receiving the payment.
+    pub to: Option<ClientId>,
+    /// The amount paid.
+    pub amount: Decimal,
+}
+
+/// Computes the net position of every client over a period, and a minimal set of
+/// payments between them which settle those positions.
+///
+/// A client's net position is the change in its total funds over the period: positive
+/// if the client is to receive funds, or negative if it is to pay them. Events without
+/// a timestamp are attributed to the most recent timestamp seen.
+///
+/// # Example
+/// ```
+/// use payments::clients::Summary;
+/// use payments::events::{Event, Record};
+/// use payments::settlement::{Movement, Settlement};
+/// use rust_decimal_macros::dec;
+///
+/// let mut settlement = Settlement::new(None, None);
+/// for (client, total) in [(1, dec!(10.0)), (2, dec!(4.0))] {
+///     let record = Record {
+///         r#type: "deposit".to_string(),
+///         client,
+///         tx: client,
+///         amount: Some(total),
+///         to: None,
+///         seq: None,
+///         timestamp: None,
+///         currency: None,
+///     };
+///     let summary = Summary { id: client, available: total, total, ..Default::default() };
+///     settlement.observe(&Event::try_from(record).unwrap(), &summary);
+/// }
+///
+/// // the settlement account pays out the funds deposited by each client
+/// assert_eq!(
+///     settlement.movements()[0],
+///     Movement { from: None, to: Some(1), amount: dec!(10.0) }
+/// );
+/// ```
+#[derive(Debug)]
+pub struct Settlement {
+    #[doc(hidden)]
+    from: Option<u64>,
+    #[doc(hidden)]
+    until: Option<u64>,
+    #[doc(hidden)]
+    clock: u64,
+    #[doc(hidden)]
+    totals: HashMap<ClientId, Decimal>,
+    #[doc(hidden)]
+    opening: HashMap<ClientId, Decimal>,
+    #[doc(hidden)]
+    closing: HashMap<ClientId, Decimal>,
+}
+
+impl Settlement {
+    /// Creates a settlement of the events timestamped at or after `from` and before
+    /// `until`, each unbounded if `None`.
+    pub fn new(from: Option<u64>, until: Option<u64>) -> Settlement {
+        Settlement {
+            from,
+            until,
+            clock: 0,
+            totals: HashMap::new(),
+            opening: HashMap::new(),
+            closing: HashMap::new(),
+        }
+    }
+
+    /// Records the balances of a client after `event` was applied.
+    pub fn observe(&mut self, event: &Event, summary: &Summary) {
+        if let Some(ts) = event.timestamp() {
+            self.clock = self.clock.max(ts);
+        }
+        let time = event.timestamp().unwrap_or(self.clock);
+        let before = self.totals.insert(summary.id, summary.total);
+
+        let in_period = self.from.is_none_or(|from| time >= from)
+            && self.until.is_none_or(|until| time < until);
+        if in_period {
+            self.opening
+                .entry(summary.id)
+                .or_insert_with(|| before.unwrap_or_default());
+            self.closing.insert(summary.id, summary.total);
+        }
+    }
+
+    /// Returns the net position of every client with events in the period, ordered by
+    /// client id.
+    pub fn positions(&self) -> BTreeMap<ClientId, Decimal> {
+        self.opening
+            .iter()
+            .map(|(&id, &opening)| (id, self.closing[&id] - opening))
+            .collect()
+    }
+
+    /// Returns payments settling every net position.
+    ///
+    /// Clients paying funds pay those receiving them, with the settlement account
+    /// making up the difference. The largest debts are paired with the largest credits, so there
+    /// is at most one fewer payment than there are parties.
+    pub fn movements(&self) -> Vec<Movement> {
+        let mut positions: Vec<(Option<ClientId>, Decimal)> = self
+            .positions()
+            .into_iter()
+            .map(|(id, position)| (Some(id), position))
+            .collect();
+        let imbalance: Decimal = positions.iter().map(|&(_, amount)| amount).sum();
+        positions.push((None, -imbalance));
+
+        let (mut owed, mut owing): (Vec<_>, Vec<_>) = positions
+            .into_iter()
+            .filter(|&(_, amount)| !amount.is_zero())
+            .partition(|&(_, amount)| amount > Decimal::ZERO);
+        owed.sort_by_key(|&(id, amount)| (-amount, id));
+        owing.sort_by_key(|&(id, amount)| (amount, id));
+
+        let mut movements = Vec::new();
+        let (mut owed, mut owing) = (owed.into_iter(), owing.into_iter());
+        let (mut payee, mut payer) = (owed.next(), owing.next());
+        while let (Some((to, due)), Some((from, debt))) = (payee.as_mut(), payer.as_mut()) {
+            let amount = (*due).min(-*debt);
+            movements.push(Movement {
+                from: *from,
+                to: *to,
+                amount,
+            });
+            *due -= amount;
+            *debt += amount;
+            if due.is_zero() {
+                payee = owed.next();
+            }
+            if debt.is_zero() {
+                payer = owing.next();
+            }
+        }
+        movements
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    use crate::events::Record;
+
+    fn observe(
+        settlement: &mut Settlement,
+        client: ClientId,
+        total: Decimal,
+        timestamp: Option<u64>,
+    ) {
+        let event = Event::try_from(Record {
+            r#type: "deposit".to_string(),
+            client,
+            tx: 1,
+            amount: Some(dec!(1.0)),
+            to: None,
+            seq: None,
+            timestamp,
+            currency: None,
+        })
+        .unwrap();
+        let summary = Summary {
+            id: client,
+            available: total,
+            total,
+            ..Default::default()
+        };
+        settlement.observe(&event, &summary);
+    }
+
+    #[test]
+    fn test_positions_over_period() {
+        let mut settlement = Settlement::new(Some(100), Some(200));
+        observe(&mut settlement, 1, dec!(10.0), Some(50));
+        observe(&mut settlement, 1, dec!(4.0), Some(100));
+        observe(&mut settlement, 2, dec!(3.0), Some(150));
+        // attributed to the time of the previous event
+        observe(&mut settlement, 2, dec!(5.5), None);
+        observe(&mut settlement, 1, dec!(20.0), Some(200));
+        observe(&mut settlement, 3, dec!(20.0), Some(250));
+
+        let positions: Vec<_> = settlement.positions().into_iter().collect();
+        assert_eq!(positions, vec![(1, dec!(-6.0)), (2, dec!(5.5))]);
+    }
+
+    #[test]
+    fn test_minimal_movements() {
+        let mut settlement = Settlement::new(Some(1), None);
+        for (client, opening, closing) in [
+            (1, dec!(50.0), dec!(20.0)),
+            (2, dec!(0.0), dec!(25.0)),
+            (3, dec!(10.0), dec!(0.0)),
+            (4, dec!(0.0), dec!(5.0)),
+        ] {
+            observe(&mut settlement, client, opening, Some(0));
+            observe(&mut settlement, client, closing, Some(1));
+        }
+        let movement = |from, to, amount| Movement { from, to, amount };
+        // the settlement account receives the 10 withdrawn in total
+        assert_eq!(
+            settlement.movements(),
+            vec![
+                movement(Some(1), Some(2), dec!(25.0)),
+                movement(Some(1), None, dec!(5.0)),
+                movement(Some(3), None, dec!(5.0)),
+                movement(Some(3), Some(4), dec!(5.0)),
+            ]
+        );
+    }
+}
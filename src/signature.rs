@@ -0,0 +1,159 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context, Result};
+use ed25519_dalek::pkcs8::DecodePublicKey;
+use ed25519_dalek::{Signature, VerifyingKey};
+
+/// Decodes a hex encoded string.
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Decodes key or signature material given either as raw bytes of the expected
+/// length, or as hex encoded text.
+fn decode<const N: usize>(bytes: &[u8]) -> Option<[u8; N]> {
+    if let Ok(raw) = bytes.try_into() {
+        return Some(raw);
+    }
+    let text = std::str::from_utf8(bytes).ok()?.trim();
+    decode_hex(text)?.try_into().ok()
+}
+
+/// An Ed25519 public key which input files must be signed with.
+///
+/// # Example
+/// ```
+/// use payments::signature::PublicKey;
+///
+/// let key = PublicKey::parse(
+///     b"d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+/// )
+/// .unwrap();
+/// let signature = "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e06522490155\
+///                  5fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b";
+///
+/// assert!(key.verify(b"", signature.as_bytes()).is_ok());
+/// assert!(key.verify(b"tampered", signature.as_bytes()).is_err());
+/// ```
+#[derive(Clone, Debug)]
+pub struct PublicKey {
+    #[doc(hidden)]
+    key: VerifyingKey,
+}
+
+impl PublicKey {
+    /// Parses a public key given as a PEM encoded SubjectPublicKeyInfo, as written by
+    /// `openssl pkey -pubout`, or as 32 raw or hex encoded bytes.
+    pub fn parse(bytes: &[u8]) -> Result<PublicKey> {
+        let pem = std::str::from_utf8(bytes)
+            .ok()
+            .filter(|text| text.contains("-----BEGIN PUBLIC KEY-----"));
+        let key = match pem {
+            Some(pem) => VerifyingKey::from_public_key_pem(pem)
+                .map_err(|e| anyhow!("invalid Ed25519 public key: {}", e))?,
+            None => {
+                let raw = decode(bytes).ok_or_else(|| {
+                    anyhow!("invalid Ed25519 public key, expected PEM or 32 raw or hex bytes")
+                })?;
+                VerifyingKey::from_bytes(&raw)
+                    .map_err(|e| anyhow!("invalid Ed25519 public key: {}", e))?
+            }
+        };
+        Ok(PublicKey { key })
+    }
+
+    /// Loads a public key from the file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<PublicKey> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        PublicKey::parse(&bytes).with_context(|| format!("in {}", path.display()))
+    }
+
+    /// Verifies a detached signature over `message`, given as 64 raw or hex encoded
+    /// bytes.
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> Result<()> {
+        let signature = decode(signature)
+            .ok_or_else(|| anyhow!("invalid Ed25519 signature, expected 64 raw or hex bytes"))?;
+        self.key
+            .verify_strict(message, &Signature::from_bytes(&signature))
+            .map_err(|_| anyhow!("signature does not match"))
+    }
+
+    /// Verifies that the detached signature in the file at `signature` was made over
+    /// the contents of the file at `path`.
+    pub fn verify_file(&self, path: &str, signature: &str) -> Result<()> {
+        let message = fs::read(path).with_context(|| format!("reading {}", path))?;
+        let signature = fs::read(signature).with_context(|| format!("reading {}", signature))?;
+        if let Err(e) = self.verify(&message, &signature) {
+            bail!("verifying the signature of {}: {}", path, e);
+        }
+        Ok(())
+    }
+
+    /// Verifies each of the input files at `paths` against the signature file at the
+    /// same position in `signatures`.
+    pub fn verify_files(&self, paths: &[String], signatures: &[String]) -> Result<()> {
+        if paths.len() != signatures.len() {
+            bail!(
+                "expected a signature for each of the {} input files, found {}",
+                paths.len(),
+                signatures.len()
+            );
+        }
+        for (path, signature) in paths.iter().zip(signatures) {
+            self.verify_file(path, signature)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ed25519_dalek::{Signer, SigningKey};
+
+    const PEM: &str = "-----BEGIN PUBLIC KEY-----
+MCowBQYDK2VwAyEA11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=
+-----END PUBLIC KEY-----
+";
+
+    #[test]
+    fn test_parse() {
+        let hex = "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a";
+        let from_pem = PublicKey::parse(PEM.as_bytes()).unwrap();
+        let from_hex = PublicKey::parse(format!("{}\n", hex).as_bytes()).unwrap();
+        let from_raw = PublicKey::parse(&decode_hex(hex).unwrap()).unwrap();
+        assert_eq!(from_pem.key, from_hex.key);
+        assert_eq!(from_pem.key, from_raw.key);
+        assert!(PublicKey::parse(b"not a key").is_err());
+    }
+
+    #[test]
+    fn test_verify() {
+        let signing = SigningKey::from_bytes(&[7; 32]);
+        let key = PublicKey {
+            key: signing.verifying_key(),
+        };
+        let signature = signing.sign(b"type,client,tx,amount\n").to_bytes();
+        assert!(key.verify(b"type,client,tx,amount\n", &signature).is_ok());
+        assert!(key
+            .verify(b"type,client,tx,amount\r\n", &signature)
+            .is_err());
+        assert!(key
+            .verify(b"type,client,tx,amount\n", &signature[1..])
+            .is_err());
+
+        let hex: String = signature.iter().map(|b| format!("{:02x}", b)).collect();
+        assert!(key
+            .verify(b"type,client,tx,amount\n", hex.as_bytes())
+            .is_ok());
+    }
+}
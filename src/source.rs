@@ -0,0 +1,426 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, JoinHandle};
+
+use anyhow::{Context, Result};
+use tracing::error;
+
+use crate::aliases::ClientAliases;
+use crate::events::Record;
+use crate::input::{self, Compression, CsvDialect, InputFormat};
+
+/// The records read from a source, in order.
+type Records<'a> = Box<dyn Iterator<Item = Result<Record>> + Send + 'a>;
+
+/// A source of payment records, to be validated into events and applied in the order
+/// they are yielded, such as a file, stdin, or an embedder's own transport.
+///
+/// Sources are iterators of records, which are `Err` when they could not be read, so
+/// that the rest of the source can still be read past them.
+///
+/// # Example
+/// ```
+/// use payments::clients::Client;
+/// use payments::events::{Event, Record};
+/// use payments::source::{EventSource, VecSource};
+/// use payments::storage::MemoryStore;
+/// use rust_decimal_macros::dec;
+///
+/// let record = Record {
+///     r#type: "deposit".to_string(),
+///     client: 1,
+///     tx: 1,
+///     amount: Some(dec!(1.5)),
+///     to: None,
+///     seq: None,
+///     timestamp: None,
+///     currency: None,
+/// };
+/// let source: Box<dyn EventSource> = Box::new(VecSource::new("queue", vec![record]));
+/// assert_eq!(source.name(), "queue");
+///
+/// let mut client = Client::new(1, MemoryStore::new());
+/// for record in source {
+///     client.update(&Event::try_from(record.unwrap()).unwrap()).unwrap();
+/// }
+/// assert_eq!(client.available(), dec!(1.5));
+/// ```
+pub trait EventSource: Iterator<Item = Result<Record>> {
+    /// Names the source, such as in the location of its invalid records.
+    fn name(&self) -> &str;
+
+    /// Returns the number of lines of the source before its first record, such as a
+    /// CSV header, for counting the line each record was read from.
+    fn header_lines(&self) -> u64 {
+        0
+    }
+}
+
+impl<S: EventSource + ?Sized> EventSource for Box<S> {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn header_lines(&self) -> u64 {
+        (**self).header_lines()
+    }
+}
+
+/// The records of a file, in any of the [`InputFormat`]s, compressed or not.
+///
+/// # Example
+/// ```
+/// use payments::aliases::ClientAliases;
+/// use payments::input::{Compression, CsvDialect, InputFormat};
+/// use payments::source::{EventSource, FileSource};
+///
+/// let aliases = ClientAliases::default();
+/// let csv = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+/// let source = FileSource::new(
+///     "events.csv",
+///     csv.as_bytes(),
+///     InputFormat::Csv,
+///     Compression::None,
+///     CsvDialect::default(),
+///     &aliases,
+/// )
+/// .unwrap();
+///
+/// assert_eq!(source.header_lines(), 1);
+/// assert_eq!(source.count(), 1);
+/// ```
+pub struct FileSource<'a> {
+    #[doc(hidden)]
+    name: String,
+    #[doc(hidden)]
+    header_lines: u64,
+    #[doc(hidden)]
+    records: Records<'a>,
+}
+
+impl<'a> FileSource<'a> {
+    /// Opens the file at `path`, with its format and compression detected from its
+    /// extension, and client ids resolved through `aliases`.
+    pub fn open(
+        path: impl AsRef<Path>,
+        dialect: CsvDialect,
+        aliases: &'a ClientAliases,
+    ) -> Result<FileSource<'a>> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+        FileSource::new(
+            &path.to_string_lossy(),
+            file,
+            InputFormat::detect(path),
+            Compression::detect(path),
+            dialect,
+            aliases,
+        )
+    }
+
+    /// Reads the contents of a file named `name` from `reader`, in the given `format`
+    /// and `compression`, with client ids resolved through `aliases`. CSV files are
+    /// read in the given `dialect`.
+    pub fn new(
+        name: &str,
+        reader: impl Read + Send + 'static,
+        format: InputFormat,
+        compression: Compression,
+        dialect: CsvDialect,
+        aliases: &'a ClientAliases,
+    ) -> Result<FileSource<'a>> {
+        let reader = input::decompress(reader, compression)?;
+        Ok(FileSource {
+            name: name.to_string(),
+            header_lines: match format {
+                InputFormat::Csv if dialect.has_headers => 1,
+                InputFormat::Csv | InputFormat::Json => 0,
+            },
+            records: input::read_records(reader, format, dialect, aliases)
+                .with_context(|| format!("reading {}", name))?,
+        })
+    }
+}
+
+impl Iterator for FileSource<'_> {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Result<Record>> {
+        self.records.next()
+    }
+}
+
+impl EventSource for FileSource<'_> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn header_lines(&self) -> u64 {
+        self.header_lines
+    }
+}
+
+/// The records written to stdin, such as by an upstream process in a pipeline.
+pub struct StdinSource<'a> {
+    #[doc(hidden)]
+    file: FileSource<'a>,
+}
+
+impl<'a> StdinSource<'a> {
+    /// Reads stdin in the given `format` and `compression`, with client ids resolved
+    /// through `aliases`. CSV is read in the given `dialect`.
+    pub fn new(
+        format: InputFormat,
+        compression: Compression,
+        dialect: CsvDialect,
+        aliases: &'a ClientAliases,
+    ) -> Result<StdinSource<'a>> {
+        Ok(StdinSource {
+            file: FileSource::new("stdin", io::stdin(), format, compression, dialect, aliases)?,
+        })
+    }
+}
+
+impl Iterator for StdinSource<'_> {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Result<Record>> {
+        self.file.next()
+    }
+}
+
+impl EventSource for StdinSource<'_> {
+    fn name(&self) -> &str {
+        self.file.name()
+    }
+
+    fn header_lines(&self) -> u64 {
+        self.file.header_lines()
+    }
+}
+
+/// Records already in memory, such as those received by an embedder over its own
+/// transport, or for tests.
+#[derive(Clone, Debug)]
+pub struct VecSource {
+    #[doc(hidden)]
+    name: String,
+    #[doc(hidden)]
+    records: std::vec::IntoIter<Record>,
+}
+
+impl VecSource {
+    /// Creates a source named `name` of `records`, yielded in order.
+    pub fn new(name: &str, records: Vec<Record>) -> VecSource {
+        VecSource {
+            name: name.to_string(),
+            records: records.into_iter(),
+        }
+    }
+}
+
+impl Iterator for VecSource {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Result<Record>> {
+        self.records.next().map(Ok)
+    }
+}
+
+impl EventSource for VecSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Reads the records of another source on a thread of its own, up to `capacity`
+/// records ahead of those taken from it, so that reading and parsing input carries on
+/// while the events of records already read are applied. Once `capacity` records are
+/// waiting to be taken, the reader waits for the next to be taken, bounding the memory
+/// records read ahead use.
+///
+/// Dropping the source stops the reader at the next record it reads.
+///
+/// # Example
+/// ```
+/// use payments::events::Record;
+/// use payments::source::{EventSource, ReadAhead, VecSource};
+///
+/// let deposit = |tx| Record {
+///     r#type: "deposit".to_string(),
+///     client: 1,
+///     tx,
+///     amount: Some("1.0".parse().unwrap()),
+///     to: None,
+///     seq: None,
+///     timestamp: None,
+///     currency: None,
+/// };
+/// let source = VecSource::new("queue", (1..=100).map(deposit).collect());
+/// let source = ReadAhead::new(source, 10);
+///
+/// assert_eq!(source.name(), "queue");
+/// assert_eq!(source.map(|record| record.unwrap().tx).sum::<u64>(), 5050);
+/// ```
+pub struct ReadAhead {
+    #[doc(hidden)]
+    name: String,
+    #[doc(hidden)]
+    header_lines: u64,
+    #[doc(hidden)]
+    receiver: Option<Receiver<Result<Record>>>,
+    #[doc(hidden)]
+    reader: Option<JoinHandle<()>>,
+}
+
+impl ReadAhead {
+    /// Starts reading `source` on a thread of its own, up to `capacity` records ahead.
+    pub fn new(source: impl EventSource + Send + 'static, capacity: usize) -> ReadAhead {
+        let (name, header_lines) = (source.name().to_string(), source.header_lines());
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        let reader = thread::spawn(move || {
+            for record in source {
+                // only stops receiving once dropped
+                if sender.send(record).is_err() {
+                    break;
+                }
+            }
+        });
+        ReadAhead {
+            name,
+            header_lines,
+            receiver: Some(receiver),
+            reader: Some(reader),
+        }
+    }
+}
+
+impl Iterator for ReadAhead {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Result<Record>> {
+        self.receiver.as_ref()?.recv().ok()
+    }
+}
+
+impl EventSource for ReadAhead {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn header_lines(&self) -> u64 {
+        self.header_lines
+    }
+}
+
+impl Drop for ReadAhead {
+    fn drop(&mut self) {
+        drop(self.receiver.take());
+        if let Some(reader) = self.reader.take() {
+            if reader.join().is_err() {
+                error!("reading {} panicked", self.name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    use rust_decimal_macros::dec;
+
+    use crate::merge::MergedRecords;
+
+    #[test]
+    fn test_sources() {
+        let aliases = ClientAliases::default();
+        let jsonl = concat!(
+            r#"{"type": "deposit", "client": 1, "tx": 1, "amount": 1, "timestamp": 10}"#,
+            "\n",
+            r#"{"type": "deposit", "client": 1, "tx": 3, "amount": 1, "timestamp": 30}"#,
+            "\n",
+        );
+        let file = FileSource::new(
+            "events.jsonl",
+            jsonl.as_bytes(),
+            InputFormat::Json,
+            Compression::None,
+            CsvDialect::default(),
+            &aliases,
+        )
+        .unwrap();
+        let memory = VecSource::new(
+            "memory",
+            vec![Record {
+                r#type: "deposit".to_string(),
+                client: 1,
+                tx: 2,
+                amount: Some(dec!(1.0)),
+                to: None,
+                seq: None,
+                timestamp: Some(20),
+                currency: None,
+            }],
+        );
+        assert_eq!((file.name(), file.header_lines()), ("events.jsonl", 0));
+
+        // sources of any kind can be read together
+        let sources: Vec<Box<dyn EventSource + Send>> = vec![Box::new(file), Box::new(memory)];
+        let merged: Vec<_> = MergedRecords::new(sources)
+            .map(|(i, record)| (i, record.map(|record| record.tx).ok()))
+            .collect();
+        assert_eq!(merged, [(0, Some(1)), (1, Some(2)), (0, Some(3))]);
+    }
+
+    /// An endless source of deposits, counting how many were read.
+    struct Endless(Arc<AtomicU64>);
+
+    impl Iterator for Endless {
+        type Item = Result<Record>;
+
+        fn next(&mut self) -> Option<Result<Record>> {
+            let tx = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+            Some(Ok(Record {
+                r#type: "deposit".to_string(),
+                client: 1,
+                tx,
+                amount: Some(dec!(1.0)),
+                to: None,
+                seq: None,
+                timestamp: None,
+                currency: None,
+            }))
+        }
+    }
+
+    impl EventSource for Endless {
+        fn name(&self) -> &str {
+            "endless"
+        }
+
+        fn header_lines(&self) -> u64 {
+            1
+        }
+    }
+
+    #[test]
+    fn test_read_ahead() {
+        let read = Arc::new(AtomicU64::new(0));
+        let mut source = ReadAhead::new(Endless(Arc::clone(&read)), 4);
+        assert_eq!((source.name(), source.header_lines()), ("endless", 1));
+        let taken: Vec<_> = source.by_ref().take(3).map(|r| r.unwrap().tx).collect();
+        assert_eq!(taken, [1, 2, 3]);
+        thread::sleep(std::time::Duration::from_millis(50));
+        // the reader waits once the records it read ahead fill the channel, holding one
+        // more it is waiting to send
+        assert!(read.load(Ordering::SeqCst) <= 3 + 4 + 1);
+        // dropping the source stops the endless reader
+        drop(source);
+    }
+}
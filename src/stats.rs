@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::clients::Summary;
+
+/// Machine-readable statistics of a processing run, such as for batch jobs to check
+/// their outcome without parsing logs.
+///
+/// # Example
+/// ```
+/// use payments::clients::Summary;
+/// use payments::stats::RunStats;
+/// use rust_decimal_macros::dec;
+///
+/// let mut stats = RunStats::default();
+/// stats.processed("deposit");
+/// stats.rejected(Some("withdrawal"));
+/// stats.rejected(None);
+/// stats.accounts(&[Summary { id: 1, available: dec!(1.5), total: dec!(1.5), ..Default::default() }]);
+///
+/// assert_eq!(stats.processed["deposit"], 1);
+/// assert_eq!(stats.invalid, 1);
+/// assert_eq!(stats.total, dec!(1.5));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct RunStats {
+    /// The number of events applied, by event type.
+    pub processed: BTreeMap<&'static str, u64>,
+    /// The number of events rejected, by event type.
+    pub rejected: BTreeMap<&'static str, u64>,
+    /// The number of records which were not valid events, so have no event type.
+    pub invalid: u64,
+    /// The number of client accounts.
+    pub clients: u64,
+    /// The number of frozen client accounts.
+    pub frozen: u64,
+    /// The total funds of every client account, in the base currency.
+    #[serde(with = "rust_decimal::serde::str")]
+    pub total: Decimal,
+}
+
+impl RunStats {
+    /// Counts an applied event of type `kind`.
+    pub fn processed(&mut self, kind: &'static str) {
+        *self.processed.entry(kind).or_default() += 1;
+    }
+
+    /// Counts a rejected event of type `kind`, or an invalid record if `None`.
+    pub fn rejected(&mut self, kind: Option<&'static str>) {
+        match kind {
+            Some(kind) => *self.rejected.entry(kind).or_default() += 1,
+            None => self.invalid += 1,
+        }
+    }
+
+    /// Counts the client accounts with the balances in `summaries`, as at the end of
+    /// the run. Balances in currencies other than the base currency only count towards
+    /// the number of clients.
+    pub fn accounts<'a>(&mut self, summaries: impl IntoIterator<Item = &'a Summary>) {
+        for summary in summaries {
+            if summary.currency.is_some() {
+                continue;
+            }
+            self.clients += 1;
+            if summary.locked {
+                self.frozen += 1;
+            }
+            self.total += summary.total;
+        }
+    }
+
+    /// Writes the statistics to the file at `path` as JSON.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut out = BufWriter::new(
+            File::create(path).with_context(|| format!("creating {}", path.display()))?,
+        );
+        serde_json::to_writer_pretty(&mut out, self)?;
+        writeln!(out)?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_run_stats() {
+        let mut stats = RunStats::default();
+        stats.processed("deposit");
+        stats.processed("deposit");
+        stats.processed("chargeback");
+        stats.rejected(Some("withdrawal"));
+        stats.rejected(None);
+        let eur = Summary {
+            id: 1,
+            total: dec!(100),
+            currency: Some("EUR".parse().unwrap()),
+            ..Default::default()
+        };
+        stats.accounts(&[
+            Summary {
+                id: 1,
+                available: dec!(1.5),
+                total: dec!(2.5),
+                ..Default::default()
+            },
+            eur,
+            Summary {
+                id: 2,
+                total: dec!(-1),
+                locked: true,
+                ..Default::default()
+            },
+        ]);
+
+        assert_eq!(
+            serde_json::to_value(&stats).unwrap(),
+            serde_json::json!({
+                "processed": {"chargeback": 1, "deposit": 2},
+                "rejected": {"withdrawal": 1},
+                "invalid": 1,
+                "clients": 2,
+                "frozen": 1,
+                "total": "1.5",
+            })
+        );
+    }
+}
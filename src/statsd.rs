@@ -0,0 +1,258 @@
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{bail, Error, Result};
+
+use crate::metrics::RejectKind;
+
+/// The largest datagram sent, chosen to fit within a typical Ethernet MTU.
+const MAX_PACKET: usize = 1432;
+
+/// The dialects of the StatsD protocol which can be emitted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatsdFlavor {
+    /// Plain StatsD, where tag values are appended to the metric name and global tags
+    /// are not sent.
+    Statsd,
+    /// DogStatsD, with tags sent in the `|#key:value` extension.
+    Datadog,
+}
+
+impl FromStr for StatsdFlavor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<StatsdFlavor> {
+        match s {
+            "statsd" => Ok(StatsdFlavor::Statsd),
+            "dogstatsd" => Ok(StatsdFlavor::Datadog),
+            v => bail!(
+                "invalid statsd flavor {:?}, expected statsd or dogstatsd",
+                v
+            ),
+        }
+    }
+}
+
+/// Replaces any character which is not safe in a metric name or tag with `_`.
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Emits the core processing metrics to a StatsD or DogStatsD agent over UDP as
+/// events are applied.
+///
+/// Metrics are buffered into datagrams of up to 1432 bytes, so [`flush`] should be
+/// called once processing completes. As is usual for StatsD, failures to send are
+/// ignored.
+///
+/// [`flush`]: StatsdEmitter::flush
+///
+/// # Example
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use payments::statsd::{StatsdEmitter, StatsdFlavor};
+///
+/// let mut statsd = StatsdEmitter::connect(
+///     "localhost:8125",
+///     "payments",
+///     StatsdFlavor::Datadog,
+///     vec!["env:prod".to_string()],
+/// )
+/// .unwrap();
+/// // sends "payments.events.processed:1|c|#env:prod,type:deposit"
+/// // and "payments.event.processing:0.003|ms|#env:prod"
+/// statsd.processed("deposit", Duration::from_micros(3));
+/// statsd.flush();
+/// ```
+#[derive(Debug)]
+pub struct StatsdEmitter {
+    #[doc(hidden)]
+    socket: UdpSocket,
+    #[doc(hidden)]
+    prefix: String,
+    #[doc(hidden)]
+    flavor: StatsdFlavor,
+    #[doc(hidden)]
+    tags: Vec<String>,
+    #[doc(hidden)]
+    buffer: String,
+}
+
+impl StatsdEmitter {
+    /// Creates an emitter sending to the agent at `addr`, prefixing every metric name
+    /// with `prefix` and, for DogStatsD, attaching `tags` to every metric. Each address
+    /// `addr` resolves to is tried in turn, from a socket of the same address family.
+    pub fn connect(
+        addr: impl ToSocketAddrs,
+        prefix: &str,
+        flavor: StatsdFlavor,
+        tags: Vec<String>,
+    ) -> io::Result<StatsdEmitter> {
+        let mut error = None;
+        let mut connected = None;
+        for addr in addr.to_socket_addrs()? {
+            let local = if addr.is_ipv4() {
+                SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+            } else {
+                SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))
+            };
+            match UdpSocket::bind(local).and_then(|socket| socket.connect(addr).map(|_| socket)) {
+                Ok(socket) => {
+                    connected = Some(socket);
+                    break;
+                }
+                Err(e) => error = Some(e),
+            }
+        }
+        let socket = connected.ok_or_else(|| {
+            error.unwrap_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "no addresses to send to")
+            })
+        })?;
+        Ok(StatsdEmitter {
+            socket,
+            prefix: prefix.trim_end_matches('.').to_string(),
+            flavor,
+            tags,
+            buffer: String::new(),
+        })
+    }
+
+    /// Records an event of type `kind` which was applied in `elapsed` time.
+    pub fn processed(&mut self, kind: &str, elapsed: Duration) {
+        self.send("events.processed", "1|c", Some(("type", kind)));
+        let millis = format!("{}|ms", elapsed.as_secs_f64() * 1000.0);
+        self.send("event.processing", &millis, None);
+    }
+
+    /// Records an event which was rejected, or an invalid record, for an error of
+    /// kind `reason`.
+    pub fn rejected(&mut self, reason: RejectKind) {
+        self.send("events.rejected", "1|c", Some(("reason", reason.name())));
+    }
+
+    /// Records the number of accounts which are currently frozen.
+    pub fn locked_accounts(&mut self, locked: u64) {
+        self.send("accounts.locked", &format!("{}|g", locked), None);
+    }
+
+    /// Sends any buffered metrics.
+    pub fn flush(&mut self) {
+        if !self.buffer.is_empty() {
+            let _ = self.socket.send(self.buffer.as_bytes());
+            self.buffer.clear();
+        }
+    }
+
+    fn send(&mut self, name: &str, value: &str, tag: Option<(&str, &str)>) {
+        let mut line = if self.prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", self.prefix, name)
+        };
+        match self.flavor {
+            StatsdFlavor::Statsd => {
+                if let Some((_, tag)) = tag {
+                    line.push('.');
+                    line.push_str(&sanitize(tag));
+                }
+                line.push(':');
+                line.push_str(value);
+            }
+            StatsdFlavor::Datadog => {
+                line.push(':');
+                line.push_str(value);
+                let mut tags = self.tags.clone();
+                if let Some((key, tag)) = tag {
+                    tags.push(format!("{}:{}", key, sanitize(tag)));
+                }
+                if !tags.is_empty() {
+                    line.push_str("|#");
+                    line.push_str(&tags.join(","));
+                }
+            }
+        }
+
+        if !self.buffer.is_empty() && self.buffer.len() + 1 + line.len() > MAX_PACKET {
+            self.flush();
+        }
+        if !self.buffer.is_empty() {
+            self.buffer.push('\n');
+        }
+        self.buffer.push_str(&line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receive(flavor: StatsdFlavor, tags: Vec<String>) -> String {
+        let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut statsd =
+            StatsdEmitter::connect(agent.local_addr().unwrap(), "payments", flavor, tags).unwrap();
+        statsd.processed("deposit", Duration::from_millis(2));
+        statsd.rejected(RejectKind::InsufficientFunds);
+        statsd.locked_accounts(1);
+        statsd.flush();
+
+        let mut buf = [0; MAX_PACKET];
+        let n = agent.recv(&mut buf).unwrap();
+        String::from_utf8(buf[..n].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_statsd() {
+        assert_eq!(
+            receive(StatsdFlavor::Statsd, vec!["env:prod".to_string()]),
+            "payments.events.processed.deposit:1|c\n\
+             payments.event.processing:2|ms\n\
+             payments.events.rejected.insufficient_funds:1|c\n\
+             payments.accounts.locked:1|g"
+        );
+    }
+
+    #[test]
+    fn test_dogstatsd() {
+        assert_eq!(
+            receive(StatsdFlavor::Datadog, vec!["env:prod".to_string()]),
+            "payments.events.processed:1|c|#env:prod,type:deposit\n\
+             payments.event.processing:2|ms|#env:prod\n\
+             payments.events.rejected:1|c|#env:prod,reason:insufficient_funds\n\
+             payments.accounts.locked:1|g|#env:prod"
+        );
+    }
+
+    #[test]
+    fn test_ipv6_agent() {
+        // not every host has an IPv6 loopback address
+        let Ok(agent) = UdpSocket::bind("[::1]:0") else {
+            return;
+        };
+        let mut statsd = StatsdEmitter::connect(
+            agent.local_addr().unwrap(),
+            "",
+            StatsdFlavor::Statsd,
+            vec![],
+        )
+        .unwrap();
+        statsd.locked_accounts(2);
+        statsd.flush();
+
+        let mut buf = [0; MAX_PACKET];
+        let n = agent.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"accounts.locked:2|g");
+    }
+}
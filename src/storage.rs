@@ -1,84 +1,2801 @@
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::ops::RangeInclusive;
+#[cfg(feature = "sled")]
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
 
-use anyhow::{bail, Result};
+#[cfg(any(feature = "sled", feature = "postgres"))]
+use anyhow::Context;
+use anyhow::{bail, Error, Result};
+#[cfg(feature = "postgres")]
+use postgres::NoTls;
+#[cfg(feature = "postgres")]
+use r2d2_postgres::PostgresConnectionManager;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 
-/// Represents a client capable of storing and retrieving transactions.
-pub trait TxStore: Default {
+use crate::amount::Amount;
+use crate::clients::AccountStatus;
+use crate::events::{ClientId, Currency, TxId, MAX_DECIMAL_PLACES};
+
+/// Represents a client capable of storing and retrieving transactions, along with
+/// the balances of client accounts as a [`ClientStore`].
+pub trait TxStore<A = Decimal>: ClientStore<A> {
+    /// Returns the requested transaction specified by `tx_id` for the client
+    /// specified by `client_id`, if both exist.
+    fn get(&self, client_id: ClientId, tx_id: TxId) -> Option<TxState<A>>;
+    /// Inserts a new transaction, or updates an existing transaction, specified by
+    /// `tx_id`, for the client specified by `client_id`.
+    fn upsert(&mut self, client_id: ClientId, tx_id: TxId, tx: TxState<A>) -> Result<()>;
+    /// Returns every transaction of the client specified by `client_id`, in order of
+    /// transaction id.
+    fn list(&self, client_id: ClientId) -> impl Iterator<Item = (TxId, TxState<A>)>;
+    /// Returns the id of every stored transaction, of any client, in no particular
+    /// order. By default each client's transactions are listed in turn, which stores
+    /// able to read every transaction in one pass override.
+    fn tx_ids(&self) -> impl Iterator<Item = TxId> {
+        let tx_ids: Vec<TxId> = self
+            .clients()
+            .into_iter()
+            .flat_map(|client_id| self.list(client_id).map(|(tx_id, _)| tx_id))
+            .collect();
+        tx_ids.into_iter()
+    }
+    /// Returns the currency of the transaction specified by `tx_id`, or `None` if it is
+    /// in the base currency or does not exist.
+    fn currency(&self, tx_id: TxId) -> Option<Currency>;
+    /// Records that the stored transaction specified by `tx_id` is in `currency` rather
+    /// than the base currency.
+    fn set_currency(&mut self, tx_id: TxId, currency: Currency) -> Result<()>;
+    /// Returns the time at which the transaction specified by `tx_id` occurred, in
+    /// seconds since the Unix epoch, if it was recorded.
+    fn timestamp(&self, tx_id: TxId) -> Option<u64>;
+    /// Records that the stored transaction specified by `tx_id` occurred at
+    /// `timestamp`, in seconds since the Unix epoch.
+    fn set_timestamp(&mut self, tx_id: TxId, timestamp: u64) -> Result<()>;
+    /// Removes every settled transaction, as told by [`TxState::is_settled`], which
+    /// occurred before `before`, in seconds since the Unix epoch, returning how many
+    /// were removed. Transactions whose time was not recorded are kept. The ids of
+    /// removed transactions are remembered, so that storing a transaction with one of
+    /// them fails as it would if the transaction were still stored.
+    fn prune(&mut self, before: u64) -> Result<usize>;
+    /// Records that the transactions specified by `tx_ids` were pruned, so that they
+    /// can't be stored again, such as when restoring a store which was pruned before.
+    fn set_pruned(&mut self, tx_ids: RangeInclusive<TxId>) -> Result<()>;
+}
+
+/// Represents a client capable of storing and retrieving snapshots of client account
+/// balances, so that a later run, such as a service restarting mid-stream, carries on
+/// from the balances saved by an earlier one.
+///
+/// # Example
+/// ```
+/// use payments::storage::{Account, ClientStore, MemoryStore};
+/// use rust_decimal_macros::dec;
+///
+/// let mut store = MemoryStore::new();
+/// let account = Account {
+///     available: dec!(1.0),
+///     total: dec!(1.5),
+///     ..Account::default()
+/// };
+/// store.save_account(1337, account.clone()).unwrap();
+///
+/// assert_eq!(store.account(1337), Some(account));
+/// assert_eq!(store.clients(), vec![1337]);
+/// ```
+pub trait ClientStore<A = Decimal> {
+    /// Returns the saved account balances of the client specified by `client_id`, if
+    /// any.
+    fn account(&self, client_id: ClientId) -> Option<Account<A>>;
+    /// Saves the account balances of the client specified by `client_id`, replacing
+    /// any previously saved.
+    fn save_account(&mut self, client_id: ClientId, account: Account<A>) -> Result<()>;
+    /// Returns the ids of every client with saved account balances, in no particular
+    /// order.
+    fn clients(&self) -> Vec<ClientId>;
+}
+
+/// Represents a client capable of storing and retrieving transactions and the
+/// balances of client accounts without blocking, for stores whose every call waits on
+/// the network. Its methods mirror those of [`TxStore`] and [`ClientStore`].
+#[cfg(feature = "async")]
+pub trait AsyncTxStore {
     /// Returns the requested transaction specified by `tx_id` for the client
     /// specified by `client_id`, if both exist.
-    fn get(&self, client_id: u16, tx_id: u32) -> Option<TxState>;
+    fn get(&self, client_id: ClientId, tx_id: TxId)
+        -> impl Future<Output = Option<TxState>> + Send;
     /// Inserts a new transaction, or updates an existing transaction, specified by
     /// `tx_id`, for the client specified by `client_id`.
-    fn upsert(&mut self, client_id: u16, tx_id: u32, tx: TxState) -> Result<()>;
+    fn upsert(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TxId,
+        tx: TxState,
+    ) -> impl Future<Output = Result<()>> + Send;
+    /// Returns every transaction of the client specified by `client_id`, in order of
+    /// transaction id.
+    fn list(&self, client_id: ClientId) -> impl Future<Output = Vec<(TxId, TxState)>> + Send;
+    /// Returns the saved account balances of the client specified by `client_id`, if
+    /// any.
+    fn account(&self, client_id: ClientId) -> impl Future<Output = Option<Account>> + Send;
+    /// Saves the account balances of the client specified by `client_id`, replacing
+    /// any previously saved.
+    fn save_account(
+        &mut self,
+        client_id: ClientId,
+        account: Account,
+    ) -> impl Future<Output = Result<()>> + Send;
+    /// Returns the ids of every client with saved account balances, in no particular
+    /// order.
+    fn clients(&self) -> impl Future<Output = Vec<ClientId>> + Send;
+    /// Returns the currency of the transaction specified by `tx_id`, or `None` if it is
+    /// in the base currency or does not exist.
+    fn currency(&self, tx_id: TxId) -> impl Future<Output = Option<Currency>> + Send;
+    /// Records that the stored transaction specified by `tx_id` is in `currency` rather
+    /// than the base currency.
+    fn set_currency(
+        &mut self,
+        tx_id: TxId,
+        currency: Currency,
+    ) -> impl Future<Output = Result<()>> + Send;
+    /// Returns the time at which the transaction specified by `tx_id` occurred, in
+    /// seconds since the Unix epoch, if it was recorded.
+    fn timestamp(&self, tx_id: TxId) -> impl Future<Output = Option<u64>> + Send;
+    /// Records that the stored transaction specified by `tx_id` occurred at
+    /// `timestamp`, in seconds since the Unix epoch.
+    fn set_timestamp(
+        &mut self,
+        tx_id: TxId,
+        timestamp: u64,
+    ) -> impl Future<Output = Result<()>> + Send;
+    /// Removes every settled transaction, as told by [`TxState::is_settled`], which
+    /// occurred before `before`, in seconds since the Unix epoch, returning how many
+    /// were removed. Transactions whose time was not recorded are kept, and the ids of
+    /// removed transactions can't be stored again.
+    fn prune(&mut self, before: u64) -> impl Future<Output = Result<usize>> + Send;
+    /// Records that the transactions specified by `tx_ids` were pruned, so that they
+    /// can't be stored again.
+    fn set_pruned(
+        &mut self,
+        tx_ids: RangeInclusive<TxId>,
+    ) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// The kinds of transaction store.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StoreKind {
+    /// A [`MemoryStore`].
+    Memory,
+    /// A [`SledStore`].
+    Sled,
+    /// A [`PostgresStore`].
+    Postgres,
+}
+
+impl FromStr for StoreKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<StoreKind> {
+        match s {
+            "memory" => Ok(StoreKind::Memory),
+            "sled" => Ok(StoreKind::Sled),
+            "postgres" => Ok(StoreKind::Postgres),
+            v => bail!("invalid store {:?}, expected memory, sled or postgres", v),
+        }
+    }
+}
+
+/// The balances of a client's account, as saved in a transaction store.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "SavedAccount<A>")]
+pub struct Account<A = Decimal> {
+    /// The funds available for withdrawal, in the base currency.
+    pub available: A,
+    /// The total funds available and held under dispute, in the base currency.
+    pub total: A,
+    /// The status of the account.
+    pub status: AccountStatus,
+    /// The balances held in currencies other than the base currency.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub currencies: BTreeMap<Currency, Balance<A>>,
+}
+
+/// An [`Account`] as read from a store, which may have been saved before account
+/// statuses were, with only whether the account was `locked` or `closed`.
+#[derive(Deserialize)]
+struct SavedAccount<A> {
+    available: A,
+    total: A,
+    #[serde(default)]
+    status: Option<AccountStatus>,
+    #[serde(default)]
+    locked: bool,
+    #[serde(default)]
+    closed: bool,
+    #[serde(default)]
+    currencies: BTreeMap<Currency, Balance<A>>,
+}
+
+impl<A> From<SavedAccount<A>> for Account<A> {
+    fn from(saved: SavedAccount<A>) -> Account<A> {
+        Account {
+            available: saved.available,
+            total: saved.total,
+            status: saved
+                .status
+                .unwrap_or_else(|| AccountStatus::from_flags(saved.locked, saved.closed)),
+            currencies: saved.currencies,
+        }
+    }
+}
+
+impl<A: Amount> Account<A> {
+    /// Returns the balance held in `currency`, or in the base currency if `None`.
+    pub fn balance(&self, currency: Option<Currency>) -> Balance<A> {
+        match currency {
+            None => Balance {
+                available: self.available,
+                total: self.total,
+            },
+            Some(currency) => self.currencies.get(&currency).copied().unwrap_or_default(),
+        }
+    }
+
+    /// Replaces the balance held in `currency`, or in the base currency if `None`.
+    pub fn set_balance(&mut self, currency: Option<Currency>, balance: Balance<A>) {
+        match currency {
+            None => {
+                self.available = balance.available;
+                self.total = balance.total;
+            }
+            Some(currency) => {
+                self.currencies.insert(currency, balance);
+            }
+        }
+    }
+}
+
+/// The funds an account holds in a single currency.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Balance<A = Decimal> {
+    /// The funds available for withdrawal.
+    pub available: A,
+    /// The total funds available and held under dispute.
+    pub total: A,
+}
+
+impl<A: Amount> Balance<A> {
+    /// Returns the funds held under dispute.
+    pub fn held(&self) -> A {
+        self.total - self.available
+    }
 }
 
 /// Defines the amount and current state of a transaction.
-#[derive(Clone, Debug)]
-pub enum TxState {
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TxState<A = Decimal> {
     /// A transaction whose funds available for withdrawal.
-    Deposit(f32),
+    Deposit(A),
     /// A transaction whose funds being held for dispute.
-    Dispute(f32),
+    Dispute(A),
+    /// A deposit only part of whose funds are held for dispute.
+    PartialDispute {
+        /// The funds held for dispute.
+        disputed: A,
+        /// The rest of the deposit's funds, which remain available.
+        undisputed: A,
+    },
     /// A transaction representing withdrawn funds.
-    Withdrawal,
+    Withdrawal(A),
+    /// A transaction whose funds were removed by a chargeback.
+    ChargedBack(A),
+    /// A deposit only part of whose funds were removed by a chargeback.
+    PartialChargedBack {
+        /// The funds removed by the chargeback.
+        charged_back: A,
+        /// The rest of the deposit's funds, which remain available.
+        undisputed: A,
+    },
+    /// A withdrawal whose funds are provisionally credited back to the client, held
+    /// for dispute.
+    WithdrawalDispute(A),
+    /// A withdrawal whose funds were returned to the client by a chargeback.
+    WithdrawalChargedBack(A),
+    /// A transaction representing funds transferred to another client.
+    Transfer(A),
+    /// A transaction which was charged back, whose funds were restored to the client by
+    /// a representment.
+    Represented(A),
+    /// An authorization whose funds are held until it is captured or voided.
+    Authorized(A),
+    /// An authorization whose funds were released without being captured.
+    Voided(A),
+}
+
+impl<A> TxState<A> {
+    /// Returns whether the transaction can only change by being disputed, so that once
+    /// it is older than the dispute window it never will. Transactions under dispute,
+    /// charged back transactions which may yet be represented, and authorizations which
+    /// may yet be captured or voided are not settled.
+    pub fn is_settled(&self) -> bool {
+        match self {
+            TxState::Deposit(_)
+            | TxState::Withdrawal(_)
+            | TxState::WithdrawalChargedBack(_)
+            | TxState::Transfer(_)
+            | TxState::Represented(_)
+            | TxState::Voided(_) => true,
+            TxState::Dispute(_)
+            | TxState::PartialDispute { .. }
+            | TxState::WithdrawalDispute(_)
+            | TxState::ChargedBack(_)
+            | TxState::PartialChargedBack { .. }
+            | TxState::Authorized(_) => false,
+        }
+    }
+
+    /// Returns whether the transaction was charged back, in full or in part, and not
+    /// since represented.
+    pub fn is_charged_back(&self) -> bool {
+        matches!(
+            self,
+            TxState::ChargedBack(_) | TxState::PartialChargedBack { .. }
+        )
+    }
+}
+
+/// The ids of pruned transactions, kept as ranges of consecutive ids so that ids
+/// counting up, as they usually do, take next to no memory however many are pruned.
+#[derive(Clone, Debug, Default)]
+struct Tombstones {
+    /// The first id of each range, mapped to its last.
+    ranges: BTreeMap<TxId, TxId>,
+}
+
+impl Tombstones {
+    /// Returns whether the transaction specified by `tx_id` was pruned.
+    fn contains(&self, tx_id: TxId) -> bool {
+        self.ranges
+            .range(..=tx_id)
+            .next_back()
+            .is_some_and(|(_, &last)| tx_id <= last)
+    }
+
+    /// Records that the transaction specified by `tx_id` was pruned.
+    fn insert(&mut self, tx_id: TxId) {
+        self.insert_range(tx_id..=tx_id);
+    }
+
+    /// Records that the transactions specified by `tx_ids` were pruned, joining the
+    /// ranges they overlap or adjoin.
+    fn insert_range(&mut self, tx_ids: RangeInclusive<TxId>) {
+        let (mut first, mut last) = tx_ids.into_inner();
+        if first > last {
+            return;
+        }
+        // ranges are disjoint, so those ending at or after `first` are the last few
+        // starting at or before `last`
+        let joined: Vec<_> = self
+            .ranges
+            .range(..=last.saturating_add(1))
+            .rev()
+            .take_while(|(_, &end)| end.saturating_add(1) >= first)
+            .map(|(&start, &end)| (start, end))
+            .collect();
+        for (start, end) in joined {
+            self.ranges.remove(&start);
+            first = first.min(start);
+            last = last.max(end);
+        }
+        self.ranges.insert(first, last);
+    }
+
+    /// Fails if the transaction specified by `tx_id` was pruned, so must not be stored
+    /// again.
+    fn check(&self, tx_id: TxId) -> Result<()> {
+        if self.contains(tx_id) {
+            bail!("cannot overwrite pruned transaction");
+        }
+        Ok(())
+    }
 }
 
 /// An in-memory transaction store backed by a [`HashMap`].
 ///
 /// # Example
 /// ```
-/// use payments::storage::{MemoryStore, TxState};
+/// use payments::storage::{MemoryStore, TxState, TxStore};
+/// use rust_decimal_macros::dec;
 ///
 /// let mut store = MemoryStore::new();
 ///
 /// // insert a transaction with available funds
-/// store.upsert(1337, 1, TxState::Deposit(1.0)).unwrap();
+/// store.upsert(1337, 1, TxState::Deposit(dec!(1.0))).unwrap();
 /// let tx = store.get(1337, 1).unwrap();
 ///
 /// // prints "Deposit(1.0)"
 /// println!("{:?}", tx);
 /// ```
 #[derive(Default, Debug)]
-pub struct MemoryStore {
+pub struct MemoryStore<A = Decimal> {
+    #[doc(hidden)]
+    transactions: HashMap<TxId, (ClientId, TxState<A>)>,
+    #[doc(hidden)]
+    accounts: HashMap<ClientId, Account<A>>,
+    #[doc(hidden)]
+    currencies: HashMap<TxId, Currency>,
+    #[doc(hidden)]
+    timestamps: HashMap<TxId, u64>,
     #[doc(hidden)]
-    transactions: HashMap<u32, (u16, TxState)>,
+    pruned: Tombstones,
 }
 
-impl MemoryStore {
-    pub fn new() -> Arc<Mutex<MemoryStore>> {
-        Arc::new(Mutex::new(MemoryStore {
-            transactions: HashMap::new(),
-        }))
+impl<A: Amount> MemoryStore<A> {
+    pub fn new() -> Arc<Mutex<MemoryStore<A>>> {
+        Arc::new(Mutex::new(MemoryStore::default()))
+    }
+
+    /// Returns the saved balances of every client account, in no particular order.
+    pub fn accounts(&self) -> impl Iterator<Item = (ClientId, &Account<A>)> {
+        self.accounts
+            .iter()
+            .map(|(client_id, account)| (*client_id, account))
+    }
+
+    /// Returns every stored transaction along with the client it belongs to, in no
+    /// particular order.
+    pub fn transactions(&self) -> impl Iterator<Item = (ClientId, TxId, &TxState<A>)> {
+        self.transactions
+            .iter()
+            .map(|(tx_id, (client_id, tx))| (*client_id, *tx_id, tx))
+    }
+
+    /// Returns the currency of the transaction specified by `tx_id`, or `None` if it is
+    /// in the base currency or does not exist.
+    pub fn currency(&self, tx_id: TxId) -> Option<Currency> {
+        self.currencies.get(&tx_id).copied()
+    }
+
+    /// Returns the time at which the transaction specified by `tx_id` occurred, if it
+    /// was recorded.
+    pub fn timestamp(&self, tx_id: TxId) -> Option<u64> {
+        self.timestamps.get(&tx_id).copied()
+    }
+
+    /// Returns the ids of every pruned transaction, as ranges of consecutive ids in
+    /// order.
+    pub fn pruned(&self) -> impl Iterator<Item = RangeInclusive<TxId>> + '_ {
+        self.pruned
+            .ranges
+            .iter()
+            .map(|(&first, &last)| first..=last)
+    }
+
+    /// Returns the transaction specified by `tx_id` if it belongs to the client
+    /// specified by `client_id`.
+    fn get(&self, client_id: ClientId, tx_id: TxId) -> Option<TxState<A>> {
+        match self.transactions.get(&tx_id) {
+            Some((cid, tx)) if *cid == client_id => Some(tx.clone()),
+            _ => None,
+        }
+    }
+
+    /// Inserts or updates the transaction specified by `tx_id`, unless it belongs to a
+    /// client other than the one specified by `client_id`.
+    fn upsert(&mut self, client_id: ClientId, tx_id: TxId, tx: TxState<A>) -> Result<()> {
+        if let Some((cid, _)) = self.transactions.get(&tx_id) {
+            if *cid != client_id {
+                bail!("transaction exists for different client");
+            }
+        }
+        self.pruned.check(tx_id)?;
+        self.transactions.insert(tx_id, (client_id, tx));
+        Ok(())
+    }
+
+    /// Returns the transactions of the client specified by `client_id`, in no
+    /// particular order.
+    fn list(&self, client_id: ClientId) -> Vec<(TxId, TxState<A>)> {
+        self.transactions()
+            .filter(|(cid, _, _)| *cid == client_id)
+            .map(|(_, tx_id, tx)| (tx_id, tx.clone()))
+            .collect()
+    }
+
+    /// Removes every settled transaction which occurred before `before`, remembering
+    /// their ids, returning how many were removed.
+    fn prune(&mut self, before: u64) -> usize {
+        let pruned: Vec<_> = self
+            .timestamps
+            .iter()
+            .filter(|(tx_id, timestamp)| {
+                **timestamp < before
+                    && self
+                        .transactions
+                        .get(tx_id)
+                        .is_some_and(|(_, tx)| tx.is_settled())
+            })
+            .map(|(tx_id, _)| *tx_id)
+            .collect();
+        for tx_id in &pruned {
+            self.transactions.remove(tx_id);
+            self.currencies.remove(tx_id);
+            self.timestamps.remove(tx_id);
+            self.pruned.insert(*tx_id);
+        }
+        pruned.len()
     }
 }
 
-impl TxStore for Arc<Mutex<MemoryStore>> {
-    fn get(&self, client_id: u16, tx_id: u32) -> Option<TxState> {
-        let (cid, tx) = self.lock().unwrap().transactions.get(&tx_id).cloned()?;
+impl<A: Amount> ClientStore<A> for Arc<Mutex<MemoryStore<A>>> {
+    fn account(&self, client_id: ClientId) -> Option<Account<A>> {
+        self.lock().unwrap().accounts.get(&client_id).cloned()
+    }
 
-        if cid != client_id {
-            None
-        } else {
-            Some(tx)
+    fn save_account(&mut self, client_id: ClientId, account: Account<A>) -> Result<()> {
+        self.lock().unwrap().accounts.insert(client_id, account);
+        Ok(())
+    }
+
+    fn clients(&self) -> Vec<ClientId> {
+        self.lock().unwrap().accounts.keys().copied().collect()
+    }
+}
+
+impl<A: Amount> TxStore<A> for Arc<Mutex<MemoryStore<A>>> {
+    fn get(&self, client_id: ClientId, tx_id: TxId) -> Option<TxState<A>> {
+        self.lock().unwrap().get(client_id, tx_id)
+    }
+
+    fn upsert(&mut self, client_id: ClientId, tx_id: TxId, tx: TxState<A>) -> Result<()> {
+        self.lock().unwrap().upsert(client_id, tx_id, tx)
+    }
+
+    fn list(&self, client_id: ClientId) -> impl Iterator<Item = (TxId, TxState<A>)> {
+        let mut transactions = self.lock().unwrap().list(client_id);
+        transactions.sort_unstable_by_key(|(tx_id, _)| *tx_id);
+        transactions.into_iter()
+    }
+
+    fn tx_ids(&self) -> impl Iterator<Item = TxId> {
+        let tx_ids: Vec<TxId> = self.lock().unwrap().transactions.keys().copied().collect();
+        tx_ids.into_iter()
+    }
+
+    fn currency(&self, tx_id: TxId) -> Option<Currency> {
+        self.lock().unwrap().currency(tx_id)
+    }
+
+    fn set_currency(&mut self, tx_id: TxId, currency: Currency) -> Result<()> {
+        self.lock().unwrap().currencies.insert(tx_id, currency);
+        Ok(())
+    }
+
+    fn timestamp(&self, tx_id: TxId) -> Option<u64> {
+        self.lock().unwrap().timestamp(tx_id)
+    }
+
+    fn set_timestamp(&mut self, tx_id: TxId, timestamp: u64) -> Result<()> {
+        self.lock().unwrap().timestamps.insert(tx_id, timestamp);
+        Ok(())
+    }
+
+    fn prune(&mut self, before: u64) -> Result<usize> {
+        Ok(self.lock().unwrap().prune(before))
+    }
+
+    fn set_pruned(&mut self, tx_ids: RangeInclusive<TxId>) -> Result<()> {
+        self.lock().unwrap().pruned.insert_range(tx_ids);
+        Ok(())
+    }
+}
+
+/// An in-memory transaction store split into shards, each a [`MemoryStore`] behind a
+/// lock of its own, so that threads storing the transactions of different clients
+/// rarely wait on each other, as they would on a single locked [`MemoryStore`].
+/// Transactions, along with their currencies and timestamps, are sharded by
+/// transaction id, and account balances by client id.
+///
+/// Clones share the same shards.
+///
+/// # Example
+/// ```
+/// use std::thread;
+///
+/// use payments::storage::{ShardedStore, TxState, TxStore};
+/// use rust_decimal_macros::dec;
+///
+/// let store = ShardedStore::new(16);
+/// thread::scope(|scope| {
+///     for client in 1..=4 {
+///         let mut store = store.clone();
+///         scope.spawn(move || {
+///             for tx in 0..100 {
+///                 let tx = client * 1000 + tx;
+///                 store.upsert(client, tx, TxState::Deposit(dec!(1.0))).unwrap();
+///             }
+///         });
+///     }
+/// });
+/// assert_eq!(store.get(4, 4099), Some(TxState::Deposit(dec!(1.0))));
+/// assert_eq!(store.list(2).count(), 100);
+/// ```
+#[derive(Debug)]
+pub struct ShardedStore<A = Decimal> {
+    #[doc(hidden)]
+    shards: Arc<[Mutex<MemoryStore<A>>]>,
+}
+
+impl<A> Clone for ShardedStore<A> {
+    fn clone(&self) -> ShardedStore<A> {
+        ShardedStore {
+            shards: Arc::clone(&self.shards),
+        }
+    }
+}
+
+impl<A: Amount> Default for ShardedStore<A> {
+    /// Creates a store with four shards for each thread the machine can run at once.
+    fn default() -> ShardedStore<A> {
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        ShardedStore::new(threads * 4)
+    }
+}
+
+impl<A: Amount> ShardedStore<A> {
+    /// Creates an empty store split into `shards` shards, of which there must be at
+    /// least one.
+    pub fn new(shards: usize) -> ShardedStore<A> {
+        assert!(shards > 0, "at least one shard is needed");
+        ShardedStore {
+            shards: (0..shards).map(|_| Mutex::default()).collect(),
         }
     }
 
-    fn upsert(&mut self, client_id: u16, tx_id: u32, tx: TxState) -> Result<()> {
-        let transactions = &mut self.lock().unwrap().transactions;
-        match transactions.get_mut(&tx_id) {
-            Some((cid, _)) => {
-                if *cid != client_id {
-                    bail!("transaction exists for different client");
-                }
+    /// Returns the number of shards the store is split into.
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Locks the shard holding the transaction or client account specified by `key`.
+    fn shard(&self, key: u64) -> MutexGuard<'_, MemoryStore<A>> {
+        let index = (key % self.shards.len() as u64) as usize;
+        self.shards[index].lock().unwrap()
+    }
+}
+
+impl<A: Amount> ClientStore<A> for ShardedStore<A> {
+    fn account(&self, client_id: ClientId) -> Option<Account<A>> {
+        self.shard(client_id).accounts.get(&client_id).cloned()
+    }
+
+    fn save_account(&mut self, client_id: ClientId, account: Account<A>) -> Result<()> {
+        self.shard(client_id).accounts.insert(client_id, account);
+        Ok(())
+    }
+
+    fn clients(&self) -> Vec<ClientId> {
+        self.shards
+            .iter()
+            .flat_map(|shard| {
+                shard
+                    .lock()
+                    .unwrap()
+                    .accounts
+                    .keys()
+                    .copied()
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+}
+
+impl<A: Amount> TxStore<A> for ShardedStore<A> {
+    fn get(&self, client_id: ClientId, tx_id: TxId) -> Option<TxState<A>> {
+        self.shard(tx_id).get(client_id, tx_id)
+    }
+
+    fn upsert(&mut self, client_id: ClientId, tx_id: TxId, tx: TxState<A>) -> Result<()> {
+        self.shard(tx_id).upsert(client_id, tx_id, tx)
+    }
+
+    fn list(&self, client_id: ClientId) -> impl Iterator<Item = (TxId, TxState<A>)> {
+        let mut transactions: Vec<_> = self
+            .shards
+            .iter()
+            .flat_map(|shard| shard.lock().unwrap().list(client_id))
+            .collect();
+        transactions.sort_unstable_by_key(|(tx_id, _)| *tx_id);
+        transactions.into_iter()
+    }
+
+    fn tx_ids(&self) -> impl Iterator<Item = TxId> {
+        let tx_ids: Vec<TxId> = self
+            .shards
+            .iter()
+            .flat_map(|shard| {
+                shard
+                    .lock()
+                    .unwrap()
+                    .transactions
+                    .keys()
+                    .copied()
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        tx_ids.into_iter()
+    }
+
+    fn currency(&self, tx_id: TxId) -> Option<Currency> {
+        self.shard(tx_id).currency(tx_id)
+    }
+
+    fn set_currency(&mut self, tx_id: TxId, currency: Currency) -> Result<()> {
+        self.shard(tx_id).currencies.insert(tx_id, currency);
+        Ok(())
+    }
+
+    fn timestamp(&self, tx_id: TxId) -> Option<u64> {
+        self.shard(tx_id).timestamp(tx_id)
+    }
+
+    fn set_timestamp(&mut self, tx_id: TxId, timestamp: u64) -> Result<()> {
+        self.shard(tx_id).timestamps.insert(tx_id, timestamp);
+        Ok(())
+    }
+
+    fn prune(&mut self, before: u64) -> Result<usize> {
+        Ok(self
+            .shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().prune(before))
+            .sum())
+    }
+
+    fn set_pruned(&mut self, tx_ids: RangeInclusive<TxId>) -> Result<()> {
+        // each shard only checks the ids sharded to it, so keeps the whole range
+        for shard in self.shards.iter() {
+            shard.lock().unwrap().pruned.insert_range(tx_ids.clone());
+        }
+        Ok(())
+    }
+}
+
+/// An in-memory transaction store keeping transactions in a fraction of the memory of
+/// a [`MemoryStore`], so that a day of a hundred million transactions fits in a few
+/// gigabytes.
+///
+/// Each transaction is packed into 16 bytes: its client, its amount in minor units of
+/// [`MAX_DECIMAL_PLACES`] decimal places, up to `u32::MAX` of them, and its state. While
+/// transaction ids are dense, as when they count up from one, packed transactions are
+/// kept in a vector indexed by transaction id rather than a map. Transactions whose ids
+/// are far beyond the others, whose amounts don't fit, or which are partially disputed
+/// are kept as a [`MemoryStore`] would. Amounts are read back with
+/// [`MAX_DECIMAL_PLACES`] decimal places.
+///
+/// Clones share the same transactions.
+///
+/// # Example
+/// ```
+/// use payments::storage::{CompactStore, TxState, TxStore};
+/// use rust_decimal_macros::dec;
+///
+/// let mut store = CompactStore::new();
+/// for tx in 1..=1000 {
+///     store.upsert(1337, tx, TxState::Deposit(dec!(1.25))).unwrap();
+/// }
+/// store.upsert(1337, 1, TxState::Dispute(dec!(1.25))).unwrap();
+///
+/// assert_eq!(store.packed(), 1000);
+/// assert_eq!(store.get(1337, 1), Some(TxState::Dispute(dec!(1.25))));
+/// assert_eq!(store.get(1, 2), None);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CompactStore {
+    #[doc(hidden)]
+    inner: Arc<Mutex<Compact>>,
+}
+
+#[derive(Debug, Default)]
+struct Compact {
+    /// The packed transactions, indexed by transaction id.
+    dense: Vec<PackedTx>,
+    /// The number of transactions in `dense`.
+    packed: usize,
+    /// The transactions which aren't packed.
+    sparse: HashMap<TxId, (ClientId, TxState)>,
+    accounts: HashMap<ClientId, Account>,
+    currencies: HashMap<TxId, Currency>,
+    timestamps: HashMap<TxId, u64>,
+    pruned: Tombstones,
+}
+
+/// How far beyond twice the number of packed transactions the vector of them may grow
+/// to pack a transaction, so that it stays mostly full however sparse transaction ids
+/// are.
+const DENSE_SLACK: usize = 1 << 16;
+
+/// A transaction packed into 16 bytes, or a vacant slot if its state is zero.
+#[derive(Clone, Copy, Debug, Default)]
+struct PackedTx {
+    client: ClientId,
+    /// The amount in minor units of [`MAX_DECIMAL_PLACES`] decimal places.
+    units: u32,
+    /// The variant of [`TxState`], counting from one.
+    state: u8,
+}
 
-                transactions.insert(tx_id, (client_id, tx));
-                Ok(())
+impl PackedTx {
+    /// Packs the transaction `tx` of the client specified by `client`, if it fits.
+    fn pack(client: ClientId, tx: &TxState) -> Option<PackedTx> {
+        let (state, amount) = match *tx {
+            TxState::Deposit(amount) => (1, amount),
+            TxState::Dispute(amount) => (2, amount),
+            TxState::PartialDispute { .. } | TxState::PartialChargedBack { .. } => return None,
+            TxState::Withdrawal(amount) => (3, amount),
+            TxState::ChargedBack(amount) => (4, amount),
+            TxState::WithdrawalDispute(amount) => (5, amount),
+            TxState::WithdrawalChargedBack(amount) => (6, amount),
+            TxState::Transfer(amount) => (7, amount),
+            TxState::Represented(amount) => (8, amount),
+            TxState::Authorized(amount) => (9, amount),
+            TxState::Voided(amount) => (10, amount),
+        };
+        let units = amount * Decimal::from(10u32.pow(MAX_DECIMAL_PLACES));
+        if !units.fract().is_zero() {
+            return None;
+        }
+        Some(PackedTx {
+            client,
+            units: units.to_u32()?,
+            state,
+        })
+    }
+
+    /// Returns whether the slot holds a transaction.
+    fn is_occupied(&self) -> bool {
+        self.state != 0
+    }
+
+    /// Returns the packed transaction.
+    fn unpack(&self) -> TxState {
+        let amount = Decimal::new(self.units.into(), MAX_DECIMAL_PLACES);
+        match self.state {
+            1 => TxState::Deposit(amount),
+            2 => TxState::Dispute(amount),
+            3 => TxState::Withdrawal(amount),
+            4 => TxState::ChargedBack(amount),
+            5 => TxState::WithdrawalDispute(amount),
+            6 => TxState::WithdrawalChargedBack(amount),
+            7 => TxState::Transfer(amount),
+            8 => TxState::Represented(amount),
+            9 => TxState::Authorized(amount),
+            10 => TxState::Voided(amount),
+            state => unreachable!("invalid packed transaction state {}", state),
+        }
+    }
+}
+
+impl CompactStore {
+    /// Creates an empty store.
+    pub fn new() -> CompactStore {
+        CompactStore::default()
+    }
+
+    /// Returns the number of transactions packed into the vector indexed by
+    /// transaction id, rather than kept in a map.
+    pub fn packed(&self) -> usize {
+        self.inner.lock().unwrap().packed
+    }
+}
+
+impl Compact {
+    /// Returns the packed slot of the transaction specified by `tx_id`, if it is
+    /// occupied.
+    fn slot(&self, tx_id: TxId) -> Option<&PackedTx> {
+        let index = usize::try_from(tx_id).ok()?;
+        self.dense.get(index).filter(|slot| slot.is_occupied())
+    }
+
+    /// Packs `tx` into the slot of `tx_id`, growing the vector of packed transactions
+    /// if it stays mostly full, returning whether it was packed.
+    fn pack(&mut self, client_id: ClientId, tx_id: TxId, tx: &TxState) -> bool {
+        let (Some(packed), Ok(index)) = (PackedTx::pack(client_id, tx), usize::try_from(tx_id))
+        else {
+            return false;
+        };
+        if index >= self.dense.len() {
+            if index > 2 * self.packed + DENSE_SLACK {
+                return false;
             }
-            None => {
-                transactions.insert(tx_id, (client_id, tx));
-                Ok(())
+            self.dense.resize(index + 1, PackedTx::default());
+        }
+        if !self.dense[index].is_occupied() {
+            self.packed += 1;
+        }
+        self.dense[index] = packed;
+        true
+    }
+}
+
+impl ClientStore for CompactStore {
+    fn account(&self, client_id: ClientId) -> Option<Account> {
+        self.inner.lock().unwrap().accounts.get(&client_id).cloned()
+    }
+
+    fn save_account(&mut self, client_id: ClientId, account: Account) -> Result<()> {
+        self.inner
+            .lock()
+            .unwrap()
+            .accounts
+            .insert(client_id, account);
+        Ok(())
+    }
+
+    fn clients(&self) -> Vec<ClientId> {
+        self.inner
+            .lock()
+            .unwrap()
+            .accounts
+            .keys()
+            .copied()
+            .collect()
+    }
+}
+
+impl TxStore for CompactStore {
+    fn get(&self, client_id: ClientId, tx_id: TxId) -> Option<TxState> {
+        let inner = self.inner.lock().unwrap();
+        match inner.slot(tx_id) {
+            Some(slot) => (slot.client == client_id).then(|| slot.unpack()),
+            None => match inner.sparse.get(&tx_id) {
+                Some((cid, tx)) if *cid == client_id => Some(tx.clone()),
+                _ => None,
+            },
+        }
+    }
+
+    fn upsert(&mut self, client_id: ClientId, tx_id: TxId, tx: TxState) -> Result<()> {
+        let inner = &mut *self.inner.lock().unwrap();
+        let owner = match inner.slot(tx_id) {
+            Some(slot) => Some(slot.client),
+            None => inner.sparse.get(&tx_id).map(|(cid, _)| *cid),
+        };
+        if owner.is_some_and(|cid| cid != client_id) {
+            bail!("transaction exists for different client");
+        }
+        inner.pruned.check(tx_id)?;
+        if inner.sparse.contains_key(&tx_id) || !inner.pack(client_id, tx_id, &tx) {
+            // a transaction which no longer fits moves out of its packed slot
+            if inner.slot(tx_id).is_some() {
+                inner.dense[tx_id as usize] = PackedTx::default();
+                inner.packed -= 1;
+            }
+            inner.sparse.insert(tx_id, (client_id, tx));
+        }
+        Ok(())
+    }
+
+    fn list(&self, client_id: ClientId) -> impl Iterator<Item = (TxId, TxState)> {
+        let inner = self.inner.lock().unwrap();
+        let packed = inner
+            .dense
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.is_occupied() && slot.client == client_id)
+            .map(|(tx_id, slot)| (tx_id as TxId, slot.unpack()));
+        let sparse = inner
+            .sparse
+            .iter()
+            .filter(|(_, (cid, _))| *cid == client_id)
+            .map(|(tx_id, (_, tx))| (*tx_id, tx.clone()));
+        let mut transactions: Vec<_> = packed.chain(sparse).collect();
+        transactions.sort_unstable_by_key(|(tx_id, _)| *tx_id);
+        transactions.into_iter()
+    }
+
+    fn tx_ids(&self) -> impl Iterator<Item = TxId> {
+        let inner = self.inner.lock().unwrap();
+        let tx_ids: Vec<TxId> = (0..)
+            .zip(&inner.dense)
+            .filter(|(_, slot)| slot.is_occupied())
+            .map(|(tx_id, _)| tx_id)
+            .chain(inner.sparse.keys().copied())
+            .collect();
+        tx_ids.into_iter()
+    }
+
+    fn currency(&self, tx_id: TxId) -> Option<Currency> {
+        self.inner.lock().unwrap().currencies.get(&tx_id).copied()
+    }
+
+    fn set_currency(&mut self, tx_id: TxId, currency: Currency) -> Result<()> {
+        self.inner
+            .lock()
+            .unwrap()
+            .currencies
+            .insert(tx_id, currency);
+        Ok(())
+    }
+
+    fn timestamp(&self, tx_id: TxId) -> Option<u64> {
+        self.inner.lock().unwrap().timestamps.get(&tx_id).copied()
+    }
+
+    fn set_timestamp(&mut self, tx_id: TxId, timestamp: u64) -> Result<()> {
+        self.inner
+            .lock()
+            .unwrap()
+            .timestamps
+            .insert(tx_id, timestamp);
+        Ok(())
+    }
+
+    fn prune(&mut self, before: u64) -> Result<usize> {
+        let inner = &mut *self.inner.lock().unwrap();
+        let mut pruned = Vec::new();
+        for (&tx_id, &timestamp) in &inner.timestamps {
+            if timestamp >= before {
+                continue;
+            }
+            let settled = match inner.slot(tx_id) {
+                Some(slot) => slot.unpack().is_settled(),
+                None => inner
+                    .sparse
+                    .get(&tx_id)
+                    .is_some_and(|(_, tx)| tx.is_settled()),
+            };
+            if settled {
+                pruned.push(tx_id);
+            }
+        }
+        for tx_id in &pruned {
+            if inner.slot(*tx_id).is_some() {
+                inner.dense[*tx_id as usize] = PackedTx::default();
+                inner.packed -= 1;
             }
+            inner.sparse.remove(tx_id);
+            inner.currencies.remove(tx_id);
+            inner.timestamps.remove(tx_id);
+            inner.pruned.insert(*tx_id);
+        }
+        Ok(pruned.len())
+    }
+
+    fn set_pruned(&mut self, tx_ids: RangeInclusive<TxId>) -> Result<()> {
+        self.inner.lock().unwrap().pruned.insert_range(tx_ids);
+        Ok(())
+    }
+}
+
+/// A transaction store which answers reads of transactions it has never stored from a
+/// bloom filter of their ids, rather than from the wrapped store, so that a store on
+/// disk or across the network is only read for transactions which most likely exist.
+///
+/// Most events are deposits and withdrawals of new transactions, whose duplicate checks
+/// find nothing; with the filter, about one in a hundred of them still reads the wrapped
+/// store while `expected` or fewer transactions are stored. The filter is seeded with
+/// the transactions of every client the wrapped store has saved balances of, and only
+/// knows of transactions stored since through the wrapper, so the wrapped store must not
+/// be written to otherwise.
+///
+/// Clones share the same filter.
+///
+/// # Example
+/// ```
+/// use payments::storage::{BloomStore, MemoryStore, TxState, TxStore};
+/// use rust_decimal_macros::dec;
+///
+/// let mut store = BloomStore::new(MemoryStore::new(), 1_000);
+/// store.upsert(1337, 1, TxState::Deposit(dec!(1.0))).unwrap();
+///
+/// assert_eq!(store.get(1337, 1), Some(TxState::Deposit(dec!(1.0))));
+/// assert_eq!(store.get(1337, 2), None);
+/// ```
+#[derive(Clone, Debug)]
+pub struct BloomStore<T> {
+    #[doc(hidden)]
+    inner: T,
+    #[doc(hidden)]
+    filter: Arc<BloomFilter>,
+}
+
+/// The number of bits set in a [`BloomFilter`] for each transaction id, which gives
+/// about one false positive in a hundred at its expected number of transactions.
+const BLOOM_HASHES: u64 = 7;
+
+/// The number of bits of a [`BloomFilter`] per expected transaction.
+const BLOOM_BITS_PER_TX: usize = 10;
+
+/// A set of transaction ids which may report ids it doesn't contain, but never misses
+/// one it does.
+#[derive(Debug)]
+struct BloomFilter {
+    bits: Box<[AtomicU64]>,
+}
+
+impl BloomFilter {
+    /// Creates an empty filter sized for `expected` transactions.
+    fn new(expected: usize) -> BloomFilter {
+        let words = (expected.max(1) * BLOOM_BITS_PER_TX).div_ceil(64);
+        BloomFilter {
+            bits: (0..words).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    /// Returns the bits set for `tx_id`, by double hashing.
+    fn indices(&self, tx_id: TxId) -> impl Iterator<Item = u64> {
+        let len = self.bits.len() as u64 * 64;
+        let h1 = mix(tx_id);
+        let h2 = mix(h1) | 1;
+        (0..BLOOM_HASHES).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % len)
+    }
+
+    fn insert(&self, tx_id: TxId) {
+        for bit in self.indices(tx_id) {
+            self.bits[(bit / 64) as usize].fetch_or(1 << (bit % 64), Ordering::Relaxed);
+        }
+    }
+
+    /// Returns whether `tx_id` may have been inserted.
+    fn contains(&self, tx_id: TxId) -> bool {
+        self.indices(tx_id).all(|bit| {
+            self.bits[(bit / 64) as usize].load(Ordering::Relaxed) & 1 << (bit % 64) != 0
+        })
+    }
+}
+
+/// Scrambles the bits of `x`, as the finalizer of splitmix64 does, so that sequential
+/// transaction ids set bits all over the filter.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+impl<T> BloomStore<T> {
+    /// Wraps `inner`, with a filter sized for `expected` transactions seeded with those
+    /// `inner` already stores.
+    pub fn new<A>(inner: T, expected: usize) -> BloomStore<T>
+    where
+        T: TxStore<A>,
+    {
+        let filter = BloomFilter::new(expected);
+        for tx_id in inner.tx_ids() {
+            filter.insert(tx_id);
+        }
+        BloomStore {
+            inner,
+            filter: Arc::new(filter),
+        }
+    }
+
+    /// Returns the wrapped store.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<A, T: ClientStore<A>> ClientStore<A> for BloomStore<T> {
+    fn account(&self, client_id: ClientId) -> Option<Account<A>> {
+        self.inner.account(client_id)
+    }
+
+    fn save_account(&mut self, client_id: ClientId, account: Account<A>) -> Result<()> {
+        self.inner.save_account(client_id, account)
+    }
+
+    fn clients(&self) -> Vec<ClientId> {
+        self.inner.clients()
+    }
+}
+
+impl<A, T: TxStore<A>> TxStore<A> for BloomStore<T> {
+    fn get(&self, client_id: ClientId, tx_id: TxId) -> Option<TxState<A>> {
+        if !self.filter.contains(tx_id) {
+            return None;
+        }
+        self.inner.get(client_id, tx_id)
+    }
+
+    fn upsert(&mut self, client_id: ClientId, tx_id: TxId, tx: TxState<A>) -> Result<()> {
+        // inserted first, so that a concurrent read never misses a stored transaction
+        self.filter.insert(tx_id);
+        self.inner.upsert(client_id, tx_id, tx)
+    }
+
+    fn list(&self, client_id: ClientId) -> impl Iterator<Item = (TxId, TxState<A>)> {
+        self.inner.list(client_id)
+    }
+
+    fn tx_ids(&self) -> impl Iterator<Item = TxId> {
+        self.inner.tx_ids()
+    }
+
+    fn currency(&self, tx_id: TxId) -> Option<Currency> {
+        self.inner.currency(tx_id)
+    }
+
+    fn set_currency(&mut self, tx_id: TxId, currency: Currency) -> Result<()> {
+        self.inner.set_currency(tx_id, currency)
+    }
+
+    fn timestamp(&self, tx_id: TxId) -> Option<u64> {
+        self.inner.timestamp(tx_id)
+    }
+
+    fn set_timestamp(&mut self, tx_id: TxId, timestamp: u64) -> Result<()> {
+        self.inner.set_timestamp(tx_id, timestamp)
+    }
+
+    fn prune(&mut self, before: u64) -> Result<usize> {
+        // pruned transactions stay in the filter, only costing a read when next looked up
+        self.inner.prune(before)
+    }
+
+    fn set_pruned(&mut self, tx_ids: RangeInclusive<TxId>) -> Result<()> {
+        self.inner.set_pruned(tx_ids)
+    }
+}
+
+/// A transaction store persisted to disk with [sled](https://sled.rs), so that memory
+/// use stays bounded however many transactions are stored, and a later run can carry on
+/// from the transactions and balances of an earlier one.
+///
+/// Clones share the same underlying database.
+///
+/// # Example
+/// ```
+/// use payments::storage::{SledStore, TxState, TxStore};
+/// use rust_decimal_macros::dec;
+///
+/// let path = std::env::temp_dir().join(format!("sled-doc-{}", std::process::id()));
+/// let mut store = SledStore::open(&path).unwrap();
+/// store.upsert(1337, 1, TxState::Deposit(dec!(1.0))).unwrap();
+/// store.flush().unwrap();
+/// drop(store);
+///
+/// let store = SledStore::open(&path).unwrap();
+/// assert_eq!(store.get(1337, 1), Some(TxState::Deposit(dec!(1.0))));
+/// # std::fs::remove_dir_all(&path).unwrap();
+/// ```
+#[cfg(feature = "sled")]
+#[derive(Clone, Debug)]
+pub struct SledStore {
+    #[doc(hidden)]
+    db: sled::Db,
+    #[doc(hidden)]
+    transactions: sled::Tree,
+    #[doc(hidden)]
+    accounts: sled::Tree,
+    #[doc(hidden)]
+    currencies: sled::Tree,
+    #[doc(hidden)]
+    timestamps: sled::Tree,
+    #[doc(hidden)]
+    pruned: sled::Tree,
+}
+
+#[cfg(feature = "sled")]
+impl SledStore {
+    /// Opens the store in the directory at `path`, creating it if it does not exist.
+    pub fn open(path: impl AsRef<Path>) -> Result<SledStore> {
+        let path = path.as_ref();
+        let db = sled::open(path).with_context(|| format!("opening {}", path.display()))?;
+        SledStore::with_db(db)
+    }
+
+    fn with_db(db: sled::Db) -> Result<SledStore> {
+        Ok(SledStore {
+            transactions: db.open_tree("transactions")?,
+            accounts: db.open_tree("accounts")?,
+            currencies: db.open_tree("currencies")?,
+            timestamps: db.open_tree("timestamps")?,
+            pruned: db.open_tree("pruned")?,
+            db,
+        })
+    }
+
+    /// Writes every change made so far to disk.
+    pub fn flush(&self) -> Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    /// Returns the range of pruned transactions stored under `key`, the first id of the
+    /// range, with its last id as the value. Ranges stored before they were kept as
+    /// such have an empty value, and cover only their first id.
+    fn pruned_range(key: &[u8], value: &[u8]) -> Result<RangeInclusive<TxId>> {
+        let first = TxId::from_be_bytes(key.try_into().context("decoding pruned id")?);
+        let last = match value {
+            [] => first,
+            value => TxId::from_be_bytes(value.try_into().context("decoding pruned id")?),
+        };
+        Ok(first..=last)
+    }
+
+    /// Returns whether the transaction specified by `tx_id` was pruned.
+    fn is_pruned(&self, tx_id: TxId) -> Result<bool> {
+        match self.pruned.range(..=tx_id.to_be_bytes()).next_back() {
+            Some(entry) => {
+                let (key, value) = entry?;
+                Ok(SledStore::pruned_range(&key, &value)?.contains(&tx_id))
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Records that the transactions specified by `tx_ids` were pruned, as one range
+    /// joining the stored ranges they overlap or adjoin, as [`Tombstones`] does.
+    fn insert_pruned(&mut self, tx_ids: RangeInclusive<TxId>) -> Result<()> {
+        let (mut first, mut last) = tx_ids.into_inner();
+        if first > last {
+            return Ok(());
+        }
+        let mut joined = Vec::new();
+        for entry in self
+            .pruned
+            .range(..=last.saturating_add(1).to_be_bytes())
+            .rev()
+        {
+            let (key, value) = entry?;
+            let range = SledStore::pruned_range(&key, &value)?;
+            if range.end().saturating_add(1) < first {
+                break;
+            }
+            first = first.min(*range.start());
+            last = last.max(*range.end());
+            joined.push(key);
+        }
+        // the joined range is stored first, so that no id is ever left unrecorded
+        self.pruned
+            .insert(first.to_be_bytes(), &last.to_be_bytes())?;
+        for key in joined {
+            if key.as_ref() != first.to_be_bytes() {
+                self.pruned.remove(key)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sled")]
+impl Default for SledStore {
+    /// Opens a temporary store, removed once every clone of it has been dropped.
+    fn default() -> SledStore {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        SledStore::with_db(db).unwrap()
+    }
+}
+
+// reading is infallible in the TxStore interface, but an unreadable transaction must not
+// be mistaken for a missing one, so read errors are fatal
+#[cfg(feature = "sled")]
+impl ClientStore for SledStore {
+    fn account(&self, client_id: ClientId) -> Option<Account> {
+        let value = self
+            .accounts
+            .get(client_id.to_be_bytes())
+            .expect("reading transaction store")?;
+        Some(serde_json::from_slice(&value).expect("decoding stored account"))
+    }
+
+    fn save_account(&mut self, client_id: ClientId, account: Account) -> Result<()> {
+        self.accounts
+            .insert(client_id.to_be_bytes(), serde_json::to_vec(&account)?)?;
+        Ok(())
+    }
+
+    fn clients(&self) -> Vec<ClientId> {
+        self.accounts
+            .iter()
+            .keys()
+            .map(|key| {
+                let key = key.expect("reading transaction store");
+                ClientId::from_be_bytes(key.as_ref().try_into().unwrap())
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "sled")]
+impl TxStore for SledStore {
+    fn get(&self, client_id: ClientId, tx_id: TxId) -> Option<TxState> {
+        let value = self
+            .transactions
+            .get(tx_id.to_be_bytes())
+            .expect("reading transaction store")?;
+        let (cid, tx): (ClientId, TxState) =
+            serde_json::from_slice(&value).expect("decoding stored transaction");
+
+        if cid != client_id {
+            None
+        } else {
+            Some(tx)
+        }
+    }
+
+    fn upsert(&mut self, client_id: ClientId, tx_id: TxId, tx: TxState) -> Result<()> {
+        if let Some(value) = self.transactions.get(tx_id.to_be_bytes())? {
+            let (cid, _): (ClientId, TxState) = serde_json::from_slice(&value)?;
+            if cid != client_id {
+                bail!("transaction exists for different client");
+            }
+        }
+        if self.is_pruned(tx_id)? {
+            bail!("cannot overwrite pruned transaction");
+        }
+        self.transactions
+            .insert(tx_id.to_be_bytes(), serde_json::to_vec(&(client_id, tx))?)?;
+        Ok(())
+    }
+
+    fn list(&self, client_id: ClientId) -> impl Iterator<Item = (TxId, TxState)> {
+        // keys are big-endian, so iterate in order of transaction id
+        self.transactions.iter().filter_map(move |entry| {
+            let (key, value) = entry.expect("reading transaction store");
+            let (cid, tx): (ClientId, TxState) =
+                serde_json::from_slice(&value).expect("decoding stored transaction");
+            (cid == client_id).then(|| (TxId::from_be_bytes(key.as_ref().try_into().unwrap()), tx))
+        })
+    }
+
+    fn tx_ids(&self) -> impl Iterator<Item = TxId> {
+        self.transactions.iter().keys().map(|key| {
+            let key = key.expect("reading transaction store");
+            TxId::from_be_bytes(key.as_ref().try_into().unwrap())
+        })
+    }
+
+    fn currency(&self, tx_id: TxId) -> Option<Currency> {
+        let value = self
+            .currencies
+            .get(tx_id.to_be_bytes())
+            .expect("reading transaction store")?;
+        Some(serde_json::from_slice(&value).expect("decoding stored currency"))
+    }
+
+    fn set_currency(&mut self, tx_id: TxId, currency: Currency) -> Result<()> {
+        self.currencies
+            .insert(tx_id.to_be_bytes(), serde_json::to_vec(&currency)?)?;
+        Ok(())
+    }
+
+    fn timestamp(&self, tx_id: TxId) -> Option<u64> {
+        let value = self
+            .timestamps
+            .get(tx_id.to_be_bytes())
+            .expect("reading transaction store")?;
+        Some(u64::from_be_bytes(
+            value
+                .as_ref()
+                .try_into()
+                .expect("decoding stored timestamp"),
+        ))
+    }
+
+    fn set_timestamp(&mut self, tx_id: TxId, timestamp: u64) -> Result<()> {
+        self.timestamps
+            .insert(tx_id.to_be_bytes(), &timestamp.to_be_bytes())?;
+        Ok(())
+    }
+
+    fn prune(&mut self, before: u64) -> Result<usize> {
+        let mut pruned = 0;
+        for entry in self.timestamps.iter() {
+            let (key, value) = entry?;
+            let timestamp = u64::from_be_bytes(
+                value
+                    .as_ref()
+                    .try_into()
+                    .context("decoding stored timestamp")?,
+            );
+            if timestamp >= before {
+                continue;
+            }
+            let Some(value) = self.transactions.get(&key)? else {
+                continue;
+            };
+            let (_, tx): (ClientId, TxState) = serde_json::from_slice(&value)?;
+            if tx.is_settled() {
+                self.transactions.remove(&key)?;
+                self.currencies.remove(&key)?;
+                self.timestamps.remove(&key)?;
+                let tx_id = TxId::from_be_bytes(key.as_ref().try_into()?);
+                self.insert_pruned(tx_id..=tx_id)?;
+                pruned += 1;
+            }
+        }
+        Ok(pruned)
+    }
+
+    fn set_pruned(&mut self, tx_ids: RangeInclusive<TxId>) -> Result<()> {
+        self.insert_pruned(tx_ids)
+    }
+}
+
+/// A transaction store keeping only the most recently used transactions in memory,
+/// spilling the rest to a temporary [`SledStore`] on disk, so that memory use stays
+/// bounded however many transactions are stored. Spilled transactions are moved back
+/// into memory when next used. Account balances, and the currencies and timestamps of
+/// transactions, are always kept in memory.
+///
+/// Clones share the same transactions.
+///
+/// # Example
+/// ```
+/// use payments::storage::{SpillStore, TxState, TxStore};
+/// use rust_decimal_macros::dec;
+///
+/// let mut store = SpillStore::new(1);
+/// store.upsert(1337, 1, TxState::Deposit(dec!(1.0))).unwrap();
+/// store.upsert(1337, 2, TxState::Deposit(dec!(2.0))).unwrap();
+///
+/// // the first transaction was spilled to disk to make room for the second
+/// assert_eq!(store.in_memory(), 1);
+/// assert_eq!(store.get(1337, 1), Some(TxState::Deposit(dec!(1.0))));
+/// ```
+#[cfg(feature = "sled")]
+#[derive(Clone, Debug)]
+pub struct SpillStore {
+    #[doc(hidden)]
+    inner: Arc<Mutex<Spill>>,
+}
+
+#[cfg(feature = "sled")]
+#[derive(Debug)]
+struct Spill {
+    /// The transactions kept in memory, along with when they were last used.
+    hot: HashMap<TxId, (ClientId, TxState, u64)>,
+    /// The transactions kept in memory, ordered from least to most recently used.
+    recency: BTreeMap<u64, TxId>,
+    /// Incremented whenever a transaction is used.
+    clock: u64,
+    /// The most transactions kept in memory.
+    capacity: usize,
+    cold: SledStore,
+    accounts: HashMap<ClientId, Account>,
+    currencies: HashMap<TxId, Currency>,
+    timestamps: HashMap<TxId, u64>,
+    pruned: Tombstones,
+}
+
+#[cfg(feature = "sled")]
+impl SpillStore {
+    /// Creates an empty store keeping at most `capacity` transactions in memory.
+    pub fn new(capacity: usize) -> SpillStore {
+        SpillStore {
+            inner: Arc::new(Mutex::new(Spill {
+                hot: HashMap::new(),
+                recency: BTreeMap::new(),
+                clock: 0,
+                capacity: capacity.max(1),
+                cold: SledStore::default(),
+                accounts: HashMap::new(),
+                currencies: HashMap::new(),
+                timestamps: HashMap::new(),
+                pruned: Tombstones::default(),
+            })),
+        }
+    }
+
+    /// Creates an empty store keeping as many transactions in memory as roughly fit in
+    /// `bytes`.
+    pub fn with_max_memory(bytes: usize) -> SpillStore {
+        // each transaction kept in memory has an entry in both maps, each of which
+        // needs about as much space again for its own bookkeeping
+        let entry = 2 * (size_of::<(TxId, (ClientId, TxState, u64))>() + size_of::<(u64, TxId)>());
+        SpillStore::new(bytes / entry)
+    }
+
+    /// Returns the number of transactions currently kept in memory.
+    pub fn in_memory(&self) -> usize {
+        self.inner.lock().unwrap().hot.len()
+    }
+}
+
+#[cfg(feature = "sled")]
+impl Spill {
+    /// Returns the transaction specified by `tx_id`, along with its client, moving it
+    /// into memory as the most recently used if it was spilled.
+    fn load(&mut self, tx_id: TxId) -> Result<Option<(ClientId, TxState)>> {
+        self.clock += 1;
+        if let Some((client_id, tx, used)) = self.hot.get_mut(&tx_id) {
+            self.recency.remove(used);
+            self.recency.insert(self.clock, tx_id);
+            *used = self.clock;
+            return Ok(Some((*client_id, tx.clone())));
+        }
+        let Some(value) = self.cold.transactions.remove(tx_id.to_be_bytes())? else {
+            return Ok(None);
+        };
+        let (client_id, tx): (ClientId, TxState) = serde_json::from_slice(&value)?;
+        self.store(client_id, tx_id, tx.clone())?;
+        Ok(Some((client_id, tx)))
+    }
+
+    /// Keeps a transaction in memory as the most recently used, spilling the least
+    /// recently used transaction to disk if there is no room for it.
+    fn store(&mut self, client_id: ClientId, tx_id: TxId, tx: TxState) -> Result<()> {
+        self.clock += 1;
+        if let Some((_, _, used)) = self.hot.insert(tx_id, (client_id, tx, self.clock)) {
+            self.recency.remove(&used);
+        }
+        self.recency.insert(self.clock, tx_id);
+        while self.hot.len() > self.capacity {
+            let (_, coldest) = self.recency.pop_first().unwrap();
+            let (client_id, tx, _) = self.hot.remove(&coldest).unwrap();
+            self.cold.upsert(client_id, coldest, tx)?;
+        }
+        Ok(())
+    }
+}
+
+// as with a SledStore, an unreadable spilled transaction must not be mistaken for a
+// missing one, so read errors are fatal
+#[cfg(feature = "sled")]
+impl ClientStore for SpillStore {
+    fn account(&self, client_id: ClientId) -> Option<Account> {
+        self.inner.lock().unwrap().accounts.get(&client_id).cloned()
+    }
+
+    fn save_account(&mut self, client_id: ClientId, account: Account) -> Result<()> {
+        self.inner
+            .lock()
+            .unwrap()
+            .accounts
+            .insert(client_id, account);
+        Ok(())
+    }
+
+    fn clients(&self) -> Vec<ClientId> {
+        self.inner
+            .lock()
+            .unwrap()
+            .accounts
+            .keys()
+            .copied()
+            .collect()
+    }
+}
+
+#[cfg(feature = "sled")]
+impl TxStore for SpillStore {
+    fn get(&self, client_id: ClientId, tx_id: TxId) -> Option<TxState> {
+        let (cid, tx) = self
+            .inner
+            .lock()
+            .unwrap()
+            .load(tx_id)
+            .expect("reading spilled transactions")?;
+
+        if cid != client_id {
+            None
+        } else {
+            Some(tx)
+        }
+    }
+
+    fn upsert(&mut self, client_id: ClientId, tx_id: TxId, tx: TxState) -> Result<()> {
+        let mut spill = self.inner.lock().unwrap();
+        if let Some((cid, _)) = spill.load(tx_id)? {
+            if cid != client_id {
+                bail!("transaction exists for different client");
+            }
+        }
+        spill.pruned.check(tx_id)?;
+        spill.store(client_id, tx_id, tx)
+    }
+
+    fn list(&self, client_id: ClientId) -> impl Iterator<Item = (TxId, TxState)> {
+        // listing doesn't count as using the transactions, so none are moved into memory
+        let spill = self.inner.lock().unwrap();
+        let mut transactions: Vec<_> = spill
+            .hot
+            .iter()
+            .filter(|(_, (cid, _, _))| *cid == client_id)
+            .map(|(tx_id, (_, tx, _))| (*tx_id, tx.clone()))
+            .chain(spill.cold.list(client_id))
+            .collect();
+        transactions.sort_unstable_by_key(|(tx_id, _)| *tx_id);
+        transactions.into_iter()
+    }
+
+    fn tx_ids(&self) -> impl Iterator<Item = TxId> {
+        let spill = self.inner.lock().unwrap();
+        let tx_ids: Vec<TxId> = spill
+            .hot
+            .keys()
+            .copied()
+            .chain(spill.cold.tx_ids())
+            .collect();
+        tx_ids.into_iter()
+    }
+
+    fn currency(&self, tx_id: TxId) -> Option<Currency> {
+        self.inner.lock().unwrap().currencies.get(&tx_id).copied()
+    }
+
+    fn set_currency(&mut self, tx_id: TxId, currency: Currency) -> Result<()> {
+        self.inner
+            .lock()
+            .unwrap()
+            .currencies
+            .insert(tx_id, currency);
+        Ok(())
+    }
+
+    fn timestamp(&self, tx_id: TxId) -> Option<u64> {
+        self.inner.lock().unwrap().timestamps.get(&tx_id).copied()
+    }
+
+    fn set_timestamp(&mut self, tx_id: TxId, timestamp: u64) -> Result<()> {
+        self.inner
+            .lock()
+            .unwrap()
+            .timestamps
+            .insert(tx_id, timestamp);
+        Ok(())
+    }
+
+    fn prune(&mut self, before: u64) -> Result<usize> {
+        // spilled transactions are pruned where they are, rather than moved back into
+        // memory
+        let spill = &mut *self.inner.lock().unwrap();
+        let mut pruned = Vec::new();
+        for (&tx_id, &timestamp) in &spill.timestamps {
+            if timestamp >= before {
+                continue;
+            }
+            let settled = match spill.hot.get(&tx_id) {
+                Some((_, tx, _)) => tx.is_settled(),
+                None => match spill.cold.transactions.get(tx_id.to_be_bytes())? {
+                    Some(value) => serde_json::from_slice::<(ClientId, TxState)>(&value)?
+                        .1
+                        .is_settled(),
+                    None => false,
+                },
+            };
+            if settled {
+                pruned.push(tx_id);
+            }
+        }
+        for tx_id in &pruned {
+            match spill.hot.remove(tx_id) {
+                Some((_, _, used)) => {
+                    spill.recency.remove(&used);
+                }
+                None => {
+                    spill.cold.transactions.remove(tx_id.to_be_bytes())?;
+                }
+            }
+            spill.currencies.remove(tx_id);
+            spill.timestamps.remove(tx_id);
+            spill.pruned.insert(*tx_id);
+        }
+        Ok(pruned.len())
+    }
+
+    fn set_pruned(&mut self, tx_ids: RangeInclusive<TxId>) -> Result<()> {
+        self.inner.lock().unwrap().pruned.insert_range(tx_ids);
+        Ok(())
+    }
+}
+
+/// The tables a [`PostgresStore`] keeps transactions and account balances in, with
+/// the balances of accounts in currencies other than the base currency, and the ids of
+/// pruned transactions, kept apart.
+#[cfg(feature = "postgres")]
+const POSTGRES_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS transactions (
+        tx BIGINT PRIMARY KEY,
+        client BIGINT NOT NULL,
+        state TEXT NOT NULL,
+        amount NUMERIC
+    );
+    CREATE INDEX IF NOT EXISTS transactions_client ON transactions (client);
+    CREATE TABLE IF NOT EXISTS accounts (
+        client BIGINT PRIMARY KEY,
+        available NUMERIC NOT NULL,
+        total NUMERIC NOT NULL,
+        locked BOOLEAN NOT NULL
+    );
+    ALTER TABLE transactions ADD COLUMN IF NOT EXISTS currency TEXT;
+    ALTER TABLE transactions ADD COLUMN IF NOT EXISTS timestamp BIGINT;
+    ALTER TABLE transactions ADD COLUMN IF NOT EXISTS undisputed NUMERIC;
+    ALTER TABLE accounts ADD COLUMN IF NOT EXISTS closed BOOLEAN NOT NULL DEFAULT FALSE;
+    ALTER TABLE accounts ADD COLUMN IF NOT EXISTS status TEXT;
+    CREATE TABLE IF NOT EXISTS balances (
+        client BIGINT NOT NULL,
+        currency TEXT NOT NULL,
+        available NUMERIC NOT NULL,
+        total NUMERIC NOT NULL,
+        PRIMARY KEY (client, currency)
+    );
+    CREATE TABLE IF NOT EXISTS pruned_transactions (
+        tx BIGINT PRIMARY KEY
+    );
+";
+
+/// Converts an id to a BIGINT column value. Ids are stored bit for bit, so those which
+/// do not fit in a signed BIGINT are stored as negative numbers.
+#[cfg(feature = "postgres")]
+fn sql_id(id: u64) -> i64 {
+    id as i64
+}
+
+/// Splits a transaction into the `state`, `amount` and `undisputed` columns of its
+/// row. Only partial disputes and chargebacks have undisputed funds.
+#[cfg(feature = "postgres")]
+fn tx_row(tx: &TxState) -> (&'static str, Option<Decimal>, Option<Decimal>) {
+    match *tx {
+        TxState::Deposit(amount) => ("deposit", Some(amount), None),
+        TxState::Dispute(amount) => ("dispute", Some(amount), None),
+        TxState::PartialDispute {
+            disputed,
+            undisputed,
+        } => ("partial_dispute", Some(disputed), Some(undisputed)),
+        TxState::Withdrawal(amount) => ("withdrawal", Some(amount), None),
+        TxState::ChargedBack(amount) => ("charged_back", Some(amount), None),
+        TxState::PartialChargedBack {
+            charged_back,
+            undisputed,
+        } => ("partial_charged_back", Some(charged_back), Some(undisputed)),
+        TxState::WithdrawalDispute(amount) => ("withdrawal_dispute", Some(amount), None),
+        TxState::WithdrawalChargedBack(amount) => ("withdrawal_charged_back", Some(amount), None),
+        TxState::Transfer(amount) => ("transfer", Some(amount), None),
+        TxState::Represented(amount) => ("represented", Some(amount), None),
+        TxState::Authorized(amount) => ("authorized", Some(amount), None),
+        TxState::Voided(amount) => ("voided", Some(amount), None),
+    }
+}
+
+/// Reads a transaction from the `state`, `amount` and `undisputed` columns of its row.
+#[cfg(feature = "postgres")]
+fn tx_from_row(
+    state: &str,
+    amount: Option<Decimal>,
+    undisputed: Option<Decimal>,
+) -> Result<TxState> {
+    match (state, amount, undisputed) {
+        ("deposit", Some(amount), None) => Ok(TxState::Deposit(amount)),
+        ("dispute", Some(amount), None) => Ok(TxState::Dispute(amount)),
+        ("partial_dispute", Some(disputed), Some(undisputed)) => Ok(TxState::PartialDispute {
+            disputed,
+            undisputed,
+        }),
+        ("withdrawal", Some(amount), None) => Ok(TxState::Withdrawal(amount)),
+        ("charged_back", Some(amount), None) => Ok(TxState::ChargedBack(amount)),
+        ("partial_charged_back", Some(charged_back), Some(undisputed)) => {
+            Ok(TxState::PartialChargedBack {
+                charged_back,
+                undisputed,
+            })
+        }
+        ("withdrawal_dispute", Some(amount), None) => Ok(TxState::WithdrawalDispute(amount)),
+        ("withdrawal_charged_back", Some(amount), None) => {
+            Ok(TxState::WithdrawalChargedBack(amount))
+        }
+        ("transfer", Some(amount), None) => Ok(TxState::Transfer(amount)),
+        ("represented", Some(amount), None) => Ok(TxState::Represented(amount)),
+        ("authorized", Some(amount), None) => Ok(TxState::Authorized(amount)),
+        ("voided", Some(amount), None) => Ok(TxState::Voided(amount)),
+        (state, amount, undisputed) => bail!(
+            "invalid stored transaction {:?} of {:?} with {:?} undisputed",
+            state,
+            amount,
+            undisputed
+        ),
+    }
+}
+
+/// A transaction store kept in a PostgreSQL database through a pool of connections, so
+/// that several processes can share one ledger.
+///
+/// Each transaction row is scoped to its client, and a transaction id is only ever
+/// claimed by one client, whichever process stores it first. Clients' balances are held
+/// by the process applying their events, so each client's events should only be
+/// applied by one process at a time.
+///
+/// Clones share the same pool of connections.
+#[cfg(feature = "postgres")]
+#[derive(Clone, Debug)]
+pub struct PostgresStore {
+    #[doc(hidden)]
+    pool: r2d2::Pool<PostgresConnectionManager<NoTls>>,
+}
+
+#[cfg(feature = "postgres")]
+impl PostgresStore {
+    /// Connects to the database at `dsn`, e.g. "host=localhost user=payments", with a
+    /// pool of up to `pool_size` connections, creating the store's tables if they do
+    /// not exist.
+    pub fn connect(dsn: &str, pool_size: u32) -> Result<PostgresStore> {
+        let config: postgres::Config = dsn.parse().context("parsing dsn")?;
+        let pool = r2d2::Pool::builder()
+            .max_size(pool_size)
+            .build(PostgresConnectionManager::new(config, NoTls))
+            .context("connecting to transaction store")?;
+        pool.get()?.batch_execute(POSTGRES_SCHEMA)?;
+        Ok(PostgresStore { pool })
+    }
+
+    fn connection(&self) -> r2d2::PooledConnection<PostgresConnectionManager<NoTls>> {
+        self.pool.get().expect("connecting to transaction store")
+    }
+}
+
+// as with SledStore, read errors are fatal rather than mistaken for missing rows
+#[cfg(feature = "postgres")]
+impl ClientStore for PostgresStore {
+    fn account(&self, client_id: ClientId) -> Option<Account> {
+        let row = self
+            .connection()
+            .query_opt(
+                "SELECT available, total, locked, closed, status FROM accounts WHERE client = $1",
+                &[&sql_id(client_id)],
+            )
+            .expect("reading transaction store")?;
+        let currencies = self
+            .connection()
+            .query(
+                "SELECT currency, available, total FROM balances WHERE client = $1",
+                &[&sql_id(client_id)],
+            )
+            .expect("reading transaction store")
+            .iter()
+            .map(|row| {
+                let currency: &str = row.get(0);
+                let balance = Balance {
+                    available: row.get(1),
+                    total: row.get(2),
+                };
+                (currency.parse().expect("decoding stored currency"), balance)
+            })
+            .collect();
+        Some(Account {
+            available: row.get(0),
+            total: row.get(1),
+            // rows saved before account statuses were only have the locked and closed
+            // columns, which are kept up to date for other readers of the table
+            status: match row.get::<_, Option<&str>>(4) {
+                Some(status) => status.parse().expect("decoding stored account"),
+                None => AccountStatus::from_flags(row.get(2), row.get(3)),
+            },
+            currencies,
+        })
+    }
+
+    fn save_account(&mut self, client_id: ClientId, account: Account) -> Result<()> {
+        self.pool.get()?.execute(
+            "INSERT INTO accounts (client, available, total, locked, closed, status)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (client) DO UPDATE SET available = EXCLUDED.available,
+                 total = EXCLUDED.total, locked = EXCLUDED.locked, closed = EXCLUDED.closed,
+                 status = EXCLUDED.status",
+            &[
+                &sql_id(client_id),
+                &account.available,
+                &account.total,
+                &(account.status == AccountStatus::Frozen),
+                &(account.status == AccountStatus::Closed),
+                &account.status.name(),
+            ],
+        )?;
+        for (currency, balance) in &account.currencies {
+            self.pool.get()?.execute(
+                "INSERT INTO balances (client, currency, available, total)
+                 VALUES ($1, $2, $3, $4)
+                 ON CONFLICT (client, currency) DO UPDATE
+                 SET available = EXCLUDED.available, total = EXCLUDED.total",
+                &[
+                    &sql_id(client_id),
+                    &currency.as_str(),
+                    &balance.available,
+                    &balance.total,
+                ],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn clients(&self) -> Vec<ClientId> {
+        self.connection()
+            .query("SELECT client FROM accounts", &[])
+            .expect("reading transaction store")
+            .iter()
+            .map(|row| row.get::<_, i64>(0) as ClientId)
+            .collect()
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl TxStore for PostgresStore {
+    fn get(&self, client_id: ClientId, tx_id: TxId) -> Option<TxState> {
+        let row = self
+            .connection()
+            .query_opt(
+                "SELECT state, amount, undisputed FROM transactions
+                 WHERE tx = $1 AND client = $2",
+                &[&sql_id(tx_id), &sql_id(client_id)],
+            )
+            .expect("reading transaction store")?;
+        Some(tx_from_row(row.get(0), row.get(1), row.get(2)).expect("decoding stored transaction"))
+    }
+
+    fn upsert(&mut self, client_id: ClientId, tx_id: TxId, tx: TxState) -> Result<()> {
+        let (state, amount, undisputed) = tx_row(&tx);
+        // a single statement, so that processes racing to store the same transaction
+        // id can't both succeed for different clients
+        let stored = self.pool.get()?.execute(
+            "INSERT INTO transactions (tx, client, state, amount, undisputed)
+             SELECT $1::BIGINT, $2::BIGINT, $3::TEXT, $4::NUMERIC, $5::NUMERIC
+             WHERE NOT EXISTS (SELECT 1 FROM pruned_transactions WHERE tx = $1)
+             ON CONFLICT (tx) DO UPDATE SET state = EXCLUDED.state, amount = EXCLUDED.amount,
+                 undisputed = EXCLUDED.undisputed
+             WHERE transactions.client = EXCLUDED.client",
+            &[
+                &sql_id(tx_id),
+                &sql_id(client_id),
+                &state,
+                &amount,
+                &undisputed,
+            ],
+        )?;
+        if stored == 0 {
+            let pruned = self.pool.get()?.query_opt(
+                "SELECT 1 FROM pruned_transactions WHERE tx = $1",
+                &[&sql_id(tx_id)],
+            )?;
+            match pruned {
+                Some(_) => bail!("cannot overwrite pruned transaction"),
+                None => bail!("transaction exists for different client"),
+            }
+        }
+        Ok(())
+    }
+
+    fn list(&self, client_id: ClientId) -> impl Iterator<Item = (TxId, TxState)> {
+        let mut transactions: Vec<_> = self
+            .connection()
+            .query(
+                "SELECT tx, state, amount, undisputed FROM transactions WHERE client = $1",
+                &[&sql_id(client_id)],
+            )
+            .expect("reading transaction store")
+            .iter()
+            .map(|row| {
+                let tx = tx_from_row(row.get(1), row.get(2), row.get(3))
+                    .expect("decoding stored transaction");
+                (row.get::<_, i64>(0) as TxId, tx)
+            })
+            .collect();
+        // ids too large for a BIGINT are stored as negative numbers, so are sorted here
+        transactions.sort_unstable_by_key(|(tx_id, _)| *tx_id);
+        transactions.into_iter()
+    }
+
+    fn tx_ids(&self) -> impl Iterator<Item = TxId> {
+        let tx_ids: Vec<TxId> = self
+            .connection()
+            .query("SELECT tx FROM transactions", &[])
+            .expect("reading transaction store")
+            .iter()
+            .map(|row| row.get::<_, i64>(0) as TxId)
+            .collect();
+        tx_ids.into_iter()
+    }
+
+    fn currency(&self, tx_id: TxId) -> Option<Currency> {
+        let row = self
+            .connection()
+            .query_opt(
+                "SELECT currency FROM transactions WHERE tx = $1",
+                &[&sql_id(tx_id)],
+            )
+            .expect("reading transaction store")?;
+        let currency: Option<&str> = row.get(0);
+        currency.map(|currency| currency.parse().expect("decoding stored currency"))
+    }
+
+    fn set_currency(&mut self, tx_id: TxId, currency: Currency) -> Result<()> {
+        self.pool.get()?.execute(
+            "UPDATE transactions SET currency = $2 WHERE tx = $1",
+            &[&sql_id(tx_id), &currency.as_str()],
+        )?;
+        Ok(())
+    }
+
+    fn timestamp(&self, tx_id: TxId) -> Option<u64> {
+        let row = self
+            .connection()
+            .query_opt(
+                "SELECT timestamp FROM transactions WHERE tx = $1",
+                &[&sql_id(tx_id)],
+            )
+            .expect("reading transaction store")?;
+        row.get::<_, Option<i64>>(0)
+            .map(|timestamp| timestamp as u64)
+    }
+
+    fn set_timestamp(&mut self, tx_id: TxId, timestamp: u64) -> Result<()> {
+        self.pool.get()?.execute(
+            "UPDATE transactions SET timestamp = $2 WHERE tx = $1",
+            &[&sql_id(tx_id), &sql_id(timestamp)],
+        )?;
+        Ok(())
+    }
+
+    fn prune(&mut self, before: u64) -> Result<usize> {
+        let pruned = self.pool.get()?.execute(
+            "WITH pruned AS (
+                 DELETE FROM transactions WHERE timestamp < $1 AND state IN
+                     ('deposit', 'withdrawal', 'withdrawal_charged_back', 'transfer',
+                      'represented', 'voided')
+                 RETURNING tx
+             )
+             INSERT INTO pruned_transactions SELECT tx FROM pruned ON CONFLICT DO NOTHING",
+            &[&sql_id(before)],
+        )?;
+        Ok(pruned as usize)
+    }
+
+    fn set_pruned(&mut self, tx_ids: RangeInclusive<TxId>) -> Result<()> {
+        let (first, last) = tx_ids.into_inner();
+        // ids too large for a BIGINT are stored as negative numbers, so a range crossing
+        // into them is stored as two
+        let wrap = i64::MAX as u64;
+        let mut ranges = vec![(first, last.min(wrap)), (first.max(wrap + 1), last)];
+        ranges.retain(|(first, last)| first <= last);
+        for (first, last) in ranges {
+            self.pool.get()?.execute(
+                "INSERT INTO pruned_transactions SELECT generate_series($1::BIGINT, $2::BIGINT)
+                 ON CONFLICT DO NOTHING",
+                &[&sql_id(first), &sql_id(last)],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Prunes settled transactions from a store once they occurred more than `horizon`
+/// seconds before the newest event applied to it, going by the timestamps of events, so
+/// that a long-running service only keeps the transactions which may still change. The
+/// horizon should be no shorter than the dispute window, beyond which settled
+/// transactions can't be disputed.
+///
+/// Pruning reads every recorded timestamp in the store, so is only done again once
+/// events have moved on by a hundredth of the horizon.
+///
+/// # Example
+/// ```
+/// use payments::storage::{MemoryStore, Pruner, TxState, TxStore};
+/// use rust_decimal_macros::dec;
+///
+/// let day = 24 * 60 * 60;
+/// let mut store = MemoryStore::new();
+/// store.upsert(1337, 1, TxState::Deposit(dec!(1.0))).unwrap();
+/// store.set_timestamp(1, 0).unwrap();
+///
+/// let mut pruner = Pruner::new(90 * day);
+/// assert_eq!(pruner.observe(&mut store, Some(30 * day)).unwrap(), 0);
+/// assert_eq!(pruner.observe(&mut store, Some(91 * day)).unwrap(), 1);
+/// assert_eq!(store.get(1337, 1), None);
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct Pruner {
+    #[doc(hidden)]
+    horizon: u64,
+    #[doc(hidden)]
+    next: Option<u64>,
+}
+
+impl Pruner {
+    /// Creates a pruner of transactions settled more than `horizon` seconds ago.
+    pub fn new(horizon: u64) -> Pruner {
+        Pruner {
+            horizon,
+            next: None,
+        }
+    }
+
+    /// Records that an event which occurred at `timestamp`, if it has one, was applied
+    /// to `store`, pruning the store if it is due, returning how many transactions were
+    /// pruned.
+    pub fn observe<A>(
+        &mut self,
+        store: &mut impl TxStore<A>,
+        timestamp: Option<u64>,
+    ) -> Result<usize> {
+        let Some(now) = timestamp else {
+            return Ok(0);
+        };
+        if self.next.is_some_and(|next| now < next) {
+            return Ok(0);
+        }
+        self.next = Some(now.saturating_add((self.horizon / 100).max(1)));
+        store.prune(now.saturating_sub(self.horizon))
+    }
+}
+
+/// Adapts a blocking [`TxStore`] into an [`AsyncTxStore`], running each call on
+/// tokio's blocking thread pool so that waiting on the store does not stall the
+/// tasks processing events. Must be used from within a tokio runtime.
+///
+/// Every call works on a clone of the store, so clones must share their state, as
+/// those of [`MemoryStore::new`], [`SledStore`] and [`PostgresStore`] do.
+///
+/// # Example
+/// ```
+/// use payments::storage::{AsyncTxStore, BlockingStore, MemoryStore, TxState};
+/// use rust_decimal_macros::dec;
+///
+/// let runtime = tokio::runtime::Runtime::new().unwrap();
+/// let mut store = BlockingStore::new(MemoryStore::new());
+/// runtime.block_on(async {
+///     store.upsert(1, 1, TxState::Deposit(dec!(1.0))).await.unwrap();
+///     assert_eq!(store.get(1, 1).await, Some(TxState::Deposit(dec!(1.0))));
+/// });
+/// ```
+#[cfg(feature = "async")]
+#[derive(Clone, Debug, Default)]
+pub struct BlockingStore<T> {
+    #[doc(hidden)]
+    store: T,
+}
+
+#[cfg(feature = "async")]
+impl<T: TxStore + Clone + Send + 'static> BlockingStore<T> {
+    /// Wraps `store` to be called without blocking.
+    pub fn new(store: T) -> BlockingStore<T> {
+        BlockingStore { store }
+    }
+
+    /// Returns the wrapped store.
+    pub fn into_inner(self) -> T {
+        self.store
+    }
+
+    /// Runs `f` against a clone of the store on the blocking thread pool.
+    async fn run<R: Send + 'static>(&self, f: impl FnOnce(T) -> R + Send + 'static) -> R {
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || f(store))
+            .await
+            .expect("transaction store call panicked")
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T: TxStore + Clone + Send + Sync + 'static> AsyncTxStore for BlockingStore<T> {
+    async fn get(&self, client_id: ClientId, tx_id: TxId) -> Option<TxState> {
+        self.run(move |store| store.get(client_id, tx_id)).await
+    }
+
+    async fn upsert(&mut self, client_id: ClientId, tx_id: TxId, tx: TxState) -> Result<()> {
+        self.run(move |mut store| store.upsert(client_id, tx_id, tx))
+            .await
+    }
+
+    async fn list(&self, client_id: ClientId) -> Vec<(TxId, TxState)> {
+        self.run(move |store| store.list(client_id).collect()).await
+    }
+
+    async fn account(&self, client_id: ClientId) -> Option<Account> {
+        self.run(move |store| store.account(client_id)).await
+    }
+
+    async fn save_account(&mut self, client_id: ClientId, account: Account) -> Result<()> {
+        self.run(move |mut store| store.save_account(client_id, account))
+            .await
+    }
+
+    async fn clients(&self) -> Vec<ClientId> {
+        self.run(|store| store.clients()).await
+    }
+
+    async fn currency(&self, tx_id: TxId) -> Option<Currency> {
+        self.run(move |store| store.currency(tx_id)).await
+    }
+
+    async fn set_currency(&mut self, tx_id: TxId, currency: Currency) -> Result<()> {
+        self.run(move |mut store| store.set_currency(tx_id, currency))
+            .await
+    }
+
+    async fn timestamp(&self, tx_id: TxId) -> Option<u64> {
+        self.run(move |store| store.timestamp(tx_id)).await
+    }
+
+    async fn set_timestamp(&mut self, tx_id: TxId, timestamp: u64) -> Result<()> {
+        self.run(move |mut store| store.set_timestamp(tx_id, timestamp))
+            .await
+    }
+
+    async fn prune(&mut self, before: u64) -> Result<usize> {
+        self.run(move |mut store| store.prune(before)).await
+    }
+
+    async fn set_pruned(&mut self, tx_ids: RangeInclusive<TxId>) -> Result<()> {
+        self.run(move |mut store| store.set_pruned(tx_ids)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    #[cfg(feature = "sled")]
+    #[test]
+    fn test_sled_store() {
+        let mut store = SledStore::default();
+        store.upsert(1, 1, TxState::Deposit(dec!(1.5))).unwrap();
+        store.upsert(1, 1, TxState::Dispute(dec!(1.5))).unwrap();
+        assert!(store.upsert(2, 1, TxState::Withdrawal(dec!(1.0))).is_err());
+        assert_eq!(store.get(1, 1), Some(TxState::Dispute(dec!(1.5))));
+        assert_eq!(store.get(2, 1), None);
+        assert_eq!(store.get(1, 2), None);
+        store.upsert(2, 256, TxState::Deposit(dec!(1.0))).unwrap();
+        store.upsert(1, 3, TxState::Withdrawal(dec!(0.5))).unwrap();
+        assert_eq!(
+            store.list(1).collect::<Vec<_>>(),
+            vec![
+                (1, TxState::Dispute(dec!(1.5))),
+                (3, TxState::Withdrawal(dec!(0.5)))
+            ]
+        );
+        assert_eq!(store.list(3).count(), 0);
+        assert_eq!(store.tx_ids().collect::<Vec<_>>(), vec![1, 3, 256]);
+
+        let eur: Currency = "EUR".parse().unwrap();
+        store.set_currency(1, eur).unwrap();
+        assert_eq!(store.currency(1), Some(eur));
+        assert_eq!(store.currency(2), None);
+        store.set_timestamp(1, 1700000000).unwrap();
+        assert_eq!(store.timestamp(1), Some(1700000000));
+        assert_eq!(store.timestamp(2), None);
+
+        let mut account = Account {
+            available: dec!(0),
+            total: dec!(1.5),
+            ..Default::default()
+        };
+        account.set_balance(
+            Some(eur),
+            Balance {
+                available: dec!(1),
+                total: dec!(1),
+            },
+        );
+        store.save_account(1, account.clone()).unwrap();
+        assert_eq!(store.clone().account(1), Some(account));
+        assert_eq!(store.account(2), None);
+        assert_eq!(store.clients(), vec![1]);
+
+        // accounts saved before statuses were are read from whether they were locked
+        store
+            .accounts
+            .insert(
+                3u64.to_be_bytes(),
+                &br#"{"available":"1","total":"1","locked":true}"#[..],
+            )
+            .unwrap();
+        assert_eq!(store.account(3).unwrap().status, AccountStatus::Frozen);
+    }
+
+    #[cfg(feature = "sled")]
+    #[test]
+    fn test_sled_pruned_ranges() {
+        let mut store = SledStore::default();
+        // a range too wide to store id by id
+        store.set_pruned(1_000..=u64::MAX - 1).unwrap();
+        store.set_pruned(10..=19).unwrap();
+        store.set_pruned(20..=29).unwrap();
+        store.set_pruned(5..=12).unwrap();
+        assert_eq!(store.pruned.len(), 2);
+        for tx_id in [5, 29, 1_000, u64::MAX - 1] {
+            let deposit = TxState::Deposit(dec!(1));
+            assert!(store.upsert(1, tx_id, deposit).is_err(), "{}", tx_id);
+        }
+        for tx_id in [4, 30, 999, u64::MAX] {
+            store.upsert(1, tx_id, TxState::Deposit(dec!(1))).unwrap();
+        }
+
+        // pruning shares the same ranges, joining the ids it prunes to their neighbours
+        for tx_id in [4, 30] {
+            store.set_timestamp(tx_id, 1).unwrap();
+        }
+        assert_eq!(store.prune(50).unwrap(), 2);
+        assert_eq!(store.pruned.len(), 2);
+        assert!(store.upsert(1, 4, TxState::Deposit(dec!(1))).is_err());
+
+        // ids pruned before ranges were stored still count
+        store.pruned.insert(500u64.to_be_bytes(), &[]).unwrap();
+        assert!(store.upsert(1, 500, TxState::Deposit(dec!(1))).is_err());
+        store.upsert(1, 501, TxState::Deposit(dec!(1))).unwrap();
+    }
+
+    #[cfg(feature = "sled")]
+    #[test]
+    fn test_spill_store() {
+        let mut store = SpillStore::new(2);
+        for tx in 1..=5 {
+            store.upsert(1, tx, TxState::Deposit(tx.into())).unwrap();
+        }
+        assert_eq!(store.in_memory(), 2);
+
+        // spilled transactions are moved back into memory, and can still be updated
+        assert_eq!(store.get(1, 1), Some(TxState::Deposit(dec!(1))));
+        store.upsert(1, 2, TxState::Dispute(dec!(2))).unwrap();
+        assert!(store.upsert(2, 3, TxState::Withdrawal(dec!(1.0))).is_err());
+        assert_eq!(store.get(2, 3), None);
+        assert_eq!(store.in_memory(), 2);
+        for tx in 1..=5 {
+            let expected = match tx {
+                2 => TxState::Dispute(dec!(2)),
+                tx => TxState::Deposit(tx.into()),
+            };
+            assert_eq!(store.clone().get(1, tx), Some(expected));
+        }
+        assert_eq!(store.get(1, 6), None);
+        // both transactions in memory and spilled ones are listed
+        store.upsert(2, 6, TxState::Deposit(dec!(6))).unwrap();
+        let listed: Vec<_> = store.list(1).map(|(tx, _)| tx).collect();
+        assert_eq!(listed, vec![1, 2, 3, 4, 5]);
+
+        store.save_account(1, Account::default()).unwrap();
+        assert_eq!(store.clients(), vec![1]);
+        assert!(
+            SpillStore::with_max_memory(1 << 20)
+                .inner
+                .lock()
+                .unwrap()
+                .capacity
+                > 1000
+        );
+    }
+
+    /// Applies `ops` deposits and lookups of each of `threads` clients to `store`
+    /// concurrently, one thread per client.
+    fn hammer<T: TxStore + Clone + Send>(store: T, threads: u64, ops: u64) {
+        std::thread::scope(|scope| {
+            for client in 0..threads {
+                let mut store = store.clone();
+                scope.spawn(move || {
+                    for tx in (0..ops).map(|tx| tx * threads + client) {
+                        store.upsert(client, tx, TxState::Deposit(dec!(1))).unwrap();
+                        assert!(store.get(client, tx).is_some());
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn test_sharded_store() {
+        let mut store = ShardedStore::new(4);
+        assert!(ShardedStore::<Decimal>::default().shards() >= 4);
+        for tx in 1..=8 {
+            store
+                .upsert(tx % 2, tx, TxState::Deposit(tx.into()))
+                .unwrap();
+        }
+        store.upsert(1, 3, TxState::Dispute(dec!(3))).unwrap();
+        assert!(store.upsert(0, 3, TxState::Withdrawal(dec!(1))).is_err());
+        assert_eq!(store.get(0, 3), None);
+        let listed: Vec<_> = store.list(1).collect();
+        assert_eq!(listed.len(), 4);
+        assert_eq!(listed[1], (3, TxState::Dispute(dec!(3))));
+        store.set_timestamp(5, 1700000000).unwrap();
+        assert_eq!(store.clone().timestamp(5), Some(1700000000));
+        for client in 1..=6 {
+            store.save_account(client, Account::default()).unwrap();
+        }
+        let mut clients = store.clients();
+        clients.sort_unstable();
+        assert_eq!(clients, vec![1, 2, 3, 4, 5, 6]);
+
+        // every thread's transactions are kept, whichever shards they landed in
+        let mut sharded = ShardedStore::new(64);
+        hammer(sharded.clone(), 8, 20_000);
+        assert_eq!(sharded.list(7).count(), 20_000);
+        assert_eq!(sharded.list(0).count(), 20_000);
+        assert!(sharded.upsert(1, 7, TxState::Deposit(dec!(1))).is_err());
+    }
+
+    #[test]
+    fn test_compact_store() {
+        assert_eq!(std::mem::size_of::<PackedTx>(), 16);
+        let mut store = CompactStore::new();
+        for tx in 1..=5 {
+            store.upsert(1, tx, TxState::Deposit(tx.into())).unwrap();
+        }
+        // far beyond the others, too large to pack, or with too many decimal places
+        store.upsert(1, 1 << 40, TxState::Deposit(dec!(1))).unwrap();
+        store.upsert(1, 6, TxState::Deposit(dec!(500000))).unwrap();
+        store
+            .upsert(1, 7, TxState::Withdrawal(dec!(0.00001)))
+            .unwrap();
+        assert_eq!(store.packed(), 5);
+        assert!(store.upsert(2, 3, TxState::Withdrawal(dec!(1))).is_err());
+        assert!(store.upsert(2, 6, TxState::Withdrawal(dec!(1))).is_err());
+        assert_eq!(store.get(2, 3), None);
+
+        // a partial dispute moves out of its packed slot
+        let partial = TxState::PartialDispute {
+            disputed: dec!(1),
+            undisputed: dec!(1),
+        };
+        store.upsert(1, 2, partial.clone()).unwrap();
+        assert_eq!(store.packed(), 4);
+        assert_eq!(store.clone().get(1, 2), Some(partial));
+        store.upsert(1, 3, TxState::Dispute(dec!(3))).unwrap();
+        assert_eq!(store.get(1, 3), Some(TxState::Dispute(dec!(3.0000))));
+        assert_eq!(store.get(1, 6), Some(TxState::Deposit(dec!(500000))));
+        let listed: Vec<_> = store.list(1).map(|(tx, _)| tx).collect();
+        assert_eq!(listed, vec![1, 2, 3, 4, 5, 6, 7, 1 << 40]);
+
+        store.save_account(1, Account::default()).unwrap();
+        assert_eq!(store.clients(), vec![1]);
+        store.set_currency(1, "EUR".parse().unwrap()).unwrap();
+        assert_eq!(store.currency(1), Some("EUR".parse().unwrap()));
+    }
+
+    #[cfg(feature = "sled")]
+    #[test]
+    fn test_bloom_store() {
+        let mut inner = SledStore::default();
+        inner.upsert(1, 1, TxState::Deposit(dec!(1))).unwrap();
+        inner.save_account(1, Account::default()).unwrap();
+
+        let mut store = BloomStore::new(inner, 10_000);
+        assert_eq!(store.get(1, 1), Some(TxState::Deposit(dec!(1))));
+        for tx in 2..=10_000 {
+            store.upsert(1, tx, TxState::Deposit(dec!(1))).unwrap();
+        }
+        assert!((1..=10_000).all(|tx| store.clone().get(1, tx).is_some()));
+        let false_positives = (10_001..20_001)
+            .filter(|&tx| store.filter.contains(tx))
+            .count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+        assert_eq!(store.get(1, 10_001), None);
+    }
+
+    /// A memory store counting the reads listing its transactions.
+    #[derive(Clone, Default)]
+    struct CountingStore {
+        inner: Arc<Mutex<MemoryStore>>,
+        reads: Arc<AtomicU64>,
+    }
+
+    impl ClientStore for CountingStore {
+        fn account(&self, client_id: ClientId) -> Option<Account> {
+            self.inner.account(client_id)
+        }
+
+        fn save_account(&mut self, client_id: ClientId, account: Account) -> Result<()> {
+            self.inner.save_account(client_id, account)
+        }
+
+        fn clients(&self) -> Vec<ClientId> {
+            self.inner.clients()
+        }
+    }
+
+    impl TxStore for CountingStore {
+        fn get(&self, client_id: ClientId, tx_id: TxId) -> Option<TxState> {
+            self.inner.get(client_id, tx_id)
+        }
+
+        fn upsert(&mut self, client_id: ClientId, tx_id: TxId, tx: TxState) -> Result<()> {
+            self.inner.upsert(client_id, tx_id, tx)
+        }
+
+        fn list(&self, client_id: ClientId) -> impl Iterator<Item = (TxId, TxState)> {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+            self.inner.list(client_id)
+        }
+
+        fn tx_ids(&self) -> impl Iterator<Item = TxId> {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+            self.inner.tx_ids()
+        }
+
+        fn currency(&self, tx_id: TxId) -> Option<Currency> {
+            self.inner.currency(tx_id)
+        }
+
+        fn set_currency(&mut self, tx_id: TxId, currency: Currency) -> Result<()> {
+            self.inner.set_currency(tx_id, currency)
+        }
+
+        fn timestamp(&self, tx_id: TxId) -> Option<u64> {
+            self.inner.timestamp(tx_id)
+        }
+
+        fn set_timestamp(&mut self, tx_id: TxId, timestamp: u64) -> Result<()> {
+            self.inner.set_timestamp(tx_id, timestamp)
+        }
+
+        fn prune(&mut self, before: u64) -> Result<usize> {
+            self.inner.prune(before)
+        }
+
+        fn set_pruned(&mut self, tx_ids: RangeInclusive<TxId>) -> Result<()> {
+            self.inner.set_pruned(tx_ids)
+        }
+    }
+
+    #[test]
+    fn test_bloom_seed() {
+        let mut inner = CountingStore::default();
+        for client in 1..=100 {
+            for tx in 0..10 {
+                let tx_id = client * 10 + tx;
+                inner
+                    .upsert(client, tx_id, TxState::Deposit(dec!(1)))
+                    .unwrap();
+            }
+            inner.save_account(client, Account::default()).unwrap();
+        }
+
+        // seeding reads every transaction in one pass, rather than once per client
+        let store = BloomStore::new(inner.clone(), 1_000);
+        assert_eq!(inner.reads.load(Ordering::Relaxed), 1);
+        assert!((10..1010).all(|tx_id| store.filter.contains(tx_id)));
+        assert_eq!(store.get(100, 1009), Some(TxState::Deposit(dec!(1))));
+    }
+
+    #[cfg(feature = "sled")]
+    #[test]
+    fn test_prune() {
+        let old = [
+            TxState::Deposit(dec!(1)),
+            TxState::Dispute(dec!(1)),
+            TxState::Withdrawal(dec!(1)),
+            TxState::ChargedBack(dec!(1)),
+            TxState::Authorized(dec!(1)),
+        ];
+        let mut memory = MemoryStore::new();
+        let mut compact = CompactStore::new();
+        let mut spill = SpillStore::new(1);
+        let mut sled = SledStore::default();
+        let stores: [&mut dyn FnMut(TxId, TxState, u64) -> Result<()>; 4] = [
+            &mut |tx_id, tx, t| {
+                memory
+                    .upsert(1, tx_id, tx)
+                    .and(memory.set_timestamp(tx_id, t))
+            },
+            &mut |tx_id, tx, t| {
+                compact
+                    .upsert(1, tx_id, tx)
+                    .and(compact.set_timestamp(tx_id, t))
+            },
+            &mut |tx_id, tx, t| {
+                spill
+                    .upsert(1, tx_id, tx)
+                    .and(spill.set_timestamp(tx_id, t))
+            },
+            &mut |tx_id, tx, t| sled.upsert(1, tx_id, tx).and(sled.set_timestamp(tx_id, t)),
+        ];
+        for store in stores {
+            for (tx_id, tx) in (1..).zip(old.clone()) {
+                store(tx_id, tx, 10).unwrap();
+            }
+            store(6, TxState::Deposit(dec!(1)), 100).unwrap();
+        }
+        // only the settled deposit and withdrawal from before the cutoff are pruned
+        let remaining = [2, 4, 5, 6];
+        assert_eq!(memory.prune(50).unwrap(), 2);
+        assert_eq!(compact.prune(50).unwrap(), 2);
+        assert_eq!(spill.prune(50).unwrap(), 2);
+        assert_eq!(sled.prune(50).unwrap(), 2);
+        for listed in [
+            memory.list(1).map(|(tx_id, _)| tx_id).collect::<Vec<_>>(),
+            compact.list(1).map(|(tx_id, _)| tx_id).collect(),
+            spill.list(1).map(|(tx_id, _)| tx_id).collect(),
+            sled.list(1).map(|(tx_id, _)| tx_id).collect(),
+        ] {
+            assert_eq!(listed, remaining);
+        }
+        assert_eq!(compact.packed(), 4);
+        assert_eq!(sled.timestamp(1), None);
+        // pruned ids can't be stored again, by any client
+        for (client, tx_id) in [(1, 1), (2, 3)] {
+            let deposit = TxState::Deposit(dec!(1));
+            assert!(memory.upsert(client, tx_id, deposit.clone()).is_err());
+            assert!(compact.upsert(client, tx_id, deposit.clone()).is_err());
+            assert!(spill.upsert(client, tx_id, deposit.clone()).is_err());
+            assert!(sled.upsert(client, tx_id, deposit).is_err());
+        }
+        // a transaction without a recorded time is never pruned
+        memory.upsert(1, 7, TxState::Deposit(dec!(1))).unwrap();
+        assert_eq!(memory.prune(u64::MAX).unwrap(), 1);
+        assert_eq!(memory.get(1, 7), Some(TxState::Deposit(dec!(1))));
+    }
+
+    #[test]
+    fn test_pruned_duplicate() {
+        use crate::clients::Client;
+        use crate::events::{Event, Record};
+
+        let deposit = Event::try_from(Record {
+            r#type: "deposit".to_string(),
+            client: 1,
+            tx: 1,
+            amount: Some(dec!(1.0)),
+            to: None,
+            seq: None,
+            timestamp: Some(10),
+            currency: None,
+        })
+        .unwrap();
+        let mut store = MemoryStore::new();
+        let mut client = Client::new(1, store.clone());
+        client.update(&deposit).unwrap();
+        store.set_timestamp(1, 10).unwrap();
+        assert_eq!(store.prune(50).unwrap(), 1);
+
+        // a replayed deposit is still a duplicate, and its id can't be claimed either
+        assert!(client.update(&deposit).is_err());
+        assert_eq!(client.available(), dec!(1.0));
+        let mut other = Client::new(2, store.clone());
+        assert!(other.update(&deposit.with_client(2)).is_err());
+        assert_eq!(other.available(), dec!(0.0));
+
+        let mut pruned = Tombstones::default();
+        for tx_id in [3, 1, 2, 5, u64::MAX] {
+            pruned.insert(tx_id);
+        }
+        assert_eq!(pruned.ranges.len(), 3);
+        assert!(pruned.contains(2) && pruned.contains(u64::MAX));
+        assert!(!pruned.contains(4) && !pruned.contains(0));
+        pruned.insert_range(4..=10);
+        pruned.insert_range(20..=30);
+        pruned.insert_range(12..=19);
+        assert_eq!(
+            pruned.ranges,
+            BTreeMap::from([(1, 10), (12, 30), (u64::MAX, u64::MAX)])
+        );
+    }
+
+    #[cfg(feature = "postgres")]
+    #[test]
+    fn test_postgres_rows() {
+        for tx in [
+            TxState::Deposit(dec!(1.5)),
+            TxState::Dispute(dec!(0.25)),
+            TxState::Withdrawal(dec!(2)),
+            TxState::ChargedBack(dec!(3)),
+            TxState::WithdrawalDispute(dec!(0.5)),
+            TxState::WithdrawalChargedBack(dec!(0.5)),
+            TxState::Transfer(dec!(4)),
+            TxState::Represented(dec!(3)),
+            TxState::Authorized(dec!(2)),
+            TxState::Voided(dec!(2)),
+            TxState::PartialDispute {
+                disputed: dec!(1),
+                undisputed: dec!(0.5),
+            },
+            TxState::PartialChargedBack {
+                charged_back: dec!(1),
+                undisputed: dec!(0.5),
+            },
+        ] {
+            let (state, amount, undisputed) = tx_row(&tx);
+            assert_eq!(tx_from_row(state, amount, undisputed).unwrap(), tx);
         }
+        assert!(tx_from_row("deposit", None, None).is_err());
+        assert!(tx_from_row("partial_dispute", Some(dec!(1)), None).is_err());
+        assert_eq!(sql_id(u64::MAX) as u64, u64::MAX);
+        assert_eq!(StoreKind::from_str("sled").unwrap(), StoreKind::Sled);
+        assert!(StoreKind::from_str("redis").is_err());
     }
 }
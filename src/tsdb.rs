@@ -0,0 +1,162 @@
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use anyhow::{bail, Error, Result};
+
+use crate::clients::Summary;
+
+/// The formats supported when exporting balances to a time-series database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TsdbFormat {
+    /// InfluxDB line protocol, with nanosecond precision timestamps.
+    Influx,
+    /// SQL `INSERT` statements suitable for TimescaleDB or plain PostgreSQL.
+    Sql,
+}
+
+impl FromStr for TsdbFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<TsdbFormat> {
+        match s {
+            "influx" => Ok(TsdbFormat::Influx),
+            "sql" => Ok(TsdbFormat::Sql),
+            v => bail!("invalid time-series format {:?}, expected influx or sql", v),
+        }
+    }
+}
+
+/// Writes a client's balances after every applied payment event, in a format which
+/// can be loaded directly into a time-series database.
+///
+/// Each point is written to the `balances` measurement (or table) with the client id
+/// as a tag. Events without a timestamp are attributed to the most recent timestamp
+/// seen; before any timestamp has been seen, the database's ingestion time is used.
+///
+/// # Example
+/// ```
+/// use payments::clients::Summary;
+/// use payments::tsdb::{TsdbExporter, TsdbFormat};
+/// use rust_decimal_macros::dec;
+///
+/// let mut out = Vec::new();
+/// let mut exporter = TsdbExporter::new(&mut out, TsdbFormat::Influx);
+/// let summary = Summary { id: 1, available: dec!(1.5), total: dec!(1.5), ..Default::default() };
+/// exporter.record(Some(10), &summary).unwrap();
+///
+/// // prints "balances,client=1 available=1.5,held=0,total=1.5,locked=false 10000000000"
+/// print!("{}", String::from_utf8(out).unwrap());
+/// ```
+#[derive(Debug)]
+pub struct TsdbExporter<W: Write> {
+    #[doc(hidden)]
+    writer: W,
+    #[doc(hidden)]
+    format: TsdbFormat,
+    #[doc(hidden)]
+    clock: Option<u64>,
+}
+
+impl<W: Write> TsdbExporter<W> {
+    /// Creates an exporter writing points in `format` to `writer`.
+    pub fn new(writer: W, format: TsdbFormat) -> TsdbExporter<W> {
+        TsdbExporter {
+            writer,
+            format,
+            clock: None,
+        }
+    }
+
+    /// Writes the balances of a client after an event which occurred at `timestamp`.
+    pub fn record(&mut self, timestamp: Option<u64>, summary: &Summary) -> io::Result<()> {
+        if let Some(ts) = timestamp {
+            self.clock = Some(self.clock.map_or(ts, |clock| clock.max(ts)));
+        }
+        let time = timestamp.or(self.clock);
+
+        match self.format {
+            TsdbFormat::Influx => {
+                write!(
+                    self.writer,
+                    "balances,client={} available={},held={},total={},locked={}",
+                    summary.id, summary.available, summary.held, summary.total, summary.locked
+                )?;
+                match time {
+                    Some(ts) => writeln!(self.writer, " {}", u128::from(ts) * 1_000_000_000),
+                    None => writeln!(self.writer),
+                }
+            }
+            TsdbFormat::Sql => {
+                let time = match time {
+                    Some(ts) => format!("to_timestamp({})", ts),
+                    None => "now()".to_string(),
+                };
+                writeln!(
+                    self.writer,
+                    "INSERT INTO balances (time, client, available, held, total, locked) \
+                     VALUES ({}, {}, {}, {}, {}, {});",
+                    time,
+                    summary.id,
+                    summary.available,
+                    summary.held,
+                    summary.total,
+                    summary.locked
+                )
+            }
+        }
+    }
+
+    /// Flushes any buffered output to the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rust_decimal_macros::dec;
+
+    fn summary() -> Summary {
+        Summary {
+            id: 7,
+            available: dec!(1.5),
+            held: dec!(2),
+            total: dec!(3.5),
+            locked: false,
+            status: Default::default(),
+            currency: None,
+        }
+    }
+
+    fn export(format: TsdbFormat, timestamps: &[Option<u64>]) -> String {
+        let mut out = Vec::new();
+        let mut exporter = TsdbExporter::new(&mut out, format);
+        for ts in timestamps {
+            exporter.record(*ts, &summary()).unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_influx() {
+        assert_eq!(
+            export(TsdbFormat::Influx, &[None, Some(5), None]),
+            "balances,client=7 available=1.5,held=2,total=3.5,locked=false\n\
+             balances,client=7 available=1.5,held=2,total=3.5,locked=false 5000000000\n\
+             balances,client=7 available=1.5,held=2,total=3.5,locked=false 5000000000\n"
+        );
+    }
+
+    #[test]
+    fn test_sql() {
+        assert_eq!(
+            export(TsdbFormat::Sql, &[None, Some(5)]),
+            "INSERT INTO balances (time, client, available, held, total, locked) \
+             VALUES (now(), 7, 1.5, 2, 3.5, false);\n\
+             INSERT INTO balances (time, client, available, held, total, locked) \
+             VALUES (to_timestamp(5), 7, 1.5, 2, 3.5, false);\n"
+        );
+    }
+}
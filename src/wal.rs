@@ -0,0 +1,720 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::checkpoint::Checkpoint;
+use crate::events::{ClientId, Currency, Event, Record, TxId};
+use crate::storage::{Account, ClientStore, TxState, TxStore};
+
+/// A write made to a transaction store while applying an event.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "write", rename_all = "snake_case")]
+enum StoreWrite {
+    Account {
+        client: ClientId,
+        account: Account,
+    },
+    Transaction {
+        client: ClientId,
+        tx: TxId,
+        state: TxState,
+    },
+    Currency {
+        tx: TxId,
+        currency: Currency,
+    },
+    Timestamp {
+        tx: TxId,
+        timestamp: u64,
+    },
+    Prune {
+        before: u64,
+    },
+    Pruned {
+        first: TxId,
+        last: TxId,
+    },
+}
+
+/// The position of a record in the input it was read from.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Input {
+    source: String,
+    position: u64,
+}
+
+/// A line of the log.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "entry", rename_all = "snake_case")]
+enum Entry {
+    /// An event about to be applied, along with the position of its record, if it was
+    /// read from an input.
+    Event {
+        seq: u64,
+        record: Record,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        input: Option<Input>,
+    },
+    /// The writes made to the store by applying the event `seq`, whether or not it was
+    /// rejected.
+    Applied { seq: u64, writes: Vec<StoreWrite> },
+    /// A record read from an input which was discarded without an event being applied.
+    Discarded { input: Input },
+    /// An input which was read to its end and won't be read again.
+    Finished { source: String },
+    /// The store as it was once the event `seq` was applied, in place of the entries
+    /// logged up to it, or `None` if the store persists itself. Always the first line.
+    Snapshot {
+        seq: u64,
+        checkpoint: Option<Checkpoint>,
+        inputs: BTreeMap<String, u64>,
+    },
+}
+
+/// The least number of bytes logged after a snapshot before the next one is taken.
+const MIN_SNAPSHOT_BYTES: u64 = 16 << 20;
+
+/// Takes the snapshots the log is truncated to.
+struct Snapshots(Box<dyn FnMut() -> Result<Option<Checkpoint>> + Send>);
+
+impl fmt::Debug for Snapshots {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Snapshots")
+    }
+}
+
+/// A log of every event applied to a transaction store, written before it is applied,
+/// along with the writes to the store applying it made, so that a service restarting
+/// after a crash recovers the store exactly as it was, rather than reprocessing its
+/// whole input.
+///
+/// The log is a JSON Lines file, appended to and synced to disk as each event is
+/// applied. On opening it, a line left partly written by a crash is discarded.
+/// [`WriteAheadLog::replay`] makes the logged writes to a store, such as an empty
+/// [`MemoryStore`](crate::storage::MemoryStore), and returns the event which was being
+/// applied when the crash happened, if any, to be applied again. Writes to the store
+/// are only logged if it is wrapped with [`WriteAheadLog::wrap`].
+///
+/// Records read from an input are noted with [`WriteAheadLog::read`], which logs their
+/// position along with their event, so that records read again after a crash, such as
+/// redelivered messages, are skipped rather than applied or rejected a second time.
+///
+/// The log is truncated by [`WriteAheadLog::snapshot`], which replaces its entries with
+/// a checkpoint of the store, and which [`WriteAheadLog::with_snapshots`] takes
+/// whenever as much has been logged since the last snapshot as it took up.
+///
+/// # Example
+/// ```
+/// use payments::events::{Event, Record};
+/// use payments::storage::{MemoryStore, TxState, TxStore};
+/// use payments::wal::WriteAheadLog;
+/// use rust_decimal_macros::dec;
+///
+/// let path = std::env::temp_dir().join(format!("wal-doc-{}.jsonl", std::process::id()));
+/// let deposit = Event::try_from(Record {
+///     r#type: "deposit".to_string(),
+///     client: 1,
+///     tx: 1,
+///     amount: Some(dec!(1.0)),
+///     to: None,
+///     seq: None,
+///     timestamp: None,
+///     currency: None,
+/// })
+/// .unwrap();
+///
+/// let mut wal = WriteAheadLog::open(&path).unwrap();
+/// let mut store = wal.wrap(MemoryStore::new());
+/// wal.begin(&deposit).unwrap();
+/// store.upsert(1, 1, TxState::Deposit(dec!(1.0))).unwrap();
+/// wal.applied().unwrap();
+/// drop(wal);
+///
+/// // after a crash
+/// let mut wal = WriteAheadLog::open(&path).unwrap();
+/// let mut recovered = MemoryStore::new();
+/// assert!(wal.replay(&mut recovered).unwrap().is_none());
+/// assert_eq!(recovered.get(1, 1), Some(TxState::Deposit(dec!(1.0))));
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct WriteAheadLog {
+    #[doc(hidden)]
+    writer: BufWriter<File>,
+    #[doc(hidden)]
+    writes: Arc<Mutex<Vec<StoreWrite>>>,
+    #[doc(hidden)]
+    path: PathBuf,
+    #[doc(hidden)]
+    seq: u64,
+    #[doc(hidden)]
+    recovered: Vec<Entry>,
+    #[doc(hidden)]
+    inputs: BTreeMap<String, u64>,
+    #[doc(hidden)]
+    input: Option<Input>,
+    #[doc(hidden)]
+    snapshots: Option<Snapshots>,
+    #[doc(hidden)]
+    snapshot_len: u64,
+    #[doc(hidden)]
+    appended: u64,
+}
+
+impl WriteAheadLog {
+    /// Opens the log at `path`, creating it if it does not exist, and reads the
+    /// entries already logged to be replayed.
+    pub fn open(path: impl AsRef<Path>) -> Result<WriteAheadLog> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .with_context(|| format!("opening {}", path.display()))?;
+        let mut logged = Vec::new();
+        file.read_to_end(&mut logged)
+            .with_context(|| format!("reading {}", path.display()))?;
+        // a line left partly written by a crash is discarded, so that the log carries on
+        // after the last whole entry
+        let len = logged
+            .iter()
+            .rposition(|b| *b == b'\n')
+            .map_or(0, |i| i + 1);
+        if len < logged.len() {
+            warn!(
+                "discarding a partly written entry at the end of {}",
+                path.display()
+            );
+            file.set_len(len as u64)?;
+        }
+        let recovered = logged[..len]
+            .split(|b| *b == b'\n')
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(i, line)| {
+                serde_json::from_slice(line)
+                    .with_context(|| format!("reading {} line {}", path.display(), i + 1))
+            })
+            .collect::<Result<Vec<Entry>>>()?;
+        let mut seq = 0;
+        let mut inputs = BTreeMap::new();
+        for entry in &recovered {
+            match entry {
+                Entry::Event { seq: s, input, .. } => {
+                    seq = *s;
+                    if let Some(input) = input {
+                        inputs.insert(input.source.clone(), input.position);
+                    }
+                }
+                Entry::Applied { seq: s, .. } => seq = *s,
+                Entry::Discarded { input } => {
+                    inputs.insert(input.source.clone(), input.position);
+                }
+                Entry::Finished { source } => {
+                    inputs.remove(source);
+                }
+                Entry::Snapshot {
+                    seq: s, inputs: i, ..
+                } => {
+                    seq = *s;
+                    inputs = i.clone();
+                }
+            }
+        }
+        let snapshot_len = match recovered.first() {
+            Some(Entry::Snapshot { .. }) => logged.iter().position(|b| *b == b'\n').unwrap() + 1,
+            _ => 0,
+        } as u64;
+        Ok(WriteAheadLog {
+            writer: BufWriter::new(file),
+            writes: Arc::default(),
+            path: path.to_path_buf(),
+            seq,
+            recovered,
+            inputs,
+            input: None,
+            snapshots: None,
+            snapshot_len,
+            appended: len as u64 - snapshot_len,
+        })
+    }
+
+    /// Snapshots the store with `take` whenever as many bytes have been logged since
+    /// the last snapshot as it took up, or 16 MiB if more, truncating the log. `take`
+    /// returns a checkpoint of the store, or `None` once a store which persists itself
+    /// has written every change made so far to disk.
+    pub fn with_snapshots(
+        self,
+        take: impl FnMut() -> Result<Option<Checkpoint>> + Send + 'static,
+    ) -> WriteAheadLog {
+        WriteAheadLog {
+            snapshots: Some(Snapshots(Box::new(take))),
+            ..self
+        }
+    }
+
+    /// Makes the writes logged by earlier runs to `store`, returning the event which was
+    /// being applied when the last of them stopped, if it stopped part way through one.
+    pub fn replay(&mut self, store: &mut impl TxStore) -> Result<Option<Event>> {
+        let path = self.path.display();
+        let mut interrupted = None;
+        for entry in std::mem::take(&mut self.recovered) {
+            match entry {
+                Entry::Event { seq, record, .. } => interrupted = Some((seq, record)),
+                Entry::Applied { seq, writes } => {
+                    interrupted = None;
+                    for write in writes {
+                        replay(store, write).with_context(|| {
+                            format!("replaying the writes of event {} in {}", seq, path)
+                        })?;
+                    }
+                }
+                Entry::Discarded { .. } | Entry::Finished { .. } => {}
+                Entry::Snapshot {
+                    seq, checkpoint, ..
+                } => {
+                    if let Some(checkpoint) = checkpoint {
+                        checkpoint.restore(store).with_context(|| {
+                            format!("restoring the snapshot at event {} in {}", seq, path)
+                        })?;
+                    }
+                }
+            }
+        }
+        interrupted
+            .map(|(seq, record)| {
+                Event::try_from(record)
+                    .with_context(|| format!("reading event {} in {}", seq, path))
+            })
+            .transpose()
+    }
+
+    /// Wraps `store` so that the writes made to it are logged.
+    pub fn wrap<T>(&self, store: T) -> LoggedStore<T> {
+        LoggedStore {
+            inner: store,
+            writes: Arc::clone(&self.writes),
+        }
+    }
+
+    /// Returns whether the record at `position` of the input `source`, such as a line
+    /// of a file or the offset of a message in its partition, is yet to be logged, in
+    /// which case it is logged along with the next event begun or record discarded.
+    /// Records already logged must be skipped, as they were applied or rejected before.
+    pub fn read(&mut self, source: &str, position: u64) -> bool {
+        if self
+            .inputs
+            .get(source)
+            .is_some_and(|&logged| position <= logged)
+        {
+            return false;
+        }
+        self.input = Some(Input {
+            source: source.to_string(),
+            position,
+        });
+        true
+    }
+
+    /// Logs that the record last read was discarded without an event being applied,
+    /// such as for being invalid, so that it is skipped if it is read again.
+    pub fn discarded(&mut self) -> Result<()> {
+        match self.input.take() {
+            Some(input) => {
+                self.inputs.insert(input.source.clone(), input.position);
+                self.append(&Entry::Discarded { input })
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Logs that the input `source` was read to its end, so that an input of the same
+    /// name is read from its start.
+    pub fn finished(&mut self, source: &str) -> Result<()> {
+        self.inputs.remove(source);
+        self.append(&Entry::Finished {
+            source: source.to_string(),
+        })
+    }
+
+    /// Logs `event` before it is applied, failing if it could not be synced to disk, in
+    /// which case the event must not be applied.
+    pub fn begin(&mut self, event: &Event) -> Result<()> {
+        self.writes.lock().unwrap().clear();
+        self.seq += 1;
+        let record = Record::from(event);
+        let input = self.input.take();
+        if let Some(input) = &input {
+            self.inputs.insert(input.source.clone(), input.position);
+        }
+        self.append(&Entry::Event {
+            seq: self.seq,
+            record,
+            input,
+        })
+    }
+
+    /// Logs the writes made to the wrapped store since the last event was logged, once
+    /// it has been applied or rejected, then snapshots the store if one is due.
+    pub fn applied(&mut self) -> Result<()> {
+        let writes = std::mem::take(&mut *self.writes.lock().unwrap());
+        self.append(&Entry::Applied {
+            seq: self.seq,
+            writes,
+        })?;
+        if self.appended < self.snapshot_len.max(MIN_SNAPSHOT_BYTES) {
+            return Ok(());
+        }
+        let Some(Snapshots(take)) = self.snapshots.as_mut() else {
+            return Ok(());
+        };
+        // the log stays as it was if a snapshot can't be taken, to be tried again later
+        match take().and_then(|checkpoint| self.snapshot(checkpoint.as_ref())) {
+            Ok(()) => info!("truncated {} to a snapshot", self.path.display()),
+            Err(e) => {
+                error!("snapshotting {}: {:?}", self.path.display(), e);
+                self.appended = 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the entries logged so far with `checkpoint` of the store as it is now,
+    /// or with none if the store has written every change made to it to disk itself,
+    /// keeping the positions of the records read from each input. The log is replaced
+    /// only once the snapshot has been completely written.
+    pub fn snapshot(&mut self, checkpoint: Option<&Checkpoint>) -> Result<()> {
+        #[derive(Serialize)]
+        #[serde(tag = "entry", rename = "snapshot")]
+        struct Snapshot<'a> {
+            seq: u64,
+            checkpoint: Option<&'a Checkpoint>,
+            inputs: &'a BTreeMap<String, u64>,
+        }
+
+        let partial = self.path.with_extension("partial");
+        let mut writer = BufWriter::new(
+            OpenOptions::new()
+                .append(true)
+                .create(true)
+                .truncate(false)
+                .open(&partial)
+                .with_context(|| format!("creating {}", partial.display()))?,
+        );
+        writer.get_ref().set_len(0)?;
+        let snapshot = Snapshot {
+            seq: self.seq,
+            checkpoint,
+            inputs: &self.inputs,
+        };
+        let mut line = serde_json::to_vec(&snapshot)?;
+        line.push(b'\n');
+        writer.write_all(&line)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        fs::rename(&partial, &self.path)
+            .with_context(|| format!("replacing {}", self.path.display()))?;
+        self.writer = writer;
+        self.snapshot_len = line.len() as u64;
+        self.appended = 0;
+        Ok(())
+    }
+
+    fn append(&mut self, entry: &Entry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        self.writer.write_all(&line)?;
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        self.appended += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// Makes a logged `write` to `store`.
+fn replay(store: &mut impl TxStore, write: StoreWrite) -> Result<()> {
+    match write {
+        StoreWrite::Account { client, account } => store.save_account(client, account),
+        StoreWrite::Transaction { client, tx, state } => {
+            // a store which persists itself may have pruned the transaction since
+            if let Err(e) = store.upsert(client, tx, state) {
+                warn!("replaying a write of transaction {}: {:#}", tx, e);
+            }
+            Ok(())
+        }
+        StoreWrite::Currency { tx, currency } => store.set_currency(tx, currency),
+        StoreWrite::Timestamp { tx, timestamp } => store.set_timestamp(tx, timestamp),
+        StoreWrite::Prune { before } => store.prune(before).map(drop),
+        StoreWrite::Pruned { first, last } => store.set_pruned(first..=last),
+    }
+}
+
+/// A transaction store whose writes are logged to a [`WriteAheadLog`].
+///
+/// Clones log to the same write-ahead log.
+#[derive(Clone, Debug)]
+pub struct LoggedStore<T> {
+    #[doc(hidden)]
+    inner: T,
+    #[doc(hidden)]
+    writes: Arc<Mutex<Vec<StoreWrite>>>,
+}
+
+impl<T> LoggedStore<T> {
+    /// Returns the wrapped store.
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    fn log(&self, write: StoreWrite) {
+        self.writes.lock().unwrap().push(write);
+    }
+}
+
+impl<T: ClientStore> ClientStore for LoggedStore<T> {
+    fn account(&self, client_id: ClientId) -> Option<Account> {
+        self.inner.account(client_id)
+    }
+
+    fn save_account(&mut self, client_id: ClientId, account: Account) -> Result<()> {
+        self.inner.save_account(client_id, account.clone())?;
+        self.log(StoreWrite::Account {
+            client: client_id,
+            account,
+        });
+        Ok(())
+    }
+
+    fn clients(&self) -> Vec<ClientId> {
+        self.inner.clients()
+    }
+}
+
+impl<T: TxStore> TxStore for LoggedStore<T> {
+    fn get(&self, client_id: ClientId, tx_id: TxId) -> Option<TxState> {
+        self.inner.get(client_id, tx_id)
+    }
+
+    fn upsert(&mut self, client_id: ClientId, tx_id: TxId, tx: TxState) -> Result<()> {
+        self.inner.upsert(client_id, tx_id, tx.clone())?;
+        self.log(StoreWrite::Transaction {
+            client: client_id,
+            tx: tx_id,
+            state: tx,
+        });
+        Ok(())
+    }
+
+    fn list(&self, client_id: ClientId) -> impl Iterator<Item = (TxId, TxState)> {
+        self.inner.list(client_id)
+    }
+
+    fn tx_ids(&self) -> impl Iterator<Item = TxId> {
+        self.inner.tx_ids()
+    }
+
+    fn currency(&self, tx_id: TxId) -> Option<Currency> {
+        self.inner.currency(tx_id)
+    }
+
+    fn set_currency(&mut self, tx_id: TxId, currency: Currency) -> Result<()> {
+        self.inner.set_currency(tx_id, currency)?;
+        self.log(StoreWrite::Currency {
+            tx: tx_id,
+            currency,
+        });
+        Ok(())
+    }
+
+    fn timestamp(&self, tx_id: TxId) -> Option<u64> {
+        self.inner.timestamp(tx_id)
+    }
+
+    fn set_timestamp(&mut self, tx_id: TxId, timestamp: u64) -> Result<()> {
+        self.inner.set_timestamp(tx_id, timestamp)?;
+        self.log(StoreWrite::Timestamp {
+            tx: tx_id,
+            timestamp,
+        });
+        Ok(())
+    }
+
+    fn prune(&mut self, before: u64) -> Result<usize> {
+        let pruned = self.inner.prune(before)?;
+        self.log(StoreWrite::Prune { before });
+        Ok(pruned)
+    }
+
+    fn set_pruned(&mut self, tx_ids: RangeInclusive<TxId>) -> Result<()> {
+        let (first, last) = (*tx_ids.start(), *tx_ids.end());
+        self.inner.set_pruned(tx_ids)?;
+        self.log(StoreWrite::Pruned { first, last });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{env, fs};
+
+    use rust_decimal_macros::dec;
+
+    use crate::clients::Client;
+    use crate::storage::MemoryStore;
+
+    fn event(t: &str, tx: TxId, amount: Option<rust_decimal::Decimal>) -> Event {
+        Event::try_from(Record {
+            r#type: t.to_string(),
+            client: 1,
+            tx,
+            amount,
+            to: None,
+            seq: None,
+            timestamp: Some(100),
+            currency: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_replay() {
+        let path = env::temp_dir().join(format!("wal-{}.jsonl", std::process::id()));
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        let mut client = Client::new(1, wal.wrap(MemoryStore::new()));
+        for event in [
+            event("deposit", 1, Some(dec!(5))),
+            event("withdrawal", 2, Some(dec!(9))),
+            event("dispute", 1, None),
+        ] {
+            wal.begin(&event).unwrap();
+            let _ = client.update(&event);
+            wal.applied().unwrap();
+        }
+        // the process crashes while applying an event, part way through writing a line
+        wal.begin(&event("resolve", 1, None)).unwrap();
+        drop(wal);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"entry\":\"appl").unwrap();
+
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        let mut store = MemoryStore::new();
+        let interrupted = wal.replay(&mut store).unwrap().unwrap();
+        let mut recovered = Client::new(1, wal.wrap(store.clone()));
+        assert_eq!(recovered.summary(), client.summary());
+        assert_eq!(store.get(1, 1), Some(TxState::Dispute(dec!(5))));
+        assert_eq!(store.get(1, 2), None);
+
+        wal.begin(&interrupted).unwrap();
+        recovered.update(&interrupted).unwrap();
+        wal.applied().unwrap();
+        drop(wal);
+        let mut store = MemoryStore::new();
+        assert!(WriteAheadLog::open(&path)
+            .unwrap()
+            .replay(&mut store)
+            .unwrap()
+            .is_none());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(store.get(1, 1), Some(TxState::Deposit(dec!(5))));
+        assert_eq!(Client::new(1, store).summary().available, dec!(5));
+    }
+
+    #[test]
+    fn test_corrupt_entry() {
+        let path = env::temp_dir().join(format!("wal-corrupt-{}.jsonl", std::process::id()));
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        wal.begin(&event("deposit", 1, Some(dec!(5)))).unwrap();
+        drop(wal);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"entry\":\"applied\"}\n").unwrap();
+
+        // unlike a partly written last line, a whole line which can't be read is an error
+        let e = WriteAheadLog::open(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(e.to_string(), format!("reading {} line 2", path.display()));
+    }
+
+    #[test]
+    fn test_read_positions() {
+        let path = env::temp_dir().join(format!("wal-read-{}.jsonl", std::process::id()));
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        let mut client = Client::new(1, wal.wrap(MemoryStore::new()));
+        assert!(wal.read("batch.csv", 1));
+        wal.begin(&event("deposit", 1, Some(dec!(5)))).unwrap();
+        let _ = client.update(&event("deposit", 1, Some(dec!(5))));
+        wal.applied().unwrap();
+        assert!(wal.read("batch.csv", 2));
+        wal.discarded().unwrap();
+        assert!(wal.read("topic/0", 7));
+        wal.begin(&event("dispute", 1, None)).unwrap();
+        drop(wal);
+
+        // records logged before the crash are skipped when read again
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        assert!(wal.replay(&mut MemoryStore::new()).unwrap().is_some());
+        assert!(!wal.read("batch.csv", 1) && !wal.read("batch.csv", 2));
+        assert!(!wal.read("topic/0", 7) && !wal.read("topic/0", 3));
+        assert!(wal.read("topic/1", 7));
+        assert!(wal.read("batch.csv", 3));
+        wal.finished("batch.csv").unwrap();
+        drop(wal);
+
+        // an input of the same name is read from its start once the last was finished
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(wal.read("batch.csv", 1));
+        assert!(!wal.read("topic/0", 7));
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let path = env::temp_dir().join(format!("wal-snapshot-{}.jsonl", std::process::id()));
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        let store = MemoryStore::new();
+        let mut client = Client::new(1, wal.wrap(store.clone()));
+        for (position, event) in [
+            event("deposit", 1, Some(dec!(5))),
+            event("deposit", 2, Some(dec!(3))),
+            event("dispute", 1, None),
+        ]
+        .iter()
+        .enumerate()
+        {
+            assert!(wal.read("batch.csv", position as u64 + 1));
+            wal.begin(event).unwrap();
+            let _ = client.update(event);
+            wal.applied().unwrap();
+        }
+        let logged = fs::metadata(&path).unwrap().len();
+        let checkpoint = Checkpoint::capture(&store.lock().unwrap());
+        wal.snapshot(Some(&checkpoint)).unwrap();
+        assert!(fs::metadata(&path).unwrap().len() < logged);
+
+        // the log carries on after the snapshot
+        let resolve = event("resolve", 1, None);
+        assert!(wal.read("batch.csv", 4));
+        wal.begin(&resolve).unwrap();
+        client.update(&resolve).unwrap();
+        wal.applied().unwrap();
+        drop(wal);
+
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        let mut recovered = MemoryStore::new();
+        assert!(wal.replay(&mut recovered).unwrap().is_none());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(Client::new(1, recovered).summary(), client.summary());
+        assert!(!wal.read("batch.csv", 4));
+        assert!(wal.read("batch.csv", 5));
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+
+/// The subdirectory of a watched directory which files are moved to once processed.
+pub const PROCESSED: &str = "processed";
+
+/// The subdirectory of a watched directory which files are moved to if they could not
+/// be processed.
+pub const FAILED: &str = "failed";
+
+/// Watches a directory for new input files by polling it, such as for a daemon
+/// processing the files dropped into it by providers.
+///
+/// A file is only ready once its size and modification time are unchanged from one
+/// poll to the next, so that files still being written are left until they are
+/// complete. Once handled, files are moved to the [`PROCESSED`] or [`FAILED`]
+/// subdirectory, so that they are never handled twice. Hidden files are ignored, so
+/// that files can also be written under a hidden name and renamed once complete.
+///
+/// # Example
+/// ```
+/// use std::fs;
+///
+/// use payments::watch::DirectoryWatcher;
+///
+/// let dir = std::env::temp_dir().join(format!("watch-doc-{}", std::process::id()));
+/// let mut watcher = DirectoryWatcher::new(&dir).unwrap();
+/// fs::write(dir.join("2022-09-01.csv"), "type,client,tx,amount\n").unwrap();
+///
+/// // files are ready once they stop changing
+/// assert!(watcher.poll().unwrap().is_empty());
+/// let ready = watcher.poll().unwrap();
+/// assert_eq!(ready, [dir.join("2022-09-01.csv")]);
+///
+/// let moved = watcher.processed(&ready[0]).unwrap();
+/// assert_eq!(moved, dir.join("processed").join("2022-09-01.csv"));
+/// assert!(watcher.poll().unwrap().is_empty());
+/// # fs::remove_dir_all(&dir).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct DirectoryWatcher {
+    #[doc(hidden)]
+    dir: PathBuf,
+    #[doc(hidden)]
+    seen: HashMap<PathBuf, (u64, Option<SystemTime>)>,
+}
+
+impl DirectoryWatcher {
+    /// Watches `dir`, creating it and its [`PROCESSED`] and [`FAILED`] subdirectories if
+    /// they do not exist.
+    pub fn new(dir: impl AsRef<Path>) -> Result<DirectoryWatcher> {
+        let dir = dir.as_ref();
+        for subdir in [PROCESSED, FAILED] {
+            fs::create_dir_all(dir.join(subdir))
+                .with_context(|| format!("creating {}", dir.join(subdir).display()))?;
+        }
+        Ok(DirectoryWatcher {
+            dir: dir.to_path_buf(),
+            seen: HashMap::new(),
+        })
+    }
+
+    /// Returns the files in the directory which are ready to be handled, ordered by name
+    /// so that files named by date are handled in chronological order.
+    pub fn poll(&mut self) -> Result<Vec<PathBuf>> {
+        let entries =
+            fs::read_dir(&self.dir).with_context(|| format!("listing {}", self.dir.display()))?;
+        let mut seen = HashMap::new();
+        let mut ready = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() || entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            let state = (metadata.len(), metadata.modified().ok());
+            if self.seen.get(&path) == Some(&state) {
+                ready.push(path.clone());
+            }
+            seen.insert(path, state);
+        }
+        // files which were moved or deleted are forgotten
+        self.seen = seen;
+        ready.sort();
+        Ok(ready)
+    }
+
+    /// Moves the file at `path` to the [`PROCESSED`] subdirectory, returning its new
+    /// path.
+    pub fn processed(&mut self, path: &Path) -> Result<PathBuf> {
+        self.move_to(path, PROCESSED)
+    }
+
+    /// Moves the file at `path` to the [`FAILED`] subdirectory, returning its new path.
+    pub fn failed(&mut self, path: &Path) -> Result<PathBuf> {
+        self.move_to(path, FAILED)
+    }
+
+    fn move_to(&mut self, path: &Path, subdir: &str) -> Result<PathBuf> {
+        let name = path.file_name().unwrap_or_default();
+        let mut to = self.dir.join(subdir).join(name);
+        // a file of the same name handled before is kept, rather than overwritten
+        let mut n = 1;
+        while to.exists() {
+            to = self
+                .dir
+                .join(subdir)
+                .join(format!("{}.{}", name.to_string_lossy(), n));
+            n += 1;
+        }
+        fs::rename(path, &to)
+            .with_context(|| format!("moving {} to {}", path.display(), to.display()))?;
+        self.seen.remove(path);
+        Ok(to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    #[test]
+    fn test_directory_watcher() {
+        let dir = env::temp_dir().join(format!("watch-{}", std::process::id()));
+        let mut watcher = DirectoryWatcher::new(&dir).unwrap();
+        fs::write(dir.join("b.csv"), "b").unwrap();
+        fs::write(dir.join("a.csv"), "a").unwrap();
+        fs::write(dir.join(".c.csv.tmp"), "c").unwrap();
+        let first = watcher.poll().unwrap();
+
+        // a file still being written is left until it stops changing
+        fs::write(dir.join("b.csv"), "b, more").unwrap();
+        let second = watcher.poll().unwrap();
+        let third = watcher.poll().unwrap();
+
+        watcher.failed(&dir.join("a.csv")).unwrap();
+        fs::write(dir.join("a.csv"), "a again").unwrap();
+        watcher.poll().unwrap();
+        let again = watcher.poll().unwrap();
+        let moved = watcher.failed(&again[0]).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(first.is_empty());
+        assert_eq!(second, [dir.join("a.csv")]);
+        assert_eq!(third, [dir.join("a.csv"), dir.join("b.csv")]);
+        assert_eq!(moved, dir.join("failed").join("a.csv.1"));
+    }
+}
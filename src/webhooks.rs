@@ -0,0 +1,224 @@
+use std::collections::HashSet;
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use anyhow::Result;
+use serde_json::{json, Value};
+use tracing::{error, warn};
+
+use crate::clients::Summary;
+use crate::events::{ClientId, Event, EventType};
+use crate::http::{self, Url};
+use crate::observer::AccountObserver;
+
+/// Posts a JSON notification to a URL whenever an account is frozen or a chargeback is
+/// applied, such as for a fraud team to follow up on.
+///
+/// Notifications are sent in the order they happen on a thread of their own, so that a
+/// slow or failing server does not hold up processing. A notification which could not
+/// be sent is tried again after `backoff`, doubling the wait each time, up to `retries`
+/// times before it is logged and dropped. Notifications not yet sent are sent before
+/// the notifier is dropped.
+///
+/// Each notification has the `kind` of notification, either `"frozen"` or
+/// `"chargeback"`, the `client` and `tx` of the event, its `timestamp` if it has one,
+/// and the client's `balances` once it was applied.
+///
+/// # Example
+/// ```no_run
+/// use std::time::Duration;
+///
+/// use payments::clients::Summary;
+/// use payments::events::{Event, Record};
+/// use payments::http::Url;
+/// use payments::webhooks::WebhookNotifier;
+///
+/// let url = Url::parse("http://fraud.internal/hooks/payments").unwrap();
+/// let mut notifier = WebhookNotifier::new(url, 5, Duration::from_secs(1));
+/// let chargeback = Event::try_from(Record {
+///     r#type: "chargeback".to_string(),
+///     client: 1,
+///     tx: 7,
+///     amount: None,
+///     to: None,
+///     seq: None,
+///     timestamp: None,
+///     currency: None,
+/// })
+/// .unwrap();
+/// notifier.observe(&chargeback, &Summary { id: 1, locked: true, ..Default::default() });
+/// ```
+#[derive(Debug)]
+pub struct WebhookNotifier {
+    #[doc(hidden)]
+    sender: Option<Sender<Value>>,
+    #[doc(hidden)]
+    worker: Option<JoinHandle<()>>,
+    #[doc(hidden)]
+    locked: HashSet<ClientId>,
+}
+
+impl WebhookNotifier {
+    /// Creates a notifier posting to `url`, retrying each notification up to `retries`
+    /// times with a wait starting at `backoff`.
+    pub fn new(url: Url, retries: u32, backoff: Duration) -> WebhookNotifier {
+        let (sender, receiver) = mpsc::channel::<Value>();
+        let worker = thread::spawn(move || {
+            for notification in receiver {
+                let body = notification.to_string();
+                if let Err(e) = deliver(&url, body.as_bytes(), retries, backoff) {
+                    error!("dropping notification {}: {:?}", body, e);
+                }
+            }
+        });
+        WebhookNotifier {
+            sender: Some(sender),
+            worker: Some(worker),
+            locked: HashSet::new(),
+        }
+    }
+
+    /// Records `event` after it was applied, given the resulting balances of its
+    /// client, notifying of chargebacks and of accounts which were not already frozen.
+    pub fn observe(&mut self, event: &Event, summary: &Summary) {
+        if let EventType::Chargeback = event.kind() {
+            self.on_chargeback(event, summary);
+        }
+        if !summary.locked {
+            self.locked.remove(&summary.id);
+        } else if self.locked.insert(summary.id) {
+            self.on_frozen(event, summary);
+        }
+    }
+
+    fn notify(&self, kind: &str, event: &Event, summary: &Summary) {
+        let notification = json!({
+            "kind": kind,
+            "client": event.client_id(),
+            "tx": event.tx(),
+            "timestamp": event.timestamp(),
+            "balances": summary,
+        });
+        if let Some(sender) = &self.sender {
+            // the worker only stops once the sender is dropped
+            sender.send(notification).unwrap();
+        }
+    }
+}
+
+impl AccountObserver for WebhookNotifier {
+    fn on_frozen(&mut self, event: &Event, summary: &Summary) {
+        self.locked.insert(summary.id);
+        self.notify("frozen", event, summary);
+    }
+
+    fn on_chargeback(&mut self, event: &Event, summary: &Summary) {
+        self.notify("chargeback", event, summary);
+    }
+}
+
+impl Drop for WebhookNotifier {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                error!("webhook notifier panicked");
+            }
+        }
+    }
+}
+
+/// Posts `body` to `url`, trying again up to `retries` times if it fails, waiting
+/// `backoff` before the first retry and twice as long before each one after it.
+fn deliver(url: &Url, body: &[u8], retries: u32, backoff: Duration) -> Result<()> {
+    let mut wait = backoff;
+    for _ in 0..retries {
+        match http::post(url, "application/json", body) {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                warn!("notifying {}: {:#}, retrying in {:?}", url, e, wait);
+                thread::sleep(wait);
+                wait *= 2;
+            }
+        }
+    }
+    http::post(url, "application/json", body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use crate::events::{Record, TxId};
+
+    fn event(r#type: &str, tx: TxId) -> Event {
+        Event::try_from(Record {
+            r#type: r#type.to_string(),
+            client: 1,
+            tx,
+            amount: None,
+            to: None,
+            seq: None,
+            timestamp: Some(1_000),
+            currency: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_notify() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // fails the first request, so that the notification is retried
+        let server = thread::spawn(move || {
+            let mut bodies = Vec::new();
+            for status in [
+                "500 Internal Server Error",
+                "204 No Content",
+                "204 No Content",
+            ] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"}") {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+                let request = String::from_utf8(request).unwrap();
+                let (_, body) = request.split_once("\r\n\r\n").unwrap();
+                bodies.push(serde_json::from_str::<Value>(body).unwrap());
+            }
+            bodies
+        });
+
+        let url = Url::parse(&format!("http://127.0.0.1:{}/hook", port)).unwrap();
+        let mut notifier = WebhookNotifier::new(url, 1, Duration::from_millis(1));
+        let locked = Summary {
+            id: 1,
+            locked: true,
+            ..Default::default()
+        };
+        notifier.observe(&event("chargeback", 7), &locked);
+        // the account is already frozen
+        notifier.observe(&event("dispute", 8), &locked);
+        drop(notifier);
+
+        let bodies = server.join().unwrap();
+        assert_eq!(bodies[0], bodies[1]);
+        assert_eq!(bodies[1]["kind"], "chargeback");
+        assert_eq!(bodies[1]["tx"], 7);
+        assert_eq!(bodies[1]["balances"]["locked"], true);
+        assert_eq!(bodies[2]["kind"], "frozen");
+        assert_eq!(bodies[2]["timestamp"], 1_000);
+    }
+}